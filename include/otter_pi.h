@@ -0,0 +1,58 @@
+/*
+ * C interface to the Otter Pi runtime, built with the `ffi` feature.
+ *
+ * Every function taking a runtime returns OTTER_ERR_NULL if it is null.
+ * See src/ffi.rs for the full documentation.
+ */
+
+#ifndef OTTER_PI_H
+#define OTTER_PI_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define OTTER_ABI_VERSION 1
+
+#define OTTER_OK 0
+#define OTTER_ERR_NULL (-1)
+#define OTTER_ERR_ESTOPPED (-2)
+#define OTTER_ERR_INVALID (-3)
+#define OTTER_ERR_NO_COMMAND (-4)
+
+typedef struct OtterRuntime OtterRuntime;
+
+/* Velocity of the chassis: metres per second forward and left, and radians
+ * per second counterclockwise. */
+typedef struct OtterTwist {
+    double vx;
+    double vy;
+    double omega;
+} OtterTwist;
+
+uint32_t otter_abi_version(void);
+
+OtterRuntime *otter_runtime_new(uint32_t timeout_ms);
+void otter_runtime_free(OtterRuntime *runtime);
+
+int otter_drive(const OtterRuntime *runtime, OtterTwist twist);
+int otter_stop(const OtterRuntime *runtime);
+
+int otter_estop(const OtterRuntime *runtime);
+int otter_estop_reset(const OtterRuntime *runtime);
+int otter_is_estopped(const OtterRuntime *runtime);
+
+int otter_command(const OtterRuntime *runtime, OtterTwist *twist);
+
+/* Returns the length of the telemetry JSON, copying as much as fits, with a
+ * terminating NUL, into buffer, as snprintf does. */
+int64_t otter_telemetry(const OtterRuntime *runtime, char *buffer, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,43 @@
+//! Listening to the people around the robot.
+//!
+//! Speech recognition is left to a backend, such as Vosk or whisper.cpp
+//! reading from the microphone, which implements [`Recognizer`] and hands
+//! over the text of each utterance. [`intents`] turns that text into
+//! commands.
+
+use std::io::Error;
+
+pub mod intents;
+
+pub use intents::{Grammar, Intents};
+
+/// Text recognized from one utterance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Utterance {
+    /// What was said.
+    pub text: String,
+    /// How sure the recognizer is, from 0 to 1.
+    pub confidence: f64,
+}
+
+impl Utterance {
+    /// Creates a new utterance.
+    pub fn new(text: &str, confidence: f64) -> Self {
+        Self {
+            text: text.to_owned(),
+            confidence,
+        }
+    }
+}
+
+/// A speech recognizer.
+pub trait Recognizer {
+    /// Waits for and returns the next utterance, or `None` once the audio
+    /// has ended.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the audio cannot be read, such
+    /// as when the microphone has been unplugged.
+    fn recognize(&mut self) -> Result<Option<Utterance>, Error>;
+}
@@ -0,0 +1,287 @@
+//! Voice commands.
+//!
+//! A [`Grammar`] matches recognized text against phrases such as “otter, go
+//! to the kitchen”: a wake word, so conversation near the robot is ignored,
+//! followed by a pattern of words with at most one `{slot}` capturing the
+//! words in its place. [`Intents`] publishes each match on the event bus as
+//! an [`Event::Command`] for whichever behavior handles it, except for the
+//! [`EMERGENCY_STOP`] intent, which engages the emergency stop directly so
+//! “otter, stop” works however busy the rest of the robot is.
+
+use std::io::Error;
+
+use super::{Recognizer, Utterance};
+use crate::events::{Command, EmergencyStop, Event, EventBus};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "intents";
+
+/// Source of the events published for voice commands.
+pub const SOURCE: &str = "voice";
+
+/// Intent that engages the emergency stop.
+pub const EMERGENCY_STOP: &str = "emergency_stop";
+
+/// A recognized command.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Intent {
+    /// Name of the intent.
+    pub name: String,
+    /// Words captured by the pattern’s slot.
+    pub argument: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Rule {
+    intent: String,
+    prefix: Vec<String>,
+    slot: bool,
+    suffix: Vec<String>,
+}
+
+/// Phrases and the intents they express.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Grammar {
+    rules: Vec<Rule>,
+    wake_word: Vec<String>,
+}
+
+impl Grammar {
+    /// Creates a new `Grammar` without rules, for phrases starting with
+    /// `wake_word`, or with no wake word if it is empty.
+    pub fn new(wake_word: &str) -> Self {
+        Self {
+            rules: Vec::new(),
+            wake_word: words(wake_word),
+        }
+    }
+
+    /// Creates a new `Grammar` with rules for the commands the robot
+    /// understands out of the box:
+    ///
+    /// | Phrase                    | Intent           | Argument |
+    /// |---------------------------|------------------|----------|
+    /// | stop, halt                | `emergency_stop` |          |
+    /// | go home, go to the dock   | `go_home`        |          |
+    /// | go to {place}             | `go_to`          | place    |
+    /// | start {mission}           | `start_mission`  | mission  |
+    /// | take a picture            | `snapshot`       |          |
+    pub fn standard(wake_word: &str) -> Self {
+        Self::new(wake_word)
+            .with_rule("stop", EMERGENCY_STOP)
+            .with_rule("halt", EMERGENCY_STOP)
+            .with_rule("go home", "go_home")
+            .with_rule("go to the dock", "go_home")
+            .with_rule("go to the {place}", "go_to")
+            .with_rule("go to {place}", "go_to")
+            .with_rule("start {mission}", "start_mission")
+            .with_rule("take a picture", "snapshot")
+    }
+
+    /// Matches `pattern` to `intent`, after the rules already added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern has more than one slot.
+    #[must_use]
+    pub fn with_rule(mut self, pattern: &str, intent: &str) -> Self {
+        let mut prefix = Vec::new();
+        let mut suffix = Vec::new();
+        let mut slot = false;
+
+        for token in pattern.split_whitespace() {
+            if token.starts_with('{') && token.ends_with('}') {
+                assert!(!slot, "pattern should have at most one slot");
+                slot = true;
+            } else if slot {
+                suffix.extend(words(token));
+            } else {
+                prefix.extend(words(token));
+            }
+        }
+
+        self.rules.push(Rule {
+            intent: intent.to_owned(),
+            prefix,
+            slot,
+            suffix,
+        });
+        self
+    }
+
+    /// Returns the intent expressed by `text`, ignoring case and
+    /// punctuation, from the first rule it matches.
+    #[must_use]
+    pub fn parse(&self, text: &str) -> Option<Intent> {
+        let words = words(text);
+        let words = words.strip_prefix(self.wake_word.as_slice())?;
+
+        self.rules.iter().find_map(|rule| {
+            let rest = words.strip_prefix(rule.prefix.as_slice())?;
+            let captured = rest.strip_suffix(rule.suffix.as_slice())?;
+
+            match (rule.slot, captured.is_empty()) {
+                (false, true) => Some(Intent {
+                    name: rule.intent.clone(),
+                    argument: None,
+                }),
+                (true, false) => Some(Intent {
+                    name: rule.intent.clone(),
+                    argument: Some(captured.join(" ")),
+                }),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// Publishes voice commands on the event bus.
+#[derive(Debug)]
+pub struct Intents {
+    bus: EventBus,
+    grammar: Grammar,
+    min_confidence: f64,
+}
+
+impl Intents {
+    /// Creates a new `Intents` publishing commands matched by `grammar` to
+    /// `bus`, from utterances recognized with a confidence of at least 0.5.
+    pub fn new(grammar: Grammar, bus: EventBus) -> Self {
+        Self {
+            bus,
+            grammar,
+            min_confidence: 0.5,
+        }
+    }
+
+    /// Ignores utterances recognized with less than `confidence`.
+    #[must_use]
+    pub fn with_min_confidence(mut self, confidence: f64) -> Self {
+        self.min_confidence = confidence;
+        self
+    }
+
+    /// Publishes the command in `utterance`, if any, and returns its intent.
+    pub fn dispatch(&self, utterance: &Utterance) -> Option<Intent> {
+        if utterance.confidence < self.min_confidence {
+            return None;
+        }
+
+        let intent = self.grammar.parse(&utterance.text)?;
+        log_event!(SUBSYSTEM, Level::Info, "heard {intent:?}");
+
+        if intent.name == EMERGENCY_STOP {
+            self.bus.publish(Event::EmergencyStop(EmergencyStop {
+                engaged: true,
+                source: SOURCE.to_owned(),
+            }));
+        } else {
+            self.bus.publish(Event::Command(Command {
+                intent: intent.name.clone(),
+                argument: intent.argument.clone(),
+                source: SOURCE.to_owned(),
+            }));
+        }
+
+        Some(intent)
+    }
+
+    /// Dispatches every utterance from `recognizer` until its audio ends.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the recognizer fails.
+    pub fn run(&self, recognizer: &mut impl Recognizer) -> Result<(), Error> {
+        while let Some(utterance) = recognizer.recognize()? {
+            self.dispatch(&utterance);
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the words of `text` in lower case, without punctuation.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    struct Script(VecDeque<Utterance>);
+
+    impl Recognizer for Script {
+        fn recognize(&mut self) -> Result<Option<Utterance>, Error> {
+            Ok(self.0.pop_front())
+        }
+    }
+
+    fn intent(name: &str, argument: Option<&str>) -> Option<Intent> {
+        Some(Intent {
+            name: name.to_owned(),
+            argument: argument.map(str::to_owned),
+        })
+    }
+
+    #[test]
+    fn it_should_match_phrases_after_the_wake_word() {
+        let grammar = Grammar::standard("otter");
+        assert_eq!(grammar.parse("Otter, stop!"), intent(EMERGENCY_STOP, None));
+        assert_eq!(grammar.parse("otter go home"), intent("go_home", None));
+        assert_eq!(
+            grammar.parse("otter, go to the front door"),
+            intent("go_to", Some("front door"))
+        );
+        assert_eq!(
+            grammar.parse("otter start night watch"),
+            intent("start_mission", Some("night watch"))
+        );
+        assert_eq!(grammar.parse("go home"), None);
+        assert_eq!(grammar.parse("otter, go to"), None);
+        assert_eq!(grammar.parse("otter, dance"), None);
+    }
+
+    #[test]
+    fn it_should_match_slots_before_trailing_words() {
+        let grammar = Grammar::new("").with_rule("turn {direction} now", "turn");
+        assert_eq!(grammar.parse("turn left now"), intent("turn", Some("left")));
+        assert_eq!(grammar.parse("turn left"), None);
+    }
+
+    #[test]
+    fn it_should_publish_commands_and_stop_on_the_bus() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(8);
+        let intents = Intents::new(Grammar::standard("otter"), bus).with_min_confidence(0.6);
+        let mut recognizer = Script(VecDeque::from([
+            Utterance::new("otter go to the kitchen", 0.9),
+            Utterance::new("otter go home", 0.3),
+            Utterance::new("what's for dinner", 0.9),
+            Utterance::new("otter stop", 0.8),
+        ]));
+        intents.run(&mut recognizer).unwrap();
+        assert_eq!(
+            subscription.try_recv(),
+            Some(Event::Command(Command {
+                intent: "go_to".to_owned(),
+                argument: Some("kitchen".to_owned()),
+                source: SOURCE.to_owned(),
+            }))
+        );
+        assert_eq!(
+            subscription.try_recv(),
+            Some(Event::EmergencyStop(EmergencyStop {
+                engaged: true,
+                source: SOURCE.to_owned(),
+            }))
+        );
+        assert_eq!(subscription.try_recv(), None);
+    }
+}
@@ -0,0 +1,75 @@
+//! Cameras and the frames they produce.
+
+use std::io::Error;
+
+pub mod depth;
+pub mod privacy;
+pub mod rig;
+
+/// A camera that takes still pictures.
+pub trait StillCamera {
+    /// Takes a picture and returns it encoded as a JPEG.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the camera cannot be read,
+    /// such as when it has been unplugged.
+    fn capture(&mut self) -> Result<Vec<u8>, Error>;
+}
+
+/// A camera that produces a stream of decoded frames.
+pub trait VideoCamera {
+    /// Returns the latest frame.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the camera cannot be read,
+    /// such as when it has been unplugged.
+    fn frame(&mut self) -> Result<RgbImage, Error>;
+}
+
+/// A decoded image with 8-bit RGB pixels in row-major order.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RgbImage {
+    /// Width, in pixels.
+    pub width: usize,
+    /// Height, in pixels.
+    pub height: usize,
+    /// Red, green, and blue bytes of each pixel, row by row.
+    pub pixels: Vec<u8>,
+}
+
+impl RgbImage {
+    /// Creates a new black image.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    /// Returns the color of the pixel at `x` and `y`, or `None` if it is
+    /// outside the image.
+    #[must_use]
+    pub fn pixel(&self, x: usize, y: usize) -> Option<[u8; 3]> {
+        let index = self.index(x, y)?;
+        Some([
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+        ])
+    }
+
+    /// Sets the color of the pixel at `x` and `y`, if it is inside the
+    /// image.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if let Some(index) = self.index(x, y) {
+            self.pixels[index..index + 3].copy_from_slice(&color);
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| (y * self.width + x) * 3)
+    }
+}
@@ -0,0 +1,158 @@
+//! Depth frames from stereo and time-of-flight cameras.
+//!
+//! Depth cameras such as the OAK-D and RealSense report, for every pixel,
+//! how far away the surface it sees is. Backends for each camera implement
+//! [`DepthCamera`] and hand over [`DepthFrame`]s, which unproject into points
+//! for obstacle detection regardless of where they came from.
+
+use std::io::Error;
+
+/// Pinhole intrinsics of a depth camera, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intrinsics {
+    /// Focal length along the image X axis.
+    pub fx: f64,
+    /// Focal length along the image Y axis.
+    pub fy: f64,
+    /// Principal point along the image X axis.
+    pub cx: f64,
+    /// Principal point along the image Y axis.
+    pub cy: f64,
+}
+
+impl Intrinsics {
+    /// Returns the intrinsics of a camera with the given horizontal field of
+    /// view, in radians, and a centred principal point.
+    #[must_use]
+    pub fn from_fov(width: usize, height: usize, horizontal_fov: f64) -> Self {
+        let focal = width as f64 / 2.0 / (horizontal_fov / 2.0).tan();
+        Self {
+            fx: focal,
+            fy: focal,
+            cx: (width as f64 - 1.0) / 2.0,
+            cy: (height as f64 - 1.0) / 2.0,
+        }
+    }
+}
+
+/// A frame of depth measurements.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthFrame {
+    depth: Vec<u16>,
+    height: usize,
+    intrinsics: Intrinsics,
+    scale: f64,
+    width: usize,
+}
+
+impl DepthFrame {
+    /// Creates a new `DepthFrame` from row-major `depth` values, each
+    /// `scale` metres per unit, with 0 marking pixels without a measurement.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` does not hold `width` by `height` values.
+    pub fn new(
+        width: usize,
+        height: usize,
+        depth: Vec<u16>,
+        scale: f64,
+        intrinsics: Intrinsics,
+    ) -> Self {
+        assert_eq!(
+            depth.len(),
+            width * height,
+            "depth should hold width by height values"
+        );
+        Self {
+            depth,
+            height,
+            intrinsics,
+            scale,
+            width,
+        }
+    }
+
+    /// Returns the width of the frame, in pixels.
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the frame, in pixels.
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the depth at the pixel in column `x` and row `y`, in metres,
+    /// if there is a measurement.
+    #[must_use]
+    pub fn depth(&self, x: usize, y: usize) -> Option<f64> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        match self.depth[y * self.width + x] {
+            0 => None,
+            raw => Some(f64::from(raw) * self.scale),
+        }
+    }
+
+    /// Returns the point seen at every `stride`th pixel in each direction,
+    /// in metres in the camera’s optical frame: X right, Y down, and Z
+    /// forward.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `stride` is 0.
+    #[must_use]
+    pub fn points(&self, stride: usize) -> Vec<[f64; 3]> {
+        assert!(stride > 0, "stride should be positive");
+        let Intrinsics { fx, fy, cx, cy } = self.intrinsics;
+        (0..self.height)
+            .step_by(stride)
+            .flat_map(|y| (0..self.width).step_by(stride).map(move |x| (x, y)))
+            .filter_map(|(x, y)| {
+                let z = self.depth(x, y)?;
+                Some([(x as f64 - cx) * z / fx, (y as f64 - cy) * z / fy, z])
+            })
+            .collect()
+    }
+}
+
+/// A camera that measures depth.
+pub trait DepthCamera {
+    /// Waits for and returns the next depth frame.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the camera cannot be read,
+    /// such as when it has been unplugged.
+    fn next_frame(&mut self) -> Result<DepthFrame, Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+
+    #[test]
+    fn it_should_unproject_pixels_into_points() {
+        let intrinsics = Intrinsics::from_fov(5, 3, FRAC_PI_2);
+        assert!((intrinsics.fx - 2.5).abs() < 1e-9);
+        let mut depth = vec![2000; 15];
+        depth[0] = 0;
+        let frame = DepthFrame::new(5, 3, depth, 0.001, intrinsics);
+        assert_eq!(frame.depth(0, 0), None);
+        assert_eq!(frame.depth(5, 0), None);
+        let points = frame.points(1);
+        assert_eq!(points.len(), 14);
+        // The centre pixel looks straight ahead.
+        assert_eq!(points[6], [0.0, 0.0, 2.0]);
+        // The right edge is 2 pixels right of centre, at a focal length of 2.5.
+        assert!((points[8][0] - 1.6).abs() < 1e-9);
+        assert_eq!(frame.points(2).len(), 5);
+    }
+}
@@ -0,0 +1,351 @@
+//! Keeping faces out of video that leaves the robot.
+//!
+//! A robot driving around a home sees the people and pets who live there.
+//! Before a frame is recorded or streamed, a [`PrivacyFilter`] blurs or
+//! drops it if a [`Detector`] found a face in it, with a [`Policy`] chosen
+//! per output: a live view on the local network might show everything while
+//! the recorder blurs and a cloud upload drops. Outputs without a policy of
+//! their own are blurred, so video is private unless configured otherwise.
+//! Blurring pixelates each detection, with a margin, into blocks coarse
+//! enough that the face cannot be recovered.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::Error;
+use std::str::FromStr;
+
+use super::RgbImage;
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "privacy";
+
+/// Fraction of a detection’s size added on every side before blurring, so
+/// hair and ears are covered along with the face.
+const MARGIN: f64 = 0.2;
+
+/// Number of blocks across a blurred detection.
+const BLOCKS: usize = 6;
+
+/// What was detected.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Label {
+    /// A human face.
+    Face,
+    /// A cat, dog, or other pet.
+    Pet,
+}
+
+/// Something a detector found in a frame, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Detection {
+    /// What it is.
+    pub label: Label,
+    /// Left edge.
+    pub x: f64,
+    /// Top edge.
+    pub y: f64,
+    /// Width.
+    pub width: f64,
+    /// Height.
+    pub height: f64,
+    /// How sure the detector is, from 0 to 1.
+    pub confidence: f64,
+}
+
+/// Finds faces and pets in frames.
+pub trait Detector {
+    /// Returns what was found in `image`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the detector fails, in which
+    /// case the frame is treated as containing a face.
+    fn detect(&mut self, image: &RgbImage) -> Result<Vec<Detection>, Error>;
+}
+
+/// How an output treats frames with detections.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Policy {
+    /// Passes frames through unchanged.
+    Allow,
+    /// Pixelates each detection.
+    #[default]
+    Blur,
+    /// Drops the whole frame.
+    Drop,
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Allow => "allow",
+            Self::Blur => "blur",
+            Self::Drop => "drop",
+        })
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "blur" => Ok(Self::Blur),
+            "drop" => Ok(Self::Drop),
+            _ => Err(format!("`{s}` should be allow, blur, or drop")),
+        }
+    }
+}
+
+/// Applies a privacy policy to each output’s frames.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrivacyFilter {
+    default: Policy,
+    labels: Vec<Label>,
+    min_confidence: f64,
+    outputs: BTreeMap<String, Policy>,
+}
+
+impl PrivacyFilter {
+    /// Creates a new `PrivacyFilter` that blurs faces detected with a
+    /// confidence of at least 0.4 for every output.
+    pub fn new() -> Self {
+        Self {
+            default: Policy::Blur,
+            labels: vec![Label::Face],
+            min_confidence: 0.4,
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Applies `policy` to frames sent to `output`, such as `recorder`.
+    #[must_use]
+    pub fn with_output(mut self, output: &str, policy: Policy) -> Self {
+        self.outputs.insert(output.to_owned(), policy);
+        self
+    }
+
+    /// Applies `policy` to outputs without a policy of their own.
+    #[must_use]
+    pub fn with_default(mut self, policy: Policy) -> Self {
+        self.default = policy;
+        self
+    }
+
+    /// Hides detections of `labels`, such as pets as well as faces.
+    #[must_use]
+    pub fn with_labels(mut self, labels: &[Label]) -> Self {
+        self.labels = labels.to_vec();
+        self
+    }
+
+    /// Ignores detections with less than `confidence`.
+    #[must_use]
+    pub fn with_min_confidence(mut self, confidence: f64) -> Self {
+        self.min_confidence = confidence;
+        self
+    }
+
+    /// Returns the policy for `output`.
+    #[must_use]
+    pub fn policy(&self, output: &str) -> Policy {
+        self.outputs.get(output).copied().unwrap_or(self.default)
+    }
+
+    /// Returns `image` as `output` may receive it given `detections`, or
+    /// `None` if it should be dropped.
+    #[must_use]
+    pub fn filter<'a>(
+        &self,
+        output: &str,
+        image: &'a RgbImage,
+        detections: &[Detection],
+    ) -> Option<Cow<'a, RgbImage>> {
+        let hidden: Vec<_> = detections
+            .iter()
+            .filter(|detection| {
+                self.labels.contains(&detection.label)
+                    && detection.confidence >= self.min_confidence
+            })
+            .collect();
+
+        if hidden.is_empty() {
+            return Some(Cow::Borrowed(image));
+        }
+
+        match self.policy(output) {
+            Policy::Allow => Some(Cow::Borrowed(image)),
+            Policy::Drop => None,
+            Policy::Blur => {
+                let mut blurred = image.clone();
+                hidden
+                    .iter()
+                    .for_each(|detection| pixelate(&mut blurred, detection));
+                Some(Cow::Owned(blurred))
+            }
+        }
+    }
+
+    /// Runs `detector` on `image` and filters it for `output`.
+    ///
+    /// If the detector fails, the frame is dropped for every output that
+    /// does not allow everything, since it cannot be known to be safe.
+    pub fn apply<'a>(
+        &self,
+        detector: &mut impl Detector,
+        output: &str,
+        image: &'a RgbImage,
+    ) -> Option<Cow<'a, RgbImage>> {
+        match detector.detect(image) {
+            Ok(detections) => self.filter(output, image, &detections),
+            Err(error) => {
+                log_event!(SUBSYSTEM, Level::Warn, "detector failed: {error}");
+                (self.policy(output) == Policy::Allow).then_some(Cow::Borrowed(image))
+            }
+        }
+    }
+}
+
+impl Default for PrivacyFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Replaces `detection` and its margin in `image` with blocks of their
+/// average color.
+fn pixelate(image: &mut RgbImage, detection: &Detection) {
+    let margin_x = detection.width * MARGIN;
+    let margin_y = detection.height * MARGIN;
+    let clamp = |value: f64, max: usize| value.clamp(0.0, max as f64) as usize;
+    let left = clamp((detection.x - margin_x).floor(), image.width);
+    let top = clamp((detection.y - margin_y).floor(), image.height);
+    let right = clamp(
+        (detection.x + detection.width + margin_x).ceil(),
+        image.width,
+    );
+    let bottom = clamp(
+        (detection.y + detection.height + margin_y).ceil(),
+        image.height,
+    );
+
+    if left >= right || top >= bottom {
+        return;
+    }
+
+    let block = ((right - left).max(bottom - top)).div_ceil(BLOCKS).max(1);
+
+    for block_top in (top..bottom).step_by(block) {
+        for block_left in (left..right).step_by(block) {
+            let xs = block_left..(block_left + block).min(right);
+            let ys = block_top..(block_top + block).min(bottom);
+            let mut sum = [0_u64; 3];
+            let mut count = 0;
+
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    if let Some(pixel) = image.pixel(x, y) {
+                        sum.iter_mut()
+                            .zip(pixel)
+                            .for_each(|(sum, value)| *sum += u64::from(value));
+                        count += 1;
+                    }
+                }
+            }
+
+            let average = sum.map(|sum| (sum / count.max(1)) as u8);
+
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    image.set_pixel(x, y, average);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard() -> RgbImage {
+        let mut image = RgbImage::new(40, 30);
+
+        for y in 0..30 {
+            for x in 0..40 {
+                let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+                image.set_pixel(x, y, [value; 3]);
+            }
+        }
+
+        image
+    }
+
+    fn face(confidence: f64) -> Detection {
+        Detection {
+            label: Label::Face,
+            x: 10.0,
+            y: 10.0,
+            width: 10.0,
+            height: 10.0,
+            confidence,
+        }
+    }
+
+    struct Failing;
+
+    impl Detector for Failing {
+        fn detect(&mut self, _image: &RgbImage) -> Result<Vec<Detection>, Error> {
+            Err(Error::other("model not loaded"))
+        }
+    }
+
+    #[test]
+    fn it_should_apply_each_outputs_policy() {
+        let filter = PrivacyFilter::new()
+            .with_output("live", Policy::Allow)
+            .with_output("upload", Policy::Drop);
+        let image = checkerboard();
+        let detections = [face(0.9)];
+        assert!(matches!(
+            filter.filter("live", &image, &detections),
+            Some(Cow::Borrowed(_))
+        ));
+        assert_eq!(filter.filter("upload", &image, &detections), None);
+        let blurred = filter.filter("recorder", &image, &detections).unwrap();
+        // The detection is flattened into gray blocks, and the rest is
+        // untouched.
+        let gray = blurred.pixel(15, 15).unwrap();
+        assert!(gray[0] > 0 && gray[0] < 255);
+        assert_eq!(blurred.pixel(14, 16), Some(gray));
+        assert_eq!(blurred.pixel(0, 0), image.pixel(0, 0));
+        assert_eq!(blurred.pixel(39, 29), image.pixel(39, 29));
+    }
+
+    #[test]
+    fn it_should_ignore_unwanted_labels_and_weak_detections() {
+        let filter = PrivacyFilter::new().with_default(Policy::Drop);
+        let image = checkerboard();
+        let pet = Detection {
+            label: Label::Pet,
+            ..face(0.9)
+        };
+        assert!(filter
+            .filter("recorder", &image, &[pet, face(0.1)])
+            .is_some());
+        let filter = filter.with_labels(&[Label::Face, Label::Pet]);
+        assert!(filter.filter("recorder", &image, &[pet]).is_none());
+    }
+
+    #[test]
+    fn it_should_drop_frames_when_the_detector_fails() {
+        let filter = PrivacyFilter::new().with_output("live", Policy::Allow);
+        let image = checkerboard();
+        assert!(filter.apply(&mut Failing, "recorder", &image).is_none());
+        assert!(filter.apply(&mut Failing, "live", &image).is_some());
+        assert_eq!("blur".parse(), Ok(Policy::Blur));
+    }
+}
@@ -0,0 +1,579 @@
+//! Several cameras and the view they are streamed as.
+//!
+//! The robot looks forward for driving and backward or down at its gripper
+//! for manipulation, each through its own V4L2 device. A [`CameraRig`] holds
+//! every camera with its [`CameraConfig`] and composes their frames into
+//! the single view that is recorded and streamed, following a [`Layout`]
+//! that clients switch through the HTTP API:
+//!
+//! | Layout        | View                                             |
+//! |---------------|--------------------------------------------------|
+//! | `front`       | The front camera alone                           |
+//! | `front+rear`  | The front camera with the rear camera inset      |
+//! | `front\|rear` | The front and rear cameras side by side          |
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{RgbImage, VideoCamera};
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+use crate::net::http::{Api, Response};
+
+const SUBSYSTEM: &str = "cameras";
+
+/// Fraction of the view’s width taken by an inset camera.
+const INSET_SCALE: usize = 4;
+
+/// Margin between an inset camera and the edges of the view, in pixels.
+const INSET_MARGIN: usize = 8;
+
+/// Settings of one camera.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CameraConfig {
+    /// Name used to select the camera, such as `front`.
+    pub name: String,
+    /// V4L2 device node, such as `video0`, or the name its driver gives it.
+    pub device: String,
+    /// Width of captured frames, in pixels.
+    pub width: usize,
+    /// Height of captured frames, in pixels.
+    pub height: usize,
+    /// Frames captured per second.
+    pub fps: u32,
+    /// Whether the camera is mounted upside down, so frames are rotated by
+    /// half a turn.
+    pub flip: bool,
+}
+
+impl CameraConfig {
+    /// Creates a new `CameraConfig` for `device` capturing 640 × 480 frames
+    /// at 30 frames per second.
+    pub fn new(name: &str, device: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            device: device.to_owned(),
+            width: 640,
+            height: 480,
+            fps: 30,
+            flip: false,
+        }
+    }
+
+    /// Captures `width` by `height` frames.
+    #[must_use]
+    pub fn with_size(mut self, width: usize, height: usize) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Captures `fps` frames per second.
+    #[must_use]
+    pub fn with_fps(mut self, fps: u32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// Rotates frames by half a turn.
+    #[must_use]
+    pub fn with_flip(mut self, flip: bool) -> Self {
+        self.flip = flip;
+        self
+    }
+
+    /// Creates a new `CameraConfig` from an object such as
+    /// `{"name": "rear", "device": "video2", "width": 320, "height": 240}`,
+    /// with defaults for missing settings.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the name or device is missing.
+    pub fn from_json(config: &Value) -> Result<Self, String> {
+        let text = |key| {
+            config
+                .get(key)
+                .and_then(Value::as_str)
+                .ok_or_else(|| format!("camera should have a {key}"))
+        };
+        let number = |key| config.get(key).and_then(Value::as_f64);
+        let mut camera = Self::new(text("name")?, text("device")?);
+
+        if let (Some(width), Some(height)) = (number("width"), number("height")) {
+            camera = camera.with_size(width as usize, height as usize);
+        }
+
+        if let Some(fps) = number("fps") {
+            camera = camera.with_fps(fps as u32);
+        }
+
+        if let Some(flip) = config.get("flip").and_then(Value::as_bool) {
+            camera = camera.with_flip(flip);
+        }
+
+        Ok(camera)
+    }
+
+    /// Returns the V4L2 device the camera is attached to.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with [`ErrorKind::NotFound`] if no
+    /// device has the configured node or name.
+    #[cfg(target_os = "linux")]
+    pub fn resolve(
+        &self,
+        v4l2: &crate::linux::v4l2::V4l2,
+    ) -> Result<crate::linux::v4l2::Device, Error> {
+        let devices = v4l2.devices()?;
+        devices
+            .iter()
+            .find(|device| device.node == self.device)
+            .or_else(|| devices.iter().find(|device| device.name == self.device))
+            .cloned()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "no V4L2 device `{}` for camera `{}`",
+                        self.device, self.name
+                    ),
+                )
+            })
+    }
+}
+
+impl ToJson for CameraConfig {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("name", &self.name)
+            .with("device", &self.device)
+            .with("width", self.width)
+            .with("height", self.height)
+            .with("fps", self.fps)
+            .with("flip", self.flip)
+    }
+}
+
+/// How the cameras are composed into one view.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Layout {
+    /// One camera fills the view.
+    Single(String),
+    /// The first camera fills the view, with the second scaled down in its
+    /// bottom right corner.
+    PictureInPicture(String, String),
+    /// The cameras each fill half the view, the first on the left.
+    SideBySide(String, String),
+}
+
+impl Layout {
+    /// Returns the names of the cameras in the view.
+    #[must_use]
+    pub fn cameras(&self) -> Vec<&str> {
+        match self {
+            Self::Single(name) => vec![name],
+            Self::PictureInPicture(main, inset) => vec![main, inset],
+            Self::SideBySide(left, right) => vec![left, right],
+        }
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Single(name) => write!(f, "{name}"),
+            Self::PictureInPicture(main, inset) => write!(f, "{main}+{inset}"),
+            Self::SideBySide(left, right) => write!(f, "{left}|{right}"),
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pair = |separator| {
+            s.split_once(separator)
+                .map(|(first, second)| (first.trim().to_owned(), second.trim().to_owned()))
+        };
+        let layout = if let Some((main, inset)) = pair('+') {
+            Self::PictureInPicture(main, inset)
+        } else if let Some((left, right)) = pair('|') {
+            Self::SideBySide(left, right)
+        } else {
+            Self::Single(s.trim().to_owned())
+        };
+
+        if layout.cameras().iter().any(|name| name.is_empty()) {
+            return Err(format!("`{s}` should name a camera on each side"));
+        }
+
+        Ok(layout)
+    }
+}
+
+struct Camera {
+    config: CameraConfig,
+    source: Box<dyn VideoCamera + Send>,
+}
+
+struct Inner {
+    cameras: Vec<Camera>,
+    layout: Option<Layout>,
+}
+
+/// Every camera on the robot.
+#[derive(Clone)]
+pub struct CameraRig {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl CameraRig {
+    /// Creates a new `CameraRig` without cameras.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                cameras: Vec::new(),
+                layout: None,
+            })),
+        }
+    }
+
+    /// Adds a camera reading frames from `source`. The first camera added
+    /// fills the view until the layout is changed.
+    #[must_use]
+    pub fn with_camera(
+        self,
+        config: CameraConfig,
+        source: impl VideoCamera + Send + 'static,
+    ) -> Self {
+        {
+            let mut inner = self.lock();
+            inner
+                .layout
+                .get_or_insert_with(|| Layout::Single(config.name.clone()));
+            inner.cameras.push(Camera {
+                config,
+                source: Box::new(source),
+            });
+        }
+        self
+    }
+
+    /// Returns the settings of every camera, in the order they were added.
+    #[must_use]
+    pub fn cameras(&self) -> Vec<CameraConfig> {
+        self.lock()
+            .cameras
+            .iter()
+            .map(|camera| camera.config.clone())
+            .collect()
+    }
+
+    /// Returns the current layout, or `None` if there are no cameras.
+    #[must_use]
+    pub fn layout(&self) -> Option<Layout> {
+        self.lock().layout.clone()
+    }
+
+    /// Switches the view to `layout`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the layout names a camera that
+    /// does not exist, or the same camera twice.
+    pub fn set_layout(&self, layout: Layout) -> Result<(), String> {
+        let mut inner = self.lock();
+        let names = layout.cameras();
+
+        if let Some(unknown) = names.iter().find(|name| {
+            !inner
+                .cameras
+                .iter()
+                .any(|camera| camera.config.name == **name)
+        }) {
+            return Err(format!("no camera `{unknown}`"));
+        }
+
+        if names.len() == 2 && names[0] == names[1] {
+            return Err(format!("`{layout}` should name two different cameras"));
+        }
+
+        log_event!(SUBSYSTEM, Level::Info, "switched to {layout}");
+        inner.layout = Some(layout);
+        Ok(())
+    }
+
+    /// Returns the view of the current layout.
+    ///
+    /// If a secondary camera fails, the view falls back to the first camera
+    /// alone rather than stopping the stream.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there are no cameras, or the
+    /// first camera in the layout cannot be read.
+    pub fn frame(&self) -> Result<RgbImage, Error> {
+        let mut inner = self.lock();
+        let layout = inner
+            .layout
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no cameras"))?;
+        let names = layout.cameras();
+        let first = inner.capture(names[0])?;
+        let second = match names.get(1).map(|name| inner.capture(name)) {
+            Some(Err(error)) => {
+                log_event!(SUBSYSTEM, Level::Warn, "{} failed: {error}", names[1]);
+                return Ok(first);
+            }
+            Some(Ok(frame)) => frame,
+            None => return Ok(first),
+        };
+
+        Ok(match layout {
+            Layout::Single(_) => first,
+            Layout::PictureInPicture(..) => {
+                let width = (first.width / INSET_SCALE).max(1);
+                let height = (second.height * width / second.width.max(1)).max(1);
+                let mut view = first;
+                let x = view.width.saturating_sub(width + INSET_MARGIN);
+                let y = view.height.saturating_sub(height + INSET_MARGIN);
+                blit(&mut view, &scale(&second, width, height), x, y);
+                view
+            }
+            Layout::SideBySide(..) => {
+                let half = first.width / 2;
+                let mut view = RgbImage::new(half * 2, first.height);
+                blit(&mut view, &scale(&first, half, first.height), 0, 0);
+                blit(&mut view, &scale(&second, half, first.height), half, 0);
+                view
+            }
+        })
+    }
+
+    /// Adds routes to `api` for listing the cameras and switching the
+    /// layout:
+    ///
+    /// * `GET /cameras` returns every camera and the layout.
+    /// * `POST /cameras/layout` with `{"layout": "front+rear"}` switches it.
+    pub fn route(&self, api: Api) -> Api {
+        let rig = self.clone();
+        let api = api.route("GET", "/cameras", move |_| Response::json(&rig));
+        let rig = self.clone();
+        api.route("POST", "/cameras/layout", move |request| {
+            let layout = request.json().and_then(|body| {
+                body.get("layout")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| "request should have a layout".to_owned())?
+                    .parse()
+            });
+
+            match layout.and_then(|layout| rig.set_layout(layout)) {
+                Ok(()) => Response::json(&rig),
+                Err(error) => Response::error(400, &error),
+            }
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CameraRig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("CameraRig")
+            .field("cameras", &inner.cameras.len())
+            .field("layout", &inner.layout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ToJson for CameraRig {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("cameras", self.cameras())
+            .with("layout", self.layout().map(|layout| layout.to_string()))
+    }
+}
+
+impl Inner {
+    fn capture(&mut self, name: &str) -> Result<RgbImage, Error> {
+        let camera = self
+            .cameras
+            .iter_mut()
+            .find(|camera| camera.config.name == name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no camera `{name}`")))?;
+        let frame = camera.source.frame()?;
+
+        Ok(if camera.config.flip {
+            RgbImage {
+                pixels: frame
+                    .pixels
+                    .chunks_exact(3)
+                    .rev()
+                    .flatten()
+                    .copied()
+                    .collect(),
+                ..frame
+            }
+        } else {
+            frame
+        })
+    }
+}
+
+/// Returns `image` scaled to `width` by `height` by sampling the nearest
+/// pixels.
+fn scale(image: &RgbImage, width: usize, height: usize) -> RgbImage {
+    let mut scaled = RgbImage::new(width, height);
+
+    for y in 0..height {
+        for x in 0..width {
+            if let Some(color) = image.pixel(x * image.width / width, y * image.height / height) {
+                scaled.set_pixel(x, y, color);
+            }
+        }
+    }
+
+    scaled
+}
+
+/// Copies `source` into `target` with its top left corner at `x` and `y`.
+fn blit(target: &mut RgbImage, source: &RgbImage, x: usize, y: usize) {
+    for row in 0..source.height {
+        for column in 0..source.width {
+            if let Some(color) = source.pixel(column, row) {
+                target.set_pixel(x + column, y + row, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::http::{Request, Router};
+
+    struct Solid([u8; 3]);
+
+    impl VideoCamera for Solid {
+        fn frame(&mut self) -> Result<RgbImage, Error> {
+            let mut image = RgbImage::new(64, 48);
+            image
+                .pixels
+                .chunks_exact_mut(3)
+                .for_each(|pixel| pixel.copy_from_slice(&self.0));
+            Ok(image)
+        }
+    }
+
+    struct Unplugged;
+
+    impl VideoCamera for Unplugged {
+        fn frame(&mut self) -> Result<RgbImage, Error> {
+            Err(Error::from(ErrorKind::NotConnected))
+        }
+    }
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    fn rig() -> CameraRig {
+        CameraRig::new()
+            .with_camera(CameraConfig::new("front", "video0"), Solid(RED))
+            .with_camera(CameraConfig::new("rear", "video2"), Solid(BLUE))
+            .with_camera(CameraConfig::new("gripper", "video4"), Unplugged)
+    }
+
+    #[test]
+    fn it_should_parse_layouts() {
+        assert_eq!("front".parse(), Ok(Layout::Single("front".to_owned())));
+        assert_eq!(
+            "front + rear".parse(),
+            Ok(Layout::PictureInPicture(
+                "front".to_owned(),
+                "rear".to_owned()
+            ))
+        );
+        assert_eq!(
+            "front|rear"
+                .parse::<Layout>()
+                .map(|layout| layout.to_string()),
+            Ok("front|rear".to_owned())
+        );
+        assert!("front+".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn it_should_compose_the_view_from_the_layout() {
+        let rig = rig();
+        assert_eq!(rig.frame().unwrap().pixel(40, 40), Some(RED));
+
+        rig.set_layout("front+rear".parse().unwrap()).unwrap();
+        let view = rig.frame().unwrap();
+        assert_eq!((view.width, view.height), (64, 48));
+        assert_eq!(view.pixel(0, 0), Some(RED));
+        assert_eq!(view.pixel(50, 36), Some(BLUE));
+
+        rig.set_layout("front|rear".parse().unwrap()).unwrap();
+        let view = rig.frame().unwrap();
+        assert_eq!(view.pixel(10, 10), Some(RED));
+        assert_eq!(view.pixel(50, 10), Some(BLUE));
+
+        rig.set_layout("rear+gripper".parse().unwrap()).unwrap();
+        assert_eq!(rig.frame().unwrap().pixel(50, 36), Some(BLUE));
+        assert!(rig.set_layout("front+front".parse().unwrap()).is_err());
+        assert!(rig.set_layout("arm".parse().unwrap()).is_err());
+        rig.set_layout("gripper".parse().unwrap()).unwrap();
+        assert!(rig.frame().is_err());
+    }
+
+    #[test]
+    fn it_should_parse_configs() {
+        let config =
+            r#"{"name": "rear", "device": "video2", "width": 320, "height": 240, "flip": true}"#
+                .parse()
+                .unwrap();
+        assert_eq!(
+            CameraConfig::from_json(&config),
+            Ok(CameraConfig::new("rear", "video2")
+                .with_size(320, 240)
+                .with_flip(true))
+        );
+        assert!(CameraConfig::from_json(&Value::object()).is_err());
+    }
+
+    #[test]
+    fn it_should_switch_layouts_through_the_api() {
+        let router = Router::new().with_api(1, rig().route(Api::new()));
+        let mut request = Request::new("POST", "/api/v1/cameras/layout");
+        request.body = br#"{"layout": "rear|front"}"#.to_vec();
+        let response = router.handle(&request);
+        assert_eq!(response.status, 200);
+        let body: Value = std::str::from_utf8(&response.body)
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            body.get("layout").and_then(Value::as_str),
+            Some("rear|front")
+        );
+
+        request.body = br#"{"layout": "arm"}"#.to_vec();
+        assert_eq!(router.handle(&request).status, 400);
+        let response = router.handle(&Request::new("GET", "/api/v1/cameras"));
+        assert_eq!(response.status, 200);
+    }
+}
@@ -0,0 +1,10 @@
+//! Feedback controllers and motion planning.
+
+pub mod autotune;
+pub mod balance;
+pub mod mux;
+pub mod pid;
+pub mod trajectory;
+
+pub use mux::CommandMux;
+pub use pid::Pid;
@@ -0,0 +1,366 @@
+//! Relay-feedback auto-tuning after Åström and Hägglund.
+//!
+//! Replacing the controller with a relay that switches its output whenever the
+//! measurement crosses the setpoint drives most loops into a steady
+//! oscillation at their ultimate period. The amplitude of that oscillation
+//! gives the ultimate gain, from which classic tuning rules suggest gains.
+
+use std::f64::consts::PI;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use super::pid::Gains;
+use crate::params::{ParamError, ParamServer, ParamValue};
+
+/// Rule used to turn the ultimate gain and period into suggested gains.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TuningRule {
+    /// The classic Ziegler–Nichols PID rule, which responds quickly but
+    /// overshoots.
+    #[default]
+    ZieglerNichols,
+    /// The Ziegler–Nichols PI rule, usually the better choice for velocity
+    /// loops, where a derivative term amplifies encoder noise.
+    ZieglerNicholsPi,
+    /// The Tyreus–Luyben rule, which is more conservative and overshoots
+    /// less.
+    TyreusLuyben,
+}
+
+impl TuningRule {
+    /// Returns the gains suggested for an ultimate gain `ku` and an ultimate
+    /// period of `tu` seconds.
+    #[must_use]
+    pub fn gains(self, ku: f64, tu: f64) -> Gains {
+        let (kp, ti, td) = match self {
+            Self::ZieglerNichols => (0.6 * ku, tu / 2.0, tu / 8.0),
+            Self::ZieglerNicholsPi => (0.45 * ku, tu / 1.2, 0.0),
+            Self::TyreusLuyben => (ku / 2.2, 2.2 * tu, tu / 6.3),
+        };
+        Gains::new(kp, kp / ti, kp * td)
+    }
+}
+
+/// Configuration of a relay auto-tune.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutotuneConfig {
+    /// Output around which the relay switches, such as the feedforward
+    /// needed to hold the setpoint.
+    pub bias: f64,
+    /// Distance of the relay output either side of the bias. Larger
+    /// amplitudes swamp noise but excite the mechanism harder.
+    pub amplitude: f64,
+    /// Distance the measurement must cross past the setpoint before the relay
+    /// switches, which stops noise from chattering the relay.
+    pub hysteresis: f64,
+    /// Number of oscillation cycles averaged after the first, which is
+    /// discarded as a transient.
+    pub cycles: usize,
+    /// Time after which tuning gives up if the loop has not oscillated.
+    pub timeout: Duration,
+    /// Rule used to suggest gains.
+    pub rule: TuningRule,
+}
+
+impl Default for AutotuneConfig {
+    fn default() -> Self {
+        Self {
+            bias: 0.0,
+            amplitude: 1.0,
+            hysteresis: 0.0,
+            cycles: 4,
+            timeout: Duration::from_secs(30),
+            rule: TuningRule::default(),
+        }
+    }
+}
+
+/// Outcome of a successful auto-tune.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TuningResult {
+    /// Proportional gain at which the loop oscillates steadily.
+    pub ultimate_gain: f64,
+    /// Period of the steady oscillation.
+    pub ultimate_period: Duration,
+    /// Gains suggested by the configured rule.
+    pub suggested: Gains,
+}
+
+impl TuningResult {
+    /// Writes the suggested gains to the float parameters `{prefix}.kp`,
+    /// `{prefix}.ki`, and `{prefix}.kd`, which must already be registered.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any parameter cannot be set.
+    pub fn write_to(&self, params: &ParamServer, prefix: &str) -> Result<(), ParamError> {
+        let Gains { kp, ki, kd } = self.suggested;
+
+        for (name, value) in [("kp", kp), ("ki", ki), ("kd", kd)] {
+            params.set(&format!("{prefix}.{name}"), ParamValue::Float(value))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Error returned when an auto-tune fails.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AutotuneError {
+    /// The loop did not complete enough oscillations before the timeout.
+    Timeout,
+    /// The oscillation was too small to measure against the hysteresis.
+    NoOscillation,
+}
+
+impl Display for AutotuneError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("auto-tune timed out before the loop oscillated"),
+            Self::NoOscillation => f.write_str("auto-tune oscillation was too small to measure"),
+        }
+    }
+}
+
+impl std::error::Error for AutotuneError {}
+
+/// State of a relay auto-tune in progress.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelayAutotune {
+    amplitudes: Vec<f64>,
+    config: AutotuneConfig,
+    cycle_max: f64,
+    cycle_min: f64,
+    elapsed: Duration,
+    last_rising_switch: Option<Duration>,
+    output_high: bool,
+    periods: Vec<f64>,
+    setpoint: f64,
+}
+
+impl RelayAutotune {
+    /// Starts an auto-tune that oscillates around `setpoint`.
+    pub fn new(setpoint: f64, config: AutotuneConfig) -> Self {
+        Self {
+            amplitudes: Vec::new(),
+            config,
+            cycle_max: f64::NEG_INFINITY,
+            cycle_min: f64::INFINITY,
+            elapsed: Duration::ZERO,
+            last_rising_switch: None,
+            output_high: true,
+            periods: Vec::new(),
+            setpoint,
+        }
+    }
+
+    /// Returns the relay output for `measurement`, taken `dt` after the
+    /// previous one, or `None` once enough cycles have been measured.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`AutotuneError::Timeout`] if the timeout
+    /// elapses first.
+    pub fn update(&mut self, measurement: f64, dt: Duration) -> Result<Option<f64>, AutotuneError> {
+        if self.is_finished() {
+            return Ok(None);
+        }
+
+        self.elapsed += dt;
+
+        if self.elapsed > self.config.timeout {
+            return Err(AutotuneError::Timeout);
+        }
+
+        self.cycle_max = self.cycle_max.max(measurement);
+        self.cycle_min = self.cycle_min.min(measurement);
+
+        if self.output_high && measurement > self.setpoint + self.config.hysteresis {
+            self.output_high = false;
+        } else if !self.output_high && measurement < self.setpoint - self.config.hysteresis {
+            self.output_high = true;
+
+            if let Some(last) = self.last_rising_switch {
+                self.periods.push((self.elapsed - last).as_secs_f64());
+                self.amplitudes
+                    .push((self.cycle_max - self.cycle_min) / 2.0);
+            }
+
+            self.last_rising_switch = Some(self.elapsed);
+            self.cycle_max = measurement;
+            self.cycle_min = measurement;
+
+            if self.is_finished() {
+                return Ok(None);
+            }
+        }
+
+        let offset = if self.output_high {
+            self.config.amplitude
+        } else {
+            -self.config.amplitude
+        };
+        Ok(Some(self.config.bias + offset))
+    }
+
+    /// Returns `true` once enough cycles have been measured.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.periods.len() > self.config.cycles
+    }
+
+    /// Returns the measured ultimate gain and period and the suggested gains.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`AutotuneError::Timeout`] if tuning has not
+    /// finished, or [`AutotuneError::NoOscillation`] if the oscillation was no
+    /// larger than the hysteresis.
+    pub fn result(&self) -> Result<TuningResult, AutotuneError> {
+        if !self.is_finished() {
+            return Err(AutotuneError::Timeout);
+        }
+
+        // The first cycle is a transient from wherever the loop started.
+        let cycles = self.periods.len() - 1;
+        let period = self.periods[1..].iter().sum::<f64>() / cycles as f64;
+        let amplitude = self.amplitudes[1..].iter().sum::<f64>() / cycles as f64;
+        let hysteresis = self.config.hysteresis;
+
+        if amplitude <= hysteresis {
+            return Err(AutotuneError::NoOscillation);
+        }
+
+        let ultimate_gain =
+            4.0 * self.config.amplitude / (PI * (amplitude.powi(2) - hysteresis.powi(2)).sqrt());
+        Ok(TuningResult {
+            ultimate_gain,
+            ultimate_period: Duration::from_secs_f64(period),
+            suggested: self.config.rule.gains(ultimate_gain, period),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::control::pid::Pid;
+    use crate::params::ParamSpec;
+
+    const DT: Duration = Duration::from_millis(1);
+
+    /// A first-order plant with a dead time, like a wheel whose speed lags
+    /// the motor command.
+    struct Plant {
+        delay: VecDeque<f64>,
+        output: f64,
+        time_constant: f64,
+    }
+
+    impl Plant {
+        fn new(time_constant: f64, dead_time: Duration) -> Self {
+            let samples = (dead_time.as_secs_f64() / DT.as_secs_f64()) as usize;
+            Self {
+                delay: VecDeque::from(vec![0.0; samples]),
+                output: 0.0,
+                time_constant,
+            }
+        }
+
+        fn step(&mut self, input: f64) -> f64 {
+            self.delay.push_back(input);
+            let input = self.delay.pop_front().unwrap_or(input);
+            self.output += (input - self.output) * DT.as_secs_f64() / self.time_constant;
+            self.output
+        }
+    }
+
+    #[test]
+    fn it_should_measure_the_ultimate_gain_and_period_of_a_plant() {
+        let mut plant = Plant::new(0.5, Duration::from_millis(50));
+        let mut pid = Pid::new(Gains::default());
+        pid.start_autotune(AutotuneConfig::default());
+        let mut measurement = 0.0;
+
+        while pid.is_autotuning() {
+            measurement = plant.step(pid.update(measurement, DT));
+        }
+
+        let result = pid.autotune_result().unwrap().unwrap();
+        // Analytically, this plant has an ultimate period of about 0.19 s and
+        // an ultimate gain of about 16; the relay approximation lands close.
+        let period = result.ultimate_period.as_secs_f64();
+        assert!((0.15..0.25).contains(&period), "{period}");
+        assert!((10.0..25.0).contains(&result.ultimate_gain));
+        assert_eq!(pid.gains(), Gains::default());
+    }
+
+    #[test]
+    fn it_should_suggest_gains_that_track_the_setpoint() {
+        let mut plant = Plant::new(0.5, Duration::from_millis(50));
+        let mut pid = Pid::new(Gains::default()).with_output_limits(-20.0, 20.0);
+        pid.start_autotune(AutotuneConfig {
+            rule: TuningRule::TyreusLuyben,
+            ..AutotuneConfig::default()
+        });
+        let mut measurement = 0.0;
+
+        while pid.is_autotuning() {
+            measurement = plant.step(pid.update(measurement, DT));
+        }
+
+        let gains = pid.autotune_result().unwrap().unwrap().suggested;
+        pid.set_gains(gains);
+        pid.set_setpoint(1.0);
+
+        for _ in 0..5000 {
+            measurement = plant.step(pid.update(measurement, DT));
+        }
+
+        assert!((measurement - 1.0).abs() < 0.01, "{measurement}");
+    }
+
+    #[test]
+    fn it_should_time_out_when_the_loop_does_not_oscillate() {
+        let mut autotune = RelayAutotune::new(
+            1.0,
+            AutotuneConfig {
+                timeout: Duration::from_millis(10),
+                ..AutotuneConfig::default()
+            },
+        );
+        let result = (0..20).try_for_each(|_| autotune.update(0.0, DT).map(drop));
+        assert_eq!(result, Err(AutotuneError::Timeout));
+    }
+
+    #[test]
+    fn it_should_apply_the_tuning_rules() {
+        let gains = TuningRule::ZieglerNichols.gains(10.0, 2.0);
+        assert_eq!(gains, Gains::new(6.0, 6.0, 1.5));
+        let gains = TuningRule::ZieglerNicholsPi.gains(10.0, 1.2);
+        assert!((gains.ki - 4.5).abs() < 1e-12 && gains.kd == 0.0);
+    }
+
+    #[test]
+    fn it_should_write_suggested_gains_to_the_parameter_server() {
+        let params = ParamServer::new();
+        let kp = params
+            .register(ParamSpec::new("drive.left.kp", 0.0))
+            .unwrap();
+        params
+            .register(ParamSpec::new("drive.left.ki", 0.0))
+            .unwrap();
+        params
+            .register(ParamSpec::new("drive.left.kd", 0.0))
+            .unwrap();
+        let result = TuningResult {
+            ultimate_gain: 10.0,
+            ultimate_period: Duration::from_secs(2),
+            suggested: Gains::new(6.0, 6.0, 1.5),
+        };
+        result.write_to(&params, "drive.left").unwrap();
+        assert_eq!(kp.get(), 6.0);
+    }
+}
@@ -0,0 +1,298 @@
+//! Balancing a two-wheeled robot as an inverted pendulum.
+//!
+//! Two loops are cascaded: the outer one compares the wheel velocity from the
+//! encoders against the commanded velocity and chooses how far to lean, and
+//! the inner one drives the wheels under the robot to hold that lean, using
+//! the IMU’s pitch and pitch rate. Leaning is how the robot accelerates, so
+//! it rolls back slightly before setting off forward.
+
+use std::time::Duration;
+
+use super::pid::{Gains, Pid};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "balance";
+
+/// Slowest rate at which the controller can hold the robot upright, in
+/// hertz.
+pub const MIN_RATE: f64 = 200.0;
+
+/// Configuration of a [`BalanceController`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BalanceConfig {
+    /// Gains of the inner loop, from pitch error in radians to motor command.
+    /// The derivative gain acts on the gyro’s pitch rate.
+    pub pitch: Gains,
+    /// Gains of the outer loop, from velocity error in metres per second to
+    /// lean in radians.
+    pub velocity: Gains,
+    /// Largest lean the outer loop may ask for, in radians.
+    pub max_lean: f64,
+    /// Pitch beyond which the robot is taken to have fallen and the motors
+    /// are cut, in radians.
+    pub tilt_limit: f64,
+    /// Pitch within which the robot must be held before it can be armed, in
+    /// radians.
+    pub arming_angle: f64,
+    /// Pitch at which the robot balances with no lean, in radians, which
+    /// trims out an IMU not mounted quite level or a chassis heavier on one
+    /// side.
+    pub balance_point: f64,
+}
+
+impl Default for BalanceConfig {
+    fn default() -> Self {
+        Self {
+            pitch: Gains::new(3.0, 0.0, 0.3),
+            velocity: Gains::new(0.1, 0.02, 0.0),
+            max_lean: 0.15,
+            tilt_limit: 0.6,
+            arming_angle: 0.05,
+            balance_point: 0.0,
+        }
+    }
+}
+
+/// Measurements taken each control cycle.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BalanceInput {
+    /// Pitch from the IMU, in radians, positive leaning forward.
+    pub pitch: f64,
+    /// Pitch rate from the gyro, in radians per second.
+    pub pitch_rate: f64,
+    /// Mean forward velocity of the wheels from the encoders, in metres per
+    /// second.
+    pub velocity: f64,
+}
+
+/// State of a [`BalanceController`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum BalanceState {
+    /// The motors are off until the controller is armed.
+    #[default]
+    Disarmed,
+    /// The controller is holding the robot upright.
+    Balancing,
+    /// The robot tilted past the limit, so the motors were cut.
+    Fallen,
+}
+
+/// Cascaded pitch and velocity controller for a two-wheeled balancing robot.
+///
+/// Call [`BalanceController::update`] at [`MIN_RATE`] or faster with fresh
+/// measurements, and drive the wheels with the commands it returns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BalanceController {
+    config: BalanceConfig,
+    last_pitch: Option<f64>,
+    pitch: Pid,
+    state: BalanceState,
+    turn: f64,
+    velocity: Pid,
+}
+
+impl BalanceController {
+    /// Creates a new, disarmed `BalanceController`.
+    pub fn new(config: BalanceConfig) -> Self {
+        let inner = Gains {
+            kd: 0.0,
+            ..config.pitch
+        };
+        Self {
+            config,
+            last_pitch: None,
+            pitch: Pid::new(inner).with_output_limits(-1.0, 1.0),
+            state: BalanceState::Disarmed,
+            turn: 0.0,
+            velocity: Pid::new(config.velocity)
+                .with_output_limits(-config.max_lean, config.max_lean),
+        }
+    }
+
+    /// Returns the state of the controller.
+    #[must_use]
+    pub fn state(&self) -> BalanceState {
+        self.state
+    }
+
+    /// Starts balancing, and returns `true` if the last measured pitch was
+    /// within the arming angle of the balance point.
+    pub fn arm(&mut self) -> bool {
+        let upright = self.last_pitch.is_some_and(|pitch| {
+            (pitch - self.config.balance_point).abs() <= self.config.arming_angle
+        });
+
+        if upright {
+            self.pitch.reset();
+            self.velocity.reset();
+            self.state = BalanceState::Balancing;
+            log_event!(SUBSYSTEM, Level::Info, "armed");
+        }
+
+        upright
+    }
+
+    /// Stops balancing and cuts the motors.
+    pub fn disarm(&mut self) {
+        self.state = BalanceState::Disarmed;
+    }
+
+    /// Sets the forward velocity to hold, in metres per second.
+    pub fn set_velocity(&mut self, velocity: f64) {
+        self.velocity.set_setpoint(velocity);
+    }
+
+    /// Sets the difference between the wheel commands that turns the robot,
+    /// positive turning counterclockwise.
+    pub fn set_turn(&mut self, turn: f64) {
+        self.turn = turn;
+    }
+
+    /// Returns the `[left, right]` motor commands, from -1 to 1, for the
+    /// measurements taken `dt` after the previous ones.
+    pub fn update(&mut self, input: &BalanceInput, dt: Duration) -> [f64; 2] {
+        self.last_pitch = Some(input.pitch);
+
+        if self.state != BalanceState::Balancing {
+            return [0.0; 2];
+        }
+
+        if (input.pitch - self.config.balance_point).abs() > self.config.tilt_limit {
+            self.state = BalanceState::Fallen;
+            log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "cut the motors at a pitch of {:.2} rad",
+                input.pitch
+            );
+            return [0.0; 2];
+        }
+
+        if dt.as_secs_f64() * MIN_RATE > 1.5 {
+            log_event!(
+                SUBSYSTEM,
+                Level::Debug,
+                "control cycle took {dt:?}, slower than {MIN_RATE} Hz"
+            );
+        }
+
+        let lean = self.velocity.update(input.velocity, dt);
+        self.pitch.set_setpoint(self.config.balance_point + lean);
+        // Driving the wheels forward under a forward lean rights the robot,
+        // which is the opposite sense to the PID’s setpoint error.
+        let drive = (-self.pitch.update(input.pitch, dt) + self.config.pitch.kd * input.pitch_rate)
+            .clamp(-1.0, 1.0);
+        [
+            (drive - self.turn).clamp(-1.0, 1.0),
+            (drive + self.turn).clamp(-1.0, 1.0),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: Duration = Duration::from_millis(5);
+
+    /// A pendulum on wheels that accelerate in proportion to their command.
+    #[derive(Debug, Default)]
+    struct Robot {
+        pitch: f64,
+        pitch_rate: f64,
+        velocity: f64,
+    }
+
+    impl Robot {
+        const GRAVITY_OVER_LENGTH: f64 = 49.0;
+        const ACCELERATION_PER_COMMAND: f64 = 10.0;
+        const LENGTH: f64 = 0.2;
+
+        fn input(&self) -> BalanceInput {
+            BalanceInput {
+                pitch: self.pitch,
+                pitch_rate: self.pitch_rate,
+                velocity: self.velocity,
+            }
+        }
+
+        fn step(&mut self, [left, right]: [f64; 2]) {
+            let dt = DT.as_secs_f64();
+            let acceleration = (left + right) / 2.0 * Self::ACCELERATION_PER_COMMAND;
+            let angular_acceleration =
+                Self::GRAVITY_OVER_LENGTH * self.pitch - acceleration / Self::LENGTH;
+            self.pitch_rate += angular_acceleration * dt;
+            self.pitch += self.pitch_rate * dt;
+            self.velocity += acceleration * dt;
+        }
+    }
+
+    fn run(controller: &mut BalanceController, robot: &mut Robot, seconds: u32) {
+        for _ in 0..seconds * 200 {
+            let commands = controller.update(&robot.input(), DT);
+            robot.step(commands);
+        }
+    }
+
+    fn armed(robot: &Robot) -> BalanceController {
+        let mut controller = BalanceController::new(BalanceConfig::default());
+        controller.update(&robot.input(), DT);
+        assert!(controller.arm());
+        controller
+    }
+
+    #[test]
+    fn it_should_recover_from_a_push() {
+        let mut robot = Robot {
+            pitch: 0.04,
+            ..Robot::default()
+        };
+        let mut controller = armed(&robot);
+        run(&mut controller, &mut robot, 10);
+        assert_eq!(controller.state(), BalanceState::Balancing);
+        assert!(robot.pitch.abs() < 0.01, "{robot:?}");
+        assert!(robot.velocity.abs() < 0.05, "{robot:?}");
+    }
+
+    #[test]
+    fn it_should_drive_at_the_commanded_velocity() {
+        let mut robot = Robot::default();
+        let mut controller = armed(&robot);
+        controller.set_velocity(0.3);
+        run(&mut controller, &mut robot, 15);
+        assert!((robot.velocity - 0.3).abs() < 0.05, "{robot:?}");
+    }
+
+    #[test]
+    fn it_should_cut_the_motors_past_the_tilt_limit() {
+        let mut controller = armed(&Robot::default());
+        let fallen = BalanceInput {
+            pitch: -0.8,
+            ..BalanceInput::default()
+        };
+        assert_eq!(controller.update(&fallen, DT), [0.0; 2]);
+        assert_eq!(controller.state(), BalanceState::Fallen);
+        assert!(!controller.arm());
+    }
+
+    #[test]
+    fn it_should_stay_off_until_armed() {
+        let mut controller = BalanceController::new(BalanceConfig::default());
+        let tilted = BalanceInput {
+            pitch: 0.1,
+            ..BalanceInput::default()
+        };
+        assert_eq!(controller.update(&tilted, DT), [0.0; 2]);
+        assert!(!controller.arm());
+        assert_eq!(controller.state(), BalanceState::Disarmed);
+    }
+
+    #[test]
+    fn it_should_turn_by_splitting_the_wheel_commands() {
+        let mut controller = armed(&Robot::default());
+        controller.set_turn(0.2);
+        let [left, right] = controller.update(&BalanceInput::default(), DT);
+        assert!((right - left - 0.4).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,317 @@
+//! Arbitration between sources commanding the same actuators.
+//!
+//! The planner, the gamepad, and the safety reflexes all want to drive the
+//! wheels at once. Each source submits commands to a [`CommandMux`], which
+//! passes on the command of the highest-priority source that has spoken
+//! recently. A source that falls silent for longer than its timeout, such as
+//! a gamepad that lost its connection, hands control back to the sources
+//! below it.
+//!
+//! Each command carries a [`CommandTrace`], which the mux stamps when it
+//! first selects the command and again when the caller reports writing it to
+//! the actuators.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::diagnostics::latency::{CommandTrace, Stage};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "mux";
+
+/// Priority of a command source, from lowest to highest.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    /// What to do when nobody is commanding, such as holding still.
+    Idle,
+    /// Autonomous behaviors, such as the planner.
+    Autonomy,
+    /// A human driving remotely.
+    Teleop,
+    /// Safety reflexes, which override everything.
+    Safety,
+}
+
+/// An error submitting to a [`CommandMux`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum MuxError {
+    /// No source was registered under the name.
+    UnknownSource(String),
+}
+
+impl Display for MuxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownSource(name) => write!(f, "unknown command source `{name}`"),
+        }
+    }
+}
+
+impl Error for MuxError {}
+
+#[derive(Clone, Debug)]
+struct Submission<T> {
+    command: T,
+    submitted: Instant,
+    trace: CommandTrace,
+}
+
+#[derive(Clone, Debug)]
+struct Source<T> {
+    command: Option<Submission<T>>,
+    name: String,
+    priority: Priority,
+    timeout: Duration,
+}
+
+/// Passes on the command of the highest-priority source that has commanded
+/// within its timeout.
+///
+/// Sources of equal priority are ranked in the order they were added.
+#[derive(Clone, Debug)]
+pub struct CommandMux<T> {
+    active: Option<usize>,
+    sources: Vec<Source<T>>,
+}
+
+impl<T> CommandMux<T> {
+    /// Creates a new `CommandMux` without any sources.
+    pub fn new() -> Self {
+        Self {
+            active: None,
+            sources: Vec::new(),
+        }
+    }
+
+    /// Adds a source named `name`, whose commands expire `timeout` after
+    /// they are submitted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a source named `name` was already added.
+    #[must_use]
+    pub fn with_source(mut self, name: &str, priority: Priority, timeout: Duration) -> Self {
+        assert!(
+            self.position(name).is_none(),
+            "source `{name}` should only be added once"
+        );
+        // Keep the sources sorted from highest priority, after any others of
+        // the same priority.
+        let index = self
+            .sources
+            .iter()
+            .position(|source| source.priority < priority)
+            .unwrap_or(self.sources.len());
+
+        if let Some(active) = self.active.as_mut().filter(|active| index <= **active) {
+            *active += 1;
+        }
+
+        self.sources.insert(
+            index,
+            Source {
+                command: None,
+                name: name.to_owned(),
+                priority,
+                timeout,
+            },
+        );
+        self
+    }
+
+    /// Submits a `command` from `source` at `now`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no source is named `source`.
+    pub fn submit(&mut self, source: &str, command: T, now: Instant) -> Result<(), MuxError> {
+        self.submit_traced(source, command, CommandTrace::received_at(now), now)
+    }
+
+    /// Submits a `command` from `source` at `now`, continuing `trace`, which
+    /// was started when the command was received.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no source is named `source`.
+    pub fn submit_traced(
+        &mut self,
+        source: &str,
+        command: T,
+        trace: CommandTrace,
+        now: Instant,
+    ) -> Result<(), MuxError> {
+        let index = self
+            .position(source)
+            .ok_or_else(|| MuxError::UnknownSource(source.to_owned()))?;
+        self.sources[index].command = Some(Submission {
+            command,
+            submitted: now,
+            trace,
+        });
+        Ok(())
+    }
+
+    /// Withdraws the command of `source`, handing control back without
+    /// waiting for its timeout.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no source is named `source`.
+    pub fn release(&mut self, source: &str) -> Result<(), MuxError> {
+        let index = self
+            .position(source)
+            .ok_or_else(|| MuxError::UnknownSource(source.to_owned()))?;
+        self.sources[index].command = None;
+        Ok(())
+    }
+
+    /// Returns the name of the source whose command was last selected.
+    #[must_use]
+    pub fn active(&self) -> Option<&str> {
+        self.active.map(|index| self.sources[index].name.as_str())
+    }
+
+    /// Returns the command to follow at `now`, or `None` if every source is
+    /// silent.
+    pub fn select(&mut self, now: Instant) -> Option<&T> {
+        let active = self.sources.iter().position(|source| {
+            source.command.as_ref().is_some_and(|submission| {
+                now.saturating_duration_since(submission.submitted) <= source.timeout
+            })
+        });
+
+        if active != self.active {
+            let name = |index: Option<usize>| index.map_or("nobody", |i| &self.sources[i].name);
+            log_event!(
+                SUBSYSTEM,
+                Level::Info,
+                "control passed from {} to {}",
+                name(self.active),
+                name(active)
+            );
+            self.active = active;
+        }
+
+        let submission = self.sources[active?].command.as_mut()?;
+
+        if submission.trace.instant(Stage::Selected).is_none() {
+            submission.trace.mark_at(Stage::Selected, now);
+        }
+
+        Some(&submission.command)
+    }
+
+    /// Records that the command last selected was written to the actuators
+    /// at `now`, returning its completed trace the first time it is written.
+    pub fn actuated(&mut self, now: Instant) -> Option<CommandTrace> {
+        let submission = self.sources[self.active?].command.as_mut()?;
+
+        if submission.trace.instant(Stage::Actuated).is_some() {
+            return None;
+        }
+
+        submission.trace.mark_at(Stage::Actuated, now);
+        Some(submission.trace)
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.sources.iter().position(|source| source.name == name)
+    }
+}
+
+impl<T> Default for CommandMux<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn mux() -> CommandMux<f64> {
+        CommandMux::new()
+            .with_source("planner", Priority::Autonomy, TIMEOUT)
+            .with_source("idle", Priority::Idle, Duration::MAX)
+            .with_source("reflex", Priority::Safety, TIMEOUT)
+            .with_source("gamepad", Priority::Teleop, TIMEOUT)
+    }
+
+    #[test]
+    fn it_should_follow_the_highest_priority_source() {
+        let now = Instant::now();
+        let mut mux = mux();
+        assert_eq!(mux.select(now), None);
+        mux.submit("idle", 0.0, now).unwrap();
+        mux.submit("planner", 0.3, now).unwrap();
+        assert_eq!(mux.select(now), Some(&0.3));
+        mux.submit("gamepad", 0.8, now).unwrap();
+        assert_eq!(mux.select(now), Some(&0.8));
+        mux.submit("reflex", -0.1, now).unwrap();
+        assert_eq!(mux.select(now), Some(&-0.1));
+        assert_eq!(mux.active(), Some("reflex"));
+    }
+
+    #[test]
+    fn it_should_hand_back_control_when_a_source_times_out_or_releases() {
+        let start = Instant::now();
+        let mut mux = mux();
+        mux.submit("idle", 0.0, start).unwrap();
+        mux.submit("planner", 0.3, start).unwrap();
+        mux.submit("gamepad", 0.8, start + Duration::from_millis(200))
+            .unwrap();
+        assert_eq!(mux.select(start + Duration::from_millis(600)), Some(&0.8));
+        assert_eq!(mux.select(start + Duration::from_millis(800)), Some(&0.0));
+        mux.submit("planner", 0.3, start + Duration::from_millis(800))
+            .unwrap();
+        mux.release("planner").unwrap();
+        assert_eq!(mux.select(start + Duration::from_millis(800)), Some(&0.0));
+        assert_eq!(mux.active(), Some("idle"));
+    }
+
+    #[test]
+    fn it_should_trace_a_command_from_receipt_to_actuation() {
+        let received = Instant::now();
+        let selected = received + Duration::from_millis(3);
+        let mut mux = mux();
+        mux.submit_traced(
+            "gamepad",
+            0.8,
+            CommandTrace::received_at(received),
+            received + Duration::from_millis(1),
+        )
+        .unwrap();
+        assert_eq!(mux.actuated(selected), None);
+        assert_eq!(mux.select(selected), Some(&0.8));
+        assert_eq!(mux.select(selected + Duration::from_millis(10)), Some(&0.8));
+        let trace = mux.actuated(selected + Duration::from_millis(2)).unwrap();
+        assert_eq!(trace.instant(Stage::Selected), Some(selected));
+        assert_eq!(trace.end_to_end(), Some(Duration::from_millis(5)));
+        assert_eq!(mux.actuated(selected + Duration::from_millis(20)), None);
+    }
+
+    #[test]
+    fn it_should_keep_the_active_source_when_a_source_is_added_before_it() {
+        let now = Instant::now();
+        let mut mux = mux();
+        mux.submit("planner", 0.3, now).unwrap();
+        assert_eq!(mux.select(now), Some(&0.3));
+        let mut mux = mux.with_source("operator", Priority::Teleop, TIMEOUT);
+        assert_eq!(mux.active(), Some("planner"));
+        let trace = mux.actuated(now).unwrap();
+        assert_eq!(trace.instant(Stage::Selected), Some(now));
+    }
+
+    #[test]
+    fn it_should_reject_unknown_sources() {
+        assert_eq!(
+            mux().submit("joystick", 1.0, Instant::now()),
+            Err(MuxError::UnknownSource("joystick".to_owned()))
+        );
+    }
+}
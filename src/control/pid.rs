@@ -0,0 +1,208 @@
+//! Proportional–integral–derivative controller.
+
+use std::time::Duration;
+
+use super::autotune::{AutotuneConfig, AutotuneError, RelayAutotune, TuningResult};
+
+/// Gains of a PID controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Gains {
+    /// Proportional gain.
+    pub kp: f64,
+    /// Integral gain, per second.
+    pub ki: f64,
+    /// Derivative gain, in seconds.
+    pub kd: f64,
+}
+
+impl Gains {
+    /// Creates new gains.
+    pub fn new(kp: f64, ki: f64, kd: f64) -> Self {
+        Self { kp, ki, kd }
+    }
+}
+
+/// A PID controller with output limits and integral anti-windup.
+///
+/// The derivative term acts on the measurement rather than the error, so a
+/// step in the setpoint does not kick the output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pid {
+    autotune: Option<RelayAutotune>,
+    gains: Gains,
+    integral: f64,
+    last_result: Option<Result<TuningResult, AutotuneError>>,
+    max_output: f64,
+    min_output: f64,
+    previous_measurement: Option<f64>,
+    setpoint: f64,
+}
+
+impl Pid {
+    /// Creates a new `Pid` with a setpoint of zero and unlimited output.
+    pub fn new(gains: Gains) -> Self {
+        Self {
+            autotune: None,
+            gains,
+            integral: 0.0,
+            last_result: None,
+            max_output: f64::INFINITY,
+            min_output: f64::NEG_INFINITY,
+            previous_measurement: None,
+            setpoint: 0.0,
+        }
+    }
+
+    /// Limits the output to the range from `min` to `max`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` is greater than `max`.
+    #[must_use]
+    pub fn with_output_limits(mut self, min: f64, max: f64) -> Self {
+        assert!(min <= max, "min should not be greater than max");
+        self.min_output = min;
+        self.max_output = max;
+        self
+    }
+
+    /// Returns the gains.
+    #[must_use]
+    pub fn gains(&self) -> Gains {
+        self.gains
+    }
+
+    /// Replaces the gains, such as after a parameter change, keeping the
+    /// accumulated integral.
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.gains = gains;
+    }
+
+    /// Returns the setpoint.
+    #[must_use]
+    pub fn setpoint(&self) -> f64 {
+        self.setpoint
+    }
+
+    /// Sets the setpoint.
+    pub fn set_setpoint(&mut self, setpoint: f64) {
+        self.setpoint = setpoint;
+    }
+
+    /// Clears the integral and derivative history.
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.previous_measurement = None;
+    }
+
+    /// Returns the output for `measurement`, taken `dt` after the previous
+    /// one.
+    ///
+    /// While auto-tuning, the output is the relay’s instead, and normal
+    /// control resumes once tuning finishes.
+    pub fn update(&mut self, measurement: f64, dt: Duration) -> f64 {
+        if let Some(autotune) = &mut self.autotune {
+            match autotune.update(measurement, dt) {
+                Ok(Some(output)) => return output,
+                result => {
+                    self.last_result = Some(result.and_then(|_| autotune.result()));
+                    self.autotune = None;
+                    self.reset();
+                }
+            }
+        }
+
+        let dt = dt.as_secs_f64();
+        let error = self.setpoint - measurement;
+        let derivative = match self.previous_measurement {
+            Some(previous) if dt > 0.0 => -(measurement - previous) / dt,
+            _ => 0.0,
+        };
+        self.previous_measurement = Some(measurement);
+        let unclamped_integral = self.integral + error * dt;
+        let output =
+            self.gains.kp * error + self.gains.ki * unclamped_integral + self.gains.kd * derivative;
+
+        // Only integrate while the output is not saturated, or while the error
+        // is pulling it back out of saturation.
+        if (output < self.max_output || error < 0.0) && (output > self.min_output || error > 0.0) {
+            self.integral = unclamped_integral;
+        }
+
+        (self.gains.kp * error + self.gains.ki * self.integral + self.gains.kd * derivative)
+            .clamp(self.min_output, self.max_output)
+    }
+
+    /// Starts a relay-feedback auto-tune around the current setpoint.
+    ///
+    /// The relay drives the loop into a steady oscillation from which the
+    /// ultimate gain and period are measured, then normal control resumes
+    /// with unchanged gains. Apply the suggested gains from
+    /// [`Pid::autotune_result`] once satisfied with them.
+    pub fn start_autotune(&mut self, config: AutotuneConfig) {
+        self.autotune = Some(RelayAutotune::new(self.setpoint, config));
+        self.last_result = None;
+    }
+
+    /// Stops an auto-tune in progress without a result.
+    pub fn cancel_autotune(&mut self) {
+        self.autotune = None;
+        self.reset();
+    }
+
+    /// Returns `true` while an auto-tune is in progress.
+    #[must_use]
+    pub fn is_autotuning(&self) -> bool {
+        self.autotune.is_some()
+    }
+
+    /// Returns the outcome of the last auto-tune, once it has finished.
+    #[must_use]
+    pub fn autotune_result(&self) -> Option<&Result<TuningResult, AutotuneError>> {
+        self.last_result.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn it_should_act_proportionally_on_the_error() {
+        let mut pid = Pid::new(Gains::new(2.0, 0.0, 0.0));
+        pid.set_setpoint(1.0);
+        assert_eq!(pid.update(0.25, DT), 1.5);
+    }
+
+    #[test]
+    fn it_should_accumulate_the_integral_over_time() {
+        let mut pid = Pid::new(Gains::new(0.0, 1.0, 0.0));
+        pid.set_setpoint(1.0);
+        pid.update(0.0, Duration::from_secs(1));
+        assert_eq!(pid.update(0.0, Duration::from_secs(1)), 2.0);
+    }
+
+    #[test]
+    fn it_should_not_kick_the_derivative_on_a_setpoint_change() {
+        let mut pid = Pid::new(Gains::new(0.0, 0.0, 1.0));
+        pid.update(0.0, DT);
+        pid.set_setpoint(10.0);
+        assert_eq!(pid.update(0.0, DT), 0.0);
+        assert!((pid.update(0.1, DT) + 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_should_stop_integrating_while_saturated() {
+        let mut pid = Pid::new(Gains::new(1.0, 1.0, 0.0)).with_output_limits(-1.0, 1.0);
+        pid.set_setpoint(10.0);
+
+        for _ in 0..100 {
+            assert_eq!(pid.update(0.0, Duration::from_secs(1)), 1.0);
+        }
+
+        pid.set_setpoint(0.0);
+        assert!(pid.update(0.5, Duration::from_millis(1)) < 0.0);
+    }
+}
@@ -0,0 +1,305 @@
+//! Time-parameterized motion profiles.
+//!
+//! A [`Trajectory`] plans a point-to-point move from rest to rest that never
+//! exceeds its velocity, acceleration, or jerk [`Limits`], and is sampled at
+//! the control-loop rate to give each loop its setpoint. Positions are in
+//! whatever unit the caller chooses, such as metres for a drive or radians
+//! for an arm joint, with the limits in the same unit per second.
+
+use std::time::Duration;
+
+/// Limits on a motion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    /// Maximum speed, in units per second.
+    pub max_velocity: f64,
+    /// Maximum acceleration and deceleration, in units per second squared.
+    pub max_acceleration: f64,
+    /// Maximum jerk, in units per second cubed, or infinity for a
+    /// trapezoidal profile.
+    pub max_jerk: f64,
+}
+
+impl Limits {
+    /// Creates new limits for a trapezoidal profile.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either limit is not positive.
+    pub fn new(max_velocity: f64, max_acceleration: f64) -> Self {
+        assert!(max_velocity > 0.0, "max_velocity should be positive");
+        assert!(
+            max_acceleration > 0.0,
+            "max_acceleration should be positive"
+        );
+        Self {
+            max_velocity,
+            max_acceleration,
+            max_jerk: f64::INFINITY,
+        }
+    }
+
+    /// Limits the jerk, giving an S-curve profile whose acceleration ramps
+    /// rather than steps, which is gentler on gearboxes and payloads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_jerk` is not positive.
+    #[must_use]
+    pub fn with_max_jerk(mut self, max_jerk: f64) -> Self {
+        assert!(max_jerk > 0.0, "max_jerk should be positive");
+        self.max_jerk = max_jerk;
+        self
+    }
+}
+
+/// Setpoint at one instant of a trajectory.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct State {
+    /// Position, in units.
+    pub position: f64,
+    /// Velocity, in units per second.
+    pub velocity: f64,
+    /// Acceleration, in units per second squared.
+    pub acceleration: f64,
+}
+
+/// A span of a trajectory with constant jerk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Segment {
+    duration: f64,
+    initial_acceleration: f64,
+    jerk: f64,
+}
+
+/// A rest-to-rest move between two positions.
+///
+/// The profile accelerates to the highest speed the limits and distance
+/// allow, cruises, and decelerates symmetrically. Short moves never reach the
+/// maximum velocity and so skip the cruise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trajectory {
+    direction: f64,
+    duration: f64,
+    end: f64,
+    segments: Vec<Segment>,
+    start: f64,
+}
+
+impl Trajectory {
+    /// Plans a move from `start` to `end` within `limits`.
+    pub fn new(start: f64, end: f64, limits: Limits) -> Self {
+        let distance = (end - start).abs();
+        let Limits {
+            max_velocity,
+            max_acceleration: a,
+            max_jerk: j,
+        } = limits;
+        let mut segments = Vec::new();
+
+        if distance > 0.0 {
+            // The distance covered while accelerating to `v` and braking back
+            // to rest is v * (a / j + v / a) when the acceleration limit is
+            // reached, or 2 * v^1.5 / sqrt(j) when the jerk limit caps it
+            // first; solve whichever applies for the peak velocity.
+            let mut peak = a / 2.0 * (-a / j + ((a / j).powi(2) + 4.0 * distance / a).sqrt());
+
+            if peak * j < a * a {
+                peak = (distance * j.sqrt() / 2.0).powf(2.0 / 3.0);
+            }
+
+            let peak = peak.min(max_velocity);
+            let (ramp, peak_acceleration) = if peak * j >= a * a {
+                (a / j, a)
+            } else {
+                ((peak / j).sqrt(), (peak * j).sqrt())
+            };
+            let constant = (peak / peak_acceleration - ramp).max(0.0);
+            let accelerating = 2.0 * ramp + constant;
+            let cruise = ((distance - peak * accelerating) / peak).max(0.0);
+            let acceleration = [
+                (ramp, 0.0, j),
+                (constant, peak_acceleration, 0.0),
+                (ramp, peak_acceleration, -j),
+            ];
+            let deceleration =
+                acceleration.map(|(duration, initial, jerk)| (duration, -initial, -jerk));
+            segments = acceleration
+                .into_iter()
+                .chain([(cruise, 0.0, 0.0)])
+                .chain(deceleration)
+                .filter(|&(duration, _, _)| duration > 0.0)
+                .map(|(duration, initial_acceleration, jerk)| Segment {
+                    duration,
+                    initial_acceleration,
+                    // An infinite jerk over an empty ramp would be NaN.
+                    jerk: if jerk.is_finite() { jerk } else { 0.0 },
+                })
+                .collect();
+        }
+
+        Self {
+            direction: (end - start).signum(),
+            duration: segments.iter().map(|segment| segment.duration).sum(),
+            end,
+            segments,
+            start,
+        }
+    }
+
+    /// Returns the time the move takes.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.duration)
+    }
+
+    /// Returns the setpoint `time` after the move started, holding the end
+    /// position once it has finished.
+    #[must_use]
+    pub fn sample(&self, time: Duration) -> State {
+        let mut remaining = time.as_secs_f64();
+
+        if remaining >= self.duration {
+            return State {
+                position: self.end,
+                ..State::default()
+            };
+        }
+
+        let mut position = 0.0;
+        let mut velocity = 0.0;
+
+        for segment in &self.segments {
+            let t = remaining.min(segment.duration);
+            let Segment {
+                initial_acceleration: a,
+                jerk: j,
+                ..
+            } = *segment;
+            let acceleration = a + j * t;
+            position += velocity * t + a * t * t / 2.0 + j * t.powi(3) / 6.0;
+            velocity += a * t + j * t * t / 2.0;
+
+            if remaining <= segment.duration {
+                return State {
+                    position: self.start + self.direction * position,
+                    velocity: self.direction * velocity,
+                    acceleration: self.direction * acceleration,
+                };
+            }
+
+            remaining -= segment.duration;
+        }
+
+        // Rounding can leave a sliver of time past the last segment.
+        State {
+            position: self.end,
+            ..State::default()
+        }
+    }
+
+    /// Returns the setpoints every `period` from the start of the move to the
+    /// end, inclusive.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn samples(&self, period: Duration) -> impl Iterator<Item = State> + '_ {
+        assert!(!period.is_zero(), "period should not be zero");
+        let count = (self.duration / period.as_secs_f64()).ceil() as u32;
+        (0..=count).map(move |index| self.sample(period * index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(5);
+
+    fn assert_within_limits(trajectory: &Trajectory, limits: Limits) {
+        let samples: Vec<_> = trajectory.samples(PERIOD).collect();
+        let dt = PERIOD.as_secs_f64();
+
+        for pair in samples.windows(2) {
+            assert!(pair[1].velocity.abs() <= limits.max_velocity + 1e-9);
+            assert!(pair[1].acceleration.abs() <= limits.max_acceleration + 1e-9);
+            let speed = (pair[1].position - pair[0].position).abs() / dt;
+            assert!(speed <= limits.max_velocity + 1e-9, "{speed}");
+        }
+    }
+
+    #[test]
+    fn it_should_plan_a_trapezoid_for_long_moves() {
+        let limits = Limits::new(1.0, 2.0);
+        let trajectory = Trajectory::new(0.0, 3.0, limits);
+        // 0.5 s to accelerate, 2.5 m at 1 m/s, and 0.5 s to stop.
+        assert!((trajectory.duration().as_secs_f64() - 3.5).abs() < 1e-9);
+        let cruise = trajectory.sample(Duration::from_secs(1));
+        assert!((cruise.velocity - 1.0).abs() < 1e-9 && cruise.acceleration == 0.0);
+        assert!((cruise.position - 0.75).abs() < 1e-9);
+        assert_within_limits(&trajectory, limits);
+    }
+
+    #[test]
+    fn it_should_plan_a_triangle_for_short_moves() {
+        let limits = Limits::new(10.0, 1.0);
+        let trajectory = Trajectory::new(0.0, 1.0, limits);
+        assert!((trajectory.duration().as_secs_f64() - 2.0).abs() < 1e-9);
+        assert!((trajectory.sample(Duration::from_secs(1)).velocity - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_should_ramp_the_acceleration_when_jerk_is_limited() {
+        let limits = Limits::new(1.0, 2.0).with_max_jerk(8.0);
+        let trajectory = Trajectory::new(0.0, 3.0, limits);
+        let samples: Vec<_> = trajectory.samples(PERIOD).collect();
+
+        for pair in samples.windows(2) {
+            let jerk = (pair[1].acceleration - pair[0].acceleration).abs() / PERIOD.as_secs_f64();
+            assert!(jerk <= 8.0 + 1e-6, "{jerk}");
+        }
+
+        assert!(samples.iter().any(|state| state.acceleration == 2.0));
+        assert_within_limits(&trajectory, limits);
+    }
+
+    #[test]
+    fn it_should_limit_the_peak_acceleration_of_short_s_curves() {
+        let limits = Limits::new(10.0, 10.0).with_max_jerk(1.0);
+        let trajectory = Trajectory::new(0.0, 0.5, limits);
+        let end = trajectory.sample(trajectory.duration());
+        assert_eq!(end.position, 0.5);
+        assert_within_limits(&trajectory, limits);
+    }
+
+    #[test]
+    fn it_should_move_continuously_to_the_end() {
+        let limits = Limits::new(1.5, 3.0).with_max_jerk(20.0);
+        let trajectory = Trajectory::new(2.0, -1.0, limits);
+        let samples: Vec<_> = trajectory.samples(PERIOD).collect();
+        assert_eq!(samples[0].position, 2.0);
+        assert_eq!(
+            samples.last(),
+            Some(&State {
+                position: -1.0,
+                ..State::default()
+            })
+        );
+        assert!(samples.iter().all(|state| state.velocity <= 0.0));
+
+        for pair in samples.windows(2) {
+            assert!(
+                (pair[1].position - pair[0].position).abs() < 1.5 * PERIOD.as_secs_f64() + 1e-9
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_hold_still_for_an_empty_move() {
+        let trajectory = Trajectory::new(1.0, 1.0, Limits::new(1.0, 1.0));
+        assert_eq!(trajectory.duration(), Duration::ZERO);
+        assert_eq!(trajectory.samples(PERIOD).count(), 1);
+        assert_eq!(trajectory.sample(Duration::ZERO).position, 1.0);
+    }
+}
@@ -0,0 +1,518 @@
+//! A serial link to a microcontroller handling hard real-time tasks.
+//!
+//! Linux cannot promise to service a pin within microseconds, so an Arduino
+//! or RP2040 generates PWM and counts encoder edges instead, and talks to the
+//! Pi over a serial link. [`Coprocessor`] speaks the link’s protocol and
+//! hands out remote devices implementing the [`hal`](crate::hal) traits, so
+//! drivers use a remote PWM channel or encoder exactly as a local one.
+//!
+//! The host sends heartbeats, and the coprocessor is expected to stop its
+//! outputs if they stop arriving, so a crashed host does not leave the motors
+//! running.
+//!
+//! Reports are read by [`Coprocessor::poll`] itself, or, given a second
+//! handle to the port, by a receive thread that queues the bytes in a
+//! [`RingBuffer`] so that none are lost while the poll is late.
+
+#[cfg(unix)]
+use std::ffi::c_int;
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::iter;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::gpio::registry::{Claim, Line, Registry};
+use crate::hal::{DigitalOutput, Encoder, PwmOutput};
+use crate::log_event;
+use crate::logging::Level;
+use crate::protocol::framing::{Deframer, Framing};
+use crate::sync::latest::{self, Latest, Publisher};
+use crate::sync::RingBuffer;
+#[cfg(unix)]
+use crate::unix::flock::DeviceLock;
+
+#[cfg(unix)]
+pub mod flash;
+pub mod message;
+
+pub use message::{Command, Report};
+
+const SUBSYSTEM: &str = "coproc";
+
+/// Chip name under which the coprocessor’s pins are claimed.
+const CHIP: &str = "coproc";
+
+/// Most encoders whose counts are kept, by channel.
+const MAX_ENCODERS: usize = 8;
+
+/// Longest frame accepted from the coprocessor, in bytes.
+const MAX_FRAME_LEN: usize = 256;
+
+/// Bytes a receive thread can queue for the next poll.
+const RECEIVE_CAPACITY: usize = 4096;
+
+/// Wait before a receive thread reads again from a non-blocking handle that
+/// had nothing to read.
+const RECEIVE_RETRY: Duration = Duration::from_millis(1);
+
+/// Flag for `open` that makes reads return instead of waiting for data.
+#[cfg(unix)]
+const O_NONBLOCK: c_int = 0o4000;
+
+#[derive(Debug)]
+struct Link<S> {
+    deframer: Deframer,
+    encoders: Publisher<[Option<i32>; MAX_ENCODERS]>,
+    last_ack: Option<Instant>,
+    received: Option<Arc<RingBuffer<u8>>>,
+    sequence: u8,
+    stream: S,
+    /// Claim on the serial port, released when the last handle is dropped.
+    #[cfg(unix)]
+    _device_lock: Option<DeviceLock>,
+}
+
+impl<S: Read + Write> Link<S> {
+    fn send(&mut self, command: Command) -> Result<(), Error> {
+        log_event!(SUBSYSTEM, Level::Trace, "sending {command:?}");
+        self.stream.write_all(&command.encode())?;
+        self.stream.flush()
+    }
+}
+
+/// A microcontroller on a serial link.
+///
+/// Cloning gives another handle to the same link.
+#[derive(Debug)]
+pub struct Coprocessor<S> {
+    // Read without the link, so that reading a count never waits on a poll.
+    encoders: Latest<[Option<i32>; MAX_ENCODERS]>,
+    link: Arc<Mutex<Link<S>>>,
+}
+
+impl<S> Clone for Coprocessor<S> {
+    fn clone(&self) -> Self {
+        Self {
+            encoders: self.encoders.clone(),
+            link: Arc::clone(&self.link),
+        }
+    }
+}
+
+impl<S: Read + Write> Coprocessor<S> {
+    /// Creates a new `Coprocessor` on `stream`, which should time out or be
+    /// non-blocking when reading so that [`Coprocessor::poll`] returns.
+    pub fn new(stream: S) -> Self {
+        let (publisher, encoders) = latest::channel([None; MAX_ENCODERS]);
+        Self {
+            encoders,
+            link: Arc::new(Mutex::new(Link {
+                deframer: Deframer::new(Framing::Cobs, MAX_FRAME_LEN),
+                encoders: publisher,
+                last_ack: None,
+                received: None,
+                sequence: 0,
+                stream,
+                #[cfg(unix)]
+                _device_lock: None,
+            })),
+        }
+    }
+
+    /// Returns PWM `channel` on the coprocessor, running at `frequency`
+    /// hertz as configured in its firmware.
+    #[must_use]
+    pub fn pwm(&self, channel: u8, frequency: f64) -> RemotePwm<S> {
+        RemotePwm {
+            channel,
+            coprocessor: self.clone(),
+            frequency,
+        }
+    }
+
+    /// Claims digital output `pin` on the coprocessor and returns it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with [`ErrorKind::ResourceBusy`]
+    /// naming the owner if the pin is already claimed.
+    pub fn output(&self, pin: u8) -> Result<RemoteOutput<S>, Error> {
+        let claim = Registry::global().claim(Line::new(CHIP, u32::from(pin)), CHIP, "output")?;
+        Ok(RemoteOutput {
+            _claim: claim,
+            coprocessor: self.clone(),
+            pin,
+        })
+    }
+
+    /// Returns encoder `channel` on the coprocessor.
+    #[must_use]
+    pub fn encoder(&self, channel: usize) -> RemoteEncoder<S> {
+        RemoteEncoder {
+            channel,
+            coprocessor: self.clone(),
+        }
+    }
+
+    /// Sends `command` to the coprocessor.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the link cannot be written.
+    pub fn send(&self, command: Command) -> Result<(), Error> {
+        self.lock().send(command)
+    }
+
+    /// Sends the next heartbeat.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the link cannot be written.
+    pub fn heartbeat(&self) -> Result<(), Error> {
+        let mut link = self.lock();
+        link.sequence = link.sequence.wrapping_add(1);
+        let sequence = link.sequence;
+        link.send(Command::Heartbeat { sequence })
+    }
+
+    /// Returns when the coprocessor last acknowledged a heartbeat.
+    #[must_use]
+    pub fn last_ack(&self) -> Option<Instant> {
+        self.lock().last_ack
+    }
+
+    /// Reads bytes from `reader`, a second handle to the link such as a clone
+    /// of the serial port, on a new thread until it ends or fails, queuing
+    /// them for [`Coprocessor::poll`] instead of it reading the stream.
+    ///
+    /// `reader` may block until bytes arrive, optionally with a timeout, or be
+    /// non-blocking like the port [`Coprocessor::open`] opens, in which case
+    /// the thread waits briefly whenever it has nothing to read. Bytes that
+    /// arrive while the queue is full are dropped, losing the
+    /// frames they belong to.
+    pub fn spawn_receiver(&self, mut reader: impl Read + Send + 'static) -> JoinHandle<()> {
+        let received = Arc::new(RingBuffer::new(RECEIVE_CAPACITY));
+        self.lock().received = Some(Arc::clone(&received));
+        thread::spawn(move || {
+            let mut buffer = [0; MAX_FRAME_LEN];
+
+            loop {
+                let len = match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(error)
+                        if matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) =>
+                    {
+                        continue
+                    }
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(RECEIVE_RETRY);
+                        continue;
+                    }
+                    Err(error) => {
+                        log_event!(SUBSYSTEM, Level::Warn, "receive failed: {error}");
+                        break;
+                    }
+                };
+                let dropped = buffer[..len]
+                    .iter()
+                    .filter(|&&byte| received.push(byte).is_err())
+                    .count();
+
+                if dropped > 0 {
+                    log_event!(SUBSYSTEM, Level::Warn, "dropped {dropped} received bytes");
+                }
+            }
+        })
+    }
+
+    /// Reads whatever the coprocessor has sent, or takes what the receive
+    /// thread has queued, applies the reports, and returns them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the link cannot be read.
+    pub fn poll(&self) -> Result<Vec<Report>, Error> {
+        let mut link = self.lock();
+        let bytes: Vec<u8> = match &link.received {
+            Some(received) => iter::from_fn(|| received.pop()).collect(),
+            None => {
+                let mut buffer = [0; MAX_FRAME_LEN];
+                let len = match link.stream.read(&mut buffer) {
+                    Ok(len) => len,
+                    Err(error)
+                        if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                    {
+                        0
+                    }
+                    Err(error) => return Err(error),
+                };
+                buffer[..len].to_vec()
+            }
+        };
+        let mut reports = Vec::new();
+
+        for frame in link.deframer.push(&bytes) {
+            let Some(report) = frame.ok().as_deref().and_then(Report::decode) else {
+                log_event!(SUBSYSTEM, Level::Warn, "discarding a corrupt frame");
+                continue;
+            };
+
+            match &report {
+                Report::HeartbeatAck { sequence } if *sequence == link.sequence => {
+                    link.last_ack = Some(Instant::now());
+                }
+                Report::HeartbeatAck { .. } => {}
+                Report::Encoders(counts) => {
+                    let mut latest = [None; MAX_ENCODERS];
+                    latest
+                        .iter_mut()
+                        .zip(counts)
+                        .for_each(|(latest, &count)| *latest = Some(count));
+                    link.encoders.publish(latest);
+                }
+            }
+
+            reports.push(report);
+        }
+
+        Ok(reports)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Link<S>> {
+        self.link.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(unix)]
+impl Coprocessor<File> {
+    /// Opens the serial port claimed by `lock`, such as one returned by
+    /// [`SerialPorts::claim`](crate::linux::serial::SerialPorts::claim),
+    /// holding the claim for as long as any handle to the link exists.
+    ///
+    /// The port is opened non-blocking, so it should already be configured
+    /// for the coprocessor’s baud rate.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the port cannot be opened.
+    pub fn open(lock: DeviceLock) -> Result<Self, Error> {
+        let stream = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(O_NONBLOCK)
+            .open(lock.device())?;
+        let coprocessor = Self::new(stream);
+        coprocessor.lock()._device_lock = Some(lock);
+        Ok(coprocessor)
+    }
+}
+
+/// A PWM channel on a [`Coprocessor`].
+#[derive(Debug)]
+pub struct RemotePwm<S> {
+    channel: u8,
+    coprocessor: Coprocessor<S>,
+    frequency: f64,
+}
+
+impl<S: Read + Write> PwmOutput for RemotePwm<S> {
+    fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: f64) -> Result<(), Error> {
+        let duty = (duty_cycle.clamp(0.0, 1.0) * f64::from(u16::MAX)).round() as u16;
+        self.coprocessor.send(Command::SetPwm {
+            channel: self.channel,
+            duty,
+        })
+    }
+}
+
+/// A digital output on a [`Coprocessor`].
+#[derive(Debug)]
+pub struct RemoteOutput<S> {
+    _claim: Claim<'static>,
+    coprocessor: Coprocessor<S>,
+    pin: u8,
+}
+
+impl<S: Read + Write> DigitalOutput for RemoteOutput<S> {
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.set_level(true)
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.set_level(false)
+    }
+
+    fn set_level(&mut self, high: bool) -> Result<(), Error> {
+        self.coprocessor.send(Command::SetDigital {
+            pin: self.pin,
+            high,
+        })
+    }
+}
+
+/// An encoder counted by a [`Coprocessor`].
+///
+/// Counts are as of the last report received by [`Coprocessor::poll`], for
+/// up to the first eight channels, and reading one never waits for a poll in
+/// progress.
+#[derive(Debug)]
+pub struct RemoteEncoder<S> {
+    channel: usize,
+    coprocessor: Coprocessor<S>,
+}
+
+impl<S: Read + Write> Encoder for RemoteEncoder<S> {
+    fn count(&mut self) -> Result<i64, Error> {
+        self.coprocessor
+            .encoders
+            .get()
+            .get(self.channel)
+            .copied()
+            .flatten()
+            .map(i64::from)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("no count reported for encoder {}", self.channel),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::protocol::framing::cobs_decode;
+
+    #[derive(Debug, Default)]
+    struct MockStream {
+        received: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.received.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn sent(coprocessor: &Coprocessor<MockStream>) -> Vec<Vec<u8>> {
+        let sent = std::mem::take(&mut coprocessor.lock().stream.sent);
+        sent.split(|&byte| byte == 0)
+            .filter(|frame| !frame.is_empty())
+            .map(|frame| cobs_decode(frame).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn it_should_drive_remote_outputs_like_local_ones() {
+        let coprocessor = Coprocessor::new(MockStream::default());
+        let mut pwm = coprocessor.pwm(1, 20_000.0);
+        pwm.set_duty_cycle(0.5).unwrap();
+        let mut output = coprocessor.output(7).unwrap();
+        output.set_high().unwrap();
+        assert!(coprocessor.output(7).is_err_and(
+            |error| error.to_string() == "line 7 of coproc already claimed by coproc output"
+        ));
+        let frames = sent(&coprocessor);
+        assert_eq!(frames[0][..4], [0x02, 1, 0x00, 0x80]);
+        assert_eq!(frames[1][..3], [0x03, 7, 1]);
+    }
+
+    #[test]
+    fn it_should_apply_reports_from_the_coprocessor() {
+        let mut received = Report::HeartbeatAck { sequence: 1 }.encode();
+        received.extend([0x01, 0x02, 0x00]);
+        received.extend(Report::Encoders(vec![10, -20]).encode());
+        let coprocessor = Coprocessor::new(MockStream {
+            received: Cursor::new(received),
+            sent: Vec::new(),
+        });
+        let mut encoder = coprocessor.encoder(1);
+        assert!(encoder.count().is_err());
+        coprocessor.heartbeat().unwrap();
+        assert_eq!(sent(&coprocessor)[0][..2], [0x01, 1]);
+        let reports = coprocessor.poll().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(coprocessor.last_ack().is_some());
+        assert_eq!(encoder.count().unwrap(), -20);
+    }
+
+    #[test]
+    fn it_should_poll_what_the_receive_thread_queued() {
+        let coprocessor = Coprocessor::new(MockStream {
+            received: Cursor::new(Report::Encoders(vec![1]).encode()),
+            sent: Vec::new(),
+        });
+        let mut received = Report::Encoders(vec![5, 6]).encode();
+        received.extend(Report::HeartbeatAck { sequence: 0 }.encode());
+        coprocessor
+            .spawn_receiver(Cursor::new(received))
+            .join()
+            .unwrap();
+        let reports = coprocessor.poll().unwrap();
+        assert_eq!(
+            reports,
+            [
+                Report::Encoders(vec![5, 6]),
+                Report::HeartbeatAck { sequence: 0 }
+            ]
+        );
+        assert_eq!(coprocessor.encoder(1).count().unwrap(), 6);
+        assert!(coprocessor.poll().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_should_keep_receiving_from_a_non_blocking_reader() {
+        struct NonBlocking {
+            empty_reads: usize,
+            received: Cursor<Vec<u8>>,
+        }
+
+        impl Read for NonBlocking {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                if self.empty_reads == 0 {
+                    return self.received.read(buf);
+                }
+
+                self.empty_reads -= 1;
+                Err(ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let coprocessor = Coprocessor::new(MockStream {
+            received: Cursor::new(Vec::new()),
+            sent: Vec::new(),
+        });
+        coprocessor
+            .spawn_receiver(NonBlocking {
+                empty_reads: 3,
+                received: Cursor::new(Report::Encoders(vec![7]).encode()),
+            })
+            .join()
+            .unwrap();
+        assert_eq!(coprocessor.poll().unwrap(), [Report::Encoders(vec![7])]);
+    }
+}
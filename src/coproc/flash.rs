@@ -0,0 +1,284 @@
+//! Field updates of the RP2040 coprocessor’s firmware.
+//!
+//! Holding the RP2040’s BOOTSEL line low while it comes out of reset starts
+//! its ROM bootloader, which appears as a USB mass storage drive. Copying a
+//! UF2 image onto the drive flashes it and reboots into the new firmware.
+//! With RUN and BOOTSEL wired to the Pi’s GPIO, [`Flasher`] does all of this
+//! without anyone opening the chassis to press the button.
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::hal::DigitalOutput;
+use crate::log_event;
+use crate::logging::Level;
+use crate::unix::fsutil;
+
+const SUBSYSTEM: &str = "flash";
+
+/// Length of each UF2 block, in bytes.
+const BLOCK_LEN: usize = 512;
+
+/// Magic numbers at the start of each UF2 block.
+const MAGIC_START: [u32; 2] = [0x0A32_4655, 0x9E5D_5157];
+
+/// Magic number at the end of each UF2 block.
+const MAGIC_END: u32 = 0x0AB1_6F30;
+
+/// Flag marking that a UF2 block names the family it is for.
+const FAMILY_ID_PRESENT: u32 = 0x2000;
+
+/// UF2 family of the RP2040.
+pub const RP2040_FAMILY: u32 = 0xE48B_FF56;
+
+/// File the bootloader drive always holds, used to recognize it.
+const INFO_FILE: &str = "INFO_UF2.TXT";
+
+/// How long RUN is held low to reset the chip.
+const RESET_PULSE: Duration = Duration::from_millis(10);
+
+/// How long BOOTSEL is held low after reset, while the ROM samples it.
+const BOOTSEL_HOLD: Duration = Duration::from_millis(100);
+
+/// How often to check whether the bootloader drive is mounted.
+const MOUNT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Checks that `image` is a UF2 image for the RP2040, and returns the number
+/// of blocks in it.
+///
+/// # Errors
+///
+/// This function will return an error with [`ErrorKind::InvalidData`] if the
+/// image is empty, is not made of UF2 blocks, or is for another family.
+pub fn validate_uf2(image: &[u8]) -> Result<usize, Error> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+
+    if image.is_empty() || !image.len().is_multiple_of(BLOCK_LEN) {
+        return Err(invalid(format!(
+            "UF2 image should be a whole number of {BLOCK_LEN}-byte blocks"
+        )));
+    }
+
+    for (index, block) in image.chunks(BLOCK_LEN).enumerate() {
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                block[offset],
+                block[offset + 1],
+                block[offset + 2],
+                block[offset + 3],
+            ])
+        };
+
+        if [word(0), word(4)] != MAGIC_START || word(BLOCK_LEN - 4) != MAGIC_END {
+            return Err(invalid(format!("block {index} is not a UF2 block")));
+        }
+
+        // The family ID takes the place of the file size when flagged.
+        if word(8) & FAMILY_ID_PRESENT != 0 && word(28) != RP2040_FAMILY {
+            return Err(invalid(format!(
+                "block {index} is for family {:#010x}, not the RP2040",
+                word(28)
+            )));
+        }
+    }
+
+    Ok(image.len() / BLOCK_LEN)
+}
+
+/// Flashes firmware onto an RP2040 through its bootloader drive.
+#[derive(Debug)]
+pub struct Flasher<R, B> {
+    boot: B,
+    mount_dir: PathBuf,
+    run: R,
+    timeout: Duration,
+}
+
+impl<R: DigitalOutput, B: DigitalOutput> Flasher<R, B> {
+    /// Creates a new `Flasher` driving the RP2040’s `run` and `boot` lines,
+    /// both active low, that expects the bootloader drive to be mounted at
+    /// `mount_dir`.
+    pub fn new(run: R, boot: B, mount_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            boot,
+            mount_dir: mount_dir.into(),
+            run,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets how long to wait for the bootloader drive to be mounted.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Resets the RP2040 into its bootloader.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lines cannot be driven.
+    pub fn enter_bootloader(&mut self) -> Result<(), Error> {
+        log_event!(SUBSYSTEM, Level::Info, "resetting into the bootloader");
+        self.boot.set_low()?;
+        self.run.set_low()?;
+        thread::sleep(RESET_PULSE);
+        self.run.set_high()?;
+        thread::sleep(BOOTSEL_HOLD);
+        self.boot.set_high()
+    }
+
+    /// Resets the RP2040 into its firmware.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lines cannot be driven.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.boot.set_high()?;
+        self.run.set_low()?;
+        thread::sleep(RESET_PULSE);
+        self.run.set_high()
+    }
+
+    /// Resets the RP2040 into its bootloader and copies `image` onto its
+    /// drive, after which it reboots into the new firmware.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the image is not valid UF2 for
+    /// the RP2040, the drive is not mounted before the timeout, or the
+    /// image cannot be copied.
+    pub fn flash(&mut self, image: &[u8]) -> Result<(), Error> {
+        let blocks = validate_uf2(image)?;
+        self.enter_bootloader()?;
+        self.wait_for_drive()?;
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "copying {blocks} blocks to {}",
+            self.mount_dir.display()
+        );
+        let path = self.mount_dir.join("firmware.uf2");
+        fs::write(&path, image)?;
+        // The bootloader flashes as blocks arrive, so everything must be
+        // written before the drive disappears.
+        fs::File::open(&path)?.sync_all()?;
+        fsutil::sync_dir(&self.mount_dir)
+    }
+
+    /// Returns the run and boot lines.
+    pub fn into_inner(self) -> (R, B) {
+        (self.run, self.boot)
+    }
+
+    fn wait_for_drive(&self) -> Result<(), Error> {
+        let deadline = Instant::now() + self.timeout;
+
+        while !is_bootloader_drive(&self.mount_dir) {
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "RP2040 bootloader drive was not mounted at {}",
+                        self.mount_dir.display()
+                    ),
+                ));
+            }
+
+            thread::sleep(MOUNT_POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+}
+
+fn is_bootloader_drive(dir: &Path) -> bool {
+    dir.join(INFO_FILE).is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::TemporaryDirectory;
+
+    #[derive(Debug)]
+    struct Line(&'static str, Rc<RefCell<Vec<(&'static str, bool)>>>);
+
+    impl DigitalOutput for Line {
+        fn set_high(&mut self) -> Result<(), Error> {
+            self.1.borrow_mut().push((self.0, true));
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Error> {
+            self.1.borrow_mut().push((self.0, false));
+            Ok(())
+        }
+    }
+
+    fn block(family: u32) -> Vec<u8> {
+        let mut block = vec![0; BLOCK_LEN];
+        block[0..4].copy_from_slice(&MAGIC_START[0].to_le_bytes());
+        block[4..8].copy_from_slice(&MAGIC_START[1].to_le_bytes());
+        block[8..12].copy_from_slice(&FAMILY_ID_PRESENT.to_le_bytes());
+        block[28..32].copy_from_slice(&family.to_le_bytes());
+        block[BLOCK_LEN - 4..].copy_from_slice(&MAGIC_END.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn it_should_validate_uf2_images() {
+        let image = [block(RP2040_FAMILY), block(RP2040_FAMILY)].concat();
+        assert_eq!(validate_uf2(&image).unwrap(), 2);
+        assert!(validate_uf2(&[]).is_err());
+        assert!(validate_uf2(&image[..100]).is_err());
+        // An image for the ESP32-S2.
+        assert!(validate_uf2(&block(0xBFDD_4EEE)).is_err());
+    }
+
+    #[test]
+    fn it_should_reset_into_the_bootloader_and_copy_the_image() {
+        let drive = TemporaryDirectory::new().unwrap();
+        fs::write(drive.path().join(INFO_FILE), "UF2 Bootloader v3.0\n").unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut flasher = Flasher::new(
+            Line("run", Rc::clone(&log)),
+            Line("boot", Rc::clone(&log)),
+            drive.path(),
+        );
+        let image = block(RP2040_FAMILY);
+        flasher.flash(&image).unwrap();
+        assert_eq!(
+            *log.borrow(),
+            [
+                ("boot", false),
+                ("run", false),
+                ("run", true),
+                ("boot", true)
+            ]
+        );
+        assert_eq!(fs::read(drive.path().join("firmware.uf2")).unwrap(), image);
+    }
+
+    #[test]
+    fn it_should_time_out_without_the_drive() {
+        let drive = TemporaryDirectory::new().unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut flasher = Flasher::new(
+            Line("run", Rc::clone(&log)),
+            Line("boot", log),
+            drive.path(),
+        )
+        .with_timeout(Duration::ZERO);
+        assert!(flasher
+            .flash(&block(RP2040_FAMILY))
+            .is_err_and(|error| error.kind() == ErrorKind::TimedOut));
+    }
+}
@@ -0,0 +1,144 @@
+//! Messages exchanged with the coprocessor.
+//!
+//! Each message is a kind byte, a body, and a CRC-16 of both, little-endian,
+//! framed with COBS. Kinds from the host have the top bit clear and kinds
+//! from the coprocessor have it set.
+
+use crate::protocol::framing::{crc16, Framing};
+
+const HEARTBEAT: u8 = 0x01;
+const SET_PWM: u8 = 0x02;
+const SET_DIGITAL: u8 = 0x03;
+const HEARTBEAT_ACK: u8 = 0x81;
+const ENCODERS: u8 = 0x82;
+
+/// A message from the host to the coprocessor.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Command {
+    /// Proves the host is alive. The coprocessor stops its outputs if
+    /// heartbeats stop arriving, and echoes the sequence number back.
+    Heartbeat {
+        /// Sequence number of the heartbeat.
+        sequence: u8,
+    },
+    /// Sets the duty cycle of a PWM channel.
+    SetPwm {
+        /// Channel to set.
+        channel: u8,
+        /// Duty cycle, from 0 for off to 65535 for always on.
+        duty: u16,
+    },
+    /// Drives a digital output.
+    SetDigital {
+        /// Pin to drive.
+        pin: u8,
+        /// Whether to drive the pin high.
+        high: bool,
+    },
+}
+
+impl Command {
+    /// Returns the command framed for the wire.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = match *self {
+            Self::Heartbeat { sequence } => vec![HEARTBEAT, sequence],
+            Self::SetPwm { channel, duty } => {
+                let [low, high] = duty.to_le_bytes();
+                vec![SET_PWM, channel, low, high]
+            }
+            Self::SetDigital { pin, high } => vec![SET_DIGITAL, pin, u8::from(high)],
+        };
+        frame(payload)
+    }
+}
+
+/// A message from the coprocessor to the host.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Report {
+    /// Acknowledges a heartbeat.
+    HeartbeatAck {
+        /// Sequence number of the heartbeat.
+        sequence: u8,
+    },
+    /// Counts of every encoder, by channel.
+    Encoders(Vec<i32>),
+}
+
+impl Report {
+    /// Returns the report in `payload`, a frame with its framing removed, or
+    /// `None` if it fails its CRC or is not a known report.
+    #[must_use]
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let (message, crc) = payload.split_at(payload.len().checked_sub(2)?);
+
+        if crc16(message).to_le_bytes() != crc {
+            return None;
+        }
+
+        let (&kind, body) = message.split_first()?;
+
+        match (kind, body) {
+            (HEARTBEAT_ACK, &[sequence]) => Some(Self::HeartbeatAck { sequence }),
+            (ENCODERS, counts) if counts.len() % 4 == 0 => Some(Self::Encoders(
+                counts
+                    .chunks(4)
+                    .map(|count| i32::from_le_bytes([count[0], count[1], count[2], count[3]]))
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Returns the report framed for the wire, as the coprocessor sends it.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = match self {
+            Self::HeartbeatAck { sequence } => vec![HEARTBEAT_ACK, *sequence],
+            Self::Encoders(counts) => [ENCODERS]
+                .into_iter()
+                .chain(counts.iter().flat_map(|count| count.to_le_bytes()))
+                .collect(),
+        };
+        frame(payload)
+    }
+}
+
+fn frame(mut payload: Vec<u8>) -> Vec<u8> {
+    let crc = crc16(&payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    Framing::Cobs.encode(&payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::framing::cobs_decode;
+
+    fn unframe(frame: &[u8]) -> Vec<u8> {
+        cobs_decode(&frame[..frame.len() - 1]).unwrap()
+    }
+
+    #[test]
+    fn it_should_encode_commands_with_a_crc() {
+        let payload = unframe(
+            &Command::SetPwm {
+                channel: 2,
+                duty: 0x1234,
+            }
+            .encode(),
+        );
+        assert_eq!(&payload[..4], [SET_PWM, 2, 0x34, 0x12]);
+        assert_eq!(payload[4..], crc16(&payload[..4]).to_le_bytes());
+    }
+
+    #[test]
+    fn it_should_round_trip_reports_and_reject_corruption() {
+        let report = Report::Encoders(vec![-5, 70_000]);
+        let mut payload = unframe(&report.encode());
+        assert_eq!(Report::decode(&payload), Some(report));
+        payload[1] ^= 1;
+        assert_eq!(Report::decode(&payload), None);
+        assert_eq!(Report::decode(&[0x81]), None);
+    }
+}
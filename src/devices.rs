@@ -0,0 +1,12 @@
+//! Drivers for peripherals that extend the Pi’s own I/O.
+
+pub mod buzzer;
+pub mod dynamixel;
+pub mod ir_beacon;
+pub mod led;
+pub mod mcp23017;
+pub mod motor;
+pub mod pan_tilt;
+pub mod pca9685;
+pub mod servo;
+pub mod vesc;
@@ -0,0 +1,382 @@
+//! Tones and melodies on a passive buzzer driven by PWM.
+//!
+//! Melodies are written in RTTTL, the ringtone format used by old Nokia
+//! phones, which keeps event sounds small enough to embed as string
+//! constants. A buzzer can only sound one frequency at a time, so chords are
+//! approximated by cycling through their notes faster than the ear can
+//! follow.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::Error;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::gpio::registry::{Claim, Line, Registry};
+use crate::hal::AdjustableFrequency;
+
+/// Duty cycle that gives a passive buzzer its loudest square wave.
+const SOUNDING_DUTY_CYCLE: f64 = 0.5;
+
+/// Time each note of a chord is sounded before moving to the next.
+const ARPEGGIO_SLICE: Duration = Duration::from_millis(15);
+
+/// Gap left between notes so that repeated notes are heard separately.
+const ARTICULATION_GAP: Duration = Duration::from_millis(10);
+
+/// A single note of a melody.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Note {
+    /// Frequency in hertz, or `None` for a rest.
+    pub frequency: Option<f64>,
+    /// How long the note lasts.
+    pub duration: Duration,
+}
+
+/// A sequence of notes parsed from RTTTL.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Melody {
+    name: String,
+    notes: Vec<Note>,
+}
+
+impl Melody {
+    /// Returns the name of the melody.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the notes of the melody.
+    #[must_use]
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    /// Returns the total duration of the melody.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.notes.iter().map(|note| note.duration).sum()
+    }
+}
+
+impl Display for Melody {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} notes)", self.name, self.notes.len())
+    }
+}
+
+impl FromStr for Melody {
+    type Err = String;
+
+    /// Parses a melody in RTTTL, such as `beep:d=8,o=6,b=180:c,e,g`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sections = s.trim().splitn(3, ':');
+        let name = sections.next().unwrap_or_default().trim().to_owned();
+        let (Some(defaults), Some(notes)) = (sections.next(), sections.next()) else {
+            return Err(format!("RTTTL `{s}` should have three sections"));
+        };
+        let (mut duration, mut octave, mut bpm) = (4, 6, 63);
+
+        for setting in defaults.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("RTTTL setting `{setting}` should be `key=value`"))?;
+            let value: u32 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("RTTTL setting `{setting}` should be a number"))?;
+
+            match key.trim() {
+                "d" => duration = value,
+                "o" => octave = value,
+                "b" => bpm = value,
+                _ => return Err(format!("unknown RTTTL setting `{setting}`")),
+            }
+        }
+
+        if bpm == 0 {
+            return Err("RTTTL tempo should be greater than zero".to_owned());
+        }
+
+        let whole_note = Duration::from_secs(240) / bpm;
+        let notes = notes
+            .split(',')
+            .map(str::trim)
+            .filter(|note| !note.is_empty())
+            .map(|note| parse_note(note, duration, octave, whole_note))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { name, notes })
+    }
+}
+
+/// Parses a single RTTTL note, such as `8c#.6`.
+fn parse_note(
+    note: &str,
+    duration: u32,
+    octave: u32,
+    whole_note: Duration,
+) -> Result<Note, String> {
+    let error = || format!("RTTTL note `{note}` is malformed");
+    let digits = note.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+    let duration = match &note[..digits] {
+        "" => duration,
+        value => value.parse().map_err(|_| error())?,
+    };
+    let mut chars = note[digits..].chars().peekable();
+    let semitone = match chars.next().map(|c| c.to_ascii_lowercase()) {
+        Some('c') => Some(0),
+        Some('d') => Some(2),
+        Some('e') => Some(4),
+        Some('f') => Some(5),
+        Some('g') => Some(7),
+        Some('a') => Some(9),
+        Some('b' | 'h') => Some(11),
+        Some('p') => None,
+        _ => return Err(error()),
+    };
+    let sharp = chars.next_if_eq(&'#').is_some();
+    let mut dotted = chars.next_if_eq(&'.').is_some();
+    let octave = match chars.next_if(char::is_ascii_digit) {
+        Some(digit) => digit.to_digit(10).unwrap_or(octave),
+        None => octave,
+    };
+    dotted |= chars.next_if_eq(&'.').is_some();
+
+    if chars.next().is_some() || duration == 0 {
+        return Err(error());
+    }
+
+    let mut duration = whole_note / duration;
+
+    if dotted {
+        duration += duration / 2;
+    }
+
+    Ok(Note {
+        frequency: semitone.map(|semitone| {
+            let semitone = semitone + i32::from(sharp) + 12 * octave as i32;
+            // A4 is 440 Hz and is 57 semitones above C0.
+            440.0 * 2_f64.powf(f64::from(semitone - 57) / 12.0)
+        }),
+        duration,
+    })
+}
+
+/// A passive buzzer driven by a PWM output.
+///
+/// Playback blocks the calling thread, so melodies should be played from a
+/// thread or actor that is not running a control loop.
+#[derive(Debug)]
+pub struct Buzzer<P> {
+    _claim: Option<Claim<'static>>,
+    pwm: P,
+}
+
+impl<P: AdjustableFrequency> Buzzer<P> {
+    /// Creates a new `Buzzer`, initially silent.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    pub fn new(mut pwm: P) -> Result<Self, Error> {
+        pwm.set_duty_cycle(0.0)?;
+        Ok(Self { _claim: None, pwm })
+    }
+
+    /// Creates a new `Buzzer` as [`Buzzer::new`] does, claiming `line`, the
+    /// line behind `pwm`, for as long as the buzzer exists.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with
+    /// [`ErrorKind::ResourceBusy`](std::io::ErrorKind::ResourceBusy) naming
+    /// the owner if the line is already claimed, or if the output cannot be driven.
+    pub fn claim(pwm: P, line: Line) -> Result<Self, Error> {
+        let claim = Registry::global().claim(line, "buzzer", "PWM")?;
+        let mut buzzer = Self::new(pwm)?;
+        buzzer._claim = Some(claim);
+        Ok(buzzer)
+    }
+
+    /// Sounds `frequency` for `duration`, then silences the buzzer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    pub fn tone(&mut self, frequency: f64, duration: Duration) -> Result<(), Error> {
+        self.sound(frequency)?;
+        thread::sleep(duration);
+        self.silence()
+    }
+
+    /// Approximates a chord by cycling through `frequencies` for `duration`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    pub fn chord(&mut self, frequencies: &[f64], duration: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + duration;
+
+        for &frequency in frequencies.iter().cycle() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                break;
+            }
+
+            self.sound(frequency)?;
+            thread::sleep(remaining.min(ARPEGGIO_SLICE));
+        }
+
+        self.silence()
+    }
+
+    /// Plays every note of `melody` in turn.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    pub fn play(&mut self, melody: &Melody) -> Result<(), Error> {
+        for note in melody.notes() {
+            match note.frequency {
+                Some(frequency) => {
+                    let gap = ARTICULATION_GAP.min(note.duration / 4);
+                    self.tone(frequency, note.duration - gap)?;
+                    thread::sleep(gap);
+                }
+                None => thread::sleep(note.duration),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Silences the buzzer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    pub fn silence(&mut self) -> Result<(), Error> {
+        self.pwm.set_duty_cycle(0.0)
+    }
+
+    /// Consumes the buzzer and returns its output.
+    pub fn into_inner(self) -> P {
+        self.pwm
+    }
+
+    fn sound(&mut self, frequency: f64) -> Result<(), Error> {
+        self.pwm.set_frequency(frequency)?;
+        self.pwm.set_duty_cycle(SOUNDING_DUTY_CYCLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::PwmOutput;
+
+    #[derive(Debug, Default)]
+    struct Pwm {
+        duty_cycle: f64,
+        frequency: f64,
+        sounded: Vec<f64>,
+    }
+
+    impl PwmOutput for Pwm {
+        fn frequency(&self) -> f64 {
+            self.frequency
+        }
+
+        fn set_duty_cycle(&mut self, duty_cycle: f64) -> Result<(), Error> {
+            if duty_cycle > 0.0 {
+                self.sounded.push(self.frequency);
+            }
+
+            self.duty_cycle = duty_cycle;
+            Ok(())
+        }
+    }
+
+    impl AdjustableFrequency for Pwm {
+        fn set_frequency(&mut self, frequency: f64) -> Result<(), Error> {
+            self.frequency = frequency;
+            Ok(())
+        }
+    }
+
+    fn assert_frequency(note: &Note, expected: f64) {
+        let frequency = note.frequency.expect("note should not be a rest");
+        assert!(
+            (frequency - expected).abs() < 0.01,
+            "{frequency} != {expected}"
+        );
+    }
+
+    #[test]
+    fn it_should_parse_the_defaults_and_notes_of_a_melody() {
+        let melody: Melody = "beep:d=8,o=5,b=120:a,4c#6,p,2e.".parse().unwrap();
+        assert_eq!(melody.name(), "beep");
+        let notes = melody.notes();
+        assert_frequency(&notes[0], 880.0);
+        assert_eq!(notes[0].duration, Duration::from_millis(250));
+        assert_frequency(&notes[1], 1108.73);
+        assert_eq!(notes[1].duration, Duration::from_millis(500));
+        assert_eq!(notes[2].frequency, None);
+        assert_frequency(&notes[3], 659.26);
+        assert_eq!(notes[3].duration, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn it_should_accept_a_dot_after_the_octave() {
+        let melody: Melody = "x:d=4,o=4,b=60:8g5.".parse().unwrap();
+        assert_eq!(melody.notes()[0].duration, Duration::from_millis(750));
+        assert_frequency(&melody.notes()[0], 783.99);
+    }
+
+    #[test]
+    fn it_should_use_the_standard_defaults() {
+        let melody: Melody = "x::a".parse().unwrap();
+        assert_frequency(&melody.notes()[0], 1760.0);
+        assert_eq!(melody.duration(), Duration::from_secs(240) / 63 / 4);
+    }
+
+    #[test]
+    fn it_should_reject_malformed_rtttl() {
+        assert!("x:d=4".parse::<Melody>().is_err());
+        assert!("x:q=4:a".parse::<Melody>().is_err());
+        assert!("x:b=0:a".parse::<Melody>().is_err());
+        assert!("x::z".parse::<Melody>().is_err());
+        assert!("x::a#q".parse::<Melody>().is_err());
+    }
+
+    #[test]
+    fn it_should_play_each_note_and_end_silent() {
+        let melody: Melody = "x:d=32,o=5,b=900:c,p,e".parse().unwrap();
+        let mut buzzer = Buzzer::new(Pwm::default()).unwrap();
+        buzzer.play(&melody).unwrap();
+        let pwm = buzzer.into_inner();
+        assert_eq!(pwm.sounded.len(), 2);
+        assert!((pwm.sounded[1] - 659.26).abs() < 0.01);
+        assert_eq!(pwm.duty_cycle, 0.0);
+    }
+
+    #[test]
+    fn it_should_cycle_through_the_notes_of_a_chord() {
+        let mut buzzer = Buzzer::new(Pwm::default()).unwrap();
+        buzzer
+            .chord(&[262.0, 330.0, 392.0], Duration::from_millis(50))
+            .unwrap();
+        let pwm = buzzer.into_inner();
+        // How many slices fit depends on the scheduler, but they always
+        // start on the root and go round the notes in order.
+        let chord = [262.0, 330.0, 392.0];
+        assert!(!pwm.sounded.is_empty());
+        assert!(pwm
+            .sounded
+            .iter()
+            .eq(chord.iter().cycle().take(pwm.sounded.len())));
+        assert_eq!(pwm.duty_cycle, 0.0);
+    }
+}
@@ -0,0 +1,498 @@
+//! Driver for Dynamixel smart servos using Protocol 2.0.
+//!
+//! Dynamixels share a half-duplex serial bus, each answering to its own ID,
+//! and hold their settings and state in a control table of registers. Unlike
+//! hobby servos, they report where they are and how hard they are working,
+//! so an arm built from them knows its pose. The register addresses used here
+//! are those of the X series.
+
+use std::f64::consts::TAU;
+use std::io::{Error, ErrorKind, Read, Write};
+
+/// Header starting every packet, followed by a reserved zero byte.
+const HEADER: [u8; 4] = [0xFF, 0xFF, 0xFD, 0x00];
+
+/// ID addressing every servo on the bus, which none answer for writes.
+pub const BROADCAST_ID: u8 = 0xFE;
+
+/// Instruction asking a servo for its model number.
+const PING: u8 = 0x01;
+
+/// Instruction reading a range of the control table.
+const READ: u8 = 0x02;
+
+/// Instruction writing a range of the control table.
+const WRITE: u8 = 0x03;
+
+/// Instruction reading ranges from several servos at once.
+const BULK_READ: u8 = 0x92;
+
+/// Instruction of a servo’s reply.
+const STATUS: u8 = 0x55;
+
+/// Address of the operating mode.
+const OPERATING_MODE: u16 = 11;
+
+/// Address of the torque enable flag.
+const TORQUE_ENABLE: u16 = 64;
+
+/// Address of the goal velocity.
+const GOAL_VELOCITY: u16 = 104;
+
+/// Address of the goal position.
+const GOAL_POSITION: u16 = 116;
+
+/// Address of the present current.
+const PRESENT_CURRENT: u16 = 126;
+
+/// Address of the present position.
+const PRESENT_POSITION: u16 = 132;
+
+/// Position counts per revolution.
+const COUNTS_PER_REVOLUTION: f64 = 4096.0;
+
+/// Revolutions per minute in one unit of velocity.
+const RPM_PER_UNIT: f64 = 0.229;
+
+/// Amperes in one unit of current.
+const AMPERES_PER_UNIT: f64 = 0.002_69;
+
+/// How a servo interprets its goals.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Mode {
+    /// Turns continuously at the goal velocity.
+    Velocity,
+    /// Holds the goal position within one revolution.
+    Position,
+    /// Holds the goal position over many revolutions.
+    ExtendedPosition,
+}
+
+impl Mode {
+    fn value(self) -> u8 {
+        match self {
+            Self::Velocity => 1,
+            Self::Position => 3,
+            Self::ExtendedPosition => 4,
+        }
+    }
+}
+
+/// State of a servo read from its control table.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Feedback {
+    /// Position, in radians from the servo’s zero.
+    pub position: f64,
+    /// Velocity, in radians per second.
+    pub velocity: f64,
+    /// Current drawn, in amperes, which tracks the load.
+    pub current: f64,
+}
+
+/// A bus of Dynamixel servos on a serial stream.
+#[derive(Debug)]
+pub struct Dynamixel<S> {
+    stream: S,
+}
+
+impl<S: Read + Write> Dynamixel<S> {
+    /// Creates a new `Dynamixel` bus over `stream`, which should have a read
+    /// timeout so a missing servo does not block forever.
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// Returns the stream, consuming the bus.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Returns the model number of the servo with `id`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo does not answer or
+    /// reports an error.
+    pub fn ping(&mut self, id: u8) -> Result<u16, Error> {
+        self.send(id, PING, &[])?;
+        let params = self.receive(id)?;
+        le_u16(&params)
+    }
+
+    /// Returns `len` bytes of the control table of the servo with `id` from
+    /// `address`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo does not answer or
+    /// reports an error.
+    pub fn read(&mut self, id: u8, address: u16, len: u16) -> Result<Vec<u8>, Error> {
+        let mut params = address.to_le_bytes().to_vec();
+        params.extend_from_slice(&len.to_le_bytes());
+        self.send(id, READ, &params)?;
+        self.receive(id)
+    }
+
+    /// Writes `data` to the control table of the servo with `id` from
+    /// `address`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo does not acknowledge
+    /// the write or reports an error.
+    pub fn write(&mut self, id: u8, address: u16, data: &[u8]) -> Result<(), Error> {
+        let mut params = address.to_le_bytes().to_vec();
+        params.extend_from_slice(data);
+        self.send(id, WRITE, &params)?;
+
+        if id != BROADCAST_ID {
+            self.receive(id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the `(id, address, len)` ranges from several servos in one
+    /// round trip, returning their bytes in the same order.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any servo does not answer or
+    /// reports an error.
+    pub fn bulk_read(&mut self, ranges: &[(u8, u16, u16)]) -> Result<Vec<Vec<u8>>, Error> {
+        let params: Vec<_> = ranges
+            .iter()
+            .flat_map(|&(id, address, len)| {
+                let [address_low, address_high] = address.to_le_bytes();
+                let [len_low, len_high] = len.to_le_bytes();
+                [id, address_low, address_high, len_low, len_high]
+            })
+            .collect();
+        self.send(BROADCAST_ID, BULK_READ, &params)?;
+        ranges.iter().map(|&(id, ..)| self.receive(id)).collect()
+    }
+
+    /// Enables or disables the torque of the servo with `id`, which must be
+    /// disabled to change its mode.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo does not acknowledge
+    /// the write.
+    pub fn set_torque(&mut self, id: u8, enabled: bool) -> Result<(), Error> {
+        self.write(id, TORQUE_ENABLE, &[u8::from(enabled)])
+    }
+
+    /// Sets the operating mode of the servo with `id`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo does not acknowledge
+    /// the write, such as when its torque is enabled.
+    pub fn set_mode(&mut self, id: u8, mode: Mode) -> Result<(), Error> {
+        self.write(id, OPERATING_MODE, &[mode.value()])
+    }
+
+    /// Moves the servo with `id` to `position`, in radians.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo does not acknowledge
+    /// the write.
+    pub fn set_position(&mut self, id: u8, position: f64) -> Result<(), Error> {
+        let counts = (position / TAU * COUNTS_PER_REVOLUTION).round() as i32;
+        self.write(id, GOAL_POSITION, &counts.to_le_bytes())
+    }
+
+    /// Turns the servo with `id` at `velocity`, in radians per second.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo does not acknowledge
+    /// the write.
+    pub fn set_velocity(&mut self, id: u8, velocity: f64) -> Result<(), Error> {
+        let units = (velocity * 60.0 / TAU / RPM_PER_UNIT).round() as i32;
+        self.write(id, GOAL_VELOCITY, &units.to_le_bytes())
+    }
+
+    /// Returns the state of the servo with `id`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo does not answer.
+    pub fn feedback(&mut self, id: u8) -> Result<Feedback, Error> {
+        let table = self.read(id, PRESENT_CURRENT, PRESENT_POSITION + 4 - PRESENT_CURRENT)?;
+        parse_feedback(&table)
+    }
+
+    /// Returns the state of each servo in `ids`, in one round trip.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any servo does not answer.
+    pub fn feedback_all(&mut self, ids: &[u8]) -> Result<Vec<Feedback>, Error> {
+        let len = PRESENT_POSITION + 4 - PRESENT_CURRENT;
+        let ranges: Vec<_> = ids.iter().map(|&id| (id, PRESENT_CURRENT, len)).collect();
+        self.bulk_read(&ranges)?
+            .iter()
+            .map(|table| parse_feedback(table))
+            .collect()
+    }
+
+    fn send(&mut self, id: u8, instruction: u8, params: &[u8]) -> Result<(), Error> {
+        let mut body = vec![instruction];
+        body.extend_from_slice(params);
+        let body = stuff(&body);
+        let mut packet = HEADER.to_vec();
+        packet.push(id);
+        packet.extend_from_slice(&(body.len() as u16 + 2).to_le_bytes());
+        packet.extend_from_slice(&body);
+        packet.extend_from_slice(&crc16(&packet).to_le_bytes());
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+
+    /// Returns the parameters of the next status packet, which should come
+    /// from `id`.
+    fn receive(&mut self, id: u8) -> Result<Vec<u8>, Error> {
+        let mut window = [0; 4];
+
+        while window != HEADER {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte)?;
+            window.rotate_left(1);
+            window[3] = byte[0];
+        }
+
+        let mut fields = [0; 3];
+        self.stream.read_exact(&mut fields)?;
+        let len = usize::from(u16::from_le_bytes([fields[1], fields[2]]));
+
+        if len < 4 {
+            return Err(Error::new(ErrorKind::InvalidData, "short Dynamixel status"));
+        }
+
+        let mut rest = vec![0; len];
+        self.stream.read_exact(&mut rest)?;
+        let mut packet = HEADER.to_vec();
+        packet.extend_from_slice(&fields);
+        packet.extend_from_slice(&rest[..len - 2]);
+
+        if crc16(&packet).to_le_bytes() != rest[len - 2..] {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "corrupt Dynamixel status",
+            ));
+        }
+
+        let body = unstuff(&rest[..len - 2]);
+
+        if fields[0] != id || body[0] != STATUS {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected packet from servo {}", fields[0]),
+            ));
+        }
+
+        match body[1] & 0x7F {
+            0 => Ok(body[2..].to_vec()),
+            code => Err(Error::other(format!(
+                "servo {id} reported {}",
+                error_name(code)
+            ))),
+        }
+    }
+}
+
+fn error_name(code: u8) -> &'static str {
+    match code {
+        1 => "a failed result",
+        2 => "an unknown instruction",
+        3 => "a checksum error",
+        4 => "a value out of range",
+        5 => "a data length error",
+        6 => "a data limit error",
+        7 => "an access error",
+        _ => "an unknown error",
+    }
+}
+
+/// Parses the present current, velocity, and position, which lie back to
+/// back in the control table.
+fn parse_feedback(table: &[u8]) -> Result<Feedback, Error> {
+    if table.len() < 10 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "short Dynamixel feedback",
+        ));
+    }
+
+    let current = i16::from_le_bytes([table[0], table[1]]);
+    let velocity = i32::from_le_bytes([table[2], table[3], table[4], table[5]]);
+    let position = i32::from_le_bytes([table[6], table[7], table[8], table[9]]);
+    Ok(Feedback {
+        position: f64::from(position) / COUNTS_PER_REVOLUTION * TAU,
+        velocity: f64::from(velocity) * RPM_PER_UNIT * TAU / 60.0,
+        current: f64::from(current) * AMPERES_PER_UNIT,
+    })
+}
+
+fn le_u16(bytes: &[u8]) -> Result<u16, Error> {
+    match bytes {
+        [low, high, ..] => Ok(u16::from_le_bytes([*low, *high])),
+        _ => Err(Error::new(ErrorKind::InvalidData, "short Dynamixel status")),
+    }
+}
+
+/// Inserts an extra 0xFD after every 0xFF 0xFF 0xFD in `body`, so it cannot
+/// be mistaken for a header.
+fn stuff(body: &[u8]) -> Vec<u8> {
+    let mut stuffed = Vec::with_capacity(body.len());
+
+    for &byte in body {
+        stuffed.push(byte);
+
+        if stuffed.ends_with(&HEADER[..3]) {
+            stuffed.push(0xFD);
+        }
+    }
+
+    stuffed
+}
+
+/// Removes the bytes inserted by [`stuff`].
+fn unstuff(body: &[u8]) -> Vec<u8> {
+    let mut unstuffed = Vec::with_capacity(body.len());
+    let mut skip = false;
+
+    for &byte in body {
+        if skip {
+            skip = false;
+            continue;
+        }
+
+        unstuffed.push(byte);
+        skip = unstuffed.ends_with(&HEADER[..3]);
+    }
+
+    unstuffed
+}
+
+/// Computes the CRC-16 used by Protocol 2.0, with polynomial 0x8005.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc: u16, _| {
+            if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x8005
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct MockStream {
+        received: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.received.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn status(id: u8, error: u8, params: &[u8]) -> Vec<u8> {
+        let mut body = vec![STATUS, error];
+        body.extend_from_slice(params);
+        let body = stuff(&body);
+        let mut packet = HEADER.to_vec();
+        packet.push(id);
+        packet.extend_from_slice(&(body.len() as u16 + 2).to_le_bytes());
+        packet.extend_from_slice(&body);
+        packet.extend_from_slice(&crc16(&packet).to_le_bytes());
+        packet
+    }
+
+    fn bus(received: Vec<u8>) -> Dynamixel<MockStream> {
+        Dynamixel::new(MockStream {
+            received: Cursor::new(received),
+            sent: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn it_should_compute_the_protocol_crc() {
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn it_should_stuff_bytes_that_look_like_a_header() {
+        let body = [0x03, 0xFF, 0xFF, 0xFD, 0x01];
+        let stuffed = stuff(&body);
+        assert_eq!(stuffed, [0x03, 0xFF, 0xFF, 0xFD, 0xFD, 0x01]);
+        assert_eq!(unstuff(&stuffed), body);
+    }
+
+    #[test]
+    fn it_should_ping_a_servo() {
+        let mut bus = bus(status(1, 0, &[0x06, 0x04, 0x2A]));
+        assert_eq!(bus.ping(1).unwrap(), 1030);
+        // The example ping from the Protocol 2.0 documentation.
+        assert_eq!(
+            bus.into_inner().sent,
+            [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E]
+        );
+    }
+
+    #[test]
+    fn it_should_write_goals_and_surface_servo_errors() {
+        let mut received = status(1, 0, &[]);
+        received.extend(status(1, 0x07, &[]));
+        let mut bus = bus(received);
+        bus.set_position(1, std::f64::consts::PI).unwrap();
+        assert!(bus
+            .set_mode(1, Mode::Velocity)
+            .is_err_and(|error| error.to_string() == "servo 1 reported an access error"));
+        let sent = bus.into_inner().sent;
+        assert_eq!(sent[7..14], [WRITE, 116, 0, 0x00, 0x08, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn it_should_read_feedback_from_several_servos_at_once() {
+        let table = |current: i16, velocity: i32, position: i32| {
+            let mut table = current.to_le_bytes().to_vec();
+            table.extend(velocity.to_le_bytes());
+            table.extend(position.to_le_bytes());
+            table
+        };
+        let mut received = status(1, 0, &table(100, 0, 1024));
+        received.extend(status(2, 0, &table(-3, 10, -2048)));
+        let mut bus = bus(received);
+        let feedback = bus.feedback_all(&[1, 2]).unwrap();
+        assert!((feedback[0].position - TAU / 4.0).abs() < 1e-9);
+        assert!((feedback[0].current - 0.269).abs() < 1e-9);
+        assert!((feedback[1].position + TAU / 2.0).abs() < 1e-9);
+        assert!((feedback[1].velocity - 2.29 * TAU / 60.0).abs() < 1e-9);
+        let sent = bus.into_inner().sent;
+        assert_eq!(sent[4], BROADCAST_ID);
+        assert_eq!(sent[7..13], [BULK_READ, 1, 126, 0, 10, 0]);
+    }
+}
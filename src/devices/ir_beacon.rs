@@ -0,0 +1,413 @@
+//! Infrared beacons that guide the robot onto its dock.
+//!
+//! A DIY dock carries three IR LEDs behind baffles: one shining a narrow
+//! beam straight out along the approach, and one to either side. Each LED
+//! keys a 38 kHz carrier with a short code naming its [`Zone`], and takes
+//! its turn so that where the beams overlap the codes arrive one after
+//! another rather than on top of each other.
+//!
+//! On the robot, a TSOP-style receiver strips the carrier and pulls its
+//! output low while the carrier is present. An [`IrDecoder`] turns the
+//! timing of those pulses back into zones, and an [`Alignment`] combines
+//! the zones heard recently into where the robot is on the approach.
+//!
+//! Codes are pulse-width coded like Sony remotes: a long header mark, then
+//! eight bits, each a mark of one unit for 0 or two units for 1 followed by
+//! a space of one unit. The first four bits are the zone and the last four
+//! their complement, so a code mangled by sunlight or a reflection is
+//! dropped rather than read as another zone.
+
+use std::io::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::hal::{AdjustableFrequency, Edge};
+
+/// Frequency of the carrier, in hertz, that receivers are tuned to.
+pub const CARRIER_FREQUENCY: f64 = 38_000.0;
+
+/// Duty cycle that drives an IR LED while the carrier is on.
+const MARK_DUTY_CYCLE: f64 = 0.5;
+
+/// Length of one unit of a code. Receivers need at least ten carrier cycles
+/// in a burst to respond, which this leaves a wide margin over.
+const UNIT: Duration = Duration::from_micros(600);
+
+/// Length of the header mark, in units.
+const HEADER_UNITS: u32 = 4;
+
+/// Number of bits in a code.
+const CODE_BITS: u32 = 8;
+
+/// Silence left after each code, so receivers see where one ends before the
+/// next emitter starts.
+const FRAME_GAP: Duration = Duration::from_millis(6);
+
+/// Fraction of a unit a pulse may be off by and still be read. Receivers
+/// stretch marks and shorten spaces by up to a couple of carrier cycles.
+const TOLERANCE: f64 = 0.35;
+
+/// Beam of the dock the robot is in, as seen facing the dock.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Zone {
+    /// Left of the approach.
+    Left,
+    /// On the approach.
+    Center,
+    /// Right of the approach.
+    Right,
+}
+
+impl Zone {
+    /// Returns the four-bit code of the zone.
+    fn code(self) -> u8 {
+        match self {
+            Self::Left => 0b1001,
+            Self::Center => 0b0110,
+            Self::Right => 0b1100,
+        }
+    }
+
+    /// Returns the zone with the four-bit `code`.
+    fn from_code(code: u8) -> Option<Self> {
+        [Self::Left, Self::Center, Self::Right]
+            .into_iter()
+            .find(|zone| zone.code() == code)
+    }
+
+    /// Returns which way to turn to get onto the approach: 1 to turn left,
+    /// -1 to turn right, or 0 when already on it.
+    #[must_use]
+    pub fn correction(self) -> f64 {
+        match self {
+            Self::Left => -1.0,
+            Self::Center => 0.0,
+            Self::Right => 1.0,
+        }
+    }
+
+    /// Returns the pulses of the code broadcast in the zone, ending with the
+    /// last mark.
+    #[must_use]
+    pub fn pulses(self) -> Vec<Pulse> {
+        let code = (self.code() << 4) | (!self.code() & 0x0f);
+        let mut pulses = vec![Pulse::mark(HEADER_UNITS), Pulse::space(1)];
+
+        for bit in (0..CODE_BITS).rev() {
+            pulses.push(Pulse::mark(1 + u32::from((code >> bit) & 1)));
+            pulses.push(Pulse::space(1));
+        }
+
+        pulses.pop();
+        pulses
+    }
+}
+
+/// A burst of carrier, or the silence between bursts.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Pulse {
+    /// Whether the carrier is on.
+    pub mark: bool,
+    /// How long the pulse lasts.
+    pub duration: Duration,
+}
+
+impl Pulse {
+    fn mark(units: u32) -> Self {
+        Self {
+            mark: true,
+            duration: UNIT * units,
+        }
+    }
+
+    fn space(units: u32) -> Self {
+        Self {
+            mark: false,
+            duration: UNIT * units,
+        }
+    }
+}
+
+/// The three emitters of a dock.
+#[derive(Debug)]
+pub struct IrBeacon<P> {
+    emitters: [(Zone, P); 3],
+}
+
+impl<P: AdjustableFrequency> IrBeacon<P> {
+    /// Creates a new `IrBeacon` from the outputs driving the `left`,
+    /// `center` and `right` emitters, initially dark.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an output cannot be driven.
+    pub fn new(left: P, center: P, right: P) -> Result<Self, Error> {
+        let mut emitters = [
+            (Zone::Left, left),
+            (Zone::Center, center),
+            (Zone::Right, right),
+        ];
+
+        for (_, pwm) in &mut emitters {
+            pwm.set_frequency(CARRIER_FREQUENCY)?;
+            pwm.set_duty_cycle(0.0)?;
+        }
+
+        Ok(Self { emitters })
+    }
+
+    /// Sends one code from each emitter in turn, taking about 70 ms.
+    ///
+    /// Marks are timed by sleeping, which is close enough for the
+    /// receiver's tolerance on an otherwise idle core.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an output cannot be driven.
+    pub fn broadcast(&mut self) -> Result<(), Error> {
+        for (zone, pwm) in &mut self.emitters {
+            for pulse in zone.pulses() {
+                pwm.set_duty_cycle(if pulse.mark { MARK_DUTY_CYCLE } else { 0.0 })?;
+                thread::sleep(pulse.duration);
+            }
+
+            pwm.set_duty_cycle(0.0)?;
+            thread::sleep(FRAME_GAP);
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes zones from the pulses of a receiver.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct IrDecoder {
+    bits: Option<(u8, u32)>,
+    expect_space: bool,
+    last_edge: Option<Instant>,
+}
+
+impl IrDecoder {
+    /// Creates a new `IrDecoder`, waiting for a header.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the `edge` on the output of a receiver at `at`, and returns
+    /// the zone if it ended a code. The output is active low, so a falling
+    /// edge starts a mark.
+    pub fn edge(&mut self, edge: Edge, at: Instant) -> Option<Zone> {
+        let last_edge = self.last_edge.replace(at)?;
+        self.pulse(Pulse {
+            mark: edge == Edge::Rising,
+            duration: at.saturating_duration_since(last_edge),
+        })
+    }
+
+    /// Decodes `pulse`, and returns the zone if it ended a code.
+    pub fn pulse(&mut self, pulse: Pulse) -> Option<Zone> {
+        let units = units(pulse.duration);
+
+        if !pulse.mark {
+            if self.expect_space && units == Some(1) {
+                self.expect_space = false;
+            } else {
+                self.reset();
+            }
+
+            return None;
+        }
+
+        let Some((code, count)) = self.bits.filter(|_| !self.expect_space) else {
+            self.start(units);
+            return None;
+        };
+
+        let bit = match units {
+            Some(1) => 0,
+            Some(2) => 1,
+            _ => {
+                self.start(units);
+                return None;
+            }
+        };
+        let code = (code << 1) | bit;
+
+        if count + 1 < CODE_BITS {
+            self.bits = Some((code, count + 1));
+            self.expect_space = true;
+            return None;
+        }
+
+        self.reset();
+
+        if code >> 4 != (!code & 0x0f) {
+            return None;
+        }
+
+        Zone::from_code(code >> 4)
+    }
+
+    /// Starts a code if a mark of `units` is a header.
+    fn start(&mut self, units: Option<u32>) {
+        if units == Some(HEADER_UNITS) {
+            self.bits = Some((0, 0));
+            self.expect_space = true;
+        } else {
+            self.reset();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.bits = None;
+        self.expect_space = false;
+    }
+}
+
+/// Returns the whole number of units `duration` is within the tolerance of.
+fn units(duration: Duration) -> Option<u32> {
+    let units = duration.as_secs_f64() / UNIT.as_secs_f64();
+    let rounded = units.round();
+
+    // Truncation is intended: codes are a few units long.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    (rounded >= 1.0 && (units - rounded).abs() <= TOLERANCE).then_some(rounded as u32)
+}
+
+/// Where the robot is on the approach, from the zones heard recently.
+///
+/// Near the approach the robot hears the side beams as well as the centre
+/// one, and beyond the end of the centre beam it hears both side beams
+/// where they cross, which also puts it on the approach.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Alignment {
+    heard: [Option<Instant>; 3],
+    window: Duration,
+}
+
+impl Alignment {
+    /// Creates a new `Alignment` that remembers a zone for `window` after it
+    /// was last heard. A few broadcasts long is enough to ride out a code
+    /// lost to interference.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            heard: [None; 3],
+            window,
+        }
+    }
+
+    /// Records hearing `zone` at `at`.
+    pub fn record(&mut self, zone: Zone, at: Instant) {
+        self.heard[zone as usize] = Some(at);
+    }
+
+    /// Returns the zone the robot is in at `now`, or `None` if the dock has
+    /// not been heard within the window.
+    #[must_use]
+    pub fn zone(&self, now: Instant) -> Option<Zone> {
+        let [left, center, right] = self
+            .heard
+            .map(|at| at.is_some_and(|at| now.saturating_duration_since(at) <= self.window));
+
+        match (left, center, right) {
+            (_, true, _) | (true, _, true) => Some(Zone::Center),
+            (true, _, _) => Some(Zone::Left),
+            (_, _, true) => Some(Zone::Right),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockPwm;
+
+    fn decode(decoder: &mut IrDecoder, pulses: &[Pulse]) -> Vec<Zone> {
+        pulses
+            .iter()
+            .filter_map(|&pulse| decoder.pulse(pulse))
+            .collect()
+    }
+
+    #[test]
+    fn it_should_decode_the_code_of_each_zone() {
+        let mut decoder = IrDecoder::new();
+
+        for zone in [Zone::Left, Zone::Center, Zone::Right] {
+            assert_eq!(decode(&mut decoder, &zone.pulses()), [zone]);
+        }
+    }
+
+    #[test]
+    fn it_should_tolerate_receivers_stretching_marks() {
+        let mut decoder = IrDecoder::new();
+        let start = Instant::now();
+        let mut at = start;
+        let mut zones = Vec::new();
+
+        for pulse in Zone::Right.pulses() {
+            let stretch = Duration::from_micros(150);
+            let edge = if pulse.mark {
+                Edge::Falling
+            } else {
+                Edge::Rising
+            };
+            zones.extend(decoder.edge(edge, at));
+            at += if pulse.mark {
+                pulse.duration + stretch
+            } else {
+                pulse.duration - stretch
+            };
+        }
+
+        zones.extend(decoder.edge(Edge::Rising, at));
+        assert_eq!(zones, [Zone::Right]);
+    }
+
+    #[test]
+    fn it_should_drop_corrupted_codes() {
+        let mut decoder = IrDecoder::new();
+        let mut pulses = Zone::Left.pulses();
+        pulses[2] = Pulse::mark(1);
+        assert!(decode(&mut decoder, &pulses).is_empty());
+        let mut pulses = Zone::Center.pulses();
+        pulses[5] = Pulse::space(3);
+        assert!(decode(&mut decoder, &pulses).is_empty());
+        assert_eq!(decode(&mut decoder, &Zone::Center.pulses()), [Zone::Center]);
+    }
+
+    #[test]
+    fn it_should_key_the_carrier_from_each_emitter_in_turn() {
+        let mut beacon =
+            IrBeacon::new(MockPwm::default(), MockPwm::default(), MockPwm::default()).unwrap();
+        beacon.broadcast().unwrap();
+
+        for (zone, pwm) in &beacon.emitters {
+            assert_eq!(pwm.frequency, CARRIER_FREQUENCY);
+            let marks = zone.pulses().iter().filter(|pulse| pulse.mark).count();
+            assert_eq!(
+                pwm.duty_cycles.iter().filter(|&&duty| duty > 0.0).count(),
+                marks
+            );
+            assert_eq!(pwm.duty_cycles.last(), Some(&0.0));
+        }
+    }
+
+    #[test]
+    fn it_should_combine_zones_heard_recently() {
+        let start = Instant::now();
+        let window = Duration::from_millis(300);
+        let mut alignment = Alignment::new(window);
+        assert_eq!(alignment.zone(start), None);
+        alignment.record(Zone::Left, start);
+        assert_eq!(alignment.zone(start), Some(Zone::Left));
+        alignment.record(Zone::Right, start + Duration::from_millis(100));
+        assert_eq!(alignment.zone(start + window), Some(Zone::Center));
+        assert_eq!(
+            alignment.zone(start + Duration::from_millis(350)),
+            Some(Zone::Right)
+        );
+        assert_eq!(alignment.zone(start + Duration::from_secs(1)), None);
+    }
+}
@@ -0,0 +1,511 @@
+//! Colored LEDs, color-space helpers, and animation presets.
+//!
+//! Animations are pure functions of elapsed time, so whichever loop drives the
+//! LEDs only has to call [`LedStrip::render`] with the time since the
+//! animation started.
+
+use std::f64::consts::TAU;
+use std::io::Error;
+use std::time::Duration;
+
+use crate::hal::PwmOutput;
+
+pub mod apa102;
+pub mod patterns;
+
+pub use patterns::StatusPatterns;
+
+/// Exponent used to map perceived brightness to duty cycle.
+const DEFAULT_GAMMA: f64 = 2.2;
+
+/// How long each flash of a blink code, and each gap between flashes, lasts.
+const CODE_FLASH: Duration = Duration::from_millis(250);
+
+/// How long a blink code stays off before repeating.
+const CODE_PAUSE: Duration = Duration::from_millis(1500);
+
+/// A color with 8-bit red, green, and blue components.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Rgb {
+    /// Red component.
+    pub red: u8,
+    /// Green component.
+    pub green: u8,
+    /// Blue component.
+    pub blue: u8,
+}
+
+impl Rgb {
+    /// Black, or off.
+    pub const BLACK: Self = Self::new(0, 0, 0);
+    /// Red.
+    pub const RED: Self = Self::new(255, 0, 0);
+    /// Orange.
+    pub const ORANGE: Self = Self::new(255, 96, 0);
+    /// Yellow.
+    pub const YELLOW: Self = Self::new(255, 200, 0);
+    /// Green.
+    pub const GREEN: Self = Self::new(0, 255, 0);
+    /// Cyan.
+    pub const CYAN: Self = Self::new(0, 255, 255);
+    /// Blue.
+    pub const BLUE: Self = Self::new(0, 0, 255);
+    /// Purple.
+    pub const PURPLE: Self = Self::new(160, 0, 255);
+    /// White.
+    pub const WHITE: Self = Self::new(255, 255, 255);
+
+    /// Creates a new color from its components.
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+
+    /// Returns the color with each component scaled by `factor`, from 0 to 1.
+    #[must_use]
+    pub fn scale(self, factor: f64) -> Self {
+        let factor = factor.clamp(0.0, 1.0);
+        let scale = |component: u8| (f64::from(component) * factor).round() as u8;
+        Self::new(scale(self.red), scale(self.green), scale(self.blue))
+    }
+}
+
+impl From<Hsv> for Rgb {
+    fn from(hsv: Hsv) -> Self {
+        let hue = hsv.hue.rem_euclid(360.0) / 60.0;
+        let saturation = hsv.saturation.clamp(0.0, 1.0);
+        let value = hsv.value.clamp(0.0, 1.0);
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (red, green, blue) = match hue as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let offset = value - chroma;
+        let component = |c: f64| ((c + offset) * 255.0).round() as u8;
+        Self::new(component(red), component(green), component(blue))
+    }
+}
+
+/// A color as hue, saturation, and value, which is easier to animate than
+/// RGB.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Hsv {
+    /// Hue in degrees, where 0 is red, 120 is green, and 240 is blue.
+    pub hue: f64,
+    /// Saturation, from 0 for gray to 1 for fully saturated.
+    pub saturation: f64,
+    /// Value, from 0 for black to 1 for full brightness.
+    pub value: f64,
+}
+
+impl Hsv {
+    /// Creates a new color from its components.
+    pub fn new(hue: f64, saturation: f64, value: f64) -> Self {
+        Self {
+            hue,
+            saturation,
+            value,
+        }
+    }
+}
+
+/// A repeating pattern of colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Animation {
+    /// A steady color.
+    Solid(Rgb),
+    /// A color switched on and off, each for half of `period`.
+    Blink {
+        /// Color while on.
+        color: Rgb,
+        /// Duration of one on–off cycle.
+        period: Duration,
+    },
+    /// A color faded smoothly in and out.
+    Breathe {
+        /// Color at full brightness.
+        color: Rgb,
+        /// Duration of one fade cycle.
+        period: Duration,
+    },
+    /// Every hue in turn at full saturation.
+    Rainbow {
+        /// Duration of one trip around the color wheel.
+        period: Duration,
+    },
+    /// A color flashed `count` times and then held off, so that a fault can
+    /// be told apart by counting flashes.
+    Code {
+        /// Color of each flash.
+        color: Rgb,
+        /// Number of flashes in each repetition.
+        count: u8,
+    },
+}
+
+impl Animation {
+    /// Returns the color shown `elapsed` after the animation started.
+    #[must_use]
+    pub fn color_at(&self, elapsed: Duration) -> Rgb {
+        match *self {
+            Self::Solid(color) => color,
+            Self::Blink { color, period } => {
+                if phase(elapsed, period) < 0.5 {
+                    color
+                } else {
+                    Rgb::BLACK
+                }
+            }
+            Self::Breathe { color, period } => {
+                color.scale((1.0 - (phase(elapsed, period) * TAU).cos()) / 2.0)
+            }
+            Self::Rainbow { period } => Hsv::new(phase(elapsed, period) * 360.0, 1.0, 1.0).into(),
+            Self::Code { color, count } => {
+                let flashes = CODE_FLASH * 2 * u32::from(count);
+                let position =
+                    phase(elapsed, flashes + CODE_PAUSE) * (flashes + CODE_PAUSE).as_secs_f64();
+                let step = (position / CODE_FLASH.as_secs_f64()) as u32;
+
+                if position < flashes.as_secs_f64() && step.is_multiple_of(2) {
+                    color
+                } else {
+                    Rgb::BLACK
+                }
+            }
+        }
+    }
+}
+
+/// Robot states that are shown on a status LED.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Status {
+    /// The robot is starting its subsystems.
+    Booting,
+    /// The robot is ready and waiting for commands.
+    Idle,
+    /// The robot is being driven or running a routine.
+    Active,
+    /// The robot is charging.
+    Charging,
+    /// The battery is low.
+    LowBattery,
+    /// A subsystem has failed.
+    Fault,
+    /// The emergency stop is engaged.
+    EmergencyStop,
+}
+
+impl Status {
+    /// Returns the name of the status in snake case, as used by
+    /// [`StatusPatterns`].
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Booting => "booting",
+            Self::Idle => "idle",
+            Self::Active => "active",
+            Self::Charging => "charging",
+            Self::LowBattery => "low_battery",
+            Self::Fault => "fault",
+            Self::EmergencyStop => "emergency_stop",
+        }
+    }
+
+    /// Returns the animation preset that shows this status.
+    #[must_use]
+    pub fn animation(self) -> Animation {
+        let ms = Duration::from_millis;
+
+        match self {
+            Self::Booting => Animation::Rainbow { period: ms(2000) },
+            Self::Idle => Animation::Breathe {
+                color: Rgb::CYAN,
+                period: ms(3000),
+            },
+            Self::Active => Animation::Solid(Rgb::GREEN),
+            Self::Charging => Animation::Breathe {
+                color: Rgb::YELLOW,
+                period: ms(2000),
+            },
+            Self::LowBattery => Animation::Blink {
+                color: Rgb::ORANGE,
+                period: ms(1000),
+            },
+            Self::Fault => Animation::Blink {
+                color: Rgb::RED,
+                period: ms(500),
+            },
+            Self::EmergencyStop => Animation::Solid(Rgb::RED),
+        }
+    }
+}
+
+/// A chain of individually addressable LEDs.
+///
+/// Colors are buffered by [`LedStrip::set_pixel`] and shown together by
+/// [`LedStrip::show`], so animation code can draw a frame without caring how
+/// the strip is driven.
+pub trait LedStrip {
+    /// Returns the number of LEDs.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if the strip has no LEDs.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sets the color of the LED at `index` in the next frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than the number of LEDs.
+    fn set_pixel(&mut self, index: usize, color: Rgb);
+
+    /// Shows the buffered frame.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the strip cannot be driven.
+    fn show(&mut self) -> Result<(), Error>;
+
+    /// Sets every LED to `color` in the next frame.
+    fn fill(&mut self, color: Rgb) {
+        for index in 0..self.len() {
+            self.set_pixel(index, color);
+        }
+    }
+
+    /// Draws and shows the frame of `animation` at `elapsed` since it started
+    /// on every LED.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the strip cannot be driven.
+    fn render(&mut self, animation: &Animation, elapsed: Duration) -> Result<(), Error> {
+        self.fill(animation.color_at(elapsed));
+        self.show()
+    }
+}
+
+/// An RGB LED driven by three PWM outputs.
+#[derive(Debug)]
+pub struct RgbLed<P> {
+    blue: P,
+    brightness: f64,
+    common_anode: bool,
+    gamma: f64,
+    green: P,
+    pending: Rgb,
+    red: P,
+}
+
+impl<P: PwmOutput> RgbLed<P> {
+    /// Creates a new `RgbLed` for a common-cathode LED, whose channels light
+    /// when driven high.
+    pub fn new(red: P, green: P, blue: P) -> Self {
+        Self {
+            blue,
+            brightness: 1.0,
+            common_anode: false,
+            gamma: DEFAULT_GAMMA,
+            green,
+            pending: Rgb::BLACK,
+            red,
+        }
+    }
+
+    /// Inverts the outputs for a common-anode LED, whose channels light when
+    /// driven low.
+    #[must_use]
+    pub fn with_common_anode(mut self, common_anode: bool) -> Self {
+        self.common_anode = common_anode;
+        self
+    }
+
+    /// Sets the gamma exponent; 1 disables gamma correction.
+    #[must_use]
+    pub fn with_gamma(mut self, gamma: f64) -> Self {
+        self.gamma = gamma;
+        self
+    }
+
+    /// Sets a brightness, from 0 to 1, applied to every color shown.
+    pub fn set_brightness(&mut self, brightness: f64) {
+        self.brightness = brightness.clamp(0.0, 1.0);
+    }
+
+    /// Shows `color`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any output cannot be driven.
+    pub fn set_color(&mut self, color: Rgb) -> Result<(), Error> {
+        let color = color.scale(self.brightness);
+        let red = self.duty_cycle(color.red);
+        let green = self.duty_cycle(color.green);
+        let blue = self.duty_cycle(color.blue);
+        self.red.set_duty_cycle(red)?;
+        self.green.set_duty_cycle(green)?;
+        self.blue.set_duty_cycle(blue)
+    }
+
+    /// Shows a color given as hue, saturation, and value.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any output cannot be driven.
+    pub fn set_hsv(&mut self, hsv: Hsv) -> Result<(), Error> {
+        self.set_color(hsv.into())
+    }
+
+    /// Turns the LED off.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any output cannot be driven.
+    pub fn off(&mut self) -> Result<(), Error> {
+        self.set_color(Rgb::BLACK)
+    }
+
+    /// Consumes the LED and returns its red, green, and blue outputs.
+    pub fn into_outputs(self) -> (P, P, P) {
+        (self.red, self.green, self.blue)
+    }
+
+    fn duty_cycle(&self, component: u8) -> f64 {
+        let duty_cycle = gamma_correct(component, self.gamma);
+
+        if self.common_anode {
+            1.0 - duty_cycle
+        } else {
+            duty_cycle
+        }
+    }
+}
+
+impl<P: PwmOutput> LedStrip for RgbLed<P> {
+    fn len(&self) -> usize {
+        1
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Rgb) {
+        assert_eq!(index, 0, "index should be 0 for a single LED");
+        self.pending = color;
+    }
+
+    fn show(&mut self) -> Result<(), Error> {
+        self.set_color(self.pending)
+    }
+}
+
+/// Maps an 8-bit component to the duty cycle that looks linearly bright to
+/// the eye.
+#[must_use]
+pub fn gamma_correct(component: u8, gamma: f64) -> f64 {
+    (f64::from(component) / 255.0).powf(gamma)
+}
+
+/// Returns how far through its current period an animation is, from 0 to 1.
+fn phase(elapsed: Duration, period: Duration) -> f64 {
+    if period.is_zero() {
+        return 0.0;
+    }
+
+    (elapsed.as_secs_f64() / period.as_secs_f64()).fract()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockPwm;
+
+    fn led() -> RgbLed<MockPwm> {
+        RgbLed::new(
+            MockPwm::new(1000.0),
+            MockPwm::new(1000.0),
+            MockPwm::new(1000.0),
+        )
+    }
+
+    #[test]
+    fn it_should_convert_primary_and_secondary_hues_to_rgb() {
+        assert_eq!(Rgb::from(Hsv::new(0.0, 1.0, 1.0)), Rgb::RED);
+        assert_eq!(Rgb::from(Hsv::new(120.0, 1.0, 1.0)), Rgb::GREEN);
+        assert_eq!(Rgb::from(Hsv::new(-120.0, 1.0, 1.0)), Rgb::BLUE);
+        assert_eq!(Rgb::from(Hsv::new(180.0, 1.0, 1.0)), Rgb::CYAN);
+        assert_eq!(Rgb::from(Hsv::new(0.0, 0.0, 0.5)), Rgb::new(128, 128, 128));
+    }
+
+    #[test]
+    fn it_should_gamma_correct_each_channel() {
+        let mut led = led();
+        led.set_color(Rgb::new(255, 128, 0)).unwrap();
+        let (red, green, blue) = led.into_outputs();
+        assert_eq!(red.duty_cycle, 1.0);
+        assert!((green.duty_cycle - (128.0_f64 / 255.0).powf(2.2)).abs() < 1e-12);
+        assert_eq!(blue.duty_cycle, 0.0);
+    }
+
+    #[test]
+    fn it_should_invert_the_outputs_of_a_common_anode_led() {
+        let mut led = led().with_common_anode(true).with_gamma(1.0);
+        led.set_color(Rgb::new(255, 51, 0)).unwrap();
+        let (red, green, blue) = led.into_outputs();
+        assert_eq!(red.duty_cycle, 0.0);
+        assert!((green.duty_cycle - 0.8).abs() < 1e-12);
+        assert_eq!(blue.duty_cycle, 1.0);
+    }
+
+    #[test]
+    fn it_should_apply_the_brightness() {
+        let mut led = led().with_gamma(1.0);
+        led.set_brightness(0.2);
+        led.set_color(Rgb::WHITE).unwrap();
+        assert!((led.red.duty_cycle - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn it_should_blink_for_half_of_each_period() {
+        let animation = Status::Fault.animation();
+        assert_eq!(animation.color_at(Duration::from_millis(100)), Rgb::RED);
+        assert_eq!(animation.color_at(Duration::from_millis(300)), Rgb::BLACK);
+        assert_eq!(animation.color_at(Duration::from_millis(600)), Rgb::RED);
+    }
+
+    #[test]
+    fn it_should_breathe_from_black_to_full_color() {
+        let animation = Animation::Breathe {
+            color: Rgb::WHITE,
+            period: Duration::from_secs(2),
+        };
+        assert_eq!(animation.color_at(Duration::ZERO), Rgb::BLACK);
+        assert_eq!(animation.color_at(Duration::from_secs(1)), Rgb::WHITE);
+    }
+
+    #[test]
+    fn it_should_flash_a_blink_code_and_pause() {
+        let animation = Animation::Code {
+            color: Rgb::RED,
+            count: 3,
+        };
+        let ms = Duration::from_millis;
+        assert_eq!(animation.color_at(ms(100)), Rgb::RED);
+        assert_eq!(animation.color_at(ms(300)), Rgb::BLACK);
+        assert_eq!(animation.color_at(ms(1100)), Rgb::RED);
+        assert_eq!(animation.color_at(ms(1600)), Rgb::BLACK);
+        assert_eq!(animation.color_at(ms(3100)), Rgb::RED);
+    }
+
+    #[test]
+    fn it_should_render_the_animation_for_a_status() {
+        let mut led = led();
+        led.render(&Status::EmergencyStop.animation(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(led.red.duty_cycle, 1.0);
+        assert_eq!(led.green.duty_cycle, 0.0);
+    }
+}
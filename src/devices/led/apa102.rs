@@ -0,0 +1,150 @@
+//! Driver for APA102 (DotStar) LED strips over SPI.
+//!
+//! Unlike the WS2812, the APA102 has separate clock and data lines, so it can
+//! be driven by the SPI peripheral at any clock rate without precise timing.
+
+use std::io::Error;
+
+use super::{LedStrip, Rgb};
+use crate::hal::Spi;
+
+/// Maximum global brightness of an LED.
+pub const MAX_BRIGHTNESS: u8 = 31;
+
+/// An APA102 strip on an SPI bus.
+#[derive(Debug)]
+pub struct Apa102<S> {
+    brightness: u8,
+    frame: Vec<u8>,
+    pixels: Vec<Rgb>,
+    spi: S,
+}
+
+impl<S: Spi> Apa102<S> {
+    /// Creates a new `Apa102` strip of `len` LEDs, all off, at full global
+    /// brightness.
+    pub fn new(spi: S, len: usize) -> Self {
+        Self {
+            brightness: MAX_BRIGHTNESS,
+            frame: Vec::new(),
+            pixels: vec![Rgb::BLACK; len],
+            spi,
+        }
+    }
+
+    /// Sets the 5-bit global brightness applied by every LED in the next
+    /// frame.
+    ///
+    /// Dimming with the global brightness keeps the full 8-bit range of each
+    /// color, at the cost of a slower current regulation loop that can
+    /// flicker on camera.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness.min(MAX_BRIGHTNESS);
+    }
+
+    /// Returns the color of the LED at `index` in the next frame.
+    #[must_use]
+    pub fn pixel(&self, index: usize) -> Rgb {
+        self.pixels[index]
+    }
+
+    /// Consumes the strip and returns its bus.
+    pub fn into_inner(self) -> S {
+        self.spi
+    }
+
+    fn encode(&mut self) {
+        let end_frame_len = self.pixels.len().div_ceil(16).max(4);
+        self.frame.clear();
+        self.frame.extend_from_slice(&[0; 4]);
+
+        for pixel in &self.pixels {
+            self.frame.extend_from_slice(&[
+                0xE0 | self.brightness,
+                pixel.blue,
+                pixel.green,
+                pixel.red,
+            ]);
+        }
+
+        self.frame.resize(self.frame.len() + end_frame_len, 0);
+    }
+}
+
+impl<S: Spi> LedStrip for Apa102<S> {
+    fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Rgb) {
+        self.pixels[index] = color;
+    }
+
+    fn show(&mut self) -> Result<(), Error> {
+        self.encode();
+        self.spi.write(&self.frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::devices::led::Animation;
+
+    #[derive(Debug, Default)]
+    struct Bus {
+        written: Vec<u8>,
+    }
+
+    impl Spi for Bus {
+        fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+            self.written = bytes.to_vec();
+            Ok(())
+        }
+
+        fn transfer(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+            self.write(bytes)?;
+            buffer.fill(0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_should_frame_each_pixel_in_blue_green_red_order() {
+        let mut strip = Apa102::new(Bus::default(), 2);
+        strip.set_pixel(1, Rgb::new(1, 2, 3));
+        strip.set_brightness(7);
+        strip.show().unwrap();
+        let bus = strip.into_inner();
+        assert_eq!(
+            bus.written,
+            [0, 0, 0, 0, 0xE7, 0, 0, 0, 0xE7, 3, 2, 1, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn it_should_clock_half_a_bit_per_pixel_at_the_end_of_a_long_strip() {
+        let mut strip = Apa102::new(Bus::default(), 100);
+        strip.show().unwrap();
+        assert_eq!(strip.into_inner().written.len(), 4 + 100 * 4 + 7);
+    }
+
+    #[test]
+    fn it_should_limit_the_global_brightness() {
+        let mut strip = Apa102::new(Bus::default(), 1);
+        strip.set_brightness(255);
+        strip.show().unwrap();
+        assert_eq!(strip.into_inner().written[4], 0xFF);
+    }
+
+    #[test]
+    fn it_should_render_an_animation_on_every_pixel() {
+        let mut strip = Apa102::new(Bus::default(), 3);
+        strip
+            .render(&Animation::Solid(Rgb::BLUE), Duration::ZERO)
+            .unwrap();
+        assert!((0..strip.len()).all(|index| strip.pixel(index) == Rgb::BLUE));
+    }
+}
@@ -0,0 +1,372 @@
+//! A small language mapping robot states and faults to LED patterns.
+//!
+//! Each line of a pattern file maps one state or fault to an animation:
+//!
+//! ```text
+//! # Lines starting with a hash are comments.
+//! state disarmed = breathe blue 3s
+//! state navigating = solid green
+//! state low_battery = blink orange 1s
+//! fault imu = code red 2
+//! fault * = blink red 500ms
+//! ```
+//!
+//! Animations are `solid COLOR`, `blink COLOR PERIOD`, `breathe COLOR
+//! PERIOD`, `rainbow PERIOD`, and `code COLOR COUNT`. Colors are named, as in
+//! `red` or `cyan`, or given as `#RRGGBB`, and periods end in `ms` or `s`.
+//! Faults take precedence over the state, in the order they are declared,
+//! with `fault *` matching any fault without a pattern of its own.
+
+use std::io::Error;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use super::{Animation, LedStrip, Rgb};
+
+/// Name matching any fault.
+const ANY_FAULT: &str = "*";
+
+/// Patterns shown for the states in [`Status`](super::Status), by name, and for any fault.
+const DEFAULT_PATTERNS: &str = "\
+state booting = rainbow 2s
+state idle = breathe cyan 3s
+state active = solid green
+state charging = breathe yellow 2s
+state low_battery = blink orange 1s
+state emergency_stop = solid red
+fault * = blink red 500ms
+";
+
+/// Mapping from robot states and faults to the animations that show them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusPatterns {
+    faults: Vec<(String, Animation)>,
+    states: Vec<(String, Animation)>,
+}
+
+impl StatusPatterns {
+    /// Creates a new, empty `StatusPatterns`.
+    pub fn new() -> Self {
+        Self {
+            faults: Vec::new(),
+            states: Vec::new(),
+        }
+    }
+
+    /// Shows `animation` while the robot is in `state`, replacing any
+    /// pattern it had.
+    #[must_use]
+    pub fn with_state(mut self, state: &str, animation: Animation) -> Self {
+        set(&mut self.states, state, animation);
+        self
+    }
+
+    /// Shows `animation` while `fault` is active, or any fault without a
+    /// pattern of its own if `fault` is `*`.
+    #[must_use]
+    pub fn with_fault(mut self, fault: &str, animation: Animation) -> Self {
+        set(&mut self.faults, fault, animation);
+        self
+    }
+
+    /// Returns the animation showing the robot in `state` with `faults`
+    /// active, or `None` if nothing matches.
+    #[must_use]
+    pub fn animation(&self, state: &str, faults: &[&str]) -> Option<Animation> {
+        let named = self
+            .faults
+            .iter()
+            .find(|(name, _)| faults.contains(&name.as_str()));
+        let any = || {
+            self.faults
+                .iter()
+                .find(|(name, _)| name == ANY_FAULT)
+                .filter(|_| !faults.is_empty())
+        };
+        let state = || self.states.iter().find(|(name, _)| name == state);
+        named
+            .or_else(any)
+            .or_else(state)
+            .map(|(_, animation)| *animation)
+    }
+}
+
+impl Default for StatusPatterns {
+    /// Returns the patterns of the presets in [`Status`](super::Status),
+    /// with states named by [`Status::name`](super::Status::name).
+    fn default() -> Self {
+        DEFAULT_PATTERNS
+            .parse()
+            .expect("default patterns should parse")
+    }
+}
+
+impl FromStr for StatusPatterns {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut patterns = Self::new();
+
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {message}", index + 1);
+            let (target, animation) = line.split_once('=').ok_or_else(|| error("expected `=`"))?;
+            let animation = parse_animation(animation).map_err(|message| error(&message))?;
+
+            match target.split_whitespace().collect::<Vec<_>>()[..] {
+                ["state", name] => patterns = patterns.with_state(name, animation),
+                ["fault", name] => patterns = patterns.with_fault(name, animation),
+                _ => return Err(error("expected `state NAME` or `fault NAME`")),
+            }
+        }
+
+        Ok(patterns)
+    }
+}
+
+/// Shows the pattern for the robot’s state on an LED strip, restarting the
+/// animation whenever the pattern changes.
+#[derive(Debug)]
+pub struct StatusIndicator {
+    current: Option<(Animation, Instant)>,
+    patterns: StatusPatterns,
+}
+
+impl StatusIndicator {
+    /// Creates a new `StatusIndicator` showing `patterns`.
+    pub fn new(patterns: StatusPatterns) -> Self {
+        Self {
+            current: None,
+            patterns,
+        }
+    }
+
+    /// Draws the frame at `now` showing `state` with `faults` active,
+    /// turning the strip off if nothing matches.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the strip cannot be driven.
+    pub fn update(
+        &mut self,
+        strip: &mut impl LedStrip,
+        state: &str,
+        faults: &[&str],
+        now: Instant,
+    ) -> Result<(), Error> {
+        let Some(animation) = self.patterns.animation(state, faults) else {
+            self.current = None;
+            strip.fill(Rgb::BLACK);
+            return strip.show();
+        };
+
+        let started = match self.current {
+            Some((current, started)) if current == animation => started,
+            _ => now,
+        };
+        self.current = Some((animation, started));
+        strip.render(&animation, now.saturating_duration_since(started))
+    }
+}
+
+fn set(patterns: &mut Vec<(String, Animation)>, name: &str, animation: Animation) {
+    match patterns.iter_mut().find(|(existing, _)| existing == name) {
+        Some((_, existing)) => *existing = animation,
+        None => patterns.push((name.to_owned(), animation)),
+    }
+}
+
+pub(crate) fn parse_animation(s: &str) -> Result<Animation, String> {
+    let words: Vec<_> = s.split_whitespace().collect();
+
+    match words[..] {
+        ["solid", color] => Ok(Animation::Solid(parse_color(color)?)),
+        ["blink", color, period] => Ok(Animation::Blink {
+            color: parse_color(color)?,
+            period: parse_period(period)?,
+        }),
+        ["breathe", color, period] => Ok(Animation::Breathe {
+            color: parse_color(color)?,
+            period: parse_period(period)?,
+        }),
+        ["rainbow", period] => Ok(Animation::Rainbow {
+            period: parse_period(period)?,
+        }),
+        ["code", color, count] => Ok(Animation::Code {
+            color: parse_color(color)?,
+            count: count
+                .parse()
+                .map_err(|_| format!("invalid blink count `{count}`"))?,
+        }),
+        _ => Err(format!("invalid animation `{}`", s.trim())),
+    }
+}
+
+fn parse_color(s: &str) -> Result<Rgb, String> {
+    let color = match s {
+        "black" | "off" => Rgb::BLACK,
+        "red" => Rgb::RED,
+        "orange" => Rgb::ORANGE,
+        "yellow" => Rgb::YELLOW,
+        "green" => Rgb::GREEN,
+        "cyan" => Rgb::CYAN,
+        "blue" => Rgb::BLUE,
+        "purple" => Rgb::PURPLE,
+        "white" => Rgb::WHITE,
+        _ => {
+            let hex = s
+                .strip_prefix('#')
+                .filter(|hex| hex.len() == 6)
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("invalid color `{s}`"))?;
+            let [_, red, green, blue] = hex.to_be_bytes();
+            Rgb::new(red, green, blue)
+        }
+    };
+    Ok(color)
+}
+
+fn parse_period(s: &str) -> Result<Duration, String> {
+    let error = || format!("invalid period `{s}`");
+
+    if let Some(ms) = s.strip_suffix("ms") {
+        ms.parse().map(Duration::from_millis).map_err(|_| error())
+    } else if let Some(seconds) = s.strip_suffix('s') {
+        seconds
+            .parse()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .ok_or_else(error)
+    } else {
+        Err(error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::led::Status;
+
+    const PATTERNS: &str = "
+        # Shown while nothing is wrong.
+        state disarmed = breathe blue 3s
+        state navigating = solid #00FF40
+        fault imu = code red 2
+        fault * = blink red 500ms
+    ";
+
+    #[derive(Debug, Default)]
+    struct Strip {
+        shown: Vec<Rgb>,
+    }
+
+    impl LedStrip for Strip {
+        fn len(&self) -> usize {
+            1
+        }
+
+        fn set_pixel(&mut self, _index: usize, color: Rgb) {
+            self.shown.push(color);
+        }
+
+        fn show(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_should_parse_patterns() {
+        let patterns: StatusPatterns = PATTERNS.parse().unwrap();
+        assert_eq!(
+            patterns.animation("navigating", &[]),
+            Some(Animation::Solid(Rgb::new(0, 255, 64)))
+        );
+        assert_eq!(
+            patterns.animation("disarmed", &[]),
+            Some(Animation::Breathe {
+                color: Rgb::BLUE,
+                period: Duration::from_secs(3)
+            })
+        );
+        assert_eq!(patterns.animation("mapping", &[]), None);
+        assert_eq!(
+            "state idle solid red".parse::<StatusPatterns>(),
+            Err("line 1: expected `=`".to_owned())
+        );
+        assert_eq!(
+            "fault x = blink mauve 1s".parse::<StatusPatterns>(),
+            Err("line 1: invalid color `mauve`".to_owned())
+        );
+    }
+
+    #[test]
+    fn it_should_show_faults_over_the_state() {
+        let patterns: StatusPatterns = PATTERNS.parse().unwrap();
+        let code = Animation::Code {
+            color: Rgb::RED,
+            count: 2,
+        };
+        assert_eq!(
+            patterns.animation("navigating", &["lidar", "imu"]),
+            Some(code)
+        );
+        assert_eq!(
+            patterns.animation("navigating", &["lidar"]),
+            Some(Animation::Blink {
+                color: Rgb::RED,
+                period: Duration::from_millis(500)
+            })
+        );
+    }
+
+    #[test]
+    fn it_should_cover_every_preset_status_by_default() {
+        let patterns = StatusPatterns::default();
+
+        for status in [
+            Status::Booting,
+            Status::Idle,
+            Status::Active,
+            Status::EmergencyStop,
+        ] {
+            assert_eq!(
+                patterns.animation(status.name(), &[]),
+                Some(status.animation())
+            );
+        }
+
+        assert_eq!(
+            patterns.animation("idle", &["camera"]),
+            Some(Status::Fault.animation())
+        );
+    }
+
+    #[test]
+    fn it_should_restart_the_animation_when_the_pattern_changes() {
+        let mut indicator = StatusIndicator::new(PATTERNS.parse().unwrap());
+        let mut strip = Strip::default();
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        indicator
+            .update(&mut strip, "navigating", &[], start)
+            .unwrap();
+        indicator
+            .update(&mut strip, "navigating", &["imu"], start + ms(300))
+            .unwrap();
+        indicator
+            .update(&mut strip, "navigating", &["imu"], start + ms(600))
+            .unwrap();
+        indicator
+            .update(&mut strip, "mapping", &[], start + ms(700))
+            .unwrap();
+        assert_eq!(
+            strip.shown,
+            [Rgb::new(0, 255, 64), Rgb::RED, Rgb::BLACK, Rgb::BLACK]
+        );
+    }
+}
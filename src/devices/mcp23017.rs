@@ -0,0 +1,427 @@
+//! Driver for the MCP23017 16-pin I2C GPIO expander.
+//!
+//! Pins 0–7 are port A and pins 8–15 are port B. The chip’s two interrupt
+//! outputs are mirrored, so either one can be wired to a native GPIO and
+//! watched for edges; [`Mcp23017::poll_interrupts`] then reports which pins
+//! changed.
+
+use std::io::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::hal::{DigitalInput, DigitalOutput, Edge, I2c};
+
+/// Default I2C address of the MCP23017, with every address pin low.
+pub const DEFAULT_ADDRESS: u8 = 0x20;
+
+/// Number of GPIO pins.
+pub const PIN_COUNT: u8 = 16;
+
+/// Register pair holding the direction of each pin.
+const IODIR: u8 = 0x00;
+
+/// Register pair enabling interrupt-on-change for each pin.
+const GPINTEN: u8 = 0x04;
+
+/// Register holding the chip configuration.
+const IOCON: u8 = 0x0A;
+
+/// Register pair enabling the pull-up of each pin.
+const GPPU: u8 = 0x0C;
+
+/// Register pair flagging the pins that caused an interrupt.
+const INTF: u8 = 0x0E;
+
+/// Register pair holding the port levels captured at the interrupt.
+const INTCAP: u8 = 0x10;
+
+/// Register pair holding the port levels.
+const GPIO: u8 = 0x12;
+
+/// Register pair holding the output latches.
+const OLAT: u8 = 0x14;
+
+/// Bit of `IOCON` that connects both interrupt outputs together.
+const IOCON_MIRROR: u8 = 0x40;
+
+/// A change in the level of an expander pin that raised an interrupt.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PinEvent {
+    /// Pin that changed.
+    pub pin: u8,
+    /// Direction of the change.
+    pub edge: Edge,
+}
+
+/// An MCP23017 on an I2C bus.
+#[derive(Debug)]
+pub struct Mcp23017<I> {
+    address: u8,
+    i2c: I,
+    interrupts: u16,
+    latches: u16,
+    outputs: u16,
+    pull_ups: u16,
+}
+
+impl<I: I2c> Mcp23017<I> {
+    /// Resets the MCP23017 at `address` with every pin an input without a
+    /// pull-up, and mirrors its interrupt outputs.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the chip cannot be configured.
+    pub fn new(i2c: I, address: u8) -> Result<Self, Error> {
+        let mut mcp23017 = Self {
+            address,
+            i2c,
+            interrupts: 0,
+            latches: 0,
+            outputs: 0,
+            pull_ups: 0,
+        };
+        mcp23017.i2c.write(address, &[IOCON, IOCON_MIRROR])?;
+        mcp23017.write_pair(OLAT, 0)?;
+        mcp23017.write_pair(IODIR, u16::MAX)?;
+        mcp23017.write_pair(GPPU, 0)?;
+        mcp23017.write_pair(GPINTEN, 0)?;
+        Ok(mcp23017)
+    }
+
+    /// Configures `pin` as an output, initially driven low.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn set_output(&mut self, pin: u8) -> Result<(), Error> {
+        let bit = bit(pin);
+        self.latches &= !bit;
+        self.write_pair(OLAT, self.latches)?;
+        self.outputs |= bit;
+        self.write_pair(IODIR, !self.outputs)
+    }
+
+    /// Configures `pin` as an input, optionally with its internal pull-up.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn set_input(&mut self, pin: u8, pull_up: bool) -> Result<(), Error> {
+        let bit = bit(pin);
+        self.pull_ups = if pull_up {
+            self.pull_ups | bit
+        } else {
+            self.pull_ups & !bit
+        };
+        self.write_pair(GPPU, self.pull_ups)?;
+        self.outputs &= !bit;
+        self.write_pair(IODIR, !self.outputs)
+    }
+
+    /// Enables or disables the interrupt raised when input `pin` changes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn set_interrupt(&mut self, pin: u8, enabled: bool) -> Result<(), Error> {
+        let bit = bit(pin);
+        self.interrupts = if enabled {
+            self.interrupts | bit
+        } else {
+            self.interrupts & !bit
+        };
+        self.write_pair(GPINTEN, self.interrupts)
+    }
+
+    /// Drives output `pin` high or low.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn write_pin(&mut self, pin: u8, high: bool) -> Result<(), Error> {
+        let bit = bit(pin);
+        self.latches = if high {
+            self.latches | bit
+        } else {
+            self.latches & !bit
+        };
+        self.write_pair(OLAT, self.latches)
+    }
+
+    /// Returns `true` if `pin` is high.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn read_pin(&mut self, pin: u8) -> Result<bool, Error> {
+        let bit = bit(pin);
+        Ok(self.read_pair(GPIO)? & bit != 0)
+    }
+
+    /// Returns the levels of every pin, with pin 0 as the least significant
+    /// bit.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    pub fn read_all(&mut self) -> Result<u16, Error> {
+        self.read_pair(GPIO)
+    }
+
+    /// Returns the pin changes that raised an interrupt, or an empty list if
+    /// none did, and rearms the interrupt.
+    ///
+    /// Call this after the interrupt output goes low.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    pub fn take_interrupts(&mut self) -> Result<Vec<PinEvent>, Error> {
+        let flags = self.read_pair(INTF)?;
+
+        if flags == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Reading the captured levels clears the interrupt.
+        let captured = self.read_pair(INTCAP)?;
+        Ok((0..PIN_COUNT)
+            .filter(|&pin| flags & bit(pin) != 0)
+            .map(|pin| PinEvent {
+                pin,
+                edge: if captured & bit(pin) != 0 {
+                    Edge::Rising
+                } else {
+                    Edge::Falling
+                },
+            })
+            .collect())
+    }
+
+    /// Reads the active-low interrupt output wired to `interrupt` and returns
+    /// the pin changes that raised it, or an empty list if it is not active.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the interrupt pin or the
+    /// registers cannot be read.
+    pub fn poll_interrupts(
+        &mut self,
+        interrupt: &mut impl DigitalInput,
+    ) -> Result<Vec<PinEvent>, Error> {
+        if interrupt.is_low()? {
+            self.take_interrupts()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Consumes the driver and splits it into its pins, in order.
+    pub fn into_pins(self) -> Vec<Pin<I>> {
+        let mcp23017 = Arc::new(Mutex::new(self));
+        (0..PIN_COUNT)
+            .map(|index| Pin {
+                index,
+                mcp23017: Arc::clone(&mcp23017),
+            })
+            .collect()
+    }
+
+    /// Consumes the driver and returns its bus.
+    pub fn into_inner(self) -> I {
+        self.i2c
+    }
+
+    fn read_pair(&mut self, register: u8) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        self.i2c
+            .write_read(self.address, &[register], &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    fn write_pair(&mut self, register: u8, value: u16) -> Result<(), Error> {
+        let [a, b] = value.to_le_bytes();
+        self.i2c.write(self.address, &[register, a, b])
+    }
+}
+
+/// A single pin of an MCP23017 shared with its other pins.
+#[derive(Debug)]
+pub struct Pin<I> {
+    index: u8,
+    mcp23017: Arc<Mutex<Mcp23017<I>>>,
+}
+
+impl<I: I2c> Pin<I> {
+    /// Returns the index of the pin on the chip.
+    #[must_use]
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Configures the pin as an output, initially driven low.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    pub fn set_output(&mut self) -> Result<(), Error> {
+        self.lock().set_output(self.index)
+    }
+
+    /// Configures the pin as an input, optionally with its internal pull-up.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    pub fn set_input(&mut self, pull_up: bool) -> Result<(), Error> {
+        self.lock().set_input(self.index, pull_up)
+    }
+
+    /// Enables or disables the interrupt raised when the pin changes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    pub fn set_interrupt(&mut self, enabled: bool) -> Result<(), Error> {
+        self.lock().set_interrupt(self.index, enabled)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Mcp23017<I>> {
+        self.mcp23017
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl<I: I2c> DigitalOutput for Pin<I> {
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.lock().write_pin(self.index, true)
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.lock().write_pin(self.index, false)
+    }
+}
+
+impl<I: I2c> DigitalInput for Pin<I> {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        self.lock().read_pin(self.index)
+    }
+}
+
+/// Returns the bit of `pin` in a register pair.
+fn bit(pin: u8) -> u16 {
+    assert!(pin < PIN_COUNT, "pin should be less than {PIN_COUNT}");
+    1 << pin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockI2c;
+
+    fn mcp23017() -> Mcp23017<MockI2c> {
+        Mcp23017::new(MockI2c::new(DEFAULT_ADDRESS), DEFAULT_ADDRESS).unwrap()
+    }
+
+    fn pair(i2c: &MockI2c, register: u8) -> u16 {
+        let register = usize::from(register);
+        u16::from_le_bytes([i2c.registers[register], i2c.registers[register + 1]])
+    }
+
+    #[test]
+    fn it_should_start_with_every_pin_an_input_and_mirrored_interrupts() {
+        let mcp23017 = mcp23017();
+        assert_eq!(pair(&mcp23017.i2c, IODIR), u16::MAX);
+        assert_eq!(mcp23017.i2c.registers[usize::from(IOCON)], IOCON_MIRROR);
+    }
+
+    #[test]
+    fn it_should_drive_an_output_pin_on_port_b() {
+        let mut pins = mcp23017().into_pins();
+        pins[9].set_output().unwrap();
+        pins[9].set_high().unwrap();
+        let mcp23017 = pins[9].lock();
+        assert_eq!(pair(&mcp23017.i2c, IODIR), !(1 << 9));
+        assert_eq!(pair(&mcp23017.i2c, OLAT), 1 << 9);
+    }
+
+    #[test]
+    fn it_should_keep_other_latches_when_driving_a_pin() {
+        let mut mcp23017 = mcp23017();
+        mcp23017.set_output(0).unwrap();
+        mcp23017.set_output(1).unwrap();
+        mcp23017.write_pin(0, true).unwrap();
+        mcp23017.write_pin(1, true).unwrap();
+        mcp23017.write_pin(0, false).unwrap();
+        assert_eq!(pair(&mcp23017.i2c, OLAT), 0b10);
+    }
+
+    #[test]
+    fn it_should_read_an_input_pin_with_a_pull_up() {
+        let mut pins = mcp23017().into_pins();
+        pins[3].set_input(true).unwrap();
+        pins[3].lock().i2c.registers[usize::from(GPIO)] = 1 << 3;
+        assert!(pins[3].is_high().unwrap());
+        assert!(pins[4].is_low().unwrap());
+        assert_eq!(pair(&pins[3].lock().i2c, GPPU), 1 << 3);
+    }
+
+    #[test]
+    fn it_should_report_the_edges_that_raised_an_interrupt() {
+        let mut mcp23017 = mcp23017();
+        mcp23017.set_interrupt(2, true).unwrap();
+        mcp23017.set_interrupt(12, true).unwrap();
+        assert_eq!(pair(&mcp23017.i2c, GPINTEN), (1 << 2) | (1 << 12));
+        mcp23017.i2c.registers[usize::from(INTF)] = 1 << 2;
+        mcp23017.i2c.registers[usize::from(INTF) + 1] = 1 << 4;
+        mcp23017.i2c.registers[usize::from(INTCAP) + 1] = 1 << 4;
+        assert_eq!(
+            mcp23017.take_interrupts().unwrap(),
+            [
+                PinEvent {
+                    pin: 2,
+                    edge: Edge::Falling
+                },
+                PinEvent {
+                    pin: 12,
+                    edge: Edge::Rising
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_not_read_the_chip_while_the_interrupt_output_is_inactive() {
+        struct Inactive;
+
+        impl DigitalInput for Inactive {
+            fn is_high(&mut self) -> Result<bool, Error> {
+                Ok(true)
+            }
+        }
+
+        let mut mcp23017 = mcp23017();
+        mcp23017.i2c.registers[usize::from(INTF)] = 1;
+        assert!(mcp23017.poll_interrupts(&mut Inactive).unwrap().is_empty());
+    }
+}
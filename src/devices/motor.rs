@@ -0,0 +1,113 @@
+//! Adapters for motors driven through the [`Motor`] trait.
+
+use std::io::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::hal::Motor;
+
+/// Latest measured supply voltage, shared between whatever monitors the
+/// battery and the motors that compensate for it.
+#[derive(Clone, Debug, Default)]
+pub struct SupplyVoltage {
+    // The bits of an `f64`, where zero means no measurement yet.
+    volts: Arc<AtomicU64>,
+}
+
+impl SupplyVoltage {
+    /// Creates a new `SupplyVoltage` with no measurement.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the latest measurement, in volts.
+    #[must_use]
+    pub fn get(&self) -> Option<f64> {
+        let volts = f64::from_bits(self.volts.load(Ordering::Relaxed));
+        (volts > 0.0).then_some(volts)
+    }
+
+    /// Records a measurement, in volts.
+    pub fn set(&self, volts: f64) {
+        self.volts
+            .store(volts.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// A motor whose commands are scaled by the supply voltage, so that the
+/// same command gives the same speed as the battery discharges.
+///
+/// A command of 1 is taken to mean full power at the nominal voltage; once
+/// the supply sags, the scaled command saturates below that.
+#[derive(Debug)]
+pub struct VoltageCompensated<M> {
+    motor: M,
+    nominal: f64,
+    supply: SupplyVoltage,
+}
+
+impl<M: Motor> VoltageCompensated<M> {
+    /// Creates a new `VoltageCompensated` motor whose commands are relative
+    /// to `nominal` volts, scaled by the measurements in `supply`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `nominal` is not positive.
+    pub fn new(motor: M, nominal: f64, supply: SupplyVoltage) -> Self {
+        assert!(nominal > 0.0, "nominal should be positive");
+        Self {
+            motor,
+            nominal,
+            supply,
+        }
+    }
+
+    /// Returns the underlying motor.
+    pub fn into_inner(self) -> M {
+        self.motor
+    }
+}
+
+impl<M: Motor> Motor for VoltageCompensated<M> {
+    /// Drives the motor at `speed`, scaled by the nominal voltage over the
+    /// latest measurement, or unscaled before the first measurement.
+    fn set_speed(&mut self, speed: f64) -> Result<(), Error> {
+        let scale = self.supply.get().map_or(1.0, |volts| self.nominal / volts);
+        self.motor.set_speed((speed * scale).clamp(-1.0, 1.0))
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        self.motor.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockMotor;
+
+    #[test]
+    fn it_should_pass_commands_through_until_measured() {
+        let mut motor = VoltageCompensated::new(MockMotor::default(), 12.0, SupplyVoltage::new());
+        motor.set_speed(0.5).unwrap();
+        assert_eq!(motor.into_inner().speed, 0.5);
+    }
+
+    #[test]
+    fn it_should_raise_the_duty_as_the_pack_sags() {
+        let supply = SupplyVoltage::new();
+        let mut motor = VoltageCompensated::new(MockMotor::default(), 12.6, supply.clone());
+        supply.set(10.5);
+        motor.set_speed(-0.5).unwrap();
+        assert!((motor.motor.speed + 0.6).abs() < 1e-12);
+        motor.set_speed(1.0).unwrap();
+        assert_eq!(motor.motor.speed, 1.0);
+    }
+
+    #[test]
+    fn it_should_ignore_nonsensical_measurements() {
+        let supply = SupplyVoltage::new();
+        supply.set(-3.0);
+        assert_eq!(supply.get(), None);
+    }
+}
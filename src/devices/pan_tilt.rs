@@ -0,0 +1,183 @@
+//! Pan/tilt camera mounts driven by servos.
+//!
+//! In [`Framing::Stabilized`] the tilt is held relative to the horizon
+//! rather than the chassis: the IMU’s pitch is fed back so the camera keeps
+//! looking at the same spot as the robot noses down under acceleration or
+//! climbs a ramp. A mount with a third servo to roll the camera also keeps
+//! the horizon level when the chassis leans sideways.
+
+use std::io::Error;
+
+use super::servo::Servo;
+use crate::hal::PwmOutput;
+
+/// What the angles the camera is pointed at are measured against.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Framing {
+    /// Angles are relative to the chassis, which the camera moves with.
+    #[default]
+    Chassis,
+    /// Tilt and roll are relative to the horizon, using the chassis
+    /// attitude from the IMU.
+    Stabilized,
+}
+
+/// A camera on pan and tilt servos, and optionally a roll servo.
+#[derive(Debug)]
+pub struct PanTilt<P> {
+    framing: Framing,
+    pan: Servo<P>,
+    pitch: f64,
+    roll: f64,
+    roll_servo: Option<Servo<P>>,
+    target: (f64, f64),
+    tilt: Servo<P>,
+}
+
+impl<P: PwmOutput> PanTilt<P> {
+    /// Creates a new `PanTilt` on the `pan` and `tilt` servos, with positive
+    /// angles turning the camera left and up.
+    pub fn new(pan: Servo<P>, tilt: Servo<P>) -> Self {
+        Self {
+            framing: Framing::default(),
+            pan,
+            pitch: 0.0,
+            roll: 0.0,
+            roll_servo: None,
+            target: (0.0, 0.0),
+            tilt,
+        }
+    }
+
+    /// Adds a servo that rolls the camera, with positive angles turning it
+    /// clockwise as seen from behind.
+    #[must_use]
+    pub fn with_roll(mut self, roll: Servo<P>) -> Self {
+        self.roll_servo = Some(roll);
+        self
+    }
+
+    /// Sets the framing.
+    #[must_use]
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Returns the framing.
+    #[must_use]
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Changes the framing and moves the servos to suit.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a servo cannot be driven.
+    pub fn set_framing(&mut self, framing: Framing) -> Result<(), Error> {
+        self.framing = framing;
+        self.drive()
+    }
+
+    /// Points the camera at `pan` and `tilt`, in radians.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a servo cannot be driven.
+    pub fn point(&mut self, pan: f64, tilt: f64) -> Result<(), Error> {
+        self.target = (pan, tilt);
+        self.drive()
+    }
+
+    /// Updates the chassis attitude from the IMU, in radians, with roll
+    /// positive leaning right and pitch positive leaning forward, and
+    /// corrects the servos if stabilized.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a servo cannot be driven.
+    pub fn update(&mut self, roll: f64, pitch: f64) -> Result<(), Error> {
+        self.roll = roll;
+        self.pitch = pitch;
+
+        if self.framing == Framing::Stabilized {
+            self.drive()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the pan and tilt servos, and the roll servo if there is one.
+    pub fn into_inner(self) -> (Servo<P>, Servo<P>, Option<Servo<P>>) {
+        (self.pan, self.tilt, self.roll_servo)
+    }
+
+    fn drive(&mut self) -> Result<(), Error> {
+        let (pan, tilt) = self.target;
+        let (roll, pitch) = match self.framing {
+            Framing::Chassis => (0.0, 0.0),
+            Framing::Stabilized => (self.roll, self.pitch),
+        };
+        self.pan.set_angle(pan)?;
+        // Leaning forward points the camera down, so tilt up to make up
+        // for it.
+        self.tilt.set_angle(tilt + pitch)?;
+
+        if let Some(servo) = &mut self.roll_servo {
+            servo.set_angle(-roll)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockPwm;
+
+    fn pan_tilt() -> PanTilt<MockPwm> {
+        PanTilt::new(
+            Servo::new(MockPwm::default()),
+            Servo::new(MockPwm::default()),
+        )
+        .with_roll(Servo::new(MockPwm::default()))
+    }
+
+    fn angles(pan_tilt: PanTilt<MockPwm>) -> [Option<f64>; 3] {
+        let (pan, tilt, roll) = pan_tilt.into_inner();
+        [
+            pan.angle(),
+            tilt.angle(),
+            roll.and_then(|roll| roll.angle()),
+        ]
+    }
+
+    #[test]
+    fn it_should_move_with_the_chassis_by_default() {
+        let mut pan_tilt = pan_tilt();
+        pan_tilt.point(0.2, -0.1).unwrap();
+        pan_tilt.update(0.1, 0.3).unwrap();
+        assert_eq!(angles(pan_tilt), [Some(0.2), Some(-0.1), Some(0.0)]);
+    }
+
+    #[test]
+    fn it_should_hold_the_horizon_when_stabilized() {
+        let mut pan_tilt = pan_tilt().with_framing(Framing::Stabilized);
+        pan_tilt.point(0.2, -0.1).unwrap();
+        pan_tilt.update(0.1, 0.3).unwrap();
+        let [pan, tilt, roll] = angles(pan_tilt);
+        assert_eq!(pan, Some(0.2));
+        assert!((tilt.unwrap() - 0.2).abs() < 1e-9);
+        assert_eq!(roll, Some(-0.1));
+    }
+
+    #[test]
+    fn it_should_recentre_when_stabilization_is_turned_off() {
+        let mut pan_tilt = pan_tilt().with_framing(Framing::Stabilized);
+        pan_tilt.update(0.1, 0.3).unwrap();
+        pan_tilt.set_framing(Framing::Chassis).unwrap();
+        assert_eq!(angles(pan_tilt), [Some(0.0), Some(0.0), Some(0.0)]);
+    }
+}
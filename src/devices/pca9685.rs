@@ -0,0 +1,335 @@
+//! Driver for the PCA9685 16-channel, 12-bit PWM expander.
+//!
+//! Every channel shares the chip’s single frequency. Channels can be split off
+//! with [`Pca9685::into_channels`] so that each one can be handed to a driver
+//! that only needs a [`PwmOutput`].
+
+use std::io::{Error, ErrorKind};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::hal::{I2c, PwmOutput};
+#[cfg(unix)]
+use crate::unix::flock::DeviceLock;
+
+/// Default I2C address of the PCA9685, with every address pin low.
+pub const DEFAULT_ADDRESS: u8 = 0x40;
+
+/// Number of output channels.
+pub const CHANNEL_COUNT: u8 = 16;
+
+/// Frequency of the internal oscillator, in hertz.
+const OSCILLATOR_FREQUENCY: f64 = 25_000_000.0;
+
+/// Number of steps in each period.
+const STEPS: u16 = 4096;
+
+/// Register holding the first mode byte.
+const MODE1: u8 = 0x00;
+
+/// Register holding the second mode byte.
+const MODE2: u8 = 0x01;
+
+/// Register holding the low on byte of the first channel.
+const LED0_ON_L: u8 = 0x06;
+
+/// Register holding the prescaler for the output frequency.
+const PRE_SCALE: u8 = 0xFE;
+
+/// Bit of `MODE1` that enables register auto-increment.
+const MODE1_AI: u8 = 0x20;
+
+/// Bit of `MODE1` that stops the oscillator.
+const MODE1_SLEEP: u8 = 0x10;
+
+/// Bit of `MODE1` that restarts channels after sleep.
+const MODE1_RESTART: u8 = 0x80;
+
+/// Bit of `MODE2` that configures the outputs as totem pole.
+const MODE2_OUTDRV: u8 = 0x04;
+
+/// Bit of the high on or off byte that forces a channel fully on or off.
+const FULL: u8 = 0x10;
+
+/// A PCA9685 on an I2C bus.
+#[derive(Debug)]
+pub struct Pca9685<I> {
+    address: u8,
+    frequency: f64,
+    i2c: I,
+    /// Claim on the chip, if it was opened with [`Pca9685::claim`].
+    #[cfg(unix)]
+    _device_lock: Option<DeviceLock>,
+}
+
+impl<I: I2c> Pca9685<I> {
+    /// Resets the PCA9685 at `address` with every channel off and sets its
+    /// output frequency.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the chip cannot be configured, or
+    /// with [`ErrorKind::InvalidInput`] if `frequency` is outside the range
+    /// the chip supports.
+    pub fn new(i2c: I, address: u8, frequency: f64) -> Result<Self, Error> {
+        let mut pca9685 = Self {
+            address,
+            frequency,
+            i2c,
+            #[cfg(unix)]
+            _device_lock: None,
+        };
+        pca9685.i2c.write(address, &[MODE2, MODE2_OUTDRV])?;
+        pca9685.set_all_off()?;
+        pca9685.set_frequency(frequency)?;
+        Ok(pca9685)
+    }
+
+    /// Claims the PCA9685 at `address` on the I2C bus at `bus`, such as
+    /// `/dev/i2c-1`, for this process, then resets it as [`Pca9685::new`]
+    /// does.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with [`ErrorKind::ResourceBusy`]
+    /// naming the owning process if another driver has claimed the chip, or
+    /// any error [`Pca9685::new`] would.
+    #[cfg(unix)]
+    pub fn claim(
+        i2c: I,
+        bus: impl AsRef<Path>,
+        address: u8,
+        frequency: f64,
+    ) -> Result<Self, Error> {
+        let lock = DeviceLock::acquire(bus.as_ref().join(format!("{address:#04x}")))?;
+        let mut pca9685 = Self::new(i2c, address, frequency)?;
+        pca9685._device_lock = Some(lock);
+        Ok(pca9685)
+    }
+
+    /// Returns the output frequency, in hertz, as realized by the prescaler.
+    #[must_use]
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// Sets the output frequency of every channel.
+    ///
+    /// The prescaler limits the frequency to 24–1526 Hz, and rounds it to the
+    /// nearest step; servos want 50 Hz, while LEDs look steadier at 1 kHz.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the chip cannot be configured, or
+    /// with [`ErrorKind::InvalidInput`] if `frequency` is outside the range
+    /// the chip supports.
+    pub fn set_frequency(&mut self, frequency: f64) -> Result<(), Error> {
+        let prescale = (OSCILLATOR_FREQUENCY / (f64::from(STEPS) * frequency)).round() - 1.0;
+
+        if !(3.0..=255.0).contains(&prescale) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("frequency {frequency} Hz out of range"),
+            ));
+        }
+
+        let mode = self.read(MODE1)? & !MODE1_RESTART;
+        self.write(&[MODE1, mode | MODE1_SLEEP])?;
+        self.write(&[PRE_SCALE, prescale as u8])?;
+        self.write(&[MODE1, (mode & !MODE1_SLEEP) | MODE1_AI])?;
+        thread::sleep(Duration::from_micros(500));
+        self.write(&[MODE1, (mode & !MODE1_SLEEP) | MODE1_AI | MODE1_RESTART])?;
+        self.frequency = OSCILLATOR_FREQUENCY / (f64::from(STEPS) * (prescale + 1.0));
+        Ok(())
+    }
+
+    /// Sets the step at which `channel` turns on and the step at which it
+    /// turns off within each period.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channel` is not less than [`CHANNEL_COUNT`], or if `on` or
+    /// `off` is not less than 4096.
+    pub fn set_steps(&mut self, channel: u8, on: u16, off: u16) -> Result<(), Error> {
+        assert!(
+            on < STEPS && off < STEPS,
+            "steps should be less than {STEPS}"
+        );
+        let [on_high, on_low] = on.to_be_bytes();
+        let [off_high, off_low] = off.to_be_bytes();
+        self.write_channel(channel, [on_low, on_high, off_low, off_high])
+    }
+
+    /// Sets the fraction of each period for which `channel` is high, from 0
+    /// to 1.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channel` is not less than [`CHANNEL_COUNT`].
+    pub fn set_duty_cycle(&mut self, channel: u8, duty_cycle: f64) -> Result<(), Error> {
+        let steps = (duty_cycle.clamp(0.0, 1.0) * f64::from(STEPS)).round() as u16;
+
+        match steps {
+            0 => self.write_channel(channel, [0, 0, 0, FULL]),
+            STEPS => self.write_channel(channel, [0, FULL, 0, 0]),
+            steps => self.set_steps(channel, 0, steps),
+        }
+    }
+
+    /// Turns every channel off.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    pub fn set_all_off(&mut self) -> Result<(), Error> {
+        for channel in 0..CHANNEL_COUNT {
+            self.set_duty_cycle(channel, 0.0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Consumes the driver and splits it into its channels, in order.
+    pub fn into_channels(self) -> Vec<Channel<I>> {
+        let pca9685 = Arc::new(Mutex::new(self));
+        (0..CHANNEL_COUNT)
+            .map(|index| Channel {
+                index,
+                pca9685: Arc::clone(&pca9685),
+            })
+            .collect()
+    }
+
+    /// Consumes the driver and returns its bus.
+    pub fn into_inner(self) -> I {
+        self.i2c
+    }
+
+    fn write_channel(&mut self, channel: u8, bytes: [u8; 4]) -> Result<(), Error> {
+        assert!(
+            channel < CHANNEL_COUNT,
+            "channel should be less than {CHANNEL_COUNT}"
+        );
+        let [on_low, on_high, off_low, off_high] = bytes;
+        self.write(&[LED0_ON_L + 4 * channel, on_low, on_high, off_low, off_high])
+    }
+
+    fn read(&mut self, register: u8) -> Result<u8, Error> {
+        let mut buffer = [0];
+        self.i2c
+            .write_read(self.address, &[register], &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.i2c.write(self.address, bytes)
+    }
+}
+
+/// A single channel of a PCA9685 shared with its other channels.
+#[derive(Debug)]
+pub struct Channel<I> {
+    index: u8,
+    pca9685: Arc<Mutex<Pca9685<I>>>,
+}
+
+impl<I> Channel<I> {
+    /// Returns the index of the channel on the chip.
+    #[must_use]
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pca9685<I>> {
+        self.pca9685
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl<I: I2c> PwmOutput for Channel<I> {
+    fn frequency(&self) -> f64 {
+        self.lock().frequency()
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: f64) -> Result<(), Error> {
+        self.lock().set_duty_cycle(self.index, duty_cycle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockI2c;
+
+    fn pca9685(frequency: f64) -> Pca9685<MockI2c> {
+        Pca9685::new(MockI2c::new(DEFAULT_ADDRESS), DEFAULT_ADDRESS, frequency).unwrap()
+    }
+
+    fn channel_registers(i2c: &MockI2c, channel: u8) -> [u8; 4] {
+        let start = usize::from(LED0_ON_L + 4 * channel);
+        i2c.registers[start..start + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn it_should_set_the_prescaler_for_the_frequency() {
+        let pca9685 = pca9685(50.0);
+        assert_eq!(pca9685.i2c.registers[usize::from(PRE_SCALE)], 121);
+        assert!((pca9685.frequency() - 50.0).abs() < 0.5);
+        assert_eq!(pca9685.i2c.registers[usize::from(MODE1)] & MODE1_SLEEP, 0);
+    }
+
+    #[test]
+    fn it_should_reject_a_frequency_out_of_range() {
+        let result = Pca9685::new(MockI2c::new(DEFAULT_ADDRESS), DEFAULT_ADDRESS, 2000.0);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn it_should_start_with_every_channel_fully_off() {
+        let pca9685 = pca9685(1000.0);
+
+        for channel in 0..CHANNEL_COUNT {
+            assert_eq!(channel_registers(&pca9685.i2c, channel), [0, 0, 0, FULL]);
+        }
+    }
+
+    #[test]
+    fn it_should_write_the_steps_of_a_duty_cycle() {
+        let mut pca9685 = pca9685(1000.0);
+        pca9685.set_duty_cycle(3, 0.25).unwrap();
+        assert_eq!(channel_registers(&pca9685.i2c, 3), [0, 0, 0x00, 0x04]);
+        pca9685.set_duty_cycle(3, 1.0).unwrap();
+        assert_eq!(channel_registers(&pca9685.i2c, 3), [0, FULL, 0, 0]);
+    }
+
+    #[test]
+    fn it_should_drive_a_servo_pulse_through_a_channel() {
+        let mut channels = pca9685(50.0).into_channels();
+        let frequency = channels[15].frequency();
+        channels[15]
+            .set_pulse_width(Duration::from_micros(1500))
+            .unwrap();
+        let pca9685 = channels[15].lock();
+        let [_, _, low, high] = channel_registers(&pca9685.i2c, 15);
+        let expected = (0.0015 * frequency * 4096.0).round() as u16;
+        assert_eq!(u16::from_be_bytes([high, low]), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "channel should be less than 16")]
+    fn it_should_panic_for_a_channel_outside_the_chip() {
+        let _ = pca9685(50.0).set_duty_cycle(16, 0.5);
+    }
+}
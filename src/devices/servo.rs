@@ -0,0 +1,151 @@
+//! Hobby servos positioned by pulse width.
+
+use std::f64::consts::FRAC_PI_4;
+use std::io::Error;
+use std::time::Duration;
+
+use crate::hal::PwmOutput;
+
+/// A hobby servo on a 50 Hz PWM output.
+///
+/// By default pulses from 1000 µs to 2000 µs sweep from -45° to 45°, which
+/// every servo accepts; most travel further, and their full range can be
+/// configured once measured.
+#[derive(Debug)]
+pub struct Servo<P> {
+    angle: Option<f64>,
+    max_angle: f64,
+    max_pulse: Duration,
+    min_angle: f64,
+    min_pulse: Duration,
+    pwm: P,
+}
+
+impl<P: PwmOutput> Servo<P> {
+    /// Creates a new `Servo` on `pwm`, leaving it unpowered until the first
+    /// angle is set.
+    pub fn new(pwm: P) -> Self {
+        Self {
+            angle: None,
+            max_angle: FRAC_PI_4,
+            max_pulse: Duration::from_micros(2000),
+            min_angle: -FRAC_PI_4,
+            min_pulse: Duration::from_micros(1000),
+            pwm,
+        }
+    }
+
+    /// Sets the pulse widths at the ends of the servo’s travel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` is not shorter than `max`.
+    #[must_use]
+    pub fn with_pulse_range(mut self, min: Duration, max: Duration) -> Self {
+        assert!(min < max, "min should be shorter than max");
+        self.min_pulse = min;
+        self.max_pulse = max;
+        self
+    }
+
+    /// Sets the angles, in radians, reached at the shortest and longest
+    /// pulses. Swapping them reverses a servo mounted the other way round.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the angles are equal.
+    #[must_use]
+    pub fn with_angle_range(mut self, min: f64, max: f64) -> Self {
+        assert!(min != max, "min and max should differ");
+        self.min_angle = min;
+        self.max_angle = max;
+        self
+    }
+
+    /// Returns the last angle set, in radians.
+    #[must_use]
+    pub fn angle(&self) -> Option<f64> {
+        self.angle
+    }
+
+    /// Moves the servo to `angle`, in radians, limited to its range, and
+    /// returns the angle commanded.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    pub fn set_angle(&mut self, angle: f64) -> Result<f64, Error> {
+        let (low, high) = if self.min_angle < self.max_angle {
+            (self.min_angle, self.max_angle)
+        } else {
+            (self.max_angle, self.min_angle)
+        };
+        let angle = angle.clamp(low, high);
+        let fraction = (angle - self.min_angle) / (self.max_angle - self.min_angle);
+        let width = self.min_pulse + (self.max_pulse - self.min_pulse).mul_f64(fraction);
+        self.pwm.set_pulse_width(width)?;
+        self.angle = Some(angle);
+        Ok(angle)
+    }
+
+    /// Stops sending pulses, so the servo no longer holds its position.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    pub fn release(&mut self) -> Result<(), Error> {
+        self.pwm.set_duty_cycle(0.0)?;
+        self.angle = None;
+        Ok(())
+    }
+
+    /// Returns the underlying output.
+    pub fn into_inner(self) -> P {
+        self.pwm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::hal::MockPwm;
+
+    fn pulse_micros(servo: Servo<MockPwm>) -> f64 {
+        servo.into_inner().duty_cycle / 50.0 * 1e6
+    }
+
+    #[test]
+    fn it_should_centre_at_zero() {
+        let mut servo = Servo::new(MockPwm::default());
+        assert_eq!(servo.angle(), None);
+        servo.set_angle(0.0).unwrap();
+        assert!((pulse_micros(servo) - 1500.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_should_clamp_to_the_range() {
+        let mut servo = Servo::new(MockPwm::default());
+        assert_eq!(servo.set_angle(FRAC_PI_2).unwrap(), FRAC_PI_4);
+        assert!((pulse_micros(servo) - 2000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_should_map_a_reversed_range() {
+        let mut servo = Servo::new(MockPwm::default())
+            .with_pulse_range(Duration::from_micros(500), Duration::from_micros(2500))
+            .with_angle_range(FRAC_PI_2, -FRAC_PI_2);
+        servo.set_angle(FRAC_PI_4).unwrap();
+        assert!((pulse_micros(servo) - 1000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_should_stop_pulsing_when_released() {
+        let mut servo = Servo::new(MockPwm::default());
+        servo.set_angle(0.1).unwrap();
+        servo.release().unwrap();
+        assert_eq!(servo.angle(), None);
+        assert_eq!(servo.into_inner().duty_cycle, 0.0);
+    }
+}
@@ -0,0 +1,504 @@
+//! Driver for VESC brushless motor controllers.
+//!
+//! A VESC is commanded by duty cycle, motor current, or electrical RPM and
+//! reports back its speed, currents, and temperatures, over either its UART
+//! or a CAN bus shared with other controllers. [`Vesc`] speaks to one
+//! controller through either [`Transport`] and implements [`Motor`] and
+//! [`Encoder`], so brushless wheels slot into the drive layer in place of
+//! brushed ones.
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+use crate::hal::{Encoder, Motor};
+use crate::json::{ToJson, Value};
+#[cfg(target_os = "linux")]
+use crate::unix::can::{CanSocket, Frame, Id};
+
+/// Byte starting a UART packet of up to 255 bytes.
+const START: u8 = 0x02;
+
+/// Byte ending a UART packet.
+const END: u8 = 0x03;
+
+/// UART command requesting telemetry.
+const COMM_GET_VALUES: u8 = 4;
+
+/// UART command setting the duty cycle.
+const COMM_SET_DUTY: u8 = 5;
+
+/// UART command setting the motor current.
+const COMM_SET_CURRENT: u8 = 6;
+
+/// UART command setting the electrical RPM.
+const COMM_SET_RPM: u8 = 8;
+
+/// CAN command setting the duty cycle.
+const CAN_PACKET_SET_DUTY: u32 = 0;
+
+/// CAN command setting the motor current.
+const CAN_PACKET_SET_CURRENT: u32 = 1;
+
+/// CAN command setting the electrical RPM.
+const CAN_PACKET_SET_RPM: u32 = 3;
+
+/// CAN status with the electrical RPM, motor current, and duty cycle.
+const CAN_PACKET_STATUS: u32 = 9;
+
+/// CAN status with the temperatures and input current.
+const CAN_PACKET_STATUS_4: u32 = 16;
+
+/// CAN status with the tachometer and input voltage.
+const CAN_PACKET_STATUS_5: u32 = 27;
+
+/// A setpoint for a VESC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Duty cycle, from -1 to 1.
+    Duty(f64),
+    /// Motor current, in amperes, negative to brake or reverse.
+    Current(f64),
+    /// Electrical RPM, which is the mechanical RPM times the motor’s pole
+    /// pairs.
+    Rpm(f64),
+}
+
+/// Readings reported by a VESC.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Telemetry {
+    /// Electrical RPM.
+    pub erpm: f64,
+    /// Motor current, in amperes.
+    pub current: f64,
+    /// Input current drawn from the battery, in amperes.
+    pub input_current: f64,
+    /// Duty cycle, from -1 to 1.
+    pub duty: f64,
+    /// Input voltage, in volts.
+    pub input_voltage: f64,
+    /// Temperature of the MOSFETs, in degrees Celsius.
+    pub fet_temperature: f64,
+    /// Temperature of the motor, in degrees Celsius, if it has a sensor.
+    pub motor_temperature: f64,
+    /// Commutation steps counted, six per electrical revolution.
+    pub tachometer: i64,
+}
+
+impl ToJson for Telemetry {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("erpm", self.erpm)
+            .with("current", self.current)
+            .with("input_current", self.input_current)
+            .with("duty", self.duty)
+            .with("input_voltage", self.input_voltage)
+            .with("fet_temperature", self.fet_temperature)
+            .with("motor_temperature", self.motor_temperature)
+            .with("tachometer", self.tachometer)
+    }
+}
+
+/// A link over which a VESC is commanded and read.
+pub trait Transport {
+    /// Sends `command` to the controller.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command cannot be sent.
+    fn send(&mut self, command: Command) -> Result<(), Error>;
+
+    /// Returns the latest telemetry from the controller.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the telemetry cannot be read.
+    fn telemetry(&mut self) -> Result<Telemetry, Error>;
+}
+
+/// A VESC on a serial port, which answers telemetry requests directly.
+#[derive(Debug)]
+pub struct Uart<S> {
+    stream: S,
+}
+
+impl<S: Read + Write> Uart<S> {
+    /// Creates a new `Uart` transport over `stream`, at 115200 baud by
+    /// default.
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// Returns the stream, consuming the transport.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn write_packet(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let mut packet = Vec::with_capacity(payload.len() + 5);
+        packet.push(START);
+        packet.push(payload.len() as u8);
+        packet.extend_from_slice(payload);
+        packet.extend_from_slice(&crc16(payload).to_be_bytes());
+        packet.push(END);
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+
+    fn read_packet(&mut self) -> Result<Vec<u8>, Error> {
+        let mut byte = [0];
+
+        // Skip anything left over from before a previous error.
+        while byte[0] != START {
+            self.stream.read_exact(&mut byte)?;
+        }
+
+        self.stream.read_exact(&mut byte)?;
+        let mut payload = vec![0; usize::from(byte[0])];
+        self.stream.read_exact(&mut payload)?;
+        let mut trailer = [0; 3];
+        self.stream.read_exact(&mut trailer)?;
+
+        if trailer[2] != END || u16::from_be_bytes([trailer[0], trailer[1]]) != crc16(&payload) {
+            return Err(Error::new(ErrorKind::InvalidData, "corrupt VESC packet"));
+        }
+
+        Ok(payload)
+    }
+}
+
+impl<S: Read + Write> Transport for Uart<S> {
+    fn send(&mut self, command: Command) -> Result<(), Error> {
+        let (id, value) = match command {
+            Command::Duty(duty) => (COMM_SET_DUTY, duty.clamp(-1.0, 1.0) * 100_000.0),
+            Command::Current(current) => (COMM_SET_CURRENT, current * 1000.0),
+            Command::Rpm(erpm) => (COMM_SET_RPM, erpm),
+        };
+        let mut payload = vec![id];
+        payload.extend_from_slice(&(value.round() as i32).to_be_bytes());
+        self.write_packet(&payload)
+    }
+
+    fn telemetry(&mut self) -> Result<Telemetry, Error> {
+        self.write_packet(&[COMM_GET_VALUES])?;
+
+        loop {
+            let payload = self.read_packet()?;
+
+            if payload.first() == Some(&COMM_GET_VALUES) {
+                return parse_values(&payload[1..]);
+            }
+        }
+    }
+}
+
+/// A VESC on a CAN bus, which broadcasts its telemetry as status frames.
+///
+/// Status broadcasting must be enabled in the controller’s app settings.
+/// Whatever receives frames from the bus passes them to
+/// [`Can::handle`] so the latest telemetry is on hand.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct Can {
+    controller: u8,
+    socket: std::sync::Arc<CanSocket>,
+    telemetry: Telemetry,
+}
+
+#[cfg(target_os = "linux")]
+impl Can {
+    /// Creates a new `Can` transport to the controller with ID `controller`
+    /// on `socket`.
+    pub fn new(socket: std::sync::Arc<CanSocket>, controller: u8) -> Self {
+        Self {
+            controller,
+            socket,
+            telemetry: Telemetry::default(),
+        }
+    }
+
+    /// Updates the telemetry from `frame` and returns `true` if it is a
+    /// status frame from this controller.
+    pub fn handle(&mut self, frame: &Frame) -> bool {
+        apply_status(&mut self.telemetry, self.controller, frame)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Transport for Can {
+    fn send(&mut self, command: Command) -> Result<(), Error> {
+        let (packet, value) = match command {
+            Command::Duty(duty) => (CAN_PACKET_SET_DUTY, duty.clamp(-1.0, 1.0) * 100_000.0),
+            Command::Current(current) => (CAN_PACKET_SET_CURRENT, current * 1000.0),
+            Command::Rpm(erpm) => (CAN_PACKET_SET_RPM, erpm),
+        };
+        let id = Id::Extended(packet << 8 | u32::from(self.controller));
+        let frame = Frame::new(id, &(value.round() as i32).to_be_bytes())
+            .expect("four bytes should fit a frame");
+        self.socket.send(&frame)
+    }
+
+    fn telemetry(&mut self) -> Result<Telemetry, Error> {
+        Ok(self.telemetry)
+    }
+}
+
+/// A VESC motor controller.
+#[derive(Debug)]
+pub struct Vesc<T> {
+    transport: T,
+}
+
+impl<T: Transport> Vesc<T> {
+    /// Creates a new `Vesc` reached through `transport`.
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    /// Drives the motor at `duty`, from -1 to 1.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command cannot be sent.
+    pub fn set_duty(&mut self, duty: f64) -> Result<(), Error> {
+        self.transport.send(Command::Duty(duty))
+    }
+
+    /// Drives the motor with `current`, in amperes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command cannot be sent.
+    pub fn set_current(&mut self, current: f64) -> Result<(), Error> {
+        self.transport.send(Command::Current(current))
+    }
+
+    /// Holds the motor at `erpm` electrical RPM.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command cannot be sent.
+    pub fn set_rpm(&mut self, erpm: f64) -> Result<(), Error> {
+        self.transport.send(Command::Rpm(erpm))
+    }
+
+    /// Returns the latest telemetry.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the telemetry cannot be read.
+    pub fn telemetry(&mut self) -> Result<Telemetry, Error> {
+        self.transport.telemetry()
+    }
+
+    /// Returns the transport.
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Returns the transport, consuming the controller.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+impl<T: Transport> Motor for Vesc<T> {
+    fn set_speed(&mut self, speed: f64) -> Result<(), Error> {
+        self.set_duty(speed)
+    }
+}
+
+impl<T: Transport> Encoder for Vesc<T> {
+    fn count(&mut self) -> Result<i64, Error> {
+        self.telemetry().map(|telemetry| telemetry.tachometer)
+    }
+}
+
+/// Updates `telemetry` from `frame` if it is a status frame from
+/// `controller`.
+#[cfg(target_os = "linux")]
+fn apply_status(telemetry: &mut Telemetry, controller: u8, frame: &Frame) -> bool {
+    let Id::Extended(id) = frame.id() else {
+        return false;
+    };
+
+    if id & 0xFF != u32::from(controller) {
+        return false;
+    }
+
+    let data = frame.data();
+    let i16_at = |index: usize| f64::from(i16::from_be_bytes([data[index], data[index + 1]]));
+    let i32_at = |index: usize| {
+        i32::from_be_bytes([
+            data[index],
+            data[index + 1],
+            data[index + 2],
+            data[index + 3],
+        ])
+    };
+
+    match id >> 8 {
+        CAN_PACKET_STATUS if data.len() >= 8 => {
+            telemetry.erpm = f64::from(i32_at(0));
+            telemetry.current = i16_at(4) / 10.0;
+            telemetry.duty = i16_at(6) / 1000.0;
+        }
+        CAN_PACKET_STATUS_4 if data.len() >= 6 => {
+            telemetry.fet_temperature = i16_at(0) / 10.0;
+            telemetry.motor_temperature = i16_at(2) / 10.0;
+            telemetry.input_current = i16_at(4) / 10.0;
+        }
+        CAN_PACKET_STATUS_5 if data.len() >= 6 => {
+            telemetry.tachometer = i64::from(i32_at(0));
+            telemetry.input_voltage = i16_at(4) / 10.0;
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+/// Parses the payload of a `COMM_GET_VALUES` response, after the command.
+fn parse_values(payload: &[u8]) -> Result<Telemetry, Error> {
+    if payload.len() < 48 {
+        return Err(Error::new(ErrorKind::InvalidData, "short VESC telemetry"));
+    }
+
+    let i16_at = |index: usize| f64::from(i16::from_be_bytes([payload[index], payload[index + 1]]));
+    let i32_at = |index: usize| {
+        i32::from_be_bytes([
+            payload[index],
+            payload[index + 1],
+            payload[index + 2],
+            payload[index + 3],
+        ])
+    };
+    Ok(Telemetry {
+        fet_temperature: i16_at(0) / 10.0,
+        motor_temperature: i16_at(2) / 10.0,
+        current: f64::from(i32_at(4)) / 100.0,
+        input_current: f64::from(i32_at(8)) / 100.0,
+        duty: i16_at(20) / 1000.0,
+        erpm: f64::from(i32_at(22)),
+        input_voltage: i16_at(26) / 10.0,
+        tachometer: i64::from(i32_at(44)),
+    })
+}
+
+/// Computes the CRC-16/XMODEM used by VESC packets.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc: u16, _| {
+            if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x1021
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct MockStream {
+        received: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.received.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![START, payload.len() as u8];
+        packet.extend_from_slice(payload);
+        packet.extend_from_slice(&crc16(payload).to_be_bytes());
+        packet.push(END);
+        packet
+    }
+
+    #[test]
+    fn it_should_compute_the_xmodem_crc() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn it_should_send_commands_over_uart() {
+        let mut vesc = Vesc::new(Uart::new(MockStream::default()));
+        vesc.set_speed(0.5).unwrap();
+        vesc.set_current(-2.5).unwrap();
+        let sent = vesc.into_inner().into_inner().sent;
+        let mut expected = packet(&[COMM_SET_DUTY, 0x00, 0x00, 0xC3, 0x50]);
+        expected.extend(packet(&[COMM_SET_CURRENT, 0xFF, 0xFF, 0xF6, 0x3C]));
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn it_should_read_telemetry_over_uart() {
+        let mut values = vec![COMM_GET_VALUES];
+        values.extend(352_i16.to_be_bytes());
+        values.extend(0_i16.to_be_bytes());
+        values.extend(1250_i32.to_be_bytes());
+        values.extend([0; 12]);
+        values.extend(500_i16.to_be_bytes());
+        values.extend((-12_000_i32).to_be_bytes());
+        values.extend(248_i16.to_be_bytes());
+        values.extend([0; 16]);
+        values.extend(4321_i32.to_be_bytes());
+        values.extend([0; 5]);
+        let mut received = vec![0xFF];
+        received.extend(packet(&values));
+        let mut vesc = Vesc::new(Uart::new(MockStream {
+            received: Cursor::new(received),
+            sent: Vec::new(),
+        }));
+        let telemetry = vesc.telemetry().unwrap();
+        assert_eq!(telemetry.fet_temperature, 35.2);
+        assert_eq!(telemetry.current, 12.5);
+        assert_eq!(telemetry.duty, 0.5);
+        assert_eq!(telemetry.erpm, -12_000.0);
+        assert_eq!(telemetry.input_voltage, 24.8);
+        assert_eq!(telemetry.tachometer, 4321);
+        assert!(vesc.count().is_err());
+        assert_eq!(
+            vesc.into_inner().into_inner().sent,
+            packet(&[COMM_GET_VALUES]).repeat(2)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_should_apply_status_frames_from_its_controller() {
+        let mut telemetry = Telemetry::default();
+        let mut data = 3000_i32.to_be_bytes().to_vec();
+        data.extend(42_i16.to_be_bytes());
+        data.extend((-250_i16).to_be_bytes());
+        let status = Frame::new(Id::Extended(CAN_PACKET_STATUS << 8 | 5), &data).unwrap();
+        assert!(!apply_status(&mut telemetry, 6, &status));
+        assert!(apply_status(&mut telemetry, 5, &status));
+        assert_eq!(telemetry.erpm, 3000.0);
+        assert_eq!(telemetry.current, 4.2);
+        assert_eq!(telemetry.duty, -0.25);
+        let mut data = (-7_i32).to_be_bytes().to_vec();
+        data.extend(240_i16.to_be_bytes());
+        let status = Frame::new(Id::Extended(CAN_PACKET_STATUS_5 << 8 | 5), &data).unwrap();
+        assert!(apply_status(&mut telemetry, 5, &status));
+        assert_eq!(telemetry.tachometer, -7);
+        assert_eq!(telemetry.input_voltage, 24.0);
+    }
+}
@@ -0,0 +1,5 @@
+//! Measurements for diagnosing the robot’s runtime behavior.
+
+pub mod latency;
+#[cfg(target_os = "linux")]
+pub mod resources;
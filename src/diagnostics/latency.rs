@@ -0,0 +1,365 @@
+//! Latency measurement for the path from a received command to an actuator.
+//!
+//! A [`CommandTrace`] is started when a command arrives from the network,
+//! travels with it through the [`CommandMux`](crate::control::mux::CommandMux)
+//! to the actuators, and is recorded in a [`LatencyTracker`], whose report
+//! is published with the rest of the telemetry.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::json::{ToJson, Value};
+
+/// Points along the command path at which a command is timestamped.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Stage {
+    /// The command was received from the network.
+    Received,
+    /// The command was selected by the command mux.
+    Selected,
+    /// The command was written to the actuator.
+    Actuated,
+}
+
+/// Timestamps recorded for a single command as it moves through each stage.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CommandTrace {
+    actuated: Option<Instant>,
+    received: Instant,
+    selected: Option<Instant>,
+}
+
+impl CommandTrace {
+    /// Starts a trace for a command received at `instant`.
+    pub fn received_at(instant: Instant) -> Self {
+        Self {
+            actuated: None,
+            received: instant,
+            selected: None,
+        }
+    }
+
+    /// Starts a trace for a command received now.
+    pub fn received_now() -> Self {
+        Self::received_at(Instant::now())
+    }
+
+    /// Records that the command reached `stage` at `instant`.
+    pub fn mark_at(&mut self, stage: Stage, instant: Instant) {
+        match stage {
+            Stage::Received => self.received = instant,
+            Stage::Selected => self.selected = Some(instant),
+            Stage::Actuated => self.actuated = Some(instant),
+        }
+    }
+
+    /// Records that the command reached `stage` now.
+    pub fn mark(&mut self, stage: Stage) {
+        self.mark_at(stage, Instant::now());
+    }
+
+    /// Returns the instant at which the command reached `stage`, if it has.
+    #[must_use]
+    pub fn instant(&self, stage: Stage) -> Option<Instant> {
+        match stage {
+            Stage::Received => Some(self.received),
+            Stage::Selected => self.selected,
+            Stage::Actuated => self.actuated,
+        }
+    }
+
+    /// Returns the time taken from receipt to actuation, if the command has
+    /// been actuated.
+    #[must_use]
+    pub fn end_to_end(&self) -> Option<Duration> {
+        self.actuated
+            .map(|actuated| actuated.saturating_duration_since(self.received))
+    }
+}
+
+/// Summary of a latency distribution.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Percentiles {
+    /// Maximum latency.
+    pub max: Duration,
+    /// Median latency.
+    pub p50: Duration,
+    /// 90th percentile latency.
+    pub p90: Duration,
+    /// 99th percentile latency.
+    pub p99: Duration,
+}
+
+impl Percentiles {
+    /// Computes the percentiles of `samples` using the nearest-rank method.
+    ///
+    /// Returns `None` if `samples` is empty.
+    pub fn from_samples(samples: impl IntoIterator<Item = Duration>) -> Option<Self> {
+        let mut samples: Vec<_> = samples.into_iter().collect();
+
+        if samples.is_empty() {
+            return None;
+        }
+
+        samples.sort_unstable();
+        let rank = |percentile: usize| {
+            let index = (percentile * samples.len()).div_ceil(100).max(1) - 1;
+            samples[index]
+        };
+
+        Some(Self {
+            max: samples[samples.len() - 1],
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+        })
+    }
+}
+
+/// Latency percentiles for each segment of the command path.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LatencyReport {
+    /// Latency from receipt to actuation.
+    pub end_to_end: Percentiles,
+    /// Latency from receipt to selection by the command mux.
+    pub receipt_to_selection: Percentiles,
+    /// Number of commands the report is based on.
+    pub samples: usize,
+    /// Latency from selection by the command mux to actuation.
+    pub selection_to_actuator: Percentiles,
+}
+
+impl ToJson for Percentiles {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("p50", self.p50.as_secs_f64())
+            .with("p90", self.p90.as_secs_f64())
+            .with("p99", self.p99.as_secs_f64())
+            .with("max", self.max.as_secs_f64())
+    }
+}
+
+impl ToJson for LatencyReport {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("samples", self.samples)
+            .with("receipt_to_selection", self.receipt_to_selection)
+            .with("selection_to_actuator", self.selection_to_actuator)
+            .with("end_to_end", self.end_to_end)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    end_to_end: Duration,
+    receipt_to_selection: Duration,
+    selection_to_actuator: Duration,
+}
+
+/// Collects completed command traces over a sliding window and reports their
+/// latency percentiles.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, Instant};
+///
+/// use otter_pi::diagnostics::latency::{CommandTrace, LatencyTracker, Stage};
+///
+/// let mut tracker = LatencyTracker::new(100);
+/// let received = Instant::now();
+/// let mut trace = CommandTrace::received_at(received);
+/// trace.mark_at(Stage::Selected, received + Duration::from_millis(2));
+/// trace.mark_at(Stage::Actuated, received + Duration::from_millis(5));
+/// tracker.record(&trace);
+///
+/// let report = tracker.report().unwrap();
+/// assert_eq!(report.end_to_end.p50, Duration::from_millis(5));
+/// ```
+#[derive(Clone, Debug)]
+pub struct LatencyTracker {
+    capacity: usize,
+    samples: VecDeque<Sample>,
+}
+
+impl LatencyTracker {
+    /// Creates a new `LatencyTracker` that reports on the most recent
+    /// `capacity` commands.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity should be greater than 0");
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records a command that has passed through every stage.
+    ///
+    /// Returns `false` and ignores the trace if the command has not been both
+    /// selected and actuated.
+    pub fn record(&mut self, trace: &CommandTrace) -> bool {
+        let (Some(selected), Some(actuated)) = (trace.selected, trace.actuated) else {
+            return false;
+        };
+
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(Sample {
+            end_to_end: actuated.saturating_duration_since(trace.received),
+            receipt_to_selection: selected.saturating_duration_since(trace.received),
+            selection_to_actuator: actuated.saturating_duration_since(selected),
+        });
+        true
+    }
+
+    /// Returns the number of commands in the current window.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns `true` if no commands have been recorded.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Discards every recorded command.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Reports the latency percentiles of the current window, or `None` if no
+    /// commands have been recorded.
+    #[must_use]
+    pub fn report(&self) -> Option<LatencyReport> {
+        let percentiles =
+            |f: fn(&Sample) -> Duration| Percentiles::from_samples(self.samples.iter().map(f));
+
+        Some(LatencyReport {
+            end_to_end: percentiles(|sample| sample.end_to_end)?,
+            receipt_to_selection: percentiles(|sample| sample.receipt_to_selection)?,
+            samples: self.samples.len(),
+            selection_to_actuator: percentiles(|sample| sample.selection_to_actuator)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(selected_ms: u64, actuated_ms: u64) -> CommandTrace {
+        let received = Instant::now();
+        let mut trace = CommandTrace::received_at(received);
+        trace.mark_at(
+            Stage::Selected,
+            received + Duration::from_millis(selected_ms),
+        );
+        trace.mark_at(
+            Stage::Actuated,
+            received + Duration::from_millis(actuated_ms),
+        );
+        trace
+    }
+
+    mod command_trace {
+        use super::*;
+
+        #[test]
+        fn it_should_not_have_an_end_to_end_latency_before_actuation() {
+            assert_eq!(CommandTrace::received_now().end_to_end(), None);
+        }
+
+        #[test]
+        fn it_should_measure_the_end_to_end_latency() {
+            assert_eq!(trace(1, 7).end_to_end(), Some(Duration::from_millis(7)));
+        }
+
+        #[test]
+        fn it_should_return_the_instant_of_each_stage() {
+            let mut trace = CommandTrace::received_now();
+            assert!(trace.instant(Stage::Selected).is_none());
+            trace.mark(Stage::Selected);
+            assert!(trace.instant(Stage::Selected) >= trace.instant(Stage::Received));
+        }
+    }
+
+    mod percentiles {
+        use super::*;
+
+        #[test]
+        fn it_should_return_none_without_samples() {
+            assert_eq!(Percentiles::from_samples([]), None);
+        }
+
+        #[test]
+        fn it_should_use_the_nearest_rank() {
+            let samples = (1..=100).map(Duration::from_millis);
+            let percentiles = Percentiles::from_samples(samples).unwrap();
+            assert_eq!(percentiles.p50, Duration::from_millis(50));
+            assert_eq!(percentiles.p90, Duration::from_millis(90));
+            assert_eq!(percentiles.p99, Duration::from_millis(99));
+            assert_eq!(percentiles.max, Duration::from_millis(100));
+        }
+
+        #[test]
+        fn it_should_handle_a_single_sample() {
+            let percentiles = Percentiles::from_samples([Duration::from_millis(3)]).unwrap();
+            assert_eq!(percentiles.p50, Duration::from_millis(3));
+            assert_eq!(percentiles.p99, Duration::from_millis(3));
+        }
+    }
+
+    mod latency_tracker {
+        use super::*;
+
+        #[test]
+        fn it_should_not_report_without_samples() {
+            assert_eq!(LatencyTracker::new(10).report(), None);
+        }
+
+        #[test]
+        fn it_should_ignore_incomplete_traces() {
+            let mut tracker = LatencyTracker::new(10);
+            assert!(!tracker.record(&CommandTrace::received_now()));
+            assert!(tracker.is_empty());
+        }
+
+        #[test]
+        fn it_should_report_each_segment() {
+            let mut tracker = LatencyTracker::new(10);
+            tracker.record(&trace(2, 5));
+            let report = tracker.report().unwrap();
+            assert_eq!(report.samples, 1);
+            assert_eq!(report.receipt_to_selection.p50, Duration::from_millis(2));
+            assert_eq!(report.selection_to_actuator.p50, Duration::from_millis(3));
+            assert_eq!(report.end_to_end.p50, Duration::from_millis(5));
+        }
+
+        #[test]
+        fn it_should_only_keep_the_most_recent_samples() {
+            let mut tracker = LatencyTracker::new(2);
+            tracker.record(&trace(1, 100));
+            tracker.record(&trace(1, 2));
+            tracker.record(&trace(1, 3));
+            assert_eq!(tracker.len(), 2);
+            assert_eq!(
+                tracker.report().unwrap().end_to_end.max,
+                Duration::from_millis(3)
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "capacity should be greater than 0")]
+        fn it_should_panic_with_a_capacity_of_0() {
+            let _ = LatencyTracker::new(0);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! CPU and memory used by each of the robot’s subsystems.
+//!
+//! Linux accounts CPU time to every thread in `/proc/self/task`, so naming
+//! the threads each subsystem spawns, as the runtime already does, is enough
+//! to tell which subsystem is busy. The [`ResourceMonitor`] attributes
+//! threads to subsystems by the start of their names, and reports how much
+//! of one core each used since the last sample, along with the resident
+//! memory of the whole process, which threads share.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::json::{ToJson, Value};
+
+/// Clock ticks per second in `/proc`, which Linux fixes at 100 for user
+/// space whatever the kernel’s own tick rate.
+const TICKS_PER_SECOND: f64 = 100.0;
+
+/// Subsystem of threads not attributed to any other.
+pub const OTHER: &str = "other";
+
+/// Resources used by the process since the previous sample.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceReport {
+    /// Share of one core used by each subsystem, where 1.0 is a whole core.
+    pub cpu: BTreeMap<String, f64>,
+    /// Memory resident in RAM, in bytes.
+    pub rss: u64,
+    /// Number of threads in the process.
+    pub threads: usize,
+}
+
+impl ResourceReport {
+    /// Returns the share of one core used by the whole process.
+    #[must_use]
+    pub fn total_cpu(&self) -> f64 {
+        self.cpu.values().sum()
+    }
+}
+
+impl ToJson for ResourceReport {
+    fn to_json(&self) -> Value {
+        let cpu = self
+            .cpu
+            .iter()
+            .fold(Value::object(), |object, (name, share)| {
+                object.with(name, *share)
+            });
+        Value::object()
+            .with("cpu", cpu)
+            .with("total_cpu", self.total_cpu())
+            .with("rss", self.rss)
+            .with("threads", self.threads)
+    }
+}
+
+/// Samples the CPU time of each thread and attributes it to subsystems.
+#[derive(Debug)]
+pub struct ResourceMonitor {
+    last: Option<(Instant, BTreeMap<String, u64>)>,
+    proc: PathBuf,
+    subsystems: Vec<(String, String)>,
+}
+
+impl ResourceMonitor {
+    /// Creates a new `ResourceMonitor` for the current process, attributing
+    /// every thread to [`OTHER`] until subsystems are added.
+    pub fn new() -> Self {
+        Self::with_proc(Path::new("/proc/self"))
+    }
+
+    /// Creates a new `ResourceMonitor` reading the process directory at
+    /// `proc`.
+    pub fn with_proc(proc: &Path) -> Self {
+        Self {
+            last: None,
+            proc: proc.to_path_buf(),
+            subsystems: Vec::new(),
+        }
+    }
+
+    /// Attributes threads whose names start with `prefix` to `subsystem`.
+    ///
+    /// Prefixes are tried in the order they are added, so a more specific
+    /// prefix should be added before a shorter one it starts with.
+    #[must_use]
+    pub fn with_subsystem(mut self, subsystem: &str, prefix: &str) -> Self {
+        self.subsystems
+            .push((prefix.to_owned(), subsystem.to_owned()));
+        self
+    }
+
+    /// Reads every thread at `now` and returns the resources used since the
+    /// previous sample, with no CPU usage on the first.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the process directory cannot
+    /// be read.
+    pub fn sample(&mut self, now: Instant) -> Result<ResourceReport, Error> {
+        let threads = self.threads()?;
+        let mut report = ResourceReport {
+            cpu: BTreeMap::new(),
+            rss: self.rss()?,
+            threads: threads.len(),
+        };
+
+        if let Some((last_sample, last_ticks)) = &self.last {
+            let seconds = now.saturating_duration_since(*last_sample).as_secs_f64();
+
+            for (tid, (name, ticks)) in &threads {
+                // A thread started since the last sample is counted from zero.
+                let used = ticks.saturating_sub(last_ticks.get(tid).copied().unwrap_or(0));
+                let share = if seconds > 0.0 {
+                    used as f64 / TICKS_PER_SECOND / seconds
+                } else {
+                    0.0
+                };
+                *report
+                    .cpu
+                    .entry(self.subsystem(name).to_owned())
+                    .or_insert(0.0) += share;
+            }
+        }
+
+        let ticks = threads
+            .into_iter()
+            .map(|(tid, (_, ticks))| (tid, ticks))
+            .collect();
+        self.last = Some((now, ticks));
+        Ok(report)
+    }
+
+    fn subsystem(&self, thread: &str) -> &str {
+        self.subsystems
+            .iter()
+            .find(|(prefix, _)| thread.starts_with(prefix.as_str()))
+            .map_or(OTHER, |(_, subsystem)| subsystem)
+    }
+
+    /// Returns the name and CPU ticks of each thread, by thread ID.
+    fn threads(&self) -> Result<BTreeMap<String, (String, u64)>, Error> {
+        let mut threads = BTreeMap::new();
+
+        for entry in fs::read_dir(self.proc.join("task"))? {
+            let entry = entry?;
+            let tid = entry.file_name().to_string_lossy().into_owned();
+
+            // A thread may exit between listing and reading it.
+            match fs::read_to_string(entry.path().join("stat")) {
+                Ok(stat) => {
+                    threads.insert(tid, parse_stat(&stat)?);
+                }
+                Err(error) if error.kind() == ErrorKind::NotFound => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(threads)
+    }
+
+    fn rss(&self) -> Result<u64, Error> {
+        let status = fs::read_to_string(self.proc.join("status"))?;
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| value.trim().strip_suffix("kB"))
+            .and_then(|kb| kb.trim().parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "status has no VmRSS"))
+    }
+}
+
+impl Default for ResourceMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the name of a thread and the CPU ticks it used, in user and
+/// kernel mode, from its `stat` file.
+fn parse_stat(stat: &str) -> Result<(String, u64), Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "malformed stat");
+    // The name is in parentheses and may itself contain spaces and parentheses.
+    let open = stat.find('(').ok_or_else(invalid)?;
+    let close = stat.rfind(')').ok_or_else(invalid)?;
+    let name = stat.get(open + 1..close).ok_or_else(invalid)?;
+    // Fields after the name start at the third, the state, so `utime` and
+    // `stime`, the fourteenth and fifteenth, are at 11 and 12.
+    let fields: Vec<_> = stat[close + 1..].split_whitespace().collect();
+    let ticks = |index: usize| {
+        fields
+            .get(index)
+            .and_then(|field| field.parse::<u64>().ok())
+            .ok_or_else(invalid)
+    };
+    Ok((name.to_owned(), ticks(11)? + ticks(12)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    fn write_thread(proc: &Path, tid: u32, name: &str, utime: u64, stime: u64) {
+        let task = proc.join("task").join(tid.to_string());
+        fs::create_dir_all(&task).unwrap();
+        let stat = format!(
+            "{tid} ({name}) S 1 1 1 0 -1 4194560 100 0 0 0 {utime} {stime} 0 0 20 0 1 0 100 0 0"
+        );
+        fs::write(task.join("stat"), stat).unwrap();
+    }
+
+    #[test]
+    fn it_should_attribute_cpu_to_subsystems_by_thread_name() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let proc = dir.path();
+        fs::write(
+            proc.join("status"),
+            "Name:\totter-pi\nVmRSS:\t   20480 kB\n",
+        )
+        .unwrap();
+        write_thread(proc, 100, "otter-pi", 50, 10);
+        write_thread(proc, 101, "vision worker", 1000, 0);
+        write_thread(proc, 102, "control", 200, 20);
+        let mut monitor = ResourceMonitor::with_proc(proc)
+            .with_subsystem("vision", "vision")
+            .with_subsystem("control", "control");
+        let start = Instant::now();
+        let first = monitor.sample(start).unwrap();
+        assert!(first.cpu.is_empty());
+        assert_eq!(first.rss, 20 * 1024 * 1024);
+        assert_eq!(first.threads, 3);
+        write_thread(proc, 101, "vision worker", 1180, 0);
+        write_thread(proc, 102, "control", 205, 25);
+        write_thread(proc, 103, "vision (2)", 20, 0);
+        let report = monitor.sample(start + Duration::from_secs(2)).unwrap();
+        assert!((report.cpu["vision"] - 1.0).abs() < 1e-9);
+        assert!((report.cpu["control"] - 0.05).abs() < 1e-9);
+        assert_eq!(report.cpu["other"], 0.0);
+        assert_eq!(report.threads, 4);
+    }
+
+    #[test]
+    fn it_should_parse_names_with_parentheses() {
+        let stat = "7 (a (b) c) R 1 1 1 0 -1 0 0 0 0 0 3 4 0 0";
+        assert_eq!(parse_stat(stat).unwrap(), ("a (b) c".to_owned(), 7));
+        assert!(parse_stat("7 (x) R 1").is_err());
+    }
+
+    #[test]
+    fn it_should_read_the_current_process() {
+        let mut monitor = ResourceMonitor::new();
+        let report = monitor.sample(Instant::now()).unwrap();
+        assert!(report.rss > 0);
+        assert!(report.threads >= 1);
+    }
+}
@@ -0,0 +1,152 @@
+//! Chassis kinematics and odometry.
+//!
+//! Every drive configuration is commanded with a [`Twist`] in the chassis
+//! frame, with X forward, Y to the left, and rotation counterclockwise. Its
+//! [`Kinematics`] turn that into wheel speeds and, from measured wheel speeds,
+//! back into the twist that [`Odometry`] integrates into a pose.
+
+use std::time::Duration;
+
+use crate::geometry::{normalize_angle, Pose};
+use crate::json::{ToJson, Value};
+
+pub mod ackermann;
+pub mod differential;
+pub mod mecanum;
+pub mod omni;
+pub mod wheel;
+
+pub use ackermann::AckermannDrive;
+pub use differential::DifferentialDrive;
+pub use mecanum::MecanumDrive;
+pub use omni::OmniDrive;
+pub use wheel::WheelController;
+
+/// Velocity of the chassis in its own frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Twist {
+    /// Forward velocity, in metres per second.
+    pub vx: f64,
+    /// Leftward velocity, in metres per second.
+    pub vy: f64,
+    /// Counterclockwise angular velocity, in radians per second.
+    pub omega: f64,
+}
+
+impl Twist {
+    /// Creates a new twist.
+    pub fn new(vx: f64, vy: f64, omega: f64) -> Self {
+        Self { vx, vy, omega }
+    }
+}
+
+impl ToJson for Twist {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("vx", self.vx)
+            .with("vy", self.vy)
+            .with("omega", self.omega)
+    }
+}
+
+/// Mapping between chassis velocity and wheel speeds.
+///
+/// Wheel speeds are the linear speeds of the wheel rims, in metres per
+/// second, with the order and signs documented by each implementation.
+pub trait Kinematics {
+    /// Wheel speeds, in the order documented by the implementation.
+    type WheelSpeeds;
+
+    /// Returns the wheel speeds that move the chassis at `twist`.
+    fn inverse(&self, twist: Twist) -> Self::WheelSpeeds;
+
+    /// Returns the chassis velocity produced by `speeds`.
+    fn forward(&self, speeds: &Self::WheelSpeeds) -> Twist;
+}
+
+/// Scales `speeds` down uniformly so none exceeds `max`, preserving the
+/// direction of motion that saturating each wheel separately would distort.
+pub fn desaturate(speeds: &mut [f64], max: f64) {
+    let fastest = speeds
+        .iter()
+        .fold(0.0_f64, |fastest, speed| fastest.max(speed.abs()));
+
+    if fastest > max {
+        let scale = max / fastest;
+        speeds.iter_mut().for_each(|speed| *speed *= scale);
+    }
+}
+
+/// Dead-reckoned pose, integrated from chassis velocities.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Odometry {
+    pose: Pose,
+}
+
+impl Odometry {
+    /// Creates new odometry starting at `pose`.
+    pub fn new(pose: Pose) -> Self {
+        Self { pose }
+    }
+
+    /// Returns the current pose estimate.
+    #[must_use]
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    /// Replaces the pose estimate, such as after localizing against a map.
+    pub fn reset(&mut self, pose: Pose) {
+        self.pose = pose;
+    }
+
+    /// Advances the pose by moving at `twist` for `dt`, and returns it.
+    pub fn update(&mut self, twist: Twist, dt: Duration) -> Pose {
+        let dt = dt.as_secs_f64();
+        // Rotating the displacement by the heading halfway through the step
+        // keeps arcs from drifting outward.
+        let heading = self.pose.heading + twist.omega * dt / 2.0;
+        let (sin, cos) = heading.sin_cos();
+        self.pose.x += (twist.vx * cos - twist.vy * sin) * dt;
+        self.pose.y += (twist.vx * sin + twist.vy * cos) * dt;
+        self.pose.heading = normalize_angle(self.pose.heading + twist.omega * dt);
+        self.pose
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn it_should_desaturate_wheel_speeds_proportionally() {
+        let mut speeds = [2.0, -1.0, 0.5];
+        desaturate(&mut speeds, 1.0);
+        assert_eq!(speeds, [1.0, -0.5, 0.25]);
+        desaturate(&mut speeds, 1.0);
+        assert_eq!(speeds, [1.0, -0.5, 0.25]);
+    }
+
+    #[test]
+    fn it_should_integrate_straight_and_sideways_motion() {
+        let mut odometry = Odometry::new(Pose::new(0.0, 0.0, PI / 2.0));
+        let pose = odometry.update(Twist::new(1.0, 0.5, 0.0), Duration::from_secs(2));
+        assert!((pose.x + 1.0).abs() < 1e-12 && (pose.y - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn it_should_integrate_a_full_circle_back_to_the_start() {
+        let mut odometry = Odometry::default();
+        let dt = Duration::from_millis(10);
+
+        for _ in 0..200 {
+            odometry.update(Twist::new(PI, 0.0, PI), dt);
+        }
+
+        let pose = odometry.pose();
+        assert!(pose.x.abs() < 1e-9 && pose.y.abs() < 1e-9, "{pose:?}");
+        assert!(pose.heading.abs() < 1e-9);
+    }
+}
@@ -0,0 +1,194 @@
+//! Kinematics and hardware of an Ackermann-steered chassis.
+
+use std::f64::consts::FRAC_PI_2;
+use std::io::Error;
+
+use super::{Kinematics, Twist};
+use crate::devices::servo::Servo;
+use crate::hal::{Motor, PwmOutput};
+
+/// Speed and steering angle of an Ackermann-steered chassis.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Steering {
+    /// Forward speed of the rear axle, in metres per second.
+    pub speed: f64,
+    /// Angle of the virtual centre front wheel, counterclockwise, in radians.
+    pub angle: f64,
+}
+
+/// Bicycle model of a car-like chassis, which lumps each axle into a single
+/// wheel and steers the front one.
+///
+/// The chassis can neither strafe nor turn on the spot, so the `vy` of a
+/// commanded twist is ignored and its turn is limited to the tightest radius
+/// the steering allows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BicycleModel {
+    max_steering_angle: f64,
+    wheelbase: f64,
+}
+
+impl BicycleModel {
+    /// Creates a new `BicycleModel` with `wheelbase` metres between the axles
+    /// that steers up to `max_steering_angle` radians either way.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `wheelbase` is not positive, or `max_steering_angle` is not
+    /// between 0 and π/2 exclusive.
+    pub fn new(wheelbase: f64, max_steering_angle: f64) -> Self {
+        assert!(wheelbase > 0.0, "wheelbase should be positive");
+        assert!(
+            max_steering_angle > 0.0 && max_steering_angle < FRAC_PI_2,
+            "max_steering_angle should be between 0 and π/2"
+        );
+        Self {
+            max_steering_angle,
+            wheelbase,
+        }
+    }
+
+    /// Returns the radius of the tightest turn, in metres.
+    #[must_use]
+    pub fn min_turning_radius(&self) -> f64 {
+        self.wheelbase / self.max_steering_angle.tan()
+    }
+}
+
+impl Kinematics for BicycleModel {
+    type WheelSpeeds = Steering;
+
+    fn inverse(&self, twist: Twist) -> Steering {
+        let angle = if twist.vx != 0.0 {
+            (self.wheelbase * twist.omega / twist.vx).atan()
+        } else if twist.omega != 0.0 {
+            // Turn the wheels ready to follow the turn once moving.
+            self.max_steering_angle.copysign(twist.omega)
+        } else {
+            0.0
+        };
+        Steering {
+            speed: twist.vx,
+            angle: angle.clamp(-self.max_steering_angle, self.max_steering_angle),
+        }
+    }
+
+    fn forward(&self, steering: &Steering) -> Twist {
+        Twist::new(
+            steering.speed,
+            0.0,
+            steering.speed * steering.angle.tan() / self.wheelbase,
+        )
+    }
+}
+
+/// An RC-car style chassis with a steering servo and a single drive motor.
+#[derive(Debug)]
+pub struct AckermannDrive<S, M> {
+    max_speed: f64,
+    model: BicycleModel,
+    motor: M,
+    servo: Servo<S>,
+}
+
+impl<S: PwmOutput, M: Motor> AckermannDrive<S, M> {
+    /// Creates a new `AckermannDrive` steered by `servo`, whose angle must be
+    /// that of the virtual centre front wheel, and driven by `motor`, which
+    /// reaches `max_speed` metres per second at full power.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_speed` is not positive.
+    pub fn new(servo: Servo<S>, motor: M, model: BicycleModel, max_speed: f64) -> Self {
+        assert!(max_speed > 0.0, "max_speed should be positive");
+        Self {
+            max_speed,
+            model,
+            motor,
+            servo,
+        }
+    }
+
+    /// Returns the kinematic model.
+    #[must_use]
+    pub fn model(&self) -> &BicycleModel {
+        &self.model
+    }
+
+    /// Steers and drives toward `twist`, and returns the twist that the
+    /// speed and turning-radius limits allow.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the servo or motor cannot be
+    /// driven.
+    pub fn drive(&mut self, twist: Twist) -> Result<Twist, Error> {
+        let mut steering = self.model.inverse(twist);
+        steering.speed = steering.speed.clamp(-self.max_speed, self.max_speed);
+        steering.angle = self.servo.set_angle(steering.angle)?;
+        self.motor.set_speed(steering.speed / self.max_speed)?;
+        Ok(self.model.forward(&steering))
+    }
+
+    /// Stops the motor, leaving the steering where it is.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the motor cannot be driven.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.motor.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_4;
+
+    use super::*;
+    use crate::hal::{MockMotor, MockPwm};
+
+    #[test]
+    fn it_should_steer_for_the_turning_radius() {
+        let model = BicycleModel::new(0.25, 0.5);
+        // A 1 m radius turn at 1 m/s needs atan(0.25 / 1) of steering.
+        let steering = model.inverse(Twist::new(1.0, 0.0, 1.0));
+        assert!((steering.angle - 0.25_f64.atan()).abs() < 1e-12);
+        let twist = model.forward(&steering);
+        assert!((twist.omega - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn it_should_limit_the_turning_radius() {
+        let model = BicycleModel::new(0.25, FRAC_PI_4);
+        assert!((model.min_turning_radius() - 0.25).abs() < 1e-12);
+        let steering = model.inverse(Twist::new(0.5, 0.0, 10.0));
+        assert_eq!(steering.angle, FRAC_PI_4);
+        assert!((model.forward(&steering).omega - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn it_should_steer_the_same_way_when_reversing() {
+        let model = BicycleModel::new(0.25, 0.5);
+        let steering = model.inverse(Twist::new(-1.0, 0.0, -1.0));
+        assert!(steering.angle > 0.0);
+        assert_eq!(model.inverse(Twist::new(0.0, 0.0, 1.0)).angle, 0.5);
+        assert_eq!(model.inverse(Twist::default()).angle, 0.0);
+    }
+
+    #[test]
+    fn it_should_drive_the_servo_and_motor() {
+        let mut drive = AckermannDrive::new(
+            Servo::new(MockPwm::default()),
+            MockMotor::default(),
+            BicycleModel::new(0.25, FRAC_PI_4),
+            2.0,
+        );
+        let twist = drive.drive(Twist::new(3.0, 0.0, 0.0)).unwrap();
+        assert_eq!(twist, Twist::new(2.0, 0.0, 0.0));
+        assert_eq!(drive.motor.speed, 1.0);
+        drive.drive(Twist::new(1.0, 0.0, 100.0)).unwrap();
+        assert_eq!(drive.servo.angle(), Some(FRAC_PI_4));
+        drive.stop().unwrap();
+        assert_eq!(drive.motor.speed, 0.0);
+    }
+}
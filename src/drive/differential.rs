@@ -0,0 +1,64 @@
+//! Kinematics of a differential drive.
+
+use super::{Kinematics, Twist};
+
+/// A chassis with a fixed wheel, or track, on each side that steers by
+/// driving the sides at different speeds.
+///
+/// Wheel speeds are ordered `[left, right]`, positive driving forward. The
+/// chassis cannot move sideways, so the `vy` of a commanded twist is ignored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DifferentialDrive {
+    track_width: f64,
+}
+
+impl DifferentialDrive {
+    /// Creates a new `DifferentialDrive` with `track_width` metres between
+    /// the wheel centres.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `track_width` is not positive.
+    pub fn new(track_width: f64) -> Self {
+        assert!(track_width > 0.0, "track_width should be positive");
+        Self { track_width }
+    }
+
+    /// Returns the distance between the wheel centres, in metres.
+    #[must_use]
+    pub fn track_width(&self) -> f64 {
+        self.track_width
+    }
+}
+
+impl Kinematics for DifferentialDrive {
+    type WheelSpeeds = [f64; 2];
+
+    fn inverse(&self, twist: Twist) -> [f64; 2] {
+        let turn = twist.omega * self.track_width / 2.0;
+        [twist.vx - turn, twist.vx + turn]
+    }
+
+    fn forward(&self, &[left, right]: &[f64; 2]) -> Twist {
+        Twist::new((left + right) / 2.0, 0.0, (right - left) / self.track_width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_turn_by_driving_the_sides_apart() {
+        let drive = DifferentialDrive::new(0.2);
+        assert_eq!(drive.inverse(Twist::new(1.0, 0.0, 2.0)), [0.8, 1.2]);
+    }
+
+    #[test]
+    fn it_should_recover_the_twist_from_wheel_speeds() {
+        let drive = DifferentialDrive::new(0.2);
+        let twist = drive.forward(&drive.inverse(Twist::new(0.5, 0.3, -1.0)));
+        assert!((twist.vx - 0.5).abs() < 1e-12 && (twist.omega + 1.0).abs() < 1e-12);
+        assert_eq!(twist.vy, 0.0);
+    }
+}
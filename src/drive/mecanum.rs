@@ -0,0 +1,86 @@
+//! Kinematics of a mecanum drive.
+
+use super::{Kinematics, Twist};
+
+/// A chassis with four mecanum wheels whose angled rollers let it strafe.
+///
+/// Wheel speeds are ordered `[front_left, front_right, rear_left,
+/// rear_right]`, positive driving forward, with the rollers forming an X
+/// when viewed from above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MecanumDrive {
+    lever: f64,
+}
+
+impl MecanumDrive {
+    /// Creates a new `MecanumDrive` with `track_width` metres between the
+    /// left and right wheel centres and `wheelbase` metres between the front
+    /// and rear.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either dimension is not positive.
+    pub fn new(track_width: f64, wheelbase: f64) -> Self {
+        assert!(track_width > 0.0, "track_width should be positive");
+        assert!(wheelbase > 0.0, "wheelbase should be positive");
+        Self {
+            lever: (track_width + wheelbase) / 2.0,
+        }
+    }
+}
+
+impl Kinematics for MecanumDrive {
+    type WheelSpeeds = [f64; 4];
+
+    fn inverse(&self, twist: Twist) -> [f64; 4] {
+        let Twist { vx, vy, omega } = twist;
+        let turn = omega * self.lever;
+        [
+            vx - vy - turn,
+            vx + vy + turn,
+            vx + vy - turn,
+            vx - vy + turn,
+        ]
+    }
+
+    fn forward(&self, &[front_left, front_right, rear_left, rear_right]: &[f64; 4]) -> Twist {
+        Twist::new(
+            (front_left + front_right + rear_left + rear_right) / 4.0,
+            (-front_left + front_right + rear_left - rear_right) / 4.0,
+            (-front_left + front_right - rear_left + rear_right) / (4.0 * self.lever),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_strafe_by_driving_diagonals_apart() {
+        let drive = MecanumDrive::new(0.3, 0.2);
+        assert_eq!(
+            drive.inverse(Twist::new(0.0, 1.0, 0.0)),
+            [-1.0, 1.0, 1.0, -1.0]
+        );
+    }
+
+    #[test]
+    fn it_should_turn_by_driving_the_sides_apart() {
+        let drive = MecanumDrive::new(0.3, 0.2);
+        assert_eq!(
+            drive.inverse(Twist::new(0.0, 0.0, 4.0)),
+            [-1.0, 1.0, -1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn it_should_recover_the_twist_from_wheel_speeds() {
+        let drive = MecanumDrive::new(0.3, 0.2);
+        let twist = Twist::new(0.4, -0.2, 1.5);
+        let recovered = drive.forward(&drive.inverse(twist));
+        assert!((recovered.vx - twist.vx).abs() < 1e-12);
+        assert!((recovered.vy - twist.vy).abs() < 1e-12);
+        assert!((recovered.omega - twist.omega).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,195 @@
+//! Kinematics of an omni-wheel drive.
+
+use std::f64::consts::TAU;
+
+use super::{Kinematics, Twist};
+
+/// Mounting of one omni wheel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OmniWheel {
+    /// Direction of the wheel from the chassis centre, counterclockwise from
+    /// the X axis, in radians.
+    pub angle: f64,
+    /// Distance of the wheel from the chassis centre, in metres.
+    pub distance: f64,
+}
+
+/// A chassis with omni wheels mounted tangentially around its centre, such
+/// as a three-wheeled kiwi drive.
+///
+/// Wheel speeds are ordered as the wheels were given, positive when they
+/// would turn the chassis counterclockwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OmniDrive {
+    // Least-squares inverse of the wheel geometry, used when the wheels
+    // overdetermine the twist.
+    pseudo_inverse: [[f64; 3]; 3],
+    wheels: Vec<OmniWheel>,
+}
+
+impl OmniDrive {
+    /// Creates a new `OmniDrive` with `wheels`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the wheels cannot determine the twist, such as when there
+    /// are fewer than three or they are all mounted in line.
+    pub fn new(wheels: Vec<OmniWheel>) -> Self {
+        let mut normal = [[0.0; 3]; 3];
+
+        for wheel in &wheels {
+            let row = Self::row(wheel);
+
+            for (i, normal) in normal.iter_mut().enumerate() {
+                for (j, normal) in normal.iter_mut().enumerate() {
+                    *normal += row[i] * row[j];
+                }
+            }
+        }
+
+        let pseudo_inverse = invert(normal).expect("wheels should determine the twist");
+        Self {
+            pseudo_inverse,
+            wheels,
+        }
+    }
+
+    /// Creates a new `OmniDrive` with `count` wheels spaced evenly
+    /// `distance` metres from the centre, the first at `first_angle`
+    /// radians counterclockwise from the X axis.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is less than three.
+    pub fn regular(count: usize, distance: f64, first_angle: f64) -> Self {
+        Self::new(
+            (0..count)
+                .map(|index| OmniWheel {
+                    angle: first_angle + TAU * index as f64 / count as f64,
+                    distance,
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the wheels.
+    #[must_use]
+    pub fn wheels(&self) -> &[OmniWheel] {
+        &self.wheels
+    }
+
+    /// Returns the rim speed of `wheel` per unit of vx, vy, and omega.
+    fn row(wheel: &OmniWheel) -> [f64; 3] {
+        let (sin, cos) = wheel.angle.sin_cos();
+        [-sin, cos, wheel.distance]
+    }
+}
+
+impl Kinematics for OmniDrive {
+    type WheelSpeeds = Vec<f64>;
+
+    fn inverse(&self, twist: Twist) -> Vec<f64> {
+        self.wheels
+            .iter()
+            .map(|wheel| {
+                let [x, y, omega] = Self::row(wheel);
+                x * twist.vx + y * twist.vy + omega * twist.omega
+            })
+            .collect()
+    }
+
+    /// Returns the twist that best fits `speeds` in the least-squares sense,
+    /// which averages out slip when there are more than three wheels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is not one speed per wheel.
+    fn forward(&self, speeds: &Vec<f64>) -> Twist {
+        assert_eq!(
+            speeds.len(),
+            self.wheels.len(),
+            "there should be one speed per wheel"
+        );
+        let mut projected = [0.0; 3];
+
+        for (wheel, speed) in self.wheels.iter().zip(speeds) {
+            for (projected, coefficient) in projected.iter_mut().zip(Self::row(wheel)) {
+                *projected += coefficient * speed;
+            }
+        }
+
+        let [vx, vy, omega] = self
+            .pseudo_inverse
+            .map(|row| row.iter().zip(projected).map(|(a, b)| a * b).sum());
+        Twist::new(vx, vy, omega)
+    }
+}
+
+fn invert(m: [[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let cofactor = |r: usize, c: usize| {
+        let (r0, r1) = ((r + 1) % 3, (r + 2) % 3);
+        let (c0, c1) = ((c + 1) % 3, (c + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant: f64 = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum();
+
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+
+    // The inverse is the transposed cofactor matrix over the determinant.
+    let mut inverse = [[0.0; 3]; 3];
+
+    for (r, row) in inverse.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = cofactor(c, r) / determinant;
+        }
+    }
+
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_4;
+
+    use super::*;
+
+    fn assert_round_trip(drive: &OmniDrive, twist: Twist) {
+        let recovered = drive.forward(&drive.inverse(twist));
+        assert!((recovered.vx - twist.vx).abs() < 1e-9, "{recovered:?}");
+        assert!((recovered.vy - twist.vy).abs() < 1e-9, "{recovered:?}");
+        assert!(
+            (recovered.omega - twist.omega).abs() < 1e-9,
+            "{recovered:?}"
+        );
+    }
+
+    #[test]
+    fn it_should_spin_every_wheel_equally_to_rotate() {
+        let drive = OmniDrive::regular(3, 0.1, 0.0);
+        let speeds = drive.inverse(Twist::new(0.0, 0.0, 10.0));
+        assert!(speeds.iter().all(|speed| (speed - 1.0).abs() < 1e-12));
+    }
+
+    #[test]
+    fn it_should_drive_a_three_wheel_kiwi() {
+        let drive = OmniDrive::regular(3, 0.1, 0.0);
+        // The wheel on the X axis rolls along Y, so it stays still going
+        // forward.
+        assert!(drive.inverse(Twist::new(1.0, 0.0, 0.0))[0].abs() < 1e-12);
+        assert_round_trip(&drive, Twist::new(0.3, -0.7, 2.0));
+    }
+
+    #[test]
+    fn it_should_fit_the_twist_for_four_wheels() {
+        let drive = OmniDrive::regular(4, 0.15, FRAC_PI_4);
+        assert_round_trip(&drive, Twist::new(-0.5, 0.25, -1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "wheels should determine the twist")]
+    fn it_should_reject_too_few_wheels() {
+        OmniDrive::regular(2, 0.1, 0.0);
+    }
+}
@@ -0,0 +1,230 @@
+//! Closed-loop velocity control of a single wheel.
+
+use std::io::Error;
+use std::time::Duration;
+
+use crate::control::pid::{Gains, Pid};
+use crate::hal::{Encoder, Motor};
+
+/// A motor and encoder driven at a commanded rim speed.
+///
+/// Feedforward supplies the command a wheel usually needs for the target
+/// speed, and a PID loop on the encoder velocity trims out whatever load,
+/// battery voltage, or wear changes, so the wheel turns at the same speed
+/// throughout a run.
+#[derive(Debug)]
+pub struct WheelController<M, E> {
+    counts_per_metre: f64,
+    encoder: E,
+    kv: f64,
+    ks: f64,
+    last_count: Option<i64>,
+    motor: M,
+    pid: Pid,
+    velocity: f64,
+}
+
+impl<M: Motor, E: Encoder> WheelController<M, E> {
+    /// Creates a new `WheelController` for `motor`, whose speed is measured
+    /// by `encoder` counting `counts_per_metre` as the wheel rolls forward.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `counts_per_metre` is zero.
+    pub fn new(motor: M, encoder: E, counts_per_metre: f64, gains: Gains) -> Self {
+        assert!(
+            counts_per_metre != 0.0,
+            "counts_per_metre should not be zero"
+        );
+        Self {
+            counts_per_metre,
+            encoder,
+            kv: 0.0,
+            ks: 0.0,
+            last_count: None,
+            motor,
+            pid: Pid::new(gains).with_output_limits(-1.0, 1.0),
+            velocity: 0.0,
+        }
+    }
+
+    /// Adds feedforward of `ks` to overcome static friction plus `kv` per
+    /// metre per second, as characterized by stepping the motor command and
+    /// recording the speed reached.
+    #[must_use]
+    pub fn with_feedforward(mut self, ks: f64, kv: f64) -> Self {
+        self.ks = ks;
+        self.kv = kv;
+        self
+    }
+
+    /// Replaces the gains of the velocity loop.
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.pid.set_gains(gains);
+    }
+
+    /// Returns the commanded speed, in metres per second.
+    #[must_use]
+    pub fn target(&self) -> f64 {
+        self.pid.setpoint()
+    }
+
+    /// Sets the speed to hold, in metres per second.
+    pub fn set_target(&mut self, velocity: f64) {
+        self.pid.set_setpoint(velocity);
+    }
+
+    /// Returns the speed measured by the last update, in metres per second.
+    #[must_use]
+    pub fn velocity(&self) -> f64 {
+        self.velocity
+    }
+
+    /// Measures the speed over the `dt` since the last update, drives the
+    /// motor toward the target, and returns the measured speed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the encoder cannot be read or
+    /// the motor cannot be driven.
+    pub fn update(&mut self, dt: Duration) -> Result<f64, Error> {
+        let count = self.encoder.count()?;
+
+        if let Some(last) = self.last_count.filter(|_| !dt.is_zero()) {
+            self.velocity = (count - last) as f64 / self.counts_per_metre / dt.as_secs_f64();
+        }
+
+        self.last_count = Some(count);
+        let target = self.target();
+        let feedforward = if target == 0.0 {
+            0.0
+        } else {
+            self.ks.copysign(target) + self.kv * target
+        };
+        let command = (feedforward + self.pid.update(self.velocity, dt)).clamp(-1.0, 1.0);
+        self.motor.set_speed(command)?;
+        Ok(self.velocity)
+    }
+
+    /// Stops the motor and clears the target and loop history.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the motor cannot be driven.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.pid.set_setpoint(0.0);
+        self.pid.reset();
+        self.motor.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    const DT: Duration = Duration::from_millis(10);
+    const COUNTS_PER_METRE: f64 = 2000.0;
+
+    /// A motor whose rim speed lags its command, reaching `top_speed` at full
+    /// power, with an encoder on the same shaft.
+    #[derive(Debug, Default)]
+    struct Wheel {
+        position: f64,
+        top_speed: f64,
+        velocity: f64,
+    }
+
+    #[derive(Debug)]
+    struct WheelMotor(Rc<Cell<f64>>);
+
+    impl Motor for WheelMotor {
+        fn set_speed(&mut self, speed: f64) -> Result<(), Error> {
+            self.0.set(speed);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct WheelEncoder(Rc<Cell<i64>>);
+
+    impl Encoder for WheelEncoder {
+        fn count(&mut self) -> Result<i64, Error> {
+            Ok(self.0.get())
+        }
+    }
+
+    type Controller = WheelController<WheelMotor, WheelEncoder>;
+
+    fn wheel(gains: Gains) -> (Controller, Rc<Cell<f64>>, Rc<Cell<i64>>) {
+        let command = Rc::new(Cell::new(0.0));
+        let count = Rc::new(Cell::new(0));
+        let controller = WheelController::new(
+            WheelMotor(Rc::clone(&command)),
+            WheelEncoder(Rc::clone(&count)),
+            COUNTS_PER_METRE,
+            gains,
+        );
+        (controller, command, count)
+    }
+
+    fn run(controller: &mut Controller, wheel: &mut Wheel, command: &Cell<f64>, count: &Cell<i64>) {
+        for _ in 0..300 {
+            controller.update(DT).unwrap();
+            let dt = DT.as_secs_f64();
+            wheel.velocity += (command.get() * wheel.top_speed - wheel.velocity) * dt / 0.1;
+            wheel.position += wheel.velocity * dt;
+            count.set((wheel.position * COUNTS_PER_METRE).round() as i64);
+        }
+    }
+
+    #[test]
+    fn it_should_measure_the_speed_from_the_encoder() {
+        let (mut controller, _, count) = wheel(Gains::default());
+        controller.update(DT).unwrap();
+        count.set(50);
+        assert!((controller.update(DT).unwrap() - 2.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn it_should_hold_the_target_as_the_battery_sags() {
+        let gains = Gains::new(0.5, 5.0, 0.0);
+        let (controller, command, count) = wheel(gains);
+        let mut controller = controller.with_feedforward(0.0, 1.0);
+        let mut wheel = Wheel {
+            top_speed: 1.0,
+            ..Wheel::default()
+        };
+        controller.set_target(0.5);
+        run(&mut controller, &mut wheel, &command, &count);
+        assert!((controller.velocity() - 0.5).abs() < 0.02);
+        // Feedforward alone would now leave the wheel at 0.4 m/s.
+        wheel.top_speed = 0.8;
+        run(&mut controller, &mut wheel, &command, &count);
+        assert!((controller.velocity() - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn it_should_overcome_static_friction_in_the_direction_of_travel() {
+        let (controller, command, _) = wheel(Gains::default());
+        let mut controller = controller.with_feedforward(0.1, 1.0);
+        controller.set_target(-0.2);
+        controller.update(DT).unwrap();
+        assert!((command.get() + 0.3).abs() < 1e-12);
+        controller.set_target(0.0);
+        controller.update(DT).unwrap();
+        assert_eq!(command.get(), 0.0);
+    }
+
+    #[test]
+    fn it_should_stop_the_motor() {
+        let (mut controller, command, _) = wheel(Gains::new(1.0, 1.0, 0.0));
+        controller.set_target(1.0);
+        controller.update(DT).unwrap();
+        assert!(command.get() > 0.0);
+        controller.stop().unwrap();
+        assert_eq!((command.get(), controller.target()), (0.0, 0.0));
+    }
+}
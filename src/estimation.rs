@@ -0,0 +1,7 @@
+//! Estimates of the robot’s state and surroundings from its sensors.
+
+pub mod compass;
+pub mod ekf;
+pub mod slip;
+pub mod terrain;
+pub mod visual_odometry;
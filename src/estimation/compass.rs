@@ -0,0 +1,404 @@
+//! Heading from the magnetometer.
+//!
+//! Outdoors without a GPS fix, the Earth’s magnetic field is the only
+//! absolute reference for which way the robot is facing. A [`Compass`]
+//! levels the magnetometer reading with the accelerometer, so the heading
+//! holds on slopes, and corrects it for the local magnetic declination.
+//!
+//! The robot’s own motors are the worst source of interference: their
+//! currents make fields that grow with load and swing the heading by tens of
+//! degrees. An [`InterferenceCalibration`], run with the robot held still
+//! while the motors spin up, measures how much field each amp makes, and the
+//! compass subtracts it. Readings are rejected outright when the predicted
+//! interference is large compared to the Earth’s field, when the field is
+//! far from its expected strength, such as next to a car or a manhole cover,
+//! or when the robot is tilted too far to level the reading, as well as
+//! when the readings are invalid or stale.
+
+use std::error;
+use std::f64::consts::FRAC_PI_2;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::geometry::normalize_angle;
+use crate::sensors::measurement::{Measurement, Unusable};
+
+/// Settings of a [`Compass`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompassConfig {
+    /// Angle from true north to magnetic north, in radians, positive to the
+    /// east.
+    pub declination: f64,
+    /// Constant field of the robot itself, subtracted from every reading,
+    /// in microteslas.
+    pub hard_iron: [f64; 3],
+    /// Field made by each amp of motor current, in microteslas per amp.
+    pub interference: [f64; 3],
+    /// Strength of the Earth’s field where the robot operates, in
+    /// microteslas.
+    pub field_strength: f64,
+    /// Fraction by which the corrected field may differ from its expected
+    /// strength.
+    pub field_tolerance: f64,
+    /// Largest motor interference, as a fraction of the expected strength,
+    /// that is corrected rather than rejected.
+    pub max_interference: f64,
+    /// Largest roll or pitch at which readings are levelled, in radians.
+    pub max_tilt: f64,
+    /// Age beyond which readings are too old to give the heading.
+    pub max_age: Duration,
+}
+
+impl Default for CompassConfig {
+    fn default() -> Self {
+        Self {
+            declination: 0.0,
+            hard_iron: [0.0; 3],
+            interference: [0.0; 3],
+            field_strength: 50.0,
+            field_tolerance: 0.25,
+            max_interference: 0.15,
+            max_tilt: 45_f64.to_radians(),
+            max_age: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Measurements taken together in the robot’s frame, with X forward, Y to
+/// the left, and Z up.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompassInput {
+    /// Magnetic field, in microteslas.
+    pub magnetic_field: [f64; 3],
+    /// Acceleration including gravity, in metres per second squared, which
+    /// points up when the robot is at rest.
+    pub acceleration: [f64; 3],
+    /// Total current drawn by the motors, in amps.
+    pub motor_current: f64,
+}
+
+/// Direction the robot is facing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Heading {
+    /// Heading counterclockwise from east, in radians from -π to π, as in a
+    /// [`Pose`](crate::geometry::Pose) on a map with X to the east.
+    pub heading: f64,
+    /// Bearing clockwise from true north, in degrees from 0 to 360.
+    pub bearing: f64,
+    /// Strength of the corrected field, in microteslas.
+    pub field: f64,
+}
+
+/// Why a reading was rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rejection {
+    /// The robot was tilted too far, in radians.
+    Tilted(f64),
+    /// The motors made too much field, as a fraction of the expected
+    /// strength.
+    MotorInterference(f64),
+    /// The field was too strong or too weak, in microteslas.
+    FieldStrength(f64),
+    /// The readings were invalid or stale.
+    Unusable(Unusable),
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tilted(tilt) => write!(f, "tilted {:.0}° from level", tilt.to_degrees()),
+            Self::MotorInterference(fraction) => {
+                write!(
+                    f,
+                    "motor interference at {:.0}% of the field",
+                    fraction * 100.0
+                )
+            }
+            Self::FieldStrength(field) => write!(f, "field of {field:.1} µT is disturbed"),
+            Self::Unusable(unusable) => unusable.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Rejection {}
+
+/// Tilt-compensated compass.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Compass {
+    config: CompassConfig,
+    last: Option<Heading>,
+}
+
+impl Compass {
+    /// Creates a new `Compass`.
+    pub fn new(config: CompassConfig) -> Self {
+        Self { config, last: None }
+    }
+
+    /// Corrects readings for `declination`, in radians, positive to the
+    /// east, such as after the robot has been taken somewhere else.
+    pub fn set_declination(&mut self, declination: f64) {
+        self.config.declination = declination;
+    }
+
+    /// Returns the most recent heading that was not rejected.
+    #[must_use]
+    pub fn last(&self) -> Option<Heading> {
+        self.last
+    }
+
+    /// Returns the heading from `input` at `now`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reading cannot be trusted,
+    /// in which case [`Compass::last`] still holds the previous heading.
+    pub fn update(
+        &mut self,
+        input: &Measurement<CompassInput>,
+        now: Instant,
+    ) -> Result<Heading, Rejection> {
+        let config = &self.config;
+        let input = input
+            .check(now, config.max_age)
+            .map_err(Rejection::Unusable)?;
+        let [ax, ay, az] = input.acceleration;
+        let roll = ay.atan2(az);
+        let pitch = (-ax).atan2(ay.hypot(az));
+        let tilt = roll.abs().max(pitch.abs());
+
+        if tilt > config.max_tilt {
+            return Err(Rejection::Tilted(tilt));
+        }
+
+        let interference = config.interference.map(|k| k * input.motor_current);
+        let fraction = norm(interference) / config.field_strength;
+
+        if fraction > config.max_interference {
+            return Err(Rejection::MotorInterference(fraction));
+        }
+
+        let [mx, my, mz] = [0, 1, 2]
+            .map(|axis| input.magnetic_field[axis] - config.hard_iron[axis] - interference[axis]);
+        let field = norm([mx, my, mz]);
+
+        if (field - config.field_strength).abs() > config.field_tolerance * config.field_strength {
+            return Err(Rejection::FieldStrength(field));
+        }
+
+        // Rotate the field into the horizontal plane, where its direction
+        // is magnetic north as seen from the robot.
+        let (sin_roll, cos_roll) = roll.sin_cos();
+        let (sin_pitch, cos_pitch) = pitch.sin_cos();
+        let north_x = mx * cos_pitch + (my * sin_roll + mz * cos_roll) * sin_pitch;
+        let north_y = my * cos_roll - mz * sin_roll;
+        let bearing = north_y.atan2(north_x) + config.declination;
+
+        let heading = Heading {
+            heading: normalize_angle(FRAC_PI_2 - bearing),
+            bearing: bearing.to_degrees().rem_euclid(360.0),
+            field,
+        };
+        self.last = Some(heading);
+        Ok(heading)
+    }
+}
+
+/// Measures the field the motors make per amp, from readings taken with the
+/// robot held still while the motor current is varied.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InterferenceCalibration {
+    count: f64,
+    current: f64,
+    current_squared: f64,
+    field: [f64; 3],
+    product: [f64; 3],
+}
+
+impl InterferenceCalibration {
+    /// Creates a new `InterferenceCalibration` without readings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a reading of `magnetic_field`, in microteslas, with the motors
+    /// drawing `motor_current`, in amps.
+    pub fn push(&mut self, motor_current: f64, magnetic_field: [f64; 3]) {
+        self.count += 1.0;
+        self.current += motor_current;
+        self.current_squared += motor_current * motor_current;
+
+        for ((field, product), value) in self
+            .field
+            .iter_mut()
+            .zip(&mut self.product)
+            .zip(magnetic_field)
+        {
+            *field += value;
+            *product += motor_current * value;
+        }
+    }
+
+    /// Returns the field made by each amp, in microteslas per amp, for
+    /// [`CompassConfig::interference`], or `None` if the current did not vary
+    /// by at least an amp.
+    #[must_use]
+    pub fn coefficients(&self) -> Option<[f64; 3]> {
+        let mean = self.current / self.count;
+        let variance = self.current_squared / self.count - mean * mean;
+
+        (self.count >= 2.0 && variance >= 0.25).then(|| {
+            [0, 1, 2].map(|axis| {
+                (self.product[axis] / self.count - mean * self.field[axis] / self.count) / variance
+            })
+        })
+    }
+}
+
+fn norm(vector: [f64; 3]) -> f64 {
+    vector.iter().map(|value| value * value).sum::<f64>().sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the field seen by a level robot at `bearing` degrees from
+    /// magnetic north, with the field dipping 60° into the ground.
+    fn field(bearing: f64) -> [f64; 3] {
+        let (horizontal, down) = (25.0, 25.0 * 3_f64.sqrt());
+        let bearing = bearing.to_radians();
+        [
+            horizontal * bearing.cos(),
+            horizontal * bearing.sin(),
+            -down,
+        ]
+    }
+
+    fn update(compass: &mut Compass, input: CompassInput) -> Result<Heading, Rejection> {
+        let now = Instant::now();
+        compass.update(&Measurement::new("bno055", input, now), now)
+    }
+
+    fn level(bearing: f64) -> CompassInput {
+        CompassInput {
+            magnetic_field: field(bearing),
+            acceleration: [0.0, 0.0, 9.81],
+            motor_current: 0.0,
+        }
+    }
+
+    #[test]
+    fn it_should_give_the_heading_corrected_for_declination() {
+        let mut compass = Compass::new(CompassConfig::default());
+        let heading = update(&mut compass, level(90.0)).unwrap();
+        assert!((heading.bearing - 90.0).abs() < 1e-9);
+        assert!(heading.heading.abs() < 1e-9);
+        assert!((heading.field - 50.0).abs() < 1e-9);
+
+        compass.set_declination(10_f64.to_radians());
+        let heading = update(&mut compass, level(0.0)).unwrap();
+        assert!((heading.bearing - 10.0).abs() < 1e-9);
+        assert!((heading.heading - 80_f64.to_radians()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_should_level_readings_on_a_slope() {
+        let mut compass = Compass::new(CompassConfig::default());
+        // Pitched 20° nose up and rolled 15°, a world vector is seen turned
+        // about the robot’s Y axis and then its X axis.
+        let (sin_pitch, cos_pitch) = 20_f64.to_radians().sin_cos();
+        let (sin_roll, cos_roll) = 15_f64.to_radians().sin_cos();
+        let tilt = |[x, y, z]: [f64; 3]| {
+            let (x, z) = (cos_pitch * x + sin_pitch * z, cos_pitch * z - sin_pitch * x);
+            [x, cos_roll * y + sin_roll * z, cos_roll * z - sin_roll * y]
+        };
+        let input = CompassInput {
+            magnetic_field: tilt(field(120.0)),
+            acceleration: tilt([0.0, 0.0, 9.81]),
+            motor_current: 0.0,
+        };
+        let heading = update(&mut compass, input).unwrap();
+        assert!((heading.bearing - 120.0).abs() < 1e-9, "{heading:?}");
+    }
+
+    #[test]
+    fn it_should_correct_and_reject_motor_interference() {
+        let per_amp = [1.5, -0.5, 0.0];
+        let mut calibration = InterferenceCalibration::new();
+
+        for step in 0..=10 {
+            let current = f64::from(step);
+            let [x, y, z] = field(30.0);
+            calibration.push(
+                current,
+                [x + per_amp[0] * current, y + per_amp[1] * current, z],
+            );
+        }
+
+        let coefficients = calibration.coefficients().unwrap();
+        assert!((coefficients[0] - 1.5).abs() < 1e-9);
+        assert!((coefficients[1] + 0.5).abs() < 1e-9);
+
+        let mut compass = Compass::new(CompassConfig {
+            interference: coefficients,
+            ..CompassConfig::default()
+        });
+        let [x, y, z] = field(30.0);
+        let heading = update(
+            &mut compass,
+            CompassInput {
+                magnetic_field: [x + 3.0, y - 1.0, z],
+                motor_current: 2.0,
+                ..level(0.0)
+            },
+        )
+        .unwrap();
+        assert!((heading.bearing - 30.0).abs() < 1e-9);
+        assert!(matches!(
+            update(
+                &mut compass,
+                CompassInput {
+                    motor_current: 10.0,
+                    ..level(30.0)
+                }
+            ),
+            Err(Rejection::MotorInterference(_))
+        ));
+        assert_eq!(compass.last(), Some(heading));
+    }
+
+    #[test]
+    fn it_should_reject_disturbed_fields_and_steep_tilts() {
+        let mut compass = Compass::new(CompassConfig::default());
+        let [x, y, z] = field(0.0);
+        assert!(matches!(
+            update(
+                &mut compass,
+                CompassInput {
+                    magnetic_field: [x * 2.0, y * 2.0, z * 2.0],
+                    ..level(0.0)
+                }
+            ),
+            Err(Rejection::FieldStrength(_))
+        ));
+        assert!(matches!(
+            update(
+                &mut compass,
+                CompassInput {
+                    acceleration: [9.81, 0.0, 0.0],
+                    ..level(0.0)
+                }
+            ),
+            Err(Rejection::Tilted(_))
+        ));
+        let taken = Instant::now();
+        let stale = Measurement::new("bno055", level(0.0), taken);
+        assert!(matches!(
+            compass.update(&stale, taken + Duration::from_secs(1)),
+            Err(Rejection::Unusable(Unusable::Stale { .. }))
+        ));
+        assert_eq!(compass.last(), None);
+        assert_eq!(InterferenceCalibration::new().coefficients(), None);
+    }
+}
@@ -0,0 +1,419 @@
+//! Pose estimation by extended Kalman filter.
+//!
+//! Odometry from the wheels and IMU is smooth but drifts without bound;
+//! absolute measurements such as GPS fixes and compass headings do not
+//! drift but jump around. The [`Ekf`] predicts the pose from odometry and
+//! corrects it with each absolute measurement in proportion to how much it
+//! is trusted, tracking a covariance for `x`, `y`, and heading.
+//!
+//! In [`Mode::Outdoor`], GPS fixes are fused in a local east-north-up frame
+//! centred on the first fix, or on a configured origin, so the pose’s X
+//! points east and its heading matches the compass’s. Each fix is trusted
+//! according to its HDOP, and fixes too far from the prediction to be
+//! plausible, such as multipath reflections off a building, are rejected
+//! until enough of them agree that the filter must have been wrong instead.
+//! Fixes that are invalid or too old to describe where the robot is now are
+//! ignored.
+
+use std::time::{Duration, Instant};
+
+use crate::drive::Twist;
+use crate::geometry::geodesy::{GeoPoint, LocalFrame};
+use crate::geometry::{normalize_angle, Pose};
+use crate::log_event;
+use crate::logging::Level;
+use crate::sensors::gps::{FixQuality, GpsFix};
+use crate::sensors::measurement::Measurement;
+
+const SUBSYSTEM: &str = "ekf";
+
+type Matrix = [[f64; 3]; 3];
+
+/// Where the robot is localized.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Mode {
+    /// Indoors, in the frame of the map, where GPS fixes are ignored.
+    #[default]
+    Indoor,
+    /// Outdoors, in a local east-north-up frame, fusing GPS fixes.
+    Outdoor,
+}
+
+/// Noise and gating settings of an [`Ekf`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EkfConfig {
+    /// Standard deviation of odometry’s error per metre travelled.
+    pub translation_noise: f64,
+    /// Standard deviation of odometry’s error per radian turned.
+    pub rotation_noise: f64,
+    /// Standard deviation of heading drift per second, in radians, such as
+    /// from gyroscope bias.
+    pub heading_drift: f64,
+    /// Standard deviation of a GPS fix’s horizontal error at an HDOP of 1,
+    /// in metres.
+    pub gps_error: f64,
+    /// Largest HDOP at which fixes are fused.
+    pub max_hdop: f64,
+    /// Age beyond which a fix is too old to fuse.
+    pub max_fix_age: Duration,
+    /// Distance, in standard deviations, beyond which a fix is rejected.
+    pub gate: f64,
+    /// Number of fixes rejected in a row after which the next is accepted
+    /// regardless.
+    pub max_rejections: u32,
+}
+
+impl Default for EkfConfig {
+    fn default() -> Self {
+        Self {
+            translation_noise: 0.05,
+            rotation_noise: 0.05,
+            heading_drift: 0.005,
+            gps_error: 2.5,
+            max_hdop: 5.0,
+            max_fix_age: Duration::from_secs(1),
+            gate: 3.0,
+            max_rejections: 5,
+        }
+    }
+}
+
+/// Pose estimator fusing odometry with absolute measurements.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ekf {
+    config: EkfConfig,
+    covariance: Matrix,
+    frame: Option<LocalFrame>,
+    mode: Mode,
+    pose: Pose,
+    rejections: u32,
+}
+
+impl Ekf {
+    /// Creates a new `Ekf` indoors, certain to be at `pose`.
+    pub fn new(config: EkfConfig, pose: Pose) -> Self {
+        Self {
+            config,
+            covariance: [[0.0; 3]; 3],
+            frame: None,
+            mode: Mode::Indoor,
+            pose,
+            rejections: 0,
+        }
+    }
+
+    /// Localizes in `mode`.
+    #[must_use]
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Centres the outdoor frame on `origin` rather than on the first fix.
+    #[must_use]
+    pub fn with_origin(mut self, origin: GeoPoint) -> Self {
+        self.frame = Some(LocalFrame::new(origin));
+        self
+    }
+
+    /// Returns the mode.
+    #[must_use]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns the estimated pose.
+    #[must_use]
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    /// Returns the covariance of `x`, `y`, and heading.
+    #[must_use]
+    pub fn covariance(&self) -> Matrix {
+        self.covariance
+    }
+
+    /// Returns the outdoor frame, once it has an origin.
+    #[must_use]
+    pub fn frame(&self) -> Option<LocalFrame> {
+        self.frame
+    }
+
+    /// Returns the estimated position on the Earth, outdoors once the frame
+    /// has an origin.
+    #[must_use]
+    pub fn geo_position(&self) -> Option<GeoPoint> {
+        (self.mode == Mode::Outdoor)
+            .then_some(self.frame?)
+            .map(|frame| frame.to_geo(self.pose.x, self.pose.y))
+    }
+
+    /// Moves the estimate by `twist` held for `dt`, growing its
+    /// uncertainty by how far the robot moved and turned.
+    pub fn predict(&mut self, twist: &Twist, dt: Duration) {
+        let seconds = dt.as_secs_f64();
+        let (sin, cos) = self.pose.heading.sin_cos();
+        let forward = twist.vx * seconds;
+        let left = twist.vy * seconds;
+        let turn = twist.omega * seconds;
+
+        self.pose.x += forward * cos - left * sin;
+        self.pose.y += forward * sin + left * cos;
+        self.pose.heading = normalize_angle(self.pose.heading + turn);
+
+        let jacobian = [
+            [1.0, 0.0, -forward * sin - left * cos],
+            [0.0, 1.0, forward * cos - left * sin],
+            [0.0, 0.0, 1.0],
+        ];
+        let translation = (self.config.translation_noise * forward.hypot(left)).powi(2);
+        let rotation = (self.config.rotation_noise * turn).powi(2)
+            + self.config.heading_drift.powi(2) * seconds;
+        let mut covariance = multiply(
+            &multiply(&jacobian, &self.covariance),
+            &transpose(&jacobian),
+        );
+        covariance[0][0] += translation;
+        covariance[1][1] += translation;
+        covariance[2][2] += rotation;
+        self.covariance = covariance;
+    }
+
+    /// Corrects the estimate with a heading, in radians, measured with
+    /// standard deviation `std_dev`, such as from the compass outdoors.
+    pub fn update_heading(&mut self, heading: f64, std_dev: f64) {
+        let innovation = normalize_angle(heading - self.pose.heading);
+        let gain =
+            self.covariance[2].map(|value| value / (self.covariance[2][2] + std_dev.powi(2)));
+
+        self.pose.x += gain[0] * innovation;
+        self.pose.y += gain[1] * innovation;
+        self.pose.heading = normalize_angle(self.pose.heading + gain[2] * innovation);
+
+        let row = self.covariance[2];
+        for (i, gain) in gain.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                self.covariance[i][j] -= gain * value;
+            }
+        }
+    }
+
+    /// Corrects the estimate with a position measured with standard
+    /// deviation `std_dev`, returning `false` if it was rejected as
+    /// implausible.
+    pub fn update_position(&mut self, x: f64, y: f64, std_dev: f64) -> bool {
+        let variance = std_dev.powi(2);
+        let p = &self.covariance;
+        let innovation = [x - self.pose.x, y - self.pose.y];
+        let s = [[p[0][0] + variance, p[0][1]], [p[1][0], p[1][1] + variance]];
+        let determinant = s[0][0] * s[1][1] - s[0][1] * s[1][0];
+
+        if determinant <= 0.0 {
+            return false;
+        }
+
+        let inverse = [
+            [s[1][1] / determinant, -s[0][1] / determinant],
+            [-s[1][0] / determinant, s[0][0] / determinant],
+        ];
+        let distance = innovation[0]
+            * (inverse[0][0] * innovation[0] + inverse[0][1] * innovation[1])
+            + innovation[1] * (inverse[1][0] * innovation[0] + inverse[1][1] * innovation[1]);
+
+        if distance > self.config.gate.powi(2) && self.rejections < self.config.max_rejections {
+            self.rejections += 1;
+            return false;
+        }
+
+        self.rejections = 0;
+
+        // Gain is P Hᵀ S⁻¹, with H selecting x and y.
+        let gain: [[f64; 2]; 3] = std::array::from_fn(|i| {
+            [
+                p[i][0] * inverse[0][0] + p[i][1] * inverse[1][0],
+                p[i][0] * inverse[0][1] + p[i][1] * inverse[1][1],
+            ]
+        });
+
+        self.pose.x += gain[0][0] * innovation[0] + gain[0][1] * innovation[1];
+        self.pose.y += gain[1][0] * innovation[0] + gain[1][1] * innovation[1];
+        self.pose.heading = normalize_angle(
+            self.pose.heading + gain[2][0] * innovation[0] + gain[2][1] * innovation[1],
+        );
+        self.covariance = std::array::from_fn(|i| {
+            std::array::from_fn(|j| p[i][j] - gain[i][0] * p[0][j] - gain[i][1] * p[1][j])
+        });
+        true
+    }
+
+    /// Corrects the estimate with a GPS fix, trusting it less the higher
+    /// its HDOP, and returns `true` if it was fused.
+    ///
+    /// The first fix fused without a configured origin becomes the origin
+    /// of the outdoor frame, and places the robot there. Fixes are ignored
+    /// indoors, when their HDOP is too high, and when they are invalid or
+    /// stale at `now`.
+    pub fn update_gps(&mut self, fix: &Measurement<GpsFix>, now: Instant) -> bool {
+        if self.mode != Mode::Outdoor {
+            return false;
+        }
+
+        let fix = match fix.check(now, self.config.max_fix_age) {
+            Ok(fix) if fix.hdop <= self.config.max_hdop && fix.position.is_valid() => fix,
+            Ok(_) => return false,
+            Err(unusable) => {
+                log_event!(SUBSYSTEM, Level::Debug, "ignored fix: {unusable}");
+                return false;
+            }
+        };
+
+        let std_dev = fix.hdop.max(0.5)
+            * match fix.quality {
+                FixQuality::Rtk => 0.02,
+                FixQuality::FloatRtk => 0.2,
+                FixQuality::Differential => self.config.gps_error / 2.0,
+                FixQuality::Gps | FixQuality::Estimated => self.config.gps_error,
+            };
+
+        let Some(frame) = self.frame else {
+            log_event!(
+                SUBSYSTEM,
+                Level::Info,
+                "outdoor origin at {:.7}, {:.7}",
+                fix.position.latitude,
+                fix.position.longitude
+            );
+            self.frame = Some(LocalFrame::new(fix.position));
+            self.pose.x = 0.0;
+            self.pose.y = 0.0;
+            self.covariance[0] = [std_dev.powi(2), 0.0, 0.0];
+            self.covariance[1] = [0.0, std_dev.powi(2), 0.0];
+            self.covariance[2][0] = 0.0;
+            self.covariance[2][1] = 0.0;
+            return true;
+        };
+
+        let (east, north) = frame.to_local(fix.position);
+        let fused = self.update_position(east, north, std_dev);
+
+        if !fused {
+            log_event!(
+                SUBSYSTEM,
+                Level::Debug,
+                "rejected fix {:.1} m away",
+                (east - self.pose.x).hypot(north - self.pose.y)
+            );
+        }
+
+        fused
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|i| std::array::from_fn(|j| (0..3).map(|k| a[i][k] * b[k][j]).sum()))
+}
+
+fn transpose(a: &Matrix) -> Matrix {
+    std::array::from_fn(|i| std::array::from_fn(|j| a[j][i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fuse(ekf: &mut Ekf, fix: GpsFix) -> bool {
+        let now = Instant::now();
+        ekf.update_gps(&Measurement::new("gps", fix, now), now)
+    }
+
+    fn fix(frame: &LocalFrame, east: f64, north: f64, hdop: f64) -> GpsFix {
+        GpsFix {
+            position: frame.to_geo(east, north),
+            altitude: 0.0,
+            hdop,
+            satellites: 9,
+            quality: FixQuality::Gps,
+        }
+    }
+
+    #[test]
+    fn it_should_dead_reckon_with_growing_uncertainty() {
+        let mut ekf = Ekf::new(EkfConfig::default(), Pose::default());
+        ekf.predict(&Twist::new(1.0, 0.0, 0.0), Duration::from_secs(1));
+        ekf.predict(&Twist::new(0.0, 0.0, 1.0), Duration::from_secs(1));
+        ekf.predict(&Twist::new(1.0, 0.0, 0.0), Duration::from_secs(1));
+        let pose = ekf.pose();
+        assert!((pose.x - 1.0 - 1_f64.cos()).abs() < 1e-9);
+        assert!((pose.y - 1_f64.sin()).abs() < 1e-9);
+        assert!(ekf.covariance()[0][0] > 0.0 && ekf.covariance()[2][2] > 0.0);
+    }
+
+    #[test]
+    fn it_should_ignore_gps_indoors() {
+        let frame = LocalFrame::new(GeoPoint::new(40.0, -74.0));
+        let mut ekf = Ekf::new(EkfConfig::default(), Pose::default());
+        assert!(!fuse(&mut ekf, fix(&frame, 0.0, 0.0, 1.0)));
+        assert_eq!(ekf.geo_position(), None);
+    }
+
+    #[test]
+    fn it_should_weigh_fixes_by_hdop() {
+        let origin = GeoPoint::new(40.0, -74.0);
+        let frame = LocalFrame::new(origin);
+        let start = |hdop| {
+            let mut ekf = Ekf::new(EkfConfig::default(), Pose::default())
+                .with_mode(Mode::Outdoor)
+                .with_origin(origin);
+            ekf.predict(&Twist::new(1.0, 0.0, 0.0), Duration::from_secs(10));
+            assert!(fuse(&mut ekf, fix(&frame, 11.0, 0.0, hdop)));
+            ekf.pose().x
+        };
+        // Odometry says 10 m east and the fix 11 m: a precise fix pulls the
+        // estimate further than a vague one.
+        let (precise, vague) = (start(0.8), start(3.0));
+        assert!(
+            precise > vague && vague > 10.0 && precise < 11.0,
+            "{precise} {vague}"
+        );
+    }
+
+    #[test]
+    fn it_should_reject_outlying_fixes_until_they_persist() {
+        let mut ekf = Ekf::new(EkfConfig::default(), Pose::default()).with_mode(Mode::Outdoor);
+        let origin = GeoPoint::new(51.5, -0.12);
+        let frame = LocalFrame::new(origin);
+        assert!(fuse(
+            &mut ekf,
+            GpsFix {
+                position: origin,
+                ..fix(&frame, 0.0, 0.0, 1.0)
+            }
+        ));
+        assert_eq!(ekf.frame(), Some(frame));
+        assert!(fuse(&mut ekf, fix(&frame, 1.0, 0.0, 1.0)));
+        assert!(!fuse(&mut ekf, fix(&frame, 60.0, 0.0, 9.0)));
+        let taken = Instant::now();
+        let stale = Measurement::new("gps", fix(&frame, 60.0, 0.0, 1.0), taken);
+        assert!(!ekf.update_gps(&stale, taken + Duration::from_secs(2)));
+
+        let rejected = (0..EkfConfig::default().max_rejections)
+            .filter(|_| !fuse(&mut ekf, fix(&frame, 60.0, 0.0, 1.0)))
+            .count();
+        assert_eq!(rejected, 5);
+        assert!(fuse(&mut ekf, fix(&frame, 60.0, 0.0, 1.0)));
+        assert!(ekf.pose().x > 1.0);
+        let position = ekf.geo_position().unwrap();
+        assert!(position.longitude > origin.longitude);
+    }
+
+    #[test]
+    fn it_should_correct_the_heading() {
+        let mut ekf = Ekf::new(EkfConfig::default(), Pose::default());
+        ekf.predict(&Twist::new(0.0, 0.0, 0.0), Duration::from_secs(1000));
+        ekf.update_heading(3.0, 0.01);
+        assert!((ekf.pose().heading - 3.0).abs() < 0.02);
+        ekf.update_heading(-3.0, 0.01);
+        assert!(ekf.pose().heading.abs() > 3.0);
+    }
+}
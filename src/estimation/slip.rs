@@ -0,0 +1,294 @@
+//! Detection of wheel slip, stalls, and the robot being lifted.
+//!
+//! Odometry assumes the wheels roll without slipping, and quietly drifts
+//! when they don’t. Comparing the wheels against their commands and against
+//! the IMU tells when that assumption has broken, so navigation can pause and
+//! odometry can stop integrating until traction returns.
+
+use std::time::Duration;
+
+use crate::events::{Event, EventBus, TractionChanged};
+use crate::log_event;
+use crate::logging::Level;
+
+pub use crate::events::Traction;
+
+const SUBSYSTEM: &str = "slip";
+
+/// Thresholds of a [`SlipDetector`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SlipConfig {
+    /// Disagreement between the velocity change seen by the wheels and by
+    /// the IMU beyond which the wheels are taken to be slipping, in metres
+    /// per second.
+    pub velocity_tolerance: f64,
+    /// Time over which old disagreement is forgotten, so that IMU bias does
+    /// not build up into a false slip.
+    pub disagreement_decay: Duration,
+    /// Fraction of its commanded speed below which a wheel is stalled.
+    pub stall_fraction: f64,
+    /// Commanded speed below which a wheel is never considered stalled, in
+    /// metres per second.
+    pub min_speed: f64,
+    /// Vertical acceleration, excluding gravity, beyond which the robot is
+    /// taken to have been lifted, in metres per second squared.
+    pub lift_acceleration: f64,
+    /// Time a condition must persist before the traction changes.
+    pub persistence: Duration,
+}
+
+impl Default for SlipConfig {
+    fn default() -> Self {
+        Self {
+            velocity_tolerance: 0.15,
+            disagreement_decay: Duration::from_secs(1),
+            stall_fraction: 0.2,
+            min_speed: 0.05,
+            lift_acceleration: 4.0,
+            persistence: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Measurements taken each control cycle.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SlipInput<'a> {
+    /// Commanded wheel speeds, in metres per second.
+    pub commanded: &'a [f64],
+    /// Wheel speeds measured by the encoders, in the same order, in metres
+    /// per second.
+    pub measured: &'a [f64],
+    /// Forward acceleration from the IMU, in metres per second squared.
+    pub forward_acceleration: f64,
+    /// Upward acceleration from the IMU, excluding gravity, in metres per
+    /// second squared.
+    pub vertical_acceleration: f64,
+}
+
+/// Classifies traction from wheel and IMU measurements, publishing a
+/// [`TractionChanged`] event whenever it changes.
+///
+/// Wheel speeds are averaged into a forward speed, so the comparison with
+/// the IMU suits differential and Ackermann drives driven mostly straight.
+/// Once lifted, the detector stays in [`Traction::Lifted`] until cleared,
+/// since a robot held still in the air looks the same to the IMU as one on
+/// the ground.
+#[derive(Clone, Debug, Default)]
+pub struct SlipDetector {
+    bus: Option<EventBus>,
+    candidate: Traction,
+    candidate_for: Duration,
+    config: SlipConfig,
+    disagreement: f64,
+    last_speed: Option<f64>,
+    traction: Traction,
+}
+
+impl SlipDetector {
+    /// Creates a new `SlipDetector`, starting out gripping.
+    pub fn new(config: SlipConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Publishes changes in traction to `bus`.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Returns the current traction.
+    #[must_use]
+    pub fn traction(&self) -> Traction {
+        self.traction
+    }
+
+    /// Returns `true` if odometry can be trusted to integrate the wheels.
+    #[must_use]
+    pub fn odometry_trusted(&self) -> bool {
+        self.traction == Traction::Gripping
+    }
+
+    /// Returns to gripping, such as once a lifted robot has been set down.
+    pub fn clear(&mut self) {
+        self.change(Traction::Gripping);
+        self.candidate = Traction::Gripping;
+        self.candidate_for = Duration::ZERO;
+        self.disagreement = 0.0;
+        self.last_speed = None;
+    }
+
+    /// Classifies the measurements taken `dt` after the previous ones and
+    /// returns the traction.
+    pub fn update(&mut self, input: &SlipInput<'_>, dt: Duration) -> Traction {
+        let seconds = dt.as_secs_f64();
+        let speed = if input.measured.is_empty() {
+            0.0
+        } else {
+            input.measured.iter().sum::<f64>() / input.measured.len() as f64
+        };
+        let wheel_change = self.last_speed.map_or(0.0, |last| speed - last);
+        self.last_speed = Some(speed);
+        let decay = (1.0 - seconds / self.config.disagreement_decay.as_secs_f64()).max(0.0);
+        self.disagreement =
+            self.disagreement * decay + wheel_change - input.forward_acceleration * seconds;
+
+        if self.traction == Traction::Lifted {
+            return self.traction;
+        }
+
+        let stalled = input
+            .commanded
+            .iter()
+            .zip(input.measured)
+            .any(|(&commanded, &measured)| {
+                commanded.abs() >= self.config.min_speed
+                    && measured * commanded.signum() < self.config.stall_fraction * commanded.abs()
+            });
+        let candidate = if input.vertical_acceleration.abs() > self.config.lift_acceleration {
+            Traction::Lifted
+        } else if stalled {
+            Traction::Stalled
+        } else if self.disagreement.abs() > self.config.velocity_tolerance {
+            Traction::Slipping
+        } else {
+            Traction::Gripping
+        };
+
+        if candidate == self.candidate {
+            self.candidate_for += dt;
+        } else {
+            self.candidate = candidate;
+            self.candidate_for = dt;
+        }
+
+        if self.candidate_for >= self.config.persistence {
+            self.change(candidate);
+        }
+
+        self.traction
+    }
+
+    fn change(&mut self, traction: Traction) {
+        if traction == self.traction {
+            return;
+        }
+
+        let previous = self.traction;
+        self.traction = traction;
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "traction changed from {previous:?} to {traction:?}"
+        );
+
+        if let Some(bus) = &self.bus {
+            bus.publish(Event::TractionChanged(TractionChanged {
+                previous,
+                traction,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+
+    const DT: Duration = Duration::from_millis(10);
+
+    fn run(detector: &mut SlipDetector, input: &SlipInput<'_>, cycles: usize) -> Traction {
+        (0..cycles).fold(Traction::Gripping, |_, _| detector.update(input, DT))
+    }
+
+    #[test]
+    fn it_should_grip_while_the_wheels_match_the_imu() {
+        let mut detector = SlipDetector::new(SlipConfig::default());
+
+        for step in 0..100 {
+            let speed = f64::from(step) * 0.01;
+            let input = SlipInput {
+                commanded: &[speed, speed],
+                measured: &[speed, speed],
+                forward_acceleration: 1.0,
+                ..SlipInput::default()
+            };
+            assert_eq!(detector.update(&input, DT), Traction::Gripping);
+        }
+    }
+
+    #[test]
+    fn it_should_detect_wheels_spinning_without_acceleration() {
+        let mut detector = SlipDetector::new(SlipConfig::default());
+        let mut traction = Traction::Gripping;
+
+        for step in 0..50 {
+            let speed = f64::from(step) * 0.02;
+            let input = SlipInput {
+                commanded: &[speed, speed],
+                measured: &[speed, speed],
+                ..SlipInput::default()
+            };
+            traction = detector.update(&input, DT);
+        }
+
+        assert_eq!(traction, Traction::Slipping);
+        assert!(!detector.odometry_trusted());
+    }
+
+    #[test]
+    fn it_should_detect_a_stalled_wheel() {
+        let mut detector = SlipDetector::new(SlipConfig::default());
+        let input = SlipInput {
+            commanded: &[0.3, 0.3],
+            measured: &[0.3, 0.01],
+            ..SlipInput::default()
+        };
+        assert_eq!(run(&mut detector, &input, 5), Traction::Gripping);
+        assert_eq!(run(&mut detector, &input, 10), Traction::Stalled);
+    }
+
+    #[test]
+    fn it_should_stay_lifted_until_cleared() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe_to(&[EventKind::TractionChanged], 4);
+        let mut detector = SlipDetector::new(SlipConfig::default()).with_bus(bus);
+        let lifting = SlipInput {
+            vertical_acceleration: 6.0,
+            ..SlipInput::default()
+        };
+        assert_eq!(run(&mut detector, &lifting, 10), Traction::Lifted);
+        assert_eq!(
+            run(&mut detector, &SlipInput::default(), 20),
+            Traction::Lifted
+        );
+        detector.clear();
+        assert_eq!(detector.traction(), Traction::Gripping);
+        assert_eq!(
+            subscription.try_recv(),
+            Some(Event::TractionChanged(TractionChanged {
+                previous: Traction::Gripping,
+                traction: Traction::Lifted,
+            }))
+        );
+        assert!(subscription.try_recv().is_some());
+    }
+
+    #[test]
+    fn it_should_ignore_brief_bumps() {
+        let mut detector = SlipDetector::new(SlipConfig::default());
+        let bump = SlipInput {
+            vertical_acceleration: 8.0,
+            ..SlipInput::default()
+        };
+        detector.update(&bump, DT);
+        assert_eq!(
+            run(&mut detector, &SlipInput::default(), 20),
+            Traction::Gripping
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Classifying the floor from the vibration it sends through the chassis.
+//!
+//! Each surface shakes the robot in its own way: tile knocks at every grout
+//! line, hardwood buzzes faintly, and carpet damps most of the high
+//! frequencies. A window of vertical acceleration is reduced to a
+//! [`Signature`] of its overall level and how its energy spreads across
+//! frequency bands, and matched against signatures recorded on known
+//! surfaces. Vibration depends on speed as well as surface, so train at the
+//! speed the robot usually cruises.
+
+use std::f64::consts::TAU;
+
+/// Number of frequency bands in a signature.
+const BANDS: usize = 4;
+
+/// Upper edges of the frequency bands, as fractions of the Nyquist
+/// frequency.
+const BAND_EDGES: [f64; BANDS] = [0.0625, 0.125, 0.25, 1.0];
+
+/// A kind of floor.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Surface {
+    /// Wooden or laminate flooring.
+    Hardwood,
+    /// Carpet or rugs.
+    Carpet,
+    /// Tiles with grout lines.
+    Tile,
+}
+
+impl Surface {
+    /// Returns the default driving adjustments for the surface.
+    #[must_use]
+    pub fn default_profile(self) -> SurfaceProfile {
+        match self {
+            Self::Hardwood => SurfaceProfile {
+                speed_scale: 1.0,
+                slip_factor: 0.99,
+            },
+            Self::Carpet => SurfaceProfile {
+                speed_scale: 0.8,
+                slip_factor: 0.95,
+            },
+            Self::Tile => SurfaceProfile {
+                speed_scale: 0.9,
+                slip_factor: 0.98,
+            },
+        }
+    }
+}
+
+/// Driving adjustments for a surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceProfile {
+    /// Fraction of the normal speed limit to drive at.
+    pub speed_scale: f64,
+    /// Distance actually travelled per metre the wheels report, by which
+    /// odometry scales its displacement.
+    pub slip_factor: f64,
+}
+
+impl Default for SurfaceProfile {
+    fn default() -> Self {
+        Self {
+            speed_scale: 1.0,
+            slip_factor: 1.0,
+        }
+    }
+}
+
+/// Features of a window of vibration.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Signature {
+    /// Natural log of the root-mean-square vibration, in metres per second
+    /// squared.
+    pub log_rms: f64,
+    /// Fraction of the vibration energy in each frequency band, from lowest
+    /// to highest.
+    pub bands: [f64; BANDS],
+}
+
+impl Signature {
+    /// Computes the signature of `samples` of vertical acceleration.
+    #[must_use]
+    pub fn from_samples(samples: &[f64]) -> Self {
+        let count = samples.len();
+
+        if count < 2 {
+            return Self::default();
+        }
+
+        let mean = samples.iter().sum::<f64>() / count as f64;
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / count as f64;
+        let mut bands = [0.0; BANDS];
+        let nyquist_bin = count / 2;
+
+        // A direct DFT is quick enough for the few hundred samples in a
+        // window, and only runs once per window.
+        for bin in 1..=nyquist_bin {
+            let (mut re, mut im) = (0.0, 0.0);
+
+            for (index, sample) in samples.iter().enumerate() {
+                let phase = TAU * (bin * index) as f64 / count as f64;
+                re += (sample - mean) * phase.cos();
+                im -= (sample - mean) * phase.sin();
+            }
+
+            let fraction = bin as f64 / nyquist_bin as f64;
+            let band = BAND_EDGES
+                .iter()
+                .position(|&edge| fraction <= edge)
+                .unwrap_or(BANDS - 1);
+            bands[band] += re * re + im * im;
+        }
+
+        let total: f64 = bands.iter().sum();
+
+        if total > 0.0 {
+            bands.iter_mut().for_each(|energy| *energy /= total);
+        }
+
+        Self {
+            log_rms: variance.sqrt().max(1e-6).ln(),
+            bands,
+        }
+    }
+
+    /// Returns how different this signature is from `other`.
+    #[must_use]
+    pub fn distance(&self, other: &Self) -> f64 {
+        let bands: f64 = self
+            .bands
+            .iter()
+            .zip(other.bands)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum();
+        ((self.log_rms - other.log_rms).powi(2) + bands).sqrt()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Centroid {
+    signature: Signature,
+    surface: Surface,
+    windows: usize,
+}
+
+/// Classifies the surface from windows of vertical acceleration.
+#[derive(Clone, Debug)]
+pub struct TerrainClassifier {
+    centroids: Vec<Centroid>,
+    max_distance: f64,
+    profiles: Vec<(Surface, SurfaceProfile)>,
+    surface: Option<Surface>,
+    window: Vec<f64>,
+    window_len: usize,
+}
+
+impl TerrainClassifier {
+    /// Creates a new, untrained `TerrainClassifier` that classifies every
+    /// `window_len` samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_len` is less than 16.
+    pub fn new(window_len: usize) -> Self {
+        assert!(window_len >= 16, "window_len should be at least 16");
+        Self {
+            centroids: Vec::new(),
+            max_distance: 1.0,
+            profiles: Vec::new(),
+            surface: None,
+            window: Vec::with_capacity(window_len),
+            window_len,
+        }
+    }
+
+    /// Sets how far a signature may be from the nearest trained surface
+    /// before it is left unclassified.
+    #[must_use]
+    pub fn with_max_distance(mut self, max_distance: f64) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    /// Overrides the driving adjustments for `surface`.
+    #[must_use]
+    pub fn with_profile(mut self, surface: Surface, profile: SurfaceProfile) -> Self {
+        self.profiles.retain(|&(existing, _)| existing != surface);
+        self.profiles.push((surface, profile));
+        self
+    }
+
+    /// Folds `signature`, recorded while driving on `surface`, into what the
+    /// classifier knows of that surface.
+    pub fn train(&mut self, surface: Surface, signature: &Signature) {
+        let Some(centroid) = self
+            .centroids
+            .iter_mut()
+            .find(|centroid| centroid.surface == surface)
+        else {
+            self.centroids.push(Centroid {
+                signature: *signature,
+                surface,
+                windows: 1,
+            });
+            return;
+        };
+
+        centroid.windows += 1;
+        let weight = 1.0 / centroid.windows as f64;
+        let mean = &mut centroid.signature;
+        mean.log_rms += (signature.log_rms - mean.log_rms) * weight;
+
+        for (mean, band) in mean.bands.iter_mut().zip(signature.bands) {
+            *mean += (band - *mean) * weight;
+        }
+    }
+
+    /// Returns the trained surface nearest to `signature`, if it is within
+    /// the maximum distance.
+    #[must_use]
+    pub fn classify(&self, signature: &Signature) -> Option<Surface> {
+        self.centroids
+            .iter()
+            .map(|centroid| (centroid.signature.distance(signature), centroid.surface))
+            .filter(|&(distance, _)| distance <= self.max_distance)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, surface)| surface)
+    }
+
+    /// Adds a sample of vertical acceleration, in metres per second squared,
+    /// and returns the signature of the window it completes, if any, having
+    /// reclassified the surface from it.
+    pub fn push(&mut self, acceleration: f64) -> Option<Signature> {
+        self.window.push(acceleration);
+
+        if self.window.len() < self.window_len {
+            return None;
+        }
+
+        let signature = Signature::from_samples(&self.window);
+        self.window.clear();
+        self.surface = self.classify(&signature);
+        Some(signature)
+    }
+
+    /// Returns the surface classified from the last complete window.
+    #[must_use]
+    pub fn surface(&self) -> Option<Surface> {
+        self.surface
+    }
+
+    /// Returns the driving adjustments for the current surface, or no
+    /// adjustment if it is unknown.
+    #[must_use]
+    pub fn profile(&self) -> SurfaceProfile {
+        self.surface
+            .map_or_else(SurfaceProfile::default, |surface| {
+                self.profiles
+                    .iter()
+                    .find(|&&(existing, _)| existing == surface)
+                    .map_or_else(|| surface.default_profile(), |&(_, profile)| profile)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: usize = 256;
+    const SAMPLE_RATE: f64 = 200.0;
+
+    /// Deterministic noise from -1 to 1.
+    fn noise(seed: u64) -> impl Iterator<Item = f64> {
+        let mut state = seed;
+        std::iter::repeat_with(move || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 11) as f64 / (1_u64 << 52) as f64 - 1.0
+        })
+    }
+
+    fn hardwood(seed: u64) -> Vec<f64> {
+        noise(seed).take(WINDOW).map(|n| 0.2 * n).collect()
+    }
+
+    fn carpet(seed: u64) -> Vec<f64> {
+        let mut smoothed = 0.0;
+        noise(seed)
+            .take(WINDOW)
+            .map(|n| {
+                smoothed += (n - smoothed) * 0.1;
+                0.5 * smoothed
+            })
+            .collect()
+    }
+
+    fn tile(seed: u64) -> Vec<f64> {
+        noise(seed)
+            .take(WINDOW)
+            .enumerate()
+            .map(|(index, n)| {
+                let knock = if index % 25 == 0 { 3.0 } else { 0.0 };
+                knock + 0.2 * n
+            })
+            .collect()
+    }
+
+    fn trained() -> TerrainClassifier {
+        let mut classifier = TerrainClassifier::new(WINDOW);
+
+        for seed in 1..4 {
+            classifier.train(Surface::Hardwood, &Signature::from_samples(&hardwood(seed)));
+            classifier.train(Surface::Carpet, &Signature::from_samples(&carpet(seed)));
+            classifier.train(Surface::Tile, &Signature::from_samples(&tile(seed)));
+        }
+
+        classifier
+    }
+
+    #[test]
+    fn it_should_put_the_energy_of_a_tone_in_its_band() {
+        let samples: Vec<_> = (0..WINDOW)
+            .map(|index| (TAU * 60.0 * index as f64 / SAMPLE_RATE).sin())
+            .collect();
+        let signature = Signature::from_samples(&samples);
+        assert!(signature.bands[3] > 0.99, "{signature:?}");
+        assert!((signature.log_rms - 0.5_f64.sqrt().ln()).abs() < 0.01);
+    }
+
+    #[test]
+    fn it_should_classify_surfaces_it_was_trained_on() {
+        let classifier = trained();
+        let classify = |samples: Vec<f64>| classifier.classify(&Signature::from_samples(&samples));
+        assert_eq!(classify(hardwood(10)), Some(Surface::Hardwood));
+        assert_eq!(classify(carpet(10)), Some(Surface::Carpet));
+        assert_eq!(classify(tile(10)), Some(Surface::Tile));
+    }
+
+    #[test]
+    fn it_should_leave_unfamiliar_vibration_unclassified() {
+        let classifier = trained();
+        let gravel: Vec<_> = noise(10).take(WINDOW).map(|n| 20.0 * n).collect();
+        assert_eq!(classifier.classify(&Signature::from_samples(&gravel)), None);
+    }
+
+    #[test]
+    fn it_should_reclassify_after_each_window() {
+        let mut classifier = trained().with_profile(
+            Surface::Carpet,
+            SurfaceProfile {
+                speed_scale: 0.5,
+                slip_factor: 0.9,
+            },
+        );
+        assert_eq!(classifier.profile(), SurfaceProfile::default());
+        let signatures: Vec<_> = carpet(20)
+            .into_iter()
+            .filter_map(|sample| classifier.push(sample))
+            .collect();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(classifier.surface(), Some(Surface::Carpet));
+        assert_eq!(classifier.profile().speed_scale, 0.5);
+    }
+}
@@ -0,0 +1,481 @@
+//! Ego-motion from a downward-looking camera.
+//!
+//! When the wheels slip or the robot is picked up and carried, wheel
+//! odometry no longer says anything about how the robot moved. A camera
+//! looking straight down at the floor still does: the floor slides through
+//! its view as the robot drives and turns, like under an optical mouse.
+//! [`VisualOdometry`] tracks a few dozen corners between downscaled frames
+//! by block matching, fits the rigid motion that best explains them, and
+//! scales it to metres from the camera’s height. Frames are processed at a
+//! low rate, so the whole pipeline takes a few milliseconds of one core per
+//! second.
+//!
+//! The camera is assumed to be mounted over the centre of rotation with the
+//! top of the image facing forward.
+
+use std::time::{Duration, Instant};
+
+use crate::camera::RgbImage;
+use crate::drive::Twist;
+use crate::geometry::Pose;
+
+/// Largest ratio between the cost of a match and the average cost over its
+/// search window.
+const MAX_MATCH_RATIO: f64 = 0.5;
+
+/// Settings of a [`VisualOdometry`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VisualOdometryConfig {
+    /// Height of the camera above the floor, in metres.
+    pub height: f64,
+    /// Focal length of the camera at full resolution, in pixels.
+    pub focal_length: f64,
+    /// Factor by which frames are shrunk before tracking.
+    pub downscale: usize,
+    /// Most corners tracked between frames.
+    pub max_features: usize,
+    /// Fewest corners that must be tracked for an estimate, below which the
+    /// floor is taken to be too plain to see motion on.
+    pub min_tracks: usize,
+    /// Half the width of the patch matched around each corner, in
+    /// downscaled pixels.
+    pub patch_radius: usize,
+    /// Farthest a corner is searched for from where it was, in downscaled
+    /// pixels.
+    pub search_radius: usize,
+    /// Shortest time between processed frames.
+    pub min_interval: Duration,
+}
+
+impl Default for VisualOdometryConfig {
+    fn default() -> Self {
+        Self {
+            height: 0.1,
+            focal_length: 500.0,
+            downscale: 4,
+            max_features: 48,
+            min_tracks: 8,
+            patch_radius: 3,
+            search_radius: 6,
+            min_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Motion between two processed frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    /// Displacement and turn in the frame of the robot at the earlier
+    /// frame.
+    pub motion: Pose,
+    /// Average velocity over the interval.
+    pub twist: Twist,
+    /// Time between the frames.
+    pub interval: Duration,
+    /// Number of corners the motion was fitted to.
+    pub tracks: usize,
+}
+
+/// A downscaled grayscale frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Luma {
+    width: usize,
+    height: usize,
+    values: Vec<u8>,
+}
+
+impl Luma {
+    fn from_rgb(image: &RgbImage, downscale: usize) -> Self {
+        let downscale = downscale.max(1);
+        let width = image.width / downscale;
+        let height = image.height / downscale;
+        let mut values = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0;
+
+                for dy in 0..downscale {
+                    for dx in 0..downscale {
+                        let [r, g, b] = image
+                            .pixel(x * downscale + dx, y * downscale + dy)
+                            .unwrap_or_default();
+                        sum += (77 * u32::from(r) + 150 * u32::from(g) + 29 * u32::from(b)) >> 8;
+                    }
+                }
+
+                values.push((sum / (downscale * downscale) as u32) as u8);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            values,
+        }
+    }
+
+    fn at(&self, x: usize, y: usize) -> i32 {
+        i32::from(self.values[y * self.width + x])
+    }
+
+    /// Returns the smaller eigenvalue of the structure tensor around `x`
+    /// and `y`, which is large only where the image changes in every
+    /// direction.
+    fn cornerness(&self, x: usize, y: usize) -> f64 {
+        let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+
+        for y in y - 1..=y + 1 {
+            for x in x - 1..=x + 1 {
+                let gx = f64::from(self.at(x + 1, y) - self.at(x - 1, y));
+                let gy = f64::from(self.at(x, y + 1) - self.at(x, y - 1));
+                xx += gx * gx;
+                yy += gy * gy;
+                xy += gx * gy;
+            }
+        }
+
+        (xx + yy) / 2.0 - (((xx - yy) / 2.0).powi(2) + xy * xy).sqrt()
+    }
+
+    /// Returns the sum of absolute differences between the patch around
+    /// `a` in this frame and the patch around `b` in `other`.
+    fn difference(&self, a: (usize, usize), other: &Self, b: (usize, usize), radius: usize) -> u32 {
+        let mut sum = 0;
+
+        for dy in 0..=2 * radius {
+            for dx in 0..=2 * radius {
+                sum += self
+                    .at(a.0 + dx - radius, a.1 + dy - radius)
+                    .abs_diff(other.at(b.0 + dx - radius, b.1 + dy - radius));
+            }
+        }
+
+        sum
+    }
+}
+
+/// Estimates the robot’s motion from frames of a downward-looking camera.
+#[derive(Clone, Debug, Default)]
+pub struct VisualOdometry {
+    config: VisualOdometryConfig,
+    previous: Option<Processed>,
+}
+
+/// A processed frame and the corners found in it.
+#[derive(Clone, Debug)]
+struct Processed {
+    taken: Instant,
+    luma: Luma,
+    corners: Vec<(usize, usize)>,
+}
+
+impl VisualOdometry {
+    /// Creates a new `VisualOdometry`.
+    pub fn new(config: VisualOdometryConfig) -> Self {
+        Self {
+            config,
+            previous: None,
+        }
+    }
+
+    /// Forgets the previous frame, such as after the camera has been
+    /// covered.
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    /// Processes `frame`, captured at `now`, and returns the motion since
+    /// the previous processed frame.
+    ///
+    /// Returns `None` for frames arriving sooner than the minimum interval,
+    /// which are skipped, for the first frame, and when too few corners
+    /// could be tracked to estimate the motion.
+    pub fn update(&mut self, frame: &RgbImage, now: Instant) -> Option<Estimate> {
+        if let Some(previous) = &self.previous {
+            if now.saturating_duration_since(previous.taken) < self.config.min_interval {
+                return None;
+            }
+        }
+
+        let luma = Luma::from_rgb(frame, self.config.downscale);
+        let corners = self.features(&luma);
+        let Processed {
+            taken,
+            luma: before,
+            corners,
+        } = self.previous.replace(Processed {
+            taken: now,
+            luma,
+            corners,
+        })?;
+        let after = &self.previous.as_ref()?.luma;
+        let interval = now.saturating_duration_since(taken);
+
+        let tracks: Vec<_> = corners
+            .iter()
+            .filter_map(|&corner| {
+                self.track(&before, after, corner).map(|moved| {
+                    let centre = |(x, y): (f64, f64)| {
+                        (
+                            x - before.width as f64 / 2.0,
+                            y - before.height as f64 / 2.0,
+                        )
+                    };
+                    (centre((corner.0 as f64, corner.1 as f64)), centre(moved))
+                })
+            })
+            .collect();
+
+        if tracks.len() < self.config.min_tracks {
+            return None;
+        }
+
+        // Fit once, then again without the corners it explains worst, which
+        // are usually on the robot’s own shadow or a moving foot.
+        let (angle, shift) = fit(&tracks);
+        let mut residuals: Vec<_> = tracks
+            .iter()
+            .map(|&(from, to)| residual(angle, shift, from, to))
+            .collect();
+        residuals.sort_by(f64::total_cmp);
+        let threshold = (2.0 * residuals[residuals.len() / 2]).max(1.0);
+        let inliers: Vec<_> = tracks
+            .into_iter()
+            .filter(|&(from, to)| residual(angle, shift, from, to) <= threshold)
+            .collect();
+
+        if inliers.len() < self.config.min_tracks {
+            return None;
+        }
+
+        let (angle, (du, dv)) = fit(&inliers);
+
+        // The image’s down and right are the robot’s back and right, which
+        // mirrors the plane and turns the floor’s rotation in the image into
+        // the robot’s own. The floor moves opposite to the robot, so the
+        // displacement is the floor’s shift, turned and reversed.
+        let scale =
+            self.config.height / self.config.focal_length * self.config.downscale.max(1) as f64;
+        let (forward, left) = (-dv * scale, -du * scale);
+        let (sin, cos) = angle.sin_cos();
+        let motion = Pose::new(
+            -(cos * forward - sin * left),
+            -(sin * forward + cos * left),
+            angle,
+        );
+        let seconds = interval.as_secs_f64().max(f64::EPSILON);
+
+        Some(Estimate {
+            motion,
+            twist: Twist::new(motion.x / seconds, motion.y / seconds, angle / seconds),
+            interval,
+            tracks: inliers.len(),
+        })
+    }
+
+    /// Returns the strongest corner in each cell of a grid over `luma`,
+    /// far enough from the edges to be searched for in the next frame.
+    fn features(&self, luma: &Luma) -> Vec<(usize, usize)> {
+        let border = self.config.patch_radius + self.config.search_radius + 1;
+
+        if luma.width <= 2 * border || luma.height <= 2 * border {
+            return Vec::new();
+        }
+
+        let (width, height) = (luma.width - 2 * border, luma.height - 2 * border);
+        let columns = ((self.config.max_features as f64 * width as f64 / height as f64).sqrt()
+            as usize)
+            .clamp(1, width);
+        let rows = (self.config.max_features / columns).clamp(1, height);
+        let mut features = Vec::new();
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let xs = border + column * width / columns..border + (column + 1) * width / columns;
+                let ys = border + row * height / rows..border + (row + 1) * height / rows;
+                let best = ys
+                    .flat_map(|y| xs.clone().map(move |x| (x, y)))
+                    .map(|(x, y)| ((x, y), luma.cornerness(x, y)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1));
+
+                // A corner needs some contrast in both directions, or block
+                // matching slides along it.
+                if let Some((corner, _)) = best.filter(|(_, score)| *score > 200.0) {
+                    features.push(corner);
+                }
+            }
+        }
+
+        features
+    }
+
+    /// Returns where `corner` in `before` has moved to in `after`, to a
+    /// fraction of a pixel, or `None` if no patch matches well.
+    fn track(&self, before: &Luma, after: &Luma, corner: (usize, usize)) -> Option<(f64, f64)> {
+        let radius = self.config.patch_radius;
+        let search = self.config.search_radius as isize;
+        let cost = |dx: isize, dy: isize| {
+            let x = corner.0.checked_add_signed(dx)?;
+            let y = corner.1.checked_add_signed(dy)?;
+            (x >= radius && y >= radius && x + radius < after.width && y + radius < after.height)
+                .then(|| before.difference(corner, after, (x, y), radius))
+        };
+        let costs: Vec<_> = (-search..=search)
+            .flat_map(|dy| (-search..=search).map(move |dx| (dx, dy)))
+            .filter_map(|(dx, dy)| cost(dx, dy).map(|cost| (dx, dy, cost)))
+            .collect();
+        let &(dx, dy, best) = costs.iter().min_by_key(|&&(_, _, cost)| cost)?;
+        let mean = costs
+            .iter()
+            .map(|&(_, _, cost)| f64::from(cost))
+            .sum::<f64>()
+            / costs.len() as f64;
+
+        // A good match stands out from the rest of the search window;
+        // otherwise the floor is too plain or too repetitive to tell where
+        // the corner went.
+        if f64::from(best) >= MAX_MATCH_RATIO * mean {
+            return None;
+        }
+
+        // Fit a parabola through the best cost and its neighbours.
+        let refine = |before: Option<u32>, after: Option<u32>| match (before, after) {
+            (Some(before), Some(after)) => {
+                let (before, best, after) = (f64::from(before), f64::from(best), f64::from(after));
+                let curvature = before - 2.0 * best + after;
+                if curvature > 0.0 {
+                    ((before - after) / (2.0 * curvature)).clamp(-0.5, 0.5)
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        };
+
+        Some((
+            corner.0 as f64 + dx as f64 + refine(cost(dx - 1, dy), cost(dx + 1, dy)),
+            corner.1 as f64 + dy as f64 + refine(cost(dx, dy - 1), cost(dx, dy + 1)),
+        ))
+    }
+}
+
+type Track = ((f64, f64), (f64, f64));
+
+/// Returns the rotation and shift best mapping the first point of each
+/// track onto the second, in the least squares sense.
+fn fit(tracks: &[Track]) -> (f64, (f64, f64)) {
+    let count = tracks.len() as f64;
+    let mean = |point: fn(&Track) -> (f64, f64)| {
+        let (x, y) = tracks
+            .iter()
+            .map(point)
+            .fold((0.0, 0.0), |sum, (x, y)| (sum.0 + x, sum.1 + y));
+        (x / count, y / count)
+    };
+    let from = mean(|track| track.0);
+    let to = mean(|track| track.1);
+    let (cross, dot) = tracks.iter().fold((0.0, 0.0), |(cross, dot), (a, b)| {
+        let (ax, ay) = (a.0 - from.0, a.1 - from.1);
+        let (bx, by) = (b.0 - to.0, b.1 - to.1);
+        (cross + ax * by - ay * bx, dot + ax * bx + ay * by)
+    });
+    let angle = cross.atan2(dot);
+    let (sin, cos) = angle.sin_cos();
+
+    (
+        angle,
+        (
+            to.0 - (cos * from.0 - sin * from.1),
+            to.1 - (sin * from.0 + cos * from.1),
+        ),
+    )
+}
+
+/// Returns how far `to` is from where the motion takes `from`.
+fn residual(angle: f64, shift: (f64, f64), from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (sin, cos) = angle.sin_cos();
+    let x = cos * from.0 - sin * from.1 + shift.0;
+    let y = sin * from.0 + cos * from.1 + shift.1;
+    (to.0 - x).hypot(to.1 - y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a view of a floor of random 8-pixel tiles, rotated by
+    /// `angle` and shifted by `shift` pixels.
+    fn floor(angle: f64, shift: (f64, f64)) -> RgbImage {
+        let mut image = RgbImage::new(320, 240);
+        let (sin, cos) = angle.sin_cos();
+
+        for v in 0..240 {
+            for u in 0..320 {
+                let (x, y) = (u as f64 - 160.0 - shift.0, v as f64 - 120.0 - shift.1);
+                let tile_x = ((cos * x + sin * y) / 8.0).floor() as i64;
+                let tile_y = ((-sin * x + cos * y) / 8.0).floor() as i64;
+                let hash = (tile_x.wrapping_mul(73_856_093) ^ tile_y.wrapping_mul(19_349_663))
+                    .wrapping_mul(2_654_435_761);
+                image.set_pixel(u, v, [(hash >> 16) as u8; 3]);
+            }
+        }
+
+        image
+    }
+
+    fn config() -> VisualOdometryConfig {
+        VisualOdometryConfig {
+            height: 0.2,
+            focal_length: 400.0,
+            ..VisualOdometryConfig::default()
+        }
+    }
+
+    #[test]
+    fn it_should_see_the_robot_drive_forward() {
+        let mut odometry = VisualOdometry::new(config());
+        let start = Instant::now();
+        assert_eq!(odometry.update(&floor(0.0, (0.0, 0.0)), start), None);
+        // Driving forward slides the floor down the image.
+        let estimate = odometry
+            .update(&floor(0.0, (0.0, 10.0)), start + Duration::from_millis(200))
+            .unwrap();
+        // 10 pixels at 0.2 m above the floor with a 400-pixel focal length.
+        assert!((estimate.motion.x - 0.005).abs() < 0.0005, "{estimate:?}");
+        assert!(estimate.motion.y.abs() < 0.0005, "{estimate:?}");
+        assert!(estimate.motion.heading.abs() < 0.005, "{estimate:?}");
+        assert!((estimate.twist.vx - 0.025).abs() < 0.0025, "{estimate:?}");
+        assert!(estimate.tracks >= config().min_tracks);
+    }
+
+    #[test]
+    fn it_should_see_the_robot_turn() {
+        let mut odometry = VisualOdometry::new(config());
+        let start = Instant::now();
+        odometry.update(&floor(0.0, (0.0, 0.0)), start);
+        let estimate = odometry
+            .update(&floor(0.05, (0.0, 0.0)), start + Duration::from_millis(100))
+            .unwrap();
+        assert!(
+            (estimate.motion.heading - 0.05).abs() < 0.01,
+            "{estimate:?}"
+        );
+        assert!(estimate.motion.x.abs() < 0.001, "{estimate:?}");
+    }
+
+    #[test]
+    fn it_should_skip_frames_and_plain_floors() {
+        let mut odometry = VisualOdometry::new(config());
+        let start = Instant::now();
+        odometry.update(&floor(0.0, (0.0, 0.0)), start);
+        assert_eq!(
+            odometry.update(&floor(0.0, (4.0, 0.0)), start + Duration::from_millis(50)),
+            None
+        );
+        assert_eq!(
+            odometry.update(&RgbImage::new(320, 240), start + Duration::from_millis(150)),
+            None
+        );
+    }
+}
@@ -0,0 +1,727 @@
+//! A bus for discrete events shared between subsystems.
+//!
+//! Unlike telemetry samples, which are sampled continuously and may be
+//! dropped, events mark something that happened once, such as a fault or the
+//! dock coming into view. Publishers and subscribers only share the bus, so a
+//! behavior can react to a low battery without holding a reference to the
+//! battery monitor.
+//!
+//! Subscribers either register a callback, which runs synchronously on the
+//! publishing thread, or take a [`Subscription`], which queues events to be
+//! received on another thread or awaited from async code.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::log_event;
+use crate::logging::Level;
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "events";
+
+/// A subsystem failure.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Fault {
+    /// Subsystem that failed.
+    pub subsystem: String,
+    /// Description of the failure.
+    pub message: String,
+}
+
+/// A change in the state of the emergency stop.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct EmergencyStop {
+    /// `true` if the emergency stop was engaged, or `false` if it was
+    /// released.
+    pub engaged: bool,
+    /// What engaged or released the emergency stop.
+    pub source: String,
+}
+
+/// The battery dropping below its warning threshold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LowBattery {
+    /// Battery voltage, in volts.
+    pub voltage: f64,
+    /// Estimated state of charge, from 0 to 1, if known.
+    pub state_of_charge: Option<f64>,
+}
+
+/// The charging dock coming into view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DockDetected {
+    /// Bearing to the dock relative to the robot’s heading, in radians.
+    pub bearing: f64,
+    /// Distance to the dock, in metres, if known.
+    pub distance: Option<f64>,
+}
+
+/// A fiducial tag coming into view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TagSeen {
+    /// Identifier encoded by the tag.
+    pub id: u32,
+    /// Bearing to the tag relative to the robot’s heading, in radians.
+    pub bearing: f64,
+    /// Distance to the tag, in metres, if known.
+    pub distance: Option<f64>,
+}
+
+/// How well the wheels grip the ground.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Traction {
+    /// The wheels roll without slipping, so odometry can be trusted.
+    #[default]
+    Gripping,
+    /// The wheels are spinning or skidding over the ground.
+    Slipping,
+    /// The wheels are commanded to turn but are held still.
+    Stalled,
+    /// The robot has been lifted off the ground.
+    Lifted,
+}
+
+/// A change in how well the wheels grip the ground.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TractionChanged {
+    /// Traction before the change.
+    pub previous: Traction,
+    /// Traction after the change.
+    pub traction: Traction,
+}
+
+/// Motion detected near the robot.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MotionDetected {
+    /// Sensor that detected the motion.
+    pub sensor: String,
+}
+
+/// How much power the robot is drawing.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum PowerMode {
+    /// Everything is powered and ready to drive.
+    #[default]
+    Awake,
+    /// Peripherals are powered down to save the battery.
+    Asleep,
+}
+
+/// What woke the robot from sleep.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum WakeSource {
+    /// A command arrived over the network.
+    Network,
+    /// Motion was detected nearby.
+    Motion,
+    /// A scheduled task came due.
+    Schedule,
+}
+
+/// A change in how much power the robot is drawing.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PowerModeChanged {
+    /// Power mode after the change.
+    pub mode: PowerMode,
+    /// What woke the robot, if it woke.
+    pub source: Option<WakeSource>,
+}
+
+/// Class of a USB peripheral that can be plugged in and out.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DeviceClass {
+    /// A serial adapter, such as a GPS or motor controller.
+    Serial,
+    /// An input device, such as a gamepad.
+    Input,
+    /// A camera.
+    Camera,
+}
+
+/// A USB peripheral attached or detached.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DeviceChanged {
+    /// Class of the device.
+    pub class: DeviceClass,
+    /// Name of the device node under `/dev`, such as `ttyUSB0`.
+    pub node: String,
+    /// Whether the device was attached, rather than detached.
+    pub attached: bool,
+}
+
+/// The supply sagging below what the robot needs, or recovering.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Brownout {
+    /// Whether the brownout began, rather than ended.
+    pub active: bool,
+    /// What sagged, such as `undervoltage flag` or a rail’s name.
+    pub cause: String,
+}
+
+/// A command given by a person, such as by voice.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Command {
+    /// What is wanted, such as `go_to`.
+    pub intent: String,
+    /// What it is wanted of, such as the place to go to, if anything.
+    pub argument: Option<String>,
+    /// Where the command came from, such as `voice`.
+    pub source: String,
+}
+
+/// A discrete event published on the bus.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A subsystem failed.
+    Fault(Fault),
+    /// The emergency stop was engaged or released.
+    EmergencyStop(EmergencyStop),
+    /// The battery is low.
+    LowBattery(LowBattery),
+    /// The charging dock came into view.
+    DockDetected(DockDetected),
+    /// A fiducial tag came into view.
+    TagSeen(TagSeen),
+    /// The wheels gained or lost traction.
+    TractionChanged(TractionChanged),
+    /// The robot went to sleep or woke.
+    PowerModeChanged(PowerModeChanged),
+    /// Something moved near the robot.
+    MotionDetected(MotionDetected),
+    /// A USB peripheral was attached or detached.
+    DeviceChanged(DeviceChanged),
+    /// The supply browned out or recovered.
+    Brownout(Brownout),
+    /// A person gave a command.
+    Command(Command),
+}
+
+impl Event {
+    /// Returns the kind of the event.
+    #[must_use]
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Fault(_) => EventKind::Fault,
+            Self::EmergencyStop(_) => EventKind::EmergencyStop,
+            Self::LowBattery(_) => EventKind::LowBattery,
+            Self::DockDetected(_) => EventKind::DockDetected,
+            Self::TagSeen(_) => EventKind::TagSeen,
+            Self::TractionChanged(_) => EventKind::TractionChanged,
+            Self::PowerModeChanged(_) => EventKind::PowerModeChanged,
+            Self::MotionDetected(_) => EventKind::MotionDetected,
+            Self::DeviceChanged(_) => EventKind::DeviceChanged,
+            Self::Brownout(_) => EventKind::Brownout,
+            Self::Command(_) => EventKind::Command,
+        }
+    }
+}
+
+/// Kind of an [`Event`], used to filter subscriptions.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EventKind {
+    /// [`Event::Fault`].
+    Fault,
+    /// [`Event::EmergencyStop`].
+    EmergencyStop,
+    /// [`Event::LowBattery`].
+    LowBattery,
+    /// [`Event::DockDetected`].
+    DockDetected,
+    /// [`Event::TagSeen`].
+    TagSeen,
+    /// [`Event::TractionChanged`].
+    TractionChanged,
+    /// [`Event::PowerModeChanged`].
+    PowerModeChanged,
+    /// [`Event::MotionDetected`].
+    MotionDetected,
+    /// [`Event::DeviceChanged`].
+    DeviceChanged,
+    /// [`Event::Brownout`].
+    Brownout,
+    /// [`Event::Command`].
+    Command,
+}
+
+/// Identifier of a registered callback, used to remove it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CallbackId(u64);
+
+type Callback = Arc<dyn Fn(&Event) + Send + Sync>;
+
+struct Registration {
+    callback: Callback,
+    id: CallbackId,
+    kinds: Option<Vec<EventKind>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    callbacks: RwLock<Vec<Registration>>,
+    next_id: AtomicU64,
+    queues: Mutex<Vec<Weak<Queue>>>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let queues = self.queues.get_mut().unwrap_or_else(|e| e.into_inner());
+
+        for queue in queues.iter().filter_map(Weak::upgrade) {
+            queue.close();
+        }
+    }
+}
+
+/// A bus that delivers published events to every matching subscriber.
+///
+/// Cloning the bus gives another handle to the same subscribers.
+/// Subscriptions end once every handle has been dropped.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Inner>,
+}
+
+impl EventBus {
+    /// Creates a new `EventBus` without subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delivers `event` to every matching callback, then to every matching
+    /// subscription.
+    ///
+    /// Callbacks run on the calling thread, so they should return quickly and
+    /// hand longer work to another thread.
+    pub fn publish(&self, event: Event) {
+        let kind = event.kind();
+        log_event!(SUBSYSTEM, Level::Debug, "publish {event:?}");
+        let callbacks: Vec<Callback> = self
+            .inner
+            .callbacks
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|registration| matches(registration.kinds.as_deref(), kind))
+            .map(|registration| Arc::clone(&registration.callback))
+            .collect();
+
+        for callback in callbacks {
+            callback(&event);
+        }
+
+        let mut queues = self.inner.queues.lock().unwrap_or_else(|e| e.into_inner());
+        queues.retain(|queue| match queue.upgrade() {
+            Some(queue) => {
+                if matches(queue.kinds.as_deref(), kind) {
+                    queue.push(event.clone());
+                }
+
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Registers `callback` to run for every event.
+    pub fn on_any(&self, callback: impl Fn(&Event) + Send + Sync + 'static) -> CallbackId {
+        self.register(None, Arc::new(callback))
+    }
+
+    /// Registers `callback` to run for every event of one of `kinds`.
+    pub fn on(
+        &self,
+        kinds: &[EventKind],
+        callback: impl Fn(&Event) + Send + Sync + 'static,
+    ) -> CallbackId {
+        self.register(Some(kinds.to_vec()), Arc::new(callback))
+    }
+
+    /// Removes a callback, returning `true` if it was registered.
+    pub fn remove(&self, id: CallbackId) -> bool {
+        let mut callbacks = self
+            .inner
+            .callbacks
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        let len = callbacks.len();
+        callbacks.retain(|registration| registration.id != id);
+        callbacks.len() != len
+    }
+
+    /// Subscribes to every event, queueing up to `capacity` events that have
+    /// not yet been received.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn subscribe(&self, capacity: usize) -> Subscription {
+        self.add_queue(None, capacity)
+    }
+
+    /// Subscribes to events of one of `kinds`, queueing up to `capacity`
+    /// events that have not yet been received.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn subscribe_to(&self, kinds: &[EventKind], capacity: usize) -> Subscription {
+        self.add_queue(Some(kinds.to_vec()), capacity)
+    }
+
+    fn register(&self, kinds: Option<Vec<EventKind>>, callback: Callback) -> CallbackId {
+        let id = CallbackId(self.inner.next_id.fetch_add(1, Ordering::Relaxed));
+        self.inner
+            .callbacks
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Registration {
+                callback,
+                id,
+                kinds,
+            });
+        id
+    }
+
+    fn add_queue(&self, kinds: Option<Vec<EventKind>>, capacity: usize) -> Subscription {
+        assert!(capacity > 0, "capacity should be greater than zero");
+        let queue = Arc::new(Queue {
+            capacity,
+            condvar: Condvar::new(),
+            kinds,
+            state: Mutex::default(),
+        });
+        self.inner
+            .queues
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Arc::downgrade(&queue));
+        Subscription { queue }
+    }
+}
+
+impl Debug for EventBus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let callbacks = self
+            .inner
+            .callbacks
+            .read()
+            .map_or(0, |callbacks| callbacks.len());
+        f.debug_struct("EventBus")
+            .field("callbacks", &callbacks)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    closed: bool,
+    events: VecDeque<Event>,
+    missed: u64,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug)]
+struct Queue {
+    capacity: usize,
+    condvar: Condvar,
+    kinds: Option<Vec<EventKind>>,
+    state: Mutex<QueueState>,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, event: Event) {
+        let mut state = self.lock();
+
+        if state.events.len() == self.capacity {
+            state.events.pop_front();
+            state.missed += 1;
+        }
+
+        state.events.push_back(event);
+        self.wake(state);
+    }
+
+    fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        self.wake(state);
+    }
+
+    fn wake(&self, mut state: MutexGuard<'_, QueueState>) {
+        let waker = state.waker.take();
+        drop(state);
+        self.condvar.notify_all();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// A queue of events delivered by an [`EventBus`].
+///
+/// When the queue is full, the oldest event is discarded to make room, so a
+/// slow subscriber sees the most recent events rather than blocking the
+/// publisher.
+#[derive(Debug)]
+pub struct Subscription {
+    queue: Arc<Queue>,
+}
+
+impl Subscription {
+    /// Returns the next event without blocking, or `None` if there is none.
+    pub fn try_recv(&self) -> Option<Event> {
+        self.queue.lock().events.pop_front()
+    }
+
+    /// Blocks until the next event, or returns `None` once the bus has been
+    /// dropped and every queued event received.
+    pub fn recv(&self) -> Option<Event> {
+        let mut state = self.queue.lock();
+
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                return Some(event);
+            }
+
+            if state.closed {
+                return None;
+            }
+
+            state = self
+                .queue
+                .condvar
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Blocks until the next event or until `timeout` elapses.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Event> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.queue.lock();
+
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                return Some(event);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if state.closed || remaining.is_zero() {
+                return None;
+            }
+
+            state = self
+                .queue
+                .condvar
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Returns a future that resolves to the next event, or to `None` once
+    /// the bus has been dropped and every queued event received.
+    pub fn recv_async(&mut self) -> RecvAsync<'_> {
+        RecvAsync { subscription: self }
+    }
+
+    /// Returns the number of events discarded because the queue was full.
+    #[must_use]
+    pub fn missed(&self) -> u64 {
+        self.queue.lock().missed
+    }
+}
+
+/// Future returned by [`Subscription::recv_async`].
+#[derive(Debug)]
+pub struct RecvAsync<'a> {
+    subscription: &'a mut Subscription,
+}
+
+impl Future for RecvAsync<'_> {
+    type Output = Option<Event>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.subscription.queue.lock();
+
+        if let Some(event) = state.events.pop_front() {
+            Poll::Ready(Some(event))
+        } else if state.closed {
+            Poll::Ready(None)
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+fn matches(kinds: Option<&[EventKind]>, kind: EventKind) -> bool {
+    kinds.is_none_or(|kinds| kinds.contains(&kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    use super::*;
+
+    struct Unparker(Thread);
+
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unparker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+
+            thread::park();
+        }
+    }
+
+    fn low_battery() -> Event {
+        Event::LowBattery(LowBattery {
+            voltage: 10.5,
+            state_of_charge: Some(0.1),
+        })
+    }
+
+    fn tag_seen(id: u32) -> Event {
+        Event::TagSeen(TagSeen {
+            id,
+            bearing: 0.0,
+            distance: None,
+        })
+    }
+
+    #[test]
+    fn it_should_run_callbacks_for_matching_kinds() {
+        let bus = EventBus::new();
+        let (any, faults) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (any_count, fault_count) = (Arc::clone(&any), Arc::clone(&faults));
+        bus.on_any(move |_| {
+            any_count.fetch_add(1, Ordering::SeqCst);
+        });
+        bus.on(&[EventKind::Fault], move |_| {
+            fault_count.fetch_add(1, Ordering::SeqCst);
+        });
+        bus.publish(low_battery());
+        bus.publish(Event::Fault(Fault {
+            subsystem: "imu".to_owned(),
+            message: "timed out".to_owned(),
+        }));
+        assert_eq!(any.load(Ordering::SeqCst), 2);
+        assert_eq!(faults.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn it_should_stop_running_a_removed_callback() {
+        let bus = EventBus::new();
+        let count = Arc::new(AtomicUsize::new(0));
+        let callback_count = Arc::clone(&count);
+        let id = bus.on_any(move |_| {
+            callback_count.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(bus.remove(id));
+        assert!(!bus.remove(id));
+        bus.publish(low_battery());
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn it_should_allow_a_callback_to_publish() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe_to(&[EventKind::EmergencyStop], 4);
+        let callback_bus = bus.clone();
+        bus.on(&[EventKind::Fault], move |_| {
+            callback_bus.publish(Event::EmergencyStop(EmergencyStop {
+                engaged: true,
+                source: "fault".to_owned(),
+            }));
+        });
+        bus.publish(Event::Fault(Fault {
+            subsystem: "drive".to_owned(),
+            message: "overcurrent".to_owned(),
+        }));
+        assert!(subscription
+            .try_recv()
+            .is_some_and(|event| event.kind() == EventKind::EmergencyStop));
+    }
+
+    #[test]
+    fn it_should_queue_only_subscribed_kinds() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe_to(&[EventKind::TagSeen], 4);
+        bus.publish(low_battery());
+        bus.publish(tag_seen(7));
+        assert_eq!(subscription.try_recv(), Some(tag_seen(7)));
+        assert_eq!(subscription.try_recv(), None);
+    }
+
+    #[test]
+    fn it_should_discard_the_oldest_event_when_a_queue_is_full() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(2);
+
+        for id in 0..3 {
+            bus.publish(tag_seen(id));
+        }
+
+        assert_eq!(subscription.missed(), 1);
+        assert_eq!(subscription.recv(), Some(tag_seen(1)));
+        assert_eq!(subscription.recv(), Some(tag_seen(2)));
+    }
+
+    #[test]
+    fn it_should_end_a_subscription_when_the_bus_is_dropped() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(4);
+        bus.publish(low_battery());
+        drop(bus);
+        assert_eq!(subscription.recv(), Some(low_battery()));
+        assert_eq!(subscription.recv(), None);
+        assert_eq!(subscription.recv_timeout(Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn it_should_time_out_waiting_for_an_event() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(1);
+        assert_eq!(subscription.recv_timeout(Duration::from_millis(10)), None);
+    }
+
+    #[test]
+    fn it_should_await_an_event_published_from_another_thread() {
+        let bus = EventBus::new();
+        let mut subscription = bus.subscribe(4);
+        let publisher = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            bus.publish(tag_seen(42));
+        });
+        assert_eq!(block_on(subscription.recv_async()), Some(tag_seen(42)));
+        publisher.join().unwrap();
+        assert_eq!(block_on(subscription.recv_async()), None);
+    }
+}
@@ -0,0 +1,482 @@
+//! A C interface for driving the robot from other languages.
+//!
+//! A Python or C++ process loads the library built with the `ffi` feature,
+//! creates an [`OtterRuntime`], and drives, stops, and watches the robot
+//! through the `otter_*` functions declared in `include/otter_pi.h`. Drive
+//! commands go through a command mux source with a timeout, so a script that
+//! hangs stops the robot rather than leaving it driving, and an e-stop
+//! refuses every command until it is reset.
+//!
+//! The Rust side of the runtime holds a clone of the same [`Runtime`] to read
+//! the command to follow and to publish telemetry for scripts to poll. Once
+//! it reports writing each command to the actuators, the telemetry includes
+//! the latency of recent commands from the call to [`otter_drive`].
+//!
+//! Build the shared library with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+//! The layout of every type and the meaning of every status code is fixed
+//! for a given [`ABI_VERSION`].
+
+use std::ffi::{c_char, c_int};
+use std::fmt::{self, Debug, Formatter};
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::control::mux::{CommandMux, Priority};
+use crate::diagnostics::latency::{LatencyReport, LatencyTracker};
+use crate::drive::Twist;
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "ffi";
+
+/// Version of the C interface, raised whenever it changes incompatibly.
+pub const ABI_VERSION: u32 = 1;
+
+/// Name of the command mux source the C interface drives through.
+const SOURCE: &str = "ffi";
+
+/// Number of recent drive commands whose latency is reported.
+const LATENCY_WINDOW: usize = 100;
+
+/// The call succeeded.
+pub const OTTER_OK: c_int = 0;
+
+/// A pointer argument was null.
+pub const OTTER_ERR_NULL: c_int = -1;
+
+/// The robot is e-stopped.
+pub const OTTER_ERR_ESTOPPED: c_int = -2;
+
+/// A velocity was not a finite number.
+pub const OTTER_ERR_INVALID: c_int = -3;
+
+/// There is no command to follow.
+pub const OTTER_ERR_NO_COMMAND: c_int = -4;
+
+/// Velocity of the chassis, laid out as in C.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OtterTwist {
+    /// Forward velocity, in metres per second.
+    pub vx: f64,
+    /// Leftward velocity, in metres per second.
+    pub vy: f64,
+    /// Counterclockwise angular velocity, in radians per second.
+    pub omega: f64,
+}
+
+impl From<OtterTwist> for Twist {
+    fn from(twist: OtterTwist) -> Self {
+        Self::new(twist.vx, twist.vy, twist.omega)
+    }
+}
+
+impl From<Twist> for OtterTwist {
+    fn from(twist: Twist) -> Self {
+        Self {
+            vx: twist.vx,
+            vy: twist.vy,
+            omega: twist.omega,
+        }
+    }
+}
+
+struct State {
+    estopped: bool,
+    latency: LatencyTracker,
+    mux: CommandMux<Twist>,
+    telemetry: String,
+}
+
+/// The control surface shared between the C interface and the runtime.
+#[derive(Clone)]
+pub struct Runtime {
+    state: Arc<Mutex<State>>,
+}
+
+impl Runtime {
+    /// Creates a new `Runtime` whose drive commands expire `timeout` after
+    /// they are sent.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                estopped: false,
+                latency: LatencyTracker::new(LATENCY_WINDOW),
+                mux: CommandMux::new().with_source(SOURCE, Priority::Autonomy, timeout),
+                telemetry: Value::object().to_string(),
+            })),
+        }
+    }
+
+    /// Commands the chassis to move at `twist`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`OTTER_ERR_ESTOPPED`] if the robot is
+    /// e-stopped, or [`OTTER_ERR_INVALID`] if a velocity is not finite.
+    pub fn drive(&self, twist: Twist, now: Instant) -> Result<(), c_int> {
+        if ![twist.vx, twist.vy, twist.omega]
+            .iter()
+            .all(|value| value.is_finite())
+        {
+            return Err(OTTER_ERR_INVALID);
+        }
+
+        let mut state = self.lock();
+
+        if state.estopped {
+            return Err(OTTER_ERR_ESTOPPED);
+        }
+
+        state
+            .mux
+            .submit(SOURCE, twist, now)
+            .expect("the source should be registered");
+        Ok(())
+    }
+
+    /// Withdraws the last drive command.
+    pub fn stop(&self) {
+        let _ = self.lock().mux.release(SOURCE);
+    }
+
+    /// Stops the robot and refuses drive commands until reset.
+    pub fn estop(&self) {
+        let mut state = self.lock();
+
+        if !state.estopped {
+            log_event!(SUBSYSTEM, Level::Warn, "e-stopped through the C interface");
+        }
+
+        state.estopped = true;
+        let _ = state.mux.release(SOURCE);
+    }
+
+    /// Accepts drive commands again after an e-stop.
+    pub fn reset_estop(&self) {
+        self.lock().estopped = false;
+    }
+
+    /// Returns `true` if the robot is e-stopped.
+    #[must_use]
+    pub fn is_estopped(&self) -> bool {
+        self.lock().estopped
+    }
+
+    /// Returns the command to follow at `now`, or `None` if the robot should
+    /// stand still.
+    #[must_use]
+    pub fn command(&self, now: Instant) -> Option<Twist> {
+        let mut state = self.lock();
+
+        if state.estopped {
+            return None;
+        }
+
+        state.mux.select(now).copied()
+    }
+
+    /// Records that the command last returned by [`Runtime::command`] was
+    /// written to the actuators at `now`.
+    pub fn actuated(&self, now: Instant) {
+        let mut state = self.lock();
+
+        if let Some(trace) = state.mux.actuated(now) {
+            state.latency.record(&trace);
+        }
+    }
+
+    /// Returns the latency of recent drive commands, or `None` if none has
+    /// been actuated.
+    #[must_use]
+    pub fn latency(&self) -> Option<LatencyReport> {
+        self.lock().latency.report()
+    }
+
+    /// Replaces the telemetry that scripts poll, adding the latency of recent
+    /// drive commands under `latency` to an object.
+    pub fn publish_telemetry(&self, telemetry: &impl ToJson) {
+        let mut state = self.lock();
+        let mut telemetry = telemetry.to_json();
+
+        if let (Value::Object(_), Some(report)) = (&telemetry, state.latency.report()) {
+            telemetry.insert("latency", report);
+        }
+
+        state.telemetry = telemetry.to_string();
+    }
+
+    /// Returns the telemetry last published, as JSON.
+    #[must_use]
+    pub fn telemetry(&self) -> String {
+        self.lock().telemetry.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Debug for Runtime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Runtime")
+            .field("estopped", &self.is_estopped())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Opaque handle to a [`Runtime`] owned by C code.
+#[derive(Debug)]
+pub struct OtterRuntime(Runtime);
+
+impl OtterRuntime {
+    /// Returns the runtime behind the handle, for the Rust side to share.
+    #[must_use]
+    pub fn runtime(&self) -> &Runtime {
+        &self.0
+    }
+}
+
+/// Returns the version of the C interface.
+#[no_mangle]
+pub extern "C" fn otter_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// Creates a runtime whose drive commands expire after `timeout_ms`
+/// milliseconds. Free it with [`otter_runtime_free`].
+#[no_mangle]
+pub extern "C" fn otter_runtime_new(timeout_ms: u32) -> *mut OtterRuntime {
+    let runtime = Runtime::new(Duration::from_millis(u64::from(timeout_ms)));
+    Box::into_raw(Box::new(OtterRuntime(runtime)))
+}
+
+/// Frees a runtime, stopping the robot.
+///
+/// # Safety
+///
+/// `runtime` must be null or have been returned by [`otter_runtime_new`]
+/// and not freed already.
+#[no_mangle]
+pub unsafe extern "C" fn otter_runtime_free(runtime: *mut OtterRuntime) {
+    if !runtime.is_null() {
+        let runtime = unsafe { Box::from_raw(runtime) };
+        runtime.0.stop();
+    }
+}
+
+/// Commands the chassis to move at `twist`.
+///
+/// # Safety
+///
+/// `runtime` must be null or a live pointer from [`otter_runtime_new`].
+#[no_mangle]
+pub unsafe extern "C" fn otter_drive(runtime: *const OtterRuntime, twist: OtterTwist) -> c_int {
+    match unsafe { runtime.as_ref() } {
+        Some(runtime) => status(runtime.0.drive(twist.into(), Instant::now())),
+        None => OTTER_ERR_NULL,
+    }
+}
+
+/// Withdraws the last drive command.
+///
+/// # Safety
+///
+/// `runtime` must be null or a live pointer from [`otter_runtime_new`].
+#[no_mangle]
+pub unsafe extern "C" fn otter_stop(runtime: *const OtterRuntime) -> c_int {
+    unsafe { with_runtime(runtime, Runtime::stop) }
+}
+
+/// Stops the robot and refuses drive commands until
+/// [`otter_estop_reset`] is called.
+///
+/// # Safety
+///
+/// `runtime` must be null or a live pointer from [`otter_runtime_new`].
+#[no_mangle]
+pub unsafe extern "C" fn otter_estop(runtime: *const OtterRuntime) -> c_int {
+    unsafe { with_runtime(runtime, Runtime::estop) }
+}
+
+/// Accepts drive commands again after an e-stop.
+///
+/// # Safety
+///
+/// `runtime` must be null or a live pointer from [`otter_runtime_new`].
+#[no_mangle]
+pub unsafe extern "C" fn otter_estop_reset(runtime: *const OtterRuntime) -> c_int {
+    unsafe { with_runtime(runtime, Runtime::reset_estop) }
+}
+
+/// Returns 1 if the robot is e-stopped, 0 if not, or a negative status.
+///
+/// # Safety
+///
+/// `runtime` must be null or a live pointer from [`otter_runtime_new`].
+#[no_mangle]
+pub unsafe extern "C" fn otter_is_estopped(runtime: *const OtterRuntime) -> c_int {
+    match unsafe { runtime.as_ref() } {
+        Some(runtime) => c_int::from(runtime.0.is_estopped()),
+        None => OTTER_ERR_NULL,
+    }
+}
+
+/// Writes the command the robot is following to `twist`.
+///
+/// # Safety
+///
+/// `runtime` must be null or a live pointer from [`otter_runtime_new`], and
+/// `twist` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn otter_command(
+    runtime: *const OtterRuntime,
+    twist: *mut OtterTwist,
+) -> c_int {
+    let (Some(runtime), false) = (unsafe { runtime.as_ref() }, twist.is_null()) else {
+        return OTTER_ERR_NULL;
+    };
+
+    match runtime.0.command(Instant::now()) {
+        Some(command) => {
+            unsafe { twist.write(command.into()) };
+            OTTER_OK
+        }
+        None => OTTER_ERR_NO_COMMAND,
+    }
+}
+
+/// Copies the latest telemetry, as NUL-terminated JSON, into the `len` bytes
+/// at `buffer`, truncating it if it does not fit, as `snprintf` does.
+///
+/// Returns the length of the whole JSON, excluding the NUL, so that a
+/// caller can retry with a larger buffer, or a negative status.
+///
+/// # Safety
+///
+/// `runtime` must be null or a live pointer from [`otter_runtime_new`], and
+/// `buffer` must be null or valid for writes of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn otter_telemetry(
+    runtime: *const OtterRuntime,
+    buffer: *mut c_char,
+    len: usize,
+) -> i64 {
+    let Some(runtime) = (unsafe { runtime.as_ref() }) else {
+        return i64::from(OTTER_ERR_NULL);
+    };
+    let telemetry = runtime.0.telemetry();
+
+    if !buffer.is_null() && len > 0 {
+        let copied = telemetry.len().min(len - 1);
+
+        unsafe {
+            ptr::copy_nonoverlapping(telemetry.as_ptr().cast(), buffer, copied);
+            buffer.add(copied).write(0);
+        }
+    }
+
+    telemetry.len() as i64
+}
+
+/// # Safety
+///
+/// `runtime` must be null or a live pointer from [`otter_runtime_new`].
+unsafe fn with_runtime(runtime: *const OtterRuntime, action: impl FnOnce(&Runtime)) -> c_int {
+    match unsafe { runtime.as_ref() } {
+        Some(runtime) => {
+            action(&runtime.0);
+            OTTER_OK
+        }
+        None => OTTER_ERR_NULL,
+    }
+}
+
+fn status(result: Result<(), c_int>) -> c_int {
+    result.err().unwrap_or(OTTER_OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use super::*;
+
+    #[test]
+    fn it_should_drive_until_the_command_expires() {
+        let runtime = Runtime::new(Duration::from_millis(100));
+        let start = Instant::now();
+        runtime.drive(Twist::new(0.3, 0.0, 0.1), start).unwrap();
+        assert_eq!(runtime.command(start), Some(Twist::new(0.3, 0.0, 0.1)));
+        assert_eq!(runtime.command(start + Duration::from_millis(150)), None);
+        assert_eq!(
+            runtime.drive(Twist::new(f64::NAN, 0.0, 0.0), start),
+            Err(OTTER_ERR_INVALID)
+        );
+    }
+
+    #[test]
+    fn it_should_publish_the_latency_of_actuated_commands() {
+        let runtime = Runtime::new(Duration::from_millis(100));
+        let start = Instant::now();
+        runtime.drive(Twist::new(0.3, 0.0, 0.0), start).unwrap();
+        runtime.actuated(start);
+        assert_eq!(runtime.latency(), None);
+        let _ = runtime.command(start + Duration::from_millis(1));
+        runtime.actuated(start + Duration::from_millis(4));
+        let report = runtime.latency().unwrap();
+        assert_eq!(report.samples, 1);
+        assert_eq!(report.end_to_end.max, Duration::from_millis(4));
+        runtime.publish_telemetry(&Value::object().with("battery", 12.5));
+        let telemetry: Value = runtime.telemetry().parse().unwrap();
+        assert_eq!(
+            telemetry
+                .get("latency")
+                .and_then(|latency| latency.get("samples")),
+            Some(&Value::Number(1.0))
+        );
+    }
+
+    #[test]
+    fn it_should_refuse_commands_while_e_stopped() {
+        let runtime = otter_runtime_new(1000);
+        let twist = OtterTwist {
+            vx: 0.5,
+            ..OtterTwist::default()
+        };
+        let mut command = OtterTwist::default();
+
+        unsafe {
+            assert_eq!(otter_drive(runtime, twist), OTTER_OK);
+            assert_eq!(otter_command(runtime, &mut command), OTTER_OK);
+            assert_eq!(command, twist);
+            assert_eq!(otter_estop(runtime), OTTER_OK);
+            assert_eq!(otter_is_estopped(runtime), 1);
+            assert_eq!(otter_command(runtime, &mut command), OTTER_ERR_NO_COMMAND);
+            assert_eq!(otter_drive(runtime, twist), OTTER_ERR_ESTOPPED);
+            assert_eq!(otter_estop_reset(runtime), OTTER_OK);
+            assert_eq!(otter_drive(runtime, twist), OTTER_OK);
+            assert_eq!(otter_drive(ptr::null(), twist), OTTER_ERR_NULL);
+            otter_runtime_free(runtime);
+        }
+    }
+
+    #[test]
+    fn it_should_copy_telemetry_like_snprintf() {
+        let runtime = otter_runtime_new(1000);
+        unsafe { &*runtime }
+            .runtime()
+            .publish_telemetry(&Value::object().with("battery", 12.5));
+        let mut buffer = [1 as c_char; 8];
+
+        unsafe {
+            assert_eq!(otter_telemetry(runtime, ptr::null_mut(), 0), 16);
+            assert_eq!(otter_telemetry(runtime, buffer.as_mut_ptr(), 8), 16);
+            assert_eq!(CStr::from_ptr(buffer.as_ptr()).to_str(), Ok("{\"batte"));
+            otter_runtime_free(runtime);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Planar geometry shared by the drive, localization, and navigation code.
+
+use std::f64::consts::{PI, TAU};
+
+use crate::json::{ToJson, Value};
+
+pub mod geodesy;
+
+/// Position and heading in a plane.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pose {
+    /// Position along the X axis, in metres.
+    pub x: f64,
+    /// Position along the Y axis, in metres.
+    pub y: f64,
+    /// Heading counterclockwise from the X axis, in radians.
+    pub heading: f64,
+}
+
+impl Pose {
+    /// Creates a new pose.
+    pub fn new(x: f64, y: f64, heading: f64) -> Self {
+        Self { x, y, heading }
+    }
+
+    /// Returns the distance to `other`, in metres.
+    #[must_use]
+    pub fn distance_to(&self, other: &Self) -> f64 {
+        (other.x - self.x).hypot(other.y - self.y)
+    }
+
+    /// Returns the bearing to `other` relative to this pose’s heading, in
+    /// radians from -π to π.
+    #[must_use]
+    pub fn bearing_to(&self, other: &Self) -> f64 {
+        normalize_angle((other.y - self.y).atan2(other.x - self.x) - self.heading)
+    }
+}
+
+impl ToJson for Pose {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("x", self.x)
+            .with("y", self.y)
+            .with("heading", self.heading)
+    }
+}
+
+/// Wraps `angle`, in radians, into the range from -π to π.
+#[must_use]
+pub fn normalize_angle(angle: f64) -> f64 {
+    let angle = (angle + PI).rem_euclid(TAU) - PI;
+
+    if angle == -PI {
+        PI
+    } else {
+        angle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+
+    #[test]
+    fn it_should_normalize_angles_into_a_half_turn_either_way() {
+        assert!((normalize_angle(3.0 * PI / 2.0) + FRAC_PI_2).abs() < 1e-12);
+        assert!((normalize_angle(-5.0 * PI / 2.0) + FRAC_PI_2).abs() < 1e-12);
+        assert_eq!(normalize_angle(-PI), PI);
+    }
+
+    #[test]
+    fn it_should_compute_the_distance_and_bearing_to_another_pose() {
+        let pose = Pose::new(1.0, 1.0, FRAC_PI_2);
+        let target = Pose::new(4.0, 5.0, 0.0);
+        assert_eq!(pose.distance_to(&target), 5.0);
+        assert!((pose.bearing_to(&target) - (4.0_f64.atan2(3.0) - FRAC_PI_2)).abs() < 1e-12);
+    }
+}
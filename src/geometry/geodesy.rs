@@ -0,0 +1,124 @@
+//! Positions on the Earth and the flat local frames they are driven in.
+//!
+//! Outdoors, the map is a local east-north-up frame: a plane tangent to the
+//! WGS 84 ellipsoid at an origin, with X to the east and Y to the north, in
+//! metres. Over the few hundred metres a robot covers in a garden or along a
+//! sidewalk, the plane departs from the ellipsoid by millimetres.
+
+use crate::json::{ToJson, Value};
+
+/// Semi-major axis of the WGS 84 ellipsoid, in metres.
+const EQUATORIAL_RADIUS: f64 = 6_378_137.0;
+
+/// Square of the eccentricity of the WGS 84 ellipsoid.
+const ECCENTRICITY_SQUARED: f64 = 6.694_379_990_14e-3;
+
+/// Position on the Earth.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GeoPoint {
+    /// Latitude, in degrees north of the equator.
+    pub latitude: f64,
+    /// Longitude, in degrees east of Greenwich.
+    pub longitude: f64,
+}
+
+impl GeoPoint {
+    /// Creates a new `GeoPoint`.
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+        }
+    }
+
+    /// Returns `true` if the latitude and longitude are in range.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+}
+
+impl ToJson for GeoPoint {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("latitude", self.latitude)
+            .with("longitude", self.longitude)
+    }
+}
+
+/// East-north-up frame tangent to the Earth at an origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LocalFrame {
+    origin: GeoPoint,
+    metres_per_degree_east: f64,
+    metres_per_degree_north: f64,
+}
+
+impl LocalFrame {
+    /// Creates a new `LocalFrame` centred on `origin`.
+    pub fn new(origin: GeoPoint) -> Self {
+        let latitude = origin.latitude.to_radians();
+        let w = 1.0 - ECCENTRICITY_SQUARED * latitude.sin().powi(2);
+        // Radii of curvature along the prime vertical and the meridian.
+        let prime_vertical = EQUATORIAL_RADIUS / w.sqrt();
+        let meridian = EQUATORIAL_RADIUS * (1.0 - ECCENTRICITY_SQUARED) / w.powf(1.5);
+        Self {
+            origin,
+            metres_per_degree_east: (prime_vertical * latitude.cos()).to_radians(),
+            metres_per_degree_north: meridian.to_radians(),
+        }
+    }
+
+    /// Returns the origin.
+    #[must_use]
+    pub fn origin(&self) -> GeoPoint {
+        self.origin
+    }
+
+    /// Returns `point` as metres east and north of the origin.
+    #[must_use]
+    pub fn to_local(&self, point: GeoPoint) -> (f64, f64) {
+        // Wrap across the antimeridian so both sides stay close together.
+        let longitude = (point.longitude - self.origin.longitude + 540.0).rem_euclid(360.0) - 180.0;
+        (
+            longitude * self.metres_per_degree_east,
+            (point.latitude - self.origin.latitude) * self.metres_per_degree_north,
+        )
+    }
+
+    /// Returns the point `east` and `north` metres from the origin.
+    #[must_use]
+    pub fn to_geo(&self, east: f64, north: f64) -> GeoPoint {
+        let longitude = self.origin.longitude + east / self.metres_per_degree_east;
+        GeoPoint {
+            latitude: self.origin.latitude + north / self.metres_per_degree_north,
+            longitude: (longitude + 540.0).rem_euclid(360.0) - 180.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_convert_to_metres_from_the_origin() {
+        let frame = LocalFrame::new(GeoPoint::new(45.0, 7.0));
+        // A degree of latitude at 45° is 111,132 m, and of longitude 78,847 m.
+        let (east, north) = frame.to_local(GeoPoint::new(45.001, 7.001));
+        assert!((east - 78.847).abs() < 0.01, "{east}");
+        assert!((north - 111.132).abs() < 0.01, "{north}");
+        let point = frame.to_geo(east, north);
+        assert!((point.latitude - 45.001).abs() < 1e-12);
+        assert!((point.longitude - 7.001).abs() < 1e-12);
+    }
+
+    #[test]
+    fn it_should_stay_continuous_across_the_antimeridian() {
+        let frame = LocalFrame::new(GeoPoint::new(0.0, 179.9999));
+        let (east, _) = frame.to_local(GeoPoint::new(0.0, -179.9999));
+        assert!((east - 22.264).abs() < 0.01, "{east}");
+        assert!((frame.to_geo(east, 0.0).longitude + 179.9999).abs() < 1e-9);
+        assert!(!GeoPoint::new(91.0, 0.0).is_valid());
+    }
+}
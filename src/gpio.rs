@@ -0,0 +1,10 @@
+//! Backends for driving general-purpose I/O pins.
+
+#[cfg(target_os = "linux")]
+pub mod chardev;
+#[cfg(all(target_os = "linux", feature = "gpiomem"))]
+pub mod fast;
+pub mod pigpio;
+pub mod registry;
+
+pub use registry::Registry;
@@ -0,0 +1,286 @@
+//! GPIO lines requested through the Linux GPIO character device.
+//!
+//! The character device (`/dev/gpiochipN`) is the kernel’s own interface to
+//! GPIO. Each access is a system call, so it is slower than `gpio::fast`,
+//! but the kernel refuses to hand a line to two requesters and releases it
+//! when the process exits. It is the reference `gpio::fast` is checked
+//! against.
+
+use std::ffi::{c_int, c_ulong};
+use std::fs::{File, OpenOptions};
+use std::io::Error;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+use super::registry::{Claim, Line, Registry};
+use crate::hal::{DigitalInput, DigitalOutput};
+use crate::unix::flock::DeviceLock;
+
+/// Name given to the kernel as the consumer of requested lines.
+const CONSUMER: &[u8] = b"otter-pi";
+
+/// Line flag requesting an input.
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+
+/// Line flag requesting an output.
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+
+/// `ioctl` request on a chip that requests lines, `_IOWR(0xB4, 0x07, struct
+/// gpio_v2_line_request)`.
+const GPIO_V2_GET_LINE_IOCTL: c_ulong = 0xC250_B407;
+
+/// `ioctl` request on a line request that reads its values, `_IOWR(0xB4, 0x0E,
+/// struct gpio_v2_line_values)`.
+const GPIO_V2_LINE_GET_VALUES_IOCTL: c_ulong = 0xC010_B40E;
+
+/// `ioctl` request on a line request that writes its values, `_IOWR(0xB4,
+/// 0x0F, struct gpio_v2_line_values)`.
+const GPIO_V2_LINE_SET_VALUES_IOCTL: c_ulong = 0xC010_B40F;
+
+/// `struct gpio_v2_line_attribute`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    value: u64,
+}
+
+/// `struct gpio_v2_line_config_attribute`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    mask: u64,
+}
+
+/// `struct gpio_v2_line_config`.
+#[repr(C)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; 10],
+}
+
+/// `struct gpio_v2_line_request`.
+#[repr(C)]
+struct LineRequest {
+    offsets: [u32; 64],
+    consumer: [u8; 32],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: c_int,
+}
+
+/// `struct gpio_v2_line_values`.
+#[repr(C)]
+struct LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+extern "C" {
+    /// Performs the device-specific `request` on the file referred to by
+    /// `fd`.
+    ///
+    /// Returns a non-negative value on success, or -1 on failure and sets
+    /// `errno` to indicate the error.
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+/// A single line requested from a GPIO chip.
+#[derive(Debug)]
+pub struct ChardevPin {
+    request: File,
+    offset: u32,
+    _claim: Claim<'static>,
+    _device_lock: DeviceLock,
+}
+
+impl ChardevPin {
+    /// Requests line `offset` of `chip`, such as `/dev/gpiochip0`, as an
+    /// output, initially low.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind
+    /// [`ErrorKind::ResourceBusy`](std::io::ErrorKind::ResourceBusy) naming
+    /// the owner if the line is already claimed in this process or another,
+    /// or any other error if the kernel refuses the request.
+    pub fn output(chip: impl AsRef<Path>, offset: u32) -> Result<Self, Error> {
+        Self::request(chip.as_ref(), offset, GPIO_V2_LINE_FLAG_OUTPUT, "output")
+    }
+
+    /// Requests line `offset` of `chip`, such as `/dev/gpiochip0`, as an
+    /// input.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind
+    /// [`ErrorKind::ResourceBusy`](std::io::ErrorKind::ResourceBusy) naming
+    /// the owner if the line is already claimed in this process or another,
+    /// or any other error if the kernel refuses the request.
+    pub fn input(chip: impl AsRef<Path>, offset: u32) -> Result<Self, Error> {
+        Self::request(chip.as_ref(), offset, GPIO_V2_LINE_FLAG_INPUT, "input")
+    }
+
+    fn request(chip: &Path, offset: u32, flags: u64, usage: &str) -> Result<Self, Error> {
+        let name = chip.file_name().unwrap_or(chip.as_os_str());
+        let line = Line::new(&name.to_string_lossy(), offset);
+        let claim = Registry::global().claim(line, "chardev", usage)?;
+        let device_lock = DeviceLock::acquire(chip.join(format!("line{offset}")))?;
+        let file = OpenOptions::new().read(true).write(true).open(chip)?;
+        let mut request = line_request(offset, flags);
+
+        if unsafe { ioctl(file.as_raw_fd(), GPIO_V2_GET_LINE_IOCTL, &mut request) } == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(Self {
+            request: unsafe { File::from_raw_fd(request.fd) },
+            offset,
+            _claim: claim,
+            _device_lock: device_lock,
+        })
+    }
+
+    /// Returns the offset of the line on its chip.
+    #[must_use]
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+}
+
+impl DigitalOutput for ChardevPin {
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.set_level(true)
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.set_level(false)
+    }
+
+    fn set_level(&mut self, high: bool) -> Result<(), Error> {
+        let mut values = LineValues {
+            bits: u64::from(high),
+            mask: 1,
+        };
+
+        if unsafe {
+            ioctl(
+                self.request.as_raw_fd(),
+                GPIO_V2_LINE_SET_VALUES_IOCTL,
+                &mut values,
+            )
+        } == -1
+        {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+impl DigitalInput for ChardevPin {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        let mut values = LineValues { bits: 0, mask: 1 };
+
+        if unsafe {
+            ioctl(
+                self.request.as_raw_fd(),
+                GPIO_V2_LINE_GET_VALUES_IOCTL,
+                &mut values,
+            )
+        } == -1
+        {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(values.bits & 1 != 0)
+    }
+}
+
+/// Returns a request for the single line `offset` with `flags`.
+fn line_request(offset: u32, flags: u64) -> LineRequest {
+    let mut offsets = [0; 64];
+    offsets[0] = offset;
+    let mut consumer = [0; 32];
+    consumer[..CONSUMER.len()].copy_from_slice(CONSUMER);
+    let attribute = LineConfigAttribute {
+        attr: LineAttribute {
+            id: 0,
+            padding: 0,
+            value: 0,
+        },
+        mask: 0,
+    };
+    LineRequest {
+        offsets,
+        consumer,
+        config: LineConfig {
+            flags,
+            num_attrs: 0,
+            padding: [0; 5],
+            attrs: [attribute; 10],
+        },
+        num_lines: 1,
+        event_buffer_size: 0,
+        padding: [0; 5],
+        fd: -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_lay_out_requests_as_the_kernel_does() {
+        assert_eq!(mem::size_of::<LineRequest>(), 592);
+        assert_eq!(mem::size_of::<LineValues>(), 16);
+        assert_eq!(GPIO_V2_GET_LINE_IOCTL >> 16 & 0x3FFF, 592);
+        assert_eq!(GPIO_V2_LINE_GET_VALUES_IOCTL >> 16 & 0x3FFF, 16);
+        let request = line_request(17, GPIO_V2_LINE_FLAG_OUTPUT);
+        assert_eq!((request.offsets[0], request.num_lines), (17, 1));
+        assert_eq!(&request.consumer[..9], b"otter-pi\0");
+    }
+
+    #[test]
+    fn it_should_release_its_claim_when_the_request_fails() {
+        let directory = TemporaryDirectory::new().unwrap();
+        let chip = directory.path().join("gpiochip-test");
+        File::create(&chip).unwrap();
+        assert!(ChardevPin::input(&chip, 5).is_err());
+        let owner = Registry::global().owner(&Line::new("gpiochip-test", 5));
+        assert_eq!(owner, None);
+    }
+
+    /// Checks `gpio::fast` against the kernel’s view of the same pin. Run it
+    /// on a Raspberry Pi with `OTTER_TEST_PIN` naming a free BCM pin.
+    #[cfg(feature = "gpiomem")]
+    #[test]
+    #[ignore = "needs /dev/gpiomem, /dev/gpiochip0, and a free pin in OTTER_TEST_PIN"]
+    fn it_should_agree_with_the_fast_path() {
+        use super::super::fast::GpioMem;
+
+        let pin: u8 = std::env::var("OTTER_TEST_PIN")
+            .expect("OTTER_TEST_PIN should name a free BCM pin")
+            .parse()
+            .unwrap();
+        let mem = unsafe { GpioMem::open() }.unwrap();
+        let mut line = ChardevPin::output("/dev/gpiochip0", u32::from(pin)).unwrap();
+
+        for high in [true, false, true, false] {
+            line.set_level(high).unwrap();
+            assert_eq!(mem.read_level(pin), high, "fast path read");
+            mem.write_level(pin, !high);
+            assert_eq!(line.is_high().unwrap(), !high, "character device read");
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Direct register access to the Raspberry Pi’s GPIO block via `/dev/gpiomem`.
+//!
+//! Toggling a pin through a register write takes tens of nanoseconds instead of
+//! the microseconds taken by a system call, which bit-banged protocols such as
+//! the HX711 and DHT22 rely on. The registers are shared with the kernel and
+//! every other process, so nothing prevents another driver from
+//! reconfiguring a pin in use here, but each [`FastPin`] claims its line
+//! with a [`DeviceLock`] so that cooperating processes refuse to share it.
+//! `gpio::chardev` pins take the same claims, and serve as the reference
+//! the register access is checked against on hardware.
+
+use std::ffi::{c_int, c_long, c_void};
+use std::fs::OpenOptions;
+use std::io::Error;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr::{self, NonNull};
+use std::sync::{Arc, Mutex};
+
+use super::registry::{Claim, Line, Registry};
+use crate::hal::{DigitalInput, DigitalOutput};
+use crate::unix::flock::{DeviceLock, LOCK_DIR};
+#[cfg(test)]
+use crate::unix::temporary_directory::TemporaryDirectory;
+
+extern "C" {
+    /// Maps `length` bytes of the file referred to by `fd`, starting at
+    /// `offset`, into the address space of the calling process.
+    ///
+    /// Returns the address of the mapping on success, or `MAP_FAILED` on
+    /// failure and sets `errno` to indicate the error.
+    fn mmap(
+        addr: *mut c_void,
+        length: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: c_long,
+    ) -> *mut c_void;
+
+    /// Removes the mapping of `length` bytes starting at `addr`.
+    ///
+    /// Returns 0 on success, or -1 on failure and sets `errno` to indicate the
+    /// error.
+    fn munmap(addr: *mut c_void, length: usize) -> c_int;
+}
+
+/// Memory protection for `mmap` that allows reads.
+const PROT_READ: c_int = 1;
+
+/// Memory protection for `mmap` that allows writes.
+const PROT_WRITE: c_int = 2;
+
+/// Flag for `mmap` that makes writes visible to the underlying device.
+const MAP_SHARED: c_int = 1;
+
+/// Size of the GPIO register block exposed by `/dev/gpiomem`, in bytes.
+const BLOCK_SIZE: usize = 4096;
+
+/// GPIO chip whose lines the register block drives, named in line claims.
+const GPIO_CHIP: &str = "/dev/gpiochip0";
+
+/// Number of GPIO pins in the register block.
+pub const PIN_COUNT: u8 = 54;
+
+/// Word offset of the first function select register.
+const GPFSEL0: usize = 0x00;
+
+/// Word offset of the first output set register.
+const GPSET0: usize = 0x1C >> 2;
+
+/// Word offset of the first output clear register.
+const GPCLR0: usize = 0x28 >> 2;
+
+/// Word offset of the first pin level register.
+const GPLEV0: usize = 0x34 >> 2;
+
+/// Function of a GPIO pin, as encoded in the function select registers.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Function {
+    /// Digital input.
+    Input = 0b000,
+    /// Digital output.
+    Output = 0b001,
+}
+
+/// A mapping of the GPIO register block.
+#[derive(Debug)]
+pub struct GpioMem {
+    base: NonNull<u32>,
+    /// Serializes read-modify-write updates of the function select registers.
+    function_select: Mutex<()>,
+    /// Directory holding the claims on pins.
+    lock_dir: PathBuf,
+    #[cfg(test)]
+    _backing: Option<(Box<[u32]>, TemporaryDirectory)>,
+}
+
+// The mapping is only accessed with volatile reads and writes of whole
+// registers, and function select updates are serialized by a mutex.
+unsafe impl Send for GpioMem {}
+unsafe impl Sync for GpioMem {}
+
+impl GpioMem {
+    /// Maps the GPIO register block from `/dev/gpiomem`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that no other driver, in this process or any
+    /// other, reconfigures or drives the pins used through the mapping.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `/dev/gpiomem` cannot be opened
+    /// or mapped.
+    pub unsafe fn open() -> Result<Arc<Self>, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/gpiomem")?;
+        let address = mmap(
+            ptr::null_mut(),
+            BLOCK_SIZE,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+
+        if address as isize == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(Arc::new(Self {
+            base: NonNull::new(address.cast()).ok_or_else(Error::last_os_error)?,
+            function_select: Mutex::new(()),
+            lock_dir: PathBuf::from(LOCK_DIR),
+            #[cfg(test)]
+            _backing: None,
+        }))
+    }
+
+    /// Configures the function of `pin`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn set_function(&self, pin: u8, function: Function) {
+        assert!(pin < PIN_COUNT, "pin should be less than {PIN_COUNT}");
+        let register = GPFSEL0 + usize::from(pin / 10);
+        let shift = u32::from(pin % 10) * 3;
+        let _guard = self
+            .function_select
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let value = self.read(register) & !(0b111 << shift);
+        self.write(register, value | ((function as u32) << shift));
+    }
+
+    /// Returns the function of `pin`, or `None` if it is set to an alternate
+    /// function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    #[must_use]
+    pub fn function(&self, pin: u8) -> Option<Function> {
+        assert!(pin < PIN_COUNT, "pin should be less than {PIN_COUNT}");
+        let register = GPFSEL0 + usize::from(pin / 10);
+        let shift = u32::from(pin % 10) * 3;
+
+        match (self.read(register) >> shift) & 0b111 {
+            0b000 => Some(Function::Input),
+            0b001 => Some(Function::Output),
+            _ => None,
+        }
+    }
+
+    /// Drives `pin` high or low.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn write_level(&self, pin: u8, high: bool) {
+        assert!(pin < PIN_COUNT, "pin should be less than {PIN_COUNT}");
+        let (offset, bit) = bank(pin);
+        let register = if high { GPSET0 } else { GPCLR0 };
+        self.write(register + offset, bit);
+    }
+
+    /// Returns `true` if `pin` is high.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    #[must_use]
+    pub fn read_level(&self, pin: u8) -> bool {
+        assert!(pin < PIN_COUNT, "pin should be less than {PIN_COUNT}");
+        let (offset, bit) = bank(pin);
+        self.read(GPLEV0 + offset) & bit != 0
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { ptr::read_volatile(self.base.as_ptr().add(register)) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.base.as_ptr().add(register), value) }
+    }
+
+    #[cfg(test)]
+    fn mock() -> Arc<Self> {
+        let mut backing = vec![0; BLOCK_SIZE / 4].into_boxed_slice();
+        let lock_dir = TemporaryDirectory::new().unwrap();
+        Arc::new(Self {
+            base: NonNull::new(backing.as_mut_ptr()).unwrap(),
+            function_select: Mutex::new(()),
+            lock_dir: lock_dir.path().to_owned(),
+            _backing: Some((backing, lock_dir)),
+        })
+    }
+}
+
+impl Drop for GpioMem {
+    fn drop(&mut self) {
+        #[cfg(test)]
+        if self._backing.is_some() {
+            return;
+        }
+
+        unsafe { munmap(self.base.as_ptr().cast(), BLOCK_SIZE) };
+    }
+}
+
+/// A single pin driven through the mapped registers.
+///
+/// Cloning gives another handle to the same claimed pin.
+#[derive(Clone, Debug)]
+pub struct FastPin {
+    mem: Arc<GpioMem>,
+    pin: u8,
+    _claim: Arc<Claim<'static>>,
+    _device_lock: Arc<DeviceLock>,
+}
+
+impl FastPin {
+    /// Claims `pin` and configures it as an output.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind
+    /// [`ErrorKind::ResourceBusy`](std::io::ErrorKind::ResourceBusy) naming
+    /// the owner if the pin is already claimed in this process or another.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn output(mem: Arc<GpioMem>, pin: u8) -> Result<Self, Error> {
+        Self::claim(mem, pin, Function::Output)
+    }
+
+    /// Claims `pin` and configures it as an input.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind
+    /// [`ErrorKind::ResourceBusy`](std::io::ErrorKind::ResourceBusy) naming
+    /// the owner if the pin is already claimed in this process or another.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` is not less than [`PIN_COUNT`].
+    pub fn input(mem: Arc<GpioMem>, pin: u8) -> Result<Self, Error> {
+        Self::claim(mem, pin, Function::Input)
+    }
+
+    fn claim(mem: Arc<GpioMem>, pin: u8, function: Function) -> Result<Self, Error> {
+        assert!(pin < PIN_COUNT, "pin should be less than {PIN_COUNT}");
+        let usage = match function {
+            Function::Input => "input",
+            Function::Output => "output",
+        };
+        let claim = Registry::global().claim(Line::bcm(u32::from(pin)), "gpiomem", usage)?;
+        let device = Path::new(GPIO_CHIP).join(format!("line{pin}"));
+        let device_lock = DeviceLock::acquire_in(&mem.lock_dir, device)?;
+        mem.set_function(pin, function);
+        Ok(Self {
+            mem,
+            pin,
+            _claim: Arc::new(claim),
+            _device_lock: Arc::new(device_lock),
+        })
+    }
+
+    /// Returns the Broadcom number of the pin.
+    #[must_use]
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+}
+
+impl DigitalOutput for FastPin {
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.mem.write_level(self.pin, true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.mem.write_level(self.pin, false);
+        Ok(())
+    }
+}
+
+impl DigitalInput for FastPin {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        Ok(self.mem.read_level(self.pin))
+    }
+}
+
+/// Returns the word offset of the bank containing `pin` and its bit within the
+/// bank.
+fn bank(pin: u8) -> (usize, u32) {
+    (usize::from(pin / 32), 1 << (pin % 32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_set_the_function_select_bits_of_a_pin() {
+        let mem = GpioMem::mock();
+        mem.set_function(17, Function::Output);
+        assert_eq!(mem.read(GPFSEL0 + 1), 0b001 << 21);
+        assert_eq!(mem.function(17), Some(Function::Output));
+    }
+
+    #[test]
+    fn it_should_preserve_the_functions_of_neighboring_pins() {
+        let mem = GpioMem::mock();
+        mem.set_function(10, Function::Output);
+        mem.set_function(12, Function::Output);
+        mem.set_function(10, Function::Input);
+        assert_eq!(mem.function(10), Some(Function::Input));
+        assert_eq!(mem.function(12), Some(Function::Output));
+    }
+
+    #[test]
+    fn it_should_report_an_alternate_function_as_none() {
+        let mem = GpioMem::mock();
+        mem.write(GPFSEL0 + 1, 0b010 << 24);
+        assert_eq!(mem.function(18), None);
+    }
+
+    #[test]
+    fn it_should_write_to_the_set_and_clear_registers_of_the_pin_bank() {
+        let mem = GpioMem::mock();
+        let mut pin = FastPin::output(Arc::clone(&mem), 40).unwrap();
+        pin.set_high().unwrap();
+        assert_eq!(mem.read(GPSET0 + 1), 1 << 8);
+        pin.set_low().unwrap();
+        assert_eq!(mem.read(GPCLR0 + 1), 1 << 8);
+    }
+
+    #[test]
+    fn it_should_read_from_the_level_register_of_the_pin_bank() {
+        let mem = GpioMem::mock();
+        let mut pin = FastPin::input(Arc::clone(&mem), 4).unwrap();
+        assert!(pin.is_low().unwrap());
+        mem.write(GPLEV0, 1 << 4);
+        assert!(pin.is_high().unwrap());
+        assert_eq!(pin.pin(), 4);
+    }
+
+    #[test]
+    fn it_should_refuse_a_pin_that_is_already_claimed() {
+        let mem = GpioMem::mock();
+        let pin = FastPin::output(Arc::clone(&mem), 17).unwrap();
+        let error = FastPin::input(Arc::clone(&mem), 17).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ResourceBusy);
+        assert_eq!(
+            error.to_string(),
+            "pin BCM17 already claimed by gpiomem output"
+        );
+        drop(pin.clone());
+        assert!(FastPin::input(Arc::clone(&mem), 17).is_err());
+        drop(pin);
+        assert!(FastPin::input(mem, 17).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "pin should be less than 54")]
+    fn it_should_panic_for_a_pin_outside_the_register_block() {
+        let _ = FastPin::output(GpioMem::mock(), 54);
+    }
+}
@@ -0,0 +1,460 @@
+//! DMA-timed pulse generation through the pigpio daemon’s socket interface.
+//!
+//! The pigpio daemon (`pigpiod`) drives GPIO from DMA-paced waveforms, which
+//! gives microsecond-accurate pulses for servos and bit-banged protocols where
+//! sysfs or software PWM jitter is unacceptable. Commands are sent over its
+//! socket protocol, so no C library needs to be linked.
+
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::log_event;
+use crate::logging::{self, Level};
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "pigpio";
+
+/// Default address on which the pigpio daemon listens.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8888";
+
+/// Highest Broadcom GPIO number that user code may drive.
+const MAX_USER_GPIO: u32 = 31;
+
+/// Servo pulse widths accepted by the daemon, in microseconds.
+const SERVO_PULSE_WIDTHS: std::ops::RangeInclusive<u32> = 500..=2500;
+
+const CMD_MODES: u32 = 0;
+const CMD_WRITE: u32 = 4;
+const CMD_SERVO: u32 = 8;
+const CMD_HWVER: u32 = 17;
+const CMD_WVCLR: u32 = 27;
+const CMD_WVAG: u32 = 28;
+const CMD_WVBSY: u32 = 32;
+const CMD_WVHLT: u32 = 33;
+const CMD_WVCRE: u32 = 49;
+const CMD_WVDEL: u32 = 50;
+const CMD_WVTX: u32 = 51;
+const CMD_WVTXR: u32 = 52;
+
+/// Function of a GPIO pin.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Mode {
+    /// Digital input.
+    Input,
+    /// Digital output.
+    Output,
+}
+
+/// A single step of a waveform.
+///
+/// Pins in `on_mask` are set high and pins in `off_mask` are set low at the
+/// start of the step, after which the waveform waits for `delay`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Pulse {
+    /// Wait after applying the pin levels.
+    pub delay: Duration,
+    /// Bit mask of pins to set low.
+    pub off_mask: u32,
+    /// Bit mask of pins to set high.
+    pub on_mask: u32,
+}
+
+impl Pulse {
+    /// Creates a step that sets `pin` high and then waits for `delay`.
+    pub fn high(pin: u32, delay: Duration) -> Self {
+        Self {
+            delay,
+            off_mask: 0,
+            on_mask: 1 << pin,
+        }
+    }
+
+    /// Creates a step that sets `pin` low and then waits for `delay`.
+    pub fn low(pin: u32, delay: Duration) -> Self {
+        Self {
+            delay,
+            off_mask: 1 << pin,
+            on_mask: 0,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 12] {
+        let delay = u32::try_from(self.delay.as_micros()).unwrap_or(u32::MAX);
+        let mut bytes = [0; 12];
+        bytes[..4].copy_from_slice(&self.on_mask.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.off_mask.to_le_bytes());
+        bytes[8..].copy_from_slice(&delay.to_le_bytes());
+        bytes
+    }
+}
+
+/// Identifier of a waveform created by the daemon.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct WaveId(u32);
+
+/// Client for the pigpio daemon.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use otter_pi::gpio::pigpio::{Mode, Pigpio, Pulse};
+///
+/// let mut pigpio = Pigpio::connect_default().unwrap();
+/// pigpio.set_mode(18, Mode::Output).unwrap();
+/// pigpio.wave_clear().unwrap();
+/// pigpio
+///     .wave_add(&[
+///         Pulse::high(18, Duration::from_micros(10)),
+///         Pulse::low(18, Duration::from_micros(90)),
+///     ])
+///     .unwrap();
+/// let wave = pigpio.wave_create().unwrap();
+/// pigpio.wave_send_repeat(wave).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Pigpio<S = TcpStream> {
+    stream: S,
+}
+
+impl Pigpio<TcpStream> {
+    /// Connects to the pigpio daemon at `address`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the connection fails.
+    pub fn connect(address: impl ToSocketAddrs) -> Result<Self, Error> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Connects to the pigpio daemon at its default local address.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the connection fails.
+    pub fn connect_default() -> Result<Self, Error> {
+        Self::connect(DEFAULT_ADDRESS)
+    }
+}
+
+impl<S: Read + Write> Pigpio<S> {
+    /// Creates a client that communicates over an existing stream.
+    pub fn from_stream(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// Returns the hardware revision of the Raspberry Pi.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command fails.
+    pub fn hardware_revision(&mut self) -> Result<u32, Error> {
+        self.command(CMD_HWVER, 0, 0, &[])
+    }
+
+    /// Sets the function of `pin`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `pin` is not a user GPIO or the
+    /// command fails.
+    pub fn set_mode(&mut self, pin: u32, mode: Mode) -> Result<(), Error> {
+        let mode = match mode {
+            Mode::Input => 0,
+            Mode::Output => 1,
+        };
+
+        self.command(CMD_MODES, check_pin(pin)?, mode, &[])
+            .map(drop)
+    }
+
+    /// Drives `pin` high or low.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `pin` is not a user GPIO or the
+    /// command fails.
+    pub fn write(&mut self, pin: u32, high: bool) -> Result<(), Error> {
+        self.command(CMD_WRITE, check_pin(pin)?, u32::from(high), &[])
+            .map(drop)
+    }
+
+    /// Starts DMA-timed servo pulses of `width` on `pin`, or stops them if
+    /// `width` is zero.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `pin` is not a user GPIO, if
+    /// `width` is outside 500–2500 µs, or if the command fails.
+    pub fn set_servo_pulse_width(&mut self, pin: u32, width: Duration) -> Result<(), Error> {
+        let width = u32::try_from(width.as_micros()).unwrap_or(u32::MAX);
+
+        if width != 0 && !SERVO_PULSE_WIDTHS.contains(&width) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("servo pulse width of {width} µs is outside 500–2500 µs"),
+            ));
+        }
+
+        self.command(CMD_SERVO, check_pin(pin)?, width, &[])
+            .map(drop)
+    }
+
+    /// Clears every waveform and any pulses that have not been created into
+    /// a waveform.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command fails.
+    pub fn wave_clear(&mut self) -> Result<(), Error> {
+        self.command(CMD_WVCLR, 0, 0, &[]).map(drop)
+    }
+
+    /// Adds `pulses` to the waveform being built, merging them with any pulses
+    /// already added, and returns the total number of pulses.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command fails.
+    pub fn wave_add(&mut self, pulses: &[Pulse]) -> Result<u32, Error> {
+        let data: Vec<u8> = pulses.iter().flat_map(|pulse| pulse.to_bytes()).collect();
+        self.command(CMD_WVAG, 0, 0, &data)
+    }
+
+    /// Creates a waveform from the pulses added since it was last cleared or
+    /// created.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there are no pulses or the
+    /// daemon is out of waveform resources.
+    pub fn wave_create(&mut self) -> Result<WaveId, Error> {
+        self.command(CMD_WVCRE, 0, 0, &[]).map(WaveId)
+    }
+
+    /// Deletes a waveform, releasing its resources.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command fails.
+    pub fn wave_delete(&mut self, wave: WaveId) -> Result<(), Error> {
+        self.command(CMD_WVDEL, wave.0, 0, &[]).map(drop)
+    }
+
+    /// Transmits a waveform once.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command fails.
+    pub fn wave_send_once(&mut self, wave: WaveId) -> Result<(), Error> {
+        self.command(CMD_WVTX, wave.0, 0, &[]).map(drop)
+    }
+
+    /// Transmits a waveform repeatedly until halted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command fails.
+    pub fn wave_send_repeat(&mut self, wave: WaveId) -> Result<(), Error> {
+        self.command(CMD_WVTXR, wave.0, 0, &[]).map(drop)
+    }
+
+    /// Returns `true` if a waveform is being transmitted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command fails.
+    pub fn wave_busy(&mut self) -> Result<bool, Error> {
+        self.command(CMD_WVBSY, 0, 0, &[]).map(|busy| busy == 1)
+    }
+
+    /// Stops transmitting the current waveform.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the command fails.
+    pub fn wave_halt(&mut self) -> Result<(), Error> {
+        self.command(CMD_WVHLT, 0, 0, &[]).map(drop)
+    }
+
+    /// Sends a command and returns its non-negative result.
+    ///
+    /// Requests are four little-endian 32-bit words: the command, two
+    /// parameters, and the length of any extension data that follows.
+    /// Responses echo the first three words and end with the signed result.
+    fn command(&mut self, command: u32, p1: u32, p2: u32, extension: &[u8]) -> Result<u32, Error> {
+        let _span = logging::transaction_span(SUBSYSTEM, "command");
+        let length = u32::try_from(extension.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "extension data is too long"))?;
+        let mut request = Vec::with_capacity(16 + extension.len());
+
+        for word in [command, p1, p2, length] {
+            request.extend_from_slice(&word.to_le_bytes());
+        }
+
+        request.extend_from_slice(extension);
+        self.stream.write_all(&request)?;
+        self.stream.flush()?;
+
+        let mut response = [0; 16];
+        self.stream.read_exact(&mut response)?;
+        let result = i32::from_le_bytes([response[12], response[13], response[14], response[15]]);
+        log_event!(
+            SUBSYSTEM,
+            Level::Trace,
+            "command {command} returned {result}"
+        );
+
+        u32::try_from(result).map_err(|_| {
+            Error::other(format!(
+                "pigpio command {command} failed with error {result}"
+            ))
+        })
+    }
+}
+
+fn check_pin(pin: u32) -> Result<u32, Error> {
+    if pin > MAX_USER_GPIO {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("GPIO {pin} is not a user GPIO"),
+        ))
+    } else {
+        Ok(pin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct MockStream {
+        requests: Vec<u8>,
+        responses: Cursor<Vec<u8>>,
+    }
+
+    impl MockStream {
+        fn with_results(results: &[i32]) -> Self {
+            let responses = results
+                .iter()
+                .flat_map(|result| {
+                    let mut response = [0; 16];
+                    response[12..].copy_from_slice(&result.to_le_bytes());
+                    response
+                })
+                .collect();
+
+            Self {
+                requests: Vec::new(),
+                responses: Cursor::new(responses),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.responses.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.requests.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn it_should_encode_a_command_as_four_little_endian_words() {
+        let mut pigpio = Pigpio::from_stream(MockStream::with_results(&[0]));
+        pigpio.write(18, true).unwrap();
+        assert_eq!(words(&pigpio.stream.requests), [CMD_WRITE, 18, 1, 0]);
+    }
+
+    #[test]
+    fn it_should_append_pulses_as_extension_data() {
+        let mut pigpio = Pigpio::from_stream(MockStream::with_results(&[2]));
+        let pulses = [
+            Pulse::high(4, Duration::from_micros(10)),
+            Pulse::low(4, Duration::from_micros(20)),
+        ];
+        assert_eq!(pigpio.wave_add(&pulses).unwrap(), 2);
+        assert_eq!(
+            words(&pigpio.stream.requests),
+            [CMD_WVAG, 0, 0, 24, 1 << 4, 0, 10, 0, 1 << 4, 20]
+        );
+    }
+
+    #[test]
+    fn it_should_return_the_created_wave_id() {
+        let mut pigpio = Pigpio::from_stream(MockStream::with_results(&[3]));
+        assert_eq!(pigpio.wave_create().unwrap(), WaveId(3));
+    }
+
+    #[test]
+    fn it_should_report_whether_a_wave_is_busy() {
+        let mut pigpio = Pigpio::from_stream(MockStream::with_results(&[1, 0]));
+        assert!(pigpio.wave_busy().unwrap());
+        assert!(!pigpio.wave_busy().unwrap());
+    }
+
+    #[test]
+    fn it_should_return_an_error_for_a_negative_result() {
+        let mut pigpio = Pigpio::from_stream(MockStream::with_results(&[-3]));
+        let error = pigpio.set_mode(4, Mode::Output).unwrap_err();
+        assert_eq!(error.to_string(), "pigpio command 0 failed with error -3");
+    }
+
+    #[test]
+    fn it_should_return_an_error_when_the_daemon_disconnects() {
+        let mut pigpio = Pigpio::from_stream(MockStream::default());
+        let error = pigpio.hardware_revision().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn it_should_reject_a_pin_that_is_not_a_user_gpio() {
+        let mut pigpio = Pigpio::from_stream(MockStream::default());
+        let error = pigpio.write(32, true).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(pigpio.stream.requests.is_empty());
+    }
+
+    #[test]
+    fn it_should_send_servo_pulse_widths_in_microseconds() {
+        let mut pigpio = Pigpio::from_stream(MockStream::with_results(&[0, 0]));
+        pigpio
+            .set_servo_pulse_width(17, Duration::from_micros(1500))
+            .unwrap();
+        pigpio.set_servo_pulse_width(17, Duration::ZERO).unwrap();
+        assert_eq!(
+            words(&pigpio.stream.requests),
+            [CMD_SERVO, 17, 1500, 0, CMD_SERVO, 17, 0, 0]
+        );
+    }
+
+    #[test]
+    fn it_should_reject_servo_pulse_widths_out_of_range() {
+        let mut pigpio = Pigpio::from_stream(MockStream::default());
+        assert!(pigpio
+            .set_servo_pulse_width(17, Duration::from_micros(3000))
+            .is_err());
+    }
+}
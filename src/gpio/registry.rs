@@ -0,0 +1,221 @@
+//! Ownership of GPIO lines within the process.
+//!
+//! Two drivers configured onto the same pin both appear to work while they
+//! fight over it. Drivers claim their lines from a [`Registry`] when they are
+//! created, so a second claim fails with an error naming the first owner
+//! instead. A claim is released when its [`Claim`] is dropped.
+//!
+//! Backends that know their lines, such as `gpio::fast` and `gpio::chardev`
+//! pins and the coprocessor’s remote outputs, claim them themselves. Drivers
+//! given a pin from a backend that does not, such as a GPIO expander, claim
+//! the line with their `claim` constructor.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind};
+use std::sync::{Mutex, OnceLock};
+
+/// Name of the chip carrying the Raspberry Pi’s own GPIO pins.
+const BCM_CHIP: &str = "gpiochip0";
+
+/// A GPIO line, identified by its chip and offset.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Line {
+    /// Name of the chip, such as `gpiochip0`.
+    pub chip: String,
+    /// Offset of the line on the chip.
+    pub offset: u32,
+}
+
+impl Line {
+    /// Creates a new `Line` at `offset` on `chip`.
+    pub fn new(chip: &str, offset: u32) -> Self {
+        Self {
+            chip: chip.to_owned(),
+            offset,
+        }
+    }
+
+    /// Returns the Raspberry Pi’s own pin with the Broadcom number `pin`.
+    #[must_use]
+    pub fn bcm(pin: u32) -> Self {
+        Self::new(BCM_CHIP, pin)
+    }
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.chip == BCM_CHIP {
+            write!(f, "pin BCM{}", self.offset)
+        } else {
+            write!(f, "line {} of {}", self.offset, self.chip)
+        }
+    }
+}
+
+/// The module holding a claim, and what it uses the line for.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Owner {
+    /// Name of the module, such as `left_motor`.
+    pub name: String,
+    /// What the line is used for, such as `PWM`.
+    pub usage: String,
+}
+
+/// An error claiming a line that is already claimed.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ClaimError {
+    /// The line.
+    pub line: Line,
+    /// The module that already holds it.
+    pub owner: Owner,
+}
+
+impl Display for ClaimError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} already claimed by {} {}",
+            self.line, self.owner.name, self.owner.usage
+        )
+    }
+}
+
+impl Error for ClaimError {}
+
+impl From<ClaimError> for io::Error {
+    fn from(error: ClaimError) -> Self {
+        Self::new(ErrorKind::ResourceBusy, error)
+    }
+}
+
+/// Tracks which module has claimed each line.
+#[derive(Debug, Default)]
+pub struct Registry {
+    claims: Mutex<BTreeMap<Line, Owner>>,
+}
+
+impl Registry {
+    /// Creates a new, empty `Registry`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the registry shared by the whole process.
+    pub fn global() -> &'static Self {
+        static REGISTRY: OnceLock<Registry> = OnceLock::new();
+        REGISTRY.get_or_init(Self::new)
+    }
+
+    /// Claims `line` for the module `name` to use for `usage`, until the
+    /// returned [`Claim`] is dropped.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the line is already claimed.
+    pub fn claim(&self, line: Line, name: &str, usage: &str) -> Result<Claim<'_>, ClaimError> {
+        let mut claims = self.claims.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(owner) = claims.get(&line) {
+            return Err(ClaimError {
+                line,
+                owner: owner.clone(),
+            });
+        }
+
+        claims.insert(
+            line.clone(),
+            Owner {
+                name: name.to_owned(),
+                usage: usage.to_owned(),
+            },
+        );
+        Ok(Claim {
+            line,
+            registry: self,
+        })
+    }
+
+    /// Returns the module holding `line`, if it is claimed.
+    #[must_use]
+    pub fn owner(&self, line: &Line) -> Option<Owner> {
+        self.claims
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(line)
+            .cloned()
+    }
+
+    /// Returns every claimed line with its owner, sorted by line.
+    #[must_use]
+    pub fn claims(&self) -> Vec<(Line, Owner)> {
+        self.claims
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(line, owner)| (line.clone(), owner.clone()))
+            .collect()
+    }
+}
+
+/// A claim on a line, released when dropped.
+#[derive(Debug)]
+#[must_use = "the line is released as soon as the claim is dropped"]
+pub struct Claim<'a> {
+    line: Line,
+    registry: &'a Registry,
+}
+
+impl Claim<'_> {
+    /// Returns the claimed line.
+    #[must_use]
+    pub fn line(&self) -> &Line {
+        &self.line
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.registry
+            .claims
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_refuse_a_second_claim_naming_the_owner() {
+        let registry = Registry::new();
+        let _claim = registry.claim(Line::bcm(18), "left_motor", "PWM").unwrap();
+        let error = registry
+            .claim(Line::bcm(18), "buzzer", "output")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "pin BCM18 already claimed by left_motor PWM"
+        );
+        assert!(registry
+            .claim(Line::new("gpiochip1", 18), "expander", "input")
+            .is_ok());
+    }
+
+    #[test]
+    fn it_should_release_a_line_when_its_claim_is_dropped() {
+        let registry = Registry::new();
+        let claim = registry.claim(Line::bcm(4), "pir", "input").unwrap();
+        assert_eq!(registry.claims().len(), 1);
+        assert_eq!(
+            registry.owner(claim.line()).map(|owner| owner.name),
+            Some("pir".to_owned())
+        );
+        drop(claim);
+        assert_eq!(registry.owner(&Line::bcm(4)), None);
+        assert!(registry.claim(Line::bcm(4), "hall", "input").is_ok());
+    }
+}
@@ -0,0 +1,378 @@
+//! Hardware abstraction traits implemented by device backends.
+//!
+//! Drivers are written against these traits rather than a particular backend,
+//! so the same driver works over native GPIO, an I/O expander, or a mock in
+//! tests.
+
+use std::io::Error;
+use std::time::Duration;
+
+/// A pin that can be driven high or low.
+pub trait DigitalOutput {
+    /// Drives the pin high.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be driven.
+    fn set_high(&mut self) -> Result<(), Error>;
+
+    /// Drives the pin low.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be driven.
+    fn set_low(&mut self) -> Result<(), Error>;
+
+    /// Drives the pin high if `high` is `true`, or low otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be driven.
+    fn set_level(&mut self, high: bool) -> Result<(), Error> {
+        if high {
+            self.set_high()
+        } else {
+            self.set_low()
+        }
+    }
+}
+
+/// A pin whose level can be read.
+pub trait DigitalInput {
+    /// Returns `true` if the pin is high.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be read.
+    fn is_high(&mut self) -> Result<bool, Error>;
+
+    /// Returns `true` if the pin is low.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be read.
+    fn is_low(&mut self) -> Result<bool, Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+/// Direction of a change in the level of a pin.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Edge {
+    /// The pin went from low to high.
+    Rising,
+    /// The pin went from high to low.
+    Falling,
+}
+
+/// A pulse-width modulated output.
+pub trait PwmOutput {
+    /// Returns the frequency of the output, in hertz.
+    fn frequency(&self) -> f64;
+
+    /// Sets the fraction of each period for which the output is high, from 0
+    /// to 1.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    fn set_duty_cycle(&mut self, duty_cycle: f64) -> Result<(), Error>;
+
+    /// Sets how long the output is high in each period, as used to position a
+    /// servo.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output cannot be driven.
+    fn set_pulse_width(&mut self, width: Duration) -> Result<(), Error> {
+        self.set_duty_cycle(width.as_secs_f64() * self.frequency())
+    }
+}
+
+/// A pulse-width modulated output whose frequency can be changed on its own,
+/// such as one driving a passive buzzer.
+pub trait AdjustableFrequency: PwmOutput {
+    /// Sets the frequency of the output, in hertz, keeping the duty cycle.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the frequency is not supported
+    /// or the output cannot be driven.
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), Error>;
+}
+
+/// A motor driven at a signed fraction of full power.
+pub trait Motor {
+    /// Drives the motor at `speed`, from -1 for full reverse to 1 for full
+    /// forward.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the motor cannot be driven.
+    fn set_speed(&mut self, speed: f64) -> Result<(), Error>;
+
+    /// Stops driving the motor.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the motor cannot be driven.
+    fn stop(&mut self) -> Result<(), Error> {
+        self.set_speed(0.0)
+    }
+}
+
+/// A counter of a shaft’s rotation, such as a quadrature or magnetic encoder.
+pub trait Encoder {
+    /// Returns the count, which increases as the shaft turns forward.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the encoder cannot be read.
+    fn count(&mut self) -> Result<i64, Error>;
+}
+
+/// A bus master for I2C transactions.
+pub trait I2c {
+    /// Writes `bytes` to the device at the 7-bit `address`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the device does not acknowledge
+    /// the transfer.
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Writes `bytes` to the device at the 7-bit `address`, then fills
+    /// `buffer` from it after a repeated start.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the device does not acknowledge
+    /// the transfer.
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error>;
+}
+
+/// A bus master for SPI transfers to a single device.
+pub trait Spi {
+    /// Writes `bytes` to the device, discarding whatever it sends back.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transfer fails.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Writes `bytes` to the device while filling `buffer`, which must be the
+    /// same length, with what it sends back.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the transfer fails.
+    fn transfer(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error>;
+}
+
+/// An I2C device with byte-wide registers and an auto-incrementing register
+/// pointer, which is how most of the supported chips behave.
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct MockI2c {
+    pub(crate) address: u8,
+    pub(crate) registers: [u8; 256],
+    pub(crate) writes: Vec<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl MockI2c {
+    pub(crate) fn new(address: u8) -> Self {
+        Self {
+            address,
+            registers: [0; 256],
+            writes: Vec::new(),
+        }
+    }
+
+    fn check_address(&self, address: u8) -> Result<(), Error> {
+        if address == self.address {
+            Ok(())
+        } else {
+            Err(Error::new(std::io::ErrorKind::NotFound, "no acknowledge"))
+        }
+    }
+}
+
+#[cfg(test)]
+impl I2c for MockI2c {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.check_address(address)?;
+        self.writes.push(bytes.to_vec());
+
+        if let Some((&pointer, data)) = bytes.split_first() {
+            for (offset, &byte) in data.iter().enumerate() {
+                self.registers[usize::from(pointer.wrapping_add(offset as u8))] = byte;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.check_address(address)?;
+        let pointer = bytes.first().copied().unwrap_or(0);
+
+        for (offset, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.registers[usize::from(pointer.wrapping_add(offset as u8))];
+        }
+
+        Ok(())
+    }
+}
+
+/// A PWM output that records every duty cycle it is set to, at 50 Hz unless
+/// created with another frequency.
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct MockPwm {
+    pub(crate) duty_cycle: f64,
+    pub(crate) duty_cycles: Vec<f64>,
+    pub(crate) frequency: f64,
+}
+
+#[cfg(test)]
+impl MockPwm {
+    pub(crate) fn new(frequency: f64) -> Self {
+        Self {
+            duty_cycle: 0.0,
+            duty_cycles: Vec::new(),
+            frequency,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Default for MockPwm {
+    fn default() -> Self {
+        Self::new(50.0)
+    }
+}
+
+#[cfg(test)]
+impl PwmOutput for MockPwm {
+    fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: f64) -> Result<(), Error> {
+        self.duty_cycle = duty_cycle;
+        self.duty_cycles.push(duty_cycle);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl AdjustableFrequency for MockPwm {
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), Error> {
+        self.frequency = frequency;
+        Ok(())
+    }
+}
+
+/// A motor that remembers the speed it was last driven at.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MockMotor {
+    pub(crate) speed: f64,
+}
+
+#[cfg(test)]
+impl Motor for MockMotor {
+    fn set_speed(&mut self, speed: f64) -> Result<(), Error> {
+        self.speed = speed;
+        Ok(())
+    }
+}
+
+/// A digital input whose level is set from the test through any of its
+/// clones.
+#[cfg(test)]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MockInput {
+    high: std::rc::Rc<std::cell::Cell<bool>>,
+}
+
+#[cfg(test)]
+impl MockInput {
+    pub(crate) fn new(high: bool) -> Self {
+        let input = Self::default();
+        input.set(high);
+        input
+    }
+
+    pub(crate) fn set(&self, high: bool) {
+        self.high.set(high);
+    }
+}
+
+#[cfg(test)]
+impl DigitalInput for MockInput {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        Ok(self.high.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Pin {
+        high: bool,
+    }
+
+    impl DigitalOutput for Pin {
+        fn set_high(&mut self) -> Result<(), Error> {
+            self.high = true;
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Error> {
+            self.high = false;
+            Ok(())
+        }
+    }
+
+    impl DigitalInput for Pin {
+        fn is_high(&mut self) -> Result<bool, Error> {
+            Ok(self.high)
+        }
+    }
+
+    #[test]
+    fn it_should_set_the_level_of_an_output() {
+        let mut pin = Pin::default();
+        pin.set_level(true).unwrap();
+        assert!(pin.high);
+        pin.set_level(false).unwrap();
+        assert!(!pin.high);
+    }
+
+    #[test]
+    fn it_should_report_an_input_as_low_when_it_is_not_high() {
+        let mut pin = Pin { high: false };
+        assert!(pin.is_low().unwrap());
+    }
+
+    #[test]
+    fn it_should_convert_a_pulse_width_to_a_duty_cycle() {
+        let mut pwm = MockPwm::default();
+        pwm.set_pulse_width(Duration::from_micros(1500)).unwrap();
+        assert!((pwm.duty_cycle - 0.075).abs() < 1e-12);
+    }
+
+    #[test]
+    fn it_should_auto_increment_the_register_pointer_of_a_mock_device() {
+        let mut i2c = MockI2c::new(0x40);
+        i2c.write(0x40, &[0x10, 1, 2, 3]).unwrap();
+        let mut buffer = [0; 2];
+        i2c.write_read(0x40, &[0x11], &mut buffer).unwrap();
+        assert_eq!(buffer, [2, 3]);
+        assert!(i2c.write(0x41, &[0]).is_err());
+    }
+}
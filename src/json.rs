@@ -0,0 +1,560 @@
+//! A minimal JSON value type shared by the network, persistence, and
+//! introspection interfaces.
+//!
+//! Objects keep their keys in insertion order so that encoded output is
+//! stable and easy to diff.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter, Write};
+use std::str::FromStr;
+
+/// A JSON value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Value {
+    /// `null`.
+    #[default]
+    Null,
+    /// `true` or `false`.
+    Bool(bool),
+    /// A number.
+    Number(f64),
+    /// A string.
+    String(String),
+    /// An array.
+    Array(Vec<Value>),
+    /// An object, with keys in insertion order.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Creates an empty object.
+    #[must_use]
+    pub fn object() -> Self {
+        Self::Object(Vec::new())
+    }
+
+    /// Returns the object with `key` set to `value`, replacing any existing
+    /// value for `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is not an object.
+    #[must_use]
+    pub fn with(mut self, key: &str, value: impl ToJson) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Sets `key` to `value` in an object, replacing any existing value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is not an object.
+    pub fn insert(&mut self, key: &str, value: impl ToJson) {
+        let Self::Object(entries) = self else {
+            panic!("value should be an object");
+        };
+        let value = value.to_json();
+
+        match entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, existing)) => *existing = value,
+            None => entries.push((key.to_owned(), value)),
+        }
+    }
+
+    /// Returns the value for `key` if this is an object containing it.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a boolean.
+    #[must_use]
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Self::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a number.
+    #[must_use]
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Self::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a string.
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements if the value is an array.
+    #[must_use]
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the value is `null`.
+    #[must_use]
+    pub fn is_null(&self) -> bool {
+        *self == Self::Null
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Number(value) if value.is_finite() => write!(f, "{value}"),
+            Self::Number(_) => f.write_str("null"),
+            Self::String(value) => write_string(f, value),
+            Self::Array(values) => {
+                f.write_char('[')?;
+
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+
+                    write!(f, "{value}")?;
+                }
+
+                f.write_char(']')
+            }
+            Self::Object(entries) => {
+                f.write_char('{')?;
+
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            bytes: s.as_bytes(),
+            position: 0,
+        };
+        let value = parser.parse_value(0)?;
+        parser.skip_whitespace();
+
+        if parser.position != s.len() {
+            return Err(parser.error("trailing characters"));
+        }
+
+        Ok(value)
+    }
+}
+
+/// Conversion into a JSON [`Value`].
+pub trait ToJson {
+    /// Returns the value as JSON.
+    fn to_json(&self) -> Value;
+}
+
+impl ToJson for Value {
+    fn to_json(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(&self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> Value {
+        Value::String(self.to_owned())
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> Value {
+        Value::String(self.clone())
+    }
+}
+
+macro_rules! impl_to_json_for_number {
+    ($($t:ty),*) => {
+        $(
+            impl ToJson for $t {
+                fn to_json(&self) -> Value {
+                    Value::Number(*self as f64)
+                }
+            }
+        )*
+    };
+}
+
+impl_to_json_for_number!(f32, f64, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> Value {
+        (**self).to_json()
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ToJson::to_json)
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Value {
+        self.as_slice().to_json()
+    }
+}
+
+fn write_string(f: &mut Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if u32::from(c) < 0x20 => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => f.write_char(c)?,
+        }
+    }
+
+    f.write_char('"')
+}
+
+/// Maximum nesting depth accepted when parsing, which bounds recursion on
+/// untrusted input.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("invalid JSON at byte {}: {message}", self.position)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), String> {
+        if self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{literal}`")))
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|()| Value::Null),
+            Some(b't') => self.expect("true").map(|()| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|()| Value::Bool(false)),
+            Some(b'"') => self.parse_string().map(Value::String),
+            Some(b'[') => self.parse_array(depth),
+            Some(b'{') => self.parse_object(depth),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<Value, String> {
+        self.position += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<Value, String> {
+        self.position += 1;
+        let mut entries: Vec<(String, Value)> = Vec::new();
+        // Where each key is, so a repeated key replaces the earlier value
+        // without scanning every entry.
+        let mut indices: HashMap<String, usize> = HashMap::new();
+        self.skip_whitespace();
+
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Value::Object(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(":")?;
+            let value = self.parse_value(depth + 1)?;
+
+            match indices.get(&key) {
+                Some(&index) => entries[index].1 = value,
+                None => {
+                    indices.insert(key.clone(), entries.len());
+                    entries.push((key, value));
+                }
+            }
+
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Value::Object(entries));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+
+        while matches!(
+            self.peek(),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.position += 1;
+        }
+
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Value::Number)
+            .ok_or_else(|| self.error("malformed number"))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut bytes = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"));
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.parse_unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.position += 1;
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(byte) if byte < 0x20 => return Err(self.error("control character")),
+                Some(byte) => {
+                    bytes.push(byte);
+                    self.position += 1;
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// Parses the hex digits of a `\u` escape, including a following low
+    /// surrogate, leaving the position on the last digit.
+    fn parse_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.parse_hex4()?;
+
+        let code = if (0xD800..0xDC00).contains(&high) {
+            self.position += 1;
+            self.expect("\\u")?;
+            self.position -= 1;
+            let low = self.parse_hex4()?;
+
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("invalid surrogate pair"));
+            }
+
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or_else(|| self.error("invalid code point"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .bytes
+            .get(self.position + 1..self.position + 5)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.position += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_encode_an_object_in_insertion_order() {
+        let value = Value::object()
+            .with("b", 1)
+            .with("a", [true, false].as_slice())
+            .with("c", None::<f64>)
+            .with("b", "two");
+        assert_eq!(
+            value.to_string(),
+            r#"{"b":"two","a":[true,false],"c":null}"#
+        );
+    }
+
+    #[test]
+    fn it_should_escape_strings() {
+        let value = "quote \" slash \\ newline \n bell \u{7}".to_json();
+        assert_eq!(
+            value.to_string(),
+            r#""quote \" slash \\ newline \n bell \u0007""#
+        );
+    }
+
+    #[test]
+    fn it_should_encode_non_finite_numbers_as_null() {
+        assert_eq!(f64::NAN.to_json().to_string(), "null");
+        assert_eq!(1.5.to_json().to_string(), "1.5");
+    }
+
+    #[test]
+    fn it_should_parse_what_it_encodes() {
+        let value = Value::object()
+            .with("gains", vec![0.5, -2.0, 1e-3])
+            .with("name", "wheel \"left\"")
+            .with("enabled", true)
+            .with("limit", Value::Null);
+        assert_eq!(value.to_string().parse::<Value>(), Ok(value));
+    }
+
+    #[test]
+    fn it_should_keep_the_last_value_of_a_repeated_key() {
+        let value: Value = r#"{"a":1,"b":2,"a":{"c":3}}"#.parse().unwrap();
+        assert_eq!(value.to_string(), r#"{"a":{"c":3},"b":2}"#);
+        let keys = (0..10_000).map(|key| format!("\"{key}\":{key}"));
+        let text = format!("{{{}}}", keys.collect::<Vec<_>>().join(","));
+        let value: Value = text.parse().unwrap();
+        assert_eq!(value.get("9999"), Some(&Value::Number(9999.0)));
+    }
+
+    #[test]
+    fn it_should_parse_whitespace_and_escapes() {
+        let value: Value = r#" { "a" : [ 1 , 2 ] , "b" : "\u00e9\ud83e\udda6\/" } "#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            value.get("a").and_then(Value::as_array).map(<[_]>::len),
+            Some(2)
+        );
+        assert_eq!(value.get("b").and_then(Value::as_str), Some("é🦦/"));
+    }
+
+    #[test]
+    fn it_should_reject_malformed_json() {
+        for input in [
+            "",
+            "{",
+            "[1,]",
+            "{\"a\" 1}",
+            "tru",
+            "\"\\x\"",
+            "1 2",
+            "{1:2}",
+        ] {
+            assert!(input.parse::<Value>().is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn it_should_reject_deeply_nested_json() {
+        let input = "[".repeat(MAX_DEPTH + 2);
+        assert!(input.parse::<Value>().is_err());
+    }
+}
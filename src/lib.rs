@@ -2,7 +2,47 @@
 //!
 //! A robot built on Raspberry Pi.
 
-#[cfg(all(target_os = "linux", test))]
-mod linux;
+pub mod audio;
+pub mod camera;
+pub mod control;
+pub mod coproc;
+pub mod devices;
+pub mod diagnostics;
+pub mod drive;
+pub mod estimation;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod geometry;
+pub mod gpio;
+pub mod hal;
+pub mod json;
+#[cfg(target_os = "linux")]
+pub mod linux;
+pub mod logging;
+pub mod mapping;
+pub mod missions;
+pub mod navigation;
+pub mod net;
+pub mod params;
+pub mod personality;
+#[cfg(target_os = "linux")]
+pub mod platform;
+pub mod power;
+pub mod protocol;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod recorder;
+pub mod runtime;
+pub mod safety;
+pub mod scripting;
+pub mod sensors;
+pub mod storage;
+pub mod sync;
+pub mod telemetry;
+pub mod ui;
 #[cfg(unix)]
 pub mod unix;
+
+#[cfg(unix)]
+pub use unix::temporary_directory::TemporaryDirectory;
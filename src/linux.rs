@@ -1,4 +1,7 @@
 //! Features available to operating systems based on the Linux kernel.
 
-#[cfg(test)]
-mod sysfs;
+pub mod cpufreq;
+pub mod serial;
+pub mod sysfs;
+pub mod v4l2;
+pub mod w1;
@@ -0,0 +1,111 @@
+//! Interfaces for the kernel’s CPU frequency scaling.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use super::sysfs::Sysfs;
+
+/// Directory, relative to the sysfs root, containing every scaling policy.
+const POLICIES_DIR: &str = "devices/system/cpu/cpufreq";
+
+/// Interface for reading and changing the CPU frequency governor.
+///
+/// The Raspberry Pi scales all of its cores together under a single policy,
+/// but every policy is changed together in case there are more.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CpuFreq<'a> {
+    sysfs: Sysfs<'a>,
+}
+
+impl<'a> CpuFreq<'a> {
+    /// Creates a new `CpuFreq` interface.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `CpuFreq` interface that accesses the kernel through
+    /// `sysfs`.
+    pub fn with_sysfs(sysfs: Sysfs<'a>) -> Self {
+        Self { sysfs }
+    }
+
+    /// Returns the names of the scaling policies, such as `policy0`.
+    pub fn policies(&self) -> Result<Vec<String>> {
+        let mut policies = self.sysfs.entries(POLICIES_DIR)?;
+        policies.retain(|entry| entry.starts_with("policy"));
+        Ok(policies)
+    }
+
+    /// Returns the governor of the first scaling policy, such as `ondemand`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there are no policies, or if
+    /// the governor cannot be read.
+    pub fn governor(&self) -> Result<String> {
+        let policy = self
+            .policies()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no cpufreq policies"))?;
+        Ok(self
+            .sysfs
+            .read_to_string(governor_path(&policy))?
+            .trim()
+            .to_owned())
+    }
+
+    /// Sets the governor of every scaling policy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a governor cannot be written,
+    /// such as when the kernel does not provide `governor`.
+    pub fn set_governor(&self, governor: &str) -> Result<()> {
+        for policy in self.policies()? {
+            self.sysfs.write(governor_path(&policy), governor)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn governor_path(policy: &str) -> PathBuf {
+    [POLICIES_DIR, policy, "scaling_governor"].iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_read_and_set_the_governor_of_every_policy() {
+        let sysfs_dir = TemporaryDirectory::new().expect("should succeed");
+        let policies_dir = sysfs_dir.path().join(POLICIES_DIR);
+
+        for policy in ["policy0", "policy4"] {
+            fs::create_dir_all(policies_dir.join(policy)).expect("should be writable");
+            fs::write(
+                policies_dir.join(policy).join("scaling_governor"),
+                "ondemand\n",
+            )
+            .expect("should be writable");
+        }
+
+        fs::write(policies_dir.join("boost"), "1").expect("should be writable");
+        let cpufreq = CpuFreq::with_sysfs(Sysfs::with_root_dir(sysfs_dir.path()));
+        assert!(cpufreq
+            .governor()
+            .is_ok_and(|governor| governor == "ondemand"));
+        cpufreq
+            .set_governor("powersave")
+            .expect("governors should be writable");
+        assert!(
+            fs::read_to_string(policies_dir.join("policy4/scaling_governor"))
+                .is_ok_and(|governor| governor == "powersave")
+        );
+    }
+}
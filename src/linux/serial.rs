@@ -0,0 +1,354 @@
+//! Serial ports identified by what is plugged in rather than by index.
+//!
+//! USB serial adapters are numbered `ttyUSB0`, `ttyUSB1`, … in whatever order
+//! they enumerate, which changes between boots and swaps the GPS with the
+//! LIDAR. A [`Selector`] names a port by its USB vendor, product, and serial
+//! number, or by its `/dev/serial/by-id` link, and [`SerialPorts`] finds
+//! whichever node it currently has.
+
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::sysfs::Sysfs;
+use crate::unix::flock::{DeviceLock, LOCK_DIR};
+
+/// Directory, relative to the sysfs root, containing every TTY.
+const TTY_DIR: &str = "class/tty";
+
+/// How many directories above a TTY’s device its USB device can be, past
+/// the interface and any driver-specific port directory.
+const MAX_USB_DEPTH: usize = 3;
+
+/// Identity of a USB device.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct UsbId {
+    /// Vendor ID.
+    pub vendor: u16,
+    /// Product ID.
+    pub product: u16,
+    /// Serial number, if the device has one.
+    pub serial: Option<String>,
+}
+
+/// A serial port.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SerialPort {
+    /// Name of the device node, such as `ttyUSB0`.
+    pub node: String,
+    /// Identity of the USB device providing the port, if it is on USB.
+    pub usb: Option<UsbId>,
+}
+
+impl SerialPort {
+    /// Returns the path of the device node.
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from("/dev").join(&self.node)
+    }
+}
+
+/// A way of naming a serial port.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Selector {
+    /// The device node, such as `ttyAMA0`, for ports that never move.
+    Node(String),
+    /// A link maintained by udev, such as
+    /// `/dev/serial/by-id/usb-u-blox_GNSS_receiver-if00`.
+    Link(PathBuf),
+    /// The identity of a USB device, matching any serial number if `None`.
+    Usb(UsbId),
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    /// Parses `usb:VID:PID` or `usb:VID:PID:SERIAL` with hexadecimal IDs, an
+    /// absolute path to a link, or the name of a device node.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(usb) = s.strip_prefix("usb:") {
+            let mut parts = usb.splitn(3, ':');
+            let mut id = |name| {
+                let part = parts.next().unwrap_or_default();
+                u16::from_str_radix(part, 16)
+                    .map_err(|_| format!("invalid USB {name} ID `{part}` in `{s}`"))
+            };
+            let vendor = id("vendor")?;
+            let product = id("product")?;
+            return Ok(Self::Usb(UsbId {
+                vendor,
+                product,
+                serial: parts.next().map(str::to_owned),
+            }));
+        }
+
+        if s.starts_with('/') {
+            return Ok(Self::Link(PathBuf::from(s)));
+        }
+
+        if s.is_empty() || s.contains('/') {
+            return Err(format!("invalid serial port `{s}`"));
+        }
+
+        Ok(Self::Node(s.to_owned()))
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Node(node) => f.write_str(node),
+            Self::Link(path) => write!(f, "{}", path.display()),
+            Self::Usb(UsbId {
+                vendor,
+                product,
+                serial,
+            }) => {
+                write!(f, "usb:{vendor:04x}:{product:04x}")?;
+                serial
+                    .as_ref()
+                    .map_or(Ok(()), |serial| write!(f, ":{serial}"))
+            }
+        }
+    }
+}
+
+/// Interface for enumerating serial ports.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SerialPorts<'a> {
+    sysfs: Sysfs<'a>,
+}
+
+impl<'a> SerialPorts<'a> {
+    /// Creates a new `SerialPorts` interface.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `SerialPorts` interface that accesses the kernel
+    /// through `sysfs`.
+    pub fn with_sysfs(sysfs: Sysfs<'a>) -> Self {
+        Self { sysfs }
+    }
+
+    /// Returns every serial port backed by hardware, sorted by node.
+    pub fn ports(&self) -> Result<Vec<SerialPort>> {
+        Ok(self
+            .sysfs
+            .entries(TTY_DIR)?
+            .into_iter()
+            // Virtual terminals and pseudo-terminals have no device.
+            .filter(|node| self.sysfs.entries(device_dir(node)).is_ok())
+            .map(|node| SerialPort {
+                usb: self.usb_id(&node),
+                node,
+            })
+            .collect())
+    }
+
+    /// Returns the path of the port named by `selector`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with [`ErrorKind::NotFound`] if
+    /// no port matches, such as when the device is unplugged.
+    pub fn resolve(&self, selector: &Selector) -> Result<PathBuf> {
+        let not_found = || Error::new(ErrorKind::NotFound, format!("no serial port `{selector}`"));
+
+        match selector {
+            Selector::Node(node) => Ok(Path::new("/dev").join(node)),
+            Selector::Link(link) => fs::canonicalize(link).map_err(|error| match error.kind() {
+                ErrorKind::NotFound => not_found(),
+                _ => error,
+            }),
+            Selector::Usb(wanted) => self
+                .ports()?
+                .into_iter()
+                .find(|port| {
+                    port.usb.as_ref().is_some_and(|usb| {
+                        usb.vendor == wanted.vendor
+                            && usb.product == wanted.product
+                            && (wanted.serial.is_none() || usb.serial == wanted.serial)
+                    })
+                })
+                .map(|port| port.path())
+                .ok_or_else(not_found),
+        }
+    }
+
+    /// Resolves `selector` and claims the port for this process using the
+    /// system lock directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with [`ErrorKind::NotFound`] if
+    /// no port matches, or with [`ErrorKind::ResourceBusy`] naming the
+    /// owning process if the port is already claimed.
+    pub fn claim(&self, selector: &Selector) -> Result<DeviceLock> {
+        self.claim_in(LOCK_DIR, selector)
+    }
+
+    /// Resolves `selector` and claims the port for this process using lock
+    /// files in `lock_dir`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with [`ErrorKind::NotFound`] if
+    /// no port matches, or with [`ErrorKind::ResourceBusy`] naming the
+    /// owning process if the port is already claimed.
+    pub fn claim_in(&self, lock_dir: impl AsRef<Path>, selector: &Selector) -> Result<DeviceLock> {
+        DeviceLock::acquire_in(lock_dir, self.resolve(selector)?)
+    }
+
+    fn usb_id(&self, node: &str) -> Option<UsbId> {
+        (0..=MAX_USB_DEPTH).find_map(|depth| {
+            let dir = Path::new(&device_dir(node)).join("../".repeat(depth));
+            let read = |name| {
+                self.sysfs
+                    .read_to_string(dir.join(name))
+                    .map(|contents| contents.trim().to_owned())
+            };
+            let id = |name| u16::from_str_radix(&read(name).ok()?, 16).ok();
+            Some(UsbId {
+                vendor: id("idVendor")?,
+                product: id("idProduct")?,
+                serial: read("serial").ok(),
+            })
+        })
+    }
+}
+
+fn device_dir(node: &str) -> String {
+    format!("{TTY_DIR}/{node}/device")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_parse_selectors() {
+        assert_eq!(
+            "usb:1546:01a8".parse(),
+            Ok(Selector::Usb(UsbId {
+                vendor: 0x1546,
+                product: 0x01A8,
+                serial: None
+            }))
+        );
+        assert_eq!(
+            "usb:0403:6001:A10K:3"
+                .parse::<Selector>()
+                .map(|selector| selector.to_string()),
+            Ok("usb:0403:6001:A10K:3".to_owned())
+        );
+        assert_eq!(
+            "/dev/serial/by-id/usb-FTDI-if00".parse(),
+            Ok(Selector::Link(PathBuf::from(
+                "/dev/serial/by-id/usb-FTDI-if00"
+            )))
+        );
+        assert_eq!("ttyAMA0".parse(), Ok(Selector::Node("ttyAMA0".to_owned())));
+        assert!("usb:xyz:0001".parse::<Selector>().is_err());
+        assert!("serial/ttyUSB0".parse::<Selector>().is_err());
+    }
+
+    #[test]
+    fn it_should_find_a_port_by_usb_identity() {
+        let sysfs_dir = TemporaryDirectory::new().unwrap();
+        let root = sysfs_dir.path();
+        // An FTDI adapter has a port directory below its interface, and an
+        // ACM device does not.
+        for (node, usb, interface, vendor, product, serial) in [
+            (
+                "ttyUSB0",
+                "1-1",
+                "1-1/1-1:1.0/ttyUSB0",
+                "0403",
+                "6001",
+                "A10K",
+            ),
+            ("ttyACM0", "1-2", "1-2/1-2:1.0", "1546", "01a8", "GPS1"),
+        ] {
+            let usb_dir = root.join("devices/usb1").join(usb);
+            fs::create_dir_all(root.join("devices/usb1").join(interface)).unwrap();
+            fs::write(usb_dir.join("idVendor"), format!("{vendor}\n")).unwrap();
+            fs::write(usb_dir.join("idProduct"), format!("{product}\n")).unwrap();
+            fs::write(usb_dir.join("serial"), format!("{serial}\n")).unwrap();
+            fs::create_dir_all(root.join(TTY_DIR).join(node)).unwrap();
+            symlink(
+                root.join("devices/usb1").join(interface),
+                root.join(device_dir(node)),
+            )
+            .unwrap();
+        }
+
+        fs::create_dir_all(root.join(TTY_DIR).join("tty1")).unwrap();
+        let ports = SerialPorts::with_sysfs(Sysfs::with_root_dir(root));
+        let found = ports.ports().unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].usb.as_ref().map(|usb| usb.vendor), Some(0x1546));
+        assert_eq!(
+            ports
+                .resolve(&"usb:0403:6001:A10K".parse().unwrap())
+                .unwrap(),
+            Path::new("/dev/ttyUSB0")
+        );
+        assert_eq!(
+            ports.resolve(&"usb:1546:01a8".parse().unwrap()).unwrap(),
+            Path::new("/dev/ttyACM0")
+        );
+        assert!(ports
+            .resolve(&"usb:0403:6001:OTHER".parse().unwrap())
+            .is_err_and(|error| error.kind() == ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn it_should_follow_by_id_links() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let node = dir.path().join("ttyUSB3");
+        fs::write(&node, "").unwrap();
+        let link = dir.path().join("usb-FTDI-if00");
+        symlink(&node, &link).unwrap();
+        let ports = SerialPorts::new();
+        assert_eq!(
+            ports.resolve(&Selector::Link(link)).unwrap(),
+            fs::canonicalize(node).unwrap()
+        );
+        assert!(ports
+            .resolve(&Selector::Link(dir.path().join("missing")))
+            .is_err_and(|error| error.kind() == ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn it_should_refuse_a_port_claimed_through_another_name() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let lock_dir = TemporaryDirectory::new().unwrap();
+        let node = dir.path().join("ttyUSB3");
+        fs::write(&node, "").unwrap();
+        let link = dir.path().join("usb-FTDI-if00");
+        symlink(&node, &link).unwrap();
+        let ports = SerialPorts::new();
+        let lock = ports
+            .claim_in(lock_dir.path(), &Selector::Link(link))
+            .unwrap();
+        assert_eq!(lock.device(), fs::canonicalize(&node).unwrap());
+        let error = ports
+            .claim_in(lock_dir.path(), &Selector::Link(node.clone()))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ResourceBusy);
+        assert_eq!(
+            error.to_string(),
+            format!("device busy (pid {})", std::process::id())
+        );
+        drop(lock);
+        assert!(ports
+            .claim_in(lock_dir.path(), &Selector::Link(node))
+            .is_ok());
+    }
+}
@@ -6,6 +6,12 @@ use std::fs;
 use std::io::Result;
 use std::path::{Path, PathBuf};
 
+use crate::log_event;
+use crate::logging::{self, Level};
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "sysfs";
+
 /// Interface for reading and writing to kernel attributes using paths that are
 /// relative to the sysfs root directory.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -30,20 +36,28 @@ impl<'a> Sysfs<'a> {
 
     /// Reads from a kernel attribute.
     pub fn read(&self, path: impl AsRef<Path>) -> Result<Vec<u8>> {
-        let path_ref = self.resolve_path(path);
-        fs::read(path_ref.as_path())
+        self.transact("read", path, |path| fs::read(path))
     }
 
     /// Reads from a kernel attribute into a [`String`].
     pub fn read_to_string(&self, path: impl AsRef<Path>) -> Result<String> {
-        let path_ref = self.resolve_path(path);
-        fs::read_to_string(path_ref.as_path())
+        self.transact("read", path, |path| fs::read_to_string(path))
+    }
+
+    /// Returns the sorted names of the entries in a kernel object directory.
+    pub fn entries(&self, path: impl AsRef<Path>) -> Result<Vec<String>> {
+        self.transact("list", path, |path| {
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<_>>>()?;
+            entries.sort_unstable();
+            Ok(entries)
+        })
     }
 
     /// Writes to a kernel attribute.
     pub fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
-        let path_ref = self.resolve_path(path);
-        fs::write(path_ref.as_path(), contents)
+        self.transact("write", path, |path| fs::write(path, contents))
     }
 
     fn cache_path(&self, attribute_path: PathBuf, path: PathBuf) {
@@ -60,6 +74,34 @@ impl<'a> Sysfs<'a> {
         self.path_cache.borrow().contains_key(path)
     }
 
+    fn transact<T>(
+        &self,
+        operation: &str,
+        attribute_path: impl AsRef<Path>,
+        f: impl FnOnce(&Path) -> Result<T>,
+    ) -> Result<T> {
+        let path_ref = self.resolve_path(attribute_path);
+        let _span = logging::transaction_span(SUBSYSTEM, operation);
+        let result = f(path_ref.as_path());
+
+        match &result {
+            Ok(_) => log_event!(
+                SUBSYSTEM,
+                Level::Trace,
+                "{operation} {}",
+                path_ref.display()
+            ),
+            Err(error) => log_event!(
+                SUBSYSTEM,
+                Level::Debug,
+                "{operation} {} failed: {error}",
+                path_ref.display()
+            ),
+        }
+
+        result
+    }
+
     fn resolve_path(&self, attribute_path: impl AsRef<Path>) -> Ref<'_, PathBuf> {
         let attribute_path = attribute_path.as_ref();
 
@@ -123,6 +165,15 @@ mod tests {
         assert!(sysfs.read_to_string("class/pwm/pwmchip1/npwm").is_err());
     }
 
+    #[test]
+    fn it_should_list_the_entries_of_a_directory() {
+        let sysfs_dir = mock_sysfs_dir();
+        let sysfs = Sysfs::with_root_dir(sysfs_dir.path());
+        assert!(sysfs
+            .entries("class/pwm/pwmchip0")
+            .is_ok_and(|entries| entries == ["export", "npwm"]));
+    }
+
     #[test]
     fn it_should_write_to_an_attribute() {
         let sysfs_dir = mock_sysfs_dir();
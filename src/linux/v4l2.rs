@@ -0,0 +1,111 @@
+//! Discovery of Video4Linux devices through sysfs.
+//!
+//! The Raspberry Pi exposes its hardware video codecs as V4L2
+//! memory-to-memory devices alongside the cameras, and their `/dev/video*`
+//! numbers shift with whatever else is attached. Devices are found by the
+//! name the driver gives them instead.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use super::sysfs::Sysfs;
+
+/// Directory, relative to the sysfs root, containing every V4L2 device.
+const DEVICES_DIR: &str = "class/video4linux";
+
+/// Name of the Raspberry Pi’s hardware H.264 encoder, from `bcm2835-codec`.
+pub const H264_ENCODER: &str = "bcm2835-codec-encode";
+
+/// A V4L2 device.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Device {
+    /// Name of the device node, such as `video11`.
+    pub node: String,
+    /// Name given by the driver, such as `bcm2835-codec-encode`.
+    pub name: String,
+}
+
+impl Device {
+    /// Returns the path of the device node.
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from("/dev").join(&self.node)
+    }
+}
+
+/// Interface for enumerating V4L2 devices.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct V4l2<'a> {
+    sysfs: Sysfs<'a>,
+}
+
+impl<'a> V4l2<'a> {
+    /// Creates a new `V4l2` interface.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `V4l2` interface that accesses the kernel through
+    /// `sysfs`.
+    pub fn with_sysfs(sysfs: Sysfs<'a>) -> Self {
+        Self { sysfs }
+    }
+
+    /// Returns every V4L2 device, sorted by node.
+    pub fn devices(&self) -> Result<Vec<Device>> {
+        self.sysfs
+            .entries(DEVICES_DIR)?
+            .into_iter()
+            .map(|node| {
+                let name = self
+                    .sysfs
+                    .read_to_string(format!("{DEVICES_DIR}/{node}/name"))?
+                    .trim()
+                    .to_owned();
+                Ok(Device { node, name })
+            })
+            .collect()
+    }
+
+    /// Returns the first device named `name`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with [`ErrorKind::NotFound`] if no
+    /// device has the name, such as when its driver is not loaded.
+    pub fn find(&self, name: &str) -> Result<Device> {
+        self.devices()?
+            .into_iter()
+            .find(|device| device.name == name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no V4L2 device `{name}`")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_find_a_device_by_name() {
+        let sysfs_dir = TemporaryDirectory::new().expect("should succeed");
+        let devices_dir = sysfs_dir.path().join(DEVICES_DIR);
+
+        for (node, name) in [("video0", "unicam-image"), ("video11", H264_ENCODER)] {
+            fs::create_dir_all(devices_dir.join(node)).expect("should be writable");
+            fs::write(devices_dir.join(node).join("name"), format!("{name}\n"))
+                .expect("should be writable");
+        }
+
+        let v4l2 = V4l2::with_sysfs(Sysfs::with_root_dir(sysfs_dir.path()));
+        assert!(v4l2
+            .find(H264_ENCODER)
+            .is_ok_and(|device| device.path() == Path::new("/dev/video11")));
+        assert!(v4l2
+            .find("bcm2835-isp")
+            .is_err_and(|error| error.kind() == ErrorKind::NotFound));
+    }
+}
@@ -0,0 +1,215 @@
+//! Interfaces for 1-Wire devices exposed by the kernel’s w1 subsystem.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use super::sysfs::Sysfs;
+
+/// Directory, relative to the sysfs root, containing every 1-Wire device.
+const DEVICES_DIR: &str = "bus/w1/devices";
+
+/// Family codes of the thermometers that report readings like the DS18B20.
+const THERMOMETER_FAMILIES: [u8; 4] = [0x10, 0x22, 0x28, 0x3B];
+
+/// Identifier of a 1-Wire slave, such as `28-0316a279f5ff`.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SlaveId(String);
+
+impl SlaveId {
+    /// Returns the family code identifying the kind of device, if the
+    /// identifier is well formed.
+    #[must_use]
+    pub fn family(&self) -> Option<u8> {
+        let (family, serial) = self.0.split_once('-')?;
+
+        if serial.is_empty() {
+            return None;
+        }
+
+        u8::from_str_radix(family, 16).ok()
+    }
+
+    /// Returns `true` if the slave is a DS18B20 or compatible thermometer.
+    #[must_use]
+    pub fn is_thermometer(&self) -> bool {
+        self.family()
+            .is_some_and(|family| THERMOMETER_FAMILIES.contains(&family))
+    }
+
+    /// Returns the identifier as a string.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for SlaveId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for SlaveId {
+    fn from(id: &str) -> Self {
+        Self(id.to_owned())
+    }
+}
+
+/// Interface for enumerating 1-Wire slaves and reading thermometers.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct W1<'a> {
+    sysfs: Sysfs<'a>,
+}
+
+impl<'a> W1<'a> {
+    /// Creates a new `W1` interface.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `W1` interface that accesses the kernel through `sysfs`.
+    pub fn with_sysfs(sysfs: Sysfs<'a>) -> Self {
+        Self { sysfs }
+    }
+
+    /// Returns the slaves on every bus, excluding the bus masters.
+    pub fn slaves(&self) -> Result<Vec<SlaveId>> {
+        Ok(self
+            .sysfs
+            .entries(DEVICES_DIR)?
+            .iter()
+            .map(|entry| SlaveId::from(entry.as_str()))
+            .filter(|id| id.family().is_some())
+            .collect())
+    }
+
+    /// Returns the DS18B20 and compatible thermometers on every bus.
+    pub fn thermometers(&self) -> Result<Vec<SlaveId>> {
+        let mut slaves = self.slaves()?;
+        slaves.retain(SlaveId::is_thermometer);
+        Ok(slaves)
+    }
+
+    /// Reads the temperature, in degrees Celsius, from a thermometer.
+    ///
+    /// Each read triggers a conversion, which takes up to 750 ms.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the slave cannot be read, or with
+    /// [`ErrorKind::InvalidData`] if the reading fails its CRC check.
+    pub fn read_temperature(&self, id: &SlaveId) -> Result<f64> {
+        let path: PathBuf = [DEVICES_DIR, id.as_str(), "w1_slave"].iter().collect();
+        parse_temperature(&self.sysfs.read_to_string(path)?)
+    }
+}
+
+/// Parses the scratchpad dump from a thermometer’s `w1_slave` attribute and
+/// returns the temperature in degrees Celsius.
+fn parse_temperature(contents: &str) -> Result<f64> {
+    let scratchpad = contents
+        .lines()
+        .next()
+        .and_then(|line| line.split_once(':'))
+        .map(|(bytes, _)| {
+            bytes
+                .split_whitespace()
+                .map(|byte| u8::from_str_radix(byte, 16))
+                .collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .and_then(|bytes| bytes.ok())
+        .filter(|bytes| bytes.len() == 9)
+        .ok_or_else(|| invalid_data("malformed w1_slave scratchpad"))?;
+
+    if crc8(&scratchpad[..8]) != scratchpad[8] {
+        return Err(invalid_data("w1_slave scratchpad failed CRC check"));
+    }
+
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    Ok(f64::from(raw) / 16.0)
+}
+
+/// Computes the Dallas/Maxim CRC-8 used by 1-Wire devices.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0x8C
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    const READING: &str = "\
+72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+72 01 4b 46 7f ff 0e 10 57 t=23125
+";
+
+    #[test]
+    fn it_should_parse_a_reading_with_a_valid_crc() {
+        assert!(parse_temperature(READING).is_ok_and(|celsius| celsius == 23.125));
+    }
+
+    #[test]
+    fn it_should_parse_a_negative_reading() {
+        let mut scratchpad = [0x5E, 0xFF, 0x4B, 0x46, 0x7F, 0xFF, 0x02, 0x10, 0];
+        scratchpad[8] = crc8(&scratchpad[..8]);
+        let line = scratchpad.map(|byte| format!("{byte:02x}")).join(" ");
+        let contents = format!("{line} : crc=00 YES\n{line} t=-10125\n");
+        assert!(parse_temperature(&contents).is_ok_and(|celsius| celsius == -10.125));
+    }
+
+    #[test]
+    fn it_should_reject_a_reading_that_fails_the_crc_check() {
+        let corrupt = READING.replacen("72 01", "73 01", 1);
+        assert!(
+            parse_temperature(&corrupt).is_err_and(|error| error.kind() == ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn it_should_reject_a_malformed_reading() {
+        assert!(parse_temperature("").is_err());
+        assert!(parse_temperature("72 01 : crc=00 NO\n").is_err());
+    }
+
+    #[test]
+    fn it_should_enumerate_thermometers_and_read_them() {
+        let sysfs_dir = TemporaryDirectory::new().expect("should succeed");
+        let devices_dir = sysfs_dir.path().join(DEVICES_DIR);
+
+        for entry in ["28-0316a279f5ff", "w1_bus_master1", "2d-000012345678"] {
+            fs::create_dir_all(devices_dir.join(entry)).expect("should be writable");
+        }
+
+        fs::write(devices_dir.join("28-0316a279f5ff/w1_slave"), READING)
+            .expect("should be writable");
+        let w1 = W1::with_sysfs(Sysfs::with_root_dir(sysfs_dir.path()));
+        assert!(w1.slaves().is_ok_and(|slaves| slaves.len() == 2));
+        let thermometers = w1.thermometers().expect("should be listable");
+        assert_eq!(thermometers, [SlaveId::from("28-0316a279f5ff")]);
+        assert!(w1
+            .read_temperature(&thermometers[0])
+            .is_ok_and(|celsius| celsius == 23.125));
+    }
+
+    #[test]
+    fn it_should_compute_the_family_code_of_a_slave() {
+        assert_eq!(SlaveId::from("3b-0000001a2b3c").family(), Some(0x3B));
+        assert_eq!(SlaveId::from("w1_bus_master1").family(), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Structured logging with levels that can be changed per subsystem at runtime.
+//!
+//! Events are emitted through [`tracing`](https://docs.rs/tracing) when the
+//! `tracing` feature is enabled, and are discarded otherwise. Every event and
+//! span carries the name of the subsystem that produced it, and is filtered
+//! against that subsystem’s level before anything is formatted.
+
+use std::collections::HashMap;
+use std::fmt::{self, Arguments, Display, Formatter};
+use std::str::FromStr;
+use std::sync::{OnceLock, RwLock};
+
+pub mod retention;
+
+/// Severity of a log event, ordered from least to most verbose.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Level {
+    /// Disables all events.
+    Off,
+    /// Failures that stop a subsystem from working.
+    Error,
+    /// Unexpected conditions that a subsystem recovered from.
+    Warn,
+    /// Significant changes in state.
+    Info,
+    /// Details useful when diagnosing a problem.
+    Debug,
+    /// Every device transaction and control loop tick.
+    Trace,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Off => "off",
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            "trace" => Ok(Self::Trace),
+            _ => Err(format!("unknown log level `{s}`")),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Levels {
+    default: Level,
+    subsystems: HashMap<String, Level>,
+}
+
+fn levels() -> &'static RwLock<Levels> {
+    static LEVELS: OnceLock<RwLock<Levels>> = OnceLock::new();
+    LEVELS.get_or_init(|| {
+        RwLock::new(Levels {
+            default: Level::Info,
+            subsystems: HashMap::new(),
+        })
+    })
+}
+
+/// Sets the level of `subsystem`, overriding the default level.
+pub fn set_level(subsystem: &str, level: Level) {
+    let mut levels = levels().write().unwrap_or_else(|error| error.into_inner());
+    levels.subsystems.insert(subsystem.to_owned(), level);
+}
+
+/// Removes any override for `subsystem` so it uses the default level again.
+pub fn reset_level(subsystem: &str) {
+    let mut levels = levels().write().unwrap_or_else(|error| error.into_inner());
+    levels.subsystems.remove(subsystem);
+}
+
+/// Sets the level of every subsystem without an override.
+pub fn set_default_level(level: Level) {
+    levels()
+        .write()
+        .unwrap_or_else(|error| error.into_inner())
+        .default = level;
+}
+
+/// Returns the level in effect for `subsystem`.
+#[must_use]
+pub fn level(subsystem: &str) -> Level {
+    let levels = levels().read().unwrap_or_else(|error| error.into_inner());
+    levels
+        .subsystems
+        .get(subsystem)
+        .copied()
+        .unwrap_or(levels.default)
+}
+
+/// Returns `true` if events at `level` from `subsystem` should be emitted.
+#[must_use]
+pub fn enabled(subsystem: &str, level: Level) -> bool {
+    level != Level::Off && level <= self::level(subsystem)
+}
+
+/// Emits an event without checking the subsystem’s level.
+///
+/// Prefer the [`log_event!`](crate::log_event) macro, which only formats the
+/// message when the event is enabled.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub fn emit(subsystem: &str, level: Level, message: Arguments<'_>) {
+    #[cfg(feature = "tracing")]
+    match level {
+        Level::Off => {}
+        Level::Error => tracing::error!(subsystem, "{message}"),
+        Level::Warn => tracing::warn!(subsystem, "{message}"),
+        Level::Info => tracing::info!(subsystem, "{message}"),
+        Level::Debug => tracing::debug!(subsystem, "{message}"),
+        Level::Trace => tracing::trace!(subsystem, "{message}"),
+    }
+}
+
+/// Emits an event from a subsystem if its level is enabled.
+///
+/// # Examples
+///
+/// ```
+/// use otter_pi::log_event;
+/// use otter_pi::logging::Level;
+///
+/// log_event!("drive", Level::Info, "armed with {} motors", 2);
+/// ```
+#[macro_export]
+macro_rules! log_event {
+    ($subsystem:expr, $level:expr, $($arg:tt)+) => {{
+        let subsystem = $subsystem;
+        let level = $level;
+
+        if $crate::logging::enabled(subsystem, level) {
+            $crate::logging::emit(subsystem, level, format_args!($($arg)+));
+        }
+    }};
+}
+
+/// A span that is active until it goes out of scope.
+#[derive(Debug)]
+#[must_use = "the span is exited as soon as it is dropped"]
+pub struct Span {
+    #[cfg(feature = "tracing")]
+    _inner: Option<tracing::span::EnteredSpan>,
+}
+
+/// Enters a span covering a single transaction with a device.
+///
+/// The span is only recorded when `subsystem` is at [`Level::Debug`] or more
+/// verbose.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub fn transaction_span(subsystem: &str, operation: &str) -> Span {
+    Span {
+        #[cfg(feature = "tracing")]
+        _inner: enabled(subsystem, Level::Debug)
+            .then(|| tracing::debug_span!("transaction", subsystem, operation).entered()),
+    }
+}
+
+/// Enters a span covering a single tick of a control loop.
+///
+/// The span is only recorded when `subsystem` is at [`Level::Debug`] or more
+/// verbose.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub fn tick_span(subsystem: &str, tick: u64) -> Span {
+    Span {
+        #[cfg(feature = "tracing")]
+        _inner: enabled(subsystem, Level::Debug)
+            .then(|| tracing::debug_span!("tick", subsystem, tick).entered()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_order_levels_by_verbosity() {
+        assert!(Level::Off < Level::Error);
+        assert!(Level::Error < Level::Warn);
+        assert!(Level::Warn < Level::Info);
+        assert!(Level::Info < Level::Debug);
+        assert!(Level::Debug < Level::Trace);
+    }
+
+    #[test]
+    fn it_should_parse_levels_case_insensitively() {
+        assert_eq!("DEBUG".parse(), Ok(Level::Debug));
+        assert!("verbose".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn it_should_display_levels_as_they_are_parsed() {
+        for level in [Level::Off, Level::Error, Level::Trace] {
+            assert_eq!(level.to_string().parse(), Ok(level));
+        }
+    }
+
+    #[test]
+    fn it_should_use_the_default_level_without_an_override() {
+        assert_eq!(
+            level("logging-test-default"),
+            levels().read().unwrap().default
+        );
+    }
+
+    #[test]
+    fn it_should_change_the_level_of_a_subsystem_at_runtime() {
+        set_level("logging-test-set", Level::Trace);
+        assert!(enabled("logging-test-set", Level::Trace));
+        set_level("logging-test-set", Level::Error);
+        assert!(!enabled("logging-test-set", Level::Warn));
+        assert!(enabled("logging-test-set", Level::Error));
+    }
+
+    #[test]
+    fn it_should_not_affect_other_subsystems_when_changing_a_level() {
+        set_level("logging-test-a", Level::Trace);
+        assert_eq!(level("logging-test-b"), levels().read().unwrap().default);
+    }
+
+    #[test]
+    fn it_should_restore_the_default_level_when_reset() {
+        set_level("logging-test-reset", Level::Trace);
+        reset_level("logging-test-reset");
+        assert_eq!(
+            level("logging-test-reset"),
+            levels().read().unwrap().default
+        );
+    }
+
+    #[test]
+    fn it_should_never_enable_events_at_the_off_level() {
+        set_level("logging-test-off", Level::Trace);
+        assert!(!enabled("logging-test-off", Level::Off));
+    }
+
+    #[test]
+    fn it_should_not_format_the_message_of_a_disabled_event() {
+        struct Panics;
+
+        impl Display for Panics {
+            fn fmt(&self, _: &mut Formatter<'_>) -> fmt::Result {
+                panic!("message should not be formatted");
+            }
+        }
+
+        set_level("logging-test-lazy", Level::Off);
+        log_event!("logging-test-lazy", Level::Error, "{}", Panics);
+    }
+}
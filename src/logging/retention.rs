@@ -0,0 +1,266 @@
+//! Compression and pruning of rotated log files.
+//!
+//! Telemetry and black-box logs are rotated by renaming the full file with a
+//! numeric suffix, such as `imu.jsonl.1`, and starting a new one. Left alone,
+//! a week of high-rate IMU logs fills the SD card. [`LogRetention`] compresses
+//! rotated files with an external compressor, `zstd` by default, and deletes
+//! the oldest once they are too old or take too much space. Logs still being
+//! written are never touched. It runs as an actor, so the compression happens
+//! off the threads doing the logging.
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use crate::log_event;
+use crate::logging::Level;
+use crate::runtime::actors::Actor;
+
+const SUBSYSTEM: &str = "retention";
+
+/// What [`LogRetention::enforce`] did.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Summary {
+    /// Rotated logs that were compressed.
+    pub compressed: Vec<PathBuf>,
+    /// Archives and rotated logs that were deleted.
+    pub deleted: Vec<PathBuf>,
+}
+
+/// Compresses and prunes the rotated logs in a directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogRetention {
+    args: Vec<String>,
+    dir: PathBuf,
+    extension: String,
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+    program: String,
+}
+
+impl LogRetention {
+    /// Creates a new `LogRetention` for the logs in `dir`, compressing with
+    /// `zstd` and keeping everything until limits are set.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            args: vec!["-q".to_owned(), "-f".to_owned(), "--rm".to_owned()],
+            dir: dir.into(),
+            extension: "zst".to_owned(),
+            max_age: None,
+            max_size: None,
+            program: "zstd".to_owned(),
+        }
+    }
+
+    /// Sets the compressor, which is run as `program args… file` and should
+    /// replace the file with one ending in `.extension`.
+    #[must_use]
+    pub fn with_compressor(mut self, program: &str, args: &[&str], extension: &str) -> Self {
+        program.clone_into(&mut self.program);
+        self.args = args.iter().map(|&arg| arg.to_owned()).collect();
+        extension.clone_into(&mut self.extension);
+        self
+    }
+
+    /// Deletes rotated logs last written more than `max_age` ago.
+    #[must_use]
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Deletes the oldest rotated logs while they take more than `max_size`
+    /// bytes together.
+    #[must_use]
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Compresses every rotated log, then deletes those beyond the limits.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory cannot be read,
+    /// the compressor cannot be run or fails, or a file cannot be deleted.
+    pub fn enforce(&self) -> Result<Summary, Error> {
+        let mut summary = Summary::default();
+
+        for path in self.files()? {
+            if !self.is_compressed(&path) {
+                self.compress(&path)?;
+                summary.compressed.push(path);
+            }
+        }
+
+        let now = SystemTime::now();
+        let mut files = Vec::new();
+
+        for path in self.files()? {
+            let metadata = fs::metadata(&path)?;
+            files.push((metadata.modified()?, metadata.len(), path));
+        }
+
+        files.sort_unstable();
+        let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+
+        for (modified, size, path) in files {
+            let expired = self
+                .max_age
+                .is_some_and(|max_age| now.duration_since(modified).is_ok_and(|age| age > max_age));
+            let over_size = self.max_size.is_some_and(|max_size| total > max_size);
+
+            if !expired && !over_size {
+                continue;
+            }
+
+            log_event!(SUBSYSTEM, Level::Info, "deleting {}", path.display());
+
+            match fs::remove_file(&path) {
+                Err(error) if error.kind() != ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+
+            total -= size;
+            summary.deleted.push(path);
+        }
+
+        Ok(summary)
+    }
+
+    /// Returns the rotated logs and archives in the directory.
+    fn files(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if entry.file_type()?.is_file() && (is_rotated(&path) || self.is_compressed(&path)) {
+                files.push(path);
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn is_compressed(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension == self.extension.as_str())
+            && path
+                .file_stem()
+                .is_some_and(|stem| is_rotated(Path::new(stem)))
+    }
+
+    fn compress(&self, path: &Path) -> Result<(), Error> {
+        log_event!(SUBSYSTEM, Level::Debug, "compressing {}", path.display());
+        let status = Command::new(&self.program)
+            .args(&self.args)
+            .arg(path)
+            .status()?;
+
+        if !status.success() {
+            return Err(Error::other(format!(
+                "`{}` failed to compress {} with {status}",
+                self.program,
+                path.display()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Actor for LogRetention {
+    type Message = ();
+
+    fn handle(&mut self, (): ()) {
+        if let Err(error) = self.enforce() {
+            log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "failed to enforce retention in {}: {error}",
+                self.dir.display()
+            );
+        }
+    }
+}
+
+/// Returns whether `path` names a rotated log, with a numeric suffix.
+fn is_rotated(path: &Path) -> bool {
+    path.file_stem().is_some_and(|stem| !stem.is_empty())
+        && path.extension().is_some_and(|extension| {
+            let extension = extension.to_string_lossy();
+            !extension.is_empty() && extension.bytes().all(|byte| byte.is_ascii_digit())
+        })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::TemporaryDirectory;
+
+    fn retention(dir: &Path) -> LogRetention {
+        LogRetention::new(dir).with_compressor("gzip", &["-q", "-f"], "gz")
+    }
+
+    #[test]
+    fn it_should_recognize_rotated_logs() {
+        assert!(is_rotated(Path::new("imu.jsonl.1")));
+        assert!(is_rotated(Path::new("blackbox.12")));
+        assert!(!is_rotated(Path::new("imu.jsonl")));
+        assert!(!is_rotated(Path::new(".1")));
+    }
+
+    #[test]
+    fn it_should_compress_rotated_logs_but_not_active_ones() {
+        let dir = TemporaryDirectory::new().unwrap();
+        fs::write(dir.path().join("imu.jsonl"), "{}\n").unwrap();
+        fs::write(dir.path().join("imu.jsonl.1"), "{}\n".repeat(1000)).unwrap();
+        let summary = retention(dir.path()).enforce().unwrap();
+        assert_eq!(summary.compressed, [dir.path().join("imu.jsonl.1")]);
+        assert!(summary.deleted.is_empty());
+        assert!(dir.path().join("imu.jsonl").exists());
+        assert!(!dir.path().join("imu.jsonl.1").exists());
+        assert!(dir.path().join("imu.jsonl.1.gz").exists());
+        assert!(retention(dir.path())
+            .enforce()
+            .unwrap()
+            .compressed
+            .is_empty());
+    }
+
+    #[test]
+    fn it_should_delete_the_oldest_logs_over_the_limits() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let now = SystemTime::now();
+
+        for (name, age) in [("a.log.1.gz", 10), ("a.log.2.gz", 20), ("a.log.3.gz", 30)] {
+            let path = dir.path().join(name);
+            fs::write(&path, [0; 100]).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+        }
+
+        fs::write(dir.path().join("a.log"), [0; 1000]).unwrap();
+        let summary = retention(dir.path())
+            .with_max_age(Duration::from_secs(25))
+            .with_max_size(150)
+            .enforce()
+            .unwrap();
+        assert_eq!(
+            summary.deleted,
+            [dir.path().join("a.log.3.gz"), dir.path().join("a.log.2.gz")]
+        );
+        assert!(dir.path().join("a.log.1.gz").exists());
+        assert!(dir.path().join("a.log").exists());
+    }
+}
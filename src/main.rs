@@ -0,0 +1,437 @@
+//! Command-line interface for setting up and operating the robot.
+
+use std::env;
+use std::io::Error;
+#[cfg(target_os = "linux")]
+use std::io::{self, ErrorKind};
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[cfg(target_os = "linux")]
+use otter_pi::json::Value;
+#[cfg(target_os = "linux")]
+use otter_pi::platform::paths::{Category, Paths};
+#[cfg(target_os = "linux")]
+use otter_pi::platform::permissions::PermissionChecker;
+#[cfg(target_os = "linux")]
+use otter_pi::platform::setup::Setup;
+#[cfg(target_os = "linux")]
+use otter_pi::recorder::inspect;
+use otter_pi::recorder::inspect::TimeRange;
+#[cfg(target_os = "linux")]
+use otter_pi::runtime::rpc::{RpcClient, DEFAULT_SOCKET};
+
+const USAGE: &str = "\
+Usage: otter-pi <command>
+
+Commands:
+  check                   Check hardware permissions for the current user
+  setup udev-rules        Print the udev rules for non-root hardware access
+  setup install <user>    Install the udev rules and add <user> to the hardware groups
+  restart <subsystem>     Restart a subsystem of the running robot
+  reload-config           Reload the running robot's configuration
+  rotate-logs             Start new log files on the running robot
+  self-test               Run the running robot's self-test and print the report
+  get-param <name>        Print a parameter of the running robot
+  set-param <name> <value>
+                          Set a parameter of the running robot
+  log list [<dir>]        List the recorded runs
+  log channels <run>      List the telemetry channels of a run
+  log csv <run> [<channel>...] [--from <s>] [--to <s>]
+                          Print channels of a run as CSV, all by default
+  log faults <run> [--from <s>] [--to <s>]
+                          Print the faults recorded in a run
+  help                    Print this message
+
+The running robot is reached through the socket named by OTTER_PI_RPC, or
+/run/otter-pi/rpc.sock by default. Runs are looked up in the logs directory
+under OTTER_PI_DATA_DIR, or /var/lib/otter-pi by default, unless given as a
+path. Times are in seconds since the run started.";
+
+/// Environment variable overriding the path of the robot's socket.
+#[cfg(target_os = "linux")]
+const SOCKET_VARIABLE: &str = "OTTER_PI_RPC";
+
+/// A parsed command-line invocation.
+#[derive(Clone, Debug, PartialEq)]
+enum Command {
+    Check,
+    Help,
+    Log(LogCommand),
+    /// A maintenance request to the running robot, with its arguments as
+    /// given.
+    Rpc {
+        verb: String,
+        args: Vec<(String, String)>,
+    },
+    SetupInstall(String),
+    SetupUdevRules,
+}
+
+/// A parsed `log` subcommand, naming runs as given.
+#[derive(Clone, Debug, PartialEq)]
+enum LogCommand {
+    List(Option<String>),
+    Channels(String),
+    Csv {
+        run: String,
+        channels: Vec<String>,
+        range: TimeRange,
+    },
+    Faults {
+        run: String,
+        range: TimeRange,
+    },
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let command = match parse(&args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("otter-pi: {message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(command) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("otter-pi: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse(args: &[String]) -> Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        [] | ["help" | "--help" | "-h"] => Ok(Command::Help),
+        ["check"] => Ok(Command::Check),
+        ["setup", "udev-rules"] => Ok(Command::SetupUdevRules),
+        ["setup", "install", user] => Ok(Command::SetupInstall((*user).to_owned())),
+        ["setup", ..] => Err(String::from("invalid arguments to `setup`")),
+        ["restart", subsystem] => Ok(rpc("restart", &[("subsystem", subsystem)])),
+        [verb @ ("reload-config" | "rotate-logs" | "self-test")] => Ok(rpc(verb, &[])),
+        ["get-param", name] => Ok(rpc("get-param", &[("name", name)])),
+        ["set-param", name, value] => Ok(rpc("set-param", &[("name", name), ("value", value)])),
+        [verb @ ("restart" | "reload-config" | "rotate-logs" | "self-test" | "get-param"
+        | "set-param"), ..] => Err(format!("invalid arguments to `{verb}`")),
+        ["log", rest @ ..] => parse_log(rest).map(Command::Log),
+        [command, ..] => Err(format!("unknown command `{command}`")),
+    }
+}
+
+fn parse_log(args: &[&str]) -> Result<LogCommand, String> {
+    match args {
+        ["list"] => Ok(LogCommand::List(None)),
+        ["list", dir] => Ok(LogCommand::List(Some((*dir).to_owned()))),
+        ["channels", run] => Ok(LogCommand::Channels((*run).to_owned())),
+        ["csv", run, rest @ ..] => {
+            let (channels, range) = parse_range(rest)?;
+            Ok(LogCommand::Csv {
+                run: (*run).to_owned(),
+                channels,
+                range,
+            })
+        }
+        ["faults", run, rest @ ..] => match parse_range(rest)? {
+            (extra, _) if !extra.is_empty() => Err(format!("unexpected argument `{}`", extra[0])),
+            (_, range) => Ok(LogCommand::Faults {
+                run: (*run).to_owned(),
+                range,
+            }),
+        },
+        _ => Err(String::from("invalid arguments to `log`")),
+    }
+}
+
+/// Splits `--from` and `--to` options from the other arguments.
+fn parse_range(args: &[&str]) -> Result<(Vec<String>, TimeRange), String> {
+    let mut others = Vec::new();
+    let mut range = TimeRange::default();
+    let mut args = args.iter();
+
+    while let Some(&arg) = args.next() {
+        let bound = match arg {
+            "--from" => &mut range.from,
+            "--to" => &mut range.to,
+            _ => {
+                others.push(arg.to_owned());
+                continue;
+            }
+        };
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for `{arg}`"))?;
+        *bound = Some(
+            value
+                .parse()
+                .map_err(|_| format!("`{value}` should be a number of seconds"))?,
+        );
+    }
+
+    Ok((others, range))
+}
+
+fn rpc(verb: &str, args: &[(&str, &str)]) -> Command {
+    Command::Rpc {
+        verb: verb.to_owned(),
+        args: args
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn run(command: Command) -> Result<ExitCode, Error> {
+    match command {
+        Command::Check => {
+            let report = PermissionChecker::raspberry_pi().check()?;
+            print!("{report}");
+
+            Ok(if report.is_ok() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            })
+        }
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::SetupInstall(user) => {
+            let setup = Setup::new();
+            setup.install(&user)?;
+            println!("installed {}", setup.rules_path().display());
+            println!("log in again as {user} for group changes to take effect");
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::SetupUdevRules => {
+            print!("{}", Setup::new().udev_rules());
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Log(command) => {
+            run_log(command)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Rpc { verb, args } => {
+            let socket = env::var_os(SOCKET_VARIABLE)
+                .map_or_else(|| PathBuf::from(DEFAULT_SOCKET), PathBuf::from);
+            // Send values as JSON where they parse, so that `0.8` and `true`
+            // keep their types, and as strings otherwise.
+            let args = args.iter().fold(Value::object(), |object, (name, value)| {
+                object.with(
+                    name,
+                    value
+                        .parse()
+                        .unwrap_or_else(|_| Value::String(value.clone())),
+                )
+            });
+            let result = RpcClient::connect(&socket)?.call(&verb, args)?;
+
+            if !result.is_null() {
+                println!("{result}");
+            }
+
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn run_log(command: LogCommand) -> Result<(), Error> {
+    let logs = Paths::from_env().dir(Category::Logs);
+    // A run may be given by name within the logs directory, or by path.
+    let resolve = |run: &str| {
+        let path = Path::new(run);
+
+        if path.is_dir() {
+            path.to_path_buf()
+        } else {
+            logs.join(run)
+        }
+    };
+    let read = |run: &str| {
+        let path = resolve(run);
+
+        if path.is_dir() {
+            inspect::read_telemetry(&path)
+        } else {
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no run named `{run}`"),
+            ))
+        }
+    };
+
+    match command {
+        LogCommand::List(dir) => {
+            let dir = dir.map_or_else(|| logs.clone(), PathBuf::from);
+
+            for run in inspect::list_runs(&dir)? {
+                println!(
+                    "{}  {:>9.1} s  {:>7} samples  {:>3} faults  {:>10} bytes",
+                    run.name, run.duration, run.samples, run.faults, run.bytes
+                );
+            }
+        }
+        LogCommand::Channels(run) => {
+            for channel in inspect::channels(&read(&run)?) {
+                println!("{channel}");
+            }
+        }
+        LogCommand::Csv {
+            run,
+            channels,
+            range,
+        } => {
+            let samples = read(&run)?;
+            let channels = if channels.is_empty() {
+                inspect::channels(&samples)
+            } else {
+                channels
+            };
+            inspect::write_csv(&samples, &channels, range, &mut io::stdout().lock())?;
+        }
+        LogCommand::Faults { run, range } => {
+            for fault in inspect::faults(&read(&run)?, range) {
+                println!(
+                    "{:>10.3}  {}: {}",
+                    fault.time, fault.subsystem, fault.message
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run(command: Command) -> Result<ExitCode, Error> {
+    use std::io::ErrorKind;
+
+    match command {
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => Err(Error::new(
+            ErrorKind::Unsupported,
+            "this command is only supported on Linux",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn it_should_print_help_without_arguments() {
+        assert_eq!(parse(&args(&[])), Ok(Command::Help));
+    }
+
+    #[test]
+    fn it_should_parse_the_check_command() {
+        assert_eq!(parse(&args(&["check"])), Ok(Command::Check));
+    }
+
+    #[test]
+    fn it_should_parse_the_setup_commands() {
+        assert_eq!(
+            parse(&args(&["setup", "udev-rules"])),
+            Ok(Command::SetupUdevRules)
+        );
+        assert_eq!(
+            parse(&args(&["setup", "install", "pi"])),
+            Ok(Command::SetupInstall(String::from("pi")))
+        );
+    }
+
+    #[test]
+    fn it_should_return_an_error_for_invalid_setup_arguments() {
+        assert!(parse(&args(&["setup", "install"])).is_err());
+    }
+
+    #[test]
+    fn it_should_parse_the_maintenance_commands() {
+        assert_eq!(
+            parse(&args(&["restart", "lidar"])),
+            Ok(Command::Rpc {
+                verb: String::from("restart"),
+                args: vec![(String::from("subsystem"), String::from("lidar"))],
+            })
+        );
+        assert_eq!(
+            parse(&args(&["set-param", "max_speed", "0.8"])),
+            Ok(Command::Rpc {
+                verb: String::from("set-param"),
+                args: vec![
+                    (String::from("name"), String::from("max_speed")),
+                    (String::from("value"), String::from("0.8")),
+                ],
+            })
+        );
+        assert_eq!(
+            parse(&args(&["rotate-logs"])),
+            Ok(Command::Rpc {
+                verb: String::from("rotate-logs"),
+                args: Vec::new(),
+            })
+        );
+        assert!(parse(&args(&["set-param", "max_speed"])).is_err());
+    }
+
+    #[test]
+    fn it_should_parse_the_log_commands() {
+        assert_eq!(
+            parse(&args(&["log", "list"])),
+            Ok(Command::Log(LogCommand::List(None)))
+        );
+        assert_eq!(
+            parse(&args(&[
+                "log",
+                "csv",
+                "run-1",
+                "imu.accel.z",
+                "--from",
+                "10",
+                "battery"
+            ])),
+            Ok(Command::Log(LogCommand::Csv {
+                run: String::from("run-1"),
+                channels: vec![String::from("imu.accel.z"), String::from("battery")],
+                range: TimeRange {
+                    from: Some(10.0),
+                    to: None,
+                },
+            }))
+        );
+        assert_eq!(
+            parse(&args(&["log", "faults", "run-1", "--to", "2.5"])),
+            Ok(Command::Log(LogCommand::Faults {
+                run: String::from("run-1"),
+                range: TimeRange {
+                    from: None,
+                    to: Some(2.5),
+                },
+            }))
+        );
+        assert!(parse(&args(&["log", "faults", "run-1", "--to"])).is_err());
+        assert!(parse(&args(&["log", "csv", "run-1", "--from", "soon"])).is_err());
+    }
+
+    #[test]
+    fn it_should_return_an_error_for_an_unknown_command() {
+        assert!(parse(&args(&["foo"])).is_err());
+    }
+}
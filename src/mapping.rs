@@ -0,0 +1,8 @@
+//! Building a picture of the robot’s surroundings from its sensors.
+
+pub mod export;
+pub mod grid;
+pub mod projection;
+
+pub use export::MapExport;
+pub use grid::OccupancyGrid;
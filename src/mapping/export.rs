@@ -0,0 +1,446 @@
+//! Rendering maps to images for reviewing runs without a live viewer.
+//!
+//! A [`MapExport`] draws an [`OccupancyGrid`] with the trajectory the robot
+//! drove, the path it planned, and its last pose on top, either as a PNG,
+//! with each cell a square of pixels, or as an SVG in metres that stays
+//! sharp at any zoom. Free cells are white, occupied cells black, and cells
+//! never seen grey.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+use super::grid::{OccupancyGrid, UNKNOWN};
+use crate::geometry::Pose;
+use crate::log_event;
+use crate::logging::Level;
+use crate::protocol::framing::crc32;
+
+const SUBSYSTEM: &str = "mapping";
+
+/// Grey of cells never seen.
+const UNKNOWN_GREY: u8 = 160;
+
+/// Color of the trajectory driven.
+const TRAJECTORY: [u8; 3] = [30, 100, 230];
+
+/// Color of the planned path.
+const PLANNED: [u8; 3] = [20, 170, 60];
+
+/// Color of the robot.
+const ROBOT: [u8; 3] = [220, 30, 30];
+
+/// Length of the heading line drawn from the robot, in metres.
+const HEADING_LENGTH: f64 = 0.3;
+
+/// Largest block a stored deflate block can hold.
+const STORED_BLOCK: usize = 65_535;
+
+/// A map with the robot’s motion drawn on top, ready to be written out.
+#[derive(Clone, Debug)]
+pub struct MapExport<'a> {
+    grid: &'a OccupancyGrid,
+    planned: Vec<[f64; 2]>,
+    robot: Option<Pose>,
+    scale: usize,
+    trajectory: Vec<[f64; 2]>,
+}
+
+impl<'a> MapExport<'a> {
+    /// Creates a new `MapExport` of `grid`, drawing each cell as one pixel
+    /// of a PNG.
+    pub fn new(grid: &'a OccupancyGrid) -> Self {
+        Self {
+            grid,
+            planned: Vec::new(),
+            robot: None,
+            scale: 1,
+            trajectory: Vec::new(),
+        }
+    }
+
+    /// Draws each cell as a `scale` by `scale` square of pixels in a PNG.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `scale` is zero.
+    #[must_use]
+    pub fn with_scale(mut self, scale: usize) -> Self {
+        assert!(scale > 0, "scale should not be zero");
+        self.scale = scale;
+        self
+    }
+
+    /// Draws the trajectory through `poses`, and the robot at the last.
+    #[must_use]
+    pub fn with_trajectory(mut self, poses: &[Pose]) -> Self {
+        self.trajectory = poses.iter().map(|pose| [pose.x, pose.y]).collect();
+        self.robot = poses.last().copied().or(self.robot);
+        self
+    }
+
+    /// Draws the planned path through `points`.
+    #[must_use]
+    pub fn with_planned_path(mut self, points: &[[f64; 2]]) -> Self {
+        self.planned = points.to_vec();
+        self
+    }
+
+    /// Draws the robot at `pose`.
+    #[must_use]
+    pub fn with_robot(mut self, pose: Pose) -> Self {
+        self.robot = Some(pose);
+        self
+    }
+
+    /// Returns the map encoded as a PNG.
+    #[must_use]
+    pub fn to_png(&self) -> Vec<u8> {
+        let width = self.grid.width() * self.scale;
+        let height = self.grid.height() * self.scale;
+        let mut canvas = Canvas {
+            height,
+            pixels: vec![0; width * height * 3],
+            width,
+        };
+
+        for y in 0..height {
+            // The image starts from the top, the grid from the bottom.
+            let row = self.grid.height() - 1 - y / self.scale;
+
+            for x in 0..width {
+                let value = self.grid.get(x / self.scale, row).unwrap_or(UNKNOWN);
+                let grey = grey(value);
+                canvas.put(x as i64, y as i64, [grey; 3]);
+            }
+        }
+
+        let to_pixel = |[x, y]: [f64; 2]| {
+            let scale = self.scale as f64 / self.grid.resolution();
+            let origin = self.grid.origin();
+            (
+                ((x - origin[0]) * scale).floor() as i64,
+                height as i64 - 1 - ((y - origin[1]) * scale).floor() as i64,
+            )
+        };
+
+        for (points, color) in [(&self.trajectory, TRAJECTORY), (&self.planned, PLANNED)] {
+            for pair in points.windows(2) {
+                canvas.line(to_pixel(pair[0]), to_pixel(pair[1]), color);
+            }
+        }
+
+        if let Some(pose) = self.robot {
+            let (x, y) = to_pixel([pose.x, pose.y]);
+            let radius = self.scale.max(2) as i64;
+
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if dx * dx + dy * dy <= radius * radius {
+                        canvas.put(x + dx, y + dy, ROBOT);
+                    }
+                }
+            }
+
+            canvas.line((x, y), to_pixel(heading_tip(pose)), ROBOT);
+        }
+
+        encode_png(&canvas)
+    }
+
+    /// Returns the map as an SVG document, in metres with Y up.
+    #[must_use]
+    pub fn to_svg(&self) -> String {
+        let resolution = self.grid.resolution();
+        let [left, bottom] = self.grid.origin();
+        let width = self.grid.width() as f64 * resolution;
+        let height = self.grid.height() as f64 * resolution;
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{left} {} {width} {height}" width="{}" height="{}">"#,
+            -(bottom + height),
+            self.grid.width() * self.scale,
+            self.grid.height() * self.scale,
+        );
+        // Flip Y so that the drawing can use map coordinates.
+        svg.push_str("<g transform=\"scale(1,-1)\" shape-rendering=\"crispEdges\">\n");
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{left}" y="{bottom}" width="{width}" height="{height}" fill="{}"/>"#,
+            hex([UNKNOWN_GREY; 3])
+        );
+
+        // Merge runs of equal cells in each row to keep the document small.
+        for row in 0..self.grid.height() {
+            let mut column = 0;
+
+            while column < self.grid.width() {
+                let value = self.grid.get(column, row).unwrap_or(UNKNOWN);
+                let start = column;
+
+                while column < self.grid.width() && self.grid.get(column, row) == Some(value) {
+                    column += 1;
+                }
+
+                if value != UNKNOWN {
+                    let [x, y] = self.grid.center(start, row);
+                    let _ = writeln!(
+                        svg,
+                        r#"<rect x="{}" y="{}" width="{}" height="{resolution}" fill="{}"/>"#,
+                        x - resolution / 2.0,
+                        y - resolution / 2.0,
+                        (column - start) as f64 * resolution,
+                        hex([grey(value); 3])
+                    );
+                }
+            }
+        }
+
+        svg.push_str("</g>\n<g transform=\"scale(1,-1)\" fill=\"none\">\n");
+        let stroke = resolution.max(0.02);
+
+        for (points, color) in [(&self.trajectory, TRAJECTORY), (&self.planned, PLANNED)] {
+            if points.len() > 1 {
+                let points: Vec<_> = points.iter().map(|[x, y]| format!("{x},{y}")).collect();
+                let _ = writeln!(
+                    svg,
+                    r#"<polyline points="{}" stroke="{}" stroke-width="{stroke}"/>"#,
+                    points.join(" "),
+                    hex(color)
+                );
+            }
+        }
+
+        if let Some(pose) = self.robot {
+            let [tip_x, tip_y] = heading_tip(pose);
+            let _ = writeln!(
+                svg,
+                r#"<circle cx="{}" cy="{}" r="{}" fill="{color}"/>"#,
+                pose.x,
+                pose.y,
+                HEADING_LENGTH / 3.0,
+                color = hex(ROBOT)
+            );
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{}" y1="{}" x2="{tip_x}" y2="{tip_y}" stroke="{}" stroke-width="{stroke}"/>"#,
+                pose.x,
+                pose.y,
+                hex(ROBOT)
+            );
+        }
+
+        svg.push_str("</g>\n</svg>\n");
+        svg
+    }
+
+    /// Writes the map to `name.png` and `name.svg` in `dir`, such as at the
+    /// end of a mission.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if either file cannot be written.
+    pub fn save(&self, dir: &Path, name: &str) -> Result<(), Error> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(format!("{name}.png")), self.to_png())?;
+        fs::write(dir.join(format!("{name}.svg")), self.to_svg())?;
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "exported {name} to {}",
+            dir.display()
+        );
+        Ok(())
+    }
+}
+
+struct Canvas {
+    height: usize,
+    pixels: Vec<u8>,
+    width: usize,
+}
+
+impl Canvas {
+    fn put(&mut self, x: i64, y: i64, color: [u8; 3]) {
+        if (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y) {
+            let index = (y as usize * self.width + x as usize) * 3;
+            self.pixels[index..index + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Draws a line with Bresenham’s algorithm.
+    fn line(&mut self, (mut x, mut y): (i64, i64), (to_x, to_y): (i64, i64), color: [u8; 3]) {
+        let dx = (to_x - x).abs();
+        let dy = -(to_y - y).abs();
+        let step_x = if x < to_x { 1 } else { -1 };
+        let step_y = if y < to_y { 1 } else { -1 };
+        let mut error = dx + dy;
+
+        loop {
+            self.put(x, y, color);
+
+            if x == to_x && y == to_y {
+                break;
+            }
+
+            if 2 * error >= dy {
+                error += dy;
+                x += step_x;
+            }
+
+            if 2 * error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+}
+
+fn grey(value: i8) -> u8 {
+    if value == UNKNOWN {
+        UNKNOWN_GREY
+    } else {
+        (255 - i32::from(value.clamp(0, 100)) * 255 / 100) as u8
+    }
+}
+
+fn hex([red, green, blue]: [u8; 3]) -> String {
+    format!("#{red:02x}{green:02x}{blue:02x}")
+}
+
+fn heading_tip(pose: Pose) -> [f64; 2] {
+    let (sin, cos) = pose.heading.sin_cos();
+    [pose.x + HEADING_LENGTH * cos, pose.y + HEADING_LENGTH * sin]
+}
+
+/// Encodes 8-bit RGB pixels as a PNG, with the image data stored
+/// uncompressed, which maps of mostly uniform cells make up for in
+/// simplicity.
+fn encode_png(canvas: &Canvas) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((canvas.width * 3 + 1) * canvas.height);
+
+    for row in canvas.pixels.chunks(canvas.width * 3) {
+        // Each row starts with its filter type, none.
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // A zlib stream of stored deflate blocks.
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<_> = raw.chunks(STORED_BLOCK).collect();
+
+    for (index, block) in blocks.iter().enumerate() {
+        let last = index + 1 == blocks.len();
+        let len = block.len() as u16;
+        zlib.push(u8::from(last));
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+
+    if blocks.is_empty() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(canvas.width as u32).to_be_bytes());
+    header.extend_from_slice(&(canvas.height as u32).to_be_bytes());
+    // 8-bit RGB, default compression and filtering, not interlaced.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1_u32, 0_u32), |(a, b), &byte| {
+        let a = (a + u32::from(byte)) % 65_521;
+        (a, (b + a) % 65_521)
+    });
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> OccupancyGrid {
+        let mut grid = OccupancyGrid::new(4, 3, 0.5, [0.0, 0.0]);
+
+        for column in 0..4 {
+            grid.set(column, 0, 0);
+        }
+
+        grid.set(3, 2, 100);
+        grid
+    }
+
+    fn pixel(png: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        // Signature, IHDR, then the IDAT length, type, zlib header, and
+        // stored block header.
+        let data = 8 + 25 + 8 + 2 + 5;
+        let index = data + y * (width * 3 + 1) + 1 + x * 3;
+        [png[index], png[index + 1], png[index + 2]]
+    }
+
+    #[test]
+    fn it_should_encode_the_grid_as_a_png() {
+        let grid = grid();
+        let png = MapExport::new(&grid).with_scale(2).to_png();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 8);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 6);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // The top right cell is occupied, the bottom row free, and the rest
+        // unknown.
+        assert_eq!(pixel(&png, 8, 7, 0), [0; 3]);
+        assert_eq!(pixel(&png, 8, 0, 5), [255; 3]);
+        assert_eq!(pixel(&png, 8, 0, 2), [UNKNOWN_GREY; 3]);
+    }
+
+    #[test]
+    fn it_should_draw_the_trajectory_and_robot() {
+        let grid = grid();
+        let poses = [Pose::new(0.1, 0.1, 0.0), Pose::new(1.9, 0.1, 0.0)];
+        let png = MapExport::new(&grid).with_trajectory(&poses).to_png();
+        assert_eq!(pixel(&png, 4, 0, 2), TRAJECTORY);
+        assert_eq!(pixel(&png, 4, 3, 2), ROBOT);
+    }
+
+    #[test]
+    fn it_should_render_an_svg_in_metres() {
+        let grid = grid();
+        let svg = MapExport::new(&grid)
+            .with_planned_path(&[[0.25, 0.25], [1.75, 1.25]])
+            .with_robot(Pose::new(0.25, 0.25, 0.0))
+            .to_svg();
+        assert!(
+            svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 -1.5 2 1.5""#)
+        );
+        assert!(svg.contains(r##"<rect x="0" y="0" width="2" height="0.5" fill="#ffffff"/>"##));
+        assert!(svg.contains(r##"<polyline points="0.25,0.25 1.75,1.25" stroke="#14aa3c""##));
+        assert!(svg.contains("<circle cx=\"0.25\" cy=\"0.25\""));
+    }
+
+    #[test]
+    fn it_should_compute_the_adler32_checksum() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
@@ -0,0 +1,128 @@
+//! A floor plan divided into square cells, each with a chance of being
+//! occupied.
+//!
+//! Cells hold the percentage chance that something is there, from 0 for
+//! free to 100 for certainly occupied, or [`UNKNOWN`] until a sensor has
+//! seen them, as in the ROS `OccupancyGrid` message. Cell `(0, 0)` is the
+//! one at the grid’s origin, with columns along X and rows along Y.
+
+/// Value of a cell no sensor has seen.
+pub const UNKNOWN: i8 = -1;
+
+/// A grid of occupancy values over the floor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OccupancyGrid {
+    cells: Vec<i8>,
+    height: usize,
+    origin: [f64; 2],
+    resolution: f64,
+    width: usize,
+}
+
+impl OccupancyGrid {
+    /// Creates a new `OccupancyGrid` of `width` by `height` unknown cells,
+    /// each `resolution` metres square, with the corner of cell `(0, 0)` at
+    /// `origin`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `resolution` is not positive.
+    pub fn new(width: usize, height: usize, resolution: f64, origin: [f64; 2]) -> Self {
+        assert!(resolution > 0.0, "resolution should be positive");
+        Self {
+            cells: vec![UNKNOWN; width * height],
+            height,
+            origin,
+            resolution,
+            width,
+        }
+    }
+
+    /// Returns the number of columns.
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the number of rows.
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the side of a cell, in metres.
+    #[must_use]
+    pub fn resolution(&self) -> f64 {
+        self.resolution
+    }
+
+    /// Returns the position of the corner of cell `(0, 0)`, in metres.
+    #[must_use]
+    pub fn origin(&self) -> [f64; 2] {
+        self.origin
+    }
+
+    /// Returns the value of the cell at `column` and `row`, or `None` if it
+    /// is outside the grid.
+    #[must_use]
+    pub fn get(&self, column: usize, row: usize) -> Option<i8> {
+        self.index(column, row).map(|index| self.cells[index])
+    }
+
+    /// Sets the cell at `column` and `row` to `value`, clamped to 100, and
+    /// returns `false` if it is outside the grid.
+    pub fn set(&mut self, column: usize, row: usize, value: i8) -> bool {
+        let Some(index) = self.index(column, row) else {
+            return false;
+        };
+        self.cells[index] = value.min(100);
+        true
+    }
+
+    /// Returns the column and row of the cell containing the point `[x, y]`,
+    /// or `None` if it is outside the grid.
+    #[must_use]
+    pub fn cell_at(&self, [x, y]: [f64; 2]) -> Option<(usize, usize)> {
+        let column = ((x - self.origin[0]) / self.resolution).floor();
+        let row = ((y - self.origin[1]) / self.resolution).floor();
+
+        if column < 0.0 || row < 0.0 {
+            return None;
+        }
+
+        let (column, row) = (column as usize, row as usize);
+        self.index(column, row).map(|_| (column, row))
+    }
+
+    /// Returns the position of the centre of the cell at `column` and `row`.
+    #[must_use]
+    pub fn center(&self, column: usize, row: usize) -> [f64; 2] {
+        [
+            self.origin[0] + (column as f64 + 0.5) * self.resolution,
+            self.origin[1] + (row as f64 + 0.5) * self.resolution,
+        ]
+    }
+
+    fn index(&self, column: usize, row: usize) -> Option<usize> {
+        (column < self.width && row < self.height).then(|| row * self.width + column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_convert_between_points_and_cells() {
+        let mut grid = OccupancyGrid::new(20, 10, 0.05, [-0.5, -0.25]);
+        assert_eq!(grid.cell_at([0.0, 0.0]), Some((10, 5)));
+        assert_eq!(grid.cell_at([-0.6, 0.0]), None);
+        assert_eq!(grid.cell_at([0.5, 0.0]), None);
+        let [x, y] = grid.center(10, 5);
+        assert!((x - 0.025).abs() < 1e-9 && (y - 0.025).abs() < 1e-9);
+        assert_eq!(grid.get(10, 5), Some(UNKNOWN));
+        assert!(grid.set(10, 5, 120));
+        assert_eq!(grid.get(10, 5), Some(100));
+        assert!(!grid.set(20, 0, 0));
+    }
+}
@@ -0,0 +1,195 @@
+//! Projecting depth measurements onto the floor plan as obstacles.
+//!
+//! A depth camera sees the floor, the furniture, and the ceiling alike. Only
+//! what lies between just above the floor and just above the robot can be
+//! driven into, so points are moved into the robot’s base frame, kept if
+//! they fall in that height band, and flattened into 2D obstacle hits for the
+//! costmap. A low band catches chair legs and table edges that a planar
+//! LIDAR passes over or under.
+
+use std::collections::HashSet;
+
+use crate::camera::depth::DepthFrame;
+
+/// Where a depth camera is mounted on the robot.
+///
+/// The base frame has X forward, Y left, and Z up from the floor beneath
+/// the robot’s centre.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Mount {
+    /// Position of the camera in the base frame, in metres.
+    pub position: [f64; 3],
+    /// Rotation of the camera about the forward axis, in radians.
+    pub roll: f64,
+    /// Downward tilt of the camera, in radians.
+    pub pitch: f64,
+    /// Rotation of the camera to the left, in radians.
+    pub yaw: f64,
+}
+
+impl Mount {
+    /// Returns the point `[x, y, z]` from the camera’s optical frame, with X
+    /// right, Y down, and Z forward, in the base frame.
+    #[must_use]
+    pub fn to_base(&self, [x, y, z]: [f64; 3]) -> [f64; 3] {
+        // Optical axes to a camera body frame aligned with the base frame.
+        let body = [z, -x, -y];
+        let (sr, cr) = self.roll.sin_cos();
+        let (sp, cp) = self.pitch.sin_cos();
+        let (sy, cy) = self.yaw.sin_cos();
+        // Roll about X, then tilt down about Y, then turn about Z.
+        let rolled = [
+            body[0],
+            cr * body[1] - sr * body[2],
+            sr * body[1] + cr * body[2],
+        ];
+        let pitched = [
+            cp * rolled[0] + sp * rolled[2],
+            rolled[1],
+            -sp * rolled[0] + cp * rolled[2],
+        ];
+        let yawed = [
+            cy * pitched[0] - sy * pitched[1],
+            sy * pitched[0] + cy * pitched[1],
+            pitched[2],
+        ];
+        [
+            yawed[0] + self.position[0],
+            yawed[1] + self.position[1],
+            yawed[2] + self.position[2],
+        ]
+    }
+}
+
+/// Filtering applied by a [`Projector`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProjectionConfig {
+    /// Height above the floor below which points are taken to be the floor,
+    /// in metres.
+    pub min_height: f64,
+    /// Height above the floor above which points clear the robot, in
+    /// metres.
+    pub max_height: f64,
+    /// Horizontal distance from the robot beyond which points are too noisy
+    /// to trust, in metres.
+    pub max_range: f64,
+    /// Size of the grid cells in which hits are merged, in metres.
+    pub resolution: f64,
+}
+
+impl Default for ProjectionConfig {
+    fn default() -> Self {
+        Self {
+            min_height: 0.03,
+            max_height: 0.4,
+            max_range: 3.0,
+            resolution: 0.05,
+        }
+    }
+}
+
+/// Turns depth measurements into 2D obstacle hits in the base frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projector {
+    config: ProjectionConfig,
+    mount: Mount,
+}
+
+impl Projector {
+    /// Creates a new `Projector` for a camera at `mount`.
+    pub fn new(mount: Mount, config: ProjectionConfig) -> Self {
+        Self { config, mount }
+    }
+
+    /// Returns the obstacle hits among `points` in the camera’s optical
+    /// frame, as `[x, y]` in the base frame at the centres of the grid cells
+    /// they fall in, each cell once.
+    #[must_use]
+    pub fn project(&self, points: &[[f64; 3]]) -> Vec<[f64; 2]> {
+        let config = &self.config;
+        let mut cells = HashSet::new();
+        let mut hits = Vec::new();
+
+        for &point in points {
+            let [x, y, z] = self.mount.to_base(point);
+
+            if z < config.min_height || z > config.max_height || x.hypot(y) > config.max_range {
+                continue;
+            }
+
+            let cell = (
+                (x / config.resolution).floor() as i64,
+                (y / config.resolution).floor() as i64,
+            );
+
+            if cells.insert(cell) {
+                hits.push([
+                    (cell.0 as f64 + 0.5) * config.resolution,
+                    (cell.1 as f64 + 0.5) * config.resolution,
+                ]);
+            }
+        }
+
+        hits
+    }
+
+    /// Returns the obstacle hits in every `stride`th pixel of `frame`, as
+    /// [`Projector::project`] does.
+    #[must_use]
+    pub fn project_frame(&self, frame: &DepthFrame, stride: usize) -> Vec<[f64; 2]> {
+        self.project(&frame.points(stride))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+
+    const MOUNT: Mount = Mount {
+        position: [0.1, 0.0, 0.2],
+        roll: 0.0,
+        pitch: 0.0,
+        yaw: 0.0,
+    };
+
+    fn close(a: [f64; 3], b: [f64; 3]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn it_should_move_points_into_the_base_frame() {
+        assert!(close(MOUNT.to_base([0.0, 0.0, 1.0]), [1.1, 0.0, 0.2]));
+        assert!(close(MOUNT.to_base([0.5, 0.1, 1.0]), [1.1, -0.5, 0.1]));
+        let tilted = Mount {
+            pitch: FRAC_PI_2,
+            ..MOUNT
+        };
+        assert!(close(tilted.to_base([0.0, 0.0, 0.2]), [0.1, 0.0, 0.0]));
+        let turned = Mount {
+            yaw: FRAC_PI_2,
+            ..MOUNT
+        };
+        assert!(close(turned.to_base([0.0, 0.0, 1.0]), [0.1, 1.0, 0.2]));
+    }
+
+    #[test]
+    fn it_should_keep_only_points_in_the_height_band_and_range() {
+        let projector = Projector::new(MOUNT, ProjectionConfig::default());
+        let floor = [0.0, 0.19, 1.0];
+        let chair_leg = [0.0, 0.1, 1.0];
+        let tabletop = [0.0, -0.5, 1.0];
+        let distant_wall = [0.0, 0.0, 5.0];
+        let hits = projector.project(&[floor, chair_leg, tabletop, distant_wall]);
+        assert_eq!(hits.len(), 1);
+        assert!((hits[0][0] - 1.125).abs() < 1e-9 && (hits[0][1] - 0.025).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_should_merge_hits_in_the_same_cell() {
+        let projector = Projector::new(MOUNT, ProjectionConfig::default());
+        let hits = projector.project(&[[0.0, 0.1, 1.0], [-0.01, 0.05, 1.01], [0.2, 0.1, 1.0]]);
+        assert_eq!(hits.len(), 2);
+    }
+}
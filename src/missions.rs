@@ -0,0 +1,5 @@
+//! Jobs the robot carries out on its own, built from navigation goals.
+
+pub mod patrol;
+
+pub use patrol::{Patrol, PatrolRun};
@@ -0,0 +1,687 @@
+//! Patrols: looped routes through named places.
+//!
+//! A [`Patrol`] visits a list of [places](crate::navigation::places) in
+//! order, performing each stop’s actions on arrival, such as pausing to
+//! listen, panning the camera across a doorway, and taking a snapshot, then
+//! starts over from the first stop for as many laps as it is given. Its
+//! times are added to the [`Schedule`] as tasks naming the patrol, for a
+//! night watch that runs itself.
+//!
+//! A patrol is defined in code or in JSON:
+//!
+//! ```text
+//! {"name":"night watch","laps":2,
+//!  "stops":[{"place":"front door","actions":[
+//!     {"type":"pause","seconds":10},
+//!     {"type":"pan","pan":0.8,"tilt":0.1},
+//!     {"type":"snapshot"}]},
+//!   {"place":"kitchen"}],
+//!  "schedule":[{"at":"22:00","days":"weekdays"}]}
+//! ```
+//!
+//! Outdoors, a stop can be a latitude and longitude instead of a place,
+//! such as `{"name":"mailbox","latitude":40.7128,"longitude":-74.006}`, for
+//! patrols guided by GPS. Geographic stops are converted to the map through
+//! the localizer’s east-north-up [`LocalFrame`] when the patrol starts.
+//!
+//! A [`PatrolRun`] carries it out, polled from the main loop with the
+//! robot’s pose, sending the navigator to each stop in turn.
+
+use std::time::{Duration, Instant};
+
+use crate::geometry::geodesy::{GeoPoint, LocalFrame};
+use crate::geometry::Pose;
+use crate::json::Value;
+use crate::log_event;
+use crate::logging::Level;
+use crate::navigation::places::PlaceError;
+use crate::navigation::{Navigator, Places};
+use crate::runtime::schedule::{Days, Schedule, Task, TimeOfDay};
+
+const SUBSYSTEM: &str = "patrol";
+
+/// Something done on arriving at a stop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Waits in place.
+    Pause(Duration),
+    /// Points the camera, in radians from straight ahead, with positive pan
+    /// to the left and positive tilt up.
+    PanCamera {
+        /// Pan angle, in radians.
+        pan: f64,
+        /// Tilt angle, in radians.
+        tilt: f64,
+    },
+    /// Takes a picture with the camera.
+    Snapshot,
+}
+
+/// The camera and recorder a patrol acts through.
+pub trait StopActions {
+    /// Points the camera at `pan` and `tilt`, in radians.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the camera cannot be moved.
+    fn pan_camera(&mut self, pan: f64, tilt: f64) -> Result<(), String>;
+
+    /// Takes a picture at the place called `place`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no picture could be taken.
+    fn snapshot(&mut self, place: &str) -> Result<(), String>;
+}
+
+/// A place on a patrol and what to do there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stop {
+    /// Name of the place, or of the waypoint if it has a location.
+    pub place: String,
+    /// Position on the Earth of a geographic waypoint, driven to instead of
+    /// looking the place up.
+    pub location: Option<GeoPoint>,
+    /// Actions performed in order on arrival.
+    pub actions: Vec<Action>,
+}
+
+impl Stop {
+    /// Creates a new stop at the place called `place`, with no actions.
+    pub fn new(place: &str) -> Self {
+        Self {
+            place: place.to_owned(),
+            location: None,
+            actions: Vec::new(),
+        }
+    }
+
+    /// Creates a new stop at the geographic waypoint `location`, called
+    /// `name`, with no actions.
+    pub fn geo(name: &str, location: GeoPoint) -> Self {
+        Self {
+            location: Some(location),
+            ..Self::new(name)
+        }
+    }
+
+    /// Performs `action` after the actions already added.
+    #[must_use]
+    pub fn then(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+}
+
+/// A looped route through named places.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Patrol {
+    laps: Option<u32>,
+    name: String,
+    schedule: Vec<(TimeOfDay, Days)>,
+    stops: Vec<Stop>,
+    tolerance: f64,
+}
+
+impl Patrol {
+    /// Creates a new patrol called `name`, with no stops, that loops until
+    /// stopped and counts a stop reached within 0.3 m.
+    pub fn new(name: &str) -> Self {
+        Self {
+            laps: None,
+            name: name.to_owned(),
+            schedule: Vec::new(),
+            stops: Vec::new(),
+            tolerance: 0.3,
+        }
+    }
+
+    /// Parses a patrol from its JSON definition.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the definition is missing a
+    /// name or stop place, or has an unknown action, a location out of
+    /// range, or a malformed schedule.
+    pub fn from_json(definition: &Value) -> Result<Self, String> {
+        let name = definition
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or("patrol should have a name")?;
+        let mut patrol = Self::new(name);
+
+        if let Some(laps) = definition.get("laps").and_then(Value::as_f64) {
+            patrol = patrol.with_laps(laps as u32);
+        }
+
+        if let Some(tolerance) = definition.get("tolerance").and_then(Value::as_f64) {
+            patrol = patrol.with_tolerance(tolerance);
+        }
+
+        for stop in array(definition, "stops") {
+            let coordinate = |key| stop.get(key).and_then(Value::as_f64);
+            let location = match (coordinate("latitude"), coordinate("longitude")) {
+                (Some(latitude), Some(longitude)) => Some(GeoPoint::new(latitude, longitude)),
+                (None, None) => None,
+                _ => return Err("stop should have both a latitude and a longitude".to_owned()),
+            };
+
+            if location.is_some_and(|location| !location.is_valid()) {
+                return Err(format!("stop location {location:?} is out of range"));
+            }
+
+            let place = match (stop.get("place").or(stop.get("name")), location) {
+                (Some(name), _) => name.as_str().ok_or("stop name should be text")?.to_owned(),
+                (None, Some(location)) => {
+                    format!("{:.6}, {:.6}", location.latitude, location.longitude)
+                }
+                (None, None) => return Err("stop should name a place".to_owned()),
+            };
+            let actions = array(stop, "actions")
+                .iter()
+                .map(parse_action)
+                .collect::<Result<_, _>>()?;
+            patrol = patrol.with_stop(Stop {
+                place,
+                location,
+                actions,
+            });
+        }
+
+        for entry in array(definition, "schedule") {
+            let at = entry
+                .get("at")
+                .and_then(Value::as_str)
+                .ok_or("schedule entry should have a time")?
+                .parse()?;
+            let days = match entry.get("days").and_then(Value::as_str) {
+                None | Some("every day") => Days::EVERY_DAY,
+                Some("weekdays") => Days::WEEKDAYS,
+                Some("weekends") => Days::WEEKENDS,
+                Some(days) => return Err(format!("unknown days `{days}`")),
+            };
+            patrol = patrol.at(at, days);
+        }
+
+        Ok(patrol)
+    }
+
+    /// Visits `stop` after the stops already added.
+    #[must_use]
+    pub fn with_stop(mut self, stop: Stop) -> Self {
+        self.stops.push(stop);
+        self
+    }
+
+    /// Finishes after `laps` times around the route, instead of looping
+    /// until stopped.
+    #[must_use]
+    pub fn with_laps(mut self, laps: u32) -> Self {
+        self.laps = Some(laps);
+        self
+    }
+
+    /// Counts a stop as reached within `tolerance`, in metres.
+    #[must_use]
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Starts the patrol at `at` on `days`.
+    #[must_use]
+    pub fn at(mut self, at: TimeOfDay, days: Days) -> Self {
+        self.schedule.push((at, days));
+        self
+    }
+
+    /// Returns the name.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the stops, in order.
+    #[must_use]
+    pub fn stops(&self) -> &[Stop] {
+        &self.stops
+    }
+
+    /// Returns the tasks that start the patrol at its scheduled times, named
+    /// after the patrol and time.
+    #[must_use]
+    pub fn tasks(&self) -> Vec<Task> {
+        self.schedule
+            .iter()
+            .map(|&(at, days)| Task::new(&format!("{} {at}", self.name), at, &self.name).on(days))
+            .collect()
+    }
+
+    /// Adds the patrol’s tasks to `schedule`.
+    pub fn schedule_into(&self, schedule: &mut Schedule) {
+        self.tasks().into_iter().for_each(|task| schedule.add(task));
+    }
+}
+
+/// What a patrol is doing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatrolStatus {
+    /// Driving to the named place.
+    Driving(String),
+    /// Performing the actions at the named place.
+    Acting(String),
+    /// Done with every lap.
+    Finished,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Departing,
+    Driving,
+    Acting {
+        action: usize,
+        until: Option<Instant>,
+    },
+    Finished,
+}
+
+/// A patrol being carried out.
+#[derive(Debug)]
+pub struct PatrolRun {
+    goals: Vec<Pose>,
+    lap: u32,
+    patrol: Patrol,
+    phase: Phase,
+    stop: usize,
+}
+
+impl PatrolRun {
+    /// Starts `patrol`, looking up its stops in `places`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a stop names an unknown place,
+    /// so a typo is caught before the robot sets off rather than at night,
+    /// or is a geographic waypoint.
+    pub fn start(patrol: Patrol, places: &Places) -> Result<Self, PlaceError> {
+        Self::begin(patrol, places, None)
+    }
+
+    /// Starts `patrol` outdoors, looking up its named stops in `places` and
+    /// converting its geographic waypoints through `frame`.
+    ///
+    /// Each waypoint’s goal faces the way the robot travels to it from the
+    /// previous stop, so it does not turn on the spot on arrival.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a stop names an unknown place.
+    pub fn start_outdoors(
+        patrol: Patrol,
+        places: &Places,
+        frame: &LocalFrame,
+    ) -> Result<Self, PlaceError> {
+        Self::begin(patrol, places, Some(frame))
+    }
+
+    fn begin(
+        patrol: Patrol,
+        places: &Places,
+        frame: Option<&LocalFrame>,
+    ) -> Result<Self, PlaceError> {
+        let mut goals: Vec<Pose> = patrol
+            .stops
+            .iter()
+            .map(|stop| match (stop.location, frame) {
+                (None, _) => places
+                    .get(&stop.place)
+                    .ok_or_else(|| PlaceError::Unknown(stop.place.clone())),
+                (Some(location), Some(frame)) => {
+                    let (east, north) = frame.to_local(location);
+                    Ok(Pose::new(east, north, 0.0))
+                }
+                (Some(_), None) => Err(PlaceError::NoOrigin(stop.place.clone())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        for index in 0..goals.len() {
+            if patrol.stops[index].location.is_some() {
+                let previous = goals[(index + goals.len() - 1) % goals.len()];
+                let goal = &mut goals[index];
+                goal.heading = (goal.y - previous.y).atan2(goal.x - previous.x);
+            }
+        }
+
+        log_event!(SUBSYSTEM, Level::Info, "starting `{}`", patrol.name);
+        let phase = if patrol.stops.is_empty() || patrol.laps == Some(0) {
+            Phase::Finished
+        } else {
+            Phase::Departing
+        };
+        Ok(Self {
+            goals,
+            lap: 0,
+            patrol,
+            phase,
+            stop: 0,
+        })
+    }
+
+    /// Returns the number of laps completed.
+    #[must_use]
+    pub fn laps(&self) -> u32 {
+        self.lap
+    }
+
+    /// Advances the patrol given the robot’s `pose` at `now`, sending
+    /// `navigator` to each stop and performing its actions through
+    /// `actions`.
+    ///
+    /// An action that fails is logged and skipped, so one missed snapshot
+    /// does not end the watch.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the navigator refuses a stop,
+    /// after which the patrol tries that stop again on the next poll.
+    pub fn poll(
+        &mut self,
+        pose: Pose,
+        now: Instant,
+        navigator: &mut impl Navigator,
+        actions: &mut impl StopActions,
+    ) -> Result<PatrolStatus, PlaceError> {
+        loop {
+            if self.phase == Phase::Finished {
+                return Ok(PatrolStatus::Finished);
+            }
+
+            let stop = &self.patrol.stops[self.stop];
+
+            match self.phase {
+                Phase::Finished => unreachable!("finished patrols return above"),
+                Phase::Departing => {
+                    navigator
+                        .navigate_to(self.goals[self.stop])
+                        .map_err(|reason| PlaceError::Refused {
+                            name: stop.place.clone(),
+                            reason,
+                        })?;
+                    self.phase = Phase::Driving;
+                }
+                Phase::Driving => {
+                    if pose.distance_to(&self.goals[self.stop]) > self.patrol.tolerance {
+                        return Ok(PatrolStatus::Driving(stop.place.clone()));
+                    }
+
+                    log_event!(SUBSYSTEM, Level::Info, "reached `{}`", stop.place);
+                    self.phase = Phase::Acting {
+                        action: 0,
+                        until: None,
+                    };
+                }
+                Phase::Acting { action, until } => {
+                    let Some(&current) = stop.actions.get(action) else {
+                        self.advance();
+                        continue;
+                    };
+                    let next = Phase::Acting {
+                        action: action + 1,
+                        until: None,
+                    };
+                    let result = match current {
+                        Action::Pause(duration) => match until {
+                            None => {
+                                self.phase = Phase::Acting {
+                                    action,
+                                    until: Some(now + duration),
+                                };
+                                continue;
+                            }
+                            Some(until) if now < until => {
+                                return Ok(PatrolStatus::Acting(stop.place.clone()))
+                            }
+                            Some(_) => Ok(()),
+                        },
+                        Action::PanCamera { pan, tilt } => actions.pan_camera(pan, tilt),
+                        Action::Snapshot => actions.snapshot(&stop.place),
+                    };
+
+                    if let Err(error) = result {
+                        log_event!(
+                            SUBSYSTEM,
+                            Level::Warn,
+                            "skipping {current:?} at `{}`: {error}",
+                            stop.place
+                        );
+                    }
+
+                    self.phase = next;
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        self.stop += 1;
+        self.phase = Phase::Departing;
+
+        if self.stop == self.patrol.stops.len() {
+            self.stop = 0;
+            self.lap += 1;
+
+            if self.patrol.laps.is_some_and(|laps| self.lap >= laps) {
+                log_event!(SUBSYSTEM, Level::Info, "finished `{}`", self.patrol.name);
+                self.phase = Phase::Finished;
+            }
+        }
+    }
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value.get(key).and_then(Value::as_array).unwrap_or(&[])
+}
+
+fn parse_action(action: &Value) -> Result<Action, String> {
+    let number = |key| action.get(key).and_then(Value::as_f64);
+
+    match action.get("type").and_then(Value::as_str) {
+        Some("pause") => number("seconds")
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .map(Action::Pause)
+            .ok_or_else(|| "pause should have a number of seconds".to_owned()),
+        Some("pan") => Ok(Action::PanCamera {
+            pan: number("pan").unwrap_or(0.0),
+            tilt: number("tilt").unwrap_or(0.0),
+        }),
+        Some("snapshot") => Ok(Action::Snapshot),
+        Some(kind) => Err(format!("unknown action `{kind}`")),
+        None => Err("action should have a type".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Camera {
+        log: Vec<String>,
+    }
+
+    impl StopActions for Camera {
+        fn pan_camera(&mut self, pan: f64, tilt: f64) -> Result<(), String> {
+            self.log.push(format!("pan {pan} {tilt}"));
+            Ok(())
+        }
+
+        fn snapshot(&mut self, place: &str) -> Result<(), String> {
+            self.log.push(format!("snapshot {place}"));
+            Err("disk full".to_owned())
+        }
+    }
+
+    fn places() -> Places {
+        let places = Places::new();
+        places.set("front door", Pose::new(5.0, 0.0, 0.0)).unwrap();
+        places.set("kitchen", Pose::new(0.0, 4.0, 0.0)).unwrap();
+        places
+    }
+
+    #[test]
+    fn it_should_parse_a_definition_and_schedule_it() {
+        let definition = r#"{"name":"night watch","laps":2,
+            "stops":[{"place":"front door","actions":[
+                {"type":"pause","seconds":10},{"type":"pan","pan":0.8},{"type":"snapshot"}]},
+              {"place":"kitchen"}],
+            "schedule":[{"at":"22:00","days":"weekdays"}]}"#;
+        let patrol = Patrol::from_json(&definition.parse().unwrap()).unwrap();
+        let expected = Patrol::new("night watch")
+            .with_laps(2)
+            .with_stop(
+                Stop::new("front door")
+                    .then(Action::Pause(Duration::from_secs(10)))
+                    .then(Action::PanCamera {
+                        pan: 0.8,
+                        tilt: 0.0,
+                    })
+                    .then(Action::Snapshot),
+            )
+            .with_stop(Stop::new("kitchen"))
+            .at(TimeOfDay::new(22, 0), Days::WEEKDAYS);
+        assert_eq!(patrol, expected);
+        let mut schedule = Schedule::new();
+        patrol.schedule_into(&mut schedule);
+        assert_eq!(
+            schedule.tasks(),
+            [
+                Task::new("night watch 22:00", TimeOfDay::new(22, 0), "night watch")
+                    .on(Days::WEEKDAYS)
+            ]
+        );
+        let bad = r#"{"name":"x","stops":[{"place":"a","actions":[{"type":"dance"}]}]}"#;
+        assert!(Patrol::from_json(&bad.parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn it_should_refuse_to_start_with_an_unknown_place() {
+        let patrol = Patrol::new("watch").with_stop(Stop::new("garage"));
+        assert!(matches!(
+            PatrolRun::start(patrol, &places()),
+            Err(PlaceError::Unknown(place)) if place == "garage"
+        ));
+    }
+
+    #[test]
+    fn it_should_drive_to_geographic_waypoints_outdoors() {
+        let definition = r#"{"name":"fence line","laps":1,"stops":[
+            {"latitude":40.0,"longitude":-74.0},
+            {"name":"gate","latitude":40.0001,"longitude":-74.0},
+            {"place":"front door"}]}"#;
+        let patrol = Patrol::from_json(&definition.parse().unwrap()).unwrap();
+        assert_eq!(
+            patrol.stops()[..2],
+            [
+                Stop::geo("40.000000, -74.000000", GeoPoint::new(40.0, -74.0)),
+                Stop::geo("gate", GeoPoint::new(40.0001, -74.0))
+            ]
+        );
+        assert!(matches!(
+            PatrolRun::start(patrol.clone(), &places()),
+            Err(PlaceError::NoOrigin(name)) if name == "40.000000, -74.000000"
+        ));
+
+        let frame = LocalFrame::new(GeoPoint::new(40.0, -74.0));
+        let mut run = PatrolRun::start_outdoors(patrol, &places(), &frame).unwrap();
+        let mut goals = Vec::new();
+        let mut navigator = |goal| {
+            goals.push(goal);
+            Ok(())
+        };
+        let start = Instant::now();
+        let mut camera = Camera::default();
+        assert_eq!(
+            run.poll(Pose::default(), start, &mut navigator, &mut camera)
+                .unwrap(),
+            PatrolStatus::Driving("gate".to_owned())
+        );
+        // The gate is 11 m north of the first waypoint, which is reached
+        // from the front door to its east.
+        let (first, gate) = (goals[0], goals[1]);
+        assert!(first.x.abs() < 1e-9 && first.y.abs() < 1e-9);
+        assert!((first.heading - std::f64::consts::PI).abs() < 1e-9);
+        assert!((gate.y - 11.1).abs() < 0.05 && gate.x.abs() < 1e-6);
+        assert!((gate.heading - std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+
+        let bad = r#"{"name":"x","stops":[{"latitude":95.0,"longitude":0.0}]}"#;
+        assert!(Patrol::from_json(&bad.parse().unwrap()).is_err());
+        let bad = r#"{"name":"x","stops":[{"latitude":45.0}]}"#;
+        assert!(Patrol::from_json(&bad.parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn it_should_visit_each_stop_and_loop_for_its_laps() {
+        let patrol = Patrol::new("watch")
+            .with_laps(2)
+            .with_stop(
+                Stop::new("front door")
+                    .then(Action::Pause(Duration::from_secs(10)))
+                    .then(Action::PanCamera {
+                        pan: 0.8,
+                        tilt: 0.0,
+                    })
+                    .then(Action::Snapshot),
+            )
+            .with_stop(Stop::new("kitchen"));
+        let mut run = PatrolRun::start(patrol, &places()).unwrap();
+        let mut goals = Vec::new();
+        let mut navigator = |goal| {
+            goals.push(goal);
+            Ok(())
+        };
+        let mut camera = Camera::default();
+        let (door, kitchen) = (Pose::new(5.0, 0.1, 0.0), Pose::new(0.0, 4.0, 0.0));
+        let start = Instant::now();
+        let mut poll = |pose, seconds| {
+            run.poll(
+                pose,
+                start + Duration::from_secs(seconds),
+                &mut navigator,
+                &mut camera,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            poll(Pose::default(), 0),
+            PatrolStatus::Driving("front door".to_owned())
+        );
+        assert_eq!(poll(door, 5), PatrolStatus::Acting("front door".to_owned()));
+        assert_eq!(
+            poll(door, 14),
+            PatrolStatus::Acting("front door".to_owned())
+        );
+        assert_eq!(poll(door, 15), PatrolStatus::Driving("kitchen".to_owned()));
+        assert_eq!(
+            poll(kitchen, 30),
+            PatrolStatus::Driving("front door".to_owned())
+        );
+        assert_eq!(
+            poll(door, 40),
+            PatrolStatus::Acting("front door".to_owned())
+        );
+        assert_eq!(poll(door, 50), PatrolStatus::Driving("kitchen".to_owned()));
+        assert_eq!(poll(kitchen, 60), PatrolStatus::Finished);
+        assert_eq!(run.laps(), 2);
+        assert_eq!(goals.len(), 4);
+        assert_eq!(
+            camera.log,
+            [
+                "pan 0.8 0",
+                "snapshot front door",
+                "pan 0.8 0",
+                "snapshot front door"
+            ]
+        );
+    }
+}
@@ -0,0 +1,28 @@
+//! Getting the robot where it is told to go.
+//!
+//! Goals are poses in the map frame, handed to a [`Navigator`] that plans
+//! and drives the way there. [`Places`] names the goals people care about,
+//! so missions and voice commands can say “kitchen” instead of coordinates.
+
+use crate::geometry::Pose;
+
+pub mod places;
+
+pub use places::Places;
+
+/// Something that drives the robot to a goal in the map frame.
+pub trait Navigator {
+    /// Starts driving to `goal`, replacing any goal already set.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the goal is refused, such as
+    /// when it is unreachable or the robot is e-stopped.
+    fn navigate_to(&mut self, goal: Pose) -> Result<(), String>;
+}
+
+impl<F: FnMut(Pose) -> Result<(), String>> Navigator for F {
+    fn navigate_to(&mut self, goal: Pose) -> Result<(), String> {
+        self(goal)
+    }
+}
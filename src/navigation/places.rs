@@ -0,0 +1,343 @@
+//! Named places on the map.
+//!
+//! A place is a pose with a name a person would use, such as “dock”,
+//! “kitchen”, or “front door”. Places are kept in `places.json` beside the
+//! map they were marked on, since their coordinates mean nothing on any
+//! other map, and are saved as soon as they change. Names are matched
+//! without regard to case or spacing, so “Front  Door” from a voice command
+//! finds the place marked as “front door”.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::Navigator;
+use crate::geometry::Pose;
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "places";
+
+/// Name of the file the places are kept in, in the map’s directory.
+pub const FILE_NAME: &str = "places.json";
+
+/// An error from managing or going to places.
+#[derive(Debug)]
+pub enum PlaceError {
+    /// No place has the name.
+    Unknown(String),
+    /// The name is empty.
+    InvalidName,
+    /// The pose has a coordinate that is not finite.
+    InvalidPose(Pose),
+    /// The place is a geographic waypoint, but the robot has no outdoor
+    /// frame to convert it through.
+    NoOrigin(String),
+    /// The navigator refused the place as a goal.
+    Refused {
+        /// Name of the place.
+        name: String,
+        /// Why the navigator refused it.
+        reason: String,
+    },
+    /// The places file could not be read or written.
+    Io(io::Error),
+}
+
+impl Display for PlaceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "no place is named `{name}`"),
+            Self::InvalidName => f.write_str("place name should not be empty"),
+            Self::InvalidPose(pose) => write!(f, "place pose {pose:?} should be finite"),
+            Self::NoOrigin(name) => {
+                write!(
+                    f,
+                    "`{name}` is a geographic waypoint, but there is no outdoor origin"
+                )
+            }
+            Self::Refused { name, reason } => write!(f, "could not go to `{name}`: {reason}"),
+            Self::Io(error) => write!(f, "could not access the places file: {error}"),
+        }
+    }
+}
+
+impl Error for PlaceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PlaceError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Named poses on one map, shared by every handle cloned from it.
+#[derive(Clone, Debug, Default)]
+pub struct Places {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    path: Option<PathBuf>,
+    places: BTreeMap<String, Pose>,
+}
+
+impl Places {
+    /// Creates a new, empty `Places` that is not saved.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the places kept with the map in `map_dir`, which are saved
+    /// there as they change.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the places file exists but
+    /// cannot be read or parsed.
+    pub fn open(map_dir: &Path) -> Result<Self, PlaceError> {
+        let path = map_dir.join(FILE_NAME);
+        let places = match std::fs::read_to_string(&path) {
+            Ok(contents) => parse(&contents)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => return Err(error.into()),
+        };
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                path: Some(path),
+                places,
+            })),
+        })
+    }
+
+    /// Returns the pose of the place called `name`.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Pose> {
+        self.lock().places.get(&normalize(name)).copied()
+    }
+
+    /// Returns every place, ordered by name.
+    #[must_use]
+    pub fn list(&self) -> Vec<(String, Pose)> {
+        self.lock()
+            .places
+            .iter()
+            .map(|(name, pose)| (name.clone(), *pose))
+            .collect()
+    }
+
+    /// Marks `pose` as the place called `name`, replacing any place already
+    /// called that.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the name is empty, the pose is
+    /// not finite, or the places cannot be saved, in which case they are
+    /// left unchanged.
+    pub fn set(&self, name: &str, pose: Pose) -> Result<(), PlaceError> {
+        let name = normalize(name);
+
+        if name.is_empty() {
+            return Err(PlaceError::InvalidName);
+        }
+
+        if ![pose.x, pose.y, pose.heading].iter().all(|v| v.is_finite()) {
+            return Err(PlaceError::InvalidPose(pose));
+        }
+
+        let mut inner = self.lock();
+        let previous = inner.places.insert(name.clone(), pose);
+
+        if let Err(error) = inner.save() {
+            match previous {
+                Some(previous) => inner.places.insert(name, previous),
+                None => inner.places.remove(&name),
+            };
+            return Err(error.into());
+        }
+
+        log_event!(SUBSYSTEM, Level::Info, "marked `{name}` at {pose:?}");
+        Ok(())
+    }
+
+    /// Forgets the place called `name`, returning whether there was one.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the places cannot be saved, in
+    /// which case they are left unchanged.
+    pub fn remove(&self, name: &str) -> Result<bool, PlaceError> {
+        let name = normalize(name);
+        let mut inner = self.lock();
+        let Some(pose) = inner.places.remove(&name) else {
+            return Ok(false);
+        };
+
+        if let Err(error) = inner.save() {
+            inner.places.insert(name, pose);
+            return Err(error.into());
+        }
+
+        log_event!(SUBSYSTEM, Level::Info, "removed `{name}`");
+        Ok(true)
+    }
+
+    /// Sends `navigator` to the place called `name`, returning its pose.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no place has the name or the
+    /// navigator refuses it.
+    pub fn go_to(&self, name: &str, navigator: &mut impl Navigator) -> Result<Pose, PlaceError> {
+        let pose = self
+            .get(name)
+            .ok_or_else(|| PlaceError::Unknown(name.to_owned()))?;
+        navigator
+            .navigate_to(pose)
+            .map_err(|reason| PlaceError::Refused {
+                name: normalize(name),
+                reason,
+            })?;
+        log_event!(SUBSYSTEM, Level::Info, "going to `{}`", normalize(name));
+        Ok(pose)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ToJson for Places {
+    fn to_json(&self) -> Value {
+        self.lock().to_json()
+    }
+}
+
+impl Inner {
+    fn save(&self) -> Result<(), io::Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let contents = self.to_json().to_string();
+        #[cfg(unix)]
+        crate::unix::fsutil::write_atomic(path, contents.as_bytes())?;
+        #[cfg(not(unix))]
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    fn to_json(&self) -> Value {
+        self.places
+            .iter()
+            .fold(Value::object(), |object, (name, pose)| {
+                object.with(name, pose)
+            })
+    }
+}
+
+/// Returns `name` in lower case with its words separated by single spaces.
+fn normalize(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn parse(contents: &str) -> Result<BTreeMap<String, Pose>, String> {
+    let Value::Object(entries) = contents.parse()? else {
+        return Err("places file should contain a JSON object".to_owned());
+    };
+
+    entries
+        .into_iter()
+        .map(|(name, pose)| {
+            let coordinate = |key| {
+                pose.get(key)
+                    .and_then(Value::as_f64)
+                    .ok_or_else(|| format!("place `{name}` should have a number `{key}`"))
+            };
+            let pose = Pose::new(coordinate("x")?, coordinate("y")?, coordinate("heading")?);
+            Ok((normalize(&name), pose))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn it_should_keep_places_with_the_map() {
+        let dir = crate::TemporaryDirectory::new().unwrap();
+        let places = Places::open(dir.path()).unwrap();
+        places.set("Kitchen", Pose::new(3.0, 1.5, 0.5)).unwrap();
+        places
+            .set("front  door", Pose::new(-2.0, 0.0, 3.0))
+            .unwrap();
+        places.set("dock", Pose::default()).unwrap();
+        assert!(places.remove("DOCK").unwrap());
+        assert!(!places.remove("dock").unwrap());
+
+        let reopened = Places::open(dir.path()).unwrap();
+        assert_eq!(
+            reopened.list(),
+            [
+                ("front door".to_owned(), Pose::new(-2.0, 0.0, 3.0)),
+                ("kitchen".to_owned(), Pose::new(3.0, 1.5, 0.5)),
+            ]
+        );
+        assert_eq!(reopened.get("Front Door"), Some(Pose::new(-2.0, 0.0, 3.0)));
+    }
+
+    #[test]
+    fn it_should_reject_unnamed_or_unbounded_places() {
+        let places = Places::new();
+        assert!(matches!(
+            places.set("  ", Pose::default()),
+            Err(PlaceError::InvalidName)
+        ));
+        assert!(matches!(
+            places.set("hall", Pose::new(f64::NAN, 0.0, 0.0)),
+            Err(PlaceError::InvalidPose(_))
+        ));
+        assert!(places.list().is_empty());
+    }
+
+    #[test]
+    fn it_should_send_the_navigator_to_a_named_place() {
+        let places = Places::new();
+        places.set("kitchen", Pose::new(3.0, 1.5, 0.5)).unwrap();
+        let mut goals = Vec::new();
+        let mut navigator = |goal| {
+            goals.push(goal);
+            Ok(())
+        };
+        assert_eq!(
+            places.go_to("kitchen", &mut navigator).unwrap(),
+            Pose::new(3.0, 1.5, 0.5)
+        );
+        assert!(matches!(
+            places.go_to("garage", &mut navigator),
+            Err(PlaceError::Unknown(_))
+        ));
+        let mut estopped = |_| Err("e-stopped".to_owned());
+        assert!(matches!(
+            places.go_to("kitchen", &mut estopped),
+            Err(PlaceError::Refused { .. })
+        ));
+        assert_eq!(goals, [Pose::new(3.0, 1.5, 0.5)]);
+    }
+}
@@ -0,0 +1,13 @@
+//! Network interfaces for watching and controlling the robot.
+
+pub mod auth;
+#[cfg(feature = "foxglove")]
+pub mod foxglove;
+pub mod http;
+#[cfg(feature = "notify")]
+pub mod notify;
+pub mod proto;
+pub mod rtp;
+pub mod telemetry;
+pub mod virtual_gamepad;
+pub mod websocket;
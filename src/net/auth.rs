@@ -0,0 +1,198 @@
+//! Authentication and authorization shared by the network interfaces.
+//!
+//! Clients present a pre-shared token, which grants a [`Permission`]: read
+//! access for watching telemetry, or drive access for moving the robot. A
+//! guest on the same network without a token gets only the anonymous
+//! permission, which is nothing unless configured otherwise.
+
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+
+/// What a client may do, from least to most privileged.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Permission {
+    /// Reading state and telemetry.
+    Read,
+    /// Reading, and commanding the robot to move.
+    Drive,
+}
+
+/// Why a client was refused.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AuthError {
+    /// The client presented no token, or one that is not recognized.
+    Unauthenticated,
+    /// The client’s token does not grant the required permission.
+    Forbidden,
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unauthenticated => f.write_str("missing or unrecognized token"),
+            Self::Forbidden => f.write_str("token does not permit this"),
+        }
+    }
+}
+
+impl Error for AuthError {}
+
+/// Pre-shared tokens and the permissions they grant.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct Auth {
+    anonymous: Option<Permission>,
+    tokens: Vec<(String, Permission)>,
+}
+
+impl Auth {
+    /// Creates a new `Auth` that refuses every client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grants `permission` to clients presenting `token`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `token` is empty.
+    #[must_use]
+    pub fn with_token(mut self, token: &str, permission: Permission) -> Self {
+        assert!(!token.is_empty(), "token should not be empty");
+        self.tokens.push((token.to_owned(), permission));
+        self
+    }
+
+    /// Grants `permission` to clients without a token.
+    #[must_use]
+    pub fn with_anonymous(mut self, permission: Permission) -> Self {
+        self.anonymous = Some(permission);
+        self
+    }
+
+    /// Returns the permission granted by `token`, or to anonymous clients if
+    /// there is none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`AuthError::Unauthenticated`] if the token
+    /// is not recognized, or if there is none and anonymous clients are
+    /// refused.
+    pub fn authenticate(&self, token: Option<&str>) -> Result<Permission, AuthError> {
+        let Some(token) = token else {
+            return self.anonymous.ok_or(AuthError::Unauthenticated);
+        };
+
+        // Check every token so the time taken does not reveal which one
+        // came close.
+        self.tokens
+            .iter()
+            .filter(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|&(_, permission)| permission)
+            .max()
+            .ok_or(AuthError::Unauthenticated)
+    }
+
+    /// Checks that `token` grants at least `required`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the token is not recognized,
+    /// or does not grant `required`.
+    pub fn authorize(
+        &self,
+        token: Option<&str>,
+        required: Permission,
+    ) -> Result<Permission, AuthError> {
+        let permission = self.authenticate(token)?;
+
+        if permission >= required {
+            Ok(permission)
+        } else {
+            Err(AuthError::Forbidden)
+        }
+    }
+}
+
+impl Debug for Auth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // Never log the tokens themselves.
+        f.debug_struct("Auth")
+            .field("anonymous", &self.anonymous)
+            .field("tokens", &self.tokens.len())
+            .finish()
+    }
+}
+
+/// Returns the token from an `Authorization: Bearer <token>` header value.
+#[must_use]
+pub fn bearer_token(header: &str) -> Option<&str> {
+    let (scheme, token) = header.trim().split_once(' ')?;
+    let token = token.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> Auth {
+        Auth::new()
+            .with_token("guest-token", Permission::Read)
+            .with_token("pilot-token", Permission::Drive)
+    }
+
+    #[test]
+    fn it_should_grant_the_permission_of_a_token() {
+        let auth = auth();
+        assert_eq!(auth.authenticate(Some("guest-token")), Ok(Permission::Read));
+        assert_eq!(
+            auth.authenticate(Some("pilot-token")),
+            Ok(Permission::Drive)
+        );
+        assert_eq!(
+            auth.authenticate(Some("pilot-toke")),
+            Err(AuthError::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn it_should_refuse_anonymous_clients_unless_configured() {
+        assert_eq!(auth().authenticate(None), Err(AuthError::Unauthenticated));
+        let auth = auth().with_anonymous(Permission::Read);
+        assert_eq!(auth.authorize(None, Permission::Read), Ok(Permission::Read));
+        assert_eq!(
+            auth.authorize(None, Permission::Drive),
+            Err(AuthError::Forbidden)
+        );
+    }
+
+    #[test]
+    fn it_should_forbid_reading_tokens_from_driving() {
+        assert_eq!(
+            auth().authorize(Some("guest-token"), Permission::Drive),
+            Err(AuthError::Forbidden)
+        );
+    }
+
+    #[test]
+    fn it_should_parse_bearer_tokens() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer  abc "), Some("abc"));
+        assert_eq!(bearer_token("Basic abc"), None);
+        assert_eq!(bearer_token("Bearer"), None);
+    }
+
+    #[test]
+    fn it_should_not_reveal_tokens_when_debug_formatted() {
+        assert!(!format!("{:?}", auth()).contains("pilot"));
+    }
+}
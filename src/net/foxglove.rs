@@ -0,0 +1,464 @@
+//! A bridge to Foxglove Studio, for viewing the robot live without building
+//! a UI of our own.
+//!
+//! The [`Bridge`] speaks the Foxglove WebSocket protocol
+//! (`foxglove.websocket.v1`): each client is told the channels on offer,
+//! subscribes to those it wants, and receives every message published to
+//! them from then on. Messages are JSON using Foxglove’s well-known schemas
+//! where one fits, so that its 3D, plot, and image panels understand
+//! transforms, scans, and camera frames without any configuration.
+//!
+//! Given [`Auth`], the bridge only upgrades clients whose token grants read
+//! permission, passed as `ws://robot:8765/?access_token=...` since Foxglove
+//! Studio cannot set headers.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::io::Error;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::auth::{Auth, Permission};
+use super::websocket::{base64, Message, Sender, WebSocket};
+use crate::geometry::Pose;
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "foxglove";
+
+/// Subprotocol of the Foxglove WebSocket protocol.
+const PROTOCOL: &str = "foxglove.websocket.v1";
+
+/// Opcode of a binary message carrying channel data.
+const MESSAGE_DATA: u8 = 0x01;
+
+/// Topic of general telemetry.
+pub const TELEMETRY: &str = "/telemetry";
+
+/// Topic of coordinate frame transforms.
+pub const TRANSFORMS: &str = "/tf";
+
+/// Topic of laser scans.
+pub const SCAN: &str = "/scan";
+
+/// Topic of camera frames.
+pub const CAMERA: &str = "/camera";
+
+#[derive(Default)]
+struct Client {
+    // Subscription IDs chosen by the client, by channel ID.
+    subscriptions: BTreeMap<u64, u32>,
+}
+
+struct Channel {
+    schema: &'static str,
+    schema_name: String,
+    topic: String,
+}
+
+/// Publishes channels of JSON messages to Foxglove Studio clients.
+#[derive(Clone)]
+pub struct Bridge {
+    auth: Option<Auth>,
+    channels: Arc<Vec<Channel>>,
+    clients: Arc<Mutex<Vec<(Sender, Client)>>>,
+    name: String,
+}
+
+impl Bridge {
+    /// Creates a new `Bridge` named `name`, offering channels for telemetry,
+    /// transforms, scans, and camera frames.
+    pub fn new(name: &str) -> Self {
+        let channels = [
+            (TELEMETRY, "otter.Telemetry", r#"{"type":"object"}"#),
+            (TRANSFORMS, "foxglove.FrameTransform", FRAME_TRANSFORM),
+            (SCAN, "foxglove.LaserScan", LASER_SCAN),
+            (CAMERA, "foxglove.CompressedImage", COMPRESSED_IMAGE),
+        ];
+        Self {
+            auth: None,
+            channels: Arc::new(
+                channels
+                    .into_iter()
+                    .map(|(topic, schema_name, schema)| Channel {
+                        schema,
+                        schema_name: schema_name.to_owned(),
+                        topic: topic.to_owned(),
+                    })
+                    .collect(),
+            ),
+            clients: Arc::default(),
+            name: name.to_owned(),
+        }
+    }
+
+    /// Requires clients to present a token granting read permission.
+    #[must_use]
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Listens for clients on `address` on a new thread, returning the
+    /// address bound and the thread.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the address cannot be bound.
+    pub fn serve(
+        &self,
+        address: impl ToSocketAddrs,
+    ) -> Result<(SocketAddr, JoinHandle<()>), Error> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let bridge = self.clone();
+        let handle = thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let bridge = bridge.clone();
+                thread::spawn(move || {
+                    if let Err(error) = bridge.handle(stream) {
+                        log_event!(SUBSYSTEM, Level::Debug, "client failed: {error}");
+                    }
+                });
+            }
+        });
+        log_event!(SUBSYSTEM, Level::Info, "listening on {address}");
+        Ok((address, handle))
+    }
+
+    /// Returns the number of clients connected.
+    #[must_use]
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Sends `message` on `topic` to every client subscribed to it,
+    /// returning how many were sent it.
+    pub fn publish(&self, topic: &str, message: &Value) -> usize {
+        let Some(channel) = self
+            .channels
+            .iter()
+            .position(|channel| channel.topic == topic)
+        else {
+            log_event!(SUBSYSTEM, Level::Warn, "no channel for {topic}");
+            return 0;
+        };
+        let channel = channel as u64;
+        let payload = message.to_string();
+        let time = now().as_nanos() as u64;
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sent = 0;
+
+        clients.retain(|(sender, client)| {
+            let Some(&subscription) = client.subscriptions.get(&channel) else {
+                return true;
+            };
+            let mut frame = Vec::with_capacity(13 + payload.len());
+            frame.push(MESSAGE_DATA);
+            frame.extend_from_slice(&subscription.to_le_bytes());
+            frame.extend_from_slice(&time.to_le_bytes());
+            frame.extend_from_slice(payload.as_bytes());
+            // Drop clients that have gone away.
+            let ok = sender.send_binary(&frame).is_ok();
+            sent += usize::from(ok);
+            ok
+        });
+
+        sent
+    }
+
+    /// Publishes a telemetry sample.
+    pub fn publish_telemetry(&self, sample: &impl ToJson) -> usize {
+        self.publish(TELEMETRY, &sample.to_json())
+    }
+
+    /// Publishes the pose of the frame `child` within `parent`.
+    pub fn publish_transform(&self, parent: &str, child: &str, pose: Pose) -> usize {
+        let (sin, cos) = (pose.heading / 2.0).sin_cos();
+        let message = Value::object()
+            .with("timestamp", timestamp())
+            .with("parent_frame_id", parent)
+            .with("child_frame_id", child)
+            .with("translation", vector(pose.x, pose.y, 0.0))
+            .with("rotation", vector(0.0, 0.0, sin).with("w", cos));
+        self.publish(TRANSFORMS, &message)
+    }
+
+    /// Publishes a scan in `frame` of `ranges`, in metres, spread evenly from
+    /// `start_angle` to `end_angle`, in radians.
+    pub fn publish_scan(
+        &self,
+        frame: &str,
+        start_angle: f64,
+        end_angle: f64,
+        ranges: &[f64],
+    ) -> usize {
+        let message = Value::object()
+            .with("timestamp", timestamp())
+            .with("frame_id", frame)
+            .with(
+                "pose",
+                Value::object()
+                    .with("position", vector(0.0, 0.0, 0.0))
+                    .with("orientation", vector(0.0, 0.0, 0.0).with("w", 1.0)),
+            )
+            .with("start_angle", start_angle)
+            .with("end_angle", end_angle)
+            .with("ranges", ranges)
+            .with("intensities", Value::Array(Vec::new()));
+        self.publish(SCAN, &message)
+    }
+
+    /// Publishes a camera frame in `frame`, compressed in `format`, such as
+    /// `jpeg`.
+    pub fn publish_image(&self, frame: &str, format: &str, data: &[u8]) -> usize {
+        let message = Value::object()
+            .with("timestamp", timestamp())
+            .with("frame_id", frame)
+            .with("format", format)
+            .with("data", base64(data));
+        self.publish(CAMERA, &message)
+    }
+
+    fn handle(&self, stream: std::net::TcpStream) -> Result<(), Error> {
+        let peer = stream.peer_addr()?;
+        let (mut socket, _) = match &self.auth {
+            Some(auth) => {
+                WebSocket::accept_authorized(stream, &[PROTOCOL], auth, Permission::Read)?
+            }
+            None => WebSocket::accept(stream, &[PROTOCOL])?,
+        };
+        let sender = socket.sender();
+        sender.send_text(&self.server_info().to_string())?;
+        sender.send_text(&self.advertisement().to_string())?;
+        self.clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((sender.clone(), Client::default()));
+        log_event!(SUBSYSTEM, Level::Info, "{peer} connected");
+
+        let result = loop {
+            match socket.read() {
+                Ok(Message::Text(text)) => self.apply(&sender, &text),
+                Ok(Message::Binary(_)) => {}
+                Ok(Message::Close) => break Ok(()),
+                Err(error) => break Err(error),
+            }
+        };
+
+        self.clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|(other, _)| *other != sender);
+        log_event!(SUBSYSTEM, Level::Info, "{peer} disconnected");
+        result
+    }
+
+    fn apply(&self, sender: &Sender, text: &str) {
+        let Ok(request) = text.parse::<Value>() else {
+            log_event!(SUBSYSTEM, Level::Debug, "ignoring malformed request");
+            return;
+        };
+        let entries = |key| {
+            request
+                .get(key)
+                .and_then(Value::as_array)
+                .unwrap_or_default()
+                .to_vec()
+        };
+
+        match request.get("op").and_then(Value::as_str) {
+            Some("subscribe") => self.with_client(sender, |client| {
+                for subscription in entries("subscriptions") {
+                    let id = subscription.get("id").and_then(Value::as_f64);
+                    let channel = subscription.get("channelId").and_then(Value::as_f64);
+
+                    if let (Some(id), Some(channel)) = (id, channel) {
+                        client.subscriptions.insert(channel as u64, id as u32);
+                    }
+                }
+            }),
+            Some("unsubscribe") => self.with_client(sender, |client| {
+                let ids: Vec<_> = entries("subscriptionIds")
+                    .iter()
+                    .filter_map(Value::as_f64)
+                    .map(|id| id as u32)
+                    .collect();
+                client.subscriptions.retain(|_, id| !ids.contains(id));
+            }),
+            op => log_event!(SUBSYSTEM, Level::Debug, "ignoring request {op:?}"),
+        }
+    }
+
+    fn with_client(&self, sender: &Sender, update: impl FnOnce(&mut Client)) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some((_, client)) = clients.iter_mut().find(|(other, _)| other == sender) {
+            update(client);
+        }
+    }
+
+    fn server_info(&self) -> Value {
+        Value::object()
+            .with("op", "serverInfo")
+            .with("name", self.name.as_str())
+            .with("capabilities", Value::Array(Vec::new()))
+            .with("supportedEncodings", Value::Array(Vec::new()))
+            .with("metadata", Value::object())
+            .with("sessionId", now().as_secs().to_string())
+    }
+
+    fn advertisement(&self) -> Value {
+        let channels = self
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(id, channel)| {
+                Value::object()
+                    .with("id", id)
+                    .with("topic", channel.topic.as_str())
+                    .with("encoding", "json")
+                    .with("schemaName", channel.schema_name.as_str())
+                    .with("schema", channel.schema)
+            })
+            .collect();
+        Value::object()
+            .with("op", "advertise")
+            .with("channels", Value::Array(channels))
+    }
+}
+
+impl Debug for Bridge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bridge")
+            .field("name", &self.name)
+            .field("auth", &self.auth)
+            .field("clients", &self.clients())
+            .finish_non_exhaustive()
+    }
+}
+
+const FRAME_TRANSFORM: &str = r##"{"type":"object","properties":{"timestamp":{"$ref":"#/$defs/time"},"parent_frame_id":{"type":"string"},"child_frame_id":{"type":"string"},"translation":{"$ref":"#/$defs/vector"},"rotation":{"$ref":"#/$defs/quaternion"}},"$defs":{"time":{"type":"object","properties":{"sec":{"type":"integer"},"nsec":{"type":"integer"}}},"vector":{"type":"object","properties":{"x":{"type":"number"},"y":{"type":"number"},"z":{"type":"number"}}},"quaternion":{"type":"object","properties":{"x":{"type":"number"},"y":{"type":"number"},"z":{"type":"number"},"w":{"type":"number"}}}}}"##;
+
+const LASER_SCAN: &str = r##"{"type":"object","properties":{"timestamp":{"type":"object","properties":{"sec":{"type":"integer"},"nsec":{"type":"integer"}}},"frame_id":{"type":"string"},"pose":{"type":"object"},"start_angle":{"type":"number"},"end_angle":{"type":"number"},"ranges":{"type":"array","items":{"type":"number"}},"intensities":{"type":"array","items":{"type":"number"}}}}"##;
+
+const COMPRESSED_IMAGE: &str = r##"{"type":"object","properties":{"timestamp":{"type":"object","properties":{"sec":{"type":"integer"},"nsec":{"type":"integer"}}},"frame_id":{"type":"string"},"data":{"type":"string","contentEncoding":"base64"},"format":{"type":"string"}}}"##;
+
+fn now() -> std::time::Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn timestamp() -> Value {
+    let now = now();
+    Value::object()
+        .with("sec", now.as_secs())
+        .with("nsec", now.subsec_nanos())
+}
+
+fn vector(x: f64, y: f64, z: f64) -> Value {
+    Value::object().with("x", x).with("y", y).with("z", z)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::super::websocket::{
+        connect_for_test, connect_to_for_test, receive_for_test, send_for_test,
+    };
+    use super::*;
+
+    fn receive_json(stream: &mut std::net::TcpStream) -> Value {
+        let (_, payload) = receive_for_test(stream);
+        String::from_utf8(payload).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn it_should_advertise_channels_and_publish_to_subscribers() {
+        let bridge = Bridge::new("otter");
+        let (address, _) = bridge.serve("127.0.0.1:0").unwrap();
+        let (mut client, headers) = connect_for_test(address, PROTOCOL);
+        assert!(headers.contains(PROTOCOL));
+
+        let info = receive_json(&mut client);
+        assert_eq!(info.get("op").and_then(Value::as_str), Some("serverInfo"));
+        let advertisement = receive_json(&mut client);
+        let channels = advertisement
+            .get("channels")
+            .and_then(Value::as_array)
+            .unwrap();
+        let scan = channels
+            .iter()
+            .find(|channel| channel.get("topic").and_then(Value::as_str) == Some(SCAN))
+            .unwrap();
+        assert_eq!(
+            scan.get("schemaName").and_then(Value::as_str),
+            Some("foxglove.LaserScan")
+        );
+
+        let channel = scan.get("id").and_then(Value::as_f64).unwrap();
+        send_for_test(
+            &mut client,
+            &format!(r#"{{"op":"subscribe","subscriptions":[{{"id":7,"channelId":{channel}}}]}}"#),
+        );
+
+        // Wait for the bridge to apply the subscription.
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while bridge.publish_scan("lidar", 0.0, 1.0, &[1.5, 2.0]) == 0 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(bridge.publish_telemetry(&Value::object()), 0);
+        let (opcode, payload) = receive_for_test(&mut client);
+        assert_eq!(opcode, 0x2);
+        assert_eq!(payload[0], MESSAGE_DATA);
+        assert_eq!(u32::from_le_bytes(payload[1..5].try_into().unwrap()), 7);
+        let scan: Value = std::str::from_utf8(&payload[13..])
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(scan.get("frame_id").and_then(Value::as_str), Some("lidar"));
+        assert_eq!(scan.get("ranges"), Some(&[1.5, 2.0].to_json()));
+    }
+
+    #[test]
+    fn it_should_only_admit_clients_with_read_permission() {
+        let bridge =
+            Bridge::new("otter").with_auth(Auth::new().with_token("viewer", Permission::Read));
+        let (address, _) = bridge.serve("127.0.0.1:0").unwrap();
+        let (_, headers) = connect_for_test(address, PROTOCOL);
+        assert!(headers.starts_with("HTTP/1.1 401"), "{headers}");
+        let (mut client, headers) = connect_to_for_test(address, "/?access_token=viewer", PROTOCOL);
+        assert!(headers.starts_with("HTTP/1.1 101"), "{headers}");
+        let info = receive_json(&mut client);
+        assert_eq!(info.get("op").and_then(Value::as_str), Some("serverInfo"));
+    }
+
+    #[test]
+    fn it_should_send_nothing_without_subscribers() {
+        let bridge = Bridge::new("otter");
+        assert_eq!(
+            bridge.publish_transform("odom", "base_link", Pose::new(1.0, 2.0, 0.5)),
+            0
+        );
+        assert_eq!(bridge.publish("/unknown", &Value::Null), 0);
+        let advertisement = bridge.advertisement();
+        assert_eq!(
+            advertisement
+                .get("channels")
+                .and_then(Value::as_array)
+                .map(<[_]>::len),
+            Some(4)
+        );
+    }
+}
@@ -0,0 +1,756 @@
+//! A small HTTP server for the browser dashboard and the JSON API.
+//!
+//! Requests are handled one per connection on their own thread, which suits
+//! a handful of browsers polling the robot. Connections beyond a small cap
+//! are answered with 503, and each client has a fixed time to send its whole
+//! request, so slow clients cannot pile up threads. The JSON API lives under
+//! `/api/v<version>/`, so a route can change shape in a new version while
+//! tools written against the old one keep working. With the `dashboard`
+//! feature, the single-page dashboard is compiled into the binary and served
+//! from `/`, so the UI always matches the API it was built against.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::auth::{bearer_token, Auth, AuthError, Permission};
+use crate::diagnostics::latency::CommandTrace;
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "http";
+
+/// Largest request body accepted, in bytes.
+const MAX_BODY: usize = 64 * 1024;
+
+/// Most header lines accepted in a request.
+const MAX_HEADERS: usize = 64;
+
+/// Time a client has to send its whole request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default number of connections handled at once.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Time allowed to write the refusal to a client beyond the connection cap.
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The single-page dashboard.
+#[cfg(feature = "dashboard")]
+const DASHBOARD: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/dashboard.html"
+));
+
+type Handler = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// An HTTP request.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Request {
+    /// Method, such as `GET`.
+    pub method: String,
+    /// Path, without the query string.
+    pub path: String,
+    /// Query string, without the leading `?`.
+    pub query: Option<String>,
+    /// Header names, in lowercase, and values.
+    pub headers: Vec<(String, String)>,
+    /// Body.
+    pub body: Vec<u8>,
+    /// When the request line arrived, if the request was read from the
+    /// network.
+    pub received: Option<Instant>,
+}
+
+impl Request {
+    /// Creates a new request without headers or a body.
+    pub fn new(method: &str, target: &str) -> Self {
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_owned())),
+            None => (target, None),
+        };
+        Self {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            query,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the value of the header `name`, ignoring case.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Starts a trace for a command carried by the request, received when
+    /// the request line arrived, or now if the request was not read from the
+    /// network.
+    #[must_use]
+    pub fn trace(&self) -> CommandTrace {
+        self.received
+            .map_or_else(CommandTrace::received_now, CommandTrace::received_at)
+    }
+
+    /// Parses the body as JSON.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the body is not valid JSON.
+    pub fn json(&self) -> Result<Value, String> {
+        std::str::from_utf8(&self.body)
+            .map_err(|error| error.to_string())?
+            .parse()
+    }
+
+    /// Reads a request from `reader`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request cannot be read, or
+    /// with [`ErrorKind::InvalidData`] if it is malformed or too large.
+    pub fn read_from(reader: &mut impl BufRead) -> Result<Self, Error> {
+        let line = read_line(reader)?;
+        let received = Instant::now();
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target), Some(version)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid_data("malformed request line"));
+        };
+
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid_data("unsupported HTTP version"));
+        }
+
+        let mut request = Self::new(method, target);
+        request.received = Some(received);
+
+        loop {
+            let line = read_line(reader)?;
+
+            if line.is_empty() {
+                break;
+            }
+
+            if request.headers.len() == MAX_HEADERS {
+                return Err(invalid_data("too many headers"));
+            }
+
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid_data("malformed header"))?;
+            request
+                .headers
+                .push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+
+        let length = match request.header("content-length") {
+            Some(length) => length
+                .parse()
+                .map_err(|_| invalid_data("malformed content length"))?,
+            None => 0,
+        };
+
+        if length > MAX_BODY {
+            return Err(invalid_data("request body too large"));
+        }
+
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+        Ok(request)
+    }
+}
+
+/// An HTTP response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Response {
+    /// Status code, such as 200.
+    pub status: u16,
+    /// Header names and values, excluding the content length.
+    pub headers: Vec<(String, String)>,
+    /// Body.
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Creates a new response with a body of `content_type`.
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_owned(), content_type.to_owned())],
+            body: body.into(),
+        }
+    }
+
+    /// Creates a new 200 response with `value` as its JSON body.
+    pub fn json(value: &impl ToJson) -> Self {
+        Self::new(200, "application/json", value.to_json().to_string())
+    }
+
+    /// Creates a new response with a JSON body describing an error.
+    pub fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            ..Self::json(&Value::object().with("error", message))
+        }
+    }
+
+    /// Adds a header.
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Writes the response to `writer`, and asks the client to close the
+    /// connection.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the response cannot be written.
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<(), Error> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason(self.status)
+        )?;
+
+        for (name, value) in &self.headers {
+            write!(writer, "{name}: {value}\r\n")?;
+        }
+
+        write!(
+            writer,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        )?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+/// Routes of one version of the JSON API.
+#[derive(Clone, Default)]
+pub struct Api {
+    routes: BTreeMap<(String, String), (Permission, Handler)>,
+}
+
+impl Api {
+    /// Creates a new `Api` without routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles `method` requests to `path`, relative to the version prefix,
+    /// such as `/status`. A `GET` route also answers `HEAD` requests that
+    /// have no route of their own.
+    ///
+    /// When the router has [`Auth`], `GET` and `HEAD` requests need read
+    /// permission and every other method needs drive permission.
+    #[must_use]
+    pub fn route(
+        self,
+        method: &str,
+        path: &str,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let permission = match method {
+            "GET" | "HEAD" => Permission::Read,
+            _ => Permission::Drive,
+        };
+        self.route_with_permission(method, path, permission, handler)
+    }
+
+    /// Handles `method` requests to `path` from clients with at least
+    /// `permission`.
+    #[must_use]
+    pub fn route_with_permission(
+        mut self,
+        method: &str,
+        path: &str,
+        permission: Permission,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.insert(
+            (method.to_owned(), path.to_owned()),
+            (permission, Arc::new(handler)),
+        );
+        self
+    }
+
+    fn handle(&self, path: &str, request: &Request, auth: Option<&Auth>) -> Response {
+        let route = |method: &str| self.routes.get(&(method.to_owned(), path.to_owned()));
+
+        if let Some((permission, handler)) = route(&request.method)
+            .or_else(|| (request.method == "HEAD").then(|| route("GET")).flatten())
+        {
+            let token = request.header("authorization").and_then(bearer_token);
+
+            return match auth.map(|auth| auth.authorize(token, *permission)) {
+                Some(Err(error @ AuthError::Unauthenticated)) => {
+                    Response::error(401, &error.to_string())
+                        .with_header("WWW-Authenticate", "Bearer")
+                }
+                Some(Err(error @ AuthError::Forbidden)) => Response::error(403, &error.to_string()),
+                _ => handler(request),
+            };
+        }
+
+        if self.routes.keys().any(|(_, route)| route == path) {
+            Response::error(405, "method not allowed")
+        } else {
+            Response::error(404, "not found")
+        }
+    }
+}
+
+impl Debug for Api {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.routes
+                    .iter()
+                    .map(|(route, (permission, _))| (route, permission)),
+            )
+            .finish()
+    }
+}
+
+/// Dispatches requests to the versions of the JSON API and the dashboard.
+#[derive(Clone, Debug, Default)]
+pub struct Router {
+    apis: BTreeMap<u32, Api>,
+    auth: Option<Auth>,
+    #[cfg(feature = "dashboard")]
+    dashboard: bool,
+}
+
+impl Router {
+    /// Creates a new `Router` without any API versions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `api` under `/api/v<version>`.
+    #[must_use]
+    pub fn with_api(mut self, version: u32, api: Api) -> Self {
+        self.apis.insert(version, api);
+        self
+    }
+
+    /// Requires clients of the API to present a token granting the
+    /// permission each route needs.
+    #[must_use]
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Serves the embedded dashboard from `/`.
+    #[cfg(feature = "dashboard")]
+    #[must_use]
+    pub fn with_dashboard(mut self) -> Self {
+        self.dashboard = true;
+        self
+    }
+
+    /// Returns the response to `request`.
+    #[must_use]
+    pub fn handle(&self, request: &Request) -> Response {
+        if request.path == "/api" || request.path == "/api/" {
+            let versions: Vec<_> = self.apis.keys().copied().collect();
+            return Response::json(&Value::object().with("versions", versions));
+        }
+
+        if let Some(rest) = request.path.strip_prefix("/api/v") {
+            let (version, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+            return match version
+                .parse()
+                .ok()
+                .and_then(|version| self.apis.get(&version))
+            {
+                Some(api) => api.handle(path, request, self.auth.as_ref()),
+                None => Response::error(404, "unsupported API version"),
+            };
+        }
+
+        #[cfg(feature = "dashboard")]
+        if self.dashboard && matches!(request.path.as_str(), "/" | "/index.html") {
+            return dashboard_page(request);
+        }
+
+        Response::error(404, "not found")
+    }
+}
+
+/// An HTTP server dispatching requests through a [`Router`].
+#[derive(Debug)]
+pub struct Server {
+    connections: Arc<AtomicUsize>,
+    listener: TcpListener,
+    max_connections: usize,
+    router: Arc<Router>,
+}
+
+impl Server {
+    /// Creates a new `Server` listening on `address`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the address cannot be bound.
+    pub fn bind(address: impl ToSocketAddrs, router: Router) -> Result<Self, Error> {
+        Ok(Self {
+            connections: Arc::new(AtomicUsize::new(0)),
+            listener: TcpListener::bind(address)?,
+            max_connections: MAX_CONNECTIONS,
+            router: Arc::new(router),
+        })
+    }
+
+    /// Sets how many connections are handled at once, beyond which clients
+    /// are answered with 503.
+    #[must_use]
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Returns the address the server is listening on.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the address cannot be read.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections until the listener fails, handling each on its
+    /// own thread, up to the connection cap.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if accepting a connection fails.
+    pub fn serve(&self) -> Result<(), Error> {
+        loop {
+            let (mut stream, peer) = self.listener.accept()?;
+
+            if self.connections.fetch_add(1, Ordering::AcqRel) >= self.max_connections {
+                self.connections.fetch_sub(1, Ordering::AcqRel);
+                log_event!(SUBSYSTEM, Level::Warn, "{peer}: too many connections");
+                let refused = stream
+                    .set_write_timeout(Some(REFUSAL_TIMEOUT))
+                    .and_then(|()| {
+                        Response::error(503, "too many connections").write_to(&mut stream)
+                    });
+
+                if let Err(error) = refused {
+                    log_event!(SUBSYSTEM, Level::Debug, "{peer}: {error}");
+                }
+
+                continue;
+            }
+
+            let connection = Connection(Arc::clone(&self.connections));
+            let router = Arc::clone(&self.router);
+            thread::spawn(move || {
+                let _connection = connection;
+
+                if let Err(error) = handle_connection(stream, &router) {
+                    log_event!(SUBSYSTEM, Level::Debug, "{peer}: {error}");
+                }
+            });
+        }
+    }
+}
+
+/// A connection counted against the cap until it is dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A stream that fails reads once its deadline has passed, however slowly
+/// the client trickles in bytes.
+struct Deadline {
+    deadline: Instant,
+    stream: TcpStream,
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            return Err(Error::new(ErrorKind::TimedOut, "request took too long"));
+        }
+
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+#[cfg(feature = "dashboard")]
+fn dashboard_page(request: &Request) -> Response {
+    if request.method == "GET" || request.method == "HEAD" {
+        Response::new(200, "text/html; charset=utf-8", DASHBOARD)
+            .with_header("Cache-Control", "no-cache")
+    } else {
+        Response::error(405, "method not allowed")
+    }
+}
+
+fn handle_connection(stream: TcpStream, router: &Router) -> Result<(), Error> {
+    let mut writer = stream.try_clone()?;
+    let deadline = Deadline {
+        deadline: Instant::now() + REQUEST_TIMEOUT,
+        stream,
+    };
+    let response = match Request::read_from(&mut BufReader::new(deadline)) {
+        Ok(request) => {
+            let mut response = router.handle(&request);
+            log_event!(
+                SUBSYSTEM,
+                Level::Trace,
+                "{} {} {}",
+                request.method,
+                request.path,
+                response.status
+            );
+
+            if request.method == "HEAD" {
+                response.body.clear();
+            }
+
+            response
+        }
+        Err(error) if error.kind() == ErrorKind::InvalidData => {
+            Response::error(400, &error.to_string())
+        }
+        Err(error) => return Err(error),
+    };
+    response.write_to(&mut writer)
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, Error> {
+    let mut line = String::new();
+    // Bound the line so a client cannot exhaust memory with one endless
+    // header.
+    reader.take(8192).read_line(&mut line)?;
+
+    if !line.ends_with('\n') {
+        return Err(invalid_data("line too long or truncated"));
+    }
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::diagnostics::latency::Stage;
+
+    fn router() -> Router {
+        Router::new()
+            .with_api(
+                1,
+                Api::new().route("GET", "/status", |_| {
+                    Response::json(&Value::object().with("speed", 0.5))
+                }),
+            )
+            .with_api(
+                2,
+                Api::new().route("GET", "/status", |_| {
+                    Response::json(&Value::object().with("velocity", 0.5))
+                }),
+            )
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(&response.body).unwrap()
+    }
+
+    #[test]
+    fn it_should_parse_a_request() {
+        let mut input = Cursor::new(
+            "POST /api/v1/drive?hold=1 HTTP/1.1\r\nHost: otter\r\nContent-Length: 11\r\n\r\n{\"vx\":0.2}\n",
+        );
+        let before = Instant::now();
+        let request = Request::read_from(&mut input).unwrap();
+        assert!(request.received.is_some_and(|received| received >= before));
+        assert_eq!(request.trace().instant(Stage::Received), request.received);
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/v1/drive");
+        assert_eq!(request.query.as_deref(), Some("hold=1"));
+        assert_eq!(request.header("HOST"), Some("otter"));
+        assert_eq!(request.json().unwrap().get("vx"), Some(&Value::Number(0.2)));
+    }
+
+    #[test]
+    fn it_should_reject_malformed_requests() {
+        let parse = |input: &str| Request::read_from(&mut Cursor::new(input.to_owned()));
+        assert!(parse("GET /\r\n\r\n").is_err());
+        assert!(parse("GET / HTTP/2\r\n\r\n").is_err());
+        assert!(parse("GET / HTTP/1.1\r\nContent-Length: 999999\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn it_should_route_each_api_version_separately() {
+        let router = router();
+        let v1 = router.handle(&Request::new("GET", "/api/v1/status"));
+        assert_eq!(body(&v1), r#"{"speed":0.5}"#);
+        let v2 = router.handle(&Request::new("GET", "/api/v2/status"));
+        assert_eq!(body(&v2), r#"{"velocity":0.5}"#);
+        let versions = router.handle(&Request::new("GET", "/api"));
+        assert_eq!(body(&versions), r#"{"versions":[1,2]}"#);
+    }
+
+    #[test]
+    fn it_should_report_unknown_routes_and_versions() {
+        let router = router();
+        let status = |method, target| router.handle(&Request::new(method, target)).status;
+        assert_eq!(status("GET", "/api/v3/status"), 404);
+        assert_eq!(status("GET", "/api/vx/status"), 404);
+        assert_eq!(status("GET", "/api/v1/missing"), 404);
+        assert_eq!(status("POST", "/api/v1/status"), 405);
+        assert_eq!(status("HEAD", "/api/v1/status"), 200);
+        assert_eq!(status("HEAD", "/api/v1/missing"), 404);
+        assert_eq!(status("GET", "/"), 404);
+    }
+
+    #[test]
+    fn it_should_enforce_the_permission_of_each_route() {
+        let router = Router::new()
+            .with_api(
+                1,
+                Api::new()
+                    .route("GET", "/status", |_| Response::new(200, "text/plain", "ok"))
+                    .route("POST", "/drive", |_| Response::new(200, "text/plain", "ok")),
+            )
+            .with_auth(Auth::new().with_token("guest", Permission::Read));
+        let status = |method, target, token: Option<&str>| {
+            let mut request = Request::new(method, target);
+
+            if let Some(token) = token {
+                request
+                    .headers
+                    .push(("authorization".to_owned(), format!("Bearer {token}")));
+            }
+
+            router.handle(&request).status
+        };
+        assert_eq!(status("GET", "/api/v1/status", None), 401);
+        assert_eq!(status("GET", "/api/v1/status", Some("wrong")), 401);
+        assert_eq!(status("GET", "/api/v1/status", Some("guest")), 200);
+        assert_eq!(status("POST", "/api/v1/drive", Some("guest")), 403);
+        assert_eq!(status("HEAD", "/api/v1/status", None), 401);
+        assert_eq!(status("HEAD", "/api/v1/drive", Some("guest")), 405);
+    }
+
+    #[cfg(feature = "dashboard")]
+    #[test]
+    fn it_should_serve_the_embedded_dashboard() {
+        let router = router().with_dashboard();
+        let response = router.handle(&Request::new("GET", "/"));
+        assert_eq!(response.status, 200);
+        assert!(body(&response).contains("/api/v1/"));
+    }
+
+    #[test]
+    fn it_should_serve_requests_over_tcp() {
+        let server = Server::bind("127.0.0.1:0", router()).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /api/v1/status HTTP/1.1\r\nHost: otter\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\n{\"speed\":0.5}"), "{response}");
+    }
+
+    #[test]
+    fn it_should_refuse_connections_beyond_the_cap() {
+        let server = Server::bind("127.0.0.1:0", router())
+            .unwrap()
+            .with_max_connections(1);
+        let address = server.local_addr().unwrap();
+        let connections = Arc::clone(&server.connections);
+        thread::spawn(move || server.serve());
+        let mut slow = TcpStream::connect(address).unwrap();
+        slow.write_all(b"GET /api/v1/status HTTP/1.1\r\n").unwrap();
+
+        while connections.load(Ordering::Acquire) == 0 {
+            thread::yield_now();
+        }
+
+        let mut refused = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        refused.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 "), "{response}");
+        slow.write_all(b"\r\n").unwrap();
+        let mut response = String::new();
+        slow.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    }
+
+    #[test]
+    fn it_should_give_up_on_a_request_past_its_deadline() {
+        let (client, server) = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            (client, listener.accept().unwrap().0)
+        };
+        let mut deadline = Deadline {
+            deadline: Instant::now() + Duration::from_millis(50),
+            stream: server,
+        };
+        (&client).write_all(b"G").unwrap();
+        assert_eq!(deadline.read(&mut [0; 8]).unwrap(), 1);
+        let error = deadline.read(&mut [0; 8]).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut
+        ));
+        let error = deadline.read(&mut [0; 8]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,500 @@
+//! Notifications pushed to the owner’s phone.
+//!
+//! Built with the `notify` feature. A [`Notifier`] turns events worth
+//! interrupting someone for, such as a low battery, the robot getting stuck,
+//! motion on a night watch, or a mission finishing, into a [`Notification`]
+//! and posts it to each configured [`Channel`]: a generic webhook, an
+//! [ntfy](https://ntfy.sh) topic, or a Telegram bot. Posts are made by an
+//! external HTTP client, `curl` by default, since the services require TLS.
+//! A topic that fires repeatedly, like motion, is sent at most once per
+//! cooldown. The notifier runs as an actor, so a slow network never holds up
+//! the thread that noticed the event.
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{Error, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::events::{Event, Traction};
+use crate::json::Value;
+use crate::log_event;
+use crate::logging::Level;
+use crate::runtime::actors::Actor;
+
+const SUBSYSTEM: &str = "notify";
+
+/// What a notification is about, for choosing which to send.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Topic {
+    /// The battery is low.
+    LowBattery,
+    /// The wheels are stalled.
+    Stuck,
+    /// Something moved near the robot.
+    Motion,
+    /// A mission finished.
+    MissionComplete,
+}
+
+impl Topic {
+    /// Every topic.
+    pub const ALL: [Self; 4] = [
+        Self::LowBattery,
+        Self::Stuck,
+        Self::Motion,
+        Self::MissionComplete,
+    ];
+}
+
+impl Display for Topic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::LowBattery => "low_battery",
+            Self::Stuck => "stuck",
+            Self::Motion => "motion",
+            Self::MissionComplete => "mission_complete",
+        })
+    }
+}
+
+/// How urgently a notification should get attention.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    /// Can wait.
+    Low,
+    /// Worth a look.
+    #[default]
+    Normal,
+    /// Needs attention now.
+    High,
+}
+
+/// A message for the owner.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    /// What it is about.
+    pub topic: Topic,
+    /// Short title.
+    pub title: String,
+    /// Details.
+    pub message: String,
+    /// How urgent it is.
+    pub priority: Priority,
+}
+
+impl Notification {
+    /// Creates a new notification of normal priority.
+    pub fn new(topic: Topic, title: &str, message: &str) -> Self {
+        Self {
+            topic,
+            title: title.to_owned(),
+            message: message.to_owned(),
+            priority: Priority::Normal,
+        }
+    }
+
+    /// Sets the priority.
+    #[must_use]
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Returns the notification for `event`, if it is worth one.
+    #[must_use]
+    pub fn from_event(event: &Event) -> Option<Self> {
+        match event {
+            Event::LowBattery(low) => {
+                let charge = low
+                    .state_of_charge
+                    .map_or(String::new(), |charge| format!(" ({:.0}%)", charge * 100.0));
+                Some(
+                    Self::new(
+                        Topic::LowBattery,
+                        "Battery low",
+                        &format!("The battery is at {:.2} V{charge}.", low.voltage),
+                    )
+                    .with_priority(Priority::High),
+                )
+            }
+            Event::TractionChanged(change) if change.traction == Traction::Stalled => Some(
+                Self::new(Topic::Stuck, "Stuck", "The wheels are stalled.")
+                    .with_priority(Priority::High),
+            ),
+            Event::MotionDetected(motion) => Some(Self::new(
+                Topic::Motion,
+                "Motion detected",
+                &format!("{} detected motion.", motion.sensor),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// An HTTP POST to make.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Post {
+    /// URL to post to.
+    pub url: String,
+    /// Header names and values.
+    pub headers: Vec<(String, String)>,
+    /// Body.
+    pub body: String,
+}
+
+/// Somewhere notifications are sent.
+#[derive(Clone, Eq, PartialEq)]
+pub enum Channel {
+    /// A URL that is posted the notification as JSON.
+    Webhook {
+        /// URL to post to.
+        url: String,
+    },
+    /// An ntfy topic.
+    Ntfy {
+        /// Server URL, such as `https://ntfy.sh`.
+        server: String,
+        /// Topic name.
+        topic: String,
+    },
+    /// A Telegram chat, through a bot.
+    Telegram {
+        /// Bot token from BotFather.
+        token: String,
+        /// Chat to send to.
+        chat_id: String,
+    },
+}
+
+impl Channel {
+    /// Returns the post that delivers `notification` to the channel.
+    #[must_use]
+    pub fn post(&self, notification: &Notification) -> Post {
+        let json = || vec![("Content-Type".to_owned(), "application/json".to_owned())];
+
+        match self {
+            Self::Webhook { url } => Post {
+                url: url.clone(),
+                headers: json(),
+                body: Value::object()
+                    .with("topic", notification.topic.to_string())
+                    .with("title", &notification.title)
+                    .with("message", &notification.message)
+                    .with(
+                        "priority",
+                        match notification.priority {
+                            Priority::Low => "low",
+                            Priority::Normal => "normal",
+                            Priority::High => "high",
+                        },
+                    )
+                    .to_string(),
+            },
+            Self::Ntfy { server, topic } => {
+                let priority = match notification.priority {
+                    Priority::Low => "2",
+                    Priority::Normal => "3",
+                    Priority::High => "5",
+                };
+                Post {
+                    url: format!("{}/{topic}", server.trim_end_matches('/')),
+                    headers: vec![
+                        ("Title".to_owned(), notification.title.clone()),
+                        ("Priority".to_owned(), priority.to_owned()),
+                        ("Tags".to_owned(), notification.topic.to_string()),
+                    ],
+                    body: notification.message.clone(),
+                }
+            }
+            Self::Telegram { token, chat_id } => Post {
+                url: format!("https://api.telegram.org/bot{token}/sendMessage"),
+                headers: json(),
+                body: Value::object()
+                    .with("chat_id", chat_id)
+                    .with(
+                        "text",
+                        format!("{}\n{}", notification.title, notification.message),
+                    )
+                    .with(
+                        "disable_notification",
+                        notification.priority == Priority::Low,
+                    )
+                    .to_string(),
+            },
+        }
+    }
+}
+
+impl Debug for Channel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Webhook { url } => f.debug_struct("Webhook").field("url", url).finish(),
+            Self::Ntfy { server, topic } => f
+                .debug_struct("Ntfy")
+                .field("server", server)
+                .field("topic", topic)
+                .finish(),
+            // The token grants control of the bot, so keep it out of logs.
+            Self::Telegram { chat_id, .. } => f
+                .debug_struct("Telegram")
+                .field("chat_id", chat_id)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+type Transport = Box<dyn FnMut(&Post) -> Result<(), Error> + Send>;
+
+/// Sends notifications to channels.
+pub struct Notifier {
+    channels: Vec<Channel>,
+    cooldown: Duration,
+    last_sent: HashMap<Topic, Instant>,
+    topics: Vec<Topic>,
+    transport: Transport,
+}
+
+impl Notifier {
+    /// Creates a new `Notifier` without channels that sends every topic at
+    /// most once every 5 minutes, posting with `curl`.
+    pub fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            cooldown: Duration::from_secs(5 * 60),
+            last_sent: HashMap::new(),
+            topics: Topic::ALL.to_vec(),
+            transport: Box::new(curl),
+        }
+    }
+
+    /// Sends notifications to `channel` too.
+    #[must_use]
+    pub fn with_channel(mut self, channel: Channel) -> Self {
+        self.channels.push(channel);
+        self
+    }
+
+    /// Sends only notifications about `topics`.
+    #[must_use]
+    pub fn with_topics(mut self, topics: &[Topic]) -> Self {
+        self.topics = topics.to_vec();
+        self
+    }
+
+    /// Sends each topic at most once every `cooldown`.
+    #[must_use]
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Makes posts with `transport` instead of `curl`.
+    #[must_use]
+    pub fn with_transport(
+        mut self,
+        transport: impl FnMut(&Post) -> Result<(), Error> + Send + 'static,
+    ) -> Self {
+        self.transport = Box::new(transport);
+        self
+    }
+
+    /// Sends `notification` to every channel at `now`, unless its topic is
+    /// not wanted or within its cooldown, and returns the number of
+    /// channels it reached.
+    ///
+    /// A channel that fails is logged and skipped, so one unreachable
+    /// service does not silence the others.
+    pub fn notify(&mut self, notification: &Notification, now: Instant) -> usize {
+        let topic = notification.topic;
+
+        if !self.topics.contains(&topic)
+            || self
+                .last_sent
+                .get(&topic)
+                .is_some_and(|&last| now.saturating_duration_since(last) < self.cooldown)
+        {
+            return 0;
+        }
+
+        self.last_sent.insert(topic, now);
+        let mut reached = 0;
+
+        for channel in &self.channels {
+            match (self.transport)(&channel.post(notification)) {
+                Ok(()) => reached += 1,
+                Err(error) => log_event!(
+                    SUBSYSTEM,
+                    Level::Warn,
+                    "could not notify {channel:?}: {error}"
+                ),
+            }
+        }
+
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "sent `{}` to {reached} of {} channels",
+            notification.title,
+            self.channels.len()
+        );
+        reached
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Notifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifier")
+            .field("channels", &self.channels)
+            .field("cooldown", &self.cooldown)
+            .field("topics", &self.topics)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Actor for Notifier {
+    type Message = Notification;
+
+    fn handle(&mut self, notification: Notification) {
+        self.notify(&notification, Instant::now());
+    }
+}
+
+/// Makes `post` with `curl`, passing the body on standard input.
+fn curl(post: &Post) -> Result<(), Error> {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail", "--max-time", "10"]);
+
+    for (name, value) in &post.headers {
+        command.arg("--header").arg(format!("{name}: {value}"));
+    }
+
+    let mut child = command
+        .args(["--data-binary", "@-", "--output", "/dev/null"])
+        .arg(&post.url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(post.body.as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!(
+            "curl failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::events::{LowBattery, MotionDetected, TractionChanged};
+
+    #[test]
+    fn it_should_turn_events_into_notifications() {
+        let low = Event::LowBattery(LowBattery {
+            voltage: 6.8,
+            state_of_charge: Some(0.1),
+        });
+        let notification = Notification::from_event(&low).unwrap();
+        assert_eq!(notification.topic, Topic::LowBattery);
+        assert_eq!(notification.message, "The battery is at 6.80 V (10%).");
+        assert_eq!(notification.priority, Priority::High);
+        let stalled = Event::TractionChanged(TractionChanged {
+            previous: Traction::Gripping,
+            traction: Traction::Stalled,
+        });
+        assert_eq!(
+            Notification::from_event(&stalled).map(|n| n.topic),
+            Some(Topic::Stuck)
+        );
+        let slipping = Event::TractionChanged(TractionChanged {
+            previous: Traction::Gripping,
+            traction: Traction::Slipping,
+        });
+        assert_eq!(Notification::from_event(&slipping), None);
+    }
+
+    #[test]
+    fn it_should_format_a_post_for_each_channel() {
+        let notification = Notification::new(Topic::MissionComplete, "Done", "Patrol finished.");
+        let ntfy = Channel::Ntfy {
+            server: "https://ntfy.sh/".to_owned(),
+            topic: "otter".to_owned(),
+        }
+        .post(&notification);
+        assert_eq!(ntfy.url, "https://ntfy.sh/otter");
+        assert!(ntfy
+            .headers
+            .contains(&("Title".to_owned(), "Done".to_owned())));
+        assert_eq!(ntfy.body, "Patrol finished.");
+        let telegram = Channel::Telegram {
+            token: "123:abc".to_owned(),
+            chat_id: "42".to_owned(),
+        };
+        assert_eq!(
+            telegram.post(&notification).url,
+            "https://api.telegram.org/bot123:abc/sendMessage"
+        );
+        assert!(!format!("{telegram:?}").contains("abc"));
+        let webhook = Channel::Webhook {
+            url: "http://hub.local/otter".to_owned(),
+        }
+        .post(&notification);
+        assert_eq!(
+            webhook.body,
+            r#"{"topic":"mission_complete","title":"Done","message":"Patrol finished.","priority":"normal"}"#
+        );
+    }
+
+    #[test]
+    fn it_should_skip_unwanted_topics_and_repeats_within_the_cooldown() {
+        let posts = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&posts);
+        let mut notifier = Notifier::new()
+            .with_channel(Channel::Webhook {
+                url: "http://hub.local/a".to_owned(),
+            })
+            .with_channel(Channel::Webhook {
+                url: "http://hub.local/b".to_owned(),
+            })
+            .with_topics(&[Topic::Motion])
+            .with_cooldown(Duration::from_secs(60))
+            .with_transport(move |post| {
+                log.lock().unwrap().push(post.url.clone());
+                if post.url.ends_with('b') {
+                    Err(Error::other("unreachable"))
+                } else {
+                    Ok(())
+                }
+            });
+        let motion = Notification::from_event(&Event::MotionDetected(MotionDetected {
+            sensor: "pir".to_owned(),
+        }))
+        .unwrap();
+        let done = Notification::new(Topic::MissionComplete, "Done", "");
+        let start = Instant::now();
+        assert_eq!(notifier.notify(&motion, start), 1);
+        assert_eq!(notifier.notify(&motion, start + Duration::from_secs(30)), 0);
+        assert_eq!(notifier.notify(&done, start), 0);
+        assert_eq!(notifier.notify(&motion, start + Duration::from_secs(60)), 1);
+        assert_eq!(posts.lock().unwrap().len(), 4);
+    }
+}
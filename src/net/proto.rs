@@ -0,0 +1,286 @@
+//! Messages exchanged with ground stations, defined in one place.
+//!
+//! Every interface that carries telemetry or commands, and the logger that
+//! records them, converts through [`Message`] instead of building its own
+//! JSON, so a field is named the same everywhere. Messages are sent as CBOR
+//! on the wire and can be logged as JSON. A peer opens with
+//! [`Message::Hello`] listing the schema versions it understands, and both
+//! sides then speak the newest version they share.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use crate::drive::Twist;
+use crate::json::{ToJson, Value};
+
+pub mod cbor;
+
+/// Newest version of the schema, raised whenever a message changes shape.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Oldest version of the schema still understood.
+pub const MIN_SCHEMA_VERSION: u32 = 1;
+
+/// An error decoding a [`Message`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProtoError {
+    /// The bytes are not valid CBOR.
+    Malformed(String),
+    /// The message is not of a known type.
+    UnknownType(String),
+    /// A required field is absent or has the wrong type.
+    MissingField(&'static str),
+    /// The peer shares no schema version with the robot.
+    UnsupportedVersion {
+        /// Oldest version the peer understands.
+        min: u32,
+        /// Newest version the peer understands.
+        max: u32,
+    },
+}
+
+impl Display for ProtoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(message) => write!(f, "{message}"),
+            Self::UnknownType(kind) => write!(f, "unknown message type `{kind}`"),
+            Self::MissingField(field) => write!(f, "missing or invalid field `{field}`"),
+            Self::UnsupportedVersion { min, max } => write!(
+                f,
+                "schema versions {min} to {max} are not supported, only \
+                 {MIN_SCHEMA_VERSION} to {SCHEMA_VERSION}"
+            ),
+        }
+    }
+}
+
+impl Error for ProtoError {}
+
+/// Returns the newest schema version shared with a peer that understands
+/// versions `min` to `max`.
+///
+/// # Errors
+///
+/// This function will return an error if no version is shared.
+pub fn negotiate(min: u32, max: u32) -> Result<u32, ProtoError> {
+    let version = max.min(SCHEMA_VERSION);
+
+    if version < min.max(MIN_SCHEMA_VERSION) {
+        return Err(ProtoError::UnsupportedVersion { min, max });
+    }
+
+    Ok(version)
+}
+
+/// A message between the robot and a ground station.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// Opens a session, listing the schema versions the sender understands.
+    Hello {
+        /// Oldest version understood.
+        min_version: u32,
+        /// Newest version understood.
+        max_version: u32,
+    },
+    /// A telemetry sample.
+    Telemetry {
+        /// Channel the sample belongs to, such as `imu`.
+        channel: String,
+        /// When the sample was taken, in seconds since the robot started.
+        time: f64,
+        /// The sample.
+        data: Value,
+    },
+    /// A velocity command.
+    Drive {
+        /// Number of the command, increasing with each one sent, so stale
+        /// commands arriving out of order can be discarded.
+        sequence: u64,
+        /// Velocity to drive at.
+        twist: Twist,
+    },
+    /// Engages or releases the emergency stop.
+    EmergencyStop {
+        /// Whether the stop is engaged.
+        engaged: bool,
+    },
+}
+
+impl Message {
+    /// Returns the [`Message::Hello`] the robot opens sessions with.
+    #[must_use]
+    pub fn hello() -> Self {
+        Self::Hello {
+            min_version: MIN_SCHEMA_VERSION,
+            max_version: SCHEMA_VERSION,
+        }
+    }
+
+    /// Returns the name of the message’s type on the wire.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Hello { .. } => "hello",
+            Self::Telemetry { .. } => "telemetry",
+            Self::Drive { .. } => "drive",
+            Self::EmergencyStop { .. } => "emergency_stop",
+        }
+    }
+
+    /// Returns the message encoded as CBOR.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        cbor::encode(&self.to_json())
+    }
+
+    /// Returns the message encoded as CBOR in `bytes`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `bytes` does not hold a known
+    /// message.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtoError> {
+        let value = cbor::decode(bytes).map_err(ProtoError::Malformed)?;
+        Self::from_value(&value)
+    }
+
+    /// Returns the message represented by `value`, ignoring unknown fields
+    /// so that newer peers can add them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `value` does not represent a
+    /// known message.
+    pub fn from_value(value: &Value) -> Result<Self, ProtoError> {
+        let kind = field(value, "type", Value::as_str)?;
+        let number = |name| field(value, name, Value::as_f64);
+
+        match kind {
+            "hello" => Ok(Self::Hello {
+                min_version: number("min_version")? as u32,
+                max_version: number("max_version")? as u32,
+            }),
+            "telemetry" => Ok(Self::Telemetry {
+                channel: field(value, "channel", Value::as_str)?.to_owned(),
+                time: number("time")?,
+                data: value.get("data").cloned().unwrap_or_default(),
+            }),
+            "drive" => {
+                let twist = value
+                    .get("twist")
+                    .ok_or(ProtoError::MissingField("twist"))?;
+                let component = |name| field(twist, name, Value::as_f64);
+                Ok(Self::Drive {
+                    sequence: number("sequence")? as u64,
+                    twist: Twist::new(component("vx")?, component("vy")?, component("omega")?),
+                })
+            }
+            "emergency_stop" => Ok(Self::EmergencyStop {
+                engaged: field(value, "engaged", Value::as_bool)?,
+            }),
+            _ => Err(ProtoError::UnknownType(kind.to_owned())),
+        }
+    }
+}
+
+impl ToJson for Message {
+    fn to_json(&self) -> Value {
+        let value = Value::object().with("type", self.kind());
+
+        match self {
+            Self::Hello {
+                min_version,
+                max_version,
+            } => value
+                .with("min_version", min_version)
+                .with("max_version", max_version),
+            Self::Telemetry {
+                channel,
+                time,
+                data,
+            } => value
+                .with("channel", channel)
+                .with("time", time)
+                .with("data", data),
+            Self::Drive { sequence, twist } => value
+                .with("sequence", sequence)
+                .with("twist", twist.to_json()),
+            Self::EmergencyStop { engaged } => value.with("engaged", engaged),
+        }
+    }
+}
+
+fn field<'a, T>(
+    value: &'a Value,
+    name: &'static str,
+    convert: impl Fn(&'a Value) -> Option<T>,
+) -> Result<T, ProtoError> {
+    value
+        .get(name)
+        .and_then(convert)
+        .ok_or(ProtoError::MissingField(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_negotiate_the_newest_shared_version() {
+        assert_eq!(negotiate(1, 5), Ok(SCHEMA_VERSION));
+        assert_eq!(negotiate(0, 1), Ok(1));
+        assert_eq!(
+            negotiate(SCHEMA_VERSION + 1, SCHEMA_VERSION + 2),
+            Err(ProtoError::UnsupportedVersion {
+                min: SCHEMA_VERSION + 1,
+                max: SCHEMA_VERSION + 2
+            })
+        );
+    }
+
+    #[test]
+    fn it_should_round_trip_messages_through_cbor() {
+        let messages = [
+            Message::hello(),
+            Message::Telemetry {
+                channel: "imu".to_owned(),
+                time: 1.25,
+                data: Value::object().with("pitch", 0.1),
+            },
+            Message::Drive {
+                sequence: 42,
+                twist: Twist::new(0.5, 0.0, -0.25),
+            },
+            Message::EmergencyStop { engaged: true },
+        ];
+
+        for message in messages {
+            assert_eq!(Message::decode(&message.encode()), Ok(message));
+        }
+    }
+
+    #[test]
+    fn it_should_ignore_unknown_fields_and_reject_missing_ones() {
+        let value = Message::EmergencyStop { engaged: false }
+            .to_json()
+            .with("reason", "operator");
+        assert_eq!(
+            Message::from_value(&value),
+            Ok(Message::EmergencyStop { engaged: false })
+        );
+        let value = Value::object().with("type", "drive").with("sequence", 1);
+        assert_eq!(
+            Message::from_value(&value),
+            Err(ProtoError::MissingField("twist"))
+        );
+        let value = Value::object().with("type", "dance");
+        assert_eq!(
+            Message::from_value(&value),
+            Err(ProtoError::UnknownType("dance".to_owned()))
+        );
+        assert!(matches!(
+            Message::decode(&[0xFF]),
+            Err(ProtoError::Malformed(_))
+        ));
+    }
+}
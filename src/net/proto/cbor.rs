@@ -0,0 +1,281 @@
+//! Encoding of [`Value`]s as CBOR, as RFC 8949 describes.
+//!
+//! CBOR carries the same data model as JSON in far fewer bytes, which
+//! matters for high-rate telemetry over a weak link. Whole numbers are
+//! encoded as integers and others as 64-bit floats; decoding accepts every
+//! definite-length encoding of the JSON data model.
+
+use crate::json::Value;
+
+/// Deepest nesting of arrays and maps accepted when decoding.
+const MAX_DEPTH: usize = 64;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const SIMPLE: u8 = 7;
+
+/// Returns `value` encoded as CBOR.
+#[must_use]
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode_into(value, &mut bytes);
+    bytes
+}
+
+/// Returns the value encoded in `bytes`.
+///
+/// # Errors
+///
+/// This function will return an error if `bytes` is not a single CBOR data
+/// item in the JSON data model.
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    let mut decoder = Decoder { bytes, position: 0 };
+    let value = decoder.decode_value(0)?;
+
+    if decoder.position != bytes.len() {
+        return Err(decoder.error("trailing bytes"));
+    }
+
+    Ok(value)
+}
+
+fn encode_into(value: &Value, bytes: &mut Vec<u8>) {
+    match value {
+        Value::Null => bytes.push(SIMPLE << 5 | 22),
+        Value::Bool(false) => bytes.push(SIMPLE << 5 | 20),
+        Value::Bool(true) => bytes.push(SIMPLE << 5 | 21),
+        Value::Number(number) => {
+            if number.fract() == 0.0 && number.abs() < 2_f64.powi(63) {
+                let integer = *number as i64;
+
+                if integer >= 0 {
+                    encode_head(UNSIGNED, integer as u64, bytes);
+                } else {
+                    encode_head(NEGATIVE, (-1 - integer) as u64, bytes);
+                }
+            } else {
+                bytes.push(SIMPLE << 5 | 27);
+                bytes.extend_from_slice(&number.to_be_bytes());
+            }
+        }
+        Value::String(string) => {
+            encode_head(TEXT, string.len() as u64, bytes);
+            bytes.extend_from_slice(string.as_bytes());
+        }
+        Value::Array(items) => {
+            encode_head(ARRAY, items.len() as u64, bytes);
+            items.iter().for_each(|item| encode_into(item, bytes));
+        }
+        Value::Object(entries) => {
+            encode_head(MAP, entries.len() as u64, bytes);
+
+            for (key, value) in entries {
+                encode_head(TEXT, key.len() as u64, bytes);
+                bytes.extend_from_slice(key.as_bytes());
+                encode_into(value, bytes);
+            }
+        }
+    }
+}
+
+fn encode_head(major: u8, argument: u64, bytes: &mut Vec<u8>) {
+    let major = major << 5;
+
+    match argument {
+        0..=23 => bytes.push(major | argument as u8),
+        24..=0xFF => bytes.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..=0xFFFF => {
+            bytes.push(major | 25);
+            bytes.extend_from_slice(&(argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            bytes.push(major | 26);
+            bytes.extend_from_slice(&(argument as u32).to_be_bytes());
+        }
+        _ => {
+            bytes.push(major | 27);
+            bytes.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Decoder<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("invalid CBOR at byte {}: {message}", self.position)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| self.error("unexpected end"))?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn decode_argument(&mut self, info: u8) -> Result<u64, String> {
+        match info {
+            0..=23 => Ok(u64::from(info)),
+            24 => Ok(u64::from(u8::from_be_bytes(self.take_array()?))),
+            25 => Ok(u64::from(u16::from_be_bytes(self.take_array()?))),
+            26 => Ok(u64::from(u32::from_be_bytes(self.take_array()?))),
+            27 => Ok(u64::from_be_bytes(self.take_array()?)),
+            _ => Err(self.error("indefinite lengths are not supported")),
+        }
+    }
+
+    fn decode_len(&mut self, info: u8) -> Result<usize, String> {
+        let len = self.decode_argument(info)?;
+        // Every item takes at least a byte, so longer lengths are truncated.
+        usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= self.bytes.len() - self.position)
+            .ok_or_else(|| self.error("length exceeds input"))
+    }
+
+    fn decode_text(&mut self, info: u8) -> Result<String, String> {
+        let len = self.decode_len(info)?;
+        let text = self.take(len)?.to_vec();
+        String::from_utf8(text).map_err(|_| self.error("text is not UTF-8"))
+    }
+
+    fn decode_value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+
+        let [initial] = self.take_array()?;
+        let (major, info) = (initial >> 5, initial & 0x1F);
+
+        match major {
+            UNSIGNED => Ok(Value::Number(self.decode_argument(info)? as f64)),
+            NEGATIVE => Ok(Value::Number(-1.0 - self.decode_argument(info)? as f64)),
+            TEXT => self.decode_text(info).map(Value::String),
+            ARRAY => {
+                let len = self.decode_len(info)?;
+                (0..len)
+                    .map(|_| self.decode_value(depth + 1))
+                    .collect::<Result<_, _>>()
+                    .map(Value::Array)
+            }
+            MAP => {
+                let len = self.decode_len(info)?;
+                let mut entries = Vec::with_capacity(len);
+
+                for _ in 0..len {
+                    let [initial] = self.take_array()?;
+
+                    if initial >> 5 != TEXT {
+                        return Err(self.error("map keys should be text"));
+                    }
+
+                    let key = self.decode_text(initial & 0x1F)?;
+                    entries.push((key, self.decode_value(depth + 1)?));
+                }
+
+                Ok(Value::Object(entries))
+            }
+            SIMPLE => match info {
+                20 => Ok(Value::Bool(false)),
+                21 => Ok(Value::Bool(true)),
+                22 | 23 => Ok(Value::Null),
+                25 => Ok(Value::Number(half_to_f64(u16::from_be_bytes(
+                    self.take_array()?,
+                )))),
+                26 => Ok(Value::Number(f64::from(f32::from_be_bytes(
+                    self.take_array()?,
+                )))),
+                27 => Ok(Value::Number(f64::from_be_bytes(self.take_array()?))),
+                _ => Err(self.error("unsupported simple value")),
+            },
+            BYTES => Err(self.error("byte strings are not supported")),
+            _ => Err(self.error("tags are not supported")),
+        }
+    }
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = i32::from(half >> 10 & 0x1F);
+    let mantissa = f64::from(half & 0x3FF);
+    let magnitude = match exponent {
+        0 => mantissa * 2_f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2_f64.powi(exponent - 15),
+    };
+
+    if half & 0x8000 == 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_encode_values_as_in_the_rfc() {
+        assert_eq!(encode(&Value::Number(0.0)), [0x00]);
+        assert_eq!(encode(&Value::Number(500.0)), [0x19, 0x01, 0xF4]);
+        assert_eq!(encode(&Value::Number(-1000.0)), [0x39, 0x03, 0xE7]);
+        assert_eq!(
+            encode(&Value::Number(1.1)),
+            [0xFB, 0x3F, 0xF1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9A]
+        );
+        assert_eq!(encode(&Value::Null), [0xF6]);
+        assert_eq!(
+            encode(&Value::object().with("a", vec![1, 2])),
+            [0xA1, 0x61, b'a', 0x82, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn it_should_round_trip_values() {
+        let value = Value::object()
+            .with("channel", "imu")
+            .with("time", 12.5)
+            .with("samples", vec![-3.0, 1e12, 0.25])
+            .with("ok", true)
+            .with("note", Value::Null);
+        assert_eq!(decode(&encode(&value)), Ok(value));
+    }
+
+    #[test]
+    fn it_should_decode_half_and_single_floats() {
+        assert_eq!(decode(&[0xF9, 0x3C, 0x00]), Ok(Value::Number(1.0)));
+        assert_eq!(decode(&[0xF9, 0xC4, 0x00]), Ok(Value::Number(-4.0)));
+        assert_eq!(
+            decode(&[0xFA, 0x47, 0xC3, 0x50, 0x00]),
+            Ok(Value::Number(100_000.0))
+        );
+    }
+
+    #[test]
+    fn it_should_reject_malformed_input() {
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0x62, b'a']).is_err());
+        assert!(decode(&[0x9B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+        assert!(decode(&[0x01, 0x02]).is_err());
+        assert!(decode(&[0xA1, 0x01, 0x02]).is_err());
+        assert!(decode(&[0x81; MAX_DEPTH + 2]).is_err());
+    }
+}
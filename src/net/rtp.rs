@@ -0,0 +1,306 @@
+//! Low-latency H.264 streaming over RTP.
+//!
+//! Encoded access units are split into RTP packets as RFC 6184 describes and
+//! sent over UDP, with no buffering beyond the socket, so video reaches the
+//! viewer within a frame or two of being encoded. Players such as `ffplay`
+//! and GStreamer open the stream from the SDP description the sender
+//! produces.
+
+use std::io::Error;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Length of an RTP header without extensions, in bytes.
+const HEADER_LEN: usize = 12;
+
+/// Clock rate of H.264 RTP timestamps, in hertz.
+const CLOCK_RATE: f64 = 90_000.0;
+
+/// NAL unit type of a fragmentation unit.
+const FU_A: u8 = 28;
+
+/// Payload type used for the dynamic H.264 mapping.
+pub const DEFAULT_PAYLOAD_TYPE: u8 = 96;
+
+/// Largest packet sent by default, which fits a 1500-byte Ethernet or Wi-Fi
+/// frame with room for IP and UDP headers.
+pub const DEFAULT_MTU: usize = 1400;
+
+/// Returns the NAL units in an Annex B byte stream, without start codes.
+#[must_use]
+pub fn nal_units(stream: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut index = 0;
+
+    while index + 3 <= stream.len() {
+        if stream[index..index + 3] == [0, 0, 1] {
+            starts.push(index + 3);
+            index += 3;
+        } else {
+            index += 1;
+        }
+    }
+
+    let ends: Vec<_> = starts
+        .iter()
+        .skip(1)
+        .map(|&start| start - 3)
+        .chain([stream.len()])
+        .collect();
+    starts
+        .into_iter()
+        .zip(ends)
+        // A four-byte start code leaves a trailing zero on the previous unit.
+        .map(|(start, end)| {
+            let mut unit = &stream[start..end];
+
+            while let [rest @ .., 0] = unit {
+                unit = rest;
+            }
+
+            unit
+        })
+        .filter(|unit| !unit.is_empty())
+        .collect()
+}
+
+/// Splits H.264 access units into RTP packets.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Packetizer {
+    mtu: usize,
+    payload_type: u8,
+    sequence: u16,
+    ssrc: u32,
+}
+
+impl Packetizer {
+    /// Creates a new `Packetizer` for the stream identified by `ssrc`.
+    pub fn new(ssrc: u32) -> Self {
+        Self {
+            mtu: DEFAULT_MTU,
+            payload_type: DEFAULT_PAYLOAD_TYPE,
+            sequence: 0,
+            ssrc,
+        }
+    }
+
+    /// Sets the largest packet to produce, in bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mtu` leaves no room for a fragment after the headers.
+    #[must_use]
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        assert!(
+            mtu > HEADER_LEN + 2,
+            "mtu should fit the RTP and FU headers"
+        );
+        self.mtu = mtu;
+        self
+    }
+
+    /// Sets the payload type, from 96 to 127.
+    #[must_use]
+    pub fn with_payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = payload_type & 0x7F;
+        self
+    }
+
+    /// Returns the packets carrying `access_unit`, an Annex B byte stream of
+    /// one frame, at `timestamp` in 90 kHz ticks.
+    pub fn packetize(&mut self, access_unit: &[u8], timestamp: u32) -> Vec<Vec<u8>> {
+        let units = nal_units(access_unit);
+        let mut packets = Vec::new();
+        let max_payload = self.mtu - HEADER_LEN;
+
+        for (index, unit) in units.iter().enumerate() {
+            let last_unit = index + 1 == units.len();
+
+            if unit.len() <= max_payload {
+                packets.push(self.packet(timestamp, last_unit, &[unit]));
+                continue;
+            }
+
+            let indicator = (unit[0] & 0xE0) | FU_A;
+            let kind = unit[0] & 0x1F;
+            let chunks: Vec<_> = unit[1..].chunks(max_payload - 2).collect();
+
+            for (chunk_index, chunk) in chunks.iter().enumerate() {
+                let start = if chunk_index == 0 { 0x80 } else { 0 };
+                let end = if chunk_index + 1 == chunks.len() {
+                    0x40
+                } else {
+                    0
+                };
+                let marker = last_unit && end != 0;
+                packets.push(self.packet(
+                    timestamp,
+                    marker,
+                    &[&[indicator, start | end | kind], chunk],
+                ));
+            }
+        }
+
+        packets
+    }
+
+    fn packet(&mut self, timestamp: u32, marker: bool, payload: &[&[u8]]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(self.mtu);
+        packet.push(0x80);
+        packet.push(u8::from(marker) << 7 | self.payload_type);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        payload
+            .iter()
+            .for_each(|part| packet.extend_from_slice(part));
+        self.sequence = self.sequence.wrapping_add(1);
+        packet
+    }
+}
+
+/// Sends H.264 access units to a viewer over RTP.
+#[derive(Debug)]
+pub struct RtpSender {
+    destination: SocketAddr,
+    packetizer: Packetizer,
+    socket: UdpSocket,
+    started: Instant,
+}
+
+impl RtpSender {
+    /// Creates a new `RtpSender` streaming to `destination`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the socket cannot be bound or
+    /// the destination cannot be resolved.
+    pub fn connect(destination: impl ToSocketAddrs) -> Result<Self, Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(destination)?;
+        // The SSRC only needs to differ between streams, so the clock is
+        // random enough.
+        let ssrc = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.subsec_nanos());
+        Ok(Self {
+            destination: socket.peer_addr()?,
+            packetizer: Packetizer::new(ssrc),
+            socket,
+            started: Instant::now(),
+        })
+    }
+
+    /// Sets the packetizer, such as to change the MTU.
+    #[must_use]
+    pub fn with_packetizer(mut self, packetizer: Packetizer) -> Self {
+        self.packetizer = packetizer;
+        self
+    }
+
+    /// Sends `access_unit`, an Annex B byte stream of one frame, `captured`
+    /// at the given instant.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a packet cannot be sent.
+    pub fn send(&mut self, access_unit: &[u8], captured: Instant) -> Result<(), Error> {
+        let elapsed = captured.saturating_duration_since(self.started);
+        // RTP timestamps wrap around by design.
+        let timestamp = (elapsed.as_secs_f64() * CLOCK_RATE) as u64 as u32;
+
+        for packet in self.packetizer.packetize(access_unit, timestamp) {
+            self.socket.send(&packet)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an SDP description of the stream for the viewer to open.
+    #[must_use]
+    pub fn sdp(&self) -> String {
+        let ip = self.destination.ip();
+        let family = if ip.is_ipv4() { "IP4" } else { "IP6" };
+        let payload_type = self.packetizer.payload_type;
+        format!(
+            "v=0\r\n\
+             o=- 0 0 IN {family} {ip}\r\n\
+             s=Otter Pi\r\n\
+             c=IN {family} {ip}\r\n\
+             t=0 0\r\n\
+             m=video {} RTP/AVP {payload_type}\r\n\
+             a=rtpmap:{payload_type} H264/90000\r\n\
+             a=fmtp:{payload_type} packetization-mode=1\r\n",
+            self.destination.port()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const SPS: [u8; 4] = [0x67, 0x42, 0x00, 0x1F];
+
+    fn access_unit(slice_len: usize) -> Vec<u8> {
+        let mut stream = vec![0, 0, 0, 1];
+        stream.extend_from_slice(&SPS);
+        stream.extend_from_slice(&[0, 0, 1, 0x65]);
+        stream.extend((0..slice_len).map(|index| index as u8 | 1));
+        stream
+    }
+
+    #[test]
+    fn it_should_split_an_annex_b_stream_into_nal_units() {
+        let stream = access_unit(3);
+        let units = nal_units(&stream);
+        assert_eq!(units, [&SPS[..], &[0x65, 1, 1, 3]]);
+    }
+
+    #[test]
+    fn it_should_send_small_units_whole_and_mark_the_last() {
+        let mut packetizer = Packetizer::new(7);
+        let packets = packetizer.packetize(&access_unit(10), 3000);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(&packets[0][..2], [0x80, 96]);
+        assert_eq!(&packets[0][12..], SPS);
+        assert_eq!(packets[1][1], 0x80 | 96);
+        assert_eq!(&packets[1][2..4], [0, 1]);
+        assert_eq!(&packets[1][4..8], 3000_u32.to_be_bytes());
+        assert_eq!(&packets[1][8..12], 7_u32.to_be_bytes());
+    }
+
+    #[test]
+    fn it_should_fragment_large_units() {
+        let mut packetizer = Packetizer::new(7).with_mtu(100);
+        let packets = packetizer.packetize(&access_unit(250), 0);
+        let fragments = &packets[1..];
+        assert_eq!(fragments.len(), 3);
+        assert!(packets.iter().all(|packet| packet.len() <= 100));
+        assert_eq!(fragments[0][12..14], [0x60 | FU_A, 0x80 | 0x05]);
+        assert_eq!(fragments[1][13], 0x05);
+        assert_eq!(fragments[2][13], 0x40 | 0x05);
+        assert_eq!(fragments[2][1] & 0x80, 0x80);
+        assert!(fragments[..2].iter().all(|packet| packet[1] & 0x80 == 0));
+        let payload: usize = fragments.iter().map(|packet| packet.len() - 14).sum();
+        assert_eq!(payload, 250);
+    }
+
+    #[test]
+    fn it_should_stream_to_a_viewer_over_udp() {
+        let viewer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        viewer
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut sender = RtpSender::connect(viewer.local_addr().unwrap()).unwrap();
+        sender.send(&access_unit(10), Instant::now()).unwrap();
+        let mut buffer = [0; 1500];
+        assert_eq!(viewer.recv(&mut buffer).unwrap(), HEADER_LEN + SPS.len());
+        let port = viewer.local_addr().unwrap().port();
+        assert!(sender
+            .sdp()
+            .contains(&format!("m=video {port} RTP/AVP 96\r\n")));
+    }
+}
@@ -0,0 +1,325 @@
+//! Fitting telemetry to the link it is streamed over.
+//!
+//! Over Ethernet every channel can be streamed at its full rate, but a weak
+//! Wi-Fi link that is sent more than it can carry queues up and delivers
+//! everything late, including the readings that matter. A
+//! [`TelemetryPolicy`] shares the measured bandwidth between channels from
+//! the highest priority down, slowing the channels that do not fit and
+//! dropping the least important altogether. [`ChannelPriority::Critical`]
+//! channels, such as the emergency stop status, are always sent at their
+//! full rate.
+
+use std::time::{Duration, Instant};
+
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "telemetry";
+
+/// Fraction of the measured bandwidth given to telemetry, leaving the rest
+/// for video and commands.
+const DEFAULT_SHARE: f64 = 0.5;
+
+/// Latency above which the link is taken to be congested.
+const DEFAULT_CONGESTED_LATENCY: Duration = Duration::from_millis(250);
+
+/// Slowest rate at which a channel is still worth sending, in hertz.
+const DEFAULT_MIN_RATE: f64 = 0.5;
+
+/// Weight of each new size in the running average of a channel’s messages.
+const SIZE_SMOOTHING: f64 = 0.2;
+
+/// Priority of a telemetry channel, from lowest to highest.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ChannelPriority {
+    /// Diagnostics that can go unseen, such as CPU temperature.
+    Low,
+    /// Readings the operator watches, such as battery voltage.
+    Normal,
+    /// Readings needed to drive, such as odometry.
+    High,
+    /// Readings that are never slowed or dropped, such as the emergency stop
+    /// status.
+    Critical,
+}
+
+/// Measured quality of the link telemetry is streamed over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Link {
+    /// Throughput, in bytes per second.
+    pub bandwidth: f64,
+    /// Round-trip latency.
+    pub latency: Duration,
+}
+
+#[derive(Clone, Debug)]
+struct Channel {
+    last_sent: Option<Instant>,
+    name: String,
+    priority: ChannelPriority,
+    rate: f64,
+    allowed: f64,
+    size: f64,
+}
+
+/// Decides how often each telemetry channel is sent over the current link.
+#[derive(Clone, Debug)]
+pub struct TelemetryPolicy {
+    channels: Vec<Channel>,
+    congested_latency: Duration,
+    link: Option<Link>,
+    min_rate: f64,
+    share: f64,
+}
+
+impl TelemetryPolicy {
+    /// Creates a new `TelemetryPolicy` without any channels, sending at full
+    /// rate until a link is measured.
+    pub fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            congested_latency: DEFAULT_CONGESTED_LATENCY,
+            link: None,
+            min_rate: DEFAULT_MIN_RATE,
+            share: DEFAULT_SHARE,
+        }
+    }
+
+    /// Adds a channel named `name`, sent at up to `rate` hertz in messages of
+    /// about `size` bytes until sizes are observed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a channel named `name` was already added.
+    #[must_use]
+    pub fn with_channel(
+        mut self,
+        name: &str,
+        priority: ChannelPriority,
+        rate: f64,
+        size: usize,
+    ) -> Self {
+        assert!(
+            self.position(name).is_none(),
+            "channel `{name}` should only be added once"
+        );
+        // Keep the channels sorted from highest priority, after any others of
+        // the same priority.
+        let index = self
+            .channels
+            .iter()
+            .position(|channel| channel.priority < priority)
+            .unwrap_or(self.channels.len());
+        self.channels.insert(
+            index,
+            Channel {
+                last_sent: None,
+                name: name.to_owned(),
+                priority,
+                rate,
+                allowed: rate,
+                size: size as f64,
+            },
+        );
+        self.allocate();
+        self
+    }
+
+    /// Sets the fraction of the link’s bandwidth telemetry may use.
+    #[must_use]
+    pub fn with_share(mut self, share: f64) -> Self {
+        self.share = share.clamp(0.0, 1.0);
+        self.allocate();
+        self
+    }
+
+    /// Sets the latency above which the link is taken to be congested, and
+    /// the bandwidth given to telemetry halved.
+    #[must_use]
+    pub fn with_congested_latency(mut self, latency: Duration) -> Self {
+        self.congested_latency = latency;
+        self.allocate();
+        self
+    }
+
+    /// Sets the slowest rate, in hertz, below which a channel is dropped
+    /// instead.
+    #[must_use]
+    pub fn with_min_rate(mut self, min_rate: f64) -> Self {
+        self.min_rate = min_rate;
+        self.allocate();
+        self
+    }
+
+    /// Updates the measured quality of the link and shares it out again.
+    pub fn update_link(&mut self, link: Link) {
+        self.link = Some(link);
+        self.allocate();
+    }
+
+    /// Returns the rate, in hertz, at which `channel` is currently sent, or
+    /// `None` if there is no such channel.
+    #[must_use]
+    pub fn rate(&self, channel: &str) -> Option<f64> {
+        self.position(channel)
+            .map(|index| self.channels[index].allowed)
+    }
+
+    /// Returns whether a message on `channel` should be sent at `now`, and if
+    /// so, counts it as sent. Unknown channels are never sent.
+    pub fn should_send(&mut self, channel: &str, now: Instant) -> bool {
+        let Some(index) = self.position(channel) else {
+            return false;
+        };
+        let channel = &mut self.channels[index];
+
+        if channel.allowed <= 0.0 {
+            return false;
+        }
+
+        let period = Duration::from_secs_f64(1.0 / channel.allowed);
+        let due = channel
+            .last_sent
+            .is_none_or(|last| now.saturating_duration_since(last) >= period);
+
+        if due {
+            channel.last_sent = Some(now);
+        }
+
+        due
+    }
+
+    /// Records that a message of `size` bytes was sent on `channel`, refining
+    /// its share of the link.
+    pub fn observe(&mut self, channel: &str, size: usize) {
+        if let Some(index) = self.position(channel) {
+            let channel = &mut self.channels[index];
+            channel.size += (size as f64 - channel.size) * SIZE_SMOOTHING;
+        }
+    }
+
+    fn allocate(&mut self) {
+        let Some(link) = self.link else {
+            return;
+        };
+        let mut budget = link.bandwidth * self.share;
+
+        if link.latency > self.congested_latency {
+            budget /= 2.0;
+        }
+
+        for channel in &mut self.channels {
+            let cost = channel.rate * channel.size;
+            let allowed = if channel.priority == ChannelPriority::Critical || cost <= budget {
+                channel.rate
+            } else if channel.size > 0.0 && budget / channel.size >= self.min_rate {
+                budget / channel.size
+            } else {
+                0.0
+            };
+            budget = (budget - allowed * channel.size).max(0.0);
+
+            if allowed != channel.allowed {
+                log_event!(
+                    SUBSYSTEM,
+                    Level::Debug,
+                    "sending {} at {allowed:.1} Hz",
+                    channel.name
+                );
+                channel.allowed = allowed;
+            }
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|channel| channel.name == name)
+    }
+}
+
+impl Default for TelemetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> TelemetryPolicy {
+        TelemetryPolicy::new()
+            .with_channel("imu", ChannelPriority::Low, 100.0, 100)
+            .with_channel("estop", ChannelPriority::Critical, 10.0, 20)
+            .with_channel("odometry", ChannelPriority::High, 50.0, 50)
+    }
+
+    #[test]
+    fn it_should_send_everything_at_full_rate_on_a_fast_link() {
+        let mut policy = policy();
+        policy.update_link(Link {
+            bandwidth: 1e6,
+            latency: Duration::from_millis(1),
+        });
+        assert_eq!(policy.rate("imu"), Some(100.0));
+        assert_eq!(policy.rate("odometry"), Some(50.0));
+        assert_eq!(policy.rate("gps"), None);
+    }
+
+    #[test]
+    fn it_should_slow_and_drop_channels_on_a_weak_link() {
+        let mut policy = policy();
+        // 2000 B/s for telemetry: the E-stop takes 200 and odometry the 1800
+        // left, slowed to 36 Hz.
+        policy.update_link(Link {
+            bandwidth: 4000.0,
+            latency: Duration::from_millis(20),
+        });
+        assert_eq!(policy.rate("estop"), Some(10.0));
+        assert!((policy.rate("odometry").unwrap() - 36.0).abs() < 1e-9);
+        assert_eq!(policy.rate("imu"), Some(0.0));
+        // Congestion halves the budget, but never touches the E-stop.
+        policy.update_link(Link {
+            bandwidth: 100.0,
+            latency: Duration::from_secs(1),
+        });
+        assert_eq!(policy.rate("estop"), Some(10.0));
+        assert_eq!(policy.rate("odometry"), Some(0.0));
+    }
+
+    #[test]
+    fn it_should_pace_messages_at_the_allowed_rate() {
+        let mut policy = policy();
+        policy.update_link(Link {
+            bandwidth: 4000.0,
+            latency: Duration::from_millis(20),
+        });
+        let start = Instant::now();
+        let sent = (0..100)
+            .filter(|&tick| policy.should_send("estop", start + Duration::from_millis(10 * tick)))
+            .count();
+        assert_eq!(sent, 10);
+        assert!(!policy.should_send("imu", start));
+    }
+
+    #[test]
+    fn it_should_learn_message_sizes() {
+        let mut policy = TelemetryPolicy::new().with_channel("imu", ChannelPriority::Low, 10.0, 10);
+        policy.update_link(Link {
+            bandwidth: 400.0,
+            latency: Duration::ZERO,
+        });
+        assert_eq!(policy.rate("imu"), Some(10.0));
+
+        for _ in 0..50 {
+            policy.observe("imu", 100);
+        }
+
+        policy.update_link(Link {
+            bandwidth: 400.0,
+            latency: Duration::ZERO,
+        });
+        assert!(policy.rate("imu").unwrap() < 2.1);
+    }
+}
@@ -0,0 +1,260 @@
+//! An on-screen gamepad for driving from the dashboard.
+//!
+//! The dashboard’s joystick and arrow keys send their state over a
+//! WebSocket, which is turned into the same [`Input`]s a physical controller
+//! produces, so both feed one [`GamepadState`] and one teleop source in the
+//! command mux. A browser tab that stops sending is caught by that source’s
+//! timeout like a controller that lost its connection, and a tab that closes
+//! cleanly releases every input at once.
+//!
+//! The gamepad moves the robot, so [`accept`] only upgrades clients whose
+//! token grants drive permission.
+//!
+//! Messages are JSON objects of one of two types:
+//!
+//! ```text
+//! {"type":"gamepad","axes":{"left_x":0.2,"left_y":-0.8},"buttons":{"south":true}}
+//! {"type":"keys","up":true,"down":false,"left":false,"right":false}
+//! ```
+//!
+//! Axes use the same signs as a physical controller, so pushing the stick up
+//! gives a negative `left_y`. Arrow keys drive the left stick to its limits.
+//!
+//! [`GamepadState`]: crate::ui::gamepad::GamepadState
+
+use std::io::Error;
+use std::net::TcpStream;
+
+use super::auth::{Auth, Permission};
+use super::websocket::{Message, WebSocket};
+use crate::diagnostics::latency::CommandTrace;
+use crate::json::Value;
+use crate::log_event;
+use crate::logging::Level;
+use crate::ui::gamepad::{Axis, Button, Input};
+
+const SUBSYSTEM: &str = "virtual_gamepad";
+
+/// Subprotocol the dashboard requests for the virtual gamepad.
+pub const PROTOCOL: &str = "otter.gamepad.v1";
+
+/// Parses a message from the dashboard into the inputs it describes.
+///
+/// # Errors
+///
+/// This function will return an error if the message is not valid JSON or
+/// names an unknown type, axis, or button.
+pub fn parse(text: &str) -> Result<Vec<Input>, String> {
+    let message: Value = text.parse()?;
+
+    match message.get("type").and_then(Value::as_str) {
+        Some("gamepad") => {
+            let mut inputs = Vec::new();
+
+            for (name, value) in entries(&message, "axes") {
+                let value = value
+                    .as_f64()
+                    .filter(|value| value.is_finite())
+                    .ok_or_else(|| format!("axis `{name}` should be a number"))?;
+                inputs.push(Input::Axis(name.parse()?, value.clamp(-1.0, 1.0)));
+            }
+
+            for (name, value) in entries(&message, "buttons") {
+                let pressed = value
+                    .as_bool()
+                    .ok_or_else(|| format!("button `{name}` should be a boolean"))?;
+                inputs.push(Input::Button(name.parse()?, pressed));
+            }
+
+            Ok(inputs)
+        }
+        Some("keys") => {
+            let held = |key| message.get(key).and_then(Value::as_bool) == Some(true);
+            let direction = |negative, positive| {
+                f64::from(u8::from(held(positive))) - f64::from(u8::from(held(negative)))
+            };
+            Ok(vec![
+                Input::Axis(Axis::LeftX, direction("left", "right")),
+                Input::Axis(Axis::LeftY, direction("up", "down")),
+            ])
+        }
+        Some(kind) => Err(format!("unknown message type `{kind}`")),
+        None => Err("message should have a type".to_owned()),
+    }
+}
+
+/// Returns the inputs that put every axis at rest and release every button.
+#[must_use]
+pub fn neutral() -> Vec<Input> {
+    Axis::ALL
+        .into_iter()
+        .map(|axis| Input::Axis(axis, 0.0))
+        .chain(
+            Button::ALL
+                .into_iter()
+                .map(|button| Input::Button(button, false)),
+        )
+        .collect()
+}
+
+/// Accepts a gamepad connection on `stream` from a client whose token
+/// grants drive permission.
+///
+/// # Errors
+///
+/// This function will return an error if the request cannot be read, is not
+/// a gamepad upgrade, or with [`std::io::ErrorKind::PermissionDenied`] if
+/// the client is refused.
+pub fn accept(stream: TcpStream, auth: &Auth) -> Result<WebSocket, Error> {
+    let (socket, _) = WebSocket::accept_authorized(stream, &[PROTOCOL], auth, Permission::Drive)?;
+    Ok(socket)
+}
+
+/// Reads messages from `socket`, accepted with [`accept`], until it closes,
+/// passing each input to `on_input`, then passes the neutral inputs so the
+/// robot stops.
+///
+/// Each input comes with a [`CommandTrace`] started when its message
+/// arrived, for the command it becomes to carry to the actuators. Malformed
+/// messages are logged and skipped.
+///
+/// # Errors
+///
+/// This function will return an error if the connection fails, after the
+/// neutral inputs are passed.
+pub fn serve(
+    mut socket: WebSocket,
+    mut on_input: impl FnMut(Input, CommandTrace),
+) -> Result<(), Error> {
+    let result = loop {
+        let message = socket.read();
+        let trace = CommandTrace::received_now();
+
+        match message {
+            Ok(Message::Text(text)) => match parse(&text) {
+                Ok(inputs) => inputs.into_iter().for_each(|input| on_input(input, trace)),
+                Err(error) => log_event!(SUBSYSTEM, Level::Debug, "ignoring message: {error}"),
+            },
+            Ok(Message::Binary(_)) => {}
+            Ok(Message::Close) => break Ok(()),
+            Err(error) => break Err(error),
+        }
+    };
+
+    let trace = CommandTrace::received_now();
+    neutral()
+        .into_iter()
+        .for_each(|input| on_input(input, trace));
+    result
+}
+
+fn entries<'a>(message: &'a Value, key: &str) -> &'a [(String, Value)] {
+    match message.get(key) {
+        Some(Value::Object(entries)) => entries,
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::super::websocket::{connect_for_test, connect_to_for_test, send_for_test};
+    use super::*;
+    use crate::control::mux::{CommandMux, Priority};
+    use crate::diagnostics::latency::Stage;
+    use crate::drive::Twist;
+    use crate::ui::gamepad::GamepadState;
+
+    fn auth() -> Auth {
+        Auth::new()
+            .with_token("pilot", Permission::Drive)
+            .with_anonymous(Permission::Read)
+    }
+
+    #[test]
+    fn it_should_parse_sticks_buttons_and_arrow_keys() {
+        assert_eq!(
+            parse(r#"{"type":"gamepad","axes":{"left_y":-2},"buttons":{"start":true}}"#),
+            Ok(vec![
+                Input::Axis(Axis::LeftY, -1.0),
+                Input::Button(Button::Start, true)
+            ])
+        );
+        assert_eq!(
+            parse(r#"{"type":"keys","up":true,"left":true,"right":true}"#),
+            Ok(vec![
+                Input::Axis(Axis::LeftX, 0.0),
+                Input::Axis(Axis::LeftY, -1.0)
+            ])
+        );
+        assert!(parse(r#"{"type":"gamepad","axes":{"throttle":1}}"#).is_err());
+        assert!(parse(r#"{"type":"mouse"}"#).is_err());
+    }
+
+    #[test]
+    fn it_should_drive_through_the_teleop_source_and_stop_on_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let socket = accept(stream, &auth()).unwrap();
+            serve(socket, |input, trace| sender.send((input, trace)).unwrap())
+        });
+
+        let (mut client, _) = connect_to_for_test(address, "/?access_token=pilot", PROTOCOL);
+        send_for_test(&mut client, r#"{"type":"keys","up":true}"#);
+        let mut mux =
+            CommandMux::new().with_source("gamepad", Priority::Teleop, Duration::from_millis(200));
+        let mut state = GamepadState::new();
+        let mut trace = None;
+
+        for (input, received) in receiver.iter().take(2) {
+            state.apply(input);
+            trace = Some(received);
+        }
+
+        let trace = trace.unwrap();
+        mux.submit_traced("gamepad", state.twist(0.5, 1.0), trace, Instant::now())
+            .unwrap();
+        assert_eq!(mux.select(Instant::now()), Some(&Twist::new(0.5, 0.0, 0.0)));
+        let actuated = mux.actuated(Instant::now()).unwrap();
+        assert_eq!(
+            actuated.instant(Stage::Received),
+            trace.instant(Stage::Received)
+        );
+        assert!(actuated.end_to_end().is_some());
+
+        // A tab that disappears without closing still releases the stick.
+        drop(client);
+        assert!(server.join().unwrap().is_err());
+        receiver
+            .try_iter()
+            .for_each(|(input, _)| state.apply(input));
+        assert_eq!(state.twist(0.5, 1.0), Twist::default());
+    }
+
+    #[test]
+    fn it_should_never_pass_inputs_from_unauthorized_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            accept(stream, &auth())
+                .map(|socket| serve(socket, |input, _| sender.send(input).unwrap()))
+        });
+
+        let (mut client, headers) = connect_for_test(address, PROTOCOL);
+        assert!(headers.starts_with("HTTP/1.1 403"), "{headers}");
+        // The client may still send, but nothing is listening.
+        let _ = std::io::Write::write_all(&mut client, b"\x81\x80\0\0\0\0");
+        let error = server.join().unwrap().err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(receiver.try_iter().count(), 0);
+    }
+}
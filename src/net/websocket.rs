@@ -0,0 +1,658 @@
+//! WebSocket connections, for clients that need pushed updates rather than
+//! polling.
+//!
+//! Only the server side of RFC 6455 is implemented: [`WebSocket::accept`]
+//! completes the HTTP upgrade, after which either side can send messages at
+//! any time. Reading and sending are split, so that one thread can block
+//! reading a client while others push messages through a [`Sender`]. Pings
+//! are answered as they are read, and fragmented messages are reassembled.
+//!
+//! Endpoints that expose the robot check the client's token before the
+//! upgrade with [`WebSocket::accept_authorized`]. Browsers cannot set
+//! headers on a WebSocket, so the token may come in an `access_token` query
+//! parameter as well as an `Authorization: Bearer` header.
+
+use std::borrow::Cow;
+use std::io::{BufReader, Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+use super::auth::{bearer_token, Auth, AuthError, Permission};
+use super::http::Request;
+
+/// GUID appended to the client’s key to prove the server speaks WebSocket.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest message accepted from a client, in bytes.
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// A message received from a client.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Message {
+    /// UTF-8 text.
+    Text(String),
+    /// Binary data.
+    Binary(Vec<u8>),
+    /// The client closed the connection.
+    Close,
+}
+
+/// The server end of a WebSocket connection.
+#[derive(Debug)]
+pub struct WebSocket {
+    reader: BufReader<TcpStream>,
+    sender: Sender,
+}
+
+impl WebSocket {
+    /// Reads an upgrade request from `stream` and accepts it, choosing the
+    /// first of `protocols` the client offers, if any.
+    ///
+    /// Returns the connection and the request, so that the caller can check
+    /// its path or credentials.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request cannot be read, or
+    /// with [`ErrorKind::InvalidData`] if it is not a WebSocket upgrade or
+    /// offers none of `protocols`, in which case the client is sent a 400
+    /// response.
+    pub fn accept(stream: TcpStream, protocols: &[&str]) -> Result<(Self, Request), Error> {
+        Self::accept_with(stream, protocols, |_| Ok(()))
+    }
+
+    /// Accepts an upgrade request from `stream` as [`WebSocket::accept`]
+    /// does, but only from a client whose token grants at least `required`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error as [`WebSocket::accept`] does, or
+    /// with [`ErrorKind::PermissionDenied`] if the client is refused, in
+    /// which case it is sent a 401 or 403 response instead of the upgrade.
+    pub fn accept_authorized(
+        stream: TcpStream,
+        protocols: &[&str],
+        auth: &Auth,
+        required: Permission,
+    ) -> Result<(Self, Request), Error> {
+        Self::accept_with(stream, protocols, |request| {
+            auth.authorize(token(request).as_deref(), required)
+                .map(|_| ())
+        })
+    }
+
+    fn accept_with(
+        stream: TcpStream,
+        protocols: &[&str],
+        authorize: impl FnOnce(&Request) -> Result<(), AuthError>,
+    ) -> Result<(Self, Request), Error> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let request = Request::read_from(&mut reader)?;
+
+        let key = request
+            .header("sec-websocket-key")
+            .filter(|_| {
+                request
+                    .header("upgrade")
+                    .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+            })
+            .map(str::to_owned);
+        let offered: Vec<_> = request
+            .header("sec-websocket-protocol")
+            .map(|offered| offered.split(',').map(str::trim).collect())
+            .unwrap_or_default();
+        let protocol = protocols.iter().find(|protocol| offered.contains(protocol));
+
+        let Some(key) = key.filter(|_| protocols.is_empty() || protocol.is_some()) else {
+            write!(
+                writer,
+                "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )?;
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "not an acceptable WebSocket upgrade",
+            ));
+        };
+
+        if let Err(error) = authorize(&request) {
+            let status = match error {
+                AuthError::Unauthenticated => "401 Unauthorized\r\nWWW-Authenticate: Bearer",
+                AuthError::Forbidden => "403 Forbidden",
+            };
+            write!(
+                writer,
+                "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )?;
+            return Err(Error::new(ErrorKind::PermissionDenied, error));
+        }
+
+        write!(
+            writer,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n",
+            accept_key(&key)
+        )?;
+
+        if let Some(protocol) = protocol {
+            write!(writer, "Sec-WebSocket-Protocol: {protocol}\r\n")?;
+        }
+
+        write!(writer, "\r\n")?;
+        writer.flush()?;
+
+        let sender = Sender {
+            writer: Arc::new(Mutex::new(writer)),
+        };
+        Ok((Self { reader, sender }, request))
+    }
+
+    /// Returns a handle for sending messages to the client from any thread.
+    #[must_use]
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    /// Waits for the next message from the client, answering pings on the
+    /// way.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the connection fails, or with
+    /// [`ErrorKind::InvalidData`] if the client breaks the protocol.
+    pub fn read(&mut self) -> Result<Message, Error> {
+        let mut message = Vec::new();
+        let mut kind = None;
+
+        loop {
+            let frame = read_frame(&mut self.reader)?;
+
+            match frame.opcode {
+                PING => self.sender.send(PONG, &frame.payload)?,
+                PONG => {}
+                CLOSE => {
+                    // Echo the close, which may fail if the client is gone.
+                    let _ = self.sender.send(CLOSE, &frame.payload);
+                    return Ok(Message::Close);
+                }
+                TEXT | BINARY if kind.is_none() => kind = Some(frame.opcode),
+                CONTINUATION if kind.is_some() => {}
+                _ => return Err(invalid_data("unexpected frame")),
+            }
+
+            if frame.opcode < CLOSE {
+                if message.len() + frame.payload.len() > MAX_MESSAGE {
+                    return Err(invalid_data("message too large"));
+                }
+
+                message.extend_from_slice(&frame.payload);
+
+                if frame.fin {
+                    break;
+                }
+            }
+        }
+
+        match kind {
+            Some(TEXT) => String::from_utf8(message)
+                .map(Message::Text)
+                .map_err(|_| invalid_data("text is not UTF-8")),
+            _ => Ok(Message::Binary(message)),
+        }
+    }
+}
+
+/// Sends messages to a WebSocket client, and can be cloned to share between
+/// threads.
+#[derive(Clone, Debug)]
+pub struct Sender {
+    writer: Arc<Mutex<TcpStream>>,
+}
+
+impl Sender {
+    /// Sends `text`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the connection fails.
+    pub fn send_text(&self, text: &str) -> Result<(), Error> {
+        self.send(TEXT, text.as_bytes())
+    }
+
+    /// Sends `data`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the connection fails.
+    pub fn send_binary(&self, data: &[u8]) -> Result<(), Error> {
+        self.send(BINARY, data)
+    }
+
+    /// Tells the client the connection is closing.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the connection fails.
+    pub fn close(&self) -> Result<(), Error> {
+        self.send(CLOSE, &[])
+    }
+
+    fn send(&self, opcode: u8, payload: &[u8]) -> Result<(), Error> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        write_frame(&mut *writer, opcode, payload, None)
+    }
+}
+
+/// Senders are equal if they send to the same connection.
+impl PartialEq for Sender {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.writer, &other.writer)
+    }
+}
+
+impl Eq for Sender {}
+
+/// Returns the token the client presented in an `Authorization: Bearer`
+/// header or an `access_token` query parameter, percent-decoded.
+fn token(request: &Request) -> Option<Cow<'_, str>> {
+    if let Some(token) = request.header("authorization").and_then(bearer_token) {
+        return Some(Cow::Borrowed(token));
+    }
+    request
+        .query
+        .as_deref()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("access_token="))
+        .and_then(percent_decode)
+        .filter(|token| !token.is_empty())
+}
+
+/// Returns `text` with its `%XX` escapes decoded, or `None` if an escape is
+/// malformed or the result is not UTF-8.
+fn percent_decode(text: &str) -> Option<Cow<'_, str>> {
+    if !text.contains('%') {
+        return Some(Cow::Borrowed(text));
+    }
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).ok().map(Cow::Owned)
+}
+
+/// Returns `bytes` encoded as standard Base64, with padding.
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let word = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |word, (index, &byte)| {
+                word | u32::from(byte) << (16 - 8 * index)
+            });
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (word >> (18 - 6 * index)) & 0x3F;
+                text.push(char::from(ALPHABET[sextet as usize]));
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<Frame, Error> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+            u64::from(u16::from_be_bytes(length))
+        }
+        127 => {
+            let mut length = [0; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => u64::from(length),
+    };
+
+    if length > MAX_MESSAGE as u64 {
+        return Err(invalid_data("frame too large"));
+    }
+
+    let mut mask = [0; 4];
+
+    // Clients must mask every frame.
+    if header[1] & 0x80 == 0 {
+        return Err(invalid_data("unmasked frame"));
+    }
+
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+
+    Ok(Frame {
+        fin: header[0] & 0x80 != 0,
+        opcode: header[0] & 0x0F,
+        payload,
+    })
+}
+
+fn write_frame(
+    writer: &mut impl Write,
+    opcode: u8,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) -> Result<(), Error> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    let masked = if mask.is_some() { 0x80 } else { 0 };
+
+    match payload.len() {
+        length @ 0..=125 => frame.push(masked | length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(masked | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(masked | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .enumerate()
+                    .map(|(index, byte)| byte ^ mask[index % 4]),
+            );
+        }
+        None => frame.extend_from_slice(payload),
+    }
+
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+
+    message.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0_u32; 80];
+
+        for (index, word) in block.chunks(4).enumerate() {
+            words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 20];
+
+    for (chunk, value) in digest.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+
+    digest
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+/// Connects to a WebSocket server for tests, returning the stream and the
+/// server’s response headers.
+#[cfg(test)]
+pub(super) fn connect_for_test(
+    address: std::net::SocketAddr,
+    protocol: &str,
+) -> (TcpStream, String) {
+    connect_to_for_test(address, "/", protocol)
+}
+
+/// Connects to `target` on a WebSocket server for tests, returning the
+/// stream and the server’s response headers.
+#[cfg(test)]
+pub(super) fn connect_to_for_test(
+    address: std::net::SocketAddr,
+    target: &str,
+    protocol: &str,
+) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "GET {target} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Protocol: {protocol}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    )
+    .unwrap();
+    let mut headers = Vec::new();
+
+    // Read a byte at a time to leave any frames that follow in the stream.
+    while !headers.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        headers.push(byte[0]);
+    }
+
+    (stream, String::from_utf8(headers).unwrap())
+}
+
+/// Sends a masked frame, as a client would.
+#[cfg(test)]
+pub(super) fn send_for_test(stream: &mut TcpStream, text: &str) {
+    write_frame(stream, TEXT, text.as_bytes(), Some([1, 2, 3, 4])).unwrap();
+}
+
+/// Reads an unmasked frame, as a client would, returning its opcode and
+/// payload.
+#[cfg(test)]
+pub(super) fn receive_for_test(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header).unwrap();
+    let length = match header[1] {
+        126 => {
+            let mut length = [0; 2];
+            stream.read_exact(&mut length).unwrap();
+            usize::from(u16::from_be_bytes(length))
+        }
+        length => usize::from(length),
+    };
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload).unwrap();
+    (header[0] & 0x0F, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn it_should_compute_the_accept_key() {
+        // From the example in RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn it_should_unmask_and_reassemble_frames() {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, TEXT, &[b'x'; 300], Some([9, 8, 7, 6])).unwrap();
+        let frame = read_frame(&mut Cursor::new(&bytes)).unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, TEXT);
+        assert_eq!(frame.payload, [b'x'; 300]);
+
+        let mut unmasked = Vec::new();
+        write_frame(&mut unmasked, TEXT, b"hi", None).unwrap();
+        assert_eq!(unmasked, [0x81, 2, b'h', b'i']);
+        let error = read_frame(&mut Cursor::new(&unmasked)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn it_should_exchange_messages_with_a_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (mut socket, request) = WebSocket::accept(stream, &["echo"]).unwrap();
+            assert_eq!(request.path, "/");
+
+            loop {
+                match socket.read().unwrap() {
+                    Message::Text(text) => socket.sender().send_text(&text).unwrap(),
+                    message => break message,
+                }
+            }
+        });
+
+        let (mut client, headers) = connect_for_test(address, "other, echo");
+        assert!(headers.starts_with("HTTP/1.1 101"));
+        assert!(headers.contains("Sec-WebSocket-Protocol: echo\r\n"));
+        send_for_test(&mut client, "hello");
+        assert_eq!(receive_for_test(&mut client), (TEXT, b"hello".to_vec()));
+        write_frame(&mut client, PING, b"?", Some([0; 4])).unwrap();
+        assert_eq!(receive_for_test(&mut client), (PONG, b"?".to_vec()));
+        write_frame(&mut client, CLOSE, &[], Some([0; 4])).unwrap();
+        assert_eq!(server.join().unwrap(), Message::Close);
+    }
+
+    #[test]
+    fn it_should_percent_decode_a_token_in_the_query() {
+        let request = Request::new("GET", "/?x=1&access_token=a%2Bb%2Fc%3D%3d");
+        assert_eq!(token(&request).as_deref(), Some("a+b/c=="));
+        let auth = Auth::new().with_token("a+b/c==", Permission::Drive);
+        assert_eq!(
+            auth.authorize(token(&request).as_deref(), Permission::Drive),
+            Ok(Permission::Drive)
+        );
+        assert_eq!(token(&Request::new("GET", "/?access_token=a%2")), None);
+        assert_eq!(token(&Request::new("GET", "/?access_token=%zz")), None);
+        assert_eq!(token(&Request::new("GET", "/?access_token=%FF")), None);
+    }
+
+    #[test]
+    fn it_should_refuse_the_upgrade_without_a_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let auth = Auth::new()
+                .with_token("guest", Permission::Read)
+                .with_token("pilot", Permission::Drive);
+            (0..3)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    WebSocket::accept_authorized(stream, &["echo"], &auth, Permission::Drive)
+                        .map(|_| ())
+                        .map_err(|error| error.kind())
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let (_, headers) = connect_for_test(address, "echo");
+        assert!(headers.starts_with("HTTP/1.1 401"), "{headers}");
+        let (_, headers) = connect_to_for_test(address, "/?access_token=guest", "echo");
+        assert!(headers.starts_with("HTTP/1.1 403"), "{headers}");
+        let (_, headers) = connect_to_for_test(address, "/?x=1&access_token=pilot", "echo");
+        assert!(headers.starts_with("HTTP/1.1 101"), "{headers}");
+        assert_eq!(
+            server.join().unwrap(),
+            [
+                Err(ErrorKind::PermissionDenied),
+                Err(ErrorKind::PermissionDenied),
+                Ok(())
+            ]
+        );
+    }
+}
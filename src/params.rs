@@ -0,0 +1,784 @@
+//! Named, typed parameters that can be tuned while the robot runs.
+//!
+//! Modules register the parameters they read, such as PID gains and speed
+//! limits, with a default and an optional range, and keep a [`Param`] handle
+//! for reading the current value. Values changed at runtime are validated,
+//! persisted to disk, and announced to change listeners, so tuning does not
+//! require rebuilding and redeploying.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "params";
+
+/// Value of a parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamValue {
+    /// A flag.
+    Bool(bool),
+    /// An integer, such as a count or a pin number.
+    Int(i64),
+    /// A real number, such as a gain or a speed.
+    Float(f64),
+    /// A string, such as a device path.
+    Text(String),
+}
+
+impl ParamValue {
+    /// Returns the kind of the value.
+    #[must_use]
+    pub fn kind(&self) -> ParamKind {
+        match self {
+            Self::Bool(_) => ParamKind::Bool,
+            Self::Int(_) => ParamKind::Int,
+            Self::Float(_) => ParamKind::Float,
+            Self::Text(_) => ParamKind::Text,
+        }
+    }
+
+    /// Converts a JSON value into a parameter value of `kind`, or returns
+    /// `None` if it has the wrong type.
+    #[must_use]
+    pub fn from_json(value: &Value, kind: ParamKind) -> Option<Self> {
+        match (kind, value) {
+            (ParamKind::Bool, Value::Bool(value)) => Some(Self::Bool(*value)),
+            (ParamKind::Int, Value::Number(value)) if value.fract() == 0.0 => {
+                Some(Self::Int(*value as i64))
+            }
+            (ParamKind::Float, Value::Number(value)) => Some(Self::Float(*value)),
+            (ParamKind::Text, Value::String(value)) => Some(Self::Text(value.clone())),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match *self {
+            Self::Int(value) => Some(value as f64),
+            Self::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ParamValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Int(value) => write!(f, "{value}"),
+            Self::Float(value) => write!(f, "{value}"),
+            Self::Text(value) => write!(f, "{value:?}"),
+        }
+    }
+}
+
+impl ToJson for ParamValue {
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Bool(value) => value.to_json(),
+            Self::Int(value) => value.to_json(),
+            Self::Float(value) => value.to_json(),
+            Self::Text(value) => value.to_json(),
+        }
+    }
+}
+
+/// Type of a parameter.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ParamKind {
+    /// [`ParamValue::Bool`].
+    Bool,
+    /// [`ParamValue::Int`].
+    Int,
+    /// [`ParamValue::Float`].
+    Float,
+    /// [`ParamValue::Text`].
+    Text,
+}
+
+impl Display for ParamKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Bool => "bool",
+            Self::Int => "int",
+            Self::Float => "float",
+            Self::Text => "text",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A Rust type that can be stored in a parameter.
+pub trait ParamType: Sized {
+    /// Kind of parameter that stores the type.
+    const KIND: ParamKind;
+
+    /// Extracts the type from a value of [`ParamType::KIND`].
+    fn from_value(value: &ParamValue) -> Option<Self>;
+
+    /// Wraps the type in a value.
+    fn into_value(self) -> ParamValue;
+}
+
+impl ParamType for bool {
+    const KIND: ParamKind = ParamKind::Bool;
+
+    fn from_value(value: &ParamValue) -> Option<Self> {
+        match *value {
+            ParamValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> ParamValue {
+        ParamValue::Bool(self)
+    }
+}
+
+impl ParamType for i64 {
+    const KIND: ParamKind = ParamKind::Int;
+
+    fn from_value(value: &ParamValue) -> Option<Self> {
+        match *value {
+            ParamValue::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> ParamValue {
+        ParamValue::Int(self)
+    }
+}
+
+impl ParamType for f64 {
+    const KIND: ParamKind = ParamKind::Float;
+
+    fn from_value(value: &ParamValue) -> Option<Self> {
+        match *value {
+            ParamValue::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> ParamValue {
+        ParamValue::Float(self)
+    }
+}
+
+impl ParamType for String {
+    const KIND: ParamKind = ParamKind::Text;
+
+    fn from_value(value: &ParamValue) -> Option<Self> {
+        match value {
+            ParamValue::Text(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> ParamValue {
+        ParamValue::Text(self)
+    }
+}
+
+/// Description of a parameter to register.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamSpec<T> {
+    default: T,
+    description: String,
+    max: Option<f64>,
+    min: Option<f64>,
+    name: String,
+}
+
+impl<T: ParamType + Clone> ParamSpec<T> {
+    /// Creates a new spec for the parameter `name` with a `default` value.
+    ///
+    /// Names are dotted paths scoped by module, such as
+    /// `drive.left.velocity.kp`.
+    pub fn new(name: &str, default: T) -> Self {
+        Self {
+            default,
+            description: String::new(),
+            max: None,
+            min: None,
+            name: name.to_owned(),
+        }
+    }
+
+    /// Sets a description shown to whoever is tuning the parameter.
+    #[must_use]
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.to_owned();
+        self
+    }
+
+    /// Limits a numeric parameter to values from `min` to `max`, inclusive.
+    #[must_use]
+    pub fn range(mut self, min: f64, max: f64) -> Self {
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+}
+
+/// Error returned when a parameter cannot be registered or changed.
+#[derive(Debug)]
+pub enum ParamError {
+    /// No parameter is registered with the name.
+    Unknown(String),
+    /// A parameter is already registered with the name, with a different
+    /// type.
+    Conflict(String),
+    /// The value has the wrong type for the parameter.
+    WrongType {
+        /// Name of the parameter.
+        name: String,
+        /// Kind of the parameter.
+        expected: ParamKind,
+    },
+    /// The value is outside the parameter’s range.
+    OutOfRange {
+        /// Name of the parameter.
+        name: String,
+        /// Rejected value.
+        value: ParamValue,
+    },
+    /// The value was accepted but could not be persisted.
+    Io(io::Error),
+}
+
+impl Display for ParamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "unknown parameter `{name}`"),
+            Self::Conflict(name) => write!(f, "parameter `{name}` registered with another type"),
+            Self::WrongType { name, expected } => {
+                write!(f, "parameter `{name}` should be a {expected}")
+            }
+            Self::OutOfRange { name, value } => {
+                write!(f, "value {value} out of range for parameter `{name}`")
+            }
+            Self::Io(error) => write!(f, "could not persist parameters: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParamError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Current state of a registered parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamInfo {
+    /// Name of the parameter.
+    pub name: String,
+    /// Description of the parameter.
+    pub description: String,
+    /// Current value.
+    pub value: ParamValue,
+    /// Default value.
+    pub default: ParamValue,
+    /// Minimum value, if limited.
+    pub min: Option<f64>,
+    /// Maximum value, if limited.
+    pub max: Option<f64>,
+}
+
+impl ToJson for ParamInfo {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("name", &self.name)
+            .with("type", self.value.kind().to_string())
+            .with("description", &self.description)
+            .with("value", &self.value)
+            .with("default", &self.default)
+            .with("min", self.min)
+            .with("max", self.max)
+    }
+}
+
+type Listener = Arc<dyn Fn(&str, &ParamValue) + Send + Sync>;
+
+#[derive(Default)]
+struct Registry {
+    listeners: Vec<(String, Listener)>,
+    params: BTreeMap<String, ParamInfo>,
+    /// Values loaded from disk for parameters that have not been registered
+    /// yet.
+    pending: BTreeMap<String, Value>,
+}
+
+struct Shared {
+    path: Option<PathBuf>,
+    registry: RwLock<Registry>,
+}
+
+/// Registry of every parameter, shared by cloning.
+#[derive(Clone)]
+pub struct ParamServer {
+    shared: Arc<Shared>,
+}
+
+impl ParamServer {
+    /// Creates a new `ParamServer` that keeps values in memory only.
+    pub fn new() -> Self {
+        Self::with_path(None, BTreeMap::new())
+    }
+
+    /// Creates a new `ParamServer` that persists changed values to `path`,
+    /// loading any values saved there by a previous run.
+    ///
+    /// Saved values are applied as each parameter is registered, and are
+    /// ignored if they no longer fit the parameter’s type or range.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `path` exists but cannot be read
+    /// or is not a JSON object.
+    #[cfg(unix)]
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let pending = match std::fs::read_to_string(path) {
+            Ok(contents) => match contents.parse::<Value>() {
+                Ok(Value::Object(entries)) => entries.into_iter().collect(),
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "parameter file should contain a JSON object",
+                    ))
+                }
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => return Err(error),
+        };
+        Ok(Self::with_path(Some(path.to_owned()), pending))
+    }
+
+    fn with_path(path: Option<PathBuf>, pending: BTreeMap<String, Value>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                path,
+                registry: RwLock::new(Registry {
+                    pending,
+                    ..Registry::default()
+                }),
+            }),
+        }
+    }
+
+    /// Registers a parameter and returns a handle for reading it.
+    ///
+    /// Registering the same name again with the same type returns a handle
+    /// to the existing parameter, so modules can be restarted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`ParamError::Conflict`] if the name is
+    /// registered with another type.
+    pub fn register<T: ParamType + Clone>(
+        &self,
+        spec: ParamSpec<T>,
+    ) -> Result<Param<T>, ParamError> {
+        let mut registry = self.write();
+
+        if let Some(existing) = registry.params.get(&spec.name) {
+            return if existing.value.kind() == T::KIND {
+                Ok(self.handle(spec.name))
+            } else {
+                Err(ParamError::Conflict(spec.name))
+            };
+        }
+
+        let default = spec.default.into_value();
+        let mut info = ParamInfo {
+            name: spec.name.clone(),
+            description: spec.description,
+            value: default.clone(),
+            default,
+            min: spec.min,
+            max: spec.max,
+        };
+
+        if let Some(saved) = registry.pending.remove(&spec.name) {
+            match ParamValue::from_json(&saved, T::KIND).filter(|value| in_range(&info, value)) {
+                Some(value) => info.value = value,
+                None => log_event!(
+                    SUBSYSTEM,
+                    Level::Warn,
+                    "ignoring saved value {saved} for `{}`",
+                    spec.name
+                ),
+            }
+        }
+
+        registry.params.insert(spec.name.clone(), info);
+        Ok(self.handle(spec.name))
+    }
+
+    /// Returns the current value of `name`.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<ParamValue> {
+        self.read().params.get(name).map(|info| info.value.clone())
+    }
+
+    /// Changes the value of `name`, persists every changed value, and
+    /// notifies listeners.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the parameter is unknown, if
+    /// the value has the wrong type or is out of range, or if it cannot be
+    /// persisted. A value that cannot be persisted still takes effect.
+    pub fn set(&self, name: &str, value: ParamValue) -> Result<(), ParamError> {
+        let (value, listeners): (ParamValue, Vec<Listener>) = {
+            let mut registry = self.write();
+            let info = registry
+                .params
+                .get_mut(name)
+                .ok_or_else(|| ParamError::Unknown(name.to_owned()))?;
+            let value = coerce(info, value)?;
+
+            if !in_range(info, &value) {
+                return Err(ParamError::OutOfRange {
+                    name: name.to_owned(),
+                    value,
+                });
+            }
+
+            info.value = value.clone();
+            let listeners = registry
+                .listeners
+                .iter()
+                .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+                .map(|(_, listener)| Arc::clone(listener))
+                .collect();
+            (value, listeners)
+        };
+        log_event!(SUBSYSTEM, Level::Info, "set `{name}` to {value}");
+
+        for listener in listeners {
+            listener(name, &value);
+        }
+
+        self.persist()
+    }
+
+    /// Changes the value of `name` from JSON, as received over a network API.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`ParamServer::set`].
+    pub fn set_json(&self, name: &str, value: &Value) -> Result<(), ParamError> {
+        let kind = self
+            .read()
+            .params
+            .get(name)
+            .map(|info| info.value.kind())
+            .ok_or_else(|| ParamError::Unknown(name.to_owned()))?;
+        let value = ParamValue::from_json(value, kind).ok_or_else(|| ParamError::WrongType {
+            name: name.to_owned(),
+            expected: kind,
+        })?;
+        self.set(name, value)
+    }
+
+    /// Restores the default value of `name`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`ParamServer::set`].
+    pub fn reset(&self, name: &str) -> Result<(), ParamError> {
+        let default = self
+            .read()
+            .params
+            .get(name)
+            .map(|info| info.default.clone())
+            .ok_or_else(|| ParamError::Unknown(name.to_owned()))?;
+        self.set(name, default)
+    }
+
+    /// Registers `listener` to be called after any parameter whose name starts
+    /// with `prefix` changes.
+    pub fn on_change(
+        &self,
+        prefix: &str,
+        listener: impl Fn(&str, &ParamValue) + Send + Sync + 'static,
+    ) {
+        self.write()
+            .listeners
+            .push((prefix.to_owned(), Arc::new(listener)));
+    }
+
+    /// Returns every registered parameter, sorted by name.
+    #[must_use]
+    pub fn list(&self) -> Vec<ParamInfo> {
+        self.read().params.values().cloned().collect()
+    }
+
+    fn handle<T>(&self, name: String) -> Param<T> {
+        Param {
+            name,
+            server: self.clone(),
+            _type: PhantomData,
+        }
+    }
+
+    fn persist(&self) -> Result<(), ParamError> {
+        let Some(path) = &self.shared.path else {
+            return Ok(());
+        };
+        let contents = {
+            let registry = self.read();
+            let saved = registry
+                .params
+                .values()
+                .filter(|info| info.value != info.default)
+                .fold(Value::object(), |object, info| {
+                    object.with(&info.name, &info.value)
+                });
+            registry
+                .pending
+                .iter()
+                .fold(saved, |object, (name, value)| object.with(name, value))
+        };
+
+        #[cfg(unix)]
+        crate::unix::fsutil::write_atomic(path, contents.to_string().as_bytes())?;
+        #[cfg(not(unix))]
+        std::fs::write(path, contents.to_string())?;
+        Ok(())
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Registry> {
+        self.shared
+            .registry
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Registry> {
+        self.shared
+            .registry
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for ParamServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for ParamServer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParamServer")
+            .field("path", &self.shared.path)
+            .field("params", &self.read().params.len())
+            .finish()
+    }
+}
+
+/// Handle for reading a registered parameter.
+#[derive(Clone, Debug)]
+pub struct Param<T> {
+    name: String,
+    server: ParamServer,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T: ParamType> Param<T> {
+    /// Returns the name of the parameter.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the current value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the parameter server no longer holds a value of type `T`,
+    /// which cannot happen through its public interface.
+    #[must_use]
+    pub fn get(&self) -> T {
+        self.server
+            .read()
+            .params
+            .get(&self.name)
+            .and_then(|info| T::from_value(&info.value))
+            .expect("registered parameter should hold a value of its type")
+    }
+
+    /// Changes the value.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error under the same conditions as
+    /// [`ParamServer::set`].
+    pub fn set(&self, value: T) -> Result<(), ParamError> {
+        self.server.set(&self.name, value.into_value())
+    }
+}
+
+/// Converts `value` to the kind of the parameter described by `info`,
+/// widening integers to floats.
+fn coerce(info: &ParamInfo, value: ParamValue) -> Result<ParamValue, ParamError> {
+    match (info.value.kind(), value) {
+        (ParamKind::Float, ParamValue::Int(value)) => Ok(ParamValue::Float(value as f64)),
+        (kind, value) if kind == value.kind() => Ok(value),
+        (expected, _) => Err(ParamError::WrongType {
+            name: info.name.clone(),
+            expected,
+        }),
+    }
+}
+
+fn in_range(info: &ParamInfo, value: &ParamValue) -> bool {
+    value.as_number().is_none_or(|number| {
+        info.min.is_none_or(|min| number >= min) && info.max.is_none_or(|max| number <= max)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_read_the_default_until_a_value_is_set() {
+        let server = ParamServer::new();
+        let kp = server
+            .register(ParamSpec::new("drive.kp", 1.5).description("Proportional gain"))
+            .unwrap();
+        assert_eq!(kp.get(), 1.5);
+        server.set("drive.kp", ParamValue::Float(2.0)).unwrap();
+        assert_eq!(kp.get(), 2.0);
+        server.reset("drive.kp").unwrap();
+        assert_eq!(kp.get(), 1.5);
+    }
+
+    #[test]
+    fn it_should_reject_values_of_the_wrong_type_or_out_of_range() {
+        let server = ParamServer::new();
+        let speed = server
+            .register(ParamSpec::new("drive.max_speed", 0.5).range(0.0, 1.0))
+            .unwrap();
+        assert!(matches!(speed.set(1.5), Err(ParamError::OutOfRange { .. })));
+        assert!(matches!(
+            server.set("drive.max_speed", ParamValue::Bool(true)),
+            Err(ParamError::WrongType { .. })
+        ));
+        assert!(matches!(
+            server.set("drive.min_speed", ParamValue::Float(0.0)),
+            Err(ParamError::Unknown(_))
+        ));
+        server.set("drive.max_speed", ParamValue::Int(1)).unwrap();
+        assert_eq!(speed.get(), 1.0);
+    }
+
+    #[test]
+    fn it_should_reject_registering_a_name_with_another_type() {
+        let server = ParamServer::new();
+        server
+            .register(ParamSpec::new("imu.enabled", true))
+            .unwrap();
+        assert!(server.register(ParamSpec::new("imu.enabled", true)).is_ok());
+        assert!(matches!(
+            server.register(ParamSpec::new("imu.enabled", 1_i64)),
+            Err(ParamError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn it_should_notify_listeners_of_matching_changes() {
+        let server = ParamServer::new();
+        server.register(ParamSpec::new("drive.kp", 1.0)).unwrap();
+        server
+            .register(ParamSpec::new("camera.device", "/dev/video0".to_owned()))
+            .unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let listener_changes = Arc::clone(&changes);
+        server.on_change("drive.", move |name, value| {
+            listener_changes
+                .lock()
+                .unwrap()
+                .push((name.to_owned(), value.clone()));
+        });
+        server.set_json("drive.kp", &Value::Number(3.0)).unwrap();
+        server
+            .set_json("camera.device", &Value::String("/dev/video1".to_owned()))
+            .unwrap();
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [("drive.kp".to_owned(), ParamValue::Float(3.0))]
+        );
+    }
+
+    #[test]
+    fn it_should_persist_changed_values_and_load_them_on_registration() {
+        let dir = TemporaryDirectory::new().expect("should succeed");
+        let path = dir.path().join("params.json");
+        let server = ParamServer::open(&path).unwrap();
+        let kp = server.register(ParamSpec::new("drive.kp", 1.0)).unwrap();
+        server.register(ParamSpec::new("drive.ki", 0.0)).unwrap();
+        kp.set(4.0).unwrap();
+        assert!(fs::read_to_string(&path).is_ok_and(|contents| contents == r#"{"drive.kp":4}"#));
+
+        let server = ParamServer::open(&path).unwrap();
+        let kp = server.register(ParamSpec::new("drive.kp", 1.0)).unwrap();
+        assert_eq!(kp.get(), 4.0);
+    }
+
+    #[test]
+    fn it_should_ignore_a_saved_value_that_no_longer_fits() {
+        let dir = TemporaryDirectory::new().expect("should succeed");
+        let path = dir.path().join("params.json");
+        fs::write(&path, r#"{"drive.kp":40,"future.param":true}"#).unwrap();
+        let server = ParamServer::open(&path).unwrap();
+        let kp = server
+            .register(ParamSpec::new("drive.kp", 1.0).range(0.0, 10.0))
+            .unwrap();
+        assert_eq!(kp.get(), 1.0);
+        kp.set(2.0).unwrap();
+        let saved: Value = fs::read_to_string(&path).unwrap().parse().unwrap();
+        assert_eq!(saved.get("future.param"), Some(&Value::Bool(true)));
+    }
+
+    #[test]
+    fn it_should_list_parameters_as_json() {
+        let server = ParamServer::new();
+        server
+            .register(ParamSpec::new("a.count", 3_i64).range(0.0, 5.0))
+            .unwrap();
+        let list = server.list();
+        assert_eq!(
+            list[0].to_json().to_string(),
+            r#"{"name":"a.count","type":"int","description":"","value":3,"default":3,"min":0,"max":5}"#
+        );
+    }
+}
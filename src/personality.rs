@@ -0,0 +1,460 @@
+//! Expressions that make the robot feel alive.
+//!
+//! Rather than every behavior blinking LEDs and beeping on its own, the
+//! robot’s state and the events on the bus are mapped to coordinated
+//! expressions by one table, written in the same style as the
+//! [LED patterns](crate::devices::led::patterns):
+//!
+//! ```text
+//! # A state sets the resting expression.
+//! state idle = led breathe cyan 3s, face sleepy
+//! state active = led solid green, face neutral
+//! # An event plays a reaction over it, for 2 s unless held longer.
+//! event low_battery = sound sad, gesture droop, face worried, hold 4s
+//! event motion_detected = gesture look, face surprised
+//! event emergency_stop = led solid red, face alarmed, sound alarm
+//! ```
+//!
+//! Each line sets any of an LED animation, a sound named for the caller to
+//! play, a head [`Gesture`] on the pan-tilt gimbal, and a face named for the
+//! OLED. Events are named after their kind, in snake case. A reaction
+//! overrides only the outputs it sets, so a surprised face can appear while
+//! the LEDs keep showing the state. [`Personality::poll`] returns what to
+//! show at each tick of the UI loop.
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::devices::led::patterns::parse_animation;
+use crate::devices::led::Animation;
+use crate::events::{Event, EventKind};
+
+/// How long a reaction lasts unless its line says otherwise.
+const DEFAULT_HOLD: Duration = Duration::from_secs(2);
+
+/// A movement of the head on the pan-tilt gimbal.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Gesture {
+    /// Tilts down and up twice, for yes.
+    Nod,
+    /// Pans left and right twice, for no.
+    Shake,
+    /// Pans to each side and back, as if looking around.
+    Look,
+    /// Tilts down and holds, for sadness or tiredness.
+    Droop,
+    /// Tilts up and holds, for attention.
+    Perk,
+}
+
+impl Gesture {
+    /// Returns the keyframes of the gesture as times from its start with
+    /// the pan and tilt, in radians, to reach by then.
+    #[must_use]
+    pub fn keyframes(self) -> &'static [(Duration, f64, f64)] {
+        const fn ms(millis: u64) -> Duration {
+            Duration::from_millis(millis)
+        }
+        const NOD: &[(Duration, f64, f64)] = &[
+            (ms(0), 0.0, 0.0),
+            (ms(200), 0.0, -0.3),
+            (ms(400), 0.0, 0.0),
+            (ms(600), 0.0, -0.3),
+            (ms(800), 0.0, 0.0),
+        ];
+        const SHAKE: &[(Duration, f64, f64)] = &[
+            (ms(0), 0.0, 0.0),
+            (ms(200), 0.4, 0.0),
+            (ms(400), -0.4, 0.0),
+            (ms(600), 0.4, 0.0),
+            (ms(800), 0.0, 0.0),
+        ];
+        const LOOK: &[(Duration, f64, f64)] = &[
+            (ms(0), 0.0, 0.0),
+            (ms(600), 0.9, 0.1),
+            (ms(1400), -0.9, 0.1),
+            (ms(2000), 0.0, 0.0),
+        ];
+        const DROOP: &[(Duration, f64, f64)] = &[(ms(0), 0.0, 0.0), (ms(800), 0.0, -0.5)];
+        const PERK: &[(Duration, f64, f64)] = &[(ms(0), 0.0, 0.0), (ms(300), 0.0, 0.3)];
+
+        match self {
+            Self::Nod => NOD,
+            Self::Shake => SHAKE,
+            Self::Look => LOOK,
+            Self::Droop => DROOP,
+            Self::Perk => PERK,
+        }
+    }
+
+    /// Returns the pan and tilt `elapsed` into the gesture, interpolating
+    /// between keyframes and holding the last.
+    #[must_use]
+    pub fn pose_at(self, elapsed: Duration) -> (f64, f64) {
+        let keyframes = self.keyframes();
+
+        for pair in keyframes.windows(2) {
+            let [(start, pan0, tilt0), (end, pan1, tilt1)] = [pair[0], pair[1]];
+
+            if elapsed < end {
+                let t = elapsed.saturating_sub(start).as_secs_f64() / (end - start).as_secs_f64();
+                return (pan0 + (pan1 - pan0) * t, tilt0 + (tilt1 - tilt0) * t);
+            }
+        }
+
+        let (_, pan, tilt) = keyframes[keyframes.len() - 1];
+        (pan, tilt)
+    }
+}
+
+impl FromStr for Gesture {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nod" => Ok(Self::Nod),
+            "shake" => Ok(Self::Shake),
+            "look" => Ok(Self::Look),
+            "droop" => Ok(Self::Droop),
+            "perk" => Ok(Self::Perk),
+            _ => Err(format!("unknown gesture `{s}`")),
+        }
+    }
+}
+
+/// Outputs set together by one line of the table.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Expression {
+    /// LED animation.
+    pub led: Option<Animation>,
+    /// Name of the sound to play once.
+    pub sound: Option<String>,
+    /// Head gesture.
+    pub gesture: Option<Gesture>,
+    /// Name of the face to draw.
+    pub face: Option<String>,
+    /// How long a reaction lasts.
+    pub hold: Option<Duration>,
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    /// Parses comma-separated outputs, such as `led solid red, face sad`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut expression = Self::default();
+
+        for output in s.split(',') {
+            let output = output.trim();
+            let (kind, value) = output
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("invalid output `{output}`"))?;
+            let value = value.trim();
+
+            match kind {
+                "led" => expression.led = Some(parse_animation(value)?),
+                "sound" => expression.sound = Some(value.to_owned()),
+                "gesture" => expression.gesture = Some(value.parse()?),
+                "face" => expression.face = Some(value.to_owned()),
+                "hold" => {
+                    let seconds = value
+                        .strip_suffix('s')
+                        .and_then(|seconds| seconds.parse().ok())
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or_else(|| format!("invalid hold `{value}`"))?;
+                    expression.hold = Some(seconds);
+                }
+                _ => return Err(format!("unknown output `{kind}`")),
+            }
+        }
+
+        Ok(expression)
+    }
+}
+
+/// Mapping from states and events to expressions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExpressionTable {
+    events: Vec<(String, Expression)>,
+    states: Vec<(String, Expression)>,
+}
+
+impl ExpressionTable {
+    /// Creates a new, empty `ExpressionTable`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows `expression` while the robot is in `state`.
+    #[must_use]
+    pub fn with_state(mut self, state: &str, expression: Expression) -> Self {
+        set(&mut self.states, state, expression);
+        self
+    }
+
+    /// Reacts to events named `event` with `expression`.
+    #[must_use]
+    pub fn with_event(mut self, event: &str, expression: Expression) -> Self {
+        set(&mut self.events, event, expression);
+        self
+    }
+
+    fn state(&self, state: &str) -> Option<&Expression> {
+        lookup(&self.states, state)
+    }
+
+    fn event(&self, event: &str) -> Option<&Expression> {
+        lookup(&self.events, event)
+    }
+}
+
+impl FromStr for ExpressionTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut table = Self::new();
+
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {message}", index + 1);
+            let (target, expression) = line.split_once('=').ok_or_else(|| error("expected `=`"))?;
+            let expression = expression
+                .parse()
+                .map_err(|message: String| error(&message))?;
+
+            match target.split_whitespace().collect::<Vec<_>>()[..] {
+                ["state", name] => table = table.with_state(name, expression),
+                ["event", name] => table = table.with_event(name, expression),
+                _ => return Err(error("expected `state NAME` or `event NAME`")),
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+/// What to show at one tick.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    /// LED animation and the time since it started, for
+    /// [`LedStrip::render`](crate::devices::led::LedStrip::render).
+    pub led: Option<(Animation, Duration)>,
+    /// Sound to start now, given only on the tick a reaction starts.
+    pub sound: Option<String>,
+    /// Pan and tilt to point the head at, in radians.
+    pub head: Option<(f64, f64)>,
+    /// Face to draw.
+    pub face: Option<String>,
+}
+
+/// Turns the robot’s state and events into what it shows.
+#[derive(Debug)]
+pub struct Personality {
+    reaction: Option<(Expression, Instant, bool)>,
+    state: Option<(String, Instant)>,
+    table: ExpressionTable,
+}
+
+impl Personality {
+    /// Creates a new `Personality` expressing itself by `table`.
+    pub fn new(table: ExpressionTable) -> Self {
+        Self {
+            reaction: None,
+            state: None,
+            table,
+        }
+    }
+
+    /// Sets the robot’s state at `now`, restarting its expression if it
+    /// changed.
+    pub fn set_state(&mut self, state: &str, now: Instant) {
+        if self
+            .state
+            .as_ref()
+            .is_none_or(|(current, _)| current != state)
+        {
+            self.state = Some((state.to_owned(), now));
+        }
+    }
+
+    /// Reacts to `event` at `now`, replacing any reaction in progress, and
+    /// returns whether the table has a reaction for it.
+    pub fn react(&mut self, event: &Event, now: Instant) -> bool {
+        match self.table.event(event_name(event.kind())) {
+            Some(expression) => {
+                self.reaction = Some((expression.clone(), now, false));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns what to show at `now`.
+    pub fn poll(&mut self, now: Instant) -> Frame {
+        let mut frame = Frame::default();
+
+        if let Some((state, since)) = &self.state {
+            if let Some(expression) = self.table.state(state) {
+                apply(
+                    &mut frame,
+                    expression,
+                    now.saturating_duration_since(*since),
+                );
+            }
+        }
+
+        if let Some((expression, since, started)) = &mut self.reaction {
+            let elapsed = now.saturating_duration_since(*since);
+
+            if elapsed < expression.hold.unwrap_or(DEFAULT_HOLD) {
+                apply(&mut frame, expression, elapsed);
+
+                if !*started {
+                    *started = true;
+                    frame.sound.clone_from(&expression.sound);
+                }
+            } else {
+                self.reaction = None;
+            }
+        }
+
+        frame
+    }
+}
+
+fn apply(frame: &mut Frame, expression: &Expression, elapsed: Duration) {
+    if let Some(led) = expression.led {
+        frame.led = Some((led, elapsed));
+    }
+
+    if let Some(gesture) = expression.gesture {
+        frame.head = Some(gesture.pose_at(elapsed));
+    }
+
+    if let Some(face) = &expression.face {
+        frame.face = Some(face.clone());
+    }
+}
+
+fn set(expressions: &mut Vec<(String, Expression)>, name: &str, expression: Expression) {
+    match expressions
+        .iter_mut()
+        .find(|(existing, _)| existing == name)
+    {
+        Some((_, existing)) => *existing = expression,
+        None => expressions.push((name.to_owned(), expression)),
+    }
+}
+
+fn lookup<'a>(expressions: &'a [(String, Expression)], name: &str) -> Option<&'a Expression> {
+    expressions
+        .iter()
+        .find(|(existing, _)| existing == name)
+        .map(|(_, expression)| expression)
+}
+
+/// Returns the name of events of `kind` in the table.
+fn event_name(kind: EventKind) -> &'static str {
+    match kind {
+        EventKind::Fault => "fault",
+        EventKind::EmergencyStop => "emergency_stop",
+        EventKind::LowBattery => "low_battery",
+        EventKind::DockDetected => "dock_detected",
+        EventKind::TagSeen => "tag_seen",
+        EventKind::TractionChanged => "traction_changed",
+        EventKind::PowerModeChanged => "power_mode_changed",
+        EventKind::MotionDetected => "motion_detected",
+        EventKind::DeviceChanged => "device_changed",
+        EventKind::Brownout => "brownout",
+        EventKind::Command => "command",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::led::Rgb;
+    use crate::events::{LowBattery, MotionDetected};
+
+    const TABLE: &str = "
+        state idle = led breathe cyan 3s, face sleepy
+        event low_battery = sound sad, gesture droop, face worried, hold 4s
+        event motion_detected = gesture look, face surprised
+    ";
+
+    #[test]
+    fn it_should_parse_a_table() {
+        let table: ExpressionTable = TABLE.parse().unwrap();
+        assert_eq!(
+            table.event("low_battery"),
+            Some(&Expression {
+                led: None,
+                sound: Some("sad".to_owned()),
+                gesture: Some(Gesture::Droop),
+                face: Some("worried".to_owned()),
+                hold: Some(Duration::from_secs(4)),
+            })
+        );
+        assert_eq!(
+            "event fault = gesture wiggle".parse::<ExpressionTable>(),
+            Err("line 1: unknown gesture `wiggle`".to_owned())
+        );
+        assert_eq!(
+            "mood happy = face smile".parse::<ExpressionTable>(),
+            Err("line 1: expected `state NAME` or `event NAME`".to_owned())
+        );
+    }
+
+    #[test]
+    fn it_should_interpolate_gestures_and_hold_the_last_keyframe() {
+        let (pan, tilt) = Gesture::Nod.pose_at(Duration::from_millis(100));
+        assert_eq!(pan, 0.0);
+        assert!((tilt + 0.15).abs() < 1e-9);
+        assert_eq!(Gesture::Droop.pose_at(Duration::from_secs(5)), (0.0, -0.5));
+    }
+
+    #[test]
+    fn it_should_layer_reactions_over_the_state() {
+        let mut personality = Personality::new(TABLE.parse().unwrap());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        personality.set_state("idle", start);
+        let breathe = Animation::Breathe {
+            color: Rgb::CYAN,
+            period: Duration::from_secs(3),
+        };
+        assert_eq!(
+            personality.poll(at(100)),
+            Frame {
+                led: Some((breathe, Duration::from_millis(100))),
+                face: Some("sleepy".to_owned()),
+                ..Frame::default()
+            }
+        );
+
+        let low = Event::LowBattery(LowBattery {
+            voltage: 6.8,
+            state_of_charge: None,
+        });
+        assert!(personality.react(&low, at(1000)));
+        let frame = personality.poll(at(1000));
+        assert_eq!(frame.sound.as_deref(), Some("sad"));
+        assert_eq!(frame.face.as_deref(), Some("worried"));
+        assert_eq!(frame.led, Some((breathe, Duration::from_secs(1))));
+        // The sound starts once, and the reaction ends after its hold.
+        assert_eq!(personality.poll(at(2000)).sound, None);
+        assert_eq!(personality.poll(at(4999)).head, Some((0.0, -0.5)));
+        assert_eq!(personality.poll(at(5000)).face.as_deref(), Some("sleepy"));
+
+        let motion = Event::MotionDetected(MotionDetected {
+            sensor: "pir".to_owned(),
+        });
+        assert!(personality.react(&motion, at(6000)));
+        assert_eq!(personality.poll(at(8000)).head, None);
+    }
+}
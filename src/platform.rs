@@ -0,0 +1,8 @@
+//! Features specific to running the robot on Raspberry Pi OS.
+
+pub mod hotplug;
+pub mod paths;
+pub mod permissions;
+pub mod setup;
+pub mod storage;
+pub mod timesync;
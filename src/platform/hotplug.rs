@@ -0,0 +1,289 @@
+//! Detection of USB peripherals being attached and detached.
+//!
+//! A cable wiggled loose drops a serial adapter, gamepad, or camera, and it
+//! comes back under a new device node. The kernel announces both over a
+//! netlink socket as uevents, which [`HotplugWatcher`] receives and
+//! publishes as [`DeviceChanged`] events, so the drivers that own each class
+//! of device can reopen it instead of failing until restarted.
+
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void};
+use std::fs::File;
+use std::io::{Error, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::thread::{self, JoinHandle};
+
+use crate::events::{DeviceChanged, DeviceClass, Event, EventBus};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "hotplug";
+
+/// Netlink address family.
+const AF_NETLINK: c_int = 16;
+
+/// Datagram socket type.
+const SOCK_DGRAM: c_int = 2;
+
+/// Flag for `socket` that closes the socket across `exec`.
+const SOCK_CLOEXEC: c_int = 0o2_000_000;
+
+/// Netlink protocol on which the kernel broadcasts uevents.
+const NETLINK_KOBJECT_UEVENT: c_int = 15;
+
+/// Multicast group of uevents sent by the kernel, rather than by udev.
+const KERNEL_GROUP: u32 = 1;
+
+/// Largest uevent the kernel sends, in bytes.
+const UEVENT_BUFFER_LEN: usize = 8192;
+
+/// Netlink socket address.
+#[repr(C)]
+struct SockaddrNl {
+    nl_family: u16,
+    nl_pad: u16,
+    nl_pid: u32,
+    nl_groups: u32,
+}
+
+extern "C" {
+    /// Creates a socket in `domain` of `ty` using `protocol`.
+    ///
+    /// Returns a file descriptor on success, or -1 on failure and sets
+    /// `errno` to indicate the error.
+    fn socket(domain: c_int, ty: c_int, protocol: c_int) -> c_int;
+
+    /// Binds the socket `sockfd` to the address at `addr`, `addrlen` bytes
+    /// long.
+    ///
+    /// Returns 0 on success, or -1 on failure and sets `errno` to indicate
+    /// the error.
+    fn bind(sockfd: c_int, addr: *const c_void, addrlen: u32) -> c_int;
+}
+
+/// A uevent from the kernel.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Uevent {
+    /// What happened, such as `add` or `remove`.
+    pub action: String,
+    /// Path of the device in sysfs, below `/sys`.
+    pub devpath: String,
+    /// Every property, including the action and path.
+    pub properties: HashMap<String, String>,
+}
+
+impl Uevent {
+    /// Returns the uevent in `message`, as sent by the kernel.
+    #[must_use]
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let mut fields = message
+            .split(|&byte| byte == 0)
+            .map(String::from_utf8_lossy);
+        // The first field summarizes the event as `action@devpath`.
+        let header = fields.next()?;
+        header.split_once('@')?;
+        let properties: HashMap<_, _> = fields
+            .filter_map(|field| {
+                let (key, value) = field.split_once('=')?;
+                Some((key.to_owned(), value.to_owned()))
+            })
+            .collect();
+        Some(Self {
+            action: properties.get("ACTION")?.clone(),
+            devpath: properties.get("DEVPATH")?.clone(),
+            properties,
+        })
+    }
+
+    /// Returns the property named `key`.
+    #[must_use]
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    /// Returns the change to a USB peripheral the uevent announces, if any.
+    #[must_use]
+    pub fn device_change(&self) -> Option<DeviceChanged> {
+        let attached = match self.action.as_str() {
+            "add" => true,
+            "remove" => false,
+            _ => return None,
+        };
+
+        if !self.devpath.contains("/usb") {
+            return None;
+        }
+
+        let node = self.property("DEVNAME")?;
+        let class = match self.property("SUBSYSTEM")? {
+            "tty" => DeviceClass::Serial,
+            "input" if node.starts_with("input/js") || node.starts_with("input/event") => {
+                DeviceClass::Input
+            }
+            "video4linux" => DeviceClass::Camera,
+            _ => return None,
+        };
+        Some(DeviceChanged {
+            class,
+            node: node.to_owned(),
+            attached,
+        })
+    }
+}
+
+/// Receives uevents for USB peripherals and publishes them on the bus.
+#[derive(Debug)]
+pub struct HotplugWatcher {
+    bus: EventBus,
+    socket: File,
+}
+
+impl HotplugWatcher {
+    /// Creates a new `HotplugWatcher` publishing to `bus`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the netlink socket cannot be
+    /// opened.
+    pub fn new(bus: EventBus) -> Result<Self, Error> {
+        let fd = unsafe {
+            socket(
+                AF_NETLINK,
+                SOCK_DGRAM | SOCK_CLOEXEC,
+                NETLINK_KOBJECT_UEVENT,
+            )
+        };
+
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let address = SockaddrNl {
+            nl_family: AF_NETLINK as u16,
+            nl_pad: 0,
+            nl_pid: 0,
+            nl_groups: KERNEL_GROUP,
+        };
+        let result = unsafe {
+            bind(
+                fd.as_raw_fd(),
+                (&address as *const SockaddrNl).cast(),
+                std::mem::size_of::<SockaddrNl>() as u32,
+            )
+        };
+
+        if result == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(Self {
+            bus,
+            socket: File::from(fd),
+        })
+    }
+
+    /// Waits for the next change to a USB peripheral, publishes it, and
+    /// returns it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the socket cannot be read.
+    pub fn next_change(&mut self) -> Result<DeviceChanged, Error> {
+        let mut buffer = vec![0; UEVENT_BUFFER_LEN];
+
+        loop {
+            let len = self.socket.read(&mut buffer)?;
+            let Some(change) =
+                Uevent::parse(&buffer[..len]).and_then(|uevent| uevent.device_change())
+            else {
+                continue;
+            };
+            log_event!(
+                SUBSYSTEM,
+                Level::Info,
+                "{:?} device /dev/{} {}",
+                change.class,
+                change.node,
+                if change.attached {
+                    "attached"
+                } else {
+                    "detached"
+                }
+            );
+            self.bus.publish(Event::DeviceChanged(change.clone()));
+            return Ok(change);
+        }
+    }
+
+    /// Watches for changes on a new thread until the socket fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the thread cannot be spawned.
+    pub fn spawn(mut self) -> Result<JoinHandle<()>, Error> {
+        thread::Builder::new()
+            .name(SUBSYSTEM.to_owned())
+            .spawn(move || loop {
+                if let Err(error) = self.next_change() {
+                    log_event!(SUBSYSTEM, Level::Error, "stopped watching: {error}");
+                    return;
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(action: &str, devpath: &str, subsystem: &str, devname: &str) -> Vec<u8> {
+        format!(
+            "{action}@{devpath}\0ACTION={action}\0DEVPATH={devpath}\0\
+             SUBSYSTEM={subsystem}\0DEVNAME={devname}\0SEQNUM=1234\0"
+        )
+        .into_bytes()
+    }
+
+    const USB_PATH: &str = "/devices/platform/scb/fd500000.pcie/pci0000:00/0000:01:00.0/usb1/1-1";
+
+    #[test]
+    fn it_should_parse_a_kernel_uevent() {
+        let uevent = Uevent::parse(&message("add", USB_PATH, "tty", "ttyUSB0")).unwrap();
+        assert_eq!(uevent.action, "add");
+        assert_eq!(uevent.devpath, USB_PATH);
+        assert_eq!(uevent.property("SEQNUM"), Some("1234"));
+        // udev rebroadcasts events with a binary header, which is skipped.
+        assert_eq!(Uevent::parse(b"libudev\0\xfe\xed"), None);
+    }
+
+    #[test]
+    fn it_should_recognize_usb_peripherals() {
+        let change = |action, devpath: &str, subsystem, devname| {
+            Uevent::parse(&message(action, devpath, subsystem, devname))
+                .and_then(|uevent| uevent.device_change())
+        };
+        assert_eq!(
+            change("add", USB_PATH, "tty", "ttyUSB0"),
+            Some(DeviceChanged {
+                class: DeviceClass::Serial,
+                node: "ttyUSB0".to_owned(),
+                attached: true
+            })
+        );
+        assert_eq!(
+            change("remove", USB_PATH, "input", "input/js0").map(|change| change.class),
+            Some(DeviceClass::Input)
+        );
+        assert_eq!(
+            change("add", USB_PATH, "video4linux", "video0").map(|change| change.class),
+            Some(DeviceClass::Camera)
+        );
+        assert_eq!(change("change", USB_PATH, "tty", "ttyUSB0"), None);
+        assert_eq!(
+            change("add", "/devices/virtual/tty/tty1", "tty", "tty1"),
+            None
+        );
+        assert_eq!(change("add", USB_PATH, "input", "input/mouse0"), None);
+    }
+}
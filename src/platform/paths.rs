@@ -0,0 +1,198 @@
+//! Where the robot keeps the state it writes.
+//!
+//! Logs, calibration, maps, and parameters all live under one data directory,
+//! so the root filesystem can be mounted read-only, such as from an overlayfs
+//! image that survives power cuts, with only the data directory on a
+//! writable partition. If the data directory turns out to be read-only too,
+//! state is kept in a volatile directory under `/run` instead, so the robot
+//! still runs, forgetting its changes at the next boot.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "paths";
+
+/// Data directory used unless configured otherwise.
+pub const DEFAULT_DATA_DIR: &str = "/var/lib/otter-pi";
+
+/// Directory, normally on a tmpfs, used when the data directory is
+/// read-only.
+pub const DEFAULT_FALLBACK_DIR: &str = "/run/otter-pi";
+
+/// Environment variable that overrides the data directory.
+pub const DATA_DIR_VAR: &str = "OTTER_PI_DATA_DIR";
+
+/// A kind of state the robot writes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Category {
+    /// Telemetry and black-box logs, and recorded runs.
+    Logs,
+    /// Sensor and actuator calibration.
+    Calibration,
+    /// Maps of the places the robot has been.
+    Maps,
+    /// Tuned parameters.
+    Params,
+}
+
+impl Category {
+    /// Every category.
+    pub const ALL: [Self; 4] = [Self::Logs, Self::Calibration, Self::Maps, Self::Params];
+
+    /// Returns the name of the category’s directory.
+    #[must_use]
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            Self::Logs => "logs",
+            Self::Calibration => "calibration",
+            Self::Maps => "maps",
+            Self::Params => "params",
+        }
+    }
+}
+
+/// The directories the robot writes its state to.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Paths {
+    data_dir: PathBuf,
+    fallback_dir: PathBuf,
+    volatile: bool,
+}
+
+impl Paths {
+    /// Creates a new `Paths` under `data_dir`.
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+            fallback_dir: PathBuf::from(DEFAULT_FALLBACK_DIR),
+            volatile: false,
+        }
+    }
+
+    /// Creates a new `Paths` under the directory named by
+    /// [`DATA_DIR_VAR`], or [`DEFAULT_DATA_DIR`] if it is not set.
+    pub fn from_env() -> Self {
+        Self::new(env::var_os(DATA_DIR_VAR).unwrap_or_else(|| DEFAULT_DATA_DIR.into()))
+    }
+
+    /// Sets the directory used when the data directory is read-only.
+    #[must_use]
+    pub fn with_fallback_dir(mut self, fallback_dir: impl Into<PathBuf>) -> Self {
+        self.fallback_dir = fallback_dir.into();
+        self
+    }
+
+    /// Returns the directory state is written under.
+    #[must_use]
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Returns whether state is being kept in the fallback directory, and
+    /// will be lost at the next boot.
+    #[must_use]
+    pub fn is_volatile(&self) -> bool {
+        self.volatile
+    }
+
+    /// Returns the directory for `category`.
+    #[must_use]
+    pub fn dir(&self, category: Category) -> PathBuf {
+        self.data_dir.join(category.dir_name())
+    }
+
+    /// Creates the directory of every category, moving to the fallback
+    /// directory if the data directory is read-only.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if neither directory is writable.
+    pub fn prepare(mut self) -> Result<Self, Error> {
+        match self.create_dirs() {
+            Err(error) if is_read_only(&error) && !self.volatile => {
+                log_event!(
+                    SUBSYSTEM,
+                    Level::Warn,
+                    "{} is read-only, keeping state in {} until reboot",
+                    self.data_dir.display(),
+                    self.fallback_dir.display()
+                );
+                self.data_dir.clone_from(&self.fallback_dir);
+                self.volatile = true;
+                self.create_dirs()?;
+                Ok(self)
+            }
+            result => result.map(|()| self),
+        }
+    }
+
+    fn create_dirs(&self) -> Result<(), Error> {
+        for category in Category::ALL {
+            fs::create_dir_all(self.dir(category))?;
+        }
+
+        // Directories can exist on a filesystem that has since been
+        // remounted read-only, so prove that it can be written.
+        let probe = self.data_dir.join(".writable");
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&probe)?;
+        fs::remove_file(probe)
+    }
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self::new(DEFAULT_DATA_DIR)
+    }
+}
+
+fn is_read_only(error: &Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::ReadOnlyFilesystem | ErrorKind::PermissionDenied
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::TemporaryDirectory;
+
+    #[test]
+    fn it_should_create_a_directory_for_every_category() {
+        let root = TemporaryDirectory::new().unwrap();
+        let paths = Paths::new(root.path().join("data")).prepare().unwrap();
+        assert!(!paths.is_volatile());
+
+        for category in Category::ALL {
+            assert!(paths.dir(category).is_dir());
+        }
+
+        assert_eq!(
+            paths.dir(Category::Maps),
+            root.path().join("data").join("maps")
+        );
+    }
+
+    #[test]
+    fn it_should_fail_when_the_data_directory_cannot_be_created() {
+        let root = TemporaryDirectory::new().unwrap();
+        fs::write(root.path().join("file"), "").unwrap();
+        assert!(Paths::new(root.path().join("file")).prepare().is_err());
+    }
+
+    #[test]
+    fn it_should_treat_read_only_filesystems_as_recoverable() {
+        const EROFS: i32 = 30;
+        assert!(is_read_only(&Error::from_raw_os_error(EROFS)));
+        assert!(!is_read_only(&Error::from(ErrorKind::NotFound)));
+    }
+}
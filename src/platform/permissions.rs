@@ -0,0 +1,459 @@
+//! Preflight checks for the privileges needed to access robot hardware.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, CString};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+extern "C" {
+    /// Checks whether the calling process can access the file at `pathname`
+    /// using its real user and group IDs.
+    ///
+    /// Returns 0 if all requested permissions are granted, or -1 otherwise and
+    /// sets `errno` to indicate the error.
+    fn access(pathname: *const c_char, mode: c_int) -> c_int;
+
+    /// Returns the effective group ID of the calling process.
+    fn getegid() -> u32;
+
+    /// Writes up to `size` supplementary group IDs of the calling process into
+    /// `list`, or only returns their number when `size` is 0.
+    ///
+    /// Returns the number of group IDs on success, or -1 on failure and sets
+    /// `errno` to indicate the error.
+    fn getgroups(size: c_int, list: *mut u32) -> c_int;
+
+    /// Returns the effective user ID of the calling process.
+    fn geteuid() -> u32;
+}
+
+/// Mode for `access` that checks for read permission.
+const R_OK: c_int = 4;
+
+/// Mode for `access` that checks for write permission.
+const W_OK: c_int = 2;
+
+/// Groups that grant access to the hardware interfaces used by the robot.
+pub const HARDWARE_GROUPS: [&str; 5] = ["gpio", "i2c", "spi", "dialout", "video"];
+
+/// Checks group membership and device node access for the current process.
+///
+/// # Examples
+///
+/// ```no_run
+/// use otter_pi::platform::permissions::PermissionChecker;
+///
+/// let report = PermissionChecker::new()
+///     .require_group("gpio")
+///     .require_device("/dev/gpiochip0")
+///     .check()
+///     .unwrap();
+///
+/// if !report.is_ok() {
+///     eprintln!("{report}");
+/// }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PermissionChecker<'a> {
+    devices: Vec<PathBuf>,
+    groups: Vec<String>,
+    root_dir: &'a Path,
+}
+
+impl<'a> PermissionChecker<'a> {
+    /// Creates a new `PermissionChecker` without any requirements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `PermissionChecker` with a non-standard root directory.
+    ///
+    /// The group database and device nodes are resolved relative to
+    /// `root_dir`.
+    pub fn with_root_dir(root_dir: &'a Path) -> Self {
+        Self {
+            root_dir,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new `PermissionChecker` requiring the standard hardware
+    /// groups and the device nodes of the Raspberry Pi’s hardware interfaces.
+    pub fn raspberry_pi() -> Self {
+        let checker = HARDWARE_GROUPS
+            .iter()
+            .fold(Self::new(), |checker, group| checker.require_group(*group));
+
+        [
+            "/dev/gpiochip0",
+            "/dev/i2c-1",
+            "/dev/spidev0.0",
+            "/dev/video0",
+        ]
+        .iter()
+        .fold(checker, |checker, device| checker.require_device(*device))
+    }
+
+    /// Requires the process to be a member of `group`.
+    #[must_use]
+    pub fn require_group(mut self, group: impl Into<String>) -> Self {
+        self.groups.push(group.into());
+        self
+    }
+
+    /// Requires the process to be able to read and write the device node at
+    /// `path`.
+    #[must_use]
+    pub fn require_device(mut self, path: impl Into<PathBuf>) -> Self {
+        self.devices.push(path.into());
+        self
+    }
+
+    /// Checks every requirement and reports the outcome of each.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the group database cannot be
+    /// read or the process’s groups cannot be determined.
+    pub fn check(&self) -> Result<PermissionReport, Error> {
+        let group_ids = self.read_group_ids()?;
+        let is_root = unsafe { geteuid() } == 0;
+        let process_groups = process_group_ids()?;
+
+        let groups = self
+            .groups
+            .iter()
+            .map(|name| {
+                let status = match group_ids.get(name) {
+                    None => GroupStatus::Undefined,
+                    Some(_) if is_root => GroupStatus::Member,
+                    Some(gid) if process_groups.contains(gid) => GroupStatus::Member,
+                    Some(_) => GroupStatus::NotMember,
+                };
+
+                GroupCheck {
+                    name: name.clone(),
+                    status,
+                }
+            })
+            .collect();
+
+        let devices = self
+            .devices
+            .iter()
+            .map(|path| DeviceCheck {
+                path: path.clone(),
+                status: self.check_device(path),
+            })
+            .collect();
+
+        Ok(PermissionReport { devices, groups })
+    }
+
+    fn check_device(&self, path: &Path) -> DeviceStatus {
+        let resolved = self.resolve_path(path);
+        let Ok(c_path) = CString::new(resolved.as_os_str().as_bytes()) else {
+            return DeviceStatus::Missing;
+        };
+
+        if unsafe { access(c_path.as_ptr(), R_OK | W_OK) } == 0 {
+            return DeviceStatus::Accessible;
+        }
+
+        match Error::last_os_error().kind() {
+            ErrorKind::NotFound => DeviceStatus::Missing,
+            _ => DeviceStatus::Denied,
+        }
+    }
+
+    fn read_group_ids(&self) -> Result<HashMap<String, u32>, Error> {
+        let contents = fs::read_to_string(self.resolve_path(Path::new("/etc/group")))?;
+        Ok(parse_group_file(&contents))
+    }
+
+    fn resolve_path(&self, path: &Path) -> PathBuf {
+        self.root_dir.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
+
+impl<'a> Default for PermissionChecker<'a> {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            groups: Vec::new(),
+            root_dir: Path::new("/"),
+        }
+    }
+}
+
+/// Outcome of a group membership check.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GroupStatus {
+    /// The process is a member of the group, or is running as root.
+    Member,
+    /// The group exists but the process is not a member of it.
+    NotMember,
+    /// The group does not exist on the system.
+    Undefined,
+}
+
+/// Outcome of a device node access check.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DeviceStatus {
+    /// The device node can be read and written.
+    Accessible,
+    /// The device node exists but cannot be read and written.
+    Denied,
+    /// The device node does not exist.
+    Missing,
+}
+
+/// Result of checking a single group requirement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GroupCheck {
+    /// Name of the required group.
+    pub name: String,
+    /// Outcome of the check.
+    pub status: GroupStatus,
+}
+
+/// Result of checking a single device requirement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceCheck {
+    /// Path to the required device node.
+    pub path: PathBuf,
+    /// Outcome of the check.
+    pub status: DeviceStatus,
+}
+
+/// Readable summary of every permission requirement that was checked.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PermissionReport {
+    /// Outcomes of the device node checks.
+    pub devices: Vec<DeviceCheck>,
+    /// Outcomes of the group membership checks.
+    pub groups: Vec<GroupCheck>,
+}
+
+impl PermissionReport {
+    /// Returns `true` if every requirement is satisfied.
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.groups
+            .iter()
+            .all(|check| check.status == GroupStatus::Member)
+            && self
+                .devices
+                .iter()
+                .all(|check| check.status == DeviceStatus::Accessible)
+    }
+}
+
+impl Display for PermissionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for check in &self.groups {
+            match check.status {
+                GroupStatus::Member => writeln!(f, "group {}: ok", check.name)?,
+                GroupStatus::NotMember => writeln!(
+                    f,
+                    "group {0}: not a member (run `sudo usermod -aG {0} $USER` and log in again)",
+                    check.name
+                )?,
+                GroupStatus::Undefined => {
+                    writeln!(f, "group {}: does not exist on this system", check.name)?;
+                }
+            }
+        }
+
+        for check in &self.devices {
+            let status = match check.status {
+                DeviceStatus::Accessible => "ok",
+                DeviceStatus::Denied => "permission denied (check udev rules)",
+                DeviceStatus::Missing => "not found (is the interface enabled?)",
+            };
+
+            writeln!(f, "device {}: {status}", check.path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_group_file(contents: &str) -> HashMap<String, u32> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let gid = fields.nth(1)?.parse().ok()?;
+            Some((name.to_owned(), gid))
+        })
+        .collect()
+}
+
+fn process_group_ids() -> Result<Vec<u32>, Error> {
+    let count = unsafe { getgroups(0, std::ptr::null_mut()) };
+
+    if count == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut groups = vec![0; usize::try_from(count).unwrap_or_default()];
+    let count = unsafe { getgroups(count, groups.as_mut_ptr()) };
+
+    if count == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    groups.truncate(usize::try_from(count).unwrap_or_default());
+    groups.push(unsafe { getegid() });
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_create_a_default_checker() {
+        assert_eq!(PermissionChecker::new(), PermissionChecker::default());
+    }
+
+    #[test]
+    fn it_should_require_the_hardware_groups_on_a_raspberry_pi() {
+        let checker = PermissionChecker::raspberry_pi();
+        assert_eq!(checker.groups, HARDWARE_GROUPS);
+    }
+
+    #[test]
+    fn it_should_report_a_group_that_does_not_exist() {
+        let root_dir = mock_root_dir();
+        let report = PermissionChecker::with_root_dir(root_dir.path())
+            .require_group("spi")
+            .check()
+            .unwrap();
+        assert_eq!(report.groups[0].status, GroupStatus::Undefined);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn it_should_report_membership_of_the_process_group() {
+        let root_dir = mock_root_dir();
+        let report = PermissionChecker::with_root_dir(root_dir.path())
+            .require_group("self")
+            .check()
+            .unwrap();
+        assert_eq!(report.groups[0].status, GroupStatus::Member);
+    }
+
+    #[test]
+    fn it_should_report_a_group_the_process_is_not_a_member_of_unless_running_as_root() {
+        let root_dir = mock_root_dir();
+        let report = PermissionChecker::with_root_dir(root_dir.path())
+            .require_group("gpio")
+            .check()
+            .unwrap();
+        let expected = if unsafe { geteuid() } == 0 {
+            GroupStatus::Member
+        } else {
+            GroupStatus::NotMember
+        };
+        assert_eq!(report.groups[0].status, expected);
+    }
+
+    #[test]
+    fn it_should_report_a_missing_device() {
+        let root_dir = mock_root_dir();
+        let report = PermissionChecker::with_root_dir(root_dir.path())
+            .require_device("/dev/i2c-1")
+            .check()
+            .unwrap();
+        assert_eq!(report.devices[0].status, DeviceStatus::Missing);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn it_should_report_an_accessible_device() {
+        let root_dir = mock_root_dir();
+        let report = PermissionChecker::with_root_dir(root_dir.path())
+            .require_device("/dev/gpiochip0")
+            .check()
+            .unwrap();
+        assert_eq!(report.devices[0].status, DeviceStatus::Accessible);
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn it_should_report_an_inaccessible_device_unless_running_as_root() {
+        let root_dir = mock_root_dir();
+        let path = root_dir.path().join("dev/gpiochip0");
+        fs::set_permissions(path, fs::Permissions::from_mode(0o000)).unwrap();
+        let report = PermissionChecker::with_root_dir(root_dir.path())
+            .require_device("/dev/gpiochip0")
+            .check()
+            .unwrap();
+        let expected = if unsafe { geteuid() } == 0 {
+            DeviceStatus::Accessible
+        } else {
+            DeviceStatus::Denied
+        };
+        assert_eq!(report.devices[0].status, expected);
+    }
+
+    #[test]
+    fn it_should_return_an_error_when_the_group_database_cannot_be_read() {
+        let root_dir = TemporaryDirectory::new().unwrap();
+        assert!(PermissionChecker::with_root_dir(root_dir.path())
+            .check()
+            .is_err());
+    }
+
+    #[test]
+    fn it_should_describe_each_requirement_in_the_report() {
+        let report = PermissionReport {
+            devices: vec![DeviceCheck {
+                path: PathBuf::from("/dev/i2c-1"),
+                status: DeviceStatus::Missing,
+            }],
+            groups: vec![GroupCheck {
+                name: String::from("gpio"),
+                status: GroupStatus::NotMember,
+            }],
+        };
+        assert_eq!(
+            report.to_string(),
+            "group gpio: not a member (run `sudo usermod -aG gpio $USER` and log in again)\n\
+             device /dev/i2c-1: not found (is the interface enabled?)\n"
+        );
+    }
+
+    #[test]
+    fn it_should_parse_names_and_ids_from_the_group_database() {
+        let groups = parse_group_file("gpio:x:997:pi\ni2c:x:998:\nbroken\n");
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups.get("gpio"), Some(&997));
+        assert_eq!(groups.get("i2c"), Some(&998));
+    }
+
+    fn mock_root_dir() -> TemporaryDirectory {
+        let root_dir = TemporaryDirectory::new().unwrap();
+        let etc_dir = root_dir.path().join("etc");
+        fs::create_dir_all(&etc_dir).expect("parent directory should be writable");
+        let gid = unsafe { getegid() };
+        fs::write(
+            etc_dir.join("group"),
+            format!("self:x:{gid}:\ngpio:x:65000:\n"),
+        )
+        .expect("parent directory should exist and be writable");
+        let dev_dir = root_dir.path().join("dev");
+        fs::create_dir_all(&dev_dir).expect("parent directory should be writable");
+        fs::write(dev_dir.join("gpiochip0"), "").expect("parent directory should be writable");
+        root_dir
+    }
+}
@@ -0,0 +1,205 @@
+//! Provisioning of udev rules and groups for running the robot without root.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::log_event;
+use crate::logging::Level;
+use crate::unix::fsutil;
+
+use super::permissions::HARDWARE_GROUPS;
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "setup";
+
+/// Path to the installed udev rules, relative to the root directory.
+pub const RULES_PATH: &str = "etc/udev/rules.d/99-otter-pi.rules";
+
+/// udev rules granting the hardware groups access to GPIO, PWM, I2C, SPI,
+/// serial, and video devices.
+const UDEV_RULES: &str = r#"# Installed by otter-pi. Grants non-root access to robot hardware.
+SUBSYSTEM=="gpio", KERNEL=="gpiochip*", GROUP="gpio", MODE="0660"
+SUBSYSTEM=="pwm*", PROGRAM="/bin/sh -c 'chgrp -R gpio /sys/class/pwm /sys/devices/platform/*pwm* && chmod -R g=u /sys/class/pwm /sys/devices/platform/*pwm*'"
+SUBSYSTEM=="i2c-dev", GROUP="i2c", MODE="0660"
+SUBSYSTEM=="spidev", GROUP="spi", MODE="0660"
+SUBSYSTEM=="tty", KERNEL=="tty[A-Z]*[0-9]|ttyAMA[0-9]*|serial[0-9]*", GROUP="dialout", MODE="0660"
+SUBSYSTEM=="video4linux", GROUP="video", MODE="0660"
+"#;
+
+/// Installs the system configuration needed to operate the robot as a
+/// non-root user.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Setup<'a> {
+    root_dir: &'a Path,
+}
+
+impl<'a> Setup<'a> {
+    /// Creates a new `Setup` operating on the running system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `Setup` with a non-standard root directory.
+    pub fn with_root_dir(root_dir: &'a Path) -> Self {
+        Self { root_dir }
+    }
+
+    /// Returns the udev rules that grant access to robot hardware.
+    #[must_use]
+    pub fn udev_rules(&self) -> &'static str {
+        UDEV_RULES
+    }
+
+    /// Returns the path at which the udev rules are installed.
+    #[must_use]
+    pub fn rules_path(&self) -> PathBuf {
+        self.root_dir.join(RULES_PATH)
+    }
+
+    /// Atomically writes the udev rules into the rules directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the rules directory does not
+    /// exist or is not writable.
+    pub fn install_udev_rules(&self) -> Result<(), Error> {
+        let path = self.rules_path();
+        fsutil::write_atomic(&path, UDEV_RULES)?;
+        log_event!(SUBSYSTEM, Level::Info, "installed {}", path.display());
+        Ok(())
+    }
+
+    /// Returns the hardware groups that do not exist on the system.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the group database cannot be
+    /// read.
+    pub fn missing_groups(&self) -> Result<Vec<&'static str>, Error> {
+        let contents = fs::read_to_string(self.root_dir.join("etc/group"))?;
+        let existing: HashSet<_> = contents
+            .lines()
+            .filter_map(|line| line.split(':').next())
+            .collect();
+
+        Ok(HARDWARE_GROUPS
+            .into_iter()
+            .filter(|group| !existing.contains(group))
+            .collect())
+    }
+
+    /// Creates any missing hardware groups and adds `user` to all of them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `groupadd` or `usermod` fail.
+    pub fn provision_user(&self, user: &str) -> Result<(), Error> {
+        for group in self.missing_groups()? {
+            run(Command::new("groupadd").args(["--system", group]))?;
+        }
+
+        run(Command::new("usermod").args(["-aG", &HARDWARE_GROUPS.join(","), user]))
+    }
+
+    /// Reloads the udev rules and re-triggers events for existing devices so
+    /// the new rules apply without a reboot.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `udevadm` fails.
+    pub fn reload_udev(&self) -> Result<(), Error> {
+        run(Command::new("udevadm").args(["control", "--reload-rules"]))?;
+        run(Command::new("udevadm").arg("trigger"))
+    }
+
+    /// Performs every setup step for `user`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any step fails, which usually
+    /// means it was not run as root.
+    pub fn install(&self, user: &str) -> Result<(), Error> {
+        self.install_udev_rules()?;
+        self.provision_user(user)?;
+        self.reload_udev()
+    }
+}
+
+impl<'a> Default for Setup<'a> {
+    fn default() -> Self {
+        Self {
+            root_dir: Path::new("/"),
+        }
+    }
+}
+
+fn run(command: &mut Command) -> Result<(), Error> {
+    log_event!(SUBSYSTEM, Level::Info, "running `{command:?}`");
+    let status = command.status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!("`{command:?}` failed with {status}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_create_a_default_setup() {
+        assert_eq!(Setup::new(), Setup::default());
+    }
+
+    #[test]
+    fn it_should_grant_each_hardware_group_access_in_the_udev_rules() {
+        let rules = Setup::new().udev_rules();
+
+        for group in HARDWARE_GROUPS {
+            assert!(rules.contains(&format!("GROUP=\"{group}\"")));
+        }
+    }
+
+    #[test]
+    fn it_should_install_the_udev_rules() {
+        let root_dir = mock_root_dir();
+        let setup = Setup::with_root_dir(root_dir.path());
+        setup.install_udev_rules().unwrap();
+        assert!(fs::read_to_string(setup.rules_path()).is_ok_and(|rules| rules == UDEV_RULES));
+    }
+
+    #[test]
+    fn it_should_return_an_error_when_the_rules_directory_does_not_exist() {
+        let root_dir = TemporaryDirectory::new().unwrap();
+        assert!(Setup::with_root_dir(root_dir.path())
+            .install_udev_rules()
+            .is_err());
+    }
+
+    #[test]
+    fn it_should_return_the_hardware_groups_that_do_not_exist() {
+        let root_dir = mock_root_dir();
+        let setup = Setup::with_root_dir(root_dir.path());
+        assert!(setup
+            .missing_groups()
+            .is_ok_and(|groups| groups == ["spi", "video"]));
+    }
+
+    fn mock_root_dir() -> TemporaryDirectory {
+        let root_dir = TemporaryDirectory::new().unwrap();
+        fs::create_dir_all(root_dir.path().join("etc/udev/rules.d"))
+            .expect("parent directory should be writable");
+        fs::write(
+            root_dir.path().join("etc/group"),
+            "gpio:x:997:pi\ni2c:x:998:pi\ndialout:x:20:pi\n",
+        )
+        .expect("parent directory should exist and be writable");
+        root_dir
+    }
+}
@@ -0,0 +1,338 @@
+//! Health of the SD card or eMMC the robot runs from.
+//!
+//! SD cards wear out, and a root filesystem that fills up takes everything
+//! down with it. [`StorageMonitor`] reports free space, the errors the
+//! filesystem has counted, and the wear the card estimates for itself where
+//! it reports one, and enters a degraded mode while free space is below a
+//! reserve so that loggers can stop writing before the disk is full.
+
+use std::ffi::{c_char, c_int, c_ulong, CString};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::json::{ToJson, Value};
+use crate::linux::sysfs::Sysfs;
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "storage";
+
+/// Filesystem statistics, as filled in by `statvfs`.
+///
+/// Only the leading fields are read, which are `unsigned long` on Linux on
+/// both 32- and 64-bit targets; the tail is left oversized because its
+/// layout differs between them.
+#[repr(C)]
+struct StatVfs {
+    f_bsize: c_ulong,
+    f_frsize: c_ulong,
+    f_blocks: c_ulong,
+    f_bfree: c_ulong,
+    f_bavail: c_ulong,
+    rest: [c_ulong; 16],
+}
+
+extern "C" {
+    /// Fills in `buf` with statistics about the filesystem containing
+    /// `path`.
+    ///
+    /// Returns 0 on success, or -1 on failure and sets `errno` to indicate
+    /// the error.
+    fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+}
+
+/// Space on a filesystem, in bytes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Usage {
+    /// Size of the filesystem.
+    pub total: u64,
+    /// Space available to unprivileged processes.
+    pub available: u64,
+}
+
+impl Usage {
+    /// Returns the space on the filesystem containing `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the filesystem cannot be
+    /// queried, such as when `path` does not exist.
+    pub fn of(path: &Path) -> Result<Self> {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+        let mut stat: StatVfs = unsafe { std::mem::zeroed() };
+
+        if unsafe { statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let block = stat.f_frsize as u64;
+        Ok(Self {
+            total: stat.f_blocks as u64 * block,
+            available: stat.f_bavail as u64 * block,
+        })
+    }
+}
+
+impl ToJson for Usage {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("total", self.total)
+            .with("available", self.available)
+    }
+}
+
+/// Wear an eMMC device estimates for itself, from its extended CSD.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lifetime {
+    /// Upper bound of the fraction of the rated erase cycles used, which may
+    /// exceed 1 once they are exceeded.
+    pub used: f64,
+    /// Whether the device has started using its reserved blocks heavily,
+    /// which warns that it is close to the end of its life.
+    pub pre_eol_warning: bool,
+}
+
+impl ToJson for Lifetime {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("used", self.used)
+            .with("pre_eol_warning", self.pre_eol_warning)
+    }
+}
+
+/// A report of the health of the storage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StorageReport {
+    /// Space on the monitored filesystem.
+    pub usage: Usage,
+    /// Errors counted by the filesystem since it was created, where it
+    /// counts them.
+    pub errors: Option<u64>,
+    /// Wear of the device, where it reports it.
+    pub lifetime: Option<Lifetime>,
+    /// Whether free space is below the reserve, so logging should stop.
+    pub degraded: bool,
+}
+
+impl ToJson for StorageReport {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("usage", self.usage.to_json())
+            .with("errors", self.errors)
+            .with("lifetime", self.lifetime.map(|lifetime| lifetime.to_json()))
+            .with("degraded", self.degraded)
+    }
+}
+
+/// Monitors the health of the storage holding a filesystem.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StorageMonitor<'a> {
+    degraded: bool,
+    device: Option<String>,
+    partition: Option<String>,
+    path: PathBuf,
+    reserve: u64,
+    sysfs: Sysfs<'a>,
+}
+
+impl<'a> StorageMonitor<'a> {
+    /// Creates a new `StorageMonitor` for the filesystem containing `path`,
+    /// which degrades once less than `reserve` bytes are free.
+    pub fn new(path: impl Into<PathBuf>, reserve: u64) -> Self {
+        Self {
+            degraded: false,
+            device: None,
+            partition: None,
+            path: path.into(),
+            reserve,
+            sysfs: Sysfs::new(),
+        }
+    }
+
+    /// Sets the ext4 partition holding the filesystem, such as `mmcblk0p2`,
+    /// to report its error count.
+    #[must_use]
+    pub fn with_partition(mut self, partition: &str) -> Self {
+        self.partition = Some(partition.to_owned());
+        self
+    }
+
+    /// Sets the block device holding the filesystem, such as `mmcblk0`, to
+    /// report its wear.
+    #[must_use]
+    pub fn with_device(mut self, device: &str) -> Self {
+        self.device = Some(device.to_owned());
+        self
+    }
+
+    /// Accesses the kernel through `sysfs`.
+    #[must_use]
+    pub fn with_sysfs(mut self, sysfs: Sysfs<'a>) -> Self {
+        self.sysfs = sysfs;
+        self
+    }
+
+    /// Returns whether free space is below the reserve, so logging should
+    /// stop.
+    #[must_use]
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Measures the storage and updates the degraded mode.
+    ///
+    /// Degraded mode is left once twice the reserve is free again, so it
+    /// does not flap as logs are written and deleted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the free space cannot be
+    /// measured, or if the error count or wear cannot be read for a reason
+    /// other than the kernel or device not reporting them.
+    pub fn update(&mut self) -> Result<StorageReport> {
+        let usage = Usage::of(&self.path)?;
+        let degraded = if self.degraded {
+            usage.available < self.reserve.saturating_mul(2)
+        } else {
+            usage.available < self.reserve
+        };
+
+        if degraded != self.degraded {
+            let (level, state) = if degraded {
+                (Level::Warn, "low on space, logging should stop")
+            } else {
+                (Level::Info, "space recovered")
+            };
+            log_event!(
+                SUBSYSTEM,
+                level,
+                "{}: {state} with {} bytes free",
+                self.path.display(),
+                usage.available
+            );
+            self.degraded = degraded;
+        }
+
+        Ok(StorageReport {
+            usage,
+            errors: self.errors()?,
+            lifetime: self.lifetime()?,
+            degraded,
+        })
+    }
+
+    fn errors(&self) -> Result<Option<u64>> {
+        let Some(partition) = &self.partition else {
+            return Ok(None);
+        };
+        let Some(contents) = optional(
+            self.sysfs
+                .read_to_string(format!("fs/ext4/{partition}/errors_count")),
+        )?
+        else {
+            return Ok(None);
+        };
+        contents
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error))
+    }
+
+    fn lifetime(&self) -> Result<Option<Lifetime>> {
+        let Some(device) = &self.device else {
+            return Ok(None);
+        };
+        let dir = format!("block/{device}/device");
+        let Some(life_time) = optional(self.sysfs.read_to_string(format!("{dir}/life_time")))?
+        else {
+            return Ok(None);
+        };
+        // Each estimate is in steps of a tenth of the rated life, with 0
+        // when unknown and 0x0B once it is exceeded.
+        let estimates = life_time
+            .split_whitespace()
+            .map(parse_hex)
+            .collect::<Result<Vec<_>>>()?;
+        let used = estimates.into_iter().max().unwrap_or(0);
+        let pre_eol = optional(self.sysfs.read_to_string(format!("{dir}/pre_eol_info")))?
+            .map(|contents| parse_hex(contents.trim()))
+            .transpose()?;
+        Ok(Some(Lifetime {
+            used: f64::from(used) / 10.0,
+            pre_eol_warning: pre_eol.is_some_and(|pre_eol| pre_eol >= 2),
+        }))
+    }
+}
+
+/// Returns `None` for attributes the kernel or device does not provide.
+fn optional(result: Result<String>) -> Result<Option<String>> {
+    match result {
+        Ok(contents) => Ok(Some(contents)),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u8> {
+    u8::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_measure_free_space() {
+        let usage = Usage::of(Path::new("/")).unwrap();
+        assert!(usage.total > 0);
+        assert!(usage.available <= usage.total);
+        assert!(Usage::of(Path::new("/nonexistent")).is_err());
+    }
+
+    #[test]
+    fn it_should_report_errors_and_wear_where_available() {
+        let sysfs_dir = TemporaryDirectory::new().unwrap();
+        let ext4_dir = sysfs_dir.path().join("fs/ext4/mmcblk0p2");
+        let device_dir = sysfs_dir.path().join("block/mmcblk0/device");
+        fs::create_dir_all(&ext4_dir).unwrap();
+        fs::create_dir_all(&device_dir).unwrap();
+        fs::write(ext4_dir.join("errors_count"), "3\n").unwrap();
+        fs::write(device_dir.join("life_time"), "0x02 0x03\n").unwrap();
+        fs::write(device_dir.join("pre_eol_info"), "0x01\n").unwrap();
+        let mut monitor = StorageMonitor::new("/", 0)
+            .with_partition("mmcblk0p2")
+            .with_device("mmcblk0")
+            .with_sysfs(Sysfs::with_root_dir(sysfs_dir.path()));
+        let report = monitor.update().unwrap();
+        assert_eq!(report.errors, Some(3));
+        assert_eq!(
+            report.lifetime,
+            Some(Lifetime {
+                used: 0.3,
+                pre_eol_warning: false
+            })
+        );
+        assert!(!report.degraded);
+        // SD cards do not report their wear.
+        let mut monitor = StorageMonitor::new("/", 0)
+            .with_device("mmcblk1")
+            .with_sysfs(Sysfs::with_root_dir(sysfs_dir.path()));
+        assert_eq!(monitor.update().unwrap().lifetime, None);
+    }
+
+    #[test]
+    fn it_should_degrade_below_the_reserve() {
+        let mut monitor = StorageMonitor::new("/", u64::MAX);
+        assert!(monitor.update().unwrap().degraded);
+        assert!(monitor.is_degraded());
+        monitor.reserve = 0;
+        assert!(!monitor.update().unwrap().degraded);
+    }
+}
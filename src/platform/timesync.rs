@@ -0,0 +1,227 @@
+//! Whether the system clock has been synchronized.
+//!
+//! The Raspberry Pi has no battery-backed clock, so after a cold boot
+//! without network the system time resumes from whenever it last shut down.
+//! Whichever NTP client is running, chrony, ntpd, or systemd-timesyncd,
+//! disciplines the kernel clock, so its synchronization status is read from
+//! the kernel with `adjtimex`. Anything stamped before synchronization, such
+//! as logs recorded off-grid, can be corrected afterwards by the step that
+//! [`ClockWatch`] measures when the clock jumps.
+
+use std::ffi::{c_int, c_long, c_uint};
+use std::io::Error;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "timesync";
+
+/// Status flag set while the clock is not synchronized.
+const STA_UNSYNC: c_int = 0x0040;
+
+/// Status flag set when the offset is in nanoseconds rather than
+/// microseconds.
+const STA_NANO: c_int = 0x2000;
+
+/// Return value of `adjtimex` when the clock is not synchronized.
+const TIME_ERROR: c_int = 5;
+
+/// Kernel clock state, as filled in by `adjtimex`.
+#[repr(C)]
+struct Timex {
+    modes: c_uint,
+    offset: c_long,
+    freq: c_long,
+    maxerror: c_long,
+    esterror: c_long,
+    status: c_int,
+    constant: c_long,
+    precision: c_long,
+    tolerance: c_long,
+    time: [c_long; 2],
+    tick: c_long,
+    ppsfreq: c_long,
+    jitter: c_long,
+    shift: c_int,
+    stabil: c_long,
+    jitcnt: c_long,
+    calcnt: c_long,
+    errcnt: c_long,
+    stbcnt: c_long,
+    tai: c_int,
+    padding: [c_int; 11],
+}
+
+extern "C" {
+    /// Reads the kernel clock state into `buf`, or also adjusts it according
+    /// to `buf.modes`, which is 0 to only read.
+    ///
+    /// Returns the clock state on success, or -1 on failure and sets `errno`
+    /// to indicate the error.
+    fn adjtimex(buf: *mut Timex) -> c_int;
+}
+
+/// Synchronization status of the system clock.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SyncStatus {
+    /// Whether an NTP client has synchronized the clock.
+    pub synchronized: bool,
+    /// Offset of the clock from the reference the NTP client last measured,
+    /// which it is slewing away.
+    pub offset: Duration,
+    /// Whether the clock is ahead of the reference, rather than behind.
+    pub ahead: bool,
+    /// Estimated error of the clock.
+    pub estimated_error: Duration,
+    /// Maximum error of the clock, which grows while it is not
+    /// synchronized.
+    pub max_error: Duration,
+}
+
+impl SyncStatus {
+    /// Returns the synchronization status of the system clock.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the kernel cannot be queried.
+    pub fn read() -> Result<Self, Error> {
+        let mut timex: Timex = unsafe { std::mem::zeroed() };
+        let state = unsafe { adjtimex(&mut timex) };
+
+        if state == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        let offset = timex.offset.unsigned_abs() as u64;
+        let micros = |value: c_long| Duration::from_micros(value.max(0) as u64);
+        Ok(Self {
+            synchronized: state != TIME_ERROR && timex.status & STA_UNSYNC == 0,
+            offset: if timex.status & STA_NANO == 0 {
+                Duration::from_micros(offset)
+            } else {
+                Duration::from_nanos(offset)
+            },
+            ahead: timex.offset < 0,
+            estimated_error: micros(timex.esterror),
+            max_error: micros(timex.maxerror),
+        })
+    }
+}
+
+/// A step in the system clock.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Step {
+    /// Size of the step.
+    pub size: Duration,
+    /// Whether the clock stepped forward.
+    pub forward: bool,
+}
+
+impl Step {
+    /// Returns `time`, stamped before the step, corrected to the clock after
+    /// it.
+    #[must_use]
+    pub fn correct(&self, time: SystemTime) -> SystemTime {
+        if self.forward {
+            time + self.size
+        } else {
+            time - self.size
+        }
+    }
+}
+
+/// Detects steps in the system clock by comparing it to the monotonic clock.
+#[derive(Clone, Copy, Debug)]
+pub struct ClockWatch {
+    epoch: Instant,
+    /// Wall clock minus monotonic clock, in nanoseconds.
+    difference: i128,
+    threshold: Duration,
+}
+
+impl ClockWatch {
+    /// Creates a new `ClockWatch` reporting steps larger than `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        let epoch = Instant::now();
+        Self {
+            epoch,
+            difference: difference(SystemTime::now(), epoch, epoch),
+            threshold,
+        }
+    }
+
+    /// Returns the step since the last check, if the clock stepped further
+    /// than the threshold.
+    pub fn check(&mut self) -> Option<Step> {
+        self.observe(SystemTime::now(), Instant::now())
+    }
+
+    fn observe(&mut self, wall: SystemTime, monotonic: Instant) -> Option<Step> {
+        let difference = difference(wall, monotonic, self.epoch);
+        let change = difference - self.difference;
+        // Slewing moves the clock gradually, so only the jump is reported
+        // and the baseline follows the clock either way.
+        self.difference = difference;
+        let size = Duration::from_nanos(change.unsigned_abs() as u64);
+
+        if size <= self.threshold {
+            return None;
+        }
+
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "system clock stepped {} by {size:?}",
+            if change > 0 { "forward" } else { "back" }
+        );
+        Some(Step {
+            size,
+            forward: change > 0,
+        })
+    }
+}
+
+fn difference(wall: SystemTime, monotonic: Instant, epoch: Instant) -> i128 {
+    let wall = match wall.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_nanos() as i128,
+        Err(error) => -(error.duration().as_nanos() as i128),
+    };
+    wall - monotonic.saturating_duration_since(epoch).as_nanos() as i128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_read_the_kernel_clock_state() {
+        let status = SyncStatus::read().unwrap();
+        assert!(status.synchronized || status.max_error > Duration::ZERO);
+    }
+
+    #[test]
+    fn it_should_detect_steps_and_correct_earlier_times() {
+        let mut watch = ClockWatch::new(Duration::from_secs(1));
+        let wall = SystemTime::now();
+        let monotonic = watch.epoch + Duration::from_secs(10);
+        watch.difference = difference(wall, monotonic, watch.epoch);
+        let later = monotonic + Duration::from_secs(5);
+        // The clock kept time, give or take some slewing.
+        assert_eq!(
+            watch.observe(wall + Duration::from_millis(5_010), later),
+            None
+        );
+        // NTP stepped the clock forward by a day.
+        let day = Duration::from_secs(86_400);
+        let step = watch
+            .observe(
+                wall + Duration::from_secs(6) + day,
+                later + Duration::from_secs(1),
+            )
+            .unwrap();
+        assert!(step.forward);
+        assert!(step.size.abs_diff(day) < Duration::from_millis(20));
+        assert_eq!(step.correct(wall), wall + step.size);
+    }
+}
@@ -0,0 +1,41 @@
+//! Managing how much power the robot draws.
+//!
+//! Peripherals that can be switched off implement [`PowerDomain`], so the
+//! [`SleepManager`] can power them down while the robot idles on its dock.
+
+use std::io::Error;
+
+#[cfg(target_os = "linux")]
+pub mod brownout;
+pub mod energy;
+#[cfg(target_os = "linux")]
+pub mod governor;
+pub mod rail;
+pub mod sleep;
+pub mod soft_start;
+
+#[cfg(target_os = "linux")]
+pub use brownout::BrownoutDetector;
+pub use energy::EnergyMeter;
+pub use rail::Rail;
+pub use sleep::SleepManager;
+pub use soft_start::SoftStart;
+
+/// Something that can be powered down to save the battery.
+pub trait PowerDomain {
+    /// Powers the domain down.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the domain cannot be powered
+    /// down.
+    fn power_down(&mut self) -> Result<(), Error>;
+
+    /// Powers the domain back up.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the domain cannot be powered
+    /// up.
+    fn power_up(&mut self) -> Result<(), Error>;
+}
@@ -0,0 +1,366 @@
+//! Detecting and riding out brownouts.
+//!
+//! When the supply sags, the Pi’s firmware raises an undervoltage flag, and
+//! rail monitors read below their nominal voltage, shortly before the SD card
+//! or USB devices start misbehaving. The [`BrownoutDetector`] watches both,
+//! and while either is low it powers down loads that can be spared, such as
+//! the camera and LIDAR, and caps the motors’ output so the battery can
+//! recover, reversing both once the supply has held up for a while.
+
+use std::fmt::{self, Debug, Formatter};
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+use super::{PowerDomain, SoftStart};
+use crate::events::{Brownout, Event, EventBus};
+use crate::linux::sysfs::Sysfs;
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "brownout";
+
+/// Directory, relative to the sysfs root, containing every hardware monitor.
+const HWMON_DIR: &str = "class/hwmon";
+
+/// Name of the hardware monitor exposing the firmware’s undervoltage flag.
+const RPI_VOLT: &str = "rpi_volt";
+
+/// Cause reported when the firmware’s flag is raised.
+const UNDERVOLTAGE_FLAG: &str = "undervoltage flag";
+
+type Reader = Box<dyn FnMut() -> Result<f64, Error> + Send>;
+
+struct RailMonitor {
+    minimum: f64,
+    name: String,
+    read: Reader,
+}
+
+/// Watches for brownouts and sheds load until the supply recovers.
+pub struct BrownoutDetector<'a> {
+    bus: Option<EventBus>,
+    ceiling: f64,
+    clear_since: Option<Instant>,
+    cause: Option<String>,
+    hold: Duration,
+    loads: Vec<Box<dyn PowerDomain>>,
+    motor_limit: Option<SoftStart>,
+    rails: Vec<RailMonitor>,
+    sysfs: Sysfs<'a>,
+}
+
+impl<'a> BrownoutDetector<'a> {
+    /// Creates a new `BrownoutDetector` watching the firmware’s undervoltage
+    /// flag, which recovers once the supply has held up for 10 seconds.
+    pub fn new() -> Self {
+        Self::with_sysfs(Sysfs::new())
+    }
+
+    /// Creates a new `BrownoutDetector` that reads the undervoltage flag
+    /// through `sysfs`.
+    pub fn with_sysfs(sysfs: Sysfs<'a>) -> Self {
+        Self {
+            bus: None,
+            ceiling: 0.5,
+            clear_since: None,
+            cause: None,
+            hold: Duration::from_secs(10),
+            loads: Vec::new(),
+            motor_limit: None,
+            rails: Vec::new(),
+            sysfs,
+        }
+    }
+
+    /// Watches a rail named `name`, read in volts by `read`, which browns
+    /// out below `minimum` volts.
+    #[must_use]
+    pub fn with_rail(
+        mut self,
+        name: &str,
+        minimum: f64,
+        read: impl FnMut() -> Result<f64, Error> + Send + 'static,
+    ) -> Self {
+        self.rails.push(RailMonitor {
+            minimum,
+            name: name.to_owned(),
+            read: Box::new(read),
+        });
+        self
+    }
+
+    /// Adds a load to power down through a brownout, in order, powering
+    /// them back up in reverse.
+    #[must_use]
+    pub fn with_load(mut self, load: impl PowerDomain + 'static) -> Self {
+        self.loads.push(Box::new(load));
+        self
+    }
+
+    /// Caps the motors sharing `limit` at `ceiling`, a fraction of full
+    /// output, through a brownout.
+    #[must_use]
+    pub fn with_motor_limit(mut self, limit: SoftStart, ceiling: f64) -> Self {
+        self.motor_limit = Some(limit);
+        self.ceiling = ceiling.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets how long the supply must hold up before recovering.
+    #[must_use]
+    pub fn with_hold(mut self, hold: Duration) -> Self {
+        self.hold = hold;
+        self
+    }
+
+    /// Publishes brownouts and recoveries to `bus`.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Returns whether a brownout is being ridden out.
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        self.cause.is_some()
+    }
+
+    /// Returns whether the firmware’s undervoltage flag is raised, or `None`
+    /// if there is no flag, such as off a Raspberry Pi.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the flag cannot be read.
+    pub fn undervoltage(&self) -> Result<Option<bool>, Error> {
+        let monitors = match self.sysfs.entries(HWMON_DIR) {
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            result => result?,
+        };
+
+        for monitor in monitors {
+            let dir = format!("{HWMON_DIR}/{monitor}");
+
+            if self
+                .sysfs
+                .read_to_string(format!("{dir}/name"))
+                .is_ok_and(|name| name.trim() == RPI_VOLT)
+            {
+                let alarm = self
+                    .sysfs
+                    .read_to_string(format!("{dir}/in0_lcrit_alarm"))?;
+                return Ok(Some(alarm.trim() != "0"));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Checks the flag and rails at `now`, entering or leaving a brownout as
+    /// needed, and returns whether one is being ridden out.
+    ///
+    /// A rail or flag that cannot be read is logged and taken to be fine, so
+    /// that a flaky monitor does not shed load on its own.
+    pub fn update(&mut self, now: Instant) -> bool {
+        match self.check() {
+            Some(cause) => {
+                self.clear_since = None;
+
+                if self.cause.is_none() {
+                    self.enter(cause);
+                }
+            }
+            None if self.cause.is_some() => {
+                let clear_since = *self.clear_since.get_or_insert(now);
+
+                if now.saturating_duration_since(clear_since) >= self.hold {
+                    self.leave();
+                }
+            }
+            None => {}
+        }
+
+        self.is_active()
+    }
+
+    fn check(&mut self) -> Option<String> {
+        match self.undervoltage() {
+            Ok(Some(true)) => return Some(UNDERVOLTAGE_FLAG.to_owned()),
+            Ok(_) => {}
+            Err(error) => log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "could not read the undervoltage flag: {error}"
+            ),
+        }
+
+        for rail in &mut self.rails {
+            match (rail.read)() {
+                Ok(volts) if volts < rail.minimum => {
+                    return Some(format!("{} at {volts:.2} V", rail.name));
+                }
+                Ok(_) => {}
+                Err(error) => log_event!(
+                    SUBSYSTEM,
+                    Level::Warn,
+                    "could not read {}: {error}",
+                    rail.name
+                ),
+            }
+        }
+
+        None
+    }
+
+    fn enter(&mut self, cause: String) {
+        log_event!(SUBSYSTEM, Level::Error, "brownout: {cause}");
+
+        if let Some(limit) = &self.motor_limit {
+            limit.set_ceiling(self.ceiling);
+        }
+
+        for load in &mut self.loads {
+            if let Err(error) = load.power_down() {
+                log_event!(SUBSYSTEM, Level::Warn, "could not shed load: {error}");
+            }
+        }
+
+        self.publish(true, &cause);
+        self.cause = Some(cause);
+    }
+
+    fn leave(&mut self) {
+        let cause = self.cause.take().unwrap_or_default();
+        self.clear_since = None;
+        log_event!(SUBSYSTEM, Level::Info, "recovered from brownout: {cause}");
+
+        for load in self.loads.iter_mut().rev() {
+            if let Err(error) = load.power_up() {
+                log_event!(SUBSYSTEM, Level::Warn, "could not restore load: {error}");
+            }
+        }
+
+        if let Some(limit) = &self.motor_limit {
+            limit.set_ceiling(1.0);
+        }
+
+        self.publish(false, &cause);
+    }
+
+    fn publish(&self, active: bool, cause: &str) {
+        if let Some(bus) = &self.bus {
+            bus.publish(Event::Brownout(Brownout {
+                active,
+                cause: cause.to_owned(),
+            }));
+        }
+    }
+}
+
+impl Default for BrownoutDetector<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for BrownoutDetector<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BrownoutDetector")
+            .field("cause", &self.cause)
+            .field("hold", &self.hold)
+            .field("loads", &self.loads.len())
+            .field("rails", &self.rails.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[derive(Clone, Debug, Default)]
+    struct Load(Arc<Mutex<Vec<bool>>>);
+
+    impl PowerDomain for Load {
+        fn power_down(&mut self) -> Result<(), Error> {
+            self.0.lock().unwrap().push(false);
+            Ok(())
+        }
+
+        fn power_up(&mut self) -> Result<(), Error> {
+            self.0.lock().unwrap().push(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_should_read_the_firmware_undervoltage_flag() {
+        let sysfs_dir = TemporaryDirectory::new().expect("should succeed");
+        let detector = BrownoutDetector::with_sysfs(Sysfs::with_root_dir(sysfs_dir.path()));
+        assert_eq!(detector.undervoltage().unwrap(), None);
+
+        for (monitor, name) in [("hwmon0", "cpu_thermal"), ("hwmon1", RPI_VOLT)] {
+            let dir = sysfs_dir.path().join(HWMON_DIR).join(monitor);
+            fs::create_dir_all(&dir).expect("should be writable");
+            fs::write(dir.join("name"), format!("{name}\n")).expect("should be writable");
+        }
+
+        let alarm = sysfs_dir
+            .path()
+            .join(HWMON_DIR)
+            .join("hwmon1/in0_lcrit_alarm");
+        fs::write(&alarm, "1\n").expect("should be writable");
+        assert_eq!(detector.undervoltage().unwrap(), Some(true));
+        fs::write(&alarm, "0\n").expect("should be writable");
+        assert_eq!(detector.undervoltage().unwrap(), Some(false));
+    }
+
+    #[test]
+    fn it_should_shed_load_until_the_rail_holds_up() {
+        let sysfs_dir = TemporaryDirectory::new().expect("should succeed");
+        let millivolts = Arc::new(AtomicU64::new(5100));
+        let reading = Arc::clone(&millivolts);
+        let load = Load::default();
+        let limit = SoftStart::new(Duration::ZERO);
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(8);
+        let mut detector = BrownoutDetector::with_sysfs(Sysfs::with_root_dir(sysfs_dir.path()))
+            .with_rail("5V", 4.75, move || {
+                Ok(reading.load(Ordering::Relaxed) as f64 / 1000.0)
+            })
+            .with_load(load.clone())
+            .with_motor_limit(limit.clone(), 0.3)
+            .with_hold(Duration::from_secs(5))
+            .with_bus(bus);
+        let start = Instant::now();
+        assert!(!detector.update(start));
+        millivolts.store(4600, Ordering::Relaxed);
+        assert!(detector.update(start));
+        assert_eq!(limit.limit(), 0.3);
+        assert_eq!(*load.0.lock().unwrap(), [false]);
+        millivolts.store(5000, Ordering::Relaxed);
+        assert!(detector.update(start + Duration::from_secs(1)));
+        assert!(!detector.update(start + Duration::from_secs(6)));
+        assert_eq!(limit.limit(), 1.0);
+        assert_eq!(*load.0.lock().unwrap(), [false, true]);
+        let events: Vec<_> = std::iter::from_fn(|| subscription.try_recv()).collect();
+        assert_eq!(
+            events,
+            [
+                Event::Brownout(Brownout {
+                    active: true,
+                    cause: "5V at 4.60 V".to_owned()
+                }),
+                Event::Brownout(Brownout {
+                    active: false,
+                    cause: "5V at 4.60 V".to_owned()
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Accounting for where the battery’s energy goes.
+//!
+//! Power monitors on individual rails attribute their draw to the subsystem
+//! each rail feeds, such as the drive motors or the sensors. With a monitor
+//! on the battery as well, whatever the rails do not account for is
+//! attributed to the remainder, which on most robots is the Pi itself. The
+//! [`EnergyMeter`] integrates samples into watt-hours per subsystem for each
+//! mission.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::io::Error;
+use std::time::{Duration, Instant};
+
+use crate::hal::I2c;
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+use crate::sensors::ina219::Ina219;
+
+const SUBSYSTEM: &str = "energy";
+
+/// Seconds in an hour, to convert joules to watt-hours.
+const SECONDS_PER_HOUR: f64 = 3600.0;
+
+/// A monitor of the power drawn by a rail.
+pub trait PowerSensor {
+    /// Returns the power drawn, in watts.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the monitor cannot be read.
+    fn power(&mut self) -> Result<f64, Error>;
+}
+
+impl<I: I2c> PowerSensor for Ina219<I> {
+    fn power(&mut self) -> Result<f64, Error> {
+        Ok(Ina219::power(self)?.valid()?)
+    }
+}
+
+impl<F: FnMut() -> Result<f64, Error>> PowerSensor for F {
+    fn power(&mut self) -> Result<f64, Error> {
+        self()
+    }
+}
+
+struct Rail {
+    sensor: Box<dyn PowerSensor + Send>,
+    subsystem: String,
+}
+
+/// Energy used by each subsystem over a mission.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnergyReport {
+    /// Name of the mission.
+    pub mission: String,
+    /// How long the mission has run.
+    pub duration: Duration,
+    /// Energy used by each subsystem, in watt-hours.
+    pub subsystems: BTreeMap<String, f64>,
+}
+
+impl EnergyReport {
+    /// Returns the energy used by every subsystem, in watt-hours.
+    #[must_use]
+    pub fn total(&self) -> f64 {
+        self.subsystems.values().sum()
+    }
+}
+
+impl ToJson for EnergyReport {
+    fn to_json(&self) -> Value {
+        let subsystems = self
+            .subsystems
+            .iter()
+            .fold(Value::object(), |object, (name, wh)| object.with(name, *wh));
+        Value::object()
+            .with("mission", self.mission.as_str())
+            .with("duration", self.duration.as_secs_f64())
+            .with("total_wh", self.total())
+            .with("subsystems", subsystems)
+    }
+}
+
+/// Integrates power samples into energy used per subsystem and mission.
+pub struct EnergyMeter {
+    energy: BTreeMap<String, f64>,
+    last_power: BTreeMap<String, f64>,
+    last_sample: Option<Instant>,
+    mission: String,
+    rails: Vec<Rail>,
+    remainder: Option<(String, Box<dyn PowerSensor + Send>)>,
+    started: Instant,
+}
+
+impl EnergyMeter {
+    /// Creates a new `EnergyMeter`, accounting to a mission named `idle`
+    /// until another starts.
+    pub fn new() -> Self {
+        Self {
+            energy: BTreeMap::new(),
+            last_power: BTreeMap::new(),
+            last_sample: None,
+            mission: "idle".to_owned(),
+            rails: Vec::new(),
+            remainder: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Attributes the draw measured by `sensor` to `subsystem`, adding to
+    /// any other rails feeding it.
+    #[must_use]
+    pub fn with_rail(mut self, subsystem: &str, sensor: impl PowerSensor + Send + 'static) -> Self {
+        self.rails.push(Rail {
+            sensor: Box::new(sensor),
+            subsystem: subsystem.to_owned(),
+        });
+        self
+    }
+
+    /// Measures the draw from the battery with `sensor`, attributing
+    /// whatever the rails do not account for to `subsystem`.
+    #[must_use]
+    pub fn with_total(
+        mut self,
+        subsystem: &str,
+        sensor: impl PowerSensor + Send + 'static,
+    ) -> Self {
+        self.remainder = Some((subsystem.to_owned(), Box::new(sensor)));
+        self
+    }
+
+    /// Starts accounting to a new mission named `name` at `now`, returning
+    /// the report of the mission that ended.
+    pub fn start_mission(&mut self, name: &str, now: Instant) -> EnergyReport {
+        let report = self.report(now);
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "mission {} used {:.2} Wh",
+            report.mission,
+            report.total()
+        );
+        name.clone_into(&mut self.mission);
+        self.energy.clear();
+        self.started = now;
+        report
+    }
+
+    /// Reads every monitor at `now` and adds the energy used since the last
+    /// sample, taking power to change linearly between samples.
+    ///
+    /// A monitor that cannot be read is logged and counted as drawing
+    /// nothing, so that one faulty monitor does not stop the others.
+    pub fn sample(&mut self, now: Instant) {
+        let mut power = BTreeMap::new();
+        let mut attributed = 0.0;
+
+        for rail in &mut self.rails {
+            let watts = read(&mut *rail.sensor, &rail.subsystem);
+            attributed += watts;
+            *power.entry(rail.subsystem.clone()).or_insert(0.0) += watts;
+        }
+
+        if let Some((subsystem, sensor)) = &mut self.remainder {
+            let watts = (read(&mut **sensor, "battery") - attributed).max(0.0);
+            *power.entry(subsystem.clone()).or_insert(0.0) += watts;
+        }
+
+        if let Some(last) = self.last_sample {
+            let dt = now.saturating_duration_since(last).as_secs_f64();
+
+            for (subsystem, &watts) in &power {
+                let previous = self.last_power.get(subsystem).copied().unwrap_or(watts);
+                *self.energy.entry(subsystem.clone()).or_insert(0.0) +=
+                    (previous + watts) / 2.0 * dt / SECONDS_PER_HOUR;
+            }
+        }
+
+        self.last_power = power;
+        self.last_sample = Some(now);
+    }
+
+    /// Returns the energy used so far in the current mission, as of `now`.
+    #[must_use]
+    pub fn report(&self, now: Instant) -> EnergyReport {
+        EnergyReport {
+            mission: self.mission.clone(),
+            duration: now.saturating_duration_since(self.started),
+            subsystems: self.energy.clone(),
+        }
+    }
+}
+
+impl Default for EnergyMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for EnergyMeter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnergyMeter")
+            .field("energy", &self.energy)
+            .field("mission", &self.mission)
+            .field("rails", &self.rails.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn read(sensor: &mut dyn PowerSensor, name: &str) -> f64 {
+    sensor.power().unwrap_or_else(|error| {
+        log_event!(SUBSYSTEM, Level::Warn, "could not read {name}: {error}");
+        0.0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn watts(value: f64) -> (Arc<AtomicU64>, impl FnMut() -> Result<f64, Error>) {
+        let shared = Arc::new(AtomicU64::new(value.to_bits()));
+        let reading = Arc::clone(&shared);
+        (shared, move || {
+            Ok(f64::from_bits(reading.load(Ordering::Relaxed)))
+        })
+    }
+
+    #[test]
+    fn it_should_attribute_energy_to_subsystems() {
+        let (drive, drive_sensor) = watts(20.0);
+        let (_, sensors_sensor) = watts(3.0);
+        let (_, lidar_sensor) = watts(2.0);
+        let (_, battery_sensor) = watts(30.0);
+        let mut meter = EnergyMeter::new()
+            .with_rail("drive", drive_sensor)
+            .with_rail("sensors", sensors_sensor)
+            .with_rail("sensors", lidar_sensor)
+            .with_total("compute", battery_sensor);
+        let start = Instant::now();
+        meter.start_mission("patrol", start);
+        meter.sample(start);
+        drive.store(40.0_f64.to_bits(), Ordering::Relaxed);
+        meter.sample(start + Duration::from_secs(3600));
+        let report = meter.report(start + Duration::from_secs(3600));
+        assert_eq!(report.mission, "patrol");
+        assert!((report.subsystems["drive"] - 30.0).abs() < 1e-9);
+        assert!((report.subsystems["sensors"] - 5.0).abs() < 1e-9);
+        // The battery draw stays at 30 W while the rails rise to 45 W.
+        assert!((report.subsystems["compute"] - 2.5).abs() < 1e-9);
+        assert!((report.total() - 37.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_should_start_each_mission_from_zero() {
+        let (_, sensor) = watts(36.0);
+        let mut meter = EnergyMeter::new().with_rail("drive", sensor);
+        let start = Instant::now();
+        meter.sample(start);
+        meter.sample(start + Duration::from_secs(100));
+        let idle = meter.start_mission("dock", start + Duration::from_secs(100));
+        assert_eq!(idle.mission, "idle");
+        assert!((idle.total() - 1.0).abs() < 1e-9);
+        assert_eq!(meter.report(start).total(), 0.0);
+        let json = idle.to_json();
+        assert_eq!(json.get("total_wh"), Some(&Value::Number(idle.total())));
+    }
+}
@@ -0,0 +1,73 @@
+//! Slowing the CPU while the robot sleeps.
+
+use std::io::Error;
+
+use super::PowerDomain;
+use crate::linux::cpufreq::CpuFreq;
+
+/// Switches the CPU frequency governor while powered down, restoring
+/// whichever governor was in use on powering up.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Governor<'a> {
+    awake: Option<String>,
+    cpufreq: CpuFreq<'a>,
+    sleeping: String,
+}
+
+impl<'a> Governor<'a> {
+    /// Creates a new `Governor` that switches to the `powersave` governor.
+    pub fn new(cpufreq: CpuFreq<'a>) -> Self {
+        Self::with_governor(cpufreq, "powersave")
+    }
+
+    /// Creates a new `Governor` that switches to `sleeping`.
+    pub fn with_governor(cpufreq: CpuFreq<'a>, sleeping: &str) -> Self {
+        Self {
+            awake: None,
+            cpufreq,
+            sleeping: sleeping.to_owned(),
+        }
+    }
+}
+
+impl PowerDomain for Governor<'_> {
+    fn power_down(&mut self) -> Result<(), Error> {
+        let awake = self.cpufreq.governor()?;
+        self.cpufreq.set_governor(&self.sleeping)?;
+        self.awake.get_or_insert(awake);
+        Ok(())
+    }
+
+    fn power_up(&mut self) -> Result<(), Error> {
+        match self.awake.take() {
+            Some(awake) => self.cpufreq.set_governor(&awake),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::linux::sysfs::Sysfs;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_restore_the_previous_governor() {
+        let sysfs_dir = TemporaryDirectory::new().expect("should succeed");
+        let policy_dir = sysfs_dir.path().join("devices/system/cpu/cpufreq/policy0");
+        fs::create_dir_all(&policy_dir).expect("should be writable");
+        let path = policy_dir.join("scaling_governor");
+        fs::write(&path, "schedutil\n").expect("should be writable");
+        let mut governor =
+            Governor::new(CpuFreq::with_sysfs(Sysfs::with_root_dir(sysfs_dir.path())));
+        governor.power_down().unwrap();
+        // Powering down twice should not forget the governor in use before.
+        governor.power_down().unwrap();
+        assert!(fs::read_to_string(&path).is_ok_and(|contents| contents == "powersave"));
+        governor.power_up().unwrap();
+        assert!(fs::read_to_string(&path).is_ok_and(|contents| contents == "schedutil"));
+    }
+}
@@ -0,0 +1,81 @@
+//! Supply rails switched by an enable pin.
+
+use std::io::Error;
+
+use super::PowerDomain;
+use crate::hal::DigitalOutput;
+
+/// A supply rail, such as the one feeding the LIDAR or camera, switched by a
+/// load switch or regulator enable pin.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rail<P: DigitalOutput> {
+    active_high: bool,
+    pin: P,
+}
+
+impl<P: DigitalOutput> Rail<P> {
+    /// Creates a new `Rail` that is powered while `pin` is high.
+    pub fn new(pin: P) -> Self {
+        Self {
+            active_high: true,
+            pin,
+        }
+    }
+
+    /// Creates a new `Rail` that is powered while `pin` is low.
+    pub fn active_low(pin: P) -> Self {
+        Self {
+            active_high: false,
+            pin,
+        }
+    }
+
+    /// Returns the enable pin.
+    pub fn into_inner(self) -> P {
+        self.pin
+    }
+}
+
+impl<P: DigitalOutput> PowerDomain for Rail<P> {
+    fn power_down(&mut self) -> Result<(), Error> {
+        self.pin.set_level(!self.active_high)
+    }
+
+    fn power_up(&mut self) -> Result<(), Error> {
+        self.pin.set_level(self.active_high)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Pin {
+        high: Option<bool>,
+    }
+
+    impl DigitalOutput for Pin {
+        fn set_high(&mut self) -> Result<(), Error> {
+            self.high = Some(true);
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Error> {
+            self.high = Some(false);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_should_drive_the_enable_pin() {
+        let mut rail = Rail::new(Pin::default());
+        rail.power_down().unwrap();
+        assert_eq!(rail.pin.high, Some(false));
+        rail.power_up().unwrap();
+        assert_eq!(rail.pin.high, Some(true));
+        let mut rail = Rail::active_low(Pin::default());
+        rail.power_down().unwrap();
+        assert_eq!(rail.into_inner().high, Some(true));
+    }
+}
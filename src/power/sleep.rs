@@ -0,0 +1,292 @@
+//! Sleeping through long idle spells on the dock.
+//!
+//! A docked robot that keeps its LIDAR spinning and its CPU at full speed
+//! drains a battery in hours rather than days. After a spell without
+//! activity, the [`SleepManager`] powers down its domains and runs its sleep
+//! actions, such as dimming the LEDs, and reverses both when something wakes
+//! it: a network command, motion nearby, or a scheduled task coming due.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::PowerDomain;
+use crate::events::{Event, EventBus, PowerModeChanged};
+use crate::log_event;
+use crate::logging::Level;
+
+pub use crate::events::{PowerMode, WakeSource};
+
+const SUBSYSTEM: &str = "sleep";
+
+type SleepAction = Arc<dyn Fn() + Send + Sync>;
+type WakeAction = Arc<dyn Fn(WakeSource) + Send + Sync>;
+
+/// Wakes a [`SleepManager`] from another thread, such as the one receiving
+/// network commands.
+#[derive(Clone, Debug, Default)]
+pub struct WakeHandle(Arc<Mutex<Option<WakeSource>>>);
+
+impl WakeHandle {
+    /// Wakes the manager, or keeps it awake, on its next update.
+    pub fn wake(&self, source: WakeSource) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert(source);
+    }
+
+    fn take(&self) -> Option<WakeSource> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+/// Puts the robot to sleep after a spell of inactivity, and wakes it.
+///
+/// Domains are powered down in the order they were added and powered up in
+/// reverse, so a rail can be added before the devices it feeds. A domain that
+/// fails is logged and skipped, so that one stuck peripheral does not keep
+/// the rest awake.
+pub struct SleepManager {
+    bus: Option<EventBus>,
+    domains: Vec<Box<dyn PowerDomain>>,
+    handle: WakeHandle,
+    idle_for: Duration,
+    mode: PowerMode,
+    sleep_actions: Vec<SleepAction>,
+    timeout: Duration,
+    wake_actions: Vec<WakeAction>,
+}
+
+impl SleepManager {
+    /// Creates a new, awake `SleepManager` that sleeps after `timeout`
+    /// without activity.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            bus: None,
+            domains: Vec::new(),
+            handle: WakeHandle::default(),
+            idle_for: Duration::ZERO,
+            mode: PowerMode::Awake,
+            sleep_actions: Vec::new(),
+            timeout,
+            wake_actions: Vec::new(),
+        }
+    }
+
+    /// Adds a domain to power down while asleep.
+    #[must_use]
+    pub fn with_domain(mut self, domain: impl PowerDomain + 'static) -> Self {
+        self.domains.push(Box::new(domain));
+        self
+    }
+
+    /// Adds an action run on going to sleep, such as dimming the LEDs.
+    #[must_use]
+    pub fn on_sleep(mut self, action: impl Fn() + Send + Sync + 'static) -> Self {
+        self.sleep_actions.push(Arc::new(action));
+        self
+    }
+
+    /// Adds an action run on waking.
+    #[must_use]
+    pub fn on_wake(mut self, action: impl Fn(WakeSource) + Send + Sync + 'static) -> Self {
+        self.wake_actions.push(Arc::new(action));
+        self
+    }
+
+    /// Publishes changes in power mode to `bus`.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Returns the power mode.
+    #[must_use]
+    pub fn mode(&self) -> PowerMode {
+        self.mode
+    }
+
+    /// Returns a handle that wakes the manager from other threads.
+    #[must_use]
+    pub fn wake_handle(&self) -> WakeHandle {
+        self.handle.clone()
+    }
+
+    /// Restarts the inactivity timer, such as when the robot is driven.
+    pub fn activity(&mut self) {
+        self.idle_for = Duration::ZERO;
+    }
+
+    /// Goes to sleep now, rather than waiting for the timeout.
+    pub fn sleep(&mut self) {
+        if self.mode == PowerMode::Asleep {
+            return;
+        }
+
+        log_event!(SUBSYSTEM, Level::Info, "going to sleep");
+
+        for domain in &mut self.domains {
+            if let Err(error) = domain.power_down() {
+                log_event!(SUBSYSTEM, Level::Warn, "could not power down: {error}");
+            }
+        }
+
+        for action in &self.sleep_actions {
+            action();
+        }
+
+        self.mode = PowerMode::Asleep;
+        self.publish(None);
+    }
+
+    /// Wakes now, and restarts the inactivity timer.
+    pub fn wake(&mut self, source: WakeSource) {
+        self.idle_for = Duration::ZERO;
+
+        if self.mode == PowerMode::Awake {
+            return;
+        }
+
+        log_event!(SUBSYSTEM, Level::Info, "woken by {source:?}");
+
+        for domain in self.domains.iter_mut().rev() {
+            if let Err(error) = domain.power_up() {
+                log_event!(SUBSYSTEM, Level::Warn, "could not power up: {error}");
+            }
+        }
+
+        for action in &self.wake_actions {
+            action(source);
+        }
+
+        self.mode = PowerMode::Awake;
+        self.publish(Some(source));
+    }
+
+    /// Advances the inactivity timer by `dt`, handles wakes requested
+    /// through handles, and returns the power mode.
+    pub fn update(&mut self, dt: Duration) -> PowerMode {
+        if let Some(source) = self.handle.take() {
+            self.wake(source);
+        } else if self.mode == PowerMode::Awake {
+            self.idle_for += dt;
+
+            if self.idle_for >= self.timeout {
+                self.sleep();
+            }
+        }
+
+        self.mode
+    }
+
+    fn publish(&self, source: Option<WakeSource>) {
+        if let Some(bus) = &self.bus {
+            bus.publish(Event::PowerModeChanged(PowerModeChanged {
+                mode: self.mode,
+                source,
+            }));
+        }
+    }
+}
+
+impl Debug for SleepManager {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SleepManager")
+            .field("domains", &self.domains.len())
+            .field("idle_for", &self.idle_for)
+            .field("mode", &self.mode)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Error;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::events::EventKind;
+
+    const DT: Duration = Duration::from_secs(1);
+
+    #[derive(Clone, Debug, Default)]
+    struct Domain {
+        log: Arc<Mutex<Vec<String>>>,
+        name: &'static str,
+    }
+
+    impl PowerDomain for Domain {
+        fn power_down(&mut self) -> Result<(), Error> {
+            self.log.lock().unwrap().push(format!("{} down", self.name));
+            Ok(())
+        }
+
+        fn power_up(&mut self) -> Result<(), Error> {
+            self.log.lock().unwrap().push(format!("{} up", self.name));
+            Ok(())
+        }
+    }
+
+    fn run(manager: &mut SleepManager, cycles: usize) -> PowerMode {
+        (0..cycles).fold(manager.mode(), |_, _| manager.update(DT))
+    }
+
+    #[test]
+    fn it_should_sleep_after_the_timeout_without_activity() {
+        let mut manager = SleepManager::new(Duration::from_secs(60));
+        assert_eq!(run(&mut manager, 50), PowerMode::Awake);
+        manager.activity();
+        assert_eq!(run(&mut manager, 50), PowerMode::Awake);
+        assert_eq!(run(&mut manager, 10), PowerMode::Asleep);
+    }
+
+    #[test]
+    fn it_should_power_domains_down_in_order_and_up_in_reverse() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let domain = |name| Domain {
+            log: Arc::clone(&log),
+            name,
+        };
+        let dimmed = Arc::new(AtomicBool::new(false));
+        let (on_sleep, on_wake) = (Arc::clone(&dimmed), Arc::clone(&dimmed));
+        let mut manager = SleepManager::new(DT)
+            .with_domain(domain("rail"))
+            .with_domain(domain("lidar"))
+            .on_sleep(move || on_sleep.store(true, Ordering::Relaxed))
+            .on_wake(move |_| on_wake.store(false, Ordering::Relaxed));
+        manager.update(DT);
+        assert!(dimmed.load(Ordering::Relaxed));
+        manager.wake(WakeSource::Network);
+        assert!(!dimmed.load(Ordering::Relaxed));
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["rail down", "lidar down", "lidar up", "rail up"]
+        );
+    }
+
+    #[test]
+    fn it_should_wake_through_a_handle() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe_to(&[EventKind::PowerModeChanged], 4);
+        let mut manager = SleepManager::new(DT).with_bus(bus);
+        manager.sleep();
+        let handle = manager.wake_handle();
+        std::thread::spawn(move || handle.wake(WakeSource::Motion))
+            .join()
+            .unwrap();
+        assert_eq!(manager.update(DT), PowerMode::Awake);
+        // Waking restarts the inactivity timer.
+        assert_eq!(manager.update(Duration::ZERO), PowerMode::Awake);
+        assert!(subscription.try_recv().is_some());
+        assert_eq!(
+            subscription.try_recv(),
+            Some(Event::PowerModeChanged(PowerModeChanged {
+                mode: PowerMode::Awake,
+                source: Some(WakeSource::Motion),
+            }))
+        );
+    }
+}
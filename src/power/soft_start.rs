@@ -0,0 +1,192 @@
+//! Ramping actuator output up after arming or powering up.
+//!
+//! Motors starting together from standstill draw their stall current all at
+//! once, which can sag the supply far enough to reset the Pi. A
+//! [`SoftStart`] shared by every actuator caps their output to a fraction
+//! that rises over a short window after each restart, so the inrush is
+//! spread out. Actuators are wrapped in [`SoftStarted`] to apply the cap.
+
+use std::io::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::hal::{Motor, PwmOutput};
+
+/// Window over which output ramps up by default.
+pub const DEFAULT_WINDOW: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+struct State {
+    ceiling: f64,
+    floor: f64,
+    started: Instant,
+    window: Duration,
+}
+
+/// Cap on actuator output that ramps from a floor to full over a window
+/// after each restart, shared between every actuator it applies to.
+#[derive(Clone, Debug)]
+pub struct SoftStart {
+    state: Arc<Mutex<State>>,
+}
+
+impl SoftStart {
+    /// Creates a new `SoftStart` ramping over `window`, starting now.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                ceiling: 1.0,
+                floor: 0.0,
+                started: Instant::now(),
+                window,
+            })),
+        }
+    }
+
+    /// Sets the fraction of full output allowed at the start of the ramp,
+    /// such as to let motors overcome static friction straight away.
+    #[must_use]
+    pub fn with_floor(self, floor: f64) -> Self {
+        self.lock().floor = floor.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Starts the ramp over, as after arming or powering a rail up.
+    pub fn restart(&self) {
+        self.restart_at(Instant::now());
+    }
+
+    /// Starts the ramp over from `now`.
+    pub fn restart_at(&self, now: Instant) {
+        self.lock().started = now;
+    }
+
+    /// Caps output at `ceiling`, a fraction of full output, even once the
+    /// ramp finishes, such as to draw less current through a brownout.
+    pub fn set_ceiling(&self, ceiling: f64) {
+        self.lock().ceiling = ceiling.clamp(0.0, 1.0);
+    }
+
+    /// Returns the cap on output once the ramp finishes.
+    #[must_use]
+    pub fn ceiling(&self) -> f64 {
+        self.lock().ceiling
+    }
+
+    /// Returns the fraction of full output currently allowed, from 0 to 1.
+    #[must_use]
+    pub fn limit(&self) -> f64 {
+        self.limit_at(Instant::now())
+    }
+
+    /// Returns the fraction of full output allowed at `now`, from 0 to 1.
+    #[must_use]
+    pub fn limit_at(&self, now: Instant) -> f64 {
+        let state = self.lock();
+
+        if state.window.is_zero() {
+            return state.ceiling;
+        }
+
+        let progress =
+            now.saturating_duration_since(state.started).as_secs_f64() / state.window.as_secs_f64();
+        (state.floor + (1.0 - state.floor) * progress).min(state.ceiling)
+    }
+
+    /// Returns whether the ramp has finished.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.limit() >= self.ceiling()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for SoftStart {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
+
+/// An actuator whose output is capped by a [`SoftStart`].
+///
+/// Commands are scaled rather than clipped, so a command sent once before
+/// the ramp finishes is not fully applied until the next command; control
+/// loops resend theirs every update.
+#[derive(Debug)]
+pub struct SoftStarted<A> {
+    actuator: A,
+    soft_start: SoftStart,
+}
+
+impl<A> SoftStarted<A> {
+    /// Creates a new `SoftStarted` actuator capped by `soft_start`.
+    pub fn new(actuator: A, soft_start: SoftStart) -> Self {
+        Self {
+            actuator,
+            soft_start,
+        }
+    }
+
+    /// Returns the underlying actuator.
+    pub fn into_inner(self) -> A {
+        self.actuator
+    }
+}
+
+impl<M: Motor> Motor for SoftStarted<M> {
+    fn set_speed(&mut self, speed: f64) -> Result<(), Error> {
+        self.actuator.set_speed(speed * self.soft_start.limit())
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        self.actuator.stop()
+    }
+}
+
+impl<P: PwmOutput> PwmOutput for SoftStarted<P> {
+    fn frequency(&self) -> f64 {
+        self.actuator.frequency()
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: f64) -> Result<(), Error> {
+        self.actuator
+            .set_duty_cycle(duty_cycle * self.soft_start.limit())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockMotor;
+
+    #[test]
+    fn it_should_ramp_from_the_floor_over_the_window() {
+        let soft_start = SoftStart::new(Duration::from_secs(1)).with_floor(0.2);
+        let start = Instant::now();
+        soft_start.restart_at(start);
+        assert!((soft_start.limit_at(start) - 0.2).abs() < 1e-9);
+        assert!((soft_start.limit_at(start + Duration::from_millis(500)) - 0.6).abs() < 1e-9);
+        assert_eq!(soft_start.limit_at(start + Duration::from_secs(2)), 1.0);
+        assert_eq!(SoftStart::new(Duration::ZERO).limit(), 1.0);
+        soft_start.set_ceiling(0.5);
+        assert_eq!(soft_start.limit_at(start + Duration::from_secs(2)), 0.5);
+    }
+
+    #[test]
+    fn it_should_cap_every_actuator_sharing_it() {
+        let soft_start = SoftStart::new(Duration::from_secs(60));
+        let mut left = SoftStarted::new(MockMotor::default(), soft_start.clone());
+        let mut right = SoftStarted::new(MockMotor::default(), soft_start.clone());
+        left.set_speed(1.0).unwrap();
+        right.set_speed(-1.0).unwrap();
+        assert!(left.actuator.speed < 0.01);
+        assert!(right.actuator.speed > -0.01);
+        soft_start.restart_at(Instant::now() - Duration::from_secs(60));
+        left.set_speed(1.0).unwrap();
+        assert_eq!(left.into_inner().speed, 1.0);
+        assert!(soft_start.is_complete());
+    }
+}
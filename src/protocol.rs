@@ -0,0 +1,3 @@
+//! Building blocks for custom links to auxiliary microcontrollers.
+
+pub mod framing;
@@ -0,0 +1,353 @@
+//! Framing and checksums for byte streams such as serial links.
+//!
+//! A serial line delivers bytes without any boundaries, and drops or
+//! corrupts some of them. Framing marks where each packet ends, so a
+//! receiver that starts mid-packet or loses bytes resynchronizes at the next
+//! delimiter, and a checksum appended to each packet catches corruption.
+//! COBS frames with a fixed overhead of one byte in 254, and SLIP is
+//! simpler to decode by hand on a logic analyzer.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Byte ending a SLIP frame.
+const SLIP_END: u8 = 0xC0;
+
+/// Byte escaping a special byte in a SLIP frame.
+const SLIP_ESC: u8 = 0xDB;
+
+/// Escaped [`SLIP_END`].
+const SLIP_ESC_END: u8 = 0xDC;
+
+/// Escaped [`SLIP_ESC`].
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// An error decoding a frame.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FramingError {
+    /// The frame is not validly encoded, such as after a lost byte.
+    Malformed,
+    /// The frame grew longer than allowed before its delimiter arrived.
+    TooLong,
+}
+
+impl Display for FramingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => f.write_str("malformed frame"),
+            Self::TooLong => f.write_str("frame too long"),
+        }
+    }
+}
+
+impl Error for FramingError {}
+
+/// Returns `data` encoded with consistent overhead byte stuffing, without
+/// the zero byte that delimits it.
+#[must_use]
+pub fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_index = 0;
+    encoded.push(0);
+
+    for &byte in data {
+        if byte != 0 {
+            encoded.push(byte);
+        }
+
+        let code = encoded.len() - code_index;
+
+        if byte == 0 || code == 0xFF {
+            encoded[code_index] = if byte == 0 { code as u8 } else { 0xFF };
+            code_index = encoded.len();
+            encoded.push(0);
+        }
+    }
+
+    encoded[code_index] = (encoded.len() - code_index) as u8;
+    encoded
+}
+
+/// Returns the data encoded with consistent overhead byte stuffing in
+/// `encoded`, without its delimiting zero byte.
+///
+/// # Errors
+///
+/// This function will return [`FramingError::Malformed`] if `encoded`
+/// contains a zero byte or a code that runs past its end.
+pub fn cobs_decode(encoded: &[u8]) -> Result<Vec<u8>, FramingError> {
+    let mut data = Vec::with_capacity(encoded.len());
+    let mut index = 0;
+
+    while index < encoded.len() {
+        let code = usize::from(encoded[index]);
+        let block = encoded
+            .get(index + 1..index + code)
+            .filter(|_| code != 0)
+            .ok_or(FramingError::Malformed)?;
+
+        if block.contains(&0) {
+            return Err(FramingError::Malformed);
+        }
+
+        data.extend_from_slice(block);
+        index += code;
+
+        if code != 0xFF && index < encoded.len() {
+            data.push(0);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Returns `data` framed with SLIP, with a delimiter at each end so that
+/// line noise before the frame is discarded.
+#[must_use]
+pub fn slip_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + 2);
+    encoded.push(SLIP_END);
+
+    for &byte in data {
+        match byte {
+            SLIP_END => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            _ => encoded.push(byte),
+        }
+    }
+
+    encoded.push(SLIP_END);
+    encoded
+}
+
+/// Returns the data framed with SLIP in `encoded`, without its delimiters.
+///
+/// # Errors
+///
+/// This function will return [`FramingError::Malformed`] if `encoded`
+/// contains an invalid escape or a delimiter.
+pub fn slip_decode(encoded: &[u8]) -> Result<Vec<u8>, FramingError> {
+    let mut data = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.iter();
+
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            SLIP_ESC => match bytes.next() {
+                Some(&SLIP_ESC_END) => data.push(SLIP_END),
+                Some(&SLIP_ESC_ESC) => data.push(SLIP_ESC),
+                _ => return Err(FramingError::Malformed),
+            },
+            SLIP_END => return Err(FramingError::Malformed),
+            _ => data.push(byte),
+        }
+    }
+
+    Ok(data)
+}
+
+/// Computes the CRC-16/CCITT-FALSE, as used by XMODEM-style links.
+#[must_use]
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xFFFF, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc, _| {
+            if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x1021
+            }
+        })
+    })
+}
+
+/// Computes the CRC-32 used by Ethernet and zlib.
+#[must_use]
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// A framing scheme.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Framing {
+    /// Consistent overhead byte stuffing, delimited by zero bytes.
+    Cobs,
+    /// Serial Line Internet Protocol framing.
+    Slip,
+}
+
+impl Framing {
+    /// Returns `data` framed, with its delimiter.
+    #[must_use]
+    pub fn encode(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Cobs => {
+                let mut encoded = cobs_encode(data);
+                encoded.push(0);
+                encoded
+            }
+            Self::Slip => slip_encode(data),
+        }
+    }
+
+    fn delimiter(self) -> u8 {
+        match self {
+            Self::Cobs => 0,
+            Self::Slip => SLIP_END,
+        }
+    }
+
+    fn decode(self, encoded: &[u8]) -> Result<Vec<u8>, FramingError> {
+        match self {
+            Self::Cobs => cobs_decode(encoded),
+            Self::Slip => slip_decode(encoded),
+        }
+    }
+}
+
+/// Splits a byte stream into frames as it arrives.
+#[derive(Clone, Debug)]
+pub struct Deframer {
+    buffer: Vec<u8>,
+    framing: Framing,
+    max_len: usize,
+    overflowed: bool,
+}
+
+impl Deframer {
+    /// Creates a new `Deframer` for frames of up to `max_len` encoded bytes.
+    pub fn new(framing: Framing, max_len: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            framing,
+            max_len,
+            overflowed: false,
+        }
+    }
+
+    /// Adds `bytes` received from the stream, and returns the frames they
+    /// complete. Empty frames, as between back-to-back delimiters, are
+    /// skipped.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<Vec<u8>, FramingError>> {
+        let mut frames = Vec::new();
+
+        for &byte in bytes {
+            if byte != self.framing.delimiter() {
+                if self.buffer.len() < self.max_len {
+                    self.buffer.push(byte);
+                } else {
+                    self.overflowed = true;
+                }
+
+                continue;
+            }
+
+            if self.overflowed {
+                frames.push(Err(FramingError::TooLong));
+            } else if !self.buffer.is_empty() {
+                frames.push(self.framing.decode(&self.buffer));
+            }
+
+            self.buffer.clear();
+            self.overflowed = false;
+        }
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_encode_cobs_as_in_the_paper() {
+        assert_eq!(cobs_encode(&[]), [0x01]);
+        assert_eq!(cobs_encode(&[0x00]), [0x01, 0x01]);
+        assert_eq!(
+            cobs_encode(&[0x11, 0x22, 0x00, 0x33]),
+            [0x03, 0x11, 0x22, 0x02, 0x33]
+        );
+        assert_eq!(
+            cobs_encode(&[0x11, 0x00, 0x00, 0x00]),
+            [0x02, 0x11, 0x01, 0x01, 0x01]
+        );
+        let long: Vec<u8> = (1..=255).collect();
+        let encoded = cobs_encode(&long);
+        assert_eq!(encoded[0], 0xFF);
+        assert_eq!(&encoded[255..], [0x02, 0xFF]);
+    }
+
+    #[test]
+    fn it_should_round_trip_cobs() {
+        let cases: [Vec<u8>; 5] = [
+            vec![],
+            vec![0, 0],
+            vec![1, 2, 0, 3],
+            (0..=255).collect(),
+            (0..600).map(|index| (index % 255 + 1) as u8).collect(),
+        ];
+
+        for data in cases {
+            let encoded = cobs_encode(&data);
+            assert!(!encoded.contains(&0));
+            assert_eq!(cobs_decode(&encoded), Ok(data));
+        }
+
+        assert_eq!(cobs_decode(&[0x05, 0x11]), Err(FramingError::Malformed));
+        assert_eq!(cobs_decode(&[0x02, 0x00]), Err(FramingError::Malformed));
+    }
+
+    #[test]
+    fn it_should_round_trip_slip() {
+        let data = [0x01, SLIP_END, 0x02, SLIP_ESC, 0x03];
+        let encoded = slip_encode(&data);
+        assert_eq!(
+            encoded,
+            [
+                SLIP_END,
+                0x01,
+                SLIP_ESC,
+                SLIP_ESC_END,
+                0x02,
+                SLIP_ESC,
+                SLIP_ESC_ESC,
+                0x03,
+                SLIP_END
+            ]
+        );
+        assert_eq!(
+            slip_decode(&encoded[1..encoded.len() - 1]),
+            Ok(data.to_vec())
+        );
+        assert_eq!(slip_decode(&[SLIP_ESC, 0x01]), Err(FramingError::Malformed));
+    }
+
+    #[test]
+    fn it_should_compute_the_standard_checks() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn it_should_split_a_stream_into_frames() {
+        for framing in [Framing::Cobs, Framing::Slip] {
+            let mut deframer = Deframer::new(framing, 16);
+            let mut stream = framing.encode(&[1, 0, 2]);
+            stream.extend(framing.encode(&[0xC0, 0xDB]));
+            let (first, second) = stream.split_at(3);
+            let mut frames = deframer.push(first);
+            frames.extend(deframer.push(second));
+            assert_eq!(frames, [Ok(vec![1, 0, 2]), Ok(vec![0xC0, 0xDB])]);
+            let oversized = framing.encode(&[7; 20]);
+            assert_eq!(deframer.push(&oversized), [Err(FramingError::TooLong)]);
+            assert_eq!(deframer.push(&framing.encode(&[9])), [Ok(vec![9])]);
+        }
+    }
+}
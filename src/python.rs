@@ -0,0 +1,294 @@
+//! Python bindings, for scripting the robot without rewriting its drivers.
+//!
+//! Built with the `python` feature into the `otter_pi` extension module,
+//! usually by `maturin develop` with the `pyproject.toml` at the root of the
+//! repository. Scripts drive through the same command mux and e-stop as the
+//! [C interface](crate::ffi), work out wheel speeds with the drive
+//! kinematics, and watch the event bus:
+//!
+//! ```python
+//! import otter_pi
+//!
+//! robot = otter_pi.Robot(timeout=0.5)
+//! robot.drive(0.2, omega=0.5)
+//! left, right = otter_pi.DifferentialDrive(0.15).inverse(robot.command())
+//!
+//! bus = otter_pi.EventBus()
+//! faults = bus.subscribe(16)
+//! bus.fault("script", "something went wrong")
+//! print(faults.try_recv())
+//! ```
+
+use std::time::{Duration, Instant};
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::drive::{DifferentialDrive, Kinematics, Twist};
+use crate::events::{self, Event, EventBus, Subscription};
+use crate::ffi::{Runtime, OTTER_ERR_ESTOPPED};
+
+/// Velocity of the chassis in its own frame.
+#[pyclass(name = "Twist", eq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PyTwist {
+    /// Forward velocity, in metres per second.
+    #[pyo3(get, set)]
+    pub vx: f64,
+    /// Leftward velocity, in metres per second.
+    #[pyo3(get, set)]
+    pub vy: f64,
+    /// Counterclockwise angular velocity, in radians per second.
+    #[pyo3(get, set)]
+    pub omega: f64,
+}
+
+#[pymethods]
+impl PyTwist {
+    #[new]
+    #[pyo3(signature = (vx = 0.0, vy = 0.0, omega = 0.0))]
+    fn new(vx: f64, vy: f64, omega: f64) -> Self {
+        Self { vx, vy, omega }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Twist(vx={}, vy={}, omega={})",
+            self.vx, self.vy, self.omega
+        )
+    }
+}
+
+impl From<Twist> for PyTwist {
+    fn from(twist: Twist) -> Self {
+        Self::new(twist.vx, twist.vy, twist.omega)
+    }
+}
+
+impl From<PyTwist> for Twist {
+    fn from(twist: PyTwist) -> Self {
+        Self::new(twist.vx, twist.vy, twist.omega)
+    }
+}
+
+/// Kinematics of a differential drive.
+#[pyclass(name = "DifferentialDrive", frozen)]
+#[derive(Clone, Copy, Debug)]
+pub struct PyDifferentialDrive(DifferentialDrive);
+
+#[pymethods]
+impl PyDifferentialDrive {
+    #[new]
+    fn new(track_width: f64) -> PyResult<Self> {
+        if track_width > 0.0 {
+            Ok(Self(DifferentialDrive::new(track_width)))
+        } else {
+            Err(PyValueError::new_err("track_width should be positive"))
+        }
+    }
+
+    /// Returns the left and right wheel speeds that move the chassis at
+    /// `twist`.
+    fn inverse(&self, twist: PyTwist) -> (f64, f64) {
+        let [left, right] = self.0.inverse(twist.into());
+        (left, right)
+    }
+
+    /// Returns the chassis velocity produced by the wheel speeds.
+    fn forward(&self, left: f64, right: f64) -> PyTwist {
+        self.0.forward(&[left, right]).into()
+    }
+}
+
+/// The robot’s command surface, shared with the runtime.
+#[pyclass(name = "Robot", frozen)]
+#[derive(Clone, Debug)]
+pub struct PyRobot(Runtime);
+
+#[pymethods]
+impl PyRobot {
+    #[new]
+    #[pyo3(signature = (timeout = 0.5))]
+    fn new(timeout: f64) -> PyResult<Self> {
+        let timeout = Duration::try_from_secs_f64(timeout)
+            .map_err(|error| PyValueError::new_err(error.to_string()))?;
+        Ok(Self(Runtime::new(timeout)))
+    }
+
+    /// Commands the chassis to move, until the timeout passes without
+    /// another command.
+    #[pyo3(signature = (vx, vy = 0.0, omega = 0.0))]
+    fn drive(&self, vx: f64, vy: f64, omega: f64) -> PyResult<()> {
+        self.0
+            .drive(Twist::new(vx, vy, omega), Instant::now())
+            .map_err(|status| match status {
+                OTTER_ERR_ESTOPPED => PyRuntimeError::new_err("the robot is e-stopped"),
+                _ => PyValueError::new_err("velocities should be finite"),
+            })
+    }
+
+    /// Withdraws the last drive command.
+    fn stop(&self) {
+        self.0.stop();
+    }
+
+    /// Stops the robot and refuses drive commands until reset.
+    fn estop(&self) {
+        self.0.estop();
+    }
+
+    /// Accepts drive commands again after an e-stop.
+    fn reset_estop(&self) {
+        self.0.reset_estop();
+    }
+
+    /// Whether the robot is e-stopped.
+    #[getter]
+    fn estopped(&self) -> bool {
+        self.0.is_estopped()
+    }
+
+    /// Returns the command the robot is following, or `None`.
+    fn command(&self) -> Option<PyTwist> {
+        self.0.command(Instant::now()).map(PyTwist::from)
+    }
+
+    /// Returns the latest telemetry, as JSON.
+    fn telemetry(&self) -> String {
+        self.0.telemetry()
+    }
+}
+
+/// Publishes events to subscribers.
+#[pyclass(name = "EventBus", frozen)]
+#[derive(Clone, Debug)]
+pub struct PyEventBus(EventBus);
+
+#[pymethods]
+impl PyEventBus {
+    #[new]
+    fn new() -> Self {
+        Self(EventBus::new())
+    }
+
+    /// Returns a queue of the next `capacity` events.
+    #[pyo3(signature = (capacity = 64))]
+    fn subscribe(&self, capacity: usize) -> PySubscription {
+        PySubscription(self.0.subscribe(capacity))
+    }
+
+    /// Reports that `subsystem` failed.
+    fn fault(&self, subsystem: &str, message: &str) {
+        self.0.publish(Event::Fault(events::Fault {
+            subsystem: subsystem.to_owned(),
+            message: message.to_owned(),
+        }));
+    }
+
+    /// Engages or releases the emergency stop.
+    fn emergency_stop(&self, engaged: bool, source: &str) {
+        self.0.publish(Event::EmergencyStop(events::EmergencyStop {
+            engaged,
+            source: source.to_owned(),
+        }));
+    }
+}
+
+/// A queue of events, received as `(kind, details)` pairs.
+#[pyclass(name = "Subscription", frozen)]
+#[derive(Debug)]
+pub struct PySubscription(Subscription);
+
+#[pymethods]
+impl PySubscription {
+    /// Returns the next event, or `None` if there is none.
+    fn try_recv(&self) -> Option<(String, String)> {
+        self.0.try_recv().map(|event| describe(&event))
+    }
+
+    /// Waits up to `timeout` seconds, or forever, for the next event, or
+    /// returns `None`.
+    #[pyo3(signature = (timeout = None))]
+    fn recv(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<Option<(String, String)>> {
+        let timeout = timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|error| PyValueError::new_err(error.to_string()))?;
+        // Let other Python threads run while waiting.
+        let event = py.allow_threads(|| match timeout {
+            Some(timeout) => self.0.recv_timeout(timeout),
+            None => self.0.recv(),
+        });
+        Ok(event.map(|event| describe(&event)))
+    }
+
+    /// Number of events discarded because the queue was full.
+    #[getter]
+    fn missed(&self) -> u64 {
+        self.0.missed()
+    }
+}
+
+/// The `otter_pi` extension module.
+#[pymodule]
+fn otter_pi(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyTwist>()?;
+    module.add_class::<PyDifferentialDrive>()?;
+    module.add_class::<PyRobot>()?;
+    module.add_class::<PyEventBus>()?;
+    module.add_class::<PySubscription>()?;
+    Ok(())
+}
+
+fn describe(event: &Event) -> (String, String) {
+    let details = match event {
+        Event::Fault(fault) => format!("{}: {}", fault.subsystem, fault.message),
+        Event::EmergencyStop(stop) => format!(
+            "{} by {}",
+            if stop.engaged { "engaged" } else { "released" },
+            stop.source
+        ),
+        event => format!("{event:?}"),
+    };
+    (format!("{:?}", event.kind()), details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_drive_and_e_stop_like_the_c_interface() {
+        let robot = PyRobot::new(1.0).unwrap();
+        robot.drive(0.2, 0.0, 0.5).unwrap();
+        assert_eq!(robot.command(), Some(PyTwist::new(0.2, 0.0, 0.5)));
+        robot.estop();
+        assert!(robot.estopped());
+        assert_eq!(robot.command(), None);
+        assert!(PyRobot::new(-1.0).is_err());
+    }
+
+    #[test]
+    fn it_should_describe_events_from_the_bus() {
+        let bus = PyEventBus::new();
+        let subscription = bus.subscribe(4);
+        bus.fault("lidar", "timed out");
+        bus.emergency_stop(true, "script");
+        assert_eq!(
+            subscription.try_recv(),
+            Some(("Fault".to_owned(), "lidar: timed out".to_owned()))
+        );
+        assert_eq!(
+            subscription.try_recv(),
+            Some(("EmergencyStop".to_owned(), "engaged by script".to_owned()))
+        );
+        assert_eq!(subscription.try_recv(), None);
+    }
+
+    #[test]
+    fn it_should_compute_wheel_speeds() {
+        let drive = PyDifferentialDrive::new(0.2).unwrap();
+        assert_eq!(drive.inverse(PyTwist::new(1.0, 0.0, 2.0)), (0.8, 1.2));
+        assert!(PyDifferentialDrive::new(0.0).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Recording camera frames and telemetry for reviewing runs afterwards.
+//!
+//! Each recording is a run directory holding the encoded frames, an index of
+//! when each was captured, and telemetry as JSON lines. Frames and telemetry
+//! are stamped against the same monotonic clock, in seconds since the run
+//! started, so they can be lined up on replay. Runs share a disk quota: once
+//! it is exceeded, the oldest runs are deleted, and then the oldest frames of
+//! the current run, like a dashcam overwriting its oldest footage.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::events::Fault;
+use crate::json::Value;
+use crate::log_event;
+use crate::logging::Level;
+
+pub mod inspect;
+pub mod snapshot;
+
+const SUBSYSTEM: &str = "recorder";
+
+/// Prefix of the name of every run directory.
+const RUN_PREFIX: &str = "run-";
+
+/// Records frames and telemetry into a run directory within a disk quota.
+#[derive(Debug)]
+pub struct Recorder {
+    extension: String,
+    frame_index: BufWriter<File>,
+    frames: VecDeque<(PathBuf, u64)>,
+    old_runs: VecDeque<(PathBuf, u64)>,
+    quota: u64,
+    run_dir: PathBuf,
+    run_bytes: u64,
+    sequence: u64,
+    started: Instant,
+    telemetry: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Starts a new run in `root`, keeping every run in `root` within
+    /// `quota` bytes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the run directory or its files
+    /// cannot be created.
+    pub fn create(root: &Path, quota: u64) -> Result<Self, Error> {
+        fs::create_dir_all(root)?;
+        let old_runs = runs(root)?;
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let run_dir = create_run_dir(root, seconds)?;
+        fs::create_dir(run_dir.join("frames"))?;
+        let open = |name| {
+            OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(run_dir.join(name))
+                .map(BufWriter::new)
+        };
+        let recorder = Self {
+            extension: "jpg".to_owned(),
+            frame_index: open("frames.jsonl")?,
+            frames: VecDeque::new(),
+            old_runs,
+            quota,
+            run_bytes: 0,
+            sequence: 0,
+            started: Instant::now(),
+            telemetry: open("telemetry.jsonl")?,
+            run_dir,
+        };
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "recording to {}",
+            recorder.run_dir.display()
+        );
+        Ok(recorder)
+    }
+
+    /// Sets the file extension of frames, such as `h264` for encoded
+    /// access units.
+    #[must_use]
+    pub fn with_frame_extension(mut self, extension: &str) -> Self {
+        extension.clone_into(&mut self.extension);
+        self
+    }
+
+    /// Returns the directory of the current run.
+    #[must_use]
+    pub fn run_dir(&self) -> &Path {
+        &self.run_dir
+    }
+
+    /// Returns the bytes used by every run, as far as the recorder knows.
+    #[must_use]
+    pub fn used(&self) -> u64 {
+        self.old_runs.iter().map(|(_, size)| size).sum::<u64>() + self.run_bytes
+    }
+
+    /// Records an encoded frame `captured` at the given instant.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the frame cannot be written.
+    pub fn record_frame(&mut self, captured: Instant, data: &[u8]) -> Result<(), Error> {
+        let name = format!("{:08}.{}", self.sequence, self.extension);
+        let path = self.run_dir.join("frames").join(&name);
+        fs::write(&path, data)?;
+        let line = Value::object()
+            .with("sequence", self.sequence)
+            .with("time", self.seconds(captured))
+            .with("file", name)
+            .to_string();
+        writeln!(self.frame_index, "{line}")?;
+        let size = data.len() as u64 + line.len() as u64 + 1;
+        self.sequence += 1;
+        self.frames.push_back((path, data.len() as u64));
+        self.run_bytes += size;
+        self.enforce_quota()
+    }
+
+    /// Records a telemetry `sample` taken at the given instant.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the sample cannot be written.
+    pub fn record_telemetry(&mut self, taken: Instant, sample: &Value) -> Result<(), Error> {
+        let line = Value::object()
+            .with("time", self.seconds(taken))
+            .with("data", sample)
+            .to_string();
+        writeln!(self.telemetry, "{line}")?;
+        self.run_bytes += line.len() as u64 + 1;
+        self.enforce_quota()
+    }
+
+    /// Records that `fault` occurred at the given instant, for the fault
+    /// timeline of [`inspect::faults`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the fault cannot be written.
+    pub fn record_fault(&mut self, occurred: Instant, fault: &Fault) -> Result<(), Error> {
+        let fault = Value::object()
+            .with("subsystem", &fault.subsystem)
+            .with("message", &fault.message);
+        self.record_telemetry(occurred, &Value::object().with(inspect::FAULT_KEY, fault))
+    }
+
+    /// Writes buffered telemetry and frame index entries to disk.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the files cannot be written.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.frame_index.flush()?;
+        self.telemetry.flush()
+    }
+
+    fn seconds(&self, instant: Instant) -> f64 {
+        match instant.checked_duration_since(self.started) {
+            Some(elapsed) => elapsed.as_secs_f64(),
+            None => -self.started.duration_since(instant).as_secs_f64(),
+        }
+    }
+
+    fn enforce_quota(&mut self) -> Result<(), Error> {
+        while self.used() > self.quota {
+            if let Some((path, _)) = self.old_runs.pop_front() {
+                log_event!(SUBSYSTEM, Level::Info, "deleting {}", path.display());
+                remove_run(&path)?;
+            } else if let Some((path, size)) = self.frames.pop_front() {
+                remove_file(&path)?;
+                self.run_bytes -= size;
+            } else {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the run directories in `root` with their sizes, oldest first.
+fn runs(root: &Path) -> Result<VecDeque<(PathBuf, u64)>, Error> {
+    let mut runs = Vec::new();
+
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+
+        if entry.file_name().to_string_lossy().starts_with(RUN_PREFIX)
+            && entry.file_type()?.is_dir()
+        {
+            let path = entry.path();
+            let size = size(&path)?;
+            runs.push((path, size));
+        }
+    }
+
+    // Names embed zero-padded start times, so they sort chronologically.
+    runs.sort_unstable();
+    Ok(runs.into())
+}
+
+fn create_run_dir(root: &Path, seconds: u64) -> Result<PathBuf, Error> {
+    let base = format!("{RUN_PREFIX}{seconds:012}");
+
+    for attempt in 0.. {
+        let name = if attempt == 0 {
+            base.clone()
+        } else {
+            format!("{base}-{attempt}")
+        };
+        let path = root.join(name);
+
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+
+    unreachable!("some attempt should find an unused name")
+}
+
+fn size(path: &Path) -> Result<u64, Error> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    fs::read_dir(path)?.try_fold(0, |total, entry| Ok(total + size(&entry?.path())?))
+}
+
+fn remove_run(path: &Path) -> Result<(), Error> {
+    match fs::remove_dir_all(path) {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn remove_file(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::TemporaryDirectory;
+
+    #[test]
+    fn it_should_record_frames_and_telemetry_against_one_clock() {
+        let root = TemporaryDirectory::new().unwrap();
+        let mut recorder = Recorder::create(root.path(), u64::MAX).unwrap();
+        let now = Instant::now();
+        recorder.record_frame(now, b"frame").unwrap();
+        recorder
+            .record_telemetry(now, &Value::object().with("speed", 0.5))
+            .unwrap();
+        recorder.flush().unwrap();
+        let run_dir = recorder.run_dir();
+        assert_eq!(
+            fs::read(run_dir.join("frames/00000000.jpg")).unwrap(),
+            b"frame"
+        );
+        let index: Value = fs::read_to_string(run_dir.join("frames.jsonl"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let telemetry: Value = fs::read_to_string(run_dir.join("telemetry.jsonl"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(index.get("time"), telemetry.get("time"));
+        assert_eq!(
+            telemetry.get("data").and_then(|data| data.get("speed")),
+            Some(&Value::Number(0.5))
+        );
+    }
+
+    #[test]
+    fn it_should_delete_the_oldest_runs_and_then_frames_over_quota() {
+        let root = TemporaryDirectory::new().unwrap();
+        let old_run = root.path().join("run-000000000001");
+        fs::create_dir(&old_run).unwrap();
+        fs::write(old_run.join("telemetry.jsonl"), [0; 500]).unwrap();
+        let mut recorder = Recorder::create(root.path(), 1000)
+            .unwrap()
+            .with_frame_extension("h264");
+        let start = Instant::now();
+
+        for index in 0..5 {
+            let captured = start + Duration::from_millis(33 * index);
+            recorder.record_frame(captured, &[0; 200]).unwrap();
+        }
+
+        assert!(!old_run.exists());
+        assert!(recorder.used() <= 1000);
+        let frames = recorder.run_dir().join("frames");
+        assert!(!frames.join("00000000.h264").exists());
+        assert!(frames.join("00000004.h264").exists());
+    }
+}
@@ -0,0 +1,346 @@
+//! Reading recorded runs back for debugging in the field.
+//!
+//! Telemetry is recorded as one JSON object per line, so any sample can nest
+//! readings however suits its subsystem. For inspection, nested readings are
+//! flattened into channels named by their path, such as `imu.accel.x`, which
+//! can be dumped side by side as CSV. Faults recorded with
+//! [`Recorder::record_fault`](super::Recorder::record_fault) are picked out
+//! into a timeline.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::{runs, RUN_PREFIX};
+use crate::json::Value;
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "recorder";
+
+/// Key of the telemetry samples that record a fault.
+pub(super) const FAULT_KEY: &str = "fault";
+
+/// Summary of a recorded run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunSummary {
+    /// Name of the run directory.
+    pub name: String,
+    /// Path of the run directory.
+    pub path: PathBuf,
+    /// When the run started, in seconds since the Unix epoch.
+    pub started: u64,
+    /// Time of the last telemetry sample, in seconds since the run started.
+    pub duration: f64,
+    /// Number of telemetry samples.
+    pub samples: usize,
+    /// Number of faults recorded.
+    pub faults: usize,
+    /// Bytes used by the run.
+    pub bytes: u64,
+}
+
+/// A telemetry sample read back from a run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// Seconds since the run started.
+    pub time: f64,
+    /// Recorded data.
+    pub data: Value,
+}
+
+/// A fault read back from a run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaultRecord {
+    /// Seconds since the run started.
+    pub time: f64,
+    /// Subsystem that failed.
+    pub subsystem: String,
+    /// Description of the failure.
+    pub message: String,
+}
+
+/// Span of a run, in seconds since it started, with either end open.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimeRange {
+    /// Earliest time included.
+    pub from: Option<f64>,
+    /// Latest time included.
+    pub to: Option<f64>,
+}
+
+impl TimeRange {
+    /// Returns `true` if `time` is within the range.
+    #[must_use]
+    pub fn contains(&self, time: f64) -> bool {
+        self.from.is_none_or(|from| time >= from) && self.to.is_none_or(|to| time <= to)
+    }
+}
+
+/// Returns a summary of every run in `root`, oldest first.
+///
+/// # Errors
+///
+/// This function will return an error if `root` or a run cannot be read.
+pub fn list_runs(root: &Path) -> Result<Vec<RunSummary>, Error> {
+    runs(root)?
+        .into_iter()
+        .map(|(path, bytes)| {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let started = name
+                .strip_prefix(RUN_PREFIX)
+                .and_then(|rest| rest.split('-').next())
+                .and_then(|seconds| seconds.parse().ok())
+                .unwrap_or(0);
+            let samples = read_telemetry(&path)?;
+            Ok(RunSummary {
+                duration: samples.last().map_or(0.0, |sample| sample.time),
+                faults: faults(&samples, TimeRange::default()).len(),
+                samples: samples.len(),
+                name,
+                path,
+                started,
+                bytes,
+            })
+        })
+        .collect()
+}
+
+/// Returns the telemetry samples recorded in the run at `run`.
+///
+/// A line that cannot be parsed, such as one cut short by a power loss, is
+/// logged and skipped.
+///
+/// # Errors
+///
+/// This function will return an error if the telemetry cannot be read.
+pub fn read_telemetry(run: &Path) -> Result<Vec<Sample>, Error> {
+    let file = match fs::File::open(run.join("telemetry.jsonl")) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let mut samples = Vec::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let sample = line.parse::<Value>().ok().and_then(|value| {
+            Some(Sample {
+                time: value.get("time")?.as_f64()?,
+                data: value.get("data")?.clone(),
+            })
+        });
+
+        match sample {
+            Some(sample) => samples.push(sample),
+            None if line.trim().is_empty() => {}
+            None => log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "skipping malformed line {} of {}",
+                index + 1,
+                run.display()
+            ),
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Returns the name of every channel in `samples`, sorted, leaving out
+/// faults.
+#[must_use]
+pub fn channels(samples: &[Sample]) -> Vec<String> {
+    let mut channels = Vec::new();
+
+    for sample in samples {
+        if sample.data.get(FAULT_KEY).is_none() {
+            flatten(&sample.data, "", &mut channels);
+        }
+    }
+
+    channels.sort_unstable();
+    channels.dedup();
+    channels
+}
+
+/// Writes the time and each of `channels` in `range` as CSV, one row per
+/// sample with any of them, leaving cells empty where a sample lacks one.
+///
+/// # Errors
+///
+/// This function will return an error if `out` cannot be written.
+pub fn write_csv(
+    samples: &[Sample],
+    channels: &[String],
+    range: TimeRange,
+    out: &mut impl Write,
+) -> Result<(), io::Error> {
+    let header: Vec<_> = channels.iter().map(|channel| csv_field(channel)).collect();
+    writeln!(out, "time,{}", header.join(","))?;
+
+    for sample in samples.iter().filter(|sample| range.contains(sample.time)) {
+        let cells: Vec<_> = channels
+            .iter()
+            .map(|channel| lookup(&sample.data, channel))
+            .collect();
+
+        if cells.iter().all(Option::is_none) {
+            continue;
+        }
+
+        let cells: Vec<_> = cells
+            .into_iter()
+            .map(|cell| match cell {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(text)) => csv_field(text),
+                Some(value) => value.to_string(),
+            })
+            .collect();
+        writeln!(out, "{},{}", sample.time, cells.join(","))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the faults in `samples` within `range`, in the order recorded.
+#[must_use]
+pub fn faults(samples: &[Sample], range: TimeRange) -> Vec<FaultRecord> {
+    samples
+        .iter()
+        .filter(|sample| range.contains(sample.time))
+        .filter_map(|sample| {
+            let fault = sample.data.get(FAULT_KEY)?;
+            let field = |key| fault.get(key).and_then(Value::as_str).unwrap_or_default();
+            Some(FaultRecord {
+                time: sample.time,
+                subsystem: field("subsystem").to_owned(),
+                message: field("message").to_owned(),
+            })
+        })
+        .collect()
+}
+
+fn flatten(value: &Value, prefix: &str, channels: &mut Vec<String>) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{prefix}.{key}")
+        }
+    };
+
+    match value {
+        Value::Object(entries) => {
+            for (key, value) in entries {
+                flatten(value, &join(key), channels);
+            }
+        }
+        Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                flatten(value, &join(&index.to_string()), channels);
+            }
+        }
+        _ if !prefix.is_empty() => channels.push(prefix.to_owned()),
+        _ => {}
+    }
+}
+
+fn lookup<'a>(value: &'a Value, channel: &str) -> Option<&'a Value> {
+    channel
+        .split('.')
+        .try_fold(value, |value, key| match value {
+            Value::Array(values) => values.get(key.parse::<usize>().ok()?),
+            _ => value.get(key),
+        })
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::TemporaryDirectory;
+
+    const TELEMETRY: &str = r#"{"time":0.5,"data":{"imu":{"accel":[0.1,0,9.8]},"mode":"idle"}}
+{"time":1,"data":{"battery":12.1}}
+{"time":1.5,"data":{"fault":{"subsystem":"lidar","message":"no data, timed out"}}}
+{"time":2,"data":{"imu":{"accel":[0.2,0,9.7]},"mode":"patrol, fast"}}
+{"time":2.5,"data":{"imu":"#;
+
+    fn run(root: &Path, name: &str) -> PathBuf {
+        let run = root.join(name);
+        fs::create_dir(&run).unwrap();
+        fs::write(run.join("telemetry.jsonl"), TELEMETRY).unwrap();
+        run
+    }
+
+    #[test]
+    fn it_should_list_runs_and_skip_truncated_lines() {
+        let root = TemporaryDirectory::new().unwrap();
+        run(root.path(), "run-000000000200");
+        run(root.path(), "run-000000000100-1");
+        let runs = list_runs(root.path()).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].started, 100);
+        assert_eq!(runs[1].name, "run-000000000200");
+        assert_eq!((runs[1].samples, runs[1].faults), (4, 1));
+        assert_eq!(runs[1].duration, 2.0);
+    }
+
+    #[test]
+    fn it_should_dump_channels_in_a_time_range_to_csv() {
+        let root = TemporaryDirectory::new().unwrap();
+        let samples = read_telemetry(&run(root.path(), "run-000000000100")).unwrap();
+        assert_eq!(
+            channels(&samples),
+            [
+                "battery",
+                "imu.accel.0",
+                "imu.accel.1",
+                "imu.accel.2",
+                "mode"
+            ]
+        );
+        let mut csv = Vec::new();
+        let selected = ["imu.accel.2".to_owned(), "mode".to_owned()];
+        let range = TimeRange {
+            from: Some(0.75),
+            to: None,
+        };
+        write_csv(&samples, &selected, range, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "time,imu.accel.2,mode\n2,9.7,\"patrol, fast\"\n"
+        );
+    }
+
+    #[test]
+    fn it_should_print_a_fault_timeline() {
+        let root = TemporaryDirectory::new().unwrap();
+        let samples = read_telemetry(&run(root.path(), "run-000000000100")).unwrap();
+        let faults = faults(&samples, TimeRange::default());
+        assert_eq!(
+            faults,
+            [FaultRecord {
+                time: 1.5,
+                subsystem: "lidar".to_owned(),
+                message: "no data, timed out".to_owned(),
+            }]
+        );
+        let later = TimeRange {
+            from: Some(1.6),
+            to: None,
+        };
+        assert!(super::faults(&samples, later).is_empty());
+    }
+}
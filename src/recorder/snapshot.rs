@@ -0,0 +1,390 @@
+//! Still photos taken when something happens.
+//!
+//! Where the [`Recorder`](super::Recorder) keeps a rolling video of the
+//! whole run, a snapshot is one picture worth keeping: whatever set off the
+//! motion sensor, what the bumper hit, a stop on a patrol, or whatever the
+//! operator asked to see. Each is saved as a JPEG with a JSON file beside it
+//! recording when and where it was taken and why. Motion and contact come in
+//! bursts, so those triggers are ignored for a cooldown after each picture;
+//! requests and patrol stops are always honoured. Only the newest snapshots
+//! are kept.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::camera::StillCamera;
+use crate::events::Event;
+use crate::geometry::Pose;
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+use crate::net::http::{Api, Response};
+
+const SUBSYSTEM: &str = "snapshot";
+
+/// Prefix of the name of every snapshot file.
+const PREFIX: &str = "snap-";
+
+/// Why a snapshot was taken.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Trigger {
+    /// The named sensor detected motion.
+    Motion(String),
+    /// The named bumper or contact switch was hit.
+    Contact(String),
+    /// Someone asked for one.
+    Request,
+    /// A patrol reached the named place.
+    Patrol(String),
+}
+
+impl Trigger {
+    fn is_bursty(&self) -> bool {
+        matches!(self, Self::Motion(_) | Self::Contact(_))
+    }
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Motion(sensor) => write!(f, "motion on {sensor}"),
+            Self::Contact(sensor) => write!(f, "contact on {sensor}"),
+            Self::Request => f.write_str("request"),
+            Self::Patrol(place) => write!(f, "patrol at {place}"),
+        }
+    }
+}
+
+/// A saved snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// Path of the JPEG.
+    pub path: PathBuf,
+    /// When it was taken.
+    pub taken: SystemTime,
+    /// Why it was taken.
+    pub trigger: Trigger,
+    /// Where the robot was.
+    pub pose: Pose,
+}
+
+impl ToJson for Snapshot {
+    fn to_json(&self) -> Value {
+        let time = self
+            .taken
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |duration| duration.as_secs_f64());
+        let file = self.path.file_name().map(|name| name.to_string_lossy());
+        Value::object()
+            .with("file", file.as_deref())
+            .with("time", time)
+            .with("trigger", self.trigger.to_string())
+            .with("pose", self.pose)
+    }
+}
+
+/// Takes and stores snapshots, shared by every handle cloned from it.
+#[derive(Clone)]
+pub struct Snapshots {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    camera: Box<dyn StillCamera + Send>,
+    cooldown: Duration,
+    dir: PathBuf,
+    keep: usize,
+    last_bursty: Option<SystemTime>,
+    saved: VecDeque<PathBuf>,
+}
+
+impl Snapshots {
+    /// Creates a new `Snapshots` saving pictures from `camera` in `dir`,
+    /// keeping the newest 500 and ignoring motion and contact for 10 s after
+    /// each.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory cannot be
+    /// created or listed.
+    pub fn create(dir: &Path, camera: impl StillCamera + Send + 'static) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let mut saved = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().map(|name| name.to_string_lossy());
+
+            if name.is_some_and(|name| name.starts_with(PREFIX) && name.ends_with(".jpg")) {
+                saved.push(path);
+            }
+        }
+
+        // Names embed zero-padded times, so they sort chronologically.
+        saved.sort_unstable();
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                camera: Box::new(camera),
+                cooldown: Duration::from_secs(10),
+                dir: dir.to_owned(),
+                keep: 500,
+                last_bursty: None,
+                saved: saved.into(),
+            })),
+        })
+    }
+
+    /// Ignores motion and contact for `cooldown` after each picture they
+    /// trigger.
+    #[must_use]
+    pub fn with_cooldown(self, cooldown: Duration) -> Self {
+        self.lock().cooldown = cooldown;
+        self
+    }
+
+    /// Keeps only the newest `keep` snapshots.
+    #[must_use]
+    pub fn with_keep(self, keep: usize) -> Self {
+        self.lock().keep = keep;
+        self
+    }
+
+    /// Takes a snapshot for `trigger` with the robot at `pose`, or returns
+    /// `None` if the trigger is within its cooldown.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the camera cannot be read or
+    /// the snapshot cannot be saved.
+    pub fn take(
+        &self,
+        trigger: Trigger,
+        pose: Pose,
+        now: SystemTime,
+    ) -> Result<Option<Snapshot>, Error> {
+        let mut inner = self.lock();
+
+        if trigger.is_bursty() {
+            let cooling = inner.last_bursty.is_some_and(|last| {
+                now.duration_since(last)
+                    .is_ok_and(|elapsed| elapsed < inner.cooldown)
+            });
+
+            if cooling {
+                return Ok(None);
+            }
+
+            inner.last_bursty = Some(now);
+        }
+
+        let jpeg = inner.camera.capture()?;
+        let millis = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis());
+        let path = unused_path(&inner.dir, millis)?;
+        let snapshot = Snapshot {
+            path,
+            taken: now,
+            trigger,
+            pose,
+        };
+        fs::write(&snapshot.path, jpeg)?;
+        fs::write(
+            snapshot.path.with_extension("json"),
+            snapshot.to_json().to_string(),
+        )?;
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "took {} for {}",
+            snapshot.path.display(),
+            snapshot.trigger
+        );
+        inner.saved.push_back(snapshot.path.clone());
+
+        while inner.saved.len() > inner.keep {
+            if let Some(oldest) = inner.saved.pop_front() {
+                remove_file(&oldest)?;
+                remove_file(&oldest.with_extension("json"))?;
+            }
+        }
+
+        Ok(Some(snapshot))
+    }
+
+    /// Takes a snapshot if `event` calls for one, such as motion being
+    /// detected.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the snapshot cannot be taken.
+    pub fn on_event(
+        &self,
+        event: &Event,
+        pose: Pose,
+        now: SystemTime,
+    ) -> Result<Option<Snapshot>, Error> {
+        match event {
+            Event::MotionDetected(motion) => {
+                self.take(Trigger::Motion(motion.sensor.clone()), pose, now)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the paths of the saved snapshots, oldest first.
+    #[must_use]
+    pub fn saved(&self) -> Vec<PathBuf> {
+        self.lock().saved.iter().cloned().collect()
+    }
+
+    /// Adds `POST /snapshot` to `api`, which takes a snapshot with the
+    /// robot at the pose returned by `pose` and answers with its metadata.
+    #[must_use]
+    pub fn route(&self, api: Api, pose: impl Fn() -> Pose + Send + Sync + 'static) -> Api {
+        let snapshots = self.clone();
+        api.route("POST", "/snapshot", move |_| {
+            match snapshots.take(Trigger::Request, pose(), SystemTime::now()) {
+                Ok(Some(snapshot)) => Response::json(&snapshot),
+                Ok(None) => Response::error(503, "snapshot was not taken"),
+                Err(error) => Response::error(500, &error.to_string()),
+            }
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Snapshots {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("Snapshots")
+            .field("dir", &inner.dir)
+            .field("saved", &inner.saved.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn unused_path(dir: &Path, millis: u128) -> Result<PathBuf, Error> {
+    let base = format!("{PREFIX}{millis:015}");
+
+    for attempt in 0.. {
+        let name = if attempt == 0 {
+            format!("{base}.jpg")
+        } else {
+            format!("{base}-{attempt}.jpg")
+        };
+        let path = dir.join(name);
+
+        if !path.try_exists()? {
+            return Ok(path);
+        }
+    }
+
+    unreachable!("some attempt should find an unused name")
+}
+
+fn remove_file(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::events::MotionDetected;
+    use crate::net::http::{Request, Router};
+    use crate::TemporaryDirectory;
+
+    struct FakeCamera;
+
+    impl StillCamera for FakeCamera {
+        fn capture(&mut self) -> Result<Vec<u8>, Error> {
+            Ok(b"\xff\xd8jpeg".to_vec())
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds)
+    }
+
+    #[test]
+    fn it_should_save_a_picture_with_its_metadata() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let snapshots = Snapshots::create(dir.path(), FakeCamera).unwrap();
+        let pose = Pose::new(1.0, 2.0, 0.5);
+        let snapshot = snapshots
+            .take(Trigger::Patrol("front door".to_owned()), pose, at(0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(fs::read(&snapshot.path).unwrap(), b"\xff\xd8jpeg");
+        let metadata: Value = fs::read_to_string(snapshot.path.with_extension("json"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            metadata.get("trigger").and_then(Value::as_str),
+            Some("patrol at front door")
+        );
+        assert_eq!(metadata.get("pose"), Some(&pose.to_json()));
+        assert_eq!(
+            metadata.get("time").and_then(Value::as_f64),
+            Some(1_700_000_000.0)
+        );
+    }
+
+    #[test]
+    fn it_should_cool_down_after_motion_but_not_requests() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let snapshots = Snapshots::create(dir.path(), FakeCamera)
+            .unwrap()
+            .with_keep(3);
+        let motion = Event::MotionDetected(MotionDetected {
+            sensor: "pir".to_owned(),
+        });
+        let take = |event: &Event, seconds| {
+            snapshots
+                .on_event(event, Pose::default(), at(seconds))
+                .unwrap()
+                .is_some()
+        };
+        assert!(take(&motion, 0));
+        assert!(!take(&motion, 5));
+        assert!(snapshots
+            .take(Trigger::Request, Pose::default(), at(5))
+            .unwrap()
+            .is_some());
+        assert!(take(&motion, 10));
+        assert!(take(&motion, 20));
+        // Only the newest three are kept.
+        let saved = snapshots.saved();
+        assert_eq!(saved.len(), 3);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 6);
+        assert!(saved[0].ends_with("snap-001700000005000.jpg"));
+    }
+
+    #[test]
+    fn it_should_take_a_snapshot_on_request_over_http() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let snapshots = Snapshots::create(dir.path(), FakeCamera).unwrap();
+        let api = snapshots.route(Api::new(), || Pose::new(3.0, 0.0, 0.0));
+        let router = Router::new().with_api(1, api);
+        let response = router.handle(&Request::new("POST", "/api/v1/snapshot"));
+        assert_eq!(response.status, 200);
+        let body: Value = std::str::from_utf8(&response.body)
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(body.get("trigger").and_then(Value::as_str), Some("request"));
+        assert_eq!(snapshots.saved().len(), 1);
+    }
+}
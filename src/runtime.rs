@@ -0,0 +1,13 @@
+//! Building blocks for running the robot’s subsystems concurrently.
+
+pub mod actors;
+pub mod panic_hook;
+pub mod periodic;
+pub mod robot;
+#[cfg(unix)]
+pub mod rpc;
+pub mod schedule;
+pub mod snapshot;
+
+pub use robot::Robot;
+pub use snapshot::snapshot;
@@ -0,0 +1,447 @@
+//! Actor-style concurrency with bounded mailboxes and panic isolation.
+//!
+//! Each actor owns its state and runs on a dedicated thread, handling one
+//! message at a time from a bounded mailbox. Senders block or fail when the
+//! mailbox is full, which makes backpressure explicit, and a panic while
+//! handling a message stops only the actor that panicked.
+
+use std::any::Any;
+use std::fmt::{self, Debug, Formatter};
+use std::io::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::log_event;
+use crate::logging::Level;
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "actors";
+
+/// A unit of state that reacts to messages sent to its mailbox.
+pub trait Actor: Send + 'static {
+    /// Type of message handled by the actor.
+    type Message: Send + 'static;
+
+    /// Handles a single message.
+    fn handle(&mut self, message: Self::Message);
+
+    /// Called on the actor’s thread before the first message is handled.
+    fn started(&mut self) {}
+
+    /// Called on the actor’s thread after the last message is handled, unless
+    /// the actor panicked.
+    fn stopped(&mut self) {}
+}
+
+/// Error returned when a message cannot be delivered to an actor.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum SendError<M> {
+    /// The mailbox is full. Only returned when sending without blocking.
+    Full(M),
+    /// The actor has stopped and will not handle any more messages.
+    Stopped(M),
+}
+
+impl<M> SendError<M> {
+    /// Returns the message that could not be delivered.
+    pub fn into_inner(self) -> M {
+        match self {
+            Self::Full(message) | Self::Stopped(message) => message,
+        }
+    }
+}
+
+impl<M> Debug for SendError<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("Full(..)"),
+            Self::Stopped(_) => f.write_str("Stopped(..)"),
+        }
+    }
+}
+
+impl<M> fmt::Display for SendError<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("actor mailbox is full"),
+            Self::Stopped(_) => f.write_str("actor has stopped"),
+        }
+    }
+}
+
+impl<M> std::error::Error for SendError<M> {}
+
+enum Envelope<M> {
+    Message(M),
+    Stop,
+}
+
+/// A cloneable handle for sending messages to an actor.
+pub struct Address<M> {
+    sender: SyncSender<Envelope<M>>,
+}
+
+impl<M> Address<M> {
+    /// Sends a message, blocking while the mailbox is full.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the actor has stopped.
+    pub fn send(&self, message: M) -> Result<(), SendError<M>> {
+        self.sender
+            .send(Envelope::Message(message))
+            .map_err(|error| SendError::Stopped(unwrap_envelope(error.0)))
+    }
+
+    /// Sends a message without blocking.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the mailbox is full or the actor
+    /// has stopped.
+    pub fn try_send(&self, message: M) -> Result<(), SendError<M>> {
+        self.sender
+            .try_send(Envelope::Message(message))
+            .map_err(|error| match error {
+                TrySendError::Full(envelope) => SendError::Full(unwrap_envelope(envelope)),
+                TrySendError::Disconnected(envelope) => {
+                    SendError::Stopped(unwrap_envelope(envelope))
+                }
+            })
+    }
+}
+
+impl<M> Clone for Address<M> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<M> Debug for Address<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Address").finish_non_exhaustive()
+    }
+}
+
+/// Reason an actor stopped running.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Exit {
+    /// The actor was stopped through its handle.
+    Stopped,
+    /// The actor panicked while handling a message.
+    Panicked(String),
+}
+
+#[derive(Debug, Default)]
+struct Status {
+    panic_message: Mutex<Option<String>>,
+    running: AtomicBool,
+}
+
+/// Owning handle to a running actor.
+///
+/// The actor is stopped once every queued message has been handled when the
+/// handle goes out of scope.
+#[derive(Debug)]
+pub struct ActorHandle<M> {
+    address: Address<M>,
+    name: String,
+    status: Arc<Status>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<M> ActorHandle<M> {
+    /// Returns an address that can be used to send messages to the actor.
+    #[must_use]
+    pub fn address(&self) -> Address<M> {
+        self.address.clone()
+    }
+
+    /// Returns the name of the actor.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns `true` if the actor is still handling messages.
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.status.running.load(Ordering::Acquire)
+    }
+
+    /// Sends a message, blocking while the mailbox is full.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the actor has stopped.
+    pub fn send(&self, message: M) -> Result<(), SendError<M>> {
+        self.address.send(message)
+    }
+
+    /// Sends a message without blocking.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the mailbox is full or the actor
+    /// has stopped.
+    pub fn try_send(&self, message: M) -> Result<(), SendError<M>> {
+        self.address.try_send(message)
+    }
+
+    /// Stops the actor after it has handled every queued message and waits for
+    /// its thread to finish.
+    pub fn stop(mut self) -> Exit {
+        self.shut_down()
+    }
+
+    fn shut_down(&mut self) -> Exit {
+        let _ = self.address.sender.send(Envelope::Stop);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        let panic_message = self
+            .status
+            .panic_message
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .clone();
+
+        match panic_message {
+            Some(message) => Exit::Panicked(message),
+            None => Exit::Stopped,
+        }
+    }
+}
+
+impl<M> Drop for ActorHandle<M> {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.shut_down();
+        }
+    }
+}
+
+/// Spawns `actor` on a new thread named `name` with a mailbox that holds up to
+/// `capacity` messages.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::mpsc;
+///
+/// use otter_pi::runtime::actors::{self, Actor};
+///
+/// struct Counter(u32, mpsc::Sender<u32>);
+///
+/// impl Actor for Counter {
+///     type Message = u32;
+///
+///     fn handle(&mut self, message: u32) {
+///         self.0 += message;
+///         self.1.send(self.0).unwrap();
+///     }
+/// }
+///
+/// let (sender, receiver) = mpsc::channel();
+/// let counter = actors::spawn("counter", Counter(0, sender), 8).unwrap();
+/// counter.send(2).unwrap();
+/// counter.send(3).unwrap();
+/// assert_eq!(receiver.iter().take(2).last(), Some(5));
+/// ```
+///
+/// # Errors
+///
+/// This function will return an error if the thread cannot be spawned.
+pub fn spawn<A: Actor>(
+    name: &str,
+    mut actor: A,
+    capacity: usize,
+) -> Result<ActorHandle<A::Message>, Error> {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let status = Arc::new(Status {
+        running: AtomicBool::new(true),
+        ..Default::default()
+    });
+    let thread_status = Arc::clone(&status);
+    let thread_name = name.to_owned();
+
+    let thread = thread::Builder::new()
+        .name(name.to_owned())
+        .spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| run(&mut actor, &receiver)));
+            drop(receiver);
+
+            if let Err(payload) = result {
+                let message = panic_message(payload.as_ref());
+                log_event!(
+                    SUBSYSTEM,
+                    Level::Error,
+                    "actor `{thread_name}` panicked: {message}"
+                );
+                *thread_status
+                    .panic_message
+                    .lock()
+                    .unwrap_or_else(|error| error.into_inner()) = Some(message);
+            }
+
+            thread_status.running.store(false, Ordering::Release);
+        })?;
+
+    Ok(ActorHandle {
+        address: Address { sender },
+        name: name.to_owned(),
+        status,
+        thread: Some(thread),
+    })
+}
+
+fn run<A: Actor>(actor: &mut A, receiver: &Receiver<Envelope<A::Message>>) {
+    actor.started();
+
+    while let Ok(Envelope::Message(message)) = receiver.recv() {
+        actor.handle(message);
+    }
+
+    actor.stopped();
+}
+
+/// Extracts a readable message from a panic payload.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic payload")
+    }
+}
+
+fn unwrap_envelope<M>(envelope: Envelope<M>) -> M {
+    match envelope {
+        Envelope::Message(message) => message,
+        Envelope::Stop => unreachable!("only messages are sent through an address"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Sender;
+    use std::time::Duration;
+
+    use super::*;
+
+    struct Echo {
+        events: Sender<String>,
+    }
+
+    impl Actor for Echo {
+        type Message = &'static str;
+
+        fn handle(&mut self, message: &'static str) {
+            if message == "panic" {
+                panic!("asked to panic");
+            }
+
+            self.events.send(message.to_owned()).unwrap();
+        }
+
+        fn started(&mut self) {
+            self.events.send(String::from("started")).unwrap();
+        }
+
+        fn stopped(&mut self) {
+            self.events.send(String::from("stopped")).unwrap();
+        }
+    }
+
+    fn spawn_echo(capacity: usize) -> (ActorHandle<&'static str>, Receiver<String>) {
+        let (events, receiver) = mpsc::channel();
+        let handle = spawn("echo", Echo { events }, capacity).unwrap();
+        (handle, receiver)
+    }
+
+    #[test]
+    fn it_should_handle_messages_in_order_between_lifecycle_hooks() {
+        let (handle, events) = spawn_echo(4);
+        handle.send("a").unwrap();
+        handle.address().send("b").unwrap();
+        assert_eq!(handle.stop(), Exit::Stopped);
+        let events: Vec<_> = events.iter().collect();
+        assert_eq!(events, ["started", "a", "b", "stopped"]);
+    }
+
+    #[test]
+    fn it_should_name_the_actor_thread() {
+        let (handle, _events) = spawn_echo(1);
+        assert_eq!(handle.name(), "echo");
+    }
+
+    #[test]
+    fn it_should_isolate_a_panic_to_the_actor() {
+        let (handle, events) = spawn_echo(4);
+        handle.send("panic").unwrap();
+        assert_eq!(events.recv().unwrap(), "started");
+        assert!(events.recv().is_err());
+        assert_eq!(
+            handle.stop(),
+            Exit::Panicked(String::from("asked to panic"))
+        );
+    }
+
+    #[test]
+    fn it_should_reject_messages_after_panicking() {
+        let (handle, events) = spawn_echo(4);
+        handle.send("panic").unwrap();
+        while events.recv().is_ok() {}
+
+        while handle.is_running() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(handle.try_send("a"), Err(SendError::Stopped("a")));
+    }
+
+    #[test]
+    fn it_should_apply_backpressure_when_the_mailbox_is_full() {
+        struct Blocked(Receiver<()>);
+
+        impl Actor for Blocked {
+            type Message = ();
+
+            fn handle(&mut self, (): ()) {
+                let _ = self.0.recv();
+            }
+        }
+
+        let (unblock, blocked) = mpsc::channel();
+        let handle = spawn("blocked", Blocked(blocked), 1).unwrap();
+        let mut result = Ok(());
+
+        for _ in 0..3 {
+            result = handle.try_send(());
+        }
+
+        assert_eq!(result, Err(SendError::Full(())));
+        drop(unblock);
+    }
+
+    #[test]
+    fn it_should_return_the_undelivered_message() {
+        assert_eq!(SendError::Full(7).into_inner(), 7);
+        assert_eq!(SendError::Stopped(8).into_inner(), 8);
+    }
+
+    #[test]
+    fn it_should_extract_panic_messages() {
+        assert_eq!(panic_message(&"foo"), "foo");
+        assert_eq!(panic_message(&String::from("bar")), "bar");
+        assert_eq!(panic_message(&1), "unknown panic payload");
+    }
+}
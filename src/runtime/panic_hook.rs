@@ -0,0 +1,180 @@
+//! A panic hook that brings the robot to a safe state before unwinding.
+//!
+//! Rust only unwinds the thread that panicked, so a panic on a telemetry or
+//! network thread would otherwise leave the control loop and its motors
+//! running. Installing this hook stops the robot on a panic from any thread.
+
+use std::backtrace::Backtrace;
+use std::fmt::{self, Debug, Formatter};
+use std::panic::{self, PanicHookInfo};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use crate::log_event;
+use crate::logging::Level;
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "panic";
+
+type Action = Arc<dyn Fn() + Send + Sync>;
+
+/// Builder for the crate-provided panic hook.
+///
+/// Actions run on the panicking thread, so they must not block indefinitely or
+/// panic themselves; a panic inside a panic hook aborts the process.
+///
+/// # Examples
+///
+/// ```no_run
+/// use otter_pi::runtime::panic_hook::PanicHook;
+///
+/// PanicHook::new()
+///     .on_estop(|| { /* cut power to the motor drivers */ })
+///     .on_flush(|| { /* flush the black box recorder */ })
+///     .install();
+/// ```
+#[derive(Clone, Default)]
+pub struct PanicHook {
+    abort: bool,
+    estop_actions: Vec<Action>,
+    flush_actions: Vec<Action>,
+}
+
+impl PanicHook {
+    /// Creates a new `PanicHook` without any actions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an action that brings actuators to a safe state.
+    ///
+    /// E-stop actions run before anything else, in the order they were added.
+    #[must_use]
+    pub fn on_estop(mut self, action: impl Fn() + Send + Sync + 'static) -> Self {
+        self.estop_actions.push(Arc::new(action));
+        self
+    }
+
+    /// Adds an action that persists buffered state, such as the black box.
+    ///
+    /// Flush actions run after every e-stop action, in the order they were
+    /// added.
+    #[must_use]
+    pub fn on_flush(mut self, action: impl Fn() + Send + Sync + 'static) -> Self {
+        self.flush_actions.push(Arc::new(action));
+        self
+    }
+
+    /// Aborts the process after the hook has run instead of unwinding.
+    #[must_use]
+    pub fn abort(mut self, abort: bool) -> Self {
+        self.abort = abort;
+        self
+    }
+
+    /// Brings the robot to a safe state by running every e-stop action and
+    /// then every flush action.
+    pub fn safe_the_robot(&self) {
+        for action in self.estop_actions.iter().chain(&self.flush_actions) {
+            action();
+        }
+    }
+
+    /// Installs the hook for every thread, chaining to the previously
+    /// installed hook after the robot has been made safe.
+    pub fn install(self) {
+        let previous = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info: &PanicHookInfo<'_>| {
+            self.safe_the_robot();
+            let thread = thread::current();
+            let name = thread.name().unwrap_or("<unnamed>");
+            log_event!(
+                SUBSYSTEM,
+                Level::Error,
+                "thread `{name}` {info}; robot stopped\n{}",
+                Backtrace::force_capture()
+            );
+            previous(info);
+
+            if self.abort {
+                process::abort();
+            }
+        }));
+    }
+}
+
+impl Debug for PanicHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PanicHook")
+            .field("abort", &self.abort)
+            .field("estop_actions", &self.estop_actions.len())
+            .field("flush_actions", &self.flush_actions.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Serializes the tests that replace the process-wide panic hook.
+    static HOOK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn it_should_run_estop_actions_before_flush_actions() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (flush_order, estop_order) = (Arc::clone(&order), Arc::clone(&order));
+        let hook = PanicHook::new()
+            .on_flush(move || flush_order.lock().unwrap().push("flush"))
+            .on_estop(move || estop_order.lock().unwrap().push("estop"));
+        hook.safe_the_robot();
+        assert_eq!(*order.lock().unwrap(), ["estop", "flush"]);
+    }
+
+    #[test]
+    fn it_should_not_abort_by_default() {
+        assert!(!PanicHook::new().abort);
+    }
+
+    #[test]
+    fn it_should_run_every_action_each_time_the_robot_is_made_safe() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (estop_runs, flush_runs) = (Arc::clone(&runs), Arc::clone(&runs));
+        let hook = PanicHook::new()
+            .on_estop(move || {
+                estop_runs.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_flush(move || {
+                flush_runs.fetch_add(1, Ordering::SeqCst);
+            });
+        hook.safe_the_robot();
+        hook.clone().safe_the_robot();
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn it_should_stop_the_robot_when_another_thread_panics() {
+        let _guard = HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let original = panic::take_hook();
+        let stops = Arc::new(AtomicUsize::new(0));
+        let hook_stops = Arc::clone(&stops);
+        PanicHook::new()
+            .on_estop(move || {
+                hook_stops.fetch_add(1, Ordering::SeqCst);
+            })
+            .install();
+        let result = thread::spawn(|| panic!("telemetry failed")).join();
+        // Restore the hook the test harness installed, rather than the
+        // default one left behind by taking ours.
+        drop(panic::take_hook());
+        panic::set_hook(original);
+        assert!(result.is_err());
+        // Tests panicking on other threads meanwhile also stop the robot.
+        assert!(stops.load(Ordering::SeqCst) >= 1);
+    }
+}
@@ -0,0 +1,394 @@
+//! Fixed-rate loops, such as the control loop, and what to do when a tick
+//! runs over its period.
+//!
+//! A [`PeriodicTask`] keeps its ticks on a fixed grid, so the period does not
+//! drift by the time the body takes. When a tick does not finish before the
+//! next one was due, its [`OverrunPolicy`] decides what happens, and the
+//! overrun is counted in [`OverrunStats`], which diagnostics can read from
+//! another thread.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{Event, EventBus, Fault};
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::{self, Level};
+
+const SUBSYSTEM: &str = "periodic";
+
+/// What a [`PeriodicTask`] does when a tick overruns its period.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OverrunPolicy {
+    /// Drops the ticks that were missed and waits for the next one on the
+    /// grid, keeping the phase.
+    SkipTick,
+    /// Runs the next tick at once and continues the grid from there, so
+    /// no tick is dropped but the phase shifts.
+    RunLate,
+    /// Doubles the period, up to `max_period`, and halves it again after
+    /// `recover_after` ticks in a row finish in time.
+    DegradeRate {
+        /// Longest period to degrade to.
+        max_period: Duration,
+        /// Ticks in a row that must finish in time before the period is
+        /// halved.
+        recover_after: u32,
+    },
+    /// Publishes a [`Fault`] on the task’s bus when overruns start, then
+    /// skips ticks as [`OverrunPolicy::SkipTick`] does.
+    RaiseFault,
+}
+
+/// Counts of the overruns of a task.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OverrunCounters {
+    /// Ticks run.
+    pub ticks: u64,
+    /// Ticks that overran their period.
+    pub overruns: u64,
+    /// Ticks dropped to catch up.
+    pub skipped: u64,
+    /// Times the period was degraded.
+    pub degraded: u64,
+    /// Faults raised.
+    pub faults: u64,
+    /// Longest time a tick ran past its period.
+    pub worst: Duration,
+    /// Current period, which differs from the nominal one while degraded.
+    pub period: Duration,
+}
+
+impl ToJson for OverrunCounters {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("ticks", self.ticks)
+            .with("overruns", self.overruns)
+            .with("skipped", self.skipped)
+            .with("degraded", self.degraded)
+            .with("faults", self.faults)
+            .with("worst", self.worst.as_secs_f64())
+            .with("period", self.period.as_secs_f64())
+    }
+}
+
+/// Shared handle to the overrun counters of a task.
+#[derive(Clone, Debug, Default)]
+pub struct OverrunStats {
+    counters: Arc<Mutex<OverrunCounters>>,
+}
+
+impl OverrunStats {
+    /// Returns the counters as they are now.
+    #[must_use]
+    pub fn get(&self) -> OverrunCounters {
+        *self.lock()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, OverrunCounters> {
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A task run at a fixed rate.
+pub struct PeriodicTask {
+    bus: Option<EventBus>,
+    in_time: u32,
+    name: String,
+    next: Option<Instant>,
+    overrunning: bool,
+    period: Duration,
+    nominal: Duration,
+    policy: OverrunPolicy,
+    stats: OverrunStats,
+}
+
+impl PeriodicTask {
+    /// Creates a new `PeriodicTask` named `name`, ticking every `period` and
+    /// skipping ticks on overruns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn new(name: &str, period: Duration) -> Self {
+        assert!(!period.is_zero(), "period should not be zero");
+        let stats = OverrunStats::default();
+        stats.lock().period = period;
+        Self {
+            bus: None,
+            in_time: 0,
+            name: name.to_owned(),
+            next: None,
+            overrunning: false,
+            period,
+            nominal: period,
+            policy: OverrunPolicy::SkipTick,
+            stats,
+        }
+    }
+
+    /// Handles overruns with `policy`.
+    #[must_use]
+    pub fn with_policy(mut self, policy: OverrunPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Publishes faults raised by [`OverrunPolicy::RaiseFault`] on `bus`.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Returns the name of the task.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the current period.
+    #[must_use]
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns a handle to the task’s overrun counters.
+    #[must_use]
+    pub fn stats(&self) -> OverrunStats {
+        self.stats.clone()
+    }
+
+    /// Returns when the next tick is due, which is `now` before the first.
+    pub fn next_tick(&mut self, now: Instant) -> Instant {
+        *self.next.get_or_insert(now)
+    }
+
+    /// Records that the tick that was due finished at `now`, and returns
+    /// when the next one is due.
+    pub fn finish_tick(&mut self, now: Instant) -> Instant {
+        let due = self.next_tick(now);
+        let mut next = due + self.period;
+        let mut stats = self.stats.lock();
+        stats.ticks += 1;
+
+        if now <= next {
+            self.overrunning = false;
+            self.in_time = self.in_time.saturating_add(1);
+
+            if let OverrunPolicy::DegradeRate { recover_after, .. } = self.policy {
+                if self.period > self.nominal && self.in_time >= recover_after {
+                    self.period = (self.period / 2).max(self.nominal);
+                    self.in_time = 0;
+                    stats.period = self.period;
+                    log_event!(
+                        SUBSYSTEM,
+                        Level::Info,
+                        "{} recovered to {:?}",
+                        self.name,
+                        self.period
+                    );
+                }
+            }
+
+            self.next = Some(next);
+            return next;
+        }
+
+        let late_by = now - next;
+        stats.overruns += 1;
+        stats.worst = stats.worst.max(late_by);
+        self.in_time = 0;
+
+        if !self.overrunning {
+            log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "{} overran its {:?} period by {late_by:?}",
+                self.name,
+                self.period
+            );
+        }
+
+        match self.policy {
+            OverrunPolicy::SkipTick => next = self.skip(next, now, &mut stats),
+            OverrunPolicy::RunLate => next = now,
+            OverrunPolicy::DegradeRate { max_period, .. } => {
+                if self.period < max_period {
+                    self.period = (self.period * 2).min(max_period);
+                    stats.degraded += 1;
+                    stats.period = self.period;
+                    log_event!(
+                        SUBSYSTEM,
+                        Level::Warn,
+                        "{} degraded to {:?}",
+                        self.name,
+                        self.period
+                    );
+                }
+
+                next = now + self.period;
+            }
+            OverrunPolicy::RaiseFault => {
+                if !self.overrunning {
+                    stats.faults += 1;
+
+                    if let Some(bus) = &self.bus {
+                        bus.publish(Event::Fault(Fault {
+                            subsystem: self.name.clone(),
+                            message: format!("overran its {:?} period by {late_by:?}", self.period),
+                        }));
+                    }
+                }
+
+                next = self.skip(next, now, &mut stats);
+            }
+        }
+
+        self.overrunning = true;
+        self.next = Some(next);
+        next
+    }
+
+    /// Runs `tick` on every tick, sleeping in between, until it returns
+    /// `false`.
+    ///
+    /// Each tick runs in a [`tick_span`](logging::tick_span) under the name
+    /// of the task.
+    pub fn run(&mut self, mut tick: impl FnMut() -> bool) {
+        loop {
+            let due = self.next_tick(Instant::now());
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+            let ticks = self.stats.lock().ticks;
+            let running = {
+                let _span = logging::tick_span(&self.name, ticks);
+                tick()
+            };
+
+            if !running {
+                return;
+            }
+
+            self.finish_tick(Instant::now());
+        }
+    }
+
+    /// Returns the first tick on the grid from `next` that is still to come
+    /// at `now`, counting the ones skipped.
+    fn skip(&self, next: Instant, now: Instant, stats: &mut OverrunCounters) -> Instant {
+        let period = self.period.as_nanos();
+        let missed = ((now - next).as_nanos() / period + 1) as u32;
+        stats.skipped += u64::from(missed);
+        next + self.period * missed
+    }
+}
+
+impl Debug for PeriodicTask {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeriodicTask")
+            .field("name", &self.name)
+            .field("period", &self.period)
+            .field("policy", &self.policy)
+            .field("stats", &self.stats.get())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn it_should_keep_ticks_on_the_grid() {
+        let mut task = PeriodicTask::new("control", PERIOD);
+        let start = Instant::now();
+        assert_eq!(task.next_tick(start), start);
+        assert_eq!(task.finish_tick(start + ms(3)), start + ms(10));
+        assert_eq!(task.finish_tick(start + ms(19)), start + ms(20));
+        assert_eq!(task.stats().get().overruns, 0);
+    }
+
+    #[test]
+    fn it_should_skip_missed_ticks() {
+        let mut task = PeriodicTask::new("control", PERIOD);
+        let start = Instant::now();
+        task.next_tick(start);
+        assert_eq!(task.finish_tick(start + ms(35)), start + ms(40));
+        let stats = task.stats().get();
+        assert_eq!((stats.overruns, stats.skipped), (1, 3));
+        assert_eq!(stats.worst, ms(25));
+    }
+
+    #[test]
+    fn it_should_run_late_without_skipping() {
+        let mut task = PeriodicTask::new("control", PERIOD).with_policy(OverrunPolicy::RunLate);
+        let start = Instant::now();
+        task.next_tick(start);
+        assert_eq!(task.finish_tick(start + ms(15)), start + ms(15));
+        assert_eq!(task.finish_tick(start + ms(17)), start + ms(25));
+        assert_eq!(task.stats().get().skipped, 0);
+    }
+
+    #[test]
+    fn it_should_degrade_and_recover_the_rate() {
+        let mut task =
+            PeriodicTask::new("vision", PERIOD).with_policy(OverrunPolicy::DegradeRate {
+                max_period: ms(30),
+                recover_after: 2,
+            });
+        let start = Instant::now();
+        task.next_tick(start);
+        assert_eq!(task.finish_tick(start + ms(25)), start + ms(45));
+        assert_eq!(task.period(), ms(20));
+        assert_eq!(task.finish_tick(start + ms(70)), start + ms(100));
+        assert_eq!(task.period(), ms(30));
+        task.finish_tick(start + ms(101));
+        task.finish_tick(start + ms(131));
+        assert_eq!(task.period(), ms(15));
+        let stats = task.stats().get();
+        assert_eq!((stats.degraded, stats.period), (2, ms(15)));
+    }
+
+    #[test]
+    fn it_should_raise_a_fault_once_per_streak_of_overruns() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(8);
+        let mut task = PeriodicTask::new("control", PERIOD)
+            .with_policy(OverrunPolicy::RaiseFault)
+            .with_bus(bus);
+        let start = Instant::now();
+        task.next_tick(start);
+        task.finish_tick(start + ms(15));
+        task.finish_tick(start + ms(45));
+        task.finish_tick(start + ms(51));
+        task.finish_tick(start + ms(75));
+        let faults: Vec<_> = std::iter::from_fn(|| subscription.try_recv()).collect();
+        assert_eq!(faults.len(), 2);
+        assert!(matches!(&faults[0], Event::Fault(fault) if fault.subsystem == "control"));
+        assert_eq!(task.stats().get().faults, 2);
+        assert_eq!(
+            task.stats().get().to_json().get("faults"),
+            Some(&Value::Number(2.0))
+        );
+    }
+
+    #[test]
+    fn it_should_run_until_the_body_stops() {
+        let mut task = PeriodicTask::new("control", Duration::from_millis(1));
+        let mut count = 0;
+        task.run(|| {
+            count += 1;
+            count < 3
+        });
+        assert_eq!(count, 3);
+        assert_eq!(task.stats().get().ticks, 2);
+    }
+}
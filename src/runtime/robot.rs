@@ -0,0 +1,445 @@
+//! Starting the robot’s subsystems in dependency order.
+//!
+//! Each subsystem names the subsystems it needs. The [`Robot`] sorts them
+//! into stages, where every subsystem depends only on earlier stages, and
+//! starts each stage concurrently once the one before it is up. A cycle or a
+//! missing dependency is reported before anything starts, rather than
+//! leaving two subsystems waiting on each other forever.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "robot";
+
+type Start = Box<dyn FnOnce() -> Result<(), io::Error> + Send>;
+
+struct Subsystem {
+    dependencies: Vec<String>,
+    name: String,
+    start: Start,
+}
+
+/// Error returned when the robot cannot start.
+#[derive(Debug)]
+pub enum StartupError {
+    /// A subsystem is registered more than once.
+    Duplicate(String),
+    /// A subsystem depends on one that is not registered.
+    MissingDependency {
+        /// Name of the subsystem.
+        subsystem: String,
+        /// Name of the dependency that is not registered.
+        dependency: String,
+    },
+    /// Subsystems depend on each other, listed in dependency order with the
+    /// first repeated at the end.
+    Cycle(Vec<String>),
+    /// A subsystem failed to start.
+    Failed {
+        /// Name of the subsystem.
+        subsystem: String,
+        /// Error returned by the subsystem.
+        error: io::Error,
+    },
+}
+
+impl Display for StartupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duplicate(name) => write!(f, "subsystem `{name}` registered twice"),
+            Self::MissingDependency {
+                subsystem,
+                dependency,
+            } => write!(
+                f,
+                "subsystem `{subsystem}` depends on `{dependency}`, which is not registered"
+            ),
+            Self::Cycle(cycle) => write!(f, "dependency cycle: {}", cycle.join(" -> ")),
+            Self::Failed { subsystem, error } => {
+                write!(f, "subsystem `{subsystem}` failed to start: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StartupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Failed { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Subsystems started together, and how long each took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stage {
+    /// Time taken by each subsystem, in registration order.
+    pub subsystems: Vec<(String, Duration)>,
+    /// Time taken by the whole stage.
+    pub duration: Duration,
+}
+
+/// How the robot started.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StartupReport {
+    /// Stages in the order they started.
+    pub stages: Vec<Stage>,
+}
+
+impl StartupReport {
+    /// Returns the time taken by every stage.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.stages.iter().map(|stage| stage.duration).sum()
+    }
+}
+
+impl Display for StartupReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, stage) in self.stages.iter().enumerate() {
+            write!(f, "stage {} ({:?}):", index + 1, stage.duration)?;
+
+            for (name, duration) in &stage.subsystems {
+                write!(f, " {name} ({duration:?})")?;
+            }
+
+            writeln!(f)?;
+        }
+
+        write!(f, "started in {:?}", self.duration())
+    }
+}
+
+impl ToJson for StartupReport {
+    fn to_json(&self) -> Value {
+        let stages: Vec<_> = self
+            .stages
+            .iter()
+            .map(|stage| {
+                let subsystems = stage
+                    .subsystems
+                    .iter()
+                    .fold(Value::object(), |object, (name, duration)| {
+                        object.with(name, duration.as_secs_f64())
+                    });
+                Value::object()
+                    .with("duration", stage.duration.as_secs_f64())
+                    .with("subsystems", subsystems)
+            })
+            .collect();
+        Value::object()
+            .with("duration", self.duration().as_secs_f64())
+            .with("stages", stages)
+    }
+}
+
+/// Builder for the robot, starting its subsystems in dependency order.
+#[derive(Default)]
+pub struct Robot {
+    subsystems: Vec<Subsystem>,
+}
+
+impl Robot {
+    /// Creates a new `Robot` without subsystems.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a subsystem named `name`, started by `start` once every
+    /// subsystem in `dependencies` has started.
+    #[must_use]
+    pub fn with_subsystem(
+        mut self,
+        name: &str,
+        dependencies: &[&str],
+        start: impl FnOnce() -> Result<(), io::Error> + Send + 'static,
+    ) -> Self {
+        self.subsystems.push(Subsystem {
+            dependencies: dependencies.iter().map(|&name| name.to_owned()).collect(),
+            name: name.to_owned(),
+            start: Box::new(start),
+        });
+        self
+    }
+
+    /// Returns the dependencies of each subsystem, by name.
+    #[must_use]
+    pub fn graph(&self) -> BTreeMap<&str, Vec<&str>> {
+        self.subsystems
+            .iter()
+            .map(|subsystem| {
+                let dependencies = subsystem.dependencies.iter().map(String::as_str);
+                (subsystem.name.as_str(), dependencies.collect())
+            })
+            .collect()
+    }
+
+    /// Returns the names of the subsystems in each stage, in the order the
+    /// stages would start.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a subsystem is registered
+    /// twice, depends on one that is not registered, or is part of a cycle.
+    pub fn stages(&self) -> Result<Vec<Vec<&str>>, StartupError> {
+        let graph = self.graph();
+
+        if graph.len() < self.subsystems.len() {
+            let mut seen = BTreeSet::new();
+            let duplicate = self
+                .subsystems
+                .iter()
+                .find(|subsystem| !seen.insert(subsystem.name.as_str()))
+                .map(|subsystem| subsystem.name.clone())
+                .unwrap_or_default();
+            return Err(StartupError::Duplicate(duplicate));
+        }
+
+        for subsystem in &self.subsystems {
+            if let Some(dependency) = subsystem
+                .dependencies
+                .iter()
+                .find(|dependency| !graph.contains_key(dependency.as_str()))
+            {
+                return Err(StartupError::MissingDependency {
+                    subsystem: subsystem.name.clone(),
+                    dependency: dependency.clone(),
+                });
+            }
+        }
+
+        let mut started = BTreeSet::new();
+        let mut stages = Vec::new();
+
+        while started.len() < self.subsystems.len() {
+            let stage: Vec<&str> = self
+                .subsystems
+                .iter()
+                .map(|subsystem| subsystem.name.as_str())
+                .filter(|name| !started.contains(name))
+                .filter(|name| {
+                    graph[name]
+                        .iter()
+                        .all(|dependency| started.contains(dependency))
+                })
+                .collect();
+
+            if stage.is_empty() {
+                return Err(StartupError::Cycle(find_cycle(&graph, &started)));
+            }
+
+            started.extend(stage.iter().copied());
+            stages.push(stage);
+        }
+
+        Ok(stages)
+    }
+
+    /// Starts every subsystem, one stage at a time, and returns how long
+    /// each took.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error, without starting anything, if
+    /// the dependencies cannot be satisfied, or, without starting later
+    /// stages, if a subsystem fails to start.
+    pub fn start(mut self) -> Result<StartupReport, StartupError> {
+        let stages: Vec<Vec<String>> = self
+            .stages()?
+            .into_iter()
+            .map(|stage| stage.into_iter().map(str::to_owned).collect())
+            .collect();
+
+        for (name, dependencies) in self.graph() {
+            log_event!(
+                SUBSYSTEM,
+                Level::Debug,
+                "{name} <- [{}]",
+                dependencies.join(", ")
+            );
+        }
+
+        let mut starts: BTreeMap<String, Start> = self
+            .subsystems
+            .drain(..)
+            .map(|subsystem| (subsystem.name, subsystem.start))
+            .collect();
+        let mut report = StartupReport::default();
+
+        for (index, names) in stages.into_iter().enumerate() {
+            let stage_started = Instant::now();
+            let results: Vec<_> = thread::scope(|scope| {
+                let threads: Vec<_> = names
+                    .iter()
+                    .map(|name| {
+                        let start = starts.remove(name).expect("stages should cover each once");
+                        scope.spawn(move || {
+                            let started = Instant::now();
+                            start().map(|()| started.elapsed())
+                        })
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|thread| {
+                        thread
+                            .join()
+                            .unwrap_or_else(|_| Err(io::Error::other("panicked while starting")))
+                    })
+                    .collect()
+            });
+            let mut stage = Stage {
+                subsystems: Vec::new(),
+                duration: stage_started.elapsed(),
+            };
+
+            for (name, result) in names.into_iter().zip(results) {
+                match result {
+                    Ok(duration) => stage.subsystems.push((name, duration)),
+                    Err(error) => {
+                        log_event!(SUBSYSTEM, Level::Error, "{name} failed to start: {error}");
+                        return Err(StartupError::Failed {
+                            subsystem: name,
+                            error,
+                        });
+                    }
+                }
+            }
+
+            log_event!(
+                SUBSYSTEM,
+                Level::Info,
+                "stage {} started in {:?}",
+                index + 1,
+                stage.duration
+            );
+            report.stages.push(stage);
+        }
+
+        Ok(report)
+    }
+}
+
+impl Debug for Robot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Robot")
+            .field("subsystems", &self.graph())
+            .finish()
+    }
+}
+
+/// Returns a cycle among the subsystems that have not started, each of
+/// which is waiting on another that has not.
+fn find_cycle(graph: &BTreeMap<&str, Vec<&str>>, started: &BTreeSet<&str>) -> Vec<String> {
+    let waiting = |name: &str| {
+        graph[name]
+            .iter()
+            .copied()
+            .find(|dependency| !started.contains(dependency))
+    };
+    let mut path: Vec<&str> = Vec::new();
+    let mut next = graph.keys().copied().find(|name| !started.contains(name));
+
+    while let Some(name) = next {
+        if let Some(position) = path.iter().position(|&visited| visited == name) {
+            let mut cycle: Vec<String> = path[position..]
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect();
+            cycle.push(name.to_owned());
+            return cycle;
+        }
+
+        path.push(name);
+        next = waiting(name);
+    }
+
+    path.into_iter().map(str::to_owned).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn recorder() -> (Arc<Mutex<Vec<String>>>, impl Fn(&str) -> Start) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let shared = Arc::clone(&log);
+        (log, move |name: &str| {
+            let log = Arc::clone(&shared);
+            let name = name.to_owned();
+            Box::new(move || {
+                log.lock().unwrap().push(name);
+                Ok(())
+            })
+        })
+    }
+
+    #[test]
+    fn it_should_start_subsystems_in_dependency_order() {
+        let (log, start) = recorder();
+        let robot = Robot::new()
+            .with_subsystem("navigation", &["imu", "lidar"], start("navigation"))
+            .with_subsystem("imu", &["i2c"], start("imu"))
+            .with_subsystem("lidar", &[], start("lidar"))
+            .with_subsystem("i2c", &[], start("i2c"));
+        assert_eq!(
+            robot.stages().unwrap(),
+            [vec!["lidar", "i2c"], vec!["imu"], vec!["navigation"]]
+        );
+        let report = robot.start().unwrap();
+        assert_eq!(report.stages.len(), 3);
+        assert_eq!(report.stages[1].subsystems[0].0, "imu");
+        let log = log.lock().unwrap();
+        assert_eq!(log[2..], ["imu", "navigation"]);
+    }
+
+    #[test]
+    fn it_should_report_cycles_and_missing_dependencies() {
+        let (log, start) = recorder();
+        let error = Robot::new()
+            .with_subsystem("lidar", &[], start("lidar"))
+            .with_subsystem("planner", &["localizer"], start("planner"))
+            .with_subsystem("localizer", &["lidar", "planner"], start("localizer"))
+            .start()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "dependency cycle: localizer -> planner -> localizer"
+        );
+        let error = Robot::new()
+            .with_subsystem("imu", &["i2c"], start("imu"))
+            .stages()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            StartupError::MissingDependency { ref dependency, .. } if dependency == "i2c"
+        ));
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_should_stop_after_a_stage_fails() {
+        let (log, start) = recorder();
+        let error = Robot::new()
+            .with_subsystem("camera", &[], || Err(io::Error::other("not detected")))
+            .with_subsystem("vision", &["camera"], start("vision"))
+            .start()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "subsystem `camera` failed to start: not detected"
+        );
+        assert!(log.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,389 @@
+//! Maintenance commands over a local Unix domain socket.
+//!
+//! Each request is a JSON object on one line, naming a verb and its
+//! arguments, and is answered by one line holding either the result or an
+//! error:
+//!
+//! ```text
+//! {"verb":"set-param","args":{"name":"max_speed","value":0.8}}
+//! {"ok":true,"result":null}
+//! ```
+//!
+//! The [`RpcServer`] runs whatever handlers the robot registers for routine
+//! maintenance, such as restarting a subsystem or rotating logs, so they do
+//! not require editing files and sending signals by hand. Access is limited
+//! by the socket’s file permissions.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::geometry::Pose;
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+use crate::navigation::{Navigator, Places};
+use crate::params::ParamServer;
+
+const SUBSYSTEM: &str = "rpc";
+
+/// Path of the socket used by default.
+pub const DEFAULT_SOCKET: &str = "/run/otter-pi/rpc.sock";
+
+type Handler = Arc<dyn Fn(&Value) -> Result<Value, String> + Send + Sync>;
+
+/// Answers maintenance requests with registered handlers.
+#[derive(Clone)]
+pub struct RpcServer {
+    handlers: BTreeMap<String, Handler>,
+}
+
+impl RpcServer {
+    /// Creates a new `RpcServer` answering only `help`, which lists the
+    /// verbs.
+    pub fn new() -> Self {
+        Self {
+            handlers: BTreeMap::new(),
+        }
+    }
+
+    /// Answers `verb` by calling `handler` with the request’s arguments.
+    #[must_use]
+    pub fn with_handler(
+        mut self,
+        verb: &str,
+        handler: impl Fn(&Value) -> Result<Value, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.handlers.insert(verb.to_owned(), Arc::new(handler));
+        self
+    }
+
+    /// Answers `restart` by calling `restart` with the `subsystem` argument.
+    #[must_use]
+    pub fn with_restart(
+        self,
+        restart: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.with_handler("restart", move |args| {
+            restart(string_arg(args, "subsystem")?).map(|()| Value::Null)
+        })
+    }
+
+    /// Answers `reload-config` by calling `reload`.
+    #[must_use]
+    pub fn with_reload_config(
+        self,
+        reload: impl Fn() -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.with_handler("reload-config", move |_| reload().map(|()| Value::Null))
+    }
+
+    /// Answers `rotate-logs` by calling `rotate`.
+    #[must_use]
+    pub fn with_rotate_logs(
+        self,
+        rotate: impl Fn() -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.with_handler("rotate-logs", move |_| rotate().map(|()| Value::Null))
+    }
+
+    /// Answers `self-test` with the report returned by `test`.
+    #[must_use]
+    pub fn with_self_test(
+        self,
+        test: impl Fn() -> Result<Value, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.with_handler("self-test", move |_| test())
+    }
+
+    /// Answers `get-param` and `set-param` from `params`.
+    #[must_use]
+    pub fn with_params(self, params: ParamServer) -> Self {
+        let getter = params.clone();
+        self.with_handler("get-param", move |args| {
+            let name = string_arg(args, "name")?;
+            getter
+                .get(name)
+                .map(|value| value.to_json())
+                .ok_or_else(|| format!("unknown parameter `{name}`"))
+        })
+        .with_handler("set-param", move |args| {
+            let name = string_arg(args, "name")?;
+            let value = args.get("value").ok_or("missing argument `value`")?;
+            params
+                .set_json(name, value)
+                .map(|()| Value::Null)
+                .map_err(|error| error.to_string())
+        })
+    }
+
+    /// Answers `list-places`, `set-place`, and `remove-place` from `places`,
+    /// and `go-to` by sending `navigator` to the named place.
+    #[must_use]
+    pub fn with_places(self, places: Places, navigator: impl Navigator + Send + 'static) -> Self {
+        let navigator = Mutex::new(navigator);
+        let (lister, setter, remover) = (places.clone(), places.clone(), places.clone());
+        self.with_handler("list-places", move |_| Ok(lister.to_json()))
+            .with_handler("set-place", move |args| {
+                let coordinate = |key| {
+                    args.get(key)
+                        .and_then(Value::as_f64)
+                        .ok_or_else(|| format!("missing argument `{key}`"))
+                };
+                let heading = args.get("heading").and_then(Value::as_f64).unwrap_or(0.0);
+                let pose = Pose::new(coordinate("x")?, coordinate("y")?, heading);
+                setter
+                    .set(string_arg(args, "name")?, pose)
+                    .map(|()| Value::Null)
+                    .map_err(|error| error.to_string())
+            })
+            .with_handler("remove-place", move |args| {
+                remover
+                    .remove(string_arg(args, "name")?)
+                    .map(Value::Bool)
+                    .map_err(|error| error.to_string())
+            })
+            .with_handler("go-to", move |args| {
+                let mut navigator = navigator.lock().unwrap_or_else(|e| e.into_inner());
+                places
+                    .go_to(string_arg(args, "name")?, &mut *navigator)
+                    .map(|pose| pose.to_json())
+                    .map_err(|error| error.to_string())
+            })
+    }
+
+    /// Returns the answer to one request line.
+    #[must_use]
+    pub fn handle_line(&self, line: &str) -> Value {
+        let result = line
+            .parse::<Value>()
+            .map_err(|error| format!("malformed request: {error}"))
+            .and_then(|request| {
+                let verb = string_arg(&request, "verb")?;
+                let args = request.get("args").cloned().unwrap_or_else(Value::object);
+                log_event!(SUBSYSTEM, Level::Info, "{verb} {args}");
+
+                match self.handlers.get(verb) {
+                    Some(handler) => handler(&args),
+                    None if verb == "help" => Ok(Value::Array(
+                        self.handlers.keys().map(ToJson::to_json).collect(),
+                    )),
+                    None => Err(format!("unknown verb `{verb}`")),
+                }
+            });
+
+        match result {
+            Ok(result) => Value::object().with("ok", true).with("result", result),
+            Err(error) => Value::object().with("ok", false).with("error", error),
+        }
+    }
+
+    /// Listens on a socket at `path`, replacing any left by a previous run,
+    /// and answers each connection on its own thread.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the socket cannot be bound.
+    pub fn serve(self, path: &Path) -> Result<JoinHandle<()>, Error> {
+        match fs::remove_file(path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+
+        let listener = UnixListener::bind(path)?;
+        log_event!(SUBSYSTEM, Level::Info, "listening on {}", path.display());
+        thread::Builder::new()
+            .name(SUBSYSTEM.to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let server = self.clone();
+                    let spawned = stream.and_then(|stream| {
+                        thread::Builder::new()
+                            .name(format!("{SUBSYSTEM}-client"))
+                            .spawn(move || {
+                                if let Err(error) = server.answer(stream) {
+                                    log_event!(SUBSYSTEM, Level::Warn, "client failed: {error}");
+                                }
+                            })
+                    });
+
+                    if let Err(error) = spawned {
+                        log_event!(SUBSYSTEM, Level::Warn, "could not accept: {error}");
+                    }
+                }
+            })
+    }
+
+    fn answer(&self, stream: UnixStream) -> Result<(), Error> {
+        let mut writer = stream.try_clone()?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+
+            if !line.trim().is_empty() {
+                writeln!(writer, "{}", self.handle_line(&line))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RpcServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for RpcServer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcServer")
+            .field("verbs", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Sends maintenance requests to a running robot.
+#[derive(Debug)]
+pub struct RpcClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl RpcClient {
+    /// Connects to the socket at `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if nothing is listening.
+    pub fn connect(path: &Path) -> Result<Self, Error> {
+        let writer = UnixStream::connect(path)?;
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    /// Calls `verb` with `args` and returns its result.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request cannot be sent, or
+    /// with [`ErrorKind::Other`] carrying the robot’s message if the verb
+    /// failed.
+    pub fn call(&mut self, verb: &str, args: Value) -> Result<Value, Error> {
+        let request = Value::object().with("verb", verb).with("args", args);
+        writeln!(self.writer, "{request}")?;
+        let mut line = String::new();
+
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed"));
+        }
+
+        let response: Value = line
+            .parse()
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+
+        if response.get("ok").and_then(Value::as_bool) == Some(true) {
+            Ok(response.get("result").cloned().unwrap_or_default())
+        } else {
+            let message = response
+                .get("error")
+                .and_then(Value::as_str)
+                .unwrap_or("failed");
+            Err(Error::other(message))
+        }
+    }
+}
+
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("missing argument `{name}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::ParamSpec;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_answer_requests_with_handlers() {
+        let restarted = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&restarted);
+        let server = RpcServer::new()
+            .with_restart(move |subsystem| {
+                log.lock().unwrap().push(subsystem.to_owned());
+                Ok(())
+            })
+            .with_rotate_logs(|| Err("log directory is read-only".to_owned()));
+        let answer = server.handle_line(r#"{"verb":"restart","args":{"subsystem":"lidar"}}"#);
+        assert_eq!(answer.to_string(), r#"{"ok":true,"result":null}"#);
+        assert_eq!(*restarted.lock().unwrap(), ["lidar"]);
+        let answer = server.handle_line(r#"{"verb":"rotate-logs"}"#);
+        assert_eq!(
+            answer.get("error").and_then(Value::as_str),
+            Some("log directory is read-only")
+        );
+        let answer = server.handle_line(r#"{"verb":"help"}"#);
+        assert_eq!(
+            answer.get("result").map(ToString::to_string).as_deref(),
+            Some(r#"["restart","rotate-logs"]"#)
+        );
+        let answer = server.handle_line("restart lidar");
+        assert_eq!(answer.get("ok"), Some(&Value::Bool(false)));
+    }
+
+    #[test]
+    fn it_should_manage_places_and_go_to_them() {
+        let goals = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&goals);
+        let server = RpcServer::new().with_places(Places::new(), move |goal| {
+            log.lock().unwrap().push(goal);
+            Ok(())
+        });
+        let answer =
+            server.handle_line(r#"{"verb":"set-place","args":{"name":"Kitchen","x":3,"y":1.5}}"#);
+        assert_eq!(answer.get("ok"), Some(&Value::Bool(true)));
+        let answer = server.handle_line(r#"{"verb":"list-places"}"#);
+        assert_eq!(
+            answer.get("result").map(ToString::to_string).as_deref(),
+            Some(r#"{"kitchen":{"x":3,"y":1.5,"heading":0}}"#)
+        );
+        let answer = server.handle_line(r#"{"verb":"go-to","args":{"name":"kitchen"}}"#);
+        assert_eq!(answer.get("ok"), Some(&Value::Bool(true)));
+        assert_eq!(*goals.lock().unwrap(), [Pose::new(3.0, 1.5, 0.0)]);
+        let answer = server.handle_line(r#"{"verb":"remove-place","args":{"name":"kitchen"}}"#);
+        assert_eq!(answer.get("result"), Some(&Value::Bool(true)));
+        let answer = server.handle_line(r#"{"verb":"go-to","args":{"name":"kitchen"}}"#);
+        assert_eq!(
+            answer.get("error").and_then(Value::as_str),
+            Some("no place is named `kitchen`")
+        );
+    }
+
+    #[test]
+    fn it_should_set_params_from_a_client() {
+        let params = ParamServer::new();
+        let max_speed = params
+            .register(ParamSpec::new("max_speed", 0.5).range(0.0, 1.0))
+            .unwrap();
+        let dir = TemporaryDirectory::new().unwrap();
+        let socket = dir.path().join("rpc.sock");
+        RpcServer::new().with_params(params).serve(&socket).unwrap();
+        let mut client = RpcClient::connect(&socket).unwrap();
+        let args = Value::object().with("name", "max_speed").with("value", 0.8);
+        client.call("set-param", args).unwrap();
+        assert_eq!(max_speed.get(), 0.8);
+        let args = Value::object().with("name", "max_speed").with("value", 2.0);
+        assert!(client.call("set-param", args).is_err());
+        let args = Value::object().with("name", "max_speed");
+        assert_eq!(client.call("get-param", args).unwrap(), Value::Number(0.8));
+    }
+}
@@ -0,0 +1,388 @@
+//! A daily routine of tasks triggered by the time of day.
+//!
+//! Each [`Task`] names a mission to start at a local time on chosen days,
+//! such as patrolling at 22:00 on weekdays. The [`Schedule`] is polled from
+//! the main loop and hands back whichever tasks have come due, leaving it to
+//! the caller to start their missions. When opened from a file, it records
+//! the day each task last ran, so a restart neither runs a task twice nor,
+//! within the grace period, misses one.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::json::Value;
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "schedule";
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A time of day, in local time.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TimeOfDay {
+    hour: u8,
+    minute: u8,
+}
+
+impl TimeOfDay {
+    /// Creates a new time of day.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hour` is not less than 24 or `minute` is not less than 60.
+    pub fn new(hour: u8, minute: u8) -> Self {
+        assert!(hour < 24, "hour should be less than 24");
+        assert!(minute < 60, "minute should be less than 60");
+        Self { hour, minute }
+    }
+
+    /// Returns the seconds since midnight.
+    #[must_use]
+    pub fn seconds(&self) -> i64 {
+        i64::from(self.hour) * 3600 + i64::from(self.minute) * 60
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    /// Parses a 24-hour time such as `22:00`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("`{s}` should be a time such as 22:00");
+        let (hour, minute) = s.trim().split_once(':').ok_or_else(error)?;
+        let hour: u8 = hour.parse().map_err(|_| error())?;
+        let minute: u8 = minute.parse().map_err(|_| error())?;
+
+        if hour < 24 && minute < 60 {
+            Ok(Self { hour, minute })
+        } else {
+            Err(error())
+        }
+    }
+}
+
+/// A set of days of the week.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Days(u8);
+
+impl Days {
+    /// Every day of the week.
+    pub const EVERY_DAY: Self = Self(0b111_1111);
+    /// Monday to Friday.
+    pub const WEEKDAYS: Self = Self(0b001_1111);
+    /// Saturday and Sunday.
+    pub const WEEKENDS: Self = Self(0b110_0000);
+
+    /// Returns the set containing only the day `index` days after Monday.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than 7.
+    #[must_use]
+    pub fn day(index: u8) -> Self {
+        assert!(index < 7, "index should be less than 7");
+        Self(1 << index)
+    }
+
+    /// Returns the union of both sets.
+    #[must_use]
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns `true` if the set contains the day `index` days after Monday.
+    #[must_use]
+    pub fn contains(&self, index: u8) -> bool {
+        index < 7 && self.0 & (1 << index) != 0
+    }
+}
+
+impl Default for Days {
+    fn default() -> Self {
+        Self::EVERY_DAY
+    }
+}
+
+/// A mission to start at a time of day.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Task {
+    /// Unique name of the task, under which its last run is recorded.
+    pub name: String,
+    /// Local time at which the task comes due.
+    pub at: TimeOfDay,
+    /// Days on which the task runs.
+    pub days: Days,
+    /// Name of the mission to start.
+    pub mission: String,
+}
+
+impl Task {
+    /// Creates a new task that starts `mission` at `at` every day.
+    pub fn new(name: &str, at: TimeOfDay, mission: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            at,
+            days: Days::EVERY_DAY,
+            mission: mission.to_owned(),
+        }
+    }
+
+    /// Runs the task only on `days`.
+    #[must_use]
+    pub fn on(mut self, days: Days) -> Self {
+        self.days = days;
+        self
+    }
+}
+
+/// Tasks run on a daily routine.
+#[derive(Debug)]
+pub struct Schedule {
+    grace: Duration,
+    last_run: BTreeMap<String, i64>,
+    path: Option<PathBuf>,
+    tasks: Vec<Task>,
+}
+
+impl Schedule {
+    /// Creates a new, empty `Schedule` that forgets its runs on restart.
+    pub fn new() -> Self {
+        Self {
+            grace: Duration::from_secs(15 * 60),
+            last_run: BTreeMap::new(),
+            path: None,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Opens a `Schedule` that records when each task last ran in the file
+    /// at `path`, which is created on the first run.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file exists but cannot be
+    /// read or parsed.
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let last_run = match std::fs::read_to_string(path) {
+            Ok(contents) => match contents.parse::<Value>() {
+                Ok(Value::Object(entries)) => entries
+                    .into_iter()
+                    .filter_map(|(name, day)| Some((name, day.as_f64()? as i64)))
+                    .collect(),
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "schedule file should contain a JSON object",
+                    ))
+                }
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => return Err(error),
+        };
+        Ok(Self {
+            last_run,
+            path: Some(path.to_owned()),
+            ..Self::new()
+        })
+    }
+
+    /// Sets how late a task may still run, such as after the robot was off
+    /// at the time it came due.
+    #[must_use]
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    /// Adds `task`, replacing any task with the same name.
+    pub fn add(&mut self, task: Task) {
+        self.tasks.retain(|existing| existing.name != task.name);
+        self.tasks.push(task);
+    }
+
+    /// Removes the task named `name`, and returns `true` if there was one.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let count = self.tasks.len();
+        self.tasks.retain(|task| task.name != name);
+        self.tasks.len() != count
+    }
+
+    /// Returns the tasks.
+    #[must_use]
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    /// Returns the tasks that have come due by `now`, in a time zone
+    /// `utc_offset` seconds ahead of UTC, and marks them as run.
+    pub fn poll(&mut self, now: SystemTime, utc_offset: i32) -> Vec<Task> {
+        let seconds = match now.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(error) => -(error.duration().as_secs() as i64),
+        };
+        let local = seconds + i64::from(utc_offset);
+        let today = local.div_euclid(SECONDS_PER_DAY);
+        let grace = self.grace.as_secs() as i64;
+        let mut due = Vec::new();
+
+        for task in &self.tasks {
+            // A task just before midnight may still be due just after it.
+            for day in [today - 1, today] {
+                let late_by = local - (day * SECONDS_PER_DAY + task.at.seconds());
+                // 1 January 1970 was a Thursday.
+                let weekday = (day + 3).rem_euclid(7) as u8;
+
+                if (0..=grace).contains(&late_by)
+                    && task.days.contains(weekday)
+                    && self.last_run.get(&task.name) < Some(&day)
+                {
+                    self.last_run.insert(task.name.clone(), day);
+                    due.push(task.clone());
+                }
+            }
+        }
+
+        if !due.is_empty() {
+            for task in &due {
+                log_event!(
+                    SUBSYSTEM,
+                    Level::Info,
+                    "`{}` is due, starting `{}`",
+                    task.name,
+                    task.mission
+                );
+            }
+
+            if let Err(error) = self.persist() {
+                log_event!(SUBSYSTEM, Level::Warn, "could not record runs: {error}");
+            }
+        }
+
+        due
+    }
+
+    /// Returns the tasks that have come due by now, in the system time zone.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the time zone cannot be read.
+    #[cfg(unix)]
+    pub fn poll_now(&mut self) -> Result<Vec<Task>, io::Error> {
+        let now = SystemTime::now();
+        let offset = crate::unix::clock::utc_offset(now)?;
+        Ok(self.poll(now, offset))
+    }
+
+    fn persist(&self) -> Result<(), io::Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let contents = self
+            .last_run
+            .iter()
+            .fold(Value::object(), |object, (name, &day)| {
+                object.with(name, day as f64)
+            })
+            .to_string();
+        #[cfg(unix)]
+        crate::unix::fsutil::write_atomic(path, contents.as_bytes())?;
+        #[cfg(not(unix))]
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Midnight UTC on Monday 2 September 2024.
+    const MONDAY: u64 = 1_725_235_200;
+
+    fn at(day: u64, hour: u64, minute: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(MONDAY + day * 86_400 + hour * 3600 + minute * 60)
+    }
+
+    fn schedule() -> Schedule {
+        let mut schedule = Schedule::new();
+        schedule.add(Task::new("patrol", "22:00".parse().unwrap(), "patrol"));
+        schedule.add(Task::new("dock", TimeOfDay::new(23, 0), "return_to_dock").on(Days::WEEKDAYS));
+        schedule
+    }
+
+    fn names(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|task| task.name.as_str()).collect()
+    }
+
+    #[test]
+    fn it_should_parse_and_display_times_of_day() {
+        assert_eq!("7:05".parse::<TimeOfDay>(), Ok(TimeOfDay::new(7, 5)));
+        assert_eq!(TimeOfDay::new(7, 5).to_string(), "07:05");
+        assert!("24:00".parse::<TimeOfDay>().is_err());
+        assert!("noon".parse::<TimeOfDay>().is_err());
+    }
+
+    #[test]
+    fn it_should_run_each_task_once_when_due() {
+        let mut schedule = schedule();
+        assert!(schedule.poll(at(0, 21, 59), 0).is_empty());
+        assert_eq!(names(&schedule.poll(at(0, 22, 0), 0)), ["patrol"]);
+        assert!(schedule.poll(at(0, 22, 5), 0).is_empty());
+        assert_eq!(names(&schedule.poll(at(0, 23, 1), 0)), ["dock"]);
+        assert_eq!(names(&schedule.poll(at(1, 22, 1), 0)), ["patrol"]);
+    }
+
+    #[test]
+    fn it_should_skip_tasks_past_the_grace_period_or_on_other_days() {
+        let mut schedule = schedule();
+        assert!(schedule.poll(at(0, 22, 30), 0).is_empty());
+        // Saturday.
+        assert_eq!(names(&schedule.poll(at(5, 23, 0), 0)), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn it_should_apply_the_utc_offset() {
+        let mut schedule = schedule();
+        // 20:00 UTC is 22:00 two hours ahead.
+        assert_eq!(names(&schedule.poll(at(0, 20, 0), 7200)), ["patrol"]);
+    }
+
+    #[test]
+    fn it_should_catch_tasks_due_just_before_midnight() {
+        let mut schedule = Schedule::new();
+        schedule.add(Task::new("lights", TimeOfDay::new(23, 55), "lights_off"));
+        assert_eq!(names(&schedule.poll(at(1, 0, 5), 0)), ["lights"]);
+        assert!(schedule.poll(at(1, 0, 6), 0).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_should_remember_runs_across_restarts() {
+        let directory = crate::TemporaryDirectory::new().unwrap();
+        let path = directory.path().join("schedule.json");
+        let mut schedule = Schedule::open(&path).unwrap();
+        schedule.add(Task::new("patrol", TimeOfDay::new(22, 0), "patrol"));
+        assert_eq!(schedule.poll(at(0, 22, 0), 0).len(), 1);
+        let mut restarted = Schedule::open(&path).unwrap();
+        restarted.add(Task::new("patrol", TimeOfDay::new(22, 0), "patrol"));
+        assert!(restarted.poll(at(0, 22, 10), 0).is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+//! A single, consistent view of the robot’s state.
+//!
+//! Subsystems publish their latest state into a [`StateStore`], and every
+//! interface that reports state — the network APIs, the black box, the CLI —
+//! reads it back through [`snapshot`], so they never disagree with each other
+//! about what the robot is doing.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::geometry::Pose;
+use crate::json::{ToJson, Value};
+
+/// Number of recent commands kept in a snapshot.
+pub const COMMAND_HISTORY_LEN: usize = 16;
+
+/// State of the battery.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Battery {
+    /// Voltage, in volts.
+    pub voltage: f64,
+    /// Current drawn, in amperes, if measured.
+    pub current: Option<f64>,
+    /// Estimated state of charge, from 0 to 1, if known.
+    pub state_of_charge: Option<f64>,
+    /// `true` if the battery is charging.
+    pub charging: bool,
+}
+
+impl ToJson for Battery {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("voltage", self.voltage)
+            .with("current", self.current)
+            .with("state_of_charge", self.state_of_charge)
+            .with("charging", self.charging)
+    }
+}
+
+/// Health of a device or subsystem.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Health {
+    /// Working normally.
+    Ok,
+    /// Working with reduced capability, for the given reason.
+    Degraded(String),
+    /// Not working, for the given reason.
+    Failed(String),
+    /// Expected but not found.
+    Missing,
+}
+
+impl ToJson for Health {
+    fn to_json(&self) -> Value {
+        let (status, reason) = match self {
+            Self::Ok => ("ok", None),
+            Self::Degraded(reason) => ("degraded", Some(reason)),
+            Self::Failed(reason) => ("failed", Some(reason)),
+            Self::Missing => ("missing", None),
+        };
+        Value::object()
+            .with("status", status)
+            .with("reason", reason)
+    }
+}
+
+/// A command received by the robot.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CommandRecord {
+    /// Interface the command arrived on, such as `gamepad` or `http`.
+    pub source: String,
+    /// Description of the command.
+    pub command: String,
+    /// When the command was received.
+    pub received_at: SystemTime,
+}
+
+impl ToJson for CommandRecord {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("source", &self.source)
+            .with("command", &self.command)
+            .with("received_at", unix_seconds(self.received_at))
+    }
+}
+
+/// The state of the robot at one instant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// Number of updates made to the store before the snapshot was taken,
+    /// which orders snapshots and tells a poller whether anything changed.
+    pub sequence: u64,
+    /// When the snapshot was taken.
+    pub taken_at: SystemTime,
+    /// Poses by estimator name, such as `odometry` or `fused`.
+    pub poses: BTreeMap<String, Pose>,
+    /// State of the battery, if it is monitored.
+    pub battery: Option<Battery>,
+    /// Name of the running behavior, if any.
+    pub active_behavior: Option<String>,
+    /// Health by device or subsystem name.
+    pub devices: BTreeMap<String, Health>,
+    /// Most recent commands, oldest first.
+    pub last_commands: Vec<CommandRecord>,
+}
+
+impl ToJson for Snapshot {
+    fn to_json(&self) -> Value {
+        let poses = self
+            .poses
+            .iter()
+            .fold(Value::object(), |object, (name, pose)| {
+                object.with(name, pose)
+            });
+        let devices = self
+            .devices
+            .iter()
+            .fold(Value::object(), |object, (name, health)| {
+                object.with(name, health)
+            });
+        Value::object()
+            .with("sequence", self.sequence)
+            .with("taken_at", unix_seconds(self.taken_at))
+            .with("poses", poses)
+            .with("battery", self.battery)
+            .with("active_behavior", &self.active_behavior)
+            .with("devices", devices)
+            .with("last_commands", &self.last_commands)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    active_behavior: Option<String>,
+    battery: Option<Battery>,
+    devices: BTreeMap<String, Health>,
+    last_commands: VecDeque<CommandRecord>,
+    poses: BTreeMap<String, Pose>,
+    sequence: u64,
+}
+
+/// Shared store of the latest state published by each subsystem.
+#[derive(Debug, Default)]
+pub struct StateStore {
+    state: RwLock<State>,
+}
+
+impl StateStore {
+    /// Creates a new, empty `StateStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the store read by [`snapshot`].
+    pub fn global() -> &'static Self {
+        static STORE: OnceLock<StateStore> = OnceLock::new();
+        STORE.get_or_init(Self::new)
+    }
+
+    /// Returns a copy of the whole state, taken under a single lock.
+    #[must_use]
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.read();
+        Snapshot {
+            sequence: state.sequence,
+            taken_at: SystemTime::now(),
+            poses: state.poses.clone(),
+            battery: state.battery,
+            active_behavior: state.active_behavior.clone(),
+            devices: state.devices.clone(),
+            last_commands: state.last_commands.iter().cloned().collect(),
+        }
+    }
+
+    /// Publishes the pose estimated by `estimator`.
+    pub fn set_pose(&self, estimator: &str, pose: Pose) {
+        self.write().poses.insert(estimator.to_owned(), pose);
+    }
+
+    /// Publishes the state of the battery.
+    pub fn set_battery(&self, battery: Battery) {
+        self.write().battery = Some(battery);
+    }
+
+    /// Publishes the name of the running behavior, or `None` if idle.
+    pub fn set_active_behavior(&self, behavior: Option<&str>) {
+        self.write().active_behavior = behavior.map(str::to_owned);
+    }
+
+    /// Publishes the health of a device or subsystem.
+    pub fn set_health(&self, device: &str, health: Health) {
+        self.write().devices.insert(device.to_owned(), health);
+    }
+
+    /// Records a command received from `source`, discarding the oldest once
+    /// [`COMMAND_HISTORY_LEN`] have been recorded.
+    pub fn record_command(&self, source: &str, command: &str) {
+        let mut state = self.write();
+
+        if state.last_commands.len() == COMMAND_HISTORY_LEN {
+            state.last_commands.pop_front();
+        }
+
+        state.last_commands.push_back(CommandRecord {
+            source: source.to_owned(),
+            command: command.to_owned(),
+            received_at: SystemTime::now(),
+        });
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, State> {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        state.sequence += 1;
+        state
+    }
+}
+
+/// Returns a snapshot of the robot’s state from the global store.
+#[must_use]
+pub fn snapshot() -> Snapshot {
+    StateStore::global().snapshot()
+}
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0.0, |duration| duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_start_empty() {
+        let snapshot = StateStore::new().snapshot();
+        assert_eq!(snapshot.sequence, 0);
+        assert!(snapshot.poses.is_empty() && snapshot.battery.is_none());
+    }
+
+    #[test]
+    fn it_should_reflect_every_published_update() {
+        let store = StateStore::new();
+        store.set_pose("odometry", Pose::new(1.0, 2.0, 0.5));
+        store.set_battery(Battery {
+            voltage: 12.1,
+            ..Battery::default()
+        });
+        store.set_active_behavior(Some("patrol"));
+        store.set_health("imu", Health::Degraded("uncalibrated".to_owned()));
+        store.record_command("gamepad", "drive 0.5 0.0");
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot.sequence, 5);
+        assert_eq!(snapshot.poses["odometry"], Pose::new(1.0, 2.0, 0.5));
+        assert_eq!(snapshot.battery.map(|battery| battery.voltage), Some(12.1));
+        assert_eq!(snapshot.active_behavior.as_deref(), Some("patrol"));
+        assert_eq!(snapshot.last_commands[0].source, "gamepad");
+    }
+
+    #[test]
+    fn it_should_keep_only_the_most_recent_commands() {
+        let store = StateStore::new();
+
+        for index in 0..COMMAND_HISTORY_LEN + 2 {
+            store.record_command("rpc", &index.to_string());
+        }
+
+        let commands = store.snapshot().last_commands;
+        assert_eq!(commands.len(), COMMAND_HISTORY_LEN);
+        assert_eq!(commands[0].command, "2");
+    }
+
+    #[test]
+    fn it_should_serialize_to_json() {
+        let store = StateStore::new();
+        store.set_pose("fused", Pose::new(0.0, 1.0, 0.0));
+        store.set_health("lidar", Health::Missing);
+        let json = store.snapshot().to_json();
+        assert_eq!(json.get("sequence").and_then(Value::as_f64), Some(2.0));
+        assert_eq!(
+            json.get("poses")
+                .and_then(|poses| poses.get("fused"))
+                .and_then(|pose| pose.get("y"))
+                .and_then(Value::as_f64),
+            Some(1.0)
+        );
+        assert_eq!(
+            json.get("devices")
+                .and_then(|devices| devices.get("lidar"))
+                .map(Value::to_string)
+                .as_deref(),
+            Some(r#"{"status":"missing","reason":null}"#)
+        );
+        assert!(json.get("battery").is_some_and(Value::is_null));
+    }
+
+    #[test]
+    fn it_should_read_the_global_store() {
+        StateStore::global().set_health("snapshot-test", Health::Ok);
+        assert_eq!(snapshot().devices.get("snapshot-test"), Some(&Health::Ok));
+    }
+}
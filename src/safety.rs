@@ -0,0 +1,7 @@
+//! Reflexes that keep the robot and the people around it safe.
+
+pub mod environment;
+pub mod pickup;
+
+pub use environment::EnvironmentGuard;
+pub use pickup::PickupDetector;
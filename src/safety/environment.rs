@@ -0,0 +1,333 @@
+//! Keeping the robot indoors when the weather is against it.
+//!
+//! Rain gets into connectors and the motor drivers, frost flattens the
+//! battery and stiffens the tyres, and a summer afternoon on tarmac cooks the
+//! Pi. An [`EnvironmentGuard`] watches a rain or moisture sensor and the
+//! ambient temperature, vetoes outdoor missions while either is out of
+//! bounds, and calls a robot that is already outside back home.
+//!
+//! Each limit has a margin before it clears, so conditions hovering at a
+//! limit do not toggle the veto, and rain must stay away for a while before
+//! the ground is dry enough to go back out. Readings that stop arriving are
+//! treated as bad weather, as are readings the sensor flags as invalid,
+//! since a sensor that has drowned looks just like one that has nothing to
+//! report.
+
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::events::{Command, Event, EventBus};
+use crate::log_event;
+use crate::logging::Level;
+use crate::sensors::measurement::{self, Measurement};
+
+const SUBSYSTEM: &str = "environment";
+
+/// Intent published to send the robot home.
+pub const GO_HOME: &str = "go_home";
+
+/// Limits of an [`EnvironmentGuard`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnvironmentConfig {
+    /// Moisture, from 0 for dry to 1 for soaked, above which it is raining.
+    pub max_moisture: f64,
+    /// Time moisture must stay below the limit before it has stopped
+    /// raining.
+    pub dry_time: Duration,
+    /// Ambient temperature below which it is too cold, in degrees Celsius.
+    pub min_temperature: f64,
+    /// Ambient temperature above which it is too hot, in degrees Celsius.
+    pub max_temperature: f64,
+    /// Margin inside a temperature limit the temperature must return to
+    /// before the limit clears, in degrees Celsius.
+    pub temperature_hysteresis: f64,
+    /// Age beyond which a reading is too old to trust.
+    pub max_age: Duration,
+}
+
+impl Default for EnvironmentConfig {
+    fn default() -> Self {
+        Self {
+            max_moisture: 0.3,
+            dry_time: Duration::from_secs(15 * 60),
+            min_temperature: 0.0,
+            max_temperature: 40.0,
+            temperature_hysteresis: 2.0,
+            max_age: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Weather that keeps the robot indoors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    /// It is raining, or stopped too recently.
+    Rain,
+    /// It is colder than the limit, in degrees Celsius.
+    TooCold(f64),
+    /// It is hotter than the limit, in degrees Celsius.
+    TooHot(f64),
+    /// A sensor has no recent valid reading, naming it.
+    NoReading(&'static str),
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rain => f.write_str("it is raining"),
+            Self::TooCold(temperature) => write!(f, "it is too cold at {temperature:.1} °C"),
+            Self::TooHot(temperature) => write!(f, "it is too hot at {temperature:.1} °C"),
+            Self::NoReading(sensor) => write!(f, "no recent {sensor} reading"),
+        }
+    }
+}
+
+/// Vetoes outdoor missions and recalls the robot in bad weather.
+#[derive(Clone, Debug, Default)]
+pub struct EnvironmentGuard {
+    bus: Option<EventBus>,
+    config: EnvironmentConfig,
+    dry_since: Option<Instant>,
+    moisture: Option<Measurement<f64>>,
+    recalled: bool,
+    temperature: Option<Measurement<f64>>,
+    temperature_condition: Option<Condition>,
+    wet: bool,
+}
+
+impl EnvironmentGuard {
+    /// Creates a new `EnvironmentGuard` that has had no readings, and so
+    /// vetoes outdoor missions until it has.
+    pub fn new(config: EnvironmentConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Publishes the command to go home on `bus` when recalling the robot.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Records `moisture` from the rain sensor, from 0 for dry to 1 for
+    /// soaked. A sensor with only a digital output reads 1 when wet and 0
+    /// when dry.
+    pub fn update_moisture(&mut self, moisture: Measurement<f64>) {
+        self.moisture = Some(moisture);
+
+        if !moisture.is_valid() {
+            return;
+        }
+
+        if moisture.value > self.config.max_moisture {
+            if !self.wet {
+                log_event!(SUBSYSTEM, Level::Info, "rain detected");
+            }
+
+            self.wet = true;
+            self.dry_since = None;
+        } else {
+            self.dry_since.get_or_insert(moisture.time);
+        }
+    }
+
+    /// Records the ambient `temperature`, in degrees Celsius.
+    pub fn update_temperature(&mut self, temperature: Measurement<f64>) {
+        let config = &self.config;
+        self.temperature = Some(temperature);
+
+        if !temperature.is_valid() {
+            return;
+        }
+
+        let temperature = temperature.value;
+        self.temperature_condition = match self.temperature_condition {
+            _ if temperature < config.min_temperature => Some(Condition::TooCold(temperature)),
+            _ if temperature > config.max_temperature => Some(Condition::TooHot(temperature)),
+            Some(Condition::TooCold(_))
+                if temperature < config.min_temperature + config.temperature_hysteresis =>
+            {
+                Some(Condition::TooCold(temperature))
+            }
+            Some(Condition::TooHot(_))
+                if temperature > config.max_temperature - config.temperature_hysteresis =>
+            {
+                Some(Condition::TooHot(temperature))
+            }
+            _ => None,
+        };
+    }
+
+    /// Returns every condition keeping the robot indoors at `now`.
+    pub fn conditions(&mut self, now: Instant) -> Vec<Condition> {
+        let max_age = self.config.max_age;
+        let mut conditions = Vec::new();
+
+        if self.wet
+            && self
+                .dry_since
+                .is_some_and(|since| now.saturating_duration_since(since) >= self.config.dry_time)
+        {
+            log_event!(SUBSYSTEM, Level::Info, "rain has stopped");
+            self.wet = false;
+        }
+
+        if measurement::check(self.moisture.as_ref(), "moisture", now, max_age).is_err() {
+            conditions.push(Condition::NoReading("moisture"));
+        } else if self.wet {
+            conditions.push(Condition::Rain);
+        }
+
+        if measurement::check(self.temperature.as_ref(), "temperature", now, max_age).is_err() {
+            conditions.push(Condition::NoReading("temperature"));
+        } else if let Some(condition) = self.temperature_condition {
+            conditions.push(condition);
+        }
+
+        conditions
+    }
+
+    /// Returns whether an outdoor mission may start at `now`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with the first condition keeping
+    /// the robot indoors.
+    pub fn check_outdoor(&mut self, now: Instant) -> Result<(), Condition> {
+        self.conditions(now)
+            .first()
+            .map_or(Ok(()), |&condition| Err(condition))
+    }
+
+    /// Checks the conditions at `now` while the robot is `outdoors`, and
+    /// returns `true` if this is when it should be recalled.
+    ///
+    /// The robot is recalled once per spell of bad weather, by publishing a
+    /// command to go home, so it can be sent back out by hand if needed.
+    pub fn poll(&mut self, now: Instant, outdoors: bool) -> bool {
+        let conditions = self.conditions(now);
+
+        if conditions.is_empty() {
+            self.recalled = false;
+            return false;
+        }
+
+        if !outdoors || self.recalled {
+            return false;
+        }
+
+        self.recalled = true;
+        let reasons = conditions
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        log_event!(SUBSYSTEM, Level::Warn, "returning home: {reasons}");
+
+        if let Some(bus) = &self.bus {
+            bus.publish(Event::Command(Command {
+                intent: GO_HOME.to_owned(),
+                argument: None,
+                source: SUBSYSTEM.to_owned(),
+            }));
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::measurement::Validity;
+
+    fn reading(value: f64, time: Instant) -> Measurement<f64> {
+        Measurement::new("weather", value, time)
+    }
+
+    fn guard(start: Instant) -> EnvironmentGuard {
+        let mut guard = EnvironmentGuard::new(EnvironmentConfig::default());
+        guard.update_moisture(reading(0.0, start));
+        guard.update_temperature(reading(20.0, start));
+        guard
+    }
+
+    #[test]
+    fn it_should_veto_until_it_has_readings() {
+        let start = Instant::now();
+        let mut guard = EnvironmentGuard::new(EnvironmentConfig::default());
+        assert_eq!(
+            guard.conditions(start),
+            [
+                Condition::NoReading("moisture"),
+                Condition::NoReading("temperature")
+            ]
+        );
+        let mut guard = self::guard(start);
+        assert_eq!(guard.check_outdoor(start), Ok(()));
+        assert_eq!(
+            guard.check_outdoor(start + Duration::from_secs(61)),
+            Err(Condition::NoReading("moisture"))
+        );
+        guard.update_temperature(reading(20.0, start).with_validity(Validity::Invalid("open")));
+        assert_eq!(
+            guard.conditions(start),
+            [Condition::NoReading("temperature")]
+        );
+    }
+
+    #[test]
+    fn it_should_wait_for_the_ground_to_dry_after_rain() {
+        let start = Instant::now();
+        let mut guard = guard(start);
+        guard.update_moisture(reading(0.8, start));
+        assert_eq!(guard.check_outdoor(start), Err(Condition::Rain));
+        let later = start + Duration::from_secs(60);
+        guard.update_moisture(reading(0.1, later));
+        guard.update_temperature(reading(20.0, later));
+        assert_eq!(guard.check_outdoor(later), Err(Condition::Rain));
+        let dry = later + Duration::from_secs(15 * 60);
+        guard.update_moisture(reading(0.1, dry));
+        guard.update_temperature(reading(20.0, dry));
+        assert_eq!(guard.check_outdoor(dry), Ok(()));
+    }
+
+    #[test]
+    fn it_should_clear_temperature_limits_with_a_margin() {
+        let start = Instant::now();
+        let mut guard = guard(start);
+        guard.update_temperature(reading(-1.0, start));
+        assert_eq!(guard.check_outdoor(start), Err(Condition::TooCold(-1.0)));
+        guard.update_temperature(reading(1.0, start));
+        assert_eq!(guard.check_outdoor(start), Err(Condition::TooCold(1.0)));
+        guard.update_temperature(reading(2.5, start));
+        assert_eq!(guard.check_outdoor(start), Ok(()));
+        guard.update_temperature(reading(41.0, start));
+        assert_eq!(guard.check_outdoor(start), Err(Condition::TooHot(41.0)));
+    }
+
+    #[test]
+    fn it_should_recall_the_robot_once_per_spell() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(8);
+        let start = Instant::now();
+        let mut guard = guard(start).with_bus(bus);
+        assert!(!guard.poll(start, true));
+        guard.update_moisture(reading(1.0, start));
+        assert!(!guard.poll(start, false));
+        assert!(guard.poll(start, true));
+        assert!(!guard.poll(start, true));
+        assert_eq!(
+            subscription.try_recv(),
+            Some(Event::Command(Command {
+                intent: GO_HOME.to_owned(),
+                argument: None,
+                source: SUBSYSTEM.to_owned(),
+            }))
+        );
+        assert_eq!(subscription.try_recv(), None);
+    }
+}
@@ -0,0 +1,289 @@
+//! Stopping the motors when someone picks the robot up.
+//!
+//! A robot grabbed mid-drive keeps spinning its wheels at whoever is holding
+//! it. The accelerometer gives it away: in free fall it reads almost nothing,
+//! tilted its gravity vector swings off the vertical, and a lift shows as a
+//! jolt of vertical acceleration.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::events::{EmergencyStop, Event, EventBus};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "pickup";
+
+/// Standard gravity, in metres per second squared.
+const GRAVITY: f64 = 9.806_65;
+
+type Action = Arc<dyn Fn(PickupCause) + Send + Sync>;
+
+/// Thresholds of a [`PickupDetector`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickupConfig {
+    /// Acceleration magnitude below which the robot is falling, in metres
+    /// per second squared.
+    pub free_fall: f64,
+    /// Angle of the measured gravity from the robot’s vertical beyond which
+    /// it is tilted, in radians.
+    pub max_tilt: f64,
+    /// Deviation of the vertical acceleration from gravity beyond which the
+    /// robot is being lifted or dropped, in metres per second squared.
+    pub vertical_tolerance: f64,
+    /// Time a condition must persist before the detector trips, which rides
+    /// out bumps and kerbs.
+    pub persistence: Duration,
+    /// Time without any condition before the detector re-arms.
+    pub cooldown: Duration,
+}
+
+impl Default for PickupConfig {
+    fn default() -> Self {
+        Self {
+            free_fall: 3.0,
+            max_tilt: 0.7,
+            vertical_tolerance: 4.0,
+            persistence: Duration::from_millis(60),
+            cooldown: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Why a [`PickupDetector`] tripped.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PickupCause {
+    /// The robot is falling.
+    FreeFall,
+    /// The robot is tilted too far.
+    Tilt,
+    /// The robot is being lifted or dropped.
+    VerticalAcceleration,
+}
+
+/// State of a [`PickupDetector`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum PickupState {
+    /// The robot is on the ground and may drive.
+    #[default]
+    Armed,
+    /// The robot was picked up, so the motors are stopped.
+    Tripped(PickupCause),
+    /// The robot appears settled, and will re-arm once the cooldown passes.
+    CoolingDown,
+}
+
+/// Watches the accelerometer and stops the motors while the robot is off the
+/// ground.
+///
+/// Tripping runs every registered action and engages the emergency stop on
+/// the bus; re-arming after the cooldown releases it.
+#[derive(Clone, Default)]
+pub struct PickupDetector {
+    actions: Vec<Action>,
+    bus: Option<EventBus>,
+    calm_for: Duration,
+    config: PickupConfig,
+    state: PickupState,
+    suspect: Option<(PickupCause, Duration)>,
+}
+
+impl PickupDetector {
+    /// Creates a new, armed `PickupDetector`.
+    pub fn new(config: PickupConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Adds an action that stops the motors, run when the detector trips.
+    #[must_use]
+    pub fn on_trip(mut self, action: impl Fn(PickupCause) + Send + Sync + 'static) -> Self {
+        self.actions.push(Arc::new(action));
+        self
+    }
+
+    /// Engages and releases the emergency stop on `bus`.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Returns the state of the detector.
+    #[must_use]
+    pub fn state(&self) -> PickupState {
+        self.state
+    }
+
+    /// Returns `true` if the motors may run.
+    #[must_use]
+    pub fn is_armed(&self) -> bool {
+        self.state == PickupState::Armed
+    }
+
+    /// Checks the `acceleration` measured `dt` after the previous sample, in
+    /// metres per second squared along the robot’s X, Y, and Z axes with Z
+    /// up, including gravity, and returns the state.
+    pub fn update(&mut self, acceleration: [f64; 3], dt: Duration) -> PickupState {
+        let cause = self.classify(acceleration);
+
+        match (self.state, cause) {
+            (PickupState::Armed, Some(cause)) => {
+                let (suspect, duration) = match self.suspect {
+                    Some((suspect, duration)) if suspect == cause => (suspect, duration + dt),
+                    _ => (cause, dt),
+                };
+                self.suspect = Some((suspect, duration));
+
+                if duration >= self.config.persistence {
+                    self.trip(cause);
+                }
+            }
+            (PickupState::Armed, None) => self.suspect = None,
+            (_, Some(cause)) => {
+                self.state = PickupState::Tripped(cause);
+                self.calm_for = Duration::ZERO;
+            }
+            (_, None) => {
+                self.state = PickupState::CoolingDown;
+                self.calm_for += dt;
+
+                if self.calm_for >= self.config.cooldown {
+                    self.rearm();
+                }
+            }
+        }
+
+        self.state
+    }
+
+    fn classify(&self, [x, y, z]: [f64; 3]) -> Option<PickupCause> {
+        let magnitude = (x * x + y * y + z * z).sqrt();
+
+        if magnitude < self.config.free_fall {
+            Some(PickupCause::FreeFall)
+        } else if (z / magnitude).clamp(-1.0, 1.0).acos() > self.config.max_tilt {
+            Some(PickupCause::Tilt)
+        } else if (z - GRAVITY).abs() > self.config.vertical_tolerance {
+            Some(PickupCause::VerticalAcceleration)
+        } else {
+            None
+        }
+    }
+
+    fn trip(&mut self, cause: PickupCause) {
+        self.state = PickupState::Tripped(cause);
+        self.suspect = None;
+        self.calm_for = Duration::ZERO;
+        log_event!(SUBSYSTEM, Level::Warn, "stopping the motors: {cause:?}");
+
+        for action in &self.actions {
+            action(cause);
+        }
+
+        self.publish(true);
+    }
+
+    fn rearm(&mut self) {
+        self.state = PickupState::Armed;
+        log_event!(SUBSYSTEM, Level::Info, "re-armed");
+        self.publish(false);
+    }
+
+    fn publish(&self, engaged: bool) {
+        if let Some(bus) = &self.bus {
+            bus.publish(Event::EmergencyStop(EmergencyStop {
+                engaged,
+                source: SUBSYSTEM.to_owned(),
+            }));
+        }
+    }
+}
+
+impl Debug for PickupDetector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PickupDetector")
+            .field("actions", &self.actions.len())
+            .field("config", &self.config)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::events::EventKind;
+
+    const DT: Duration = Duration::from_millis(10);
+    const LEVEL: [f64; 3] = [0.0, 0.0, GRAVITY];
+
+    fn run(detector: &mut PickupDetector, acceleration: [f64; 3], cycles: usize) -> PickupState {
+        (0..cycles).fold(detector.state(), |_, _| detector.update(acceleration, DT))
+    }
+
+    #[test]
+    fn it_should_stay_armed_while_driving() {
+        let mut detector = PickupDetector::new(PickupConfig::default());
+        // Braking hard tips the measured gravity forward.
+        assert_eq!(
+            run(&mut detector, [-4.0, 0.0, GRAVITY], 100),
+            PickupState::Armed
+        );
+    }
+
+    #[test]
+    fn it_should_trip_once_on_a_sustained_lift() {
+        let trips = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&trips);
+        let mut detector = PickupDetector::new(PickupConfig::default()).on_trip(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let lift = [0.0, 0.0, GRAVITY + 6.0];
+        assert_eq!(run(&mut detector, lift, 3), PickupState::Armed);
+        assert_eq!(
+            run(&mut detector, lift, 10),
+            PickupState::Tripped(PickupCause::VerticalAcceleration)
+        );
+        assert_eq!(trips.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn it_should_detect_free_fall_and_tilt() {
+        let mut detector = PickupDetector::new(PickupConfig::default());
+        assert_eq!(
+            run(&mut detector, [0.0, 0.5, 1.0], 10),
+            PickupState::Tripped(PickupCause::FreeFall)
+        );
+        let mut detector = PickupDetector::new(PickupConfig::default());
+        assert_eq!(
+            run(&mut detector, [0.0, GRAVITY, 1.0], 10),
+            PickupState::Tripped(PickupCause::Tilt)
+        );
+    }
+
+    #[test]
+    fn it_should_rearm_after_the_cooldown() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe_to(&[EventKind::EmergencyStop], 4);
+        let mut detector = PickupDetector::new(PickupConfig::default()).with_bus(bus);
+        run(&mut detector, [0.0, 0.0, 0.0], 10);
+        assert_eq!(run(&mut detector, LEVEL, 150), PickupState::CoolingDown);
+        // Being handled again restarts the cooldown.
+        run(&mut detector, [0.0, 0.0, 0.0], 1);
+        assert_eq!(run(&mut detector, LEVEL, 150), PickupState::CoolingDown);
+        assert_eq!(run(&mut detector, LEVEL, 50), PickupState::Armed);
+        let engaged: Vec<_> = std::iter::from_fn(|| subscription.try_recv())
+            .map(|event| match event {
+                Event::EmergencyStop(stop) => stop.engaged,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(engaged, [true, false]);
+    }
+}
@@ -0,0 +1,5 @@
+//! Running user scripts without trusting them.
+
+pub mod sandbox;
+
+pub use sandbox::{Limits, Sandbox};
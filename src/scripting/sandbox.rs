@@ -0,0 +1,349 @@
+//! Execution limits for user scripts.
+//!
+//! A script run from the control loop must give the loop back on time, and a
+//! script someone downloaded must not read the robot’s keys or phone home. A
+//! [`Sandbox`] meters a script as its interpreter runs it: the interpreter
+//! charges instructions from its instruction hook, reports allocations from
+//! its allocator, and asks before exposing any capability, and the sandbox
+//! refuses once the script is over its [`Limits`]. Hitting a limit aborts the
+//! script’s current tick, not the robot, and is published as a [`Fault`] so
+//! the operator hears about it.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::events::{Event, EventBus, Fault};
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "scripting";
+
+/// Something outside the interpreter a script might reach for.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Capability {
+    /// Reading or writing files.
+    Filesystem,
+    /// Opening network connections.
+    Network,
+    /// Running other programs.
+    Process,
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Filesystem => "filesystem",
+            Self::Network => "network",
+            Self::Process => "process",
+        })
+    }
+}
+
+/// Limits on what a script may use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    capabilities: Vec<Capability>,
+    instruction_budget: u64,
+    memory_cap: usize,
+    time_limit: Duration,
+}
+
+impl Limits {
+    /// Creates new limits of 100 000 instructions and 5 ms per tick, 8 MiB
+    /// of memory, and no capabilities.
+    pub fn new() -> Self {
+        Self {
+            capabilities: Vec::new(),
+            instruction_budget: 100_000,
+            memory_cap: 8 * 1024 * 1024,
+            time_limit: Duration::from_millis(5),
+        }
+    }
+
+    /// Limits each tick to `budget` instructions.
+    #[must_use]
+    pub fn with_instruction_budget(mut self, budget: u64) -> Self {
+        self.instruction_budget = budget;
+        self
+    }
+
+    /// Limits each tick to `limit` of wall time, checked as instructions are
+    /// charged, for scripts that block in slow built-ins.
+    #[must_use]
+    pub fn with_time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = limit;
+        self
+    }
+
+    /// Limits the memory the script holds to `bytes`.
+    #[must_use]
+    pub fn with_memory_cap(mut self, bytes: usize) -> Self {
+        self.memory_cap = bytes;
+        self
+    }
+
+    /// Grants `capability`, which no script has unless granted.
+    #[must_use]
+    pub fn with_capability(mut self, capability: Capability) -> Self {
+        if !self.capabilities.contains(&capability) {
+            self.capabilities.push(capability);
+        }
+
+        self
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A limit a script ran into.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SandboxError {
+    /// The script used up its instructions for the tick.
+    InstructionBudget(u64),
+    /// The script ran past its time for the tick.
+    TimeLimit(Duration),
+    /// An allocation would take the script past its memory cap.
+    MemoryCap {
+        /// Bytes the script would hold.
+        requested: usize,
+        /// Most bytes the script may hold.
+        cap: usize,
+    },
+    /// The script reached for a capability it was not granted.
+    Denied(Capability),
+}
+
+impl Display for SandboxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InstructionBudget(budget) => {
+                write!(f, "script used its budget of {budget} instructions")
+            }
+            Self::TimeLimit(limit) => write!(f, "script ran past its limit of {limit:?}"),
+            Self::MemoryCap { requested, cap } => {
+                write!(f, "script needed {requested} bytes of its {cap} byte cap")
+            }
+            Self::Denied(capability) => write!(f, "script may not use the {capability}"),
+        }
+    }
+}
+
+impl Error for SandboxError {}
+
+/// Use of a sandbox, for diagnostics.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SandboxStats {
+    /// Ticks started.
+    pub ticks: u64,
+    /// Instructions run in the last tick.
+    pub instructions: u64,
+    /// Bytes the script holds.
+    pub memory: usize,
+    /// Most bytes the script has held.
+    pub peak_memory: usize,
+    /// Times the script hit a limit.
+    pub violations: u64,
+}
+
+impl ToJson for SandboxStats {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("ticks", self.ticks)
+            .with("instructions", self.instructions)
+            .with("memory", self.memory)
+            .with("peak_memory", self.peak_memory)
+            .with("violations", self.violations)
+    }
+}
+
+/// Meters one script against its limits.
+#[derive(Debug)]
+pub struct Sandbox {
+    bus: Option<EventBus>,
+    limits: Limits,
+    name: String,
+    started: Option<Instant>,
+    stats: SandboxStats,
+}
+
+impl Sandbox {
+    /// Creates a new `Sandbox` for the script `name`.
+    pub fn new(name: &str, limits: Limits) -> Self {
+        Self {
+            bus: None,
+            limits,
+            name: name.to_owned(),
+            started: None,
+            stats: SandboxStats::default(),
+        }
+    }
+
+    /// Publishes a fault on `bus` whenever the script hits a limit.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Starts a tick at `now`, resetting the instruction budget and clock.
+    pub fn start_tick(&mut self, now: Instant) {
+        self.started = Some(now);
+        self.stats.ticks += 1;
+        self.stats.instructions = 0;
+    }
+
+    /// Charges `count` instructions at `now`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the tick is over its
+    /// instruction budget or time limit, after which the interpreter should
+    /// abort the script’s tick.
+    pub fn charge(&mut self, count: u64, now: Instant) -> Result<(), SandboxError> {
+        self.stats.instructions = self.stats.instructions.saturating_add(count);
+
+        if self.stats.instructions > self.limits.instruction_budget {
+            return Err(self.violate(SandboxError::InstructionBudget(
+                self.limits.instruction_budget,
+            )));
+        }
+
+        let elapsed = self.started.map_or(Duration::ZERO, |started| {
+            now.saturating_duration_since(started)
+        });
+
+        if elapsed > self.limits.time_limit {
+            return Err(self.violate(SandboxError::TimeLimit(self.limits.time_limit)));
+        }
+
+        Ok(())
+    }
+
+    /// Records that the script resized an allocation from `old` to `new`
+    /// bytes, as an interpreter’s allocator reports.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error, without recording the change, if
+    /// the script would hold more than its cap, in which case the allocator
+    /// should fail the allocation.
+    pub fn reallocate(&mut self, old: usize, new: usize) -> Result<(), SandboxError> {
+        let requested = self.stats.memory.saturating_sub(old).saturating_add(new);
+
+        if new > old && requested > self.limits.memory_cap {
+            return Err(self.violate(SandboxError::MemoryCap {
+                requested,
+                cap: self.limits.memory_cap,
+            }));
+        }
+
+        self.stats.memory = requested;
+        self.stats.peak_memory = self.stats.peak_memory.max(requested);
+        Ok(())
+    }
+
+    /// Checks whether the script may use `capability`, as an interpreter
+    /// asks before exposing a library such as `io` or `socket`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the capability was not granted.
+    pub fn check(&mut self, capability: Capability) -> Result<(), SandboxError> {
+        if self.limits.capabilities.contains(&capability) {
+            Ok(())
+        } else {
+            Err(self.violate(SandboxError::Denied(capability)))
+        }
+    }
+
+    /// Returns how much of its limits the script has used.
+    #[must_use]
+    pub fn stats(&self) -> SandboxStats {
+        self.stats
+    }
+
+    fn violate(&mut self, error: SandboxError) -> SandboxError {
+        self.stats.violations += 1;
+        log_event!(SUBSYSTEM, Level::Warn, "{}: {error}", self.name);
+
+        if let Some(bus) = &self.bus {
+            bus.publish(Event::Fault(Fault {
+                subsystem: SUBSYSTEM.to_owned(),
+                message: format!("{}: {error}", self.name),
+            }));
+        }
+
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_stop_a_tick_over_its_budget_or_time() {
+        let limits = Limits::new()
+            .with_instruction_budget(1_000)
+            .with_time_limit(Duration::from_millis(2));
+        let mut sandbox = Sandbox::new("patrol.lua", limits);
+        let start = Instant::now();
+        sandbox.start_tick(start);
+        sandbox.charge(900, start).unwrap();
+        assert_eq!(
+            sandbox.charge(200, start),
+            Err(SandboxError::InstructionBudget(1_000))
+        );
+        sandbox.start_tick(start + Duration::from_millis(10));
+        sandbox
+            .charge(100, start + Duration::from_millis(11))
+            .unwrap();
+        assert_eq!(
+            sandbox.charge(100, start + Duration::from_millis(13)),
+            Err(SandboxError::TimeLimit(Duration::from_millis(2)))
+        );
+        assert_eq!(sandbox.stats().violations, 2);
+    }
+
+    #[test]
+    fn it_should_refuse_allocations_past_the_cap() {
+        let mut sandbox = Sandbox::new("patrol.lua", Limits::new().with_memory_cap(1_024));
+        sandbox.reallocate(0, 1_000).unwrap();
+        assert!(matches!(
+            sandbox.reallocate(0, 100),
+            Err(SandboxError::MemoryCap {
+                requested: 1_100,
+                ..
+            })
+        ));
+        // Shrinking is always allowed.
+        sandbox.reallocate(1_000, 10).unwrap();
+        sandbox.reallocate(0, 100).unwrap();
+        assert_eq!(sandbox.stats().memory, 110);
+        assert_eq!(sandbox.stats().peak_memory, 1_000);
+    }
+
+    #[test]
+    fn it_should_deny_capabilities_not_granted_and_raise_a_fault() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe(4);
+        let limits = Limits::new().with_capability(Capability::Filesystem);
+        let mut sandbox = Sandbox::new("patrol.lua", limits).with_bus(bus);
+        sandbox.check(Capability::Filesystem).unwrap();
+        assert_eq!(
+            sandbox.check(Capability::Network),
+            Err(SandboxError::Denied(Capability::Network))
+        );
+        let faults: Vec<_> = std::iter::from_fn(|| subscription.try_recv()).collect();
+        assert!(matches!(
+            &faults[..],
+            [Event::Fault(fault)] if fault.message == "patrol.lua: script may not use the network"
+        ));
+    }
+}
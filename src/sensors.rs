@@ -0,0 +1,12 @@
+//! Drivers for sensors attached to the robot.
+
+pub mod ads1115;
+pub mod as5600;
+pub mod bno055;
+pub mod debounce;
+pub mod gps;
+pub mod hall;
+pub mod hx711;
+pub mod ina219;
+pub mod measurement;
+pub mod pir;
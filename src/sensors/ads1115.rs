@@ -0,0 +1,388 @@
+//! Driver for the ADS1115 16-bit, 4-channel ADC.
+//!
+//! Conversions are [`Measurement`]s, invalid when the input is beyond the
+//! full-scale range of the gain and the result is clipped.
+
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::measurement::{Measurement, Validity};
+use crate::hal::I2c;
+
+/// Default I2C address of the ADS1115, with its address pin tied to ground.
+pub const DEFAULT_ADDRESS: u8 = 0x48;
+
+/// Source named in the ADS1115’s measurements.
+pub const SOURCE: &str = "ads1115";
+
+/// Register holding the last conversion.
+const CONVERSION: u8 = 0x00;
+
+/// Register holding the configuration.
+const CONFIG: u8 = 0x01;
+
+/// Bit of the configuration that starts a single-shot conversion when written
+/// and is set when no conversion is in progress.
+const CONFIG_OS: u16 = 0x8000;
+
+/// Bit of the configuration that selects single-shot mode.
+const CONFIG_MODE_SINGLE: u16 = 0x0100;
+
+/// Bits of the configuration that disable the comparator.
+const CONFIG_COMP_DISABLE: u16 = 0x0003;
+
+/// Input measured by a conversion.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Input {
+    /// AIN0 relative to AIN1.
+    Ain0MinusAin1 = 0b000,
+    /// AIN0 relative to AIN3.
+    Ain0MinusAin3 = 0b001,
+    /// AIN1 relative to AIN3.
+    Ain1MinusAin3 = 0b010,
+    /// AIN2 relative to AIN3.
+    Ain2MinusAin3 = 0b011,
+    /// AIN0 relative to ground.
+    Ain0 = 0b100,
+    /// AIN1 relative to ground.
+    Ain1 = 0b101,
+    /// AIN2 relative to ground.
+    Ain2 = 0b110,
+    /// AIN3 relative to ground.
+    Ain3 = 0b111,
+}
+
+/// Gain of the programmable amplifier, named by its full-scale range.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Gain {
+    /// ±6.144 V.
+    Fsr6_144V,
+    /// ±4.096 V.
+    Fsr4_096V,
+    /// ±2.048 V.
+    #[default]
+    Fsr2_048V,
+    /// ±1.024 V.
+    Fsr1_024V,
+    /// ±0.512 V.
+    Fsr0_512V,
+    /// ±0.256 V.
+    Fsr0_256V,
+}
+
+impl Gain {
+    /// Returns the full-scale range in volts.
+    #[must_use]
+    pub fn full_scale(self) -> f64 {
+        match self {
+            Self::Fsr6_144V => 6.144,
+            Self::Fsr4_096V => 4.096,
+            Self::Fsr2_048V => 2.048,
+            Self::Fsr1_024V => 1.024,
+            Self::Fsr0_512V => 0.512,
+            Self::Fsr0_256V => 0.256,
+        }
+    }
+
+    fn bits(self) -> u16 {
+        match self {
+            Self::Fsr6_144V => 0b000,
+            Self::Fsr4_096V => 0b001,
+            Self::Fsr2_048V => 0b010,
+            Self::Fsr1_024V => 0b011,
+            Self::Fsr0_512V => 0b100,
+            Self::Fsr0_256V => 0b101,
+        }
+    }
+}
+
+/// Number of conversions per second.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum DataRate {
+    /// 8 samples per second.
+    Sps8,
+    /// 16 samples per second.
+    Sps16,
+    /// 32 samples per second.
+    Sps32,
+    /// 64 samples per second.
+    Sps64,
+    /// 128 samples per second.
+    #[default]
+    Sps128,
+    /// 250 samples per second.
+    Sps250,
+    /// 475 samples per second.
+    Sps475,
+    /// 860 samples per second.
+    Sps860,
+}
+
+impl DataRate {
+    /// Returns the number of samples per second.
+    #[must_use]
+    pub fn samples_per_second(self) -> u16 {
+        match self {
+            Self::Sps8 => 8,
+            Self::Sps16 => 16,
+            Self::Sps32 => 32,
+            Self::Sps64 => 64,
+            Self::Sps128 => 128,
+            Self::Sps250 => 250,
+            Self::Sps475 => 475,
+            Self::Sps860 => 860,
+        }
+    }
+
+    /// Returns the time taken by one conversion.
+    #[must_use]
+    pub fn conversion_time(self) -> Duration {
+        Duration::from_secs(1) / u32::from(self.samples_per_second())
+    }
+
+    fn bits(self) -> u16 {
+        self as u16
+    }
+}
+
+/// An ADS1115 on an I2C bus.
+#[derive(Debug)]
+pub struct Ads1115<I> {
+    address: u8,
+    data_rate: DataRate,
+    gain: Gain,
+    i2c: I,
+}
+
+impl<I: I2c> Ads1115<I> {
+    /// Creates a new `Ads1115` at `address` with a ±2.048 V range at 128
+    /// samples per second.
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self {
+            address,
+            data_rate: DataRate::default(),
+            gain: Gain::default(),
+            i2c,
+        }
+    }
+
+    /// Sets the gain used by subsequent conversions.
+    #[must_use]
+    pub fn with_gain(mut self, gain: Gain) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// Sets the data rate used by subsequent conversions.
+    #[must_use]
+    pub fn with_data_rate(mut self, data_rate: DataRate) -> Self {
+        self.data_rate = data_rate;
+        self
+    }
+
+    /// Returns the gain.
+    #[must_use]
+    pub fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    /// Sets the gain used by subsequent conversions.
+    ///
+    /// A continuous conversion must be restarted to pick up the change.
+    pub fn set_gain(&mut self, gain: Gain) {
+        self.gain = gain;
+    }
+
+    /// Starts a single-shot conversion of `input`, waits for it, and returns
+    /// the raw result, invalid if it was clipped to the full-scale range.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be
+    /// accessed, or with [`ErrorKind::TimedOut`] if the conversion does not
+    /// finish within twice its expected time.
+    pub fn read_single(&mut self, input: Input) -> Result<Measurement<i16>, Error> {
+        let conversion_time = self.data_rate.conversion_time();
+        let deadline = Instant::now() + conversion_time * 2;
+        self.write_config(self.config(input) | CONFIG_OS | CONFIG_MODE_SINGLE)?;
+        thread::sleep(conversion_time);
+
+        while self.read(CONFIG)? & CONFIG_OS == 0 {
+            if Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::TimedOut, "ADS1115 conversion"));
+            }
+
+            thread::sleep(Duration::from_micros(100));
+        }
+
+        self.read_conversion()
+    }
+
+    /// Starts a single-shot conversion of `input` and returns the result in
+    /// volts, invalid if it was clipped to the full-scale range.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the conversion fails.
+    pub fn read_voltage(&mut self, input: Input) -> Result<Measurement<f64>, Error> {
+        let raw = self.read_single(input)?;
+        Ok(raw.map(|raw| self.to_volts(raw)))
+    }
+
+    /// Starts converting `input` continuously.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be written.
+    pub fn start_continuous(&mut self, input: Input) -> Result<(), Error> {
+        self.write_config(self.config(input))
+    }
+
+    /// Stops converting continuously and powers the converter down.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be written.
+    pub fn stop_continuous(&mut self) -> Result<(), Error> {
+        self.write_config(self.config(Input::Ain0MinusAin1) | CONFIG_MODE_SINGLE)
+    }
+
+    /// Returns the raw result of the most recent conversion, invalid if it
+    /// was clipped to the full-scale range.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be read.
+    pub fn read_conversion(&mut self) -> Result<Measurement<i16>, Error> {
+        let raw = self.read(CONVERSION)? as i16;
+        let validity = if raw == i16::MIN || raw == i16::MAX {
+            Validity::Invalid("input is beyond the full-scale range")
+        } else {
+            Validity::Valid
+        };
+        Ok(Measurement::new(SOURCE, raw, Instant::now()).with_validity(validity))
+    }
+
+    /// Converts a raw result into volts using the current gain.
+    #[must_use]
+    pub fn to_volts(&self, raw: i16) -> f64 {
+        f64::from(raw) * self.gain.full_scale() / 32768.0
+    }
+
+    /// Consumes the driver and returns its bus.
+    pub fn into_inner(self) -> I {
+        self.i2c
+    }
+
+    fn config(&self, input: Input) -> u16 {
+        (input as u16) << 12
+            | self.gain.bits() << 9
+            | self.data_rate.bits() << 5
+            | CONFIG_COMP_DISABLE
+    }
+
+    fn read(&mut self, register: u8) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        self.i2c
+            .write_read(self.address, &[register], &mut buffer)?;
+        Ok(u16::from_be_bytes(buffer))
+    }
+
+    fn write_config(&mut self, config: u16) -> Result<(), Error> {
+        let [high, low] = config.to_be_bytes();
+        self.i2c.write(self.address, &[CONFIG, high, low])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ADS1115 with 16-bit registers that finishes conversions instantly.
+    #[derive(Debug, Default)]
+    struct Mock {
+        config: u16,
+        conversion: u16,
+        busy: bool,
+    }
+
+    impl I2c for Mock {
+        fn write(&mut self, _address: u8, bytes: &[u8]) -> Result<(), Error> {
+            if let [CONFIG, high, low] = *bytes {
+                self.config = u16::from_be_bytes([high, low]);
+            }
+
+            Ok(())
+        }
+
+        fn write_read(
+            &mut self,
+            _address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), Error> {
+            let value = match bytes {
+                [CONVERSION] => self.conversion,
+                _ if self.busy => self.config & !CONFIG_OS,
+                _ => self.config | CONFIG_OS,
+            };
+            buffer.copy_from_slice(&value.to_be_bytes());
+            Ok(())
+        }
+    }
+
+    fn ads1115(conversion: i16) -> Ads1115<Mock> {
+        let mock = Mock {
+            conversion: conversion as u16,
+            ..Mock::default()
+        };
+        Ads1115::new(mock, DEFAULT_ADDRESS).with_data_rate(DataRate::Sps860)
+    }
+
+    #[test]
+    fn it_should_configure_a_single_shot_conversion() {
+        let mut ads1115 = ads1115(1234).with_gain(Gain::Fsr4_096V);
+        assert_eq!(ads1115.read_single(Input::Ain2).unwrap().value, 1234);
+        assert_eq!(ads1115.i2c.config, 0b1110_0011_1110_0011);
+    }
+
+    #[test]
+    fn it_should_configure_a_differential_continuous_conversion() {
+        let mut ads1115 = ads1115(-500);
+        ads1115.start_continuous(Input::Ain2MinusAin3).unwrap();
+        assert_eq!(ads1115.i2c.config, 0b0011_0100_1110_0011);
+        assert_eq!(ads1115.read_conversion().unwrap().value, -500);
+        ads1115.stop_continuous().unwrap();
+        assert_ne!(ads1115.i2c.config & CONFIG_MODE_SINGLE, 0);
+    }
+
+    #[test]
+    fn it_should_convert_a_result_to_volts_for_the_gain() {
+        let mut ads1115 = ads1115(16384).with_gain(Gain::Fsr0_256V);
+        let voltage = ads1115.read_voltage(Input::Ain0).unwrap();
+        assert_eq!((voltage.value, voltage.source), (0.128, SOURCE));
+        assert!(voltage.is_valid());
+        ads1115.set_gain(Gain::Fsr6_144V);
+        assert_eq!(ads1115.to_volts(-32768), -6.144);
+    }
+
+    #[test]
+    fn it_should_mark_clipped_conversions_invalid() {
+        for raw in [i16::MAX, i16::MIN] {
+            let conversion = ads1115(raw).read_single(Input::Ain1).unwrap();
+            assert_eq!(
+                conversion.validity,
+                Validity::Invalid("input is beyond the full-scale range")
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_time_out_when_a_conversion_does_not_finish() {
+        let mut ads1115 = ads1115(0);
+        ads1115.i2c.busy = true;
+        let error = ads1115.read_single(Input::Ain0).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
+}
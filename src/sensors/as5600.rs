@@ -0,0 +1,360 @@
+//! Driver for the AS5600 12-bit magnetic angle sensor.
+//!
+//! The sensor reports an absolute angle within a single turn. Full turns are
+//! counted in software by watching for the angle wrapping around, so
+//! [`As5600::update`] must be called at least twice per half turn for the
+//! count to stay correct. Angles are [`Measurement`]s, invalid while the
+//! magnet is missing or out of range.
+
+use std::f64::consts::TAU;
+use std::io::Error;
+use std::time::Instant;
+
+use super::measurement::{Measurement, Validity};
+use crate::hal::{Encoder, I2c};
+
+/// Fixed I2C address of the AS5600.
+pub const ADDRESS: u8 = 0x36;
+
+/// Source named in the AS5600’s measurements.
+pub const SOURCE: &str = "as5600";
+
+/// Number of counts in one full turn.
+pub const COUNTS_PER_TURN: u16 = 4096;
+
+/// Register holding the zero position.
+const ZPOS: u8 = 0x01;
+
+/// Register holding the status of the magnet.
+const STATUS: u8 = 0x0B;
+
+/// Register holding the angle before the zero position is applied.
+const RAW_ANGLE: u8 = 0x0C;
+
+/// Register holding the angle after the zero position is applied.
+const ANGLE: u8 = 0x0E;
+
+/// Register holding the automatic gain control value.
+const AGC: u8 = 0x1A;
+
+/// Mask of the 12 bits that make up an angle.
+const ANGLE_MASK: u16 = 0x0FFF;
+
+/// Status of the magnet above the sensor.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MagnetStatus {
+    /// A magnet was detected.
+    pub detected: bool,
+    /// The magnet is too strong, or too close to the sensor.
+    pub too_strong: bool,
+    /// The magnet is too weak, or too far from the sensor.
+    pub too_weak: bool,
+}
+
+impl MagnetStatus {
+    /// Returns `true` if a magnet was detected within the usable range.
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.detected && !self.too_strong && !self.too_weak
+    }
+
+    /// Returns whether angles read with the magnet in this state can be
+    /// trusted.
+    #[must_use]
+    pub fn validity(&self) -> Validity {
+        if !self.detected {
+            Validity::Invalid("magnet not detected")
+        } else if self.too_strong {
+            Validity::Invalid("magnet too strong")
+        } else if self.too_weak {
+            Validity::Invalid("magnet too weak")
+        } else {
+            Validity::Valid
+        }
+    }
+}
+
+/// An AS5600 on an I2C bus.
+#[derive(Debug)]
+pub struct As5600<I> {
+    i2c: I,
+    last: Option<u16>,
+    turns: i64,
+}
+
+impl<I: I2c> As5600<I> {
+    /// Creates a new `As5600` with a turn count of zero.
+    pub fn new(i2c: I) -> Self {
+        Self {
+            i2c,
+            last: None,
+            turns: 0,
+        }
+    }
+
+    /// Returns the angle, in counts, before the zero position is applied,
+    /// invalid unless the magnet is in range.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    pub fn raw_angle(&mut self) -> Result<Measurement<u16>, Error> {
+        self.read_angle(RAW_ANGLE)
+    }
+
+    /// Returns the angle, in counts, relative to the zero position, invalid
+    /// unless the magnet is in range.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    pub fn angle(&mut self) -> Result<Measurement<u16>, Error> {
+        self.read_angle(ANGLE)
+    }
+
+    /// Returns the zero position in raw counts.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be read.
+    pub fn zero_position(&mut self) -> Result<u16, Error> {
+        Ok(self.read_u16(ZPOS)? & ANGLE_MASK)
+    }
+
+    /// Sets the zero position in raw counts.
+    ///
+    /// The setting is lost when the sensor loses power, so it should be
+    /// restored from saved parameters at startup. Resets the turn count.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be written.
+    pub fn set_zero_position(&mut self, position: u16) -> Result<(), Error> {
+        let [high, low] = (position & ANGLE_MASK).to_be_bytes();
+        self.i2c.write(ADDRESS, &[ZPOS, high, low])?;
+        self.reset_turns();
+        Ok(())
+    }
+
+    /// Makes the current angle the zero position and returns it in raw
+    /// counts.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be
+    /// accessed, or with
+    /// [`ErrorKind::InvalidData`](std::io::ErrorKind::InvalidData) if the
+    /// magnet is not in range.
+    pub fn zero_here(&mut self) -> Result<u16, Error> {
+        let position = self.raw_angle()?.valid()?;
+        self.set_zero_position(position)?;
+        Ok(position)
+    }
+
+    /// Returns the status of the magnet.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be read.
+    pub fn magnet_status(&mut self) -> Result<MagnetStatus, Error> {
+        let status = self.read_u8(STATUS)?;
+        Ok(MagnetStatus {
+            detected: status & 0x20 != 0,
+            too_strong: status & 0x08 != 0,
+            too_weak: status & 0x10 != 0,
+        })
+    }
+
+    /// Returns the automatic gain control value, which drifts with the
+    /// distance between the magnet and the sensor.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be read.
+    pub fn agc(&mut self) -> Result<u8, Error> {
+        self.read_u8(AGC)
+    }
+
+    /// Reads the angle and returns the position, in counts, including full
+    /// turns since the turn count was last reset, invalid unless the magnet
+    /// is in range.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    pub fn update(&mut self) -> Result<Measurement<i64>, Error> {
+        let measurement = self.angle()?;
+        let angle = measurement.value;
+
+        if let Some(last) = self.last {
+            let delta = i32::from(angle) - i32::from(last);
+            let half_turn = i32::from(COUNTS_PER_TURN / 2);
+
+            if delta > half_turn {
+                self.turns -= 1;
+            } else if delta < -half_turn {
+                self.turns += 1;
+            }
+        }
+
+        self.last = Some(angle);
+        Ok(measurement.map(|_| self.counts()))
+    }
+
+    /// Returns the position in radians as of the last update.
+    #[must_use]
+    pub fn position(&self) -> f64 {
+        self.counts() as f64 * TAU / f64::from(COUNTS_PER_TURN)
+    }
+
+    /// Returns the number of full turns as of the last update.
+    #[must_use]
+    pub fn turns(&self) -> i64 {
+        self.turns
+    }
+
+    /// Resets the turn count to zero.
+    pub fn reset_turns(&mut self) {
+        self.last = None;
+        self.turns = 0;
+    }
+
+    /// Consumes the driver and returns its bus.
+    pub fn into_inner(self) -> I {
+        self.i2c
+    }
+
+    fn counts(&self) -> i64 {
+        self.turns * i64::from(COUNTS_PER_TURN) + i64::from(self.last.unwrap_or(0))
+    }
+
+    fn read_angle(&mut self, register: u8) -> Result<Measurement<u16>, Error> {
+        let angle = self.read_u16(register)? & ANGLE_MASK;
+        let time = Instant::now();
+        let validity = self.magnet_status()?.validity();
+        Ok(Measurement::new(SOURCE, angle, time).with_validity(validity))
+    }
+
+    fn read_u8(&mut self, register: u8) -> Result<u8, Error> {
+        let mut buffer = [0];
+        self.i2c.write_read(ADDRESS, &[register], &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    fn read_u16(&mut self, register: u8) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        self.i2c.write_read(ADDRESS, &[register], &mut buffer)?;
+        Ok(u16::from_be_bytes(buffer))
+    }
+}
+
+impl<I: I2c> Encoder for As5600<I> {
+    fn count(&mut self) -> Result<i64, Error> {
+        Ok(self.update()?.valid()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockI2c;
+
+    fn as5600() -> As5600<MockI2c> {
+        let mut i2c = MockI2c::new(ADDRESS);
+        i2c.registers[usize::from(STATUS)] = 0x20;
+        As5600::new(i2c)
+    }
+
+    fn set_angle(sensor: &mut As5600<MockI2c>, angle: u16) {
+        let [high, low] = angle.to_be_bytes();
+        sensor.i2c.registers[usize::from(ANGLE)] = high;
+        sensor.i2c.registers[usize::from(ANGLE) + 1] = low;
+    }
+
+    #[test]
+    fn it_should_mask_the_angle_to_12_bits() {
+        let mut sensor = as5600();
+        sensor.i2c.registers[usize::from(RAW_ANGLE)] = 0xFA;
+        sensor.i2c.registers[usize::from(RAW_ANGLE) + 1] = 0xBC;
+        assert_eq!(sensor.raw_angle().unwrap().value, 0x0ABC);
+    }
+
+    #[test]
+    fn it_should_mark_angles_invalid_without_a_magnet_in_range() {
+        let mut sensor = as5600();
+        let before = Instant::now();
+        let angle = sensor.angle().unwrap();
+        assert!(angle.is_valid() && angle.time >= before);
+        sensor.i2c.registers[usize::from(STATUS)] = 0x00;
+        assert_eq!(
+            sensor.update().unwrap().validity,
+            Validity::Invalid("magnet not detected")
+        );
+        assert!(sensor.count().is_err());
+        sensor.i2c.registers[usize::from(STATUS)] = 0x30;
+        assert_eq!(
+            sensor.angle().unwrap().validity,
+            Validity::Invalid("magnet too weak")
+        );
+        assert!(sensor.zero_here().is_err());
+    }
+
+    #[test]
+    fn it_should_write_the_current_angle_as_the_zero_position() {
+        let mut sensor = as5600();
+        sensor.i2c.registers[usize::from(RAW_ANGLE)] = 0x03;
+        sensor.i2c.registers[usize::from(RAW_ANGLE) + 1] = 0x21;
+        assert_eq!(sensor.zero_here().unwrap(), 0x0321);
+        assert_eq!(sensor.zero_position().unwrap(), 0x0321);
+    }
+
+    #[test]
+    fn it_should_decode_the_magnet_status() {
+        let mut sensor = as5600();
+        sensor.i2c.registers[usize::from(STATUS)] = 0x20;
+        assert!(sensor.magnet_status().unwrap().is_ok());
+        sensor.i2c.registers[usize::from(STATUS)] = 0x28;
+        let status = sensor.magnet_status().unwrap();
+        assert!(status.too_strong && !status.is_ok());
+    }
+
+    #[test]
+    fn it_should_count_turns_forward_across_the_wrap() {
+        let mut sensor = as5600();
+
+        for angle in [3000, 4000, 100, 1200, 2400, 3600, 500] {
+            set_angle(&mut sensor, angle);
+            sensor.update().unwrap();
+        }
+
+        assert_eq!(sensor.turns(), 2);
+        assert_eq!(sensor.update().unwrap().value, 2 * 4096 + 500);
+    }
+
+    #[test]
+    fn it_should_count_turns_backward_across_the_wrap() {
+        let mut sensor = as5600();
+
+        for angle in [100, 4000, 3000] {
+            set_angle(&mut sensor, angle);
+            sensor.update().unwrap();
+        }
+
+        assert_eq!(sensor.turns(), -1);
+        assert_eq!(sensor.update().unwrap().value, 3000 - 4096);
+        assert!((sensor.position() - (-1096.0 * TAU / 4096.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_should_reset_the_turn_count_when_the_zero_position_changes() {
+        let mut sensor = as5600();
+
+        for angle in [4000, 100] {
+            set_angle(&mut sensor, angle);
+            sensor.update().unwrap();
+        }
+
+        sensor.set_zero_position(0).unwrap();
+        assert_eq!(sensor.turns(), 0);
+    }
+}
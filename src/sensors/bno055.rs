@@ -0,0 +1,669 @@
+//! Driver for the BNO055 9-axis absolute orientation sensor.
+//!
+//! The BNO055 runs its own sensor fusion, so in a fusion mode it reports an
+//! orientation quaternion directly and the Pi does not need to run a filter.
+//! Readings are [`Measurement`]s, invalid while the sensor behind them
+//! reports no calibration at all.
+
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::measurement::{Measurement, Validity};
+use crate::hal::I2c;
+
+/// Default I2C address of the BNO055, with its address pin low.
+pub const DEFAULT_ADDRESS: u8 = 0x28;
+
+/// Source named in the BNO055’s measurements.
+pub const SOURCE: &str = "bno055";
+
+/// Value of the chip identifier register.
+const CHIP_ID_VALUE: u8 = 0xA0;
+
+/// Register holding the chip identifier.
+const CHIP_ID: u8 = 0x00;
+
+/// Register holding the first byte of the raw acceleration.
+const ACC_DATA: u8 = 0x08;
+
+/// Register holding the first byte of the magnetic field.
+const MAG_DATA: u8 = 0x0E;
+
+/// Register holding the first byte of the Euler angles.
+const EUL_DATA: u8 = 0x1A;
+
+/// Register holding the first byte of the quaternion.
+const QUA_DATA: u8 = 0x20;
+
+/// Register holding the first byte of the linear acceleration.
+const LIA_DATA: u8 = 0x28;
+
+/// Register holding the calibration status of each sensor.
+const CALIB_STAT: u8 = 0x35;
+
+/// Register holding the operation mode.
+const OPR_MODE: u8 = 0x3D;
+
+/// Register triggering a reset.
+const SYS_TRIGGER: u8 = 0x3F;
+
+/// Register selecting the source of each axis.
+const AXIS_MAP_CONFIG: u8 = 0x41;
+
+/// Register selecting the sign of each axis.
+const AXIS_MAP_SIGN: u8 = 0x42;
+
+/// Register holding the first byte of the calibration offsets.
+const ACC_OFFSET: u8 = 0x55;
+
+/// Number of bytes of calibration offsets and radii.
+pub const CALIBRATION_LEN: usize = 22;
+
+/// Time taken to switch out of configuration mode.
+const FROM_CONFIG_TIME: Duration = Duration::from_millis(7);
+
+/// Time taken to switch into configuration mode.
+const TO_CONFIG_TIME: Duration = Duration::from_millis(19);
+
+/// Operation mode of the sensor.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Mode {
+    /// Configuration mode, in which no data is produced.
+    Config = 0x00,
+    /// Accelerometer only.
+    AccOnly = 0x01,
+    /// Magnetometer only.
+    MagOnly = 0x02,
+    /// Gyroscope only.
+    GyroOnly = 0x03,
+    /// Accelerometer and magnetometer.
+    AccMag = 0x04,
+    /// Accelerometer and gyroscope.
+    AccGyro = 0x05,
+    /// Magnetometer and gyroscope.
+    MagGyro = 0x06,
+    /// Every sensor without fusion.
+    Amg = 0x07,
+    /// Fusion of the accelerometer and gyroscope, relative to the starting
+    /// heading.
+    Imu = 0x08,
+    /// Fusion of the accelerometer and magnetometer.
+    Compass = 0x09,
+    /// Fusion of the accelerometer and magnetometer, using the magnetometer in
+    /// place of a gyroscope.
+    M4g = 0x0A,
+    /// Fusion of every sensor without fast magnetometer calibration.
+    NdofFmcOff = 0x0B,
+    /// Fusion of every sensor, giving an absolute orientation.
+    Ndof = 0x0C,
+}
+
+impl Mode {
+    /// Returns `true` if the mode produces fused orientation.
+    #[must_use]
+    pub fn is_fusion(self) -> bool {
+        self as u8 >= Self::Imu as u8
+    }
+}
+
+/// Calibration level of each sensor, from 0 for uncalibrated to 3 for fully
+/// calibrated.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CalibrationStatus {
+    /// Calibration of the fusion system as a whole.
+    pub system: u8,
+    /// Calibration of the gyroscope.
+    pub gyroscope: u8,
+    /// Calibration of the accelerometer.
+    pub accelerometer: u8,
+    /// Calibration of the magnetometer.
+    pub magnetometer: u8,
+}
+
+impl CalibrationStatus {
+    /// Returns `true` if every sensor is fully calibrated.
+    #[must_use]
+    pub fn is_fully_calibrated(&self) -> bool {
+        [
+            self.system,
+            self.gyroscope,
+            self.accelerometer,
+            self.magnetometer,
+        ] == [3; 4]
+    }
+}
+
+/// Axis of the sensor’s own frame.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Axis {
+    /// The sensor’s X axis.
+    X = 0b00,
+    /// The sensor’s Y axis.
+    Y = 0b01,
+    /// The sensor’s Z axis.
+    Z = 0b10,
+}
+
+/// Mapping from the sensor’s axes to the robot’s axes, for when the sensor is
+/// not mounted in its default orientation.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AxisRemap {
+    /// Sensor axis reported as X.
+    pub x: Axis,
+    /// Sensor axis reported as Y.
+    pub y: Axis,
+    /// Sensor axis reported as Z.
+    pub z: Axis,
+    /// Negates the X axis.
+    pub negate_x: bool,
+    /// Negates the Y axis.
+    pub negate_y: bool,
+    /// Negates the Z axis.
+    pub negate_z: bool,
+}
+
+impl AxisRemap {
+    fn config(&self) -> u8 {
+        (self.z as u8) << 4 | (self.y as u8) << 2 | self.x as u8
+    }
+
+    fn sign(&self) -> u8 {
+        u8::from(self.negate_x) << 2 | u8::from(self.negate_y) << 1 | u8::from(self.negate_z)
+    }
+
+    fn is_permutation(&self) -> bool {
+        self.x != self.y && self.y != self.z && self.x != self.z
+    }
+}
+
+impl Default for AxisRemap {
+    fn default() -> Self {
+        Self {
+            x: Axis::X,
+            y: Axis::Y,
+            z: Axis::Z,
+            negate_x: false,
+            negate_y: false,
+            negate_z: false,
+        }
+    }
+}
+
+/// Orientation as a unit quaternion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    /// Scalar component.
+    pub w: f64,
+    /// X component.
+    pub x: f64,
+    /// Y component.
+    pub y: f64,
+    /// Z component.
+    pub z: f64,
+}
+
+/// Orientation as Euler angles, in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EulerAngles {
+    /// Heading, from 0 to 360.
+    pub heading: f64,
+    /// Roll, from -90 to 90.
+    pub roll: f64,
+    /// Pitch, from -180 to 180.
+    pub pitch: f64,
+}
+
+/// Sensor whose calibration decides whether a reading is valid.
+#[derive(Clone, Copy, Debug)]
+enum Calibrated {
+    System,
+    Accelerometer,
+    Magnetometer,
+}
+
+/// A BNO055 on an I2C bus.
+#[derive(Debug)]
+pub struct Bno055<I> {
+    address: u8,
+    i2c: I,
+    mode: Mode,
+}
+
+impl<I: I2c> Bno055<I> {
+    /// Connects to the BNO055 at `address`, leaving it in configuration mode.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the chip cannot be read, or with
+    /// [`ErrorKind::InvalidData`] if the chip identifier does not match.
+    pub fn new(i2c: I, address: u8) -> Result<Self, Error> {
+        let mut bno055 = Self {
+            address,
+            i2c,
+            mode: Mode::Config,
+        };
+        let chip_id = bno055.read_u8(CHIP_ID)?;
+
+        if chip_id != CHIP_ID_VALUE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected BNO055 chip ID {chip_id:#04x}"),
+            ));
+        }
+
+        bno055.set_mode(Mode::Config)?;
+        Ok(bno055)
+    }
+
+    /// Returns the operation mode.
+    #[must_use]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switches the operation mode and waits for the switch to complete.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be written.
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), Error> {
+        self.write(&[OPR_MODE, mode as u8])?;
+        thread::sleep(if mode == Mode::Config {
+            TO_CONFIG_TIME
+        } else {
+            FROM_CONFIG_TIME
+        });
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// Applies `remap` so readings are reported in the robot’s frame.
+    ///
+    /// The sensor is briefly put in configuration mode and then returned to
+    /// its current mode.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written,
+    /// or with [`ErrorKind::InvalidInput`] if `remap` maps two axes to the
+    /// same sensor axis.
+    pub fn set_axis_remap(&mut self, remap: AxisRemap) -> Result<(), Error> {
+        if !remap.is_permutation() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "axis remap must use each sensor axis once",
+            ));
+        }
+
+        self.configure(|bno055| {
+            bno055.write(&[AXIS_MAP_CONFIG, remap.config()])?;
+            bno055.write(&[AXIS_MAP_SIGN, remap.sign()])
+        })
+    }
+
+    /// Returns the calibration level of each sensor.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be read.
+    pub fn calibration_status(&mut self) -> Result<CalibrationStatus, Error> {
+        let status = self.read_u8(CALIB_STAT)?;
+        Ok(CalibrationStatus {
+            system: status >> 6 & 0b11,
+            gyroscope: status >> 4 & 0b11,
+            accelerometer: status >> 2 & 0b11,
+            magnetometer: status & 0b11,
+        })
+    }
+
+    /// Returns the calibration offsets and radii, which can be saved once the
+    /// sensor is fully calibrated and restored at the next startup.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    pub fn calibration(&mut self) -> Result<[u8; CALIBRATION_LEN], Error> {
+        let mut calibration = [0; CALIBRATION_LEN];
+        self.configure(|bno055| bno055.read_into(ACC_OFFSET, &mut calibration))?;
+        Ok(calibration)
+    }
+
+    /// Restores calibration offsets and radii saved by
+    /// [`Bno055::calibration`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be written.
+    pub fn set_calibration(&mut self, calibration: &[u8; CALIBRATION_LEN]) -> Result<(), Error> {
+        let mut bytes = [0; CALIBRATION_LEN + 1];
+        bytes[0] = ACC_OFFSET;
+        bytes[1..].copy_from_slice(calibration);
+        self.configure(|bno055| bno055.write(&bytes))
+    }
+
+    /// Returns the fused orientation, invalid until the fusion has any
+    /// system calibration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read, or
+    /// with [`ErrorKind::InvalidInput`] if the sensor is not in a fusion mode.
+    pub fn quaternion(&mut self) -> Result<Measurement<Quaternion>, Error> {
+        self.require_fusion()?;
+        let [w, x, y, z] = self.read_i16s(QUA_DATA)?;
+        let scale = f64::from(1_u16 << 14);
+        let quaternion = Quaternion {
+            w: f64::from(w) / scale,
+            x: f64::from(x) / scale,
+            y: f64::from(y) / scale,
+            z: f64::from(z) / scale,
+        };
+        self.measure(quaternion, Calibrated::System)
+    }
+
+    /// Returns the fused orientation as Euler angles, invalid until the
+    /// fusion has any system calibration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read, or
+    /// with [`ErrorKind::InvalidInput`] if the sensor is not in a fusion mode.
+    pub fn euler_angles(&mut self) -> Result<Measurement<EulerAngles>, Error> {
+        self.require_fusion()?;
+        let [heading, roll, pitch] = self.read_i16s(EUL_DATA)?;
+        let angles = EulerAngles {
+            heading: f64::from(heading) / 16.0,
+            roll: f64::from(roll) / 16.0,
+            pitch: f64::from(pitch) / 16.0,
+        };
+        self.measure(angles, Calibrated::System)
+    }
+
+    /// Returns the acceleration without gravity, in metres per second squared,
+    /// invalid until the fusion has any system calibration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read, or
+    /// with [`ErrorKind::InvalidInput`] if the sensor is not in a fusion mode.
+    pub fn linear_acceleration(&mut self) -> Result<Measurement<[f64; 3]>, Error> {
+        self.require_fusion()?;
+        let acceleration = self
+            .read_i16s(LIA_DATA)?
+            .map(|value| f64::from(value) / 100.0);
+        self.measure(acceleration, Calibrated::System)
+    }
+
+    /// Returns the acceleration including gravity, in metres per second
+    /// squared, which points up when the sensor is at rest, invalid until the
+    /// accelerometer has any calibration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    pub fn acceleration(&mut self) -> Result<Measurement<[f64; 3]>, Error> {
+        let acceleration = self
+            .read_i16s(ACC_DATA)?
+            .map(|value| f64::from(value) / 100.0);
+        self.measure(acceleration, Calibrated::Accelerometer)
+    }
+
+    /// Returns the magnetic field, in microteslas, invalid until the
+    /// magnetometer has any calibration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the registers cannot be read.
+    pub fn magnetic_field(&mut self) -> Result<Measurement<[f64; 3]>, Error> {
+        let field = self
+            .read_i16s(MAG_DATA)?
+            .map(|value| f64::from(value) / 16.0);
+        self.measure(field, Calibrated::Magnetometer)
+    }
+
+    /// Resets the sensor, which returns it to configuration mode.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be written.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.write(&[SYS_TRIGGER, 0x20])?;
+        self.mode = Mode::Config;
+        Ok(())
+    }
+
+    /// Consumes the driver and returns its bus.
+    pub fn into_inner(self) -> I {
+        self.i2c
+    }
+
+    fn configure(&mut self, f: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        let mode = self.mode;
+
+        if mode != Mode::Config {
+            self.set_mode(Mode::Config)?;
+        }
+
+        let result = f(self);
+
+        if mode != Mode::Config {
+            self.set_mode(mode)?;
+        }
+
+        result
+    }
+
+    /// Returns `value`, read just now, as a measurement that is invalid if
+    /// `sensor` has no calibration.
+    fn measure<T>(&mut self, value: T, sensor: Calibrated) -> Result<Measurement<T>, Error> {
+        let time = Instant::now();
+        let status = self.calibration_status()?;
+        let (level, reason) = match sensor {
+            Calibrated::System => (status.system, "fusion is uncalibrated"),
+            Calibrated::Accelerometer => (status.accelerometer, "accelerometer is uncalibrated"),
+            Calibrated::Magnetometer => (status.magnetometer, "magnetometer is uncalibrated"),
+        };
+        let validity = if level == 0 {
+            Validity::Invalid(reason)
+        } else {
+            Validity::Valid
+        };
+        Ok(Measurement::new(SOURCE, value, time).with_validity(validity))
+    }
+
+    fn require_fusion(&self) -> Result<(), Error> {
+        if self.mode.is_fusion() {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} mode does not produce fused orientation", self.mode),
+            ))
+        }
+    }
+
+    fn read_i16s<const N: usize>(&mut self, register: u8) -> Result<[i16; N], Error> {
+        let mut buffer = vec![0; N * 2];
+        self.read_into(register, &mut buffer)?;
+        let mut values = [0; N];
+
+        for (value, bytes) in values.iter_mut().zip(buffer.chunks_exact(2)) {
+            *value = i16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        Ok(values)
+    }
+
+    fn read_into(&mut self, register: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.i2c.write_read(self.address, &[register], buffer)
+    }
+
+    fn read_u8(&mut self, register: u8) -> Result<u8, Error> {
+        let mut buffer = [0];
+        self.read_into(register, &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.i2c.write(self.address, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::MockI2c;
+
+    fn bno055() -> Bno055<MockI2c> {
+        let mut i2c = MockI2c::new(DEFAULT_ADDRESS);
+        i2c.registers[usize::from(CHIP_ID)] = CHIP_ID_VALUE;
+        Bno055::new(i2c, DEFAULT_ADDRESS).unwrap()
+    }
+
+    fn set_i16s(i2c: &mut MockI2c, register: u8, values: &[i16]) {
+        for (index, value) in values.iter().enumerate() {
+            let start = usize::from(register) + index * 2;
+            i2c.registers[start..start + 2].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn it_should_reject_a_chip_with_the_wrong_identifier() {
+        let result = Bno055::new(MockI2c::new(DEFAULT_ADDRESS), DEFAULT_ADDRESS);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn it_should_read_the_quaternion_in_a_fusion_mode() {
+        let mut bno055 = bno055();
+        bno055.set_mode(Mode::Ndof).unwrap();
+        set_i16s(
+            &mut bno055.i2c,
+            QUA_DATA,
+            &[1 << 14, 0, -(1 << 13), 1 << 12],
+        );
+        assert_eq!(
+            bno055.quaternion().unwrap().value,
+            Quaternion {
+                w: 1.0,
+                x: 0.0,
+                y: -0.5,
+                z: 0.25
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_refuse_fused_output_outside_a_fusion_mode() {
+        let mut bno055 = bno055();
+        bno055.set_mode(Mode::Amg).unwrap();
+        assert_eq!(
+            bno055.quaternion().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn it_should_scale_euler_angles_and_linear_acceleration() {
+        let mut bno055 = bno055();
+        bno055.set_mode(Mode::Imu).unwrap();
+        set_i16s(&mut bno055.i2c, EUL_DATA, &[5760, -160, 32]);
+        set_i16s(&mut bno055.i2c, LIA_DATA, &[981, 0, -50]);
+        let angles = bno055.euler_angles().unwrap().value;
+        assert_eq!(
+            (angles.heading, angles.roll, angles.pitch),
+            (360.0, -10.0, 2.0)
+        );
+        assert_eq!(
+            bno055.linear_acceleration().unwrap().value,
+            [9.81, 0.0, -0.5]
+        );
+    }
+
+    #[test]
+    fn it_should_scale_raw_acceleration_and_magnetic_field() {
+        let mut bno055 = bno055();
+        bno055.set_mode(Mode::Amg).unwrap();
+        set_i16s(&mut bno055.i2c, ACC_DATA, &[0, 50, 981]);
+        set_i16s(&mut bno055.i2c, MAG_DATA, &[320, -80, -640]);
+        assert_eq!(bno055.acceleration().unwrap().value, [0.0, 0.5, 9.81]);
+        assert_eq!(bno055.magnetic_field().unwrap().value, [20.0, -5.0, -40.0]);
+    }
+
+    #[test]
+    fn it_should_mark_readings_from_uncalibrated_sensors_invalid() {
+        let mut bno055 = bno055();
+        bno055.set_mode(Mode::Ndof).unwrap();
+        let before = Instant::now();
+        let field = bno055.magnetic_field().unwrap();
+        assert!(field.time >= before);
+        assert_eq!(field.source, SOURCE);
+        assert_eq!(
+            field.validity,
+            Validity::Invalid("magnetometer is uncalibrated")
+        );
+        bno055.i2c.registers[usize::from(CALIB_STAT)] = 0b01_00_00_01;
+        assert!(bno055.magnetic_field().unwrap().is_valid());
+        assert!(bno055.quaternion().unwrap().is_valid());
+        assert!(!bno055.acceleration().unwrap().is_valid());
+    }
+
+    #[test]
+    fn it_should_decode_the_calibration_status() {
+        let mut bno055 = bno055();
+        bno055.i2c.registers[usize::from(CALIB_STAT)] = 0b11_10_01_11;
+        let status = bno055.calibration_status().unwrap();
+        assert_eq!(
+            status,
+            CalibrationStatus {
+                system: 3,
+                gyroscope: 2,
+                accelerometer: 1,
+                magnetometer: 3
+            }
+        );
+        assert!(!status.is_fully_calibrated());
+    }
+
+    #[test]
+    fn it_should_remap_axes_in_configuration_mode_and_restore_the_mode() {
+        let mut bno055 = bno055();
+        bno055.set_mode(Mode::Ndof).unwrap();
+        let remap = AxisRemap {
+            x: Axis::Y,
+            y: Axis::X,
+            negate_z: true,
+            ..AxisRemap::default()
+        };
+        bno055.set_axis_remap(remap).unwrap();
+        assert_eq!(bno055.i2c.registers[usize::from(AXIS_MAP_CONFIG)], 0x21);
+        assert_eq!(bno055.i2c.registers[usize::from(AXIS_MAP_SIGN)], 0x01);
+        let modes: Vec<_> = bno055
+            .i2c
+            .writes
+            .iter()
+            .filter(|bytes| bytes[0] == OPR_MODE)
+            .map(|bytes| bytes[1])
+            .collect();
+        assert_eq!(modes, [0x00, 0x0C, 0x00, 0x0C]);
+    }
+
+    #[test]
+    fn it_should_reject_an_axis_remap_that_is_not_a_permutation() {
+        let remap = AxisRemap {
+            x: Axis::Z,
+            ..AxisRemap::default()
+        };
+        let error = bno055().set_axis_remap(remap).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn it_should_restore_saved_calibration() {
+        let mut bno055 = bno055();
+        let calibration = std::array::from_fn(|index| index as u8);
+        bno055.set_calibration(&calibration).unwrap();
+        assert_eq!(bno055.calibration().unwrap(), calibration);
+    }
+}
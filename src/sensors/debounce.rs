@@ -0,0 +1,96 @@
+//! Debouncing digital inputs.
+//!
+//! Contacts bounce and long wires near motors pick up glitches, so a raw pin
+//! level flickers around each real change. A [`Debouncer`] only accepts a new
+//! level once it has held for a settling time.
+
+use std::io::Error;
+use std::time::Duration;
+
+use crate::hal::{DigitalInput, Edge};
+
+/// Filters a sampled level, accepting changes that hold for a settling time.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Debouncer {
+    candidate_for: Duration,
+    level: bool,
+    settle: Duration,
+}
+
+impl Debouncer {
+    /// Creates a new `Debouncer`, starting low, that accepts levels held for
+    /// `settle`.
+    pub fn new(settle: Duration) -> Self {
+        Self {
+            settle,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the debounced level.
+    #[must_use]
+    pub fn level(&self) -> bool {
+        self.level
+    }
+
+    /// Accepts `level` immediately, such as from the first sample after
+    /// startup, without reporting an edge.
+    pub fn reset(&mut self, level: bool) {
+        self.level = level;
+        self.candidate_for = Duration::ZERO;
+    }
+
+    /// Filters the `level` sampled `dt` after the previous sample, and
+    /// returns the edge if the debounced level changed.
+    pub fn update(&mut self, level: bool, dt: Duration) -> Option<Edge> {
+        if level == self.level {
+            self.candidate_for = Duration::ZERO;
+            return None;
+        }
+
+        self.candidate_for += dt;
+
+        if self.candidate_for < self.settle {
+            return None;
+        }
+
+        self.level = level;
+        self.candidate_for = Duration::ZERO;
+        Some(if level { Edge::Rising } else { Edge::Falling })
+    }
+
+    /// Samples `pin` and filters its level as [`Debouncer::update`] does.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be read.
+    pub fn poll(
+        &mut self,
+        pin: &mut impl DigitalInput,
+        dt: Duration,
+    ) -> Result<Option<Edge>, Error> {
+        let level = pin.is_high()?;
+        Ok(self.update(level, dt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn it_should_ignore_glitches_shorter_than_the_settling_time() {
+        let mut debouncer = Debouncer::new(Duration::from_millis(30));
+        let edges: Vec<_> = [true, true, false, true, true, true, true]
+            .into_iter()
+            .filter_map(|level| debouncer.update(level, DT))
+            .collect();
+        assert_eq!(edges, [Edge::Rising]);
+        assert!(debouncer.level());
+        assert_eq!(debouncer.update(false, DT), None);
+        assert_eq!(debouncer.update(false, DT), None);
+        assert_eq!(debouncer.update(false, DT), Some(Edge::Falling));
+    }
+}
@@ -0,0 +1,218 @@
+//! Position fixes from GPS receivers.
+//!
+//! Receivers such as the u-blox NEO series report their fixes as NMEA 0183
+//! sentences over a serial port, several times a second. The GGA sentence
+//! carries everything localization needs: the position, the kind of fix,
+//! and the horizontal dilution of precision (HDOP), which scales how far
+//! the position can be trusted. Fixes are returned as [`Measurement`]s
+//! stamped with when their sentence arrived, ready for
+//! [`Ekf::update_gps`](crate::estimation::ekf::Ekf::update_gps).
+
+use std::time::Instant;
+
+use super::measurement::Measurement;
+use crate::geometry::geodesy::GeoPoint;
+
+/// Source named in GPS measurements.
+pub const SOURCE: &str = "gps";
+
+/// Kind of fix a receiver has.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FixQuality {
+    /// Standalone satellite fix.
+    Gps,
+    /// Fix corrected by SBAS or a differential station.
+    Differential,
+    /// Real-time kinematic fix with fixed integers, accurate to centimetres.
+    Rtk,
+    /// Real-time kinematic fix with floating integers, accurate to
+    /// decimetres.
+    FloatRtk,
+    /// Position estimated by the receiver from its own dead reckoning.
+    Estimated,
+}
+
+/// A position fix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsFix {
+    /// Position.
+    pub position: GeoPoint,
+    /// Height above mean sea level, in metres.
+    pub altitude: f64,
+    /// Horizontal dilution of precision.
+    pub hdop: f64,
+    /// Number of satellites used.
+    pub satellites: u8,
+    /// Kind of fix.
+    pub quality: FixQuality,
+}
+
+impl GpsFix {
+    /// Returns the fix in a GGA sentence, such as
+    /// `$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47`,
+    /// received at `time`, or `None` if the sentence is of another type or
+    /// the receiver has no fix.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the checksum does not match or
+    /// a field is malformed.
+    pub fn from_nmea(sentence: &str, time: Instant) -> Result<Option<Measurement<Self>>, String> {
+        let body = checked_body(sentence.trim())?;
+        let fields: Vec<_> = body.split(',').collect();
+
+        if fields[0].len() != 5 || !fields[0].ends_with("GGA") {
+            return Ok(None);
+        }
+
+        if fields.len() < 10 {
+            return Err(format!("GGA sentence has {} fields", fields.len()));
+        }
+
+        let quality = match fields[6] {
+            "0" | "" => return Ok(None),
+            "1" => FixQuality::Gps,
+            "2" | "3" => FixQuality::Differential,
+            "4" => FixQuality::Rtk,
+            "5" => FixQuality::FloatRtk,
+            "6" => FixQuality::Estimated,
+            other => return Err(format!("`{other}` is not a fix quality")),
+        };
+        let number = |index: usize| {
+            fields[index]
+                .parse::<f64>()
+                .map_err(|_| format!("`{}` is not a number", fields[index]))
+        };
+
+        let fix = Self {
+            position: GeoPoint {
+                latitude: coordinate(fields[2], fields[3], 'N', 'S')?,
+                longitude: coordinate(fields[4], fields[5], 'E', 'W')?,
+            },
+            altitude: number(9)?,
+            hdop: number(8)?,
+            satellites: fields[7]
+                .parse()
+                .map_err(|_| format!("`{}` is not a satellite count", fields[7]))?,
+            quality,
+        };
+        Ok(Some(Measurement::new(SOURCE, fix, time)))
+    }
+}
+
+/// Returns the text between `$` and `*` in `sentence` if its checksum,
+/// where present, matches.
+fn checked_body(sentence: &str) -> Result<&str, String> {
+    let sentence = sentence
+        .strip_prefix('$')
+        .ok_or("NMEA sentence should start with `$`")?;
+
+    let Some((body, checksum)) = sentence.split_once('*') else {
+        return Ok(sentence);
+    };
+
+    let expected =
+        u8::from_str_radix(checksum, 16).map_err(|_| format!("`{checksum}` is not a checksum"))?;
+    let actual = body.bytes().fold(0, |sum, byte| sum ^ byte);
+
+    if actual == expected {
+        Ok(body)
+    } else {
+        Err(format!("checksum should be {actual:02X}, not {checksum}"))
+    }
+}
+
+/// Returns the coordinate in degrees from NMEA degrees and minutes, such as
+/// `4807.038`, and a hemisphere.
+fn coordinate(
+    value: &str,
+    hemisphere: &str,
+    positive: char,
+    negative: char,
+) -> Result<f64, String> {
+    let invalid = || format!("`{value}` is not a coordinate");
+    let point = value.find('.').unwrap_or(value.len());
+    let split = point.checked_sub(2).ok_or_else(invalid)?;
+    let parse = |text: Option<&str>| {
+        text.and_then(|text| text.parse::<f64>().ok())
+            .ok_or_else(invalid)
+    };
+    let degrees = if split == 0 {
+        0.0
+    } else {
+        parse(value.get(..split))?
+    };
+    let degrees = degrees + parse(value.get(split..))? / 60.0;
+
+    match hemisphere.chars().next() {
+        Some(c) if c == positive => Ok(degrees),
+        Some(c) if c == negative => Ok(-degrees),
+        _ => Err(format!("`{hemisphere}` is not a hemisphere")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_a_gga_sentence() {
+        let now = Instant::now();
+        let measurement = GpsFix::from_nmea(
+            "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n",
+            now,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!((measurement.source, measurement.time), (SOURCE, now));
+        assert!(measurement.is_valid());
+        let fix = measurement.value;
+        assert!((fix.position.latitude - 48.1173).abs() < 1e-9);
+        assert!((fix.position.longitude - 11.516_666_666).abs() < 1e-6);
+        assert_eq!(
+            (fix.altitude, fix.hdop, fix.satellites, fix.quality),
+            (545.4, 0.9, 8, FixQuality::Gps)
+        );
+        let fix = GpsFix::from_nmea("$GNGGA,0,3345.5,S,07030.0,W,4,12,0.6,10.0,M,0,M,,", now)
+            .unwrap()
+            .unwrap()
+            .value;
+        assert_eq!(fix.position, GeoPoint::new(-33.758_333_333_333_33, -70.5));
+        assert_eq!(fix.quality, FixQuality::Rtk);
+    }
+
+    #[test]
+    fn it_should_skip_other_sentences_and_missing_fixes() {
+        let now = Instant::now();
+        assert_eq!(
+            GpsFix::from_nmea(
+                "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W",
+                now
+            ),
+            Ok(None)
+        );
+        assert_eq!(
+            GpsFix::from_nmea("$GPGGA,123519,,,,,0,00,99.9,,M,,M,,", now),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn it_should_reject_corrupted_sentences() {
+        let now = Instant::now();
+        assert!(GpsFix::from_nmea(
+            "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48",
+            now
+        )
+        .is_err());
+        assert!(
+            GpsFix::from_nmea("$GPGGA,123519,4807.038,X,01131.000,E,1,08,0.9,545.4,M", now)
+                .is_err()
+        );
+        assert!(GpsFix::from_nmea("GPGGA", now).is_err());
+        assert_eq!(
+            GpsFix::from_nmea("$GPGGA,0,€.5,N,01131.000,E,1,08,0.9,545.4,M", now),
+            Err("`€.5` is not a coordinate".to_owned())
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! Driver for digital hall-effect switches, such as the A3144.
+//!
+//! A hall switch pulls its output low while a magnet is near, which makes it
+//! a contactless door or alignment sensor. Under the dock, a magnet in the
+//! base tells the robot it is seated on the contacts; in the chassis, a
+//! magnet on the service hatch closes a [`HatchInterlock`] that disarms the
+//! actuators whenever the hatch is open.
+
+use std::fmt::{self, Debug, Formatter};
+use std::io::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::debounce::Debouncer;
+use super::measurement::Measurement;
+use crate::events::{EmergencyStop, Event, EventBus};
+use crate::gpio::registry::{Claim, Line, Registry};
+use crate::hal::DigitalInput;
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "hall";
+
+/// Name of the interlock used as the emergency stop source.
+const INTERLOCK: &str = "hatch";
+
+type Action = Arc<dyn Fn() + Send + Sync>;
+
+/// A hall-effect switch connected to a digital input.
+#[derive(Debug)]
+pub struct Hall<P: DigitalInput> {
+    active_low: bool,
+    _claim: Option<Claim<'static>>,
+    debouncer: Debouncer,
+    pin: P,
+    sampled: Option<Instant>,
+}
+
+impl<P: DigitalInput> Hall<P> {
+    /// Creates a new `Hall` whose output is low while a magnet is near,
+    /// ignoring changes shorter than 20 ms.
+    pub fn new(pin: P) -> Self {
+        Self {
+            active_low: true,
+            _claim: None,
+            debouncer: Debouncer::new(Duration::from_millis(20)),
+            pin,
+            sampled: None,
+        }
+    }
+
+    /// Creates a new `Hall` as [`Hall::new`] does, claiming `line`, the line
+    /// behind `pin`, for as long as the sensor exists.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with
+    /// [`ErrorKind::ResourceBusy`](std::io::ErrorKind::ResourceBusy) naming
+    /// the owner if the line is already claimed.
+    pub fn claim(pin: P, line: Line) -> Result<Self, Error> {
+        let claim = Registry::global().claim(line, SUBSYSTEM, "input")?;
+        let mut hall = Self::new(pin);
+        hall._claim = Some(claim);
+        Ok(hall)
+    }
+
+    /// Treats the output as high while a magnet is near.
+    #[must_use]
+    pub fn with_active_high(mut self) -> Self {
+        self.active_low = false;
+        self
+    }
+
+    /// Sets how long the output must hold a level before it is believed.
+    #[must_use]
+    pub fn with_debounce(mut self, settle: Duration) -> Self {
+        self.debouncer = Debouncer::new(settle);
+        self
+    }
+
+    /// Returns whether a magnet is near as of the last sample, or `None`
+    /// before the first sample.
+    #[must_use]
+    pub fn magnet(&self) -> Option<Measurement<bool>> {
+        self.sampled
+            .map(|time| Measurement::new(SUBSYSTEM, self.debouncer.level(), time))
+    }
+
+    /// Samples the switch `dt` after the previous sample, and returns whether
+    /// a magnet is near if that changed or this is the first sample.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be read.
+    pub fn update(&mut self, dt: Duration) -> Result<Option<Measurement<bool>>, Error> {
+        let magnet = self.pin.is_high()? != self.active_low;
+        let time = Instant::now();
+        let first = self.sampled.replace(time).is_none();
+
+        if first {
+            self.debouncer.reset(magnet);
+        } else if self.debouncer.update(magnet, dt).is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Measurement::new(SUBSYSTEM, magnet, time)))
+    }
+
+    /// Returns the pin.
+    pub fn into_inner(self) -> P {
+        self.pin
+    }
+}
+
+/// Disarms the actuators while the service hatch is open.
+///
+/// The hatch is closed while its magnet is near the switch, so a missing
+/// magnet or a cut wire reads as open. Until the first sample the hatch is
+/// assumed open. Opening runs every registered action and engages the
+/// emergency stop on the bus; closing releases it.
+pub struct HatchInterlock<P: DigitalInput> {
+    actions: Vec<Action>,
+    bus: Option<EventBus>,
+    hall: Hall<P>,
+    open: Option<bool>,
+}
+
+impl<P: DigitalInput> HatchInterlock<P> {
+    /// Creates a new `HatchInterlock` watching `hall`.
+    pub fn new(hall: Hall<P>) -> Self {
+        Self {
+            actions: Vec::new(),
+            bus: None,
+            hall,
+            open: None,
+        }
+    }
+
+    /// Adds an action that disarms the actuators, run when the hatch opens.
+    #[must_use]
+    pub fn on_open(mut self, action: impl Fn() + Send + Sync + 'static) -> Self {
+        self.actions.push(Arc::new(action));
+        self
+    }
+
+    /// Engages and releases the emergency stop on `bus`.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Returns `true` if the hatch is closed, so the actuators may run.
+    #[must_use]
+    pub fn is_armed(&self) -> bool {
+        self.open == Some(false)
+    }
+
+    /// Samples the switch `dt` after the previous sample, and returns `true`
+    /// if the actuators may run.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be read, leaving
+    /// the interlock as it was.
+    pub fn update(&mut self, dt: Duration) -> Result<bool, Error> {
+        let Some(magnet) = self.hall.update(dt)? else {
+            return Ok(self.is_armed());
+        };
+        let open = !magnet.value;
+        let first = self.open.is_none();
+        self.open = Some(open);
+
+        if open {
+            log_event!(SUBSYSTEM, Level::Warn, "service hatch open, disarming");
+
+            for action in &self.actions {
+                action();
+            }
+
+            self.publish(true);
+        } else if !first {
+            log_event!(SUBSYSTEM, Level::Info, "service hatch closed");
+            self.publish(false);
+        }
+
+        Ok(self.is_armed())
+    }
+
+    fn publish(&self, engaged: bool) {
+        if let Some(bus) = &self.bus {
+            bus.publish(Event::EmergencyStop(EmergencyStop {
+                engaged,
+                source: INTERLOCK.to_owned(),
+            }));
+        }
+    }
+}
+
+impl<P: DigitalInput + Debug> Debug for HatchInterlock<P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HatchInterlock")
+            .field("actions", &self.actions.len())
+            .field("hall", &self.hall)
+            .field("open", &self.open)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::events::EventKind;
+    use crate::hal::MockInput;
+
+    const DT: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn it_should_report_the_first_sample_and_debounced_changes() {
+        let pin = MockInput::default();
+        let mut hall = Hall::new(pin.clone());
+        let magnet = |update: Option<Measurement<bool>>| update.map(|magnet| magnet.value);
+        assert_eq!(hall.magnet(), None);
+        let before = Instant::now();
+        let first = hall.update(DT).unwrap().unwrap();
+        assert!(first.value && first.time >= before);
+        assert_eq!(hall.magnet(), Some(first));
+        pin.set(true);
+        assert_eq!(magnet(hall.update(DT).unwrap()), None);
+        assert_eq!(magnet(hall.update(DT).unwrap()), Some(false));
+        assert_eq!(magnet(hall.magnet()), Some(false));
+        let mut hall = Hall::new(pin).with_active_high();
+        assert_eq!(magnet(hall.update(DT).unwrap()), Some(true));
+    }
+
+    #[test]
+    fn it_should_hold_its_line_until_dropped() {
+        let line = Line::new("hall-test", 3);
+        let hall = Hall::claim(MockInput::default(), line.clone()).unwrap();
+        let error = Hall::claim(MockInput::default(), line.clone()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 3 of hall-test already claimed by hall input"
+        );
+        drop(hall);
+        assert_eq!(Registry::global().owner(&line), None);
+    }
+
+    #[test]
+    fn it_should_disarm_while_the_hatch_is_open() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe_to(&[EventKind::EmergencyStop], 4);
+        let disarms = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&disarms);
+        let pin = MockInput::default();
+        let mut interlock = HatchInterlock::new(Hall::new(pin.clone()))
+            .on_open(move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .with_bus(bus);
+        assert!(!interlock.is_armed());
+        assert!(interlock.update(DT).unwrap());
+        assert_eq!(subscription.try_recv(), None);
+        pin.set(true);
+        interlock.update(DT).unwrap();
+        assert!(!interlock.update(DT).unwrap());
+        pin.set(false);
+        interlock.update(DT).unwrap();
+        assert!(interlock.update(DT).unwrap());
+        assert_eq!(disarms.load(Ordering::Relaxed), 1);
+        let engaged: Vec<_> = std::iter::from_fn(|| subscription.try_recv())
+            .map(|event| match event {
+                Event::EmergencyStop(stop) => stop.engaged,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(engaged, [true, false]);
+    }
+
+    #[test]
+    fn it_should_disarm_if_the_hatch_starts_open() {
+        let pin = MockInput::new(true);
+        let mut interlock = HatchInterlock::new(Hall::new(pin));
+        assert!(!interlock.update(DT).unwrap());
+    }
+}
@@ -0,0 +1,447 @@
+//! Driver for the HX711 load cell amplifier.
+//!
+//! The HX711 is read by bit-banging its serial clock and data lines. Holding
+//! the clock high for more than 60 µs powers the chip down, so the clock pin
+//! should be backed by a fast path such as `gpio::fast` rather than a backend
+//! that goes through a daemon. Readings are [`Measurement`]s, invalid when a
+//! conversion is saturated.
+
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::measurement::{Measurement, Validity};
+use crate::hal::{DigitalInput, DigitalOutput};
+
+/// Source named in the HX711’s measurements.
+pub const SOURCE: &str = "hx711";
+
+/// Number of data bits in a conversion.
+const DATA_BITS: u32 = 24;
+
+/// Codes the chip outputs when its input is above or below its range.
+const SATURATED: [u32; 2] = [0x7F_FFFF, 0x80_0000];
+
+/// Code read when the data line stays high, as a disconnected one does.
+const ALL_ONES: u32 = 0xFF_FFFF;
+
+/// Time the clock is held high to power the chip down.
+const POWER_DOWN_TIME: Duration = Duration::from_micros(100);
+
+/// Input channel and gain used for the next conversion.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Gain {
+    /// Channel A with a gain of 128.
+    #[default]
+    A128,
+    /// Channel A with a gain of 64.
+    A64,
+    /// Channel B with a gain of 32.
+    B32,
+}
+
+impl Gain {
+    /// Returns the number of clock pulses after the data bits that select this
+    /// gain for the next conversion.
+    fn pulses(self) -> u32 {
+        match self {
+            Self::A128 => 1,
+            Self::B32 => 2,
+            Self::A64 => 3,
+        }
+    }
+}
+
+/// An HX711 connected to a data input and a clock output.
+#[derive(Debug)]
+pub struct Hx711<D, C> {
+    clock: C,
+    data: D,
+    gain: Gain,
+    offset: i32,
+    scale: f64,
+    timeout: Duration,
+}
+
+impl<D: DigitalInput, C: DigitalOutput> Hx711<D, C> {
+    /// Creates a new `Hx711` reading channel A with a gain of 128, no tare
+    /// offset, and a calibration factor of 1.
+    pub fn new(data: D, clock: C) -> Self {
+        Self {
+            clock,
+            data,
+            gain: Gain::default(),
+            offset: 0,
+            scale: 1.0,
+            timeout: Duration::from_millis(500),
+        }
+    }
+
+    /// Sets the channel and gain used from the conversion after next.
+    #[must_use]
+    pub fn with_gain(mut self, gain: Gain) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// Sets how long to wait for a conversion before timing out.
+    ///
+    /// The default of 500 ms covers the 10 Hz output rate with margin.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns `true` if a conversion is ready to be read.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the data pin cannot be read.
+    pub fn is_ready(&mut self) -> Result<bool, Error> {
+        self.data.is_low()
+    }
+
+    /// Waits for a conversion and returns its raw value, invalid if the
+    /// conversion is saturated.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pins cannot be accessed,
+    /// with [`ErrorKind::TimedOut`] if no conversion is ready within the
+    /// timeout, or with [`ErrorKind::InvalidData`] if every bit read high, as
+    /// from a disconnected data line.
+    pub fn read_raw(&mut self) -> Result<Measurement<i32>, Error> {
+        let deadline = Instant::now() + self.timeout;
+
+        while !self.is_ready()? {
+            if Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::TimedOut, "HX711 not ready"));
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut value = 0_u32;
+
+        for _ in 0..DATA_BITS {
+            self.clock.set_high()?;
+            let bit = self.data.is_high()?;
+            self.clock.set_low()?;
+            value = (value << 1) | u32::from(bit);
+        }
+
+        for _ in 0..self.gain.pulses() {
+            self.clock.set_high()?;
+            self.clock.set_low()?;
+        }
+
+        if value == ALL_ONES {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "HX711 data line read all ones; is it connected?",
+            ));
+        }
+
+        let validity = if SATURATED.contains(&value) {
+            Validity::Invalid("conversion is saturated")
+        } else {
+            Validity::Valid
+        };
+        Ok(Measurement::new(SOURCE, sign_extend(value), Instant::now()).with_validity(validity))
+    }
+
+    /// Reads `samples` conversions and returns their median, which rejects the
+    /// occasional spike caused by vibration or a bumped payload, taken when
+    /// the last conversion was read and invalid if any conversion is.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any conversion cannot be read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` is zero.
+    pub fn read_median(&mut self, samples: usize) -> Result<Measurement<i32>, Error> {
+        assert!(samples > 0, "samples should be greater than zero");
+        let measurements = (0..samples)
+            .map(|_| self.read_raw())
+            .collect::<Result<Vec<_>, _>>()?;
+        let validity = measurements
+            .iter()
+            .map(|measurement| measurement.validity)
+            .find(|validity| *validity != Validity::Valid)
+            .unwrap_or_default();
+        let time = measurements[samples - 1].time;
+        let values = measurements.iter().map(|measurement| measurement.value);
+        Ok(Measurement::new(SOURCE, median(values.collect()), time).with_validity(validity))
+    }
+
+    /// Sets the tare offset to the median of `samples` conversions, so that
+    /// the current load reads as zero.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any conversion cannot be read,
+    /// or with [`ErrorKind::InvalidData`] if any is saturated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` is zero.
+    pub fn tare(&mut self, samples: usize) -> Result<(), Error> {
+        self.offset = self.read_median(samples)?.valid()?;
+        Ok(())
+    }
+
+    /// Sets the calibration factor from a known load currently on the cell,
+    /// after the cell has been tared.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any conversion cannot be read,
+    /// with [`ErrorKind::InvalidData`] if any is saturated, or with
+    /// [`ErrorKind::InvalidInput`] if the reading does not change from the
+    /// tare offset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` is zero.
+    pub fn calibrate(&mut self, known_weight: f64, samples: usize) -> Result<(), Error> {
+        let raw = self.read_median(samples)?.valid()?;
+        let delta = f64::from(raw) - f64::from(self.offset);
+
+        if delta == 0.0 || known_weight == 0.0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "calibration load did not change the reading",
+            ));
+        }
+
+        self.scale = delta / known_weight;
+        Ok(())
+    }
+
+    /// Returns the median weight of `samples` conversions in the units of the
+    /// calibration load, invalid if any conversion is saturated.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any conversion cannot be read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` is zero.
+    pub fn weight(&mut self, samples: usize) -> Result<Measurement<f64>, Error> {
+        let (offset, scale) = (f64::from(self.offset), self.scale);
+        let raw = self.read_median(samples)?;
+        Ok(raw.map(|raw| (f64::from(raw) - offset) / scale))
+    }
+
+    /// Returns the tare offset.
+    #[must_use]
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// Sets the tare offset, such as one restored from saved parameters.
+    pub fn set_offset(&mut self, offset: i32) {
+        self.offset = offset;
+    }
+
+    /// Returns the calibration factor in raw counts per unit of weight.
+    #[must_use]
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Sets the calibration factor in raw counts per unit of weight.
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+
+    /// Puts the chip into its power-down mode.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the clock pin cannot be driven.
+    pub fn power_down(&mut self) -> Result<(), Error> {
+        self.clock.set_low()?;
+        self.clock.set_high()?;
+        thread::sleep(POWER_DOWN_TIME);
+        Ok(())
+    }
+
+    /// Wakes the chip from its power-down mode, which resets it to channel A
+    /// with a gain of 128.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the clock pin cannot be driven.
+    pub fn power_up(&mut self) -> Result<(), Error> {
+        self.clock.set_low()
+    }
+
+    /// Consumes the driver and returns its data and clock pins.
+    pub fn into_pins(self) -> (D, C) {
+        (self.data, self.clock)
+    }
+}
+
+/// Sign-extends a 24-bit two's complement value.
+fn sign_extend(value: u32) -> i32 {
+    ((value << 8) as i32) >> 8
+}
+
+/// Returns the median of `values`, averaging the middle pair if there is an
+/// even number of them.
+fn median(mut values: Vec<i32>) -> i32 {
+    values.sort_unstable();
+    let middle = values.len() / 2;
+
+    if values.len().is_multiple_of(2) {
+        ((i64::from(values[middle - 1]) + i64::from(values[middle])) / 2) as i32
+    } else {
+        values[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct DataPin {
+        levels: Rc<RefCell<VecDeque<bool>>>,
+    }
+
+    impl DigitalInput for DataPin {
+        fn is_high(&mut self) -> Result<bool, Error> {
+            Ok(self.levels.borrow_mut().pop_front().unwrap_or(true))
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct ClockPin {
+        pulses: Rc<Cell<u32>>,
+    }
+
+    impl DigitalOutput for ClockPin {
+        fn set_high(&mut self) -> Result<(), Error> {
+            self.pulses.set(self.pulses.get() + 1);
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn queue_conversion(levels: &RefCell<VecDeque<bool>>, value: i32) {
+        let mut levels = levels.borrow_mut();
+        levels.push_back(false);
+        levels.extend((0..DATA_BITS).rev().map(|bit| (value >> bit) & 1 == 1));
+    }
+
+    fn hx711(values: &[i32]) -> (Hx711<DataPin, ClockPin>, Rc<Cell<u32>>) {
+        let data = DataPin::default();
+        values
+            .iter()
+            .for_each(|&value| queue_conversion(&data.levels, value));
+        let clock = ClockPin::default();
+        let pulses = Rc::clone(&clock.pulses);
+        let hx711 = Hx711::new(data, clock).with_timeout(Duration::from_millis(5));
+        (hx711, pulses)
+    }
+
+    #[test]
+    fn it_should_read_a_positive_conversion() {
+        let (mut hx711, _) = hx711(&[0x12_3456]);
+        let before = Instant::now();
+        let conversion = hx711.read_raw().unwrap();
+        assert_eq!((conversion.value, conversion.source), (0x12_3456, SOURCE));
+        assert!(conversion.is_valid() && conversion.time >= before);
+    }
+
+    #[test]
+    fn it_should_sign_extend_a_negative_conversion() {
+        let (mut hx711, _) = hx711(&[-1000]);
+        assert_eq!(hx711.read_raw().unwrap().value, -1000);
+    }
+
+    #[test]
+    fn it_should_mark_saturated_conversions_invalid_and_reject_all_ones() {
+        let (mut hx711, pulses) = hx711(&[0x7F_FFFF, -0x80_0000, -1, 5]);
+        for value in [0x7F_FFFF, -0x80_0000] {
+            let conversion = hx711.read_raw().unwrap();
+            assert_eq!(conversion.value, value);
+            assert_eq!(
+                conversion.validity,
+                Validity::Invalid("conversion is saturated")
+            );
+        }
+        let error = hx711.read_raw().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            "HX711 data line read all ones; is it connected?"
+        );
+        assert_eq!(pulses.get(), 75);
+        assert_eq!(hx711.read_raw().unwrap().value, 5);
+    }
+
+    #[test]
+    fn it_should_refuse_to_tare_on_a_saturated_conversion() {
+        let (mut hx711, _) = hx711(&[100, 0x7F_FFFF, 101]);
+        let error = hx711.tare(3).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(hx711.offset(), 0);
+    }
+
+    #[test]
+    fn it_should_pulse_the_clock_for_the_selected_gain() {
+        for (gain, pulses) in [(Gain::A128, 25), (Gain::B32, 26), (Gain::A64, 27)] {
+            let (hx711, count) = hx711(&[0]);
+            hx711.with_gain(gain).read_raw().unwrap();
+            assert_eq!(count.get(), pulses);
+        }
+    }
+
+    #[test]
+    fn it_should_time_out_when_no_conversion_is_ready() {
+        let (mut hx711, _) = hx711(&[]);
+        assert_eq!(hx711.read_raw().unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn it_should_reject_spikes_with_the_median() {
+        let (mut hx711, _) = hx711(&[100, 8_000_000, 102, 101, -8_000_000]);
+        assert_eq!(hx711.read_median(5).unwrap().value, 101);
+    }
+
+    #[test]
+    fn it_should_weigh_a_load_after_taring_and_calibrating() {
+        let (mut hx711, _) = hx711(&[1000, 1000, 1000, 3000, 3000, 3000, 2000]);
+        hx711.tare(3).unwrap();
+        assert_eq!(hx711.offset(), 1000);
+        hx711.calibrate(100.0, 3).unwrap();
+        assert_eq!(hx711.scale(), 20.0);
+        assert_eq!(hx711.weight(1).unwrap().value, 50.0);
+    }
+
+    #[test]
+    fn it_should_reject_a_calibration_load_that_does_not_change_the_reading() {
+        let (mut hx711, _) = hx711(&[0]);
+        let error = hx711.calibrate(100.0, 1).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn it_should_average_the_middle_pair_of_an_even_number_of_samples() {
+        assert_eq!(median(vec![4, 1, 3, 2]), 2);
+        assert_eq!(median(vec![10, 20]), 15);
+    }
+}
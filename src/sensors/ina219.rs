@@ -0,0 +1,186 @@
+//! Driver for the INA219 current and power monitor.
+//!
+//! The INA219 measures the voltage across a shunt resistor in series with a
+//! supply rail, and the voltage of the rail itself, so one on each rail
+//! tells how much power it draws. Readings are [`Measurement`]s, invalid
+//! while the chip flags an overflow.
+
+use std::io::Error;
+use std::time::Instant;
+
+use super::measurement::{Measurement, Validity};
+use crate::hal::I2c;
+
+/// Default I2C address of the INA219, with both address pins tied to ground.
+pub const DEFAULT_ADDRESS: u8 = 0x40;
+
+/// Source named in the INA219’s measurements.
+pub const SOURCE: &str = "ina219";
+
+/// Register holding the shunt voltage.
+const SHUNT_VOLTAGE: u8 = 0x01;
+
+/// Register holding the bus voltage.
+const BUS_VOLTAGE: u8 = 0x02;
+
+/// Volts per bit of the shunt voltage.
+const SHUNT_LSB: f64 = 10e-6;
+
+/// Volts per bit of the bus voltage, which is stored from bit 3 up.
+const BUS_LSB: f64 = 4e-3;
+
+/// Bit of the bus voltage register set when a product overflowed.
+const OVERFLOW: u16 = 0x0001;
+
+/// An INA219 on an I2C bus, measuring current through a shunt resistor.
+#[derive(Debug)]
+pub struct Ina219<I> {
+    address: u8,
+    i2c: I,
+    shunt: f64,
+}
+
+impl<I: I2c> Ina219<I> {
+    /// Creates a new `Ina219` at `address` with a shunt of `shunt` ohms,
+    /// such as 0.1 on most breakout boards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shunt` is not positive.
+    pub fn new(i2c: I, address: u8, shunt: f64) -> Self {
+        assert!(shunt > 0.0, "shunt should be positive");
+        Self {
+            address,
+            i2c,
+            shunt,
+        }
+    }
+
+    /// Returns the voltage across the shunt, in volts.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be read.
+    pub fn shunt_voltage(&mut self) -> Result<Measurement<f64>, Error> {
+        let raw = self.read(SHUNT_VOLTAGE)? as i16;
+        Ok(Measurement::new(
+            SOURCE,
+            f64::from(raw) * SHUNT_LSB,
+            Instant::now(),
+        ))
+    }
+
+    /// Returns the voltage of the rail on the load side of the shunt, in
+    /// volts, invalid if the chip flagged an overflow.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be read.
+    pub fn bus_voltage(&mut self) -> Result<Measurement<f64>, Error> {
+        let raw = self.read(BUS_VOLTAGE)?;
+        let validity = if raw & OVERFLOW == 0 {
+            Validity::Valid
+        } else {
+            Validity::Invalid("measurement overflowed")
+        };
+        Ok(
+            Measurement::new(SOURCE, f64::from(raw >> 3) * BUS_LSB, Instant::now())
+                .with_validity(validity),
+        )
+    }
+
+    /// Returns the current drawn through the shunt, in amperes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the register cannot be read.
+    pub fn current(&mut self) -> Result<Measurement<f64>, Error> {
+        let shunt = self.shunt;
+        Ok(self.shunt_voltage()?.map(|voltage| voltage / shunt))
+    }
+
+    /// Returns the power drawn by the rail, in watts, invalid if the bus
+    /// voltage is.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if either voltage cannot be read.
+    pub fn power(&mut self) -> Result<Measurement<f64>, Error> {
+        let current = self.current()?.value;
+        Ok(self.bus_voltage()?.map(|voltage| voltage * current))
+    }
+
+    /// Returns the I2C bus, consuming the driver.
+    pub fn into_inner(self) -> I {
+        self.i2c
+    }
+
+    fn read(&mut self, register: u8) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        self.i2c
+            .write_read(self.address, &[register], &mut buffer)?;
+        Ok(u16::from_be_bytes(buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Mock {
+        bus: u16,
+        shunt: u16,
+    }
+
+    impl I2c for Mock {
+        fn write(&mut self, _address: u8, _bytes: &[u8]) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn write_read(
+            &mut self,
+            _address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), Error> {
+            let value = match bytes {
+                [SHUNT_VOLTAGE] => self.shunt,
+                _ => self.bus,
+            };
+            buffer.copy_from_slice(&value.to_be_bytes());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_should_measure_current_and_power() {
+        let mock = Mock {
+            // 12 V, with the conversion-ready bit set.
+            bus: (3000 << 3) | 0x0002,
+            // 15 mV across the shunt.
+            shunt: 1500,
+        };
+        let mut ina219 = Ina219::new(mock, DEFAULT_ADDRESS, 0.01);
+        let before = Instant::now();
+        let bus = ina219.bus_voltage().unwrap();
+        assert!(bus.is_valid() && bus.time >= before);
+        assert_eq!(bus.source, SOURCE);
+        assert!((bus.value - 12.0).abs() < 1e-9);
+        assert!((ina219.current().unwrap().value - 1.5).abs() < 1e-9);
+        assert!((ina219.power().unwrap().value - 18.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_should_read_negative_current_and_mark_overflow_invalid() {
+        let mock = Mock {
+            bus: 0x0001,
+            shunt: (-500_i16) as u16,
+        };
+        let mut ina219 = Ina219::new(mock, DEFAULT_ADDRESS, 0.1);
+        assert!((ina219.current().unwrap().value + 0.05).abs() < 1e-9);
+        let invalid = Validity::Invalid("measurement overflowed");
+        assert_eq!(ina219.bus_voltage().unwrap().validity, invalid);
+        assert_eq!(ina219.power().unwrap().validity, invalid);
+    }
+}
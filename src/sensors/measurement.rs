@@ -0,0 +1,225 @@
+//! Readings that know when they were taken, where they came from, and
+//! whether they can be trusted.
+//!
+//! A reading on its own cannot say it is out of date. A temperature read a
+//! minute ago from a sensor that has since drowned looks the same as one
+//! read just now, and a GPS position from a receiver without a fix looks
+//! like any other. A [`Measurement`] carries its value together with when
+//! it was taken, the sensor it came from, and whether the driver judged it
+//! valid, so consumers turn away stale and invalid readings with
+//! [`Measurement::check`] rather than each keeping its own timestamps.
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind};
+use std::time::{Duration, Instant};
+
+/// Whether a reading can be trusted.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Validity {
+    /// The reading can be trusted.
+    #[default]
+    Valid,
+    /// The reading cannot be trusted, giving why, such as a sensor that has
+    /// not finished calibrating or a value out of its range.
+    Invalid(&'static str),
+}
+
+/// Why a measurement cannot be used.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Unusable {
+    /// There is no reading from the named sensor.
+    Missing(&'static str),
+    /// The reading was judged invalid.
+    Invalid {
+        /// Sensor the reading came from.
+        source: &'static str,
+        /// Why the reading is invalid.
+        reason: &'static str,
+    },
+    /// The reading is too old.
+    Stale {
+        /// Sensor the reading came from.
+        source: &'static str,
+        /// Age of the reading.
+        age: Duration,
+    },
+}
+
+impl Display for Unusable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(source) => write!(f, "no {source} reading"),
+            Self::Invalid { source, reason } => write!(f, "{source} reading is invalid: {reason}"),
+            Self::Stale { source, age } => write!(f, "{source} reading is {age:?} old"),
+        }
+    }
+}
+
+impl error::Error for Unusable {}
+
+impl From<Unusable> for io::Error {
+    fn from(unusable: Unusable) -> Self {
+        Self::new(ErrorKind::InvalidData, unusable)
+    }
+}
+
+/// A reading with when it was taken, its source, and its validity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement<T> {
+    /// Reading.
+    pub value: T,
+    /// When the reading was taken.
+    pub time: Instant,
+    /// Sensor the reading came from.
+    pub source: &'static str,
+    /// Whether the reading can be trusted.
+    pub validity: Validity,
+}
+
+impl<T> Measurement<T> {
+    /// Creates a new valid `Measurement` of `value`, taken by `source` at
+    /// `time`.
+    pub fn new(source: &'static str, value: T, time: Instant) -> Self {
+        Self {
+            value,
+            time,
+            source,
+            validity: Validity::Valid,
+        }
+    }
+
+    /// Sets whether the reading can be trusted.
+    #[must_use]
+    pub fn with_validity(mut self, validity: Validity) -> Self {
+        self.validity = validity;
+        self
+    }
+
+    /// Returns `true` if the reading can be trusted.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.validity == Validity::Valid
+    }
+
+    /// Returns how long before `now` the reading was taken.
+    #[must_use]
+    pub fn age(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.time)
+    }
+
+    /// Returns the measurement of the value `f` derives from the reading,
+    /// keeping its time, source, and validity.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Measurement<U> {
+        Measurement {
+            value: f(self.value),
+            time: self.time,
+            source: self.source,
+            validity: self.validity,
+        }
+    }
+
+    /// Returns the reading if it is valid, however old it is.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reading is invalid.
+    pub fn valid(self) -> Result<T, Unusable> {
+        match self.validity {
+            Validity::Valid => Ok(self.value),
+            Validity::Invalid(reason) => Err(Unusable::Invalid {
+                source: self.source,
+                reason,
+            }),
+        }
+    }
+
+    /// Returns the reading if it is valid and no older than `max_age` at
+    /// `now`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reading is invalid or
+    /// stale.
+    pub fn check(&self, now: Instant, max_age: Duration) -> Result<&T, Unusable> {
+        if let Validity::Invalid(reason) = self.validity {
+            return Err(Unusable::Invalid {
+                source: self.source,
+                reason,
+            });
+        }
+
+        let age = self.age(now);
+
+        if age > max_age {
+            return Err(Unusable::Stale {
+                source: self.source,
+                age,
+            });
+        }
+
+        Ok(&self.value)
+    }
+}
+
+/// Returns the latest reading of `source`, if there is one, when it is
+/// valid and no older than `max_age` at `now`.
+///
+/// # Errors
+///
+/// This function will return an error if there is no reading, or it is
+/// invalid or stale.
+pub fn check<'a, T>(
+    measurement: Option<&'a Measurement<T>>,
+    source: &'static str,
+    now: Instant,
+    max_age: Duration,
+) -> Result<&'a T, Unusable> {
+    measurement
+        .ok_or(Unusable::Missing(source))?
+        .check(now, max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_accept_fresh_valid_readings() {
+        let start = Instant::now();
+        let reading = Measurement::new("ina219", 12.4, start);
+        let max_age = Duration::from_millis(100);
+        assert_eq!(reading.check(start + max_age, max_age), Ok(&12.4));
+        assert_eq!(
+            reading.map(|volts| volts * 2.0).check(start, max_age),
+            Ok(&24.8)
+        );
+    }
+
+    #[test]
+    fn it_should_reject_missing_invalid_and_stale_readings() {
+        let start = Instant::now();
+        let max_age = Duration::from_millis(100);
+        assert_eq!(
+            check::<f64>(None, "gps", start, max_age),
+            Err(Unusable::Missing("gps"))
+        );
+        let reading = Measurement::new("bno055", 0.5, start);
+        let later = start + Duration::from_millis(150);
+        assert_eq!(
+            check(Some(&reading), "imu", later, max_age),
+            Err(Unusable::Stale {
+                source: "bno055",
+                age: Duration::from_millis(150)
+            })
+        );
+        let reading = reading.with_validity(Validity::Invalid("uncalibrated"));
+        assert!(!reading.is_valid());
+        let error = io::Error::from(reading.valid().unwrap_err());
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            reading.check(start, max_age).unwrap_err().to_string(),
+            "bno055 reading is invalid: uncalibrated"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Driver for passive infrared motion sensors, such as the HC-SR501.
+//!
+//! The sensor drives its output high while it sees motion, holding it for a
+//! time set on the module itself. Motion is announced on the event bus and
+//! can wake the robot from sleep, so it perks up when someone walks in.
+
+use std::io::Error;
+use std::time::{Duration, Instant};
+
+use super::debounce::Debouncer;
+use super::measurement::Measurement;
+use crate::events::{Event, EventBus, MotionDetected};
+use crate::gpio::registry::{Claim, Line, Registry};
+use crate::hal::{DigitalInput, Edge};
+use crate::log_event;
+use crate::logging::Level;
+use crate::power::sleep::{WakeHandle, WakeSource};
+
+const SUBSYSTEM: &str = "pir";
+
+/// A PIR motion sensor connected to a digital input.
+#[derive(Debug)]
+pub struct Pir<P: DigitalInput> {
+    bus: Option<EventBus>,
+    _claim: Option<Claim<'static>>,
+    debouncer: Debouncer,
+    name: String,
+    pin: P,
+    sampled: Option<Instant>,
+    wake: Option<WakeHandle>,
+}
+
+impl<P: DigitalInput> Pir<P> {
+    /// Creates a new `Pir` named `pir`, ignoring pulses shorter than 100 ms
+    /// such as those induced by the motors.
+    pub fn new(pin: P) -> Self {
+        Self {
+            bus: None,
+            _claim: None,
+            debouncer: Debouncer::new(Duration::from_millis(100)),
+            name: SUBSYSTEM.to_owned(),
+            pin,
+            sampled: None,
+            wake: None,
+        }
+    }
+
+    /// Creates a new `Pir` as [`Pir::new`] does, claiming `line`, the line
+    /// behind `pin`, for as long as the sensor exists.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error with
+    /// [`ErrorKind::ResourceBusy`](std::io::ErrorKind::ResourceBusy) naming
+    /// the owner if the line is already claimed.
+    pub fn claim(pin: P, line: Line) -> Result<Self, Error> {
+        let claim = Registry::global().claim(line, SUBSYSTEM, "input")?;
+        let mut pir = Self::new(pin);
+        pir._claim = Some(claim);
+        Ok(pir)
+    }
+
+    /// Sets the name the sensor reports motion under.
+    #[must_use]
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    /// Sets how long the output must hold a level before it is believed.
+    #[must_use]
+    pub fn with_debounce(mut self, settle: Duration) -> Self {
+        self.debouncer = Debouncer::new(settle);
+        self
+    }
+
+    /// Publishes a [`MotionDetected`] event to `bus` when motion starts.
+    #[must_use]
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Wakes the robot through `handle` when motion starts.
+    #[must_use]
+    pub fn with_wake(mut self, handle: WakeHandle) -> Self {
+        self.wake = Some(handle);
+        self
+    }
+
+    /// Returns whether the sensor saw motion as of the last sample, or `None`
+    /// before the first sample.
+    #[must_use]
+    pub fn motion(&self) -> Option<Measurement<bool>> {
+        self.sampled
+            .map(|time| Measurement::new(SUBSYSTEM, self.debouncer.level(), time))
+    }
+
+    /// Samples the sensor `dt` after the previous sample, and returns whether
+    /// it sees motion.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the pin cannot be read.
+    pub fn update(&mut self, dt: Duration) -> Result<Measurement<bool>, Error> {
+        let edge = self.debouncer.poll(&mut self.pin, dt)?;
+        let time = Instant::now();
+        self.sampled = Some(time);
+
+        if edge == Some(Edge::Rising) {
+            log_event!(SUBSYSTEM, Level::Debug, "{} detected motion", self.name);
+
+            if let Some(bus) = &self.bus {
+                bus.publish(Event::MotionDetected(MotionDetected {
+                    sensor: self.name.clone(),
+                }));
+            }
+
+            if let Some(wake) = &self.wake {
+                wake.wake(WakeSource::Motion);
+            }
+        }
+
+        Ok(Measurement::new(SUBSYSTEM, self.debouncer.level(), time))
+    }
+
+    /// Returns the pin.
+    pub fn into_inner(self) -> P {
+        self.pin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventKind, PowerMode};
+    use crate::hal::MockInput;
+    use crate::power::SleepManager;
+
+    const DT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn it_should_ignore_brief_pulses() {
+        let pin = MockInput::default();
+        let mut pir = Pir::new(pin.clone());
+        assert_eq!(pir.motion(), None);
+        let mut motion = || pir.update(DT).unwrap().value;
+        pin.set(true);
+        assert!(!motion());
+        pin.set(false);
+        assert!(!motion());
+        pin.set(true);
+        assert!(!motion());
+        assert!(motion());
+    }
+
+    #[test]
+    fn it_should_announce_motion_and_wake_the_robot() {
+        let bus = EventBus::new();
+        let subscription = bus.subscribe_to(&[EventKind::MotionDetected], 4);
+        let mut sleep = SleepManager::new(Duration::from_secs(60));
+        sleep.sleep();
+        let pin = MockInput::default();
+        let mut pir = Pir::new(pin.clone())
+            .with_name("hallway")
+            .with_bus(bus)
+            .with_wake(sleep.wake_handle());
+        pin.set(true);
+
+        for _ in 0..10 {
+            pir.update(DT).unwrap();
+        }
+
+        assert_eq!(
+            subscription.try_recv(),
+            Some(Event::MotionDetected(MotionDetected {
+                sensor: "hallway".to_owned(),
+            }))
+        );
+        assert_eq!(subscription.try_recv(), None);
+        assert_eq!(sleep.update(DT), PowerMode::Awake);
+        assert!(pir.motion().is_some_and(|motion| motion.value));
+    }
+}
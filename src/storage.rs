@@ -0,0 +1,4 @@
+//! Persistent state that survives abrupt power loss.
+
+#[cfg(unix)]
+pub mod kv;
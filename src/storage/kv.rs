@@ -0,0 +1,346 @@
+//! A crash-safe key-value store.
+//!
+//! Every change is appended to a log as a record carrying its own length and
+//! CRC-32, and synced before it is acknowledged. When the robot loses power
+//! mid-write, the torn record at the end of the log fails its check on the
+//! next open and is cut off, leaving every change made before it intact.
+//! Once the log has grown well past the live data, it is compacted by
+//! atomically replacing it with one record per key. Compaction only tidies
+//! up, so a change that reached the log is kept even if compacting fails.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::json::{ToJson, Value};
+use crate::log_event;
+use crate::logging::Level;
+use crate::protocol::framing::crc32;
+use crate::unix::fsutil;
+
+const SUBSYSTEM: &str = "kv";
+
+/// Length of the header before each record’s payload: its length and CRC.
+const HEADER_LEN: usize = 8;
+
+/// Size below which the log is never compacted, in bytes.
+const MIN_COMPACT_LEN: u64 = 64 * 1024;
+
+/// Ratio of log size to live size above which the log is compacted.
+const COMPACT_RATIO: u64 = 4;
+
+/// A key-value store persisted to an append-only log.
+#[derive(Debug)]
+pub struct Store {
+    entries: BTreeMap<String, Value>,
+    file: File,
+    live_len: u64,
+    log_len: u64,
+    path: PathBuf,
+}
+
+impl Store {
+    /// Opens the store at `path`, creating it if it does not exist and
+    /// discarding any record torn by a crash.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be read or
+    /// written.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        let (entries, valid_len) = replay(&contents);
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        if valid_len < contents.len() {
+            log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "discarding {} torn bytes at the end of {}",
+                contents.len() - valid_len,
+                path.display()
+            );
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+
+        let live_len = entries
+            .iter()
+            .map(|(key, value)| live_record_len(key, value))
+            .sum();
+
+        Ok(Self {
+            entries,
+            file,
+            live_len,
+            log_len: valid_len as u64,
+            path: path.to_owned(),
+        })
+    }
+
+    /// Returns the value of `key`.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.get(key)
+    }
+
+    /// Returns every key and its value, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Sets `key` to `value`, returning once the change is on disk.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the change cannot be written,
+    /// in which case the store is left unchanged.
+    pub fn set(&mut self, key: &str, value: impl ToJson) -> Result<(), Error> {
+        let value = value.to_json();
+        let record = record(key, Some(&value));
+        self.append(&record)?;
+        self.live_len += record.len() as u64;
+
+        if let Some(old) = self.entries.insert(key.to_owned(), value) {
+            self.live_len -= live_record_len(key, &old);
+        }
+
+        self.compact_if_bloated();
+        Ok(())
+    }
+
+    /// Removes `key`, returning whether it was present once the change is on
+    /// disk.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the change cannot be written,
+    /// in which case the store is left unchanged.
+    pub fn remove(&mut self, key: &str) -> Result<bool, Error> {
+        if !self.entries.contains_key(key) {
+            return Ok(false);
+        }
+
+        self.append(&record(key, None))?;
+
+        if let Some(old) = self.entries.remove(key) {
+            self.live_len -= live_record_len(key, &old);
+        }
+
+        self.compact_if_bloated();
+        Ok(true)
+    }
+
+    /// Rewrites the log with a single record per key.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the new log cannot be written.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let contents: Vec<u8> = self
+            .entries
+            .iter()
+            .flat_map(|(key, value)| record(key, Some(value)))
+            .collect();
+        fsutil::write_atomic(&self.path, &contents)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.log_len = contents.len() as u64;
+        self.live_len = self.log_len;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &[u8]) -> Result<(), Error> {
+        let result = self
+            .file
+            .write_all(record)
+            .and_then(|()| self.file.sync_data());
+
+        if let Err(error) = result {
+            // Cut off whatever part of the record made it, so the next
+            // append does not follow garbage.
+            let _ = self.file.set_len(self.log_len);
+            return Err(error);
+        }
+
+        self.log_len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Compacts the log if it has grown well past the live data. A failure
+    /// is only logged, since the change that grew the log is already safe
+    /// and the next change will try again.
+    fn compact_if_bloated(&mut self) {
+        if self.log_len <= MIN_COMPACT_LEN || self.log_len <= self.live_len * COMPACT_RATIO {
+            return;
+        }
+
+        log_event!(
+            SUBSYSTEM,
+            Level::Debug,
+            "compacting {}",
+            self.path.display()
+        );
+
+        if let Err(error) = self.compact() {
+            log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "cannot compact {}: {error}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Returns the record setting `key` to `value`, or removing it if `None`.
+fn record(key: &str, value: Option<&Value>) -> Vec<u8> {
+    let mut payload = Value::object().with("k", key);
+
+    if let Some(value) = value {
+        payload.insert("v", value);
+    }
+
+    let payload = payload.to_string().into_bytes();
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record
+}
+
+/// Returns the length of the record that set `key` to `value`.
+fn live_record_len(key: &str, value: &Value) -> u64 {
+    record(key, Some(value)).len() as u64
+}
+
+/// Applies the records in `log` in order, stopping at the first that is
+/// incomplete or corrupt, and returns the entries and the length of the
+/// valid prefix.
+fn replay(log: &[u8]) -> (BTreeMap<String, Value>, usize) {
+    let mut entries = BTreeMap::new();
+    let mut position = 0;
+
+    while let Some(header) = log.get(position..position + HEADER_LEN) {
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let start = position + HEADER_LEN;
+        let Some(payload) = start.checked_add(len).and_then(|end| log.get(start..end)) else {
+            break;
+        };
+
+        if crc32(payload) != crc {
+            break;
+        }
+
+        let Some(value) = std::str::from_utf8(payload)
+            .ok()
+            .and_then(|payload| payload.parse::<Value>().ok())
+        else {
+            break;
+        };
+        let Some(key) = value.get("k").and_then(Value::as_str) else {
+            break;
+        };
+
+        match value.get("v") {
+            Some(value) => entries.insert(key.to_owned(), value.clone()),
+            None => entries.remove(key),
+        };
+
+        position = start + len;
+    }
+
+    (entries, position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TemporaryDirectory;
+
+    #[test]
+    fn it_should_persist_changes_across_opens() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let path = dir.path().join("state.kv");
+        let mut store = Store::open(&path).unwrap();
+        store.set("gyro.offset", 0.02).unwrap();
+        store.set("mission", "patrol").unwrap();
+        store.set("mission", "dock").unwrap();
+        assert!(store.remove("gyro.offset").unwrap());
+        assert!(!store.remove("gyro.offset").unwrap());
+        drop(store);
+        let store = Store::open(&path).unwrap();
+        assert_eq!(store.get("gyro.offset"), None);
+        assert_eq!(
+            store.get("mission"),
+            Some(&Value::String("dock".to_owned()))
+        );
+    }
+
+    #[test]
+    fn it_should_discard_a_torn_record() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let path = dir.path().join("state.kv");
+        let mut store = Store::open(&path).unwrap();
+        store.set("a", 1).unwrap();
+        store.set("b", 2).unwrap();
+        drop(store);
+        // Lose the last few bytes of the second record, as a power cut might.
+        let contents = fs::read(&path).unwrap();
+        fs::write(&path, &contents[..contents.len() - 3]).unwrap();
+        let mut store = Store::open(&path).unwrap();
+        assert_eq!(store.get("a"), Some(&Value::Number(1.0)));
+        assert_eq!(store.get("b"), None);
+        store.set("c", 3).unwrap();
+        drop(store);
+        let store = Store::open(&path).unwrap();
+        assert_eq!(
+            store.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            ["a", "c"]
+        );
+    }
+
+    #[test]
+    fn it_should_discard_a_corrupt_record() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let path = dir.path().join("state.kv");
+        let mut store = Store::open(&path).unwrap();
+        store.set("a", 1).unwrap();
+        drop(store);
+        let mut contents = fs::read(&path).unwrap();
+        let last = contents.len() - 2;
+        contents[last] ^= 0xFF;
+        fs::write(&path, contents).unwrap();
+        assert_eq!(Store::open(&path).unwrap().get("a"), None);
+    }
+
+    #[test]
+    fn it_should_compact_a_bloated_log() {
+        let dir = TemporaryDirectory::new().unwrap();
+        let path = dir.path().join("state.kv");
+        let mut store = Store::open(&path).unwrap();
+
+        for count in 0..5000 {
+            store.set("odometer", count).unwrap();
+        }
+
+        assert!(fs::metadata(&path).unwrap().len() <= MIN_COMPACT_LEN);
+        store.set("name", "otter").unwrap();
+        assert!(store.remove("name").unwrap());
+        let live_len = store.live_len;
+        store.compact().unwrap();
+        assert_eq!(store.live_len, live_len);
+        drop(store);
+        assert_eq!(
+            Store::open(&path).unwrap().get("odometer"),
+            Some(&Value::Number(4999.0))
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! Primitives for sharing data between real-time threads without locks.
+
+pub mod latest;
+pub mod ring_buffer;
+
+pub use latest::Latest;
+pub use ring_buffer::RingBuffer;
@@ -0,0 +1,321 @@
+//! The latest value of a fast-changing reading, shared without locks.
+//!
+//! A sensor thread sampling at 1 kHz should never wait on the control loop
+//! or the telemetry thread reading its samples, and they only ever want the
+//! newest one. [`Latest`] keeps a single slot guarded by a sequence number,
+//! as a seqlock does: the [`Publisher`] bumps the number to odd, writes the
+//! value, and bumps it back to even, and a reader that sees the number
+//! change while it copies the value simply copies it again. Neither side
+//! locks or allocates after the slot is created, and the publisher never
+//! waits.
+//!
+//! The value is stored as [`Words`] in atomics rather than in a cell, so a
+//! reader copying it while it is written sees a torn value to discard
+//! instead of racing with the write.
+
+use std::fmt::{self, Debug, Formatter};
+use std::hint;
+use std::marker::PhantomData;
+use std::sync::atomic::{self, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A value that can be stored as a fixed number of 64-bit words.
+pub trait Words: Copy {
+    /// Number of words the value is stored in.
+    const LEN: usize;
+
+    /// Passes each word of the value to `write`, in order.
+    fn write_words(&self, write: &mut impl FnMut(u64));
+
+    /// Reads a value from its words, taking each from `read` in order.
+    fn read_words(read: &mut impl FnMut() -> u64) -> Self;
+}
+
+macro_rules! impl_words_for_integer {
+    ($($t:ty),*) => {
+        $(
+            impl Words for $t {
+                const LEN: usize = 1;
+
+                fn write_words(&self, write: &mut impl FnMut(u64)) {
+                    write(*self as u64);
+                }
+
+                fn read_words(read: &mut impl FnMut() -> u64) -> Self {
+                    read() as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_words_for_integer!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl Words for f64 {
+    const LEN: usize = 1;
+
+    fn write_words(&self, write: &mut impl FnMut(u64)) {
+        write(self.to_bits());
+    }
+
+    fn read_words(read: &mut impl FnMut() -> u64) -> Self {
+        Self::from_bits(read())
+    }
+}
+
+impl Words for f32 {
+    const LEN: usize = 1;
+
+    fn write_words(&self, write: &mut impl FnMut(u64)) {
+        write(u64::from(self.to_bits()));
+    }
+
+    fn read_words(read: &mut impl FnMut() -> u64) -> Self {
+        Self::from_bits(read() as u32)
+    }
+}
+
+impl Words for bool {
+    const LEN: usize = 1;
+
+    fn write_words(&self, write: &mut impl FnMut(u64)) {
+        write(u64::from(*self));
+    }
+
+    fn read_words(read: &mut impl FnMut() -> u64) -> Self {
+        read() != 0
+    }
+}
+
+impl<T: Words> Words for Option<T> {
+    const LEN: usize = T::LEN + 1;
+
+    fn write_words(&self, write: &mut impl FnMut(u64)) {
+        write(u64::from(self.is_some()));
+
+        match self {
+            Some(value) => value.write_words(write),
+            None => (0..T::LEN).for_each(|_| write(0)),
+        }
+    }
+
+    fn read_words(read: &mut impl FnMut() -> u64) -> Self {
+        let is_some = read() != 0;
+        let value = T::read_words(read);
+        is_some.then_some(value)
+    }
+}
+
+impl<T: Words, const N: usize> Words for [T; N] {
+    const LEN: usize = T::LEN * N;
+
+    fn write_words(&self, write: &mut impl FnMut(u64)) {
+        self.iter().for_each(|value| value.write_words(write));
+    }
+
+    fn read_words(read: &mut impl FnMut() -> u64) -> Self {
+        std::array::from_fn(|_| T::read_words(read))
+    }
+}
+
+struct Slot<T> {
+    sequence: AtomicU64,
+    words: Box<[AtomicU64]>,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T: Words> Slot<T> {
+    fn store(&self, value: T) {
+        let mut words = self.words.iter();
+        value.write_words(&mut |word| {
+            words
+                .next()
+                .expect("the value should fit its words")
+                .store(word, Ordering::Relaxed);
+        });
+    }
+
+    fn load(&self) -> T {
+        let mut words = self.words.iter();
+        T::read_words(&mut || {
+            words
+                .next()
+                .expect("the value should fit its words")
+                .load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Creates a slot holding `initial`, returning its only publisher and a
+/// reader that can be cloned for each consumer.
+pub fn channel<T: Words>(initial: T) -> (Publisher<T>, Latest<T>) {
+    let slot = Arc::new(Slot {
+        sequence: AtomicU64::new(0),
+        words: (0..T::LEN).map(|_| AtomicU64::new(0)).collect(),
+        value: PhantomData,
+    });
+    slot.store(initial);
+    (
+        Publisher {
+            slot: Arc::clone(&slot),
+        },
+        Latest { slot },
+    )
+}
+
+/// Writing end of a slot, of which there is only ever one.
+pub struct Publisher<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T: Words> Publisher<T> {
+    /// Replaces the value, without waiting for readers.
+    pub fn publish(&mut self, value: T) {
+        let sequence = self.slot.sequence.load(Ordering::Relaxed);
+        self.slot
+            .sequence
+            .store(sequence.wrapping_add(1), Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+        self.slot.store(value);
+        self.slot
+            .sequence
+            .store(sequence.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Returns a new reader of the slot.
+    #[must_use]
+    pub fn subscribe(&self) -> Latest<T> {
+        Latest {
+            slot: Arc::clone(&self.slot),
+        }
+    }
+}
+
+impl<T> Debug for Publisher<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Publisher").finish_non_exhaustive()
+    }
+}
+
+/// Reading end of a slot, returning the value last published.
+pub struct Latest<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T: Words> Latest<T> {
+    /// Returns the value last published.
+    #[must_use]
+    pub fn get(&self) -> T {
+        self.read().1
+    }
+
+    /// Returns how many values have been published.
+    #[must_use]
+    pub fn version(&self) -> u64 {
+        self.slot.sequence.load(Ordering::Acquire) / 2
+    }
+
+    /// Returns the value last published if it is newer than `seen`, and
+    /// updates `seen` to its version.
+    pub fn get_newer(&self, seen: &mut u64) -> Option<T> {
+        let (version, value) = self.read();
+
+        if version == *seen {
+            return None;
+        }
+
+        *seen = version;
+        Some(value)
+    }
+
+    fn read(&self) -> (u64, T) {
+        loop {
+            let before = self.slot.sequence.load(Ordering::Acquire);
+
+            if before.is_multiple_of(2) {
+                let value = self.slot.load();
+                atomic::fence(Ordering::Acquire);
+
+                if self.slot.sequence.load(Ordering::Relaxed) == before {
+                    return (before / 2, value);
+                }
+            }
+
+            hint::spin_loop();
+        }
+    }
+}
+
+impl<T> Clone for Latest<T> {
+    fn clone(&self) -> Self {
+        Self {
+            slot: Arc::clone(&self.slot),
+        }
+    }
+}
+
+impl<T> Debug for Latest<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Latest").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn it_should_return_the_latest_value_once() {
+        let (mut publisher, latest) = channel([0.0; 3]);
+        let mut seen = latest.version();
+        assert_eq!(latest.get_newer(&mut seen), None);
+        publisher.publish([0.1, 0.2, 9.8]);
+        publisher.publish([0.2, 0.1, 9.8]);
+        assert_eq!(latest.version(), 2);
+        assert_eq!(latest.get_newer(&mut seen), Some([0.2, 0.1, 9.8]));
+        assert_eq!(latest.get_newer(&mut seen), None);
+        assert_eq!(publisher.subscribe().get(), [0.2, 0.1, 9.8]);
+    }
+
+    #[test]
+    fn it_should_store_values_as_words() {
+        let (mut publisher, latest) = channel([Some(-3_i32), None]);
+        assert_eq!(<[Option<i32>; 2]>::LEN, 4);
+        assert_eq!(latest.get(), [Some(-3), None]);
+        publisher.publish([None, Some(i32::MIN)]);
+        assert_eq!(latest.get(), [None, Some(i32::MIN)]);
+        let (mut publisher, latest) = channel(0.5_f64);
+        publisher.publish(-0.25);
+        assert_eq!(latest.get(), -0.25);
+    }
+
+    #[test]
+    fn it_should_never_return_a_torn_value() {
+        let (mut publisher, latest) = channel([0_u64; 8]);
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let latest = latest.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+
+                    for _ in 0..100_000 {
+                        let value = latest.get();
+                        assert!(value.iter().all(|&word| word == value[0]));
+                        assert!(value[0] >= last);
+                        last = value[0];
+                    }
+                })
+            })
+            .collect();
+
+        for sample in 1..=100_000 {
+            publisher.publish([sample; 8]);
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
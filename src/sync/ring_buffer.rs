@@ -0,0 +1,233 @@
+//! A bounded queue that never allocates after it is created.
+//!
+//! Real-time paths such as a serial receive thread or an encoder edge
+//! handler cannot afford to allocate or wait on a lock held by a slower
+//! thread. A [`RingBuffer`] reserves all of its slots up front, and each
+//! slot carries a sequence number telling producers and consumers whose turn
+//! it is, so they claim slots with a single compare-and-swap. When it is
+//! full, [`RingBuffer::push`] hands the value back rather than waiting or
+//! overwriting, leaving the producer to decide what to drop.
+
+use std::cell::UnsafeCell;
+use std::fmt::{self, Debug, Formatter};
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+struct Slot<T> {
+    // Equal to the position of the next push into the slot while it is
+    // empty, and to one more than that position once it holds a value.
+    sequence: AtomicU64,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A fixed-capacity queue for any number of producers and consumers.
+pub struct RingBuffer<T> {
+    head: AtomicU64,
+    slots: Box<[Slot<T>]>,
+    tail: AtomicU64,
+}
+
+// A slot’s value is only accessed by the thread that claimed its position,
+// and the sequence number hands it over with release and acquire ordering.
+unsafe impl<T: Send> Send for RingBuffer<T> {}
+unsafe impl<T: Send> Sync for RingBuffer<T> {}
+
+impl<T> RingBuffer<T> {
+    /// Creates a new, empty `RingBuffer` holding up to `capacity` values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity should not be zero");
+        let slots = (0..capacity as u64)
+            .map(|position| Slot {
+                sequence: AtomicU64::new(position),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        Self {
+            head: AtomicU64::new(0),
+            slots,
+            tail: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of values the buffer can hold.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns the number of values in the buffer, which may already have
+    /// changed if other threads are using it.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        head.saturating_sub(tail).min(self.slots.len() as u64) as usize
+    }
+
+    /// Returns `true` if the buffer holds no values.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds `value` to the back of the buffer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `value` back if the buffer is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut position = self.head.load(Ordering::Relaxed);
+
+        loop {
+            let slot = self.slot(position);
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            if sequence == position {
+                match self.head.compare_exchange_weak(
+                    position,
+                    position + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence.store(position + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => position = current,
+                }
+            } else if sequence < position {
+                // The slot still holds the value pushed a lap ago.
+                return Err(value);
+            } else {
+                position = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Removes the value at the front of the buffer, or returns `None` if it
+    /// is empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut position = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let slot = self.slot(position);
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            if sequence == position + 1 {
+                match self.tail.compare_exchange_weak(
+                    position,
+                    position + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.sequence
+                            .store(position + self.slots.len() as u64, Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => position = current,
+                }
+            } else if sequence <= position {
+                return None;
+            } else {
+                position = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn slot(&self, position: u64) -> &Slot<T> {
+        &self.slots[(position % self.slots.len() as u64) as usize]
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T> Debug for RingBuffer<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RingBuffer")
+            .field("capacity", &self.capacity())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn it_should_refuse_values_when_full() {
+        let buffer = RingBuffer::new(3);
+
+        for byte in b"abc" {
+            buffer.push(*byte).unwrap();
+        }
+
+        assert_eq!(buffer.push(b'd'), Err(b'd'));
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.pop(), Some(b'a'));
+        buffer.push(b'd').unwrap();
+        let drained: Vec<_> = std::iter::from_fn(|| buffer.pop()).collect();
+        assert_eq!(drained, b"bcd");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn it_should_drop_values_left_in_the_buffer() {
+        let value = Arc::new(());
+        let buffer = RingBuffer::new(4);
+        buffer.push(Arc::clone(&value)).unwrap();
+        buffer.push(Arc::clone(&value)).unwrap();
+        drop(buffer);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn it_should_deliver_every_value_from_many_producers_once() {
+        const PER_PRODUCER: u64 = 10_000;
+        let buffer = Arc::new(RingBuffer::new(16));
+        let producers: Vec<_> = (0..4)
+            .map(|producer| {
+                let buffer = Arc::clone(&buffer);
+                thread::spawn(move || {
+                    for index in 0..PER_PRODUCER {
+                        let mut value = producer * PER_PRODUCER + index;
+
+                        while let Err(rejected) = buffer.push(value) {
+                            value = rejected;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        let mut received = Vec::new();
+
+        while received.len() < 4 * PER_PRODUCER as usize {
+            match buffer.pop() {
+                Some(value) => received.push(value),
+                None => thread::yield_now(),
+            }
+        }
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        received.sort_unstable();
+        assert!(received.iter().copied().eq(0..4 * PER_PRODUCER));
+    }
+}
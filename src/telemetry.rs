@@ -0,0 +1,3 @@
+//! Streams of timestamped readings from the robot's sensors.
+
+pub mod align;
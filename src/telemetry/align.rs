@@ -0,0 +1,449 @@
+//! Lining up readings from sensors that sample at different times.
+//!
+//! Sensors disagree about when things happened. The IMU reports at 1 kHz
+//! within a millisecond of the motion, while a camera frame arrives about
+//! 80 ms after the light that made it, and only 30 times a second. An
+//! estimator that pairs each frame with the latest IMU reading fuses motion
+//! that happened at different times, which shows up as lag and overshoot
+//! whenever the robot turns.
+//!
+//! Each sensor's readings go into a [`Channel`], stamped with when they
+//! were taken by subtracting the channel's latency from when they arrived.
+//! The slowest channel then drives the estimator: [`Channel::pop_aligned`]
+//! hands out each of its samples once every other channel has caught up
+//! past it, with their readings interpolated to the same instant.
+//!
+//! Latencies are rarely in a datasheet, so [`Channel::latency_offset`]
+//! measures them against a channel whose latency is known, by finding the
+//! shift that best lines up a signal both can see, such as the yaw rate
+//! from the gyro and from visual odometry.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::drive::Twist;
+use crate::geometry::{normalize_angle, Pose};
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "align";
+
+/// Widest gap between samples that is interpolated across by default.
+const DEFAULT_MAX_GAP: Duration = Duration::from_millis(100);
+
+/// Step between the shifts tried when measuring a latency.
+const CALIBRATION_STEP: Duration = Duration::from_millis(1);
+
+/// Fewest overlapping samples a shift must line up to be considered.
+const MIN_CALIBRATION_SAMPLES: usize = 20;
+
+/// A value that can be blended between two readings.
+pub trait Interpolate: Clone {
+    /// Returns the value `fraction` of the way from `self` to `other`,
+    /// where `fraction` is from 0 to 1.
+    #[must_use]
+    fn interpolate(&self, other: &Self, fraction: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &Self, fraction: f64) -> Self {
+        self + (other - self) * fraction
+    }
+}
+
+impl<const N: usize> Interpolate for [f64; N] {
+    fn interpolate(&self, other: &Self, fraction: f64) -> Self {
+        std::array::from_fn(|i| self[i].interpolate(&other[i], fraction))
+    }
+}
+
+impl Interpolate for Pose {
+    fn interpolate(&self, other: &Self, fraction: f64) -> Self {
+        Self {
+            x: self.x.interpolate(&other.x, fraction),
+            y: self.y.interpolate(&other.y, fraction),
+            heading: normalize_angle(
+                self.heading + normalize_angle(other.heading - self.heading) * fraction,
+            ),
+        }
+    }
+}
+
+impl Interpolate for Twist {
+    fn interpolate(&self, other: &Self, fraction: f64) -> Self {
+        Self {
+            vx: self.vx.interpolate(&other.vx, fraction),
+            vy: self.vy.interpolate(&other.vy, fraction),
+            omega: self.omega.interpolate(&other.omega, fraction),
+        }
+    }
+}
+
+/// A reading and when it was taken.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample<T> {
+    /// When the reading was taken.
+    pub time: Instant,
+    /// Reading.
+    pub value: T,
+}
+
+/// A channel's reading at some instant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aligned<T> {
+    /// The reading at the instant.
+    Ready(T),
+    /// The channel has not yet caught up to the instant.
+    Pending,
+    /// The channel has no reading near the instant, and never will.
+    Missing,
+}
+
+impl<T> Aligned<T> {
+    /// Returns the readings of both channels, which are only ready once both
+    /// are.
+    pub fn zip<U>(self, other: Aligned<U>) -> Aligned<(T, U)> {
+        match (self, other) {
+            (Self::Ready(value), Aligned::Ready(other)) => Aligned::Ready((value, other)),
+            (Self::Missing, _) | (_, Aligned::Missing) => Aligned::Missing,
+            _ => Aligned::Pending,
+        }
+    }
+
+    /// Returns the reading if it is ready.
+    pub fn ready(self) -> Option<T> {
+        match self {
+            Self::Ready(value) => Some(value),
+            Self::Pending | Self::Missing => None,
+        }
+    }
+
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Aligned<U> {
+        match self {
+            Self::Ready(value) => Aligned::Ready(f(value)),
+            Self::Pending => Aligned::Pending,
+            Self::Missing => Aligned::Missing,
+        }
+    }
+}
+
+/// Recent readings of one sensor, stamped with when they were taken.
+#[derive(Clone, Debug)]
+pub struct Channel<T> {
+    capacity: usize,
+    latency: Duration,
+    max_gap: Duration,
+    name: String,
+    samples: VecDeque<Sample<T>>,
+}
+
+impl<T> Channel<T> {
+    /// Creates a new `Channel` named `name` that keeps the latest
+    /// `capacity` readings, which must cover the latency of the slowest
+    /// channel it is aligned with.
+    pub fn new(name: &str, capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            latency: Duration::ZERO,
+            max_gap: DEFAULT_MAX_GAP,
+            name: name.to_owned(),
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Sets the time from taking a reading to its arrival.
+    #[must_use]
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sets the widest gap between readings that is interpolated across.
+    #[must_use]
+    pub fn with_max_gap(mut self, max_gap: Duration) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    /// Returns the name.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the time from taking a reading to its arrival.
+    #[must_use]
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Changes the latency by `offset` seconds, such as one measured by
+    /// [`Channel::latency_offset`], restamping the readings already held.
+    pub fn adjust_latency(&mut self, offset: f64) {
+        let latency = (self.latency.as_secs_f64() + offset).max(0.0);
+        let shift = latency - self.latency.as_secs_f64();
+        self.latency = Duration::from_secs_f64(latency);
+
+        for sample in &mut self.samples {
+            sample.time = shifted(sample.time, -shift);
+        }
+
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "{} latency is now {:?}",
+            self.name,
+            self.latency
+        );
+    }
+
+    /// Returns the readings held, oldest first.
+    pub fn samples(&self) -> impl Iterator<Item = &Sample<T>> {
+        self.samples.iter()
+    }
+
+    /// Records `value`, which arrived at `received`, and returns `false` if
+    /// it was dropped for being taken before the latest reading.
+    pub fn push(&mut self, value: T, received: Instant) -> bool {
+        let time = received.checked_sub(self.latency).unwrap_or(received);
+
+        if self.samples.back().is_some_and(|last| last.time > time) {
+            return false;
+        }
+
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(Sample { time, value });
+        true
+    }
+
+    /// Returns the reading at `time`, interpolated between the readings
+    /// either side of it.
+    pub fn at(&self, time: Instant) -> Aligned<T>
+    where
+        T: Interpolate,
+    {
+        self.bracket(time).map(|(before, after)| match after {
+            Some(after) => {
+                let span = after.time.duration_since(before.time).as_secs_f64();
+                let fraction = time.duration_since(before.time).as_secs_f64() / span;
+                before.value.interpolate(&after.value, fraction)
+            }
+            None => before.value.clone(),
+        })
+    }
+
+    /// Returns the reading taken closest to `time`, for readings such as
+    /// camera frames that cannot be blended.
+    pub fn nearest(&self, time: Instant) -> Aligned<T>
+    where
+        T: Clone,
+    {
+        self.bracket(time).map(|(before, after)| match after {
+            Some(after) if after.time.duration_since(time) < time.duration_since(before.time) => {
+                after.value.clone()
+            }
+            _ => before.value.clone(),
+        })
+    }
+
+    /// Removes and returns the oldest reading together with the other
+    /// channels' readings at the same time, returned by `lookup`, once they
+    /// are all ready. Readings the other channels have no match for are
+    /// dropped.
+    ///
+    /// If another channel stops reporting, this channel's readings wait for
+    /// it until they fall off the end of the buffer.
+    pub fn pop_aligned<A>(
+        &mut self,
+        mut lookup: impl FnMut(Instant) -> Aligned<A>,
+    ) -> Option<(Sample<T>, A)> {
+        loop {
+            let time = self.samples.front()?.time;
+
+            match lookup(time) {
+                Aligned::Ready(other) => {
+                    let sample = self.samples.pop_front()?;
+                    return Some((sample, other));
+                }
+                Aligned::Pending => return None,
+                Aligned::Missing => {
+                    log_event!(SUBSYSTEM, Level::Debug, "{} reading unmatched", self.name);
+                    self.samples.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Returns the readings either side of `time`, or the reading at it.
+    fn bracket(&self, time: Instant) -> Aligned<(&Sample<T>, Option<&Sample<T>>)> {
+        let index = self.samples.partition_point(|sample| sample.time <= time);
+
+        let Some(before) = index.checked_sub(1).and_then(|i| self.samples.get(i)) else {
+            return Aligned::Missing;
+        };
+
+        if before.time == time {
+            return Aligned::Ready((before, None));
+        }
+
+        match self.samples.get(index) {
+            None => Aligned::Pending,
+            Some(after) if after.time.duration_since(before.time) > self.max_gap => {
+                Aligned::Missing
+            }
+            Some(after) => Aligned::Ready((before, Some(after))),
+        }
+    }
+}
+
+impl Channel<f64> {
+    /// Measures how much later than `reference` this channel sees the same
+    /// signal, in seconds, searching shifts up to `range` either way. Add
+    /// the offset to the latency of this channel, or of the channel the
+    /// signal was derived from, with [`Channel::adjust_latency`].
+    ///
+    /// Returns `None` if the channels do not overlap enough, or the signal
+    /// is flat. The robot should be turning back and forth, or otherwise
+    /// varying the signal, while the readings are collected.
+    #[must_use]
+    pub fn latency_offset(&self, reference: &Channel<f64>, range: Duration) -> Option<f64> {
+        let step = CALIBRATION_STEP.as_secs_f64();
+        // Truncation is intended: the range is a fraction of a second.
+        #[allow(clippy::cast_possible_truncation)]
+        let steps = (range.as_secs_f64() / step).round() as i64;
+
+        (-steps..=steps)
+            .filter_map(|i| {
+                #[allow(clippy::cast_precision_loss)]
+                let offset = i as f64 * step;
+                let pairs: Vec<_> = reference
+                    .samples()
+                    .filter_map(|sample| {
+                        let value = self.at(shifted(sample.time, offset)).ready()?;
+                        Some((sample.value, value))
+                    })
+                    .collect();
+                Some((offset, correlation(&pairs)?))
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(offset, _)| offset)
+    }
+}
+
+/// Returns the correlation coefficient of `pairs`, or `None` if there are
+/// too few or either side is constant.
+fn correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < MIN_CALIBRATION_SAMPLES {
+        return None;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let n = pairs.len() as f64;
+    let (mean_a, mean_b) = pairs
+        .iter()
+        .fold((0.0, 0.0), |(a, b), (x, y)| (a + x / n, b + y / n));
+    let (covariance, variance_a, variance_b) =
+        pairs.iter().fold((0.0, 0.0, 0.0), |(c, va, vb), (x, y)| {
+            let (dx, dy) = (x - mean_a, y - mean_b);
+            (c + dx * dy, va + dx * dx, vb + dy * dy)
+        });
+    let scale = (variance_a * variance_b).sqrt();
+    (scale > f64::EPSILON).then(|| covariance / scale)
+}
+
+/// Returns `time` moved by `seconds`, which may be negative.
+fn shifted(time: Instant, seconds: f64) -> Instant {
+    let shift = Duration::from_secs_f64(seconds.abs());
+
+    if seconds < 0.0 {
+        time.checked_sub(shift).unwrap_or(time)
+    } else {
+        time + shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn it_should_interpolate_between_readings() {
+        let start = Instant::now();
+        let mut imu = Channel::new("imu", 16);
+        imu.push([0.0, 10.0], start);
+        imu.push([1.0, 20.0], start + 10 * MS);
+        assert_eq!(imu.at(start + 5 * MS), Aligned::Ready([0.5, 15.0]));
+        assert_eq!(imu.at(start + 10 * MS), Aligned::Ready([1.0, 20.0]));
+        assert_eq!(imu.at(start + 11 * MS), Aligned::Pending);
+        assert_eq!(imu.at(start.checked_sub(MS).unwrap()), Aligned::Missing);
+        assert_eq!(imu.nearest(start + 6 * MS), Aligned::Ready([1.0, 20.0]));
+        let pose = Pose::new(0.0, 0.0, 3.0).interpolate(&Pose::new(2.0, 0.0, -3.0), 0.5);
+        assert!((pose.heading.abs() - std::f64::consts::PI).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_should_stamp_readings_with_when_they_were_taken() {
+        let start = Instant::now();
+        let mut camera = Channel::new("camera", 4).with_latency(80 * MS);
+        assert!(camera.push(1, start + 100 * MS));
+        assert!(!camera.push(2, start + 90 * MS));
+        assert_eq!(camera.samples().next().unwrap().time, start + 20 * MS);
+        camera.adjust_latency(-0.03);
+        assert_eq!(camera.latency(), 50 * MS);
+        assert_eq!(camera.samples().next().unwrap().time, start + 50 * MS);
+    }
+
+    #[test]
+    fn it_should_hand_out_readings_once_every_channel_has_caught_up() {
+        let start = Instant::now();
+        let mut camera = Channel::new("camera", 8).with_latency(80 * MS);
+        let mut imu = Channel::new("imu", 1000).with_max_gap(5 * MS);
+        let mut odometry = Channel::new("odometry", 100);
+
+        for i in 0..=60 {
+            imu.push(f64::from(i), start + i * MS);
+        }
+
+        for i in 0..=3 {
+            odometry.push(f64::from(i) * 20.0, start + i * 20 * MS);
+        }
+
+        camera.push("early", start + 70 * MS);
+        camera.push("frame", start + 100 * MS);
+        camera.push("late", start + 150 * MS);
+        let mut aligned = || camera.pop_aligned(|time| imu.at(time).zip(odometry.at(time)));
+        let (sample, (rate, distance)) = aligned().unwrap();
+        assert_eq!(sample.value, "frame");
+        assert_eq!((rate, distance), (20.0, 20.0));
+        assert_eq!(aligned(), None);
+    }
+
+    #[test]
+    fn it_should_measure_the_latency_between_channels() {
+        let start = Instant::now() + Duration::from_secs(1);
+        let signal = |t: f64| (t * 7.0).sin() + (t * 3.0).cos();
+        let mut gyro = Channel::new("gyro", 2000);
+        let mut camera = Channel::new("camera", 100);
+
+        for i in 0..1000 {
+            let t = f64::from(i) / 1000.0;
+            gyro.push(signal(t), start + i * MS);
+        }
+
+        for i in 0..30 {
+            let t = f64::from(i) / 30.0;
+            // The camera is really 80 ms behind, but assumed to have none.
+            camera.push(signal(t), start + Duration::from_secs_f64(t + 0.08));
+        }
+
+        let offset = camera.latency_offset(&gyro, 120 * MS).unwrap();
+        assert!((offset - 0.08).abs() < 0.002, "{offset}");
+        camera.adjust_latency(offset);
+        assert!((camera.latency().as_secs_f64() - 0.08).abs() < 0.002);
+    }
+}
@@ -0,0 +1,4 @@
+//! Interfaces for using the robot without a laptop.
+
+pub mod gamepad;
+pub mod menu;
@@ -0,0 +1,697 @@
+//! Calibrated, remappable gamepad input.
+//!
+//! Every controller reports its sticks and buttons under different Linux
+//! input codes and over different ranges, and worn sticks rarely rest at
+//! zero. A [`Gamepad`] maps raw events to named [`Axis`] and [`Button`]
+//! inputs through a [`Profile`], then scales each axis by its calibration
+//! into -1 to 1, or 0 to 1 for triggers. The profile and calibration of each
+//! device are parameters under `gamepad.<device>.`, so they persist with the
+//! rest of the parameters and can be changed while the robot runs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::drive::Twist;
+use crate::log_event;
+use crate::logging::Level;
+use crate::params::{Param, ParamError, ParamServer, ParamSpec};
+
+const SUBSYSTEM: &str = "gamepad";
+
+/// Name of the profile used for a device until another is selected.
+pub const DEFAULT_PROFILE: &str = "generic";
+
+/// A stick or trigger.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Axis {
+    /// Left stick, positive to the right.
+    LeftX,
+    /// Left stick, positive down, as devices report it.
+    LeftY,
+    /// Right stick, positive to the right.
+    RightX,
+    /// Right stick, positive down, as devices report it.
+    RightY,
+    /// Left trigger, positive when pulled.
+    LeftTrigger,
+    /// Right trigger, positive when pulled.
+    RightTrigger,
+}
+
+impl Axis {
+    /// Every axis.
+    pub const ALL: [Self; 6] = [
+        Self::LeftX,
+        Self::LeftY,
+        Self::RightX,
+        Self::RightY,
+        Self::LeftTrigger,
+        Self::RightTrigger,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::LeftX => "left_x",
+            Self::LeftY => "left_y",
+            Self::RightX => "right_x",
+            Self::RightY => "right_y",
+            Self::LeftTrigger => "left_trigger",
+            Self::RightTrigger => "right_trigger",
+        }
+    }
+}
+
+impl Display for Axis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Axis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|axis| axis.name() == s)
+            .ok_or_else(|| format!("unknown axis `{s}`"))
+    }
+}
+
+/// A button, named by its position as on an Xbox controller.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Button {
+    /// Bottom face button, A or cross.
+    South,
+    /// Right face button, B or circle.
+    East,
+    /// Left face button, X or square.
+    West,
+    /// Top face button, Y or triangle.
+    North,
+    /// Left shoulder button.
+    LeftBumper,
+    /// Right shoulder button.
+    RightBumper,
+    /// Back, view, or share button.
+    Select,
+    /// Start, menu, or options button.
+    Start,
+}
+
+impl Button {
+    /// Every button.
+    pub const ALL: [Self; 8] = [
+        Self::South,
+        Self::East,
+        Self::West,
+        Self::North,
+        Self::LeftBumper,
+        Self::RightBumper,
+        Self::Select,
+        Self::Start,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::South => "south",
+            Self::East => "east",
+            Self::West => "west",
+            Self::North => "north",
+            Self::LeftBumper => "left_bumper",
+            Self::RightBumper => "right_bumper",
+            Self::Select => "select",
+            Self::Start => "start",
+        }
+    }
+}
+
+impl Display for Button {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|button| button.name() == s)
+            .ok_or_else(|| format!("unknown button `{s}`"))
+    }
+}
+
+/// A mapped input event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    /// An axis moved to a calibrated position.
+    Axis(Axis, f64),
+    /// A button was pressed or released.
+    Button(Button, bool),
+}
+
+// Linux input event codes.
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_Z: u16 = 0x02;
+const ABS_RX: u16 = 0x03;
+const ABS_RY: u16 = 0x04;
+const ABS_RZ: u16 = 0x05;
+const BTN_TRIGGER: u16 = 0x120;
+const BTN_SOUTH: u16 = 0x130;
+
+/// Input codes of a kind of controller.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Profile {
+    axes: BTreeMap<u16, Axis>,
+    buttons: BTreeMap<u16, Button>,
+    name: &'static str,
+}
+
+impl Profile {
+    /// Names of the built-in profiles.
+    pub const NAMES: [&'static str; 3] = ["xbox", "ps4", "generic"];
+
+    /// Returns the built-in profile `name`, or `None` if there is none.
+    #[must_use]
+    pub fn named(name: &str) -> Option<Self> {
+        use Axis::*;
+        use Button::*;
+
+        let (name, axes, buttons) = match name {
+            // The xpad driver, following the kernel’s gamepad layout.
+            "xbox" => (
+                "xbox",
+                [
+                    (ABS_X, LeftX),
+                    (ABS_Y, LeftY),
+                    (ABS_RX, RightX),
+                    (ABS_RY, RightY),
+                    (ABS_Z, LeftTrigger),
+                    (ABS_RZ, RightTrigger),
+                ],
+                [
+                    (BTN_SOUTH, South),
+                    (BTN_SOUTH + 1, East),
+                    (BTN_SOUTH + 3, North),
+                    (BTN_SOUTH + 4, West),
+                    (BTN_SOUTH + 6, LeftBumper),
+                    (BTN_SOUTH + 7, RightBumper),
+                    (BTN_SOUTH + 10, Select),
+                    (BTN_SOUTH + 11, Start),
+                ],
+            ),
+            // A DualShock 4 over USB HID without the Sony driver.
+            "ps4" => (
+                "ps4",
+                [
+                    (ABS_X, LeftX),
+                    (ABS_Y, LeftY),
+                    (ABS_Z, RightX),
+                    (ABS_RZ, RightY),
+                    (ABS_RX, LeftTrigger),
+                    (ABS_RY, RightTrigger),
+                ],
+                [
+                    (BTN_TRIGGER, West),
+                    (BTN_TRIGGER + 1, South),
+                    (BTN_TRIGGER + 2, East),
+                    (BTN_TRIGGER + 3, North),
+                    (BTN_TRIGGER + 4, LeftBumper),
+                    (BTN_TRIGGER + 5, RightBumper),
+                    (BTN_TRIGGER + 8, Select),
+                    (BTN_TRIGGER + 9, Start),
+                ],
+            ),
+            // Most inexpensive USB gamepads.
+            "generic" => (
+                "generic",
+                [
+                    (ABS_X, LeftX),
+                    (ABS_Y, LeftY),
+                    (ABS_Z, RightX),
+                    (ABS_RZ, RightY),
+                    (ABS_RX, LeftTrigger),
+                    (ABS_RY, RightTrigger),
+                ],
+                [
+                    (BTN_TRIGGER, South),
+                    (BTN_TRIGGER + 1, East),
+                    (BTN_TRIGGER + 2, West),
+                    (BTN_TRIGGER + 3, North),
+                    (BTN_TRIGGER + 4, LeftBumper),
+                    (BTN_TRIGGER + 5, RightBumper),
+                    (BTN_TRIGGER + 8, Select),
+                    (BTN_TRIGGER + 9, Start),
+                ],
+            ),
+            _ => return None,
+        };
+
+        Some(Self {
+            axes: axes.into_iter().collect(),
+            buttons: buttons.into_iter().collect(),
+            name,
+        })
+    }
+
+    /// Returns the name of the profile.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// Returns the axis reported under the absolute axis `code`.
+    #[must_use]
+    pub fn axis(&self, code: u16) -> Option<Axis> {
+        self.axes.get(&code).copied()
+    }
+
+    /// Returns the button reported under the key `code`.
+    #[must_use]
+    pub fn button(&self, code: u16) -> Option<Button> {
+        self.buttons.get(&code).copied()
+    }
+}
+
+/// Raw range of an axis, as measured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisCalibration {
+    /// Raw value at one end of travel.
+    pub min: f64,
+    /// Raw value at rest.
+    pub center: f64,
+    /// Raw value at the other end of travel.
+    pub max: f64,
+    /// Whether to reverse the direction.
+    pub invert: bool,
+    /// Fraction of travel around the center treated as zero.
+    pub deadzone: f64,
+}
+
+impl AxisCalibration {
+    /// Scales `raw` to -1 to 1, or to 0 to 1 for an axis that rests at one
+    /// end, such as a trigger.
+    #[must_use]
+    pub fn normalize(&self, raw: f64) -> f64 {
+        let offset = raw - self.center;
+        let span = if offset >= 0.0 {
+            self.max - self.center
+        } else {
+            self.center - self.min
+        };
+
+        if span <= 0.0 {
+            return 0.0;
+        }
+
+        let value = (offset / span).clamp(-1.0, 1.0);
+        // Rescale past the deadzone so the output still reaches zero smoothly.
+        let magnitude = ((value.abs() - self.deadzone) / (1.0 - self.deadzone)).max(0.0);
+        let value = magnitude.copysign(value);
+
+        if self.invert {
+            -value
+        } else {
+            value
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AxisParams {
+    center: Param<f64>,
+    deadzone: Param<f64>,
+    invert: Param<bool>,
+    max: Param<f64>,
+    min: Param<f64>,
+}
+
+/// A connected controller, mapped and calibrated by its parameters.
+#[derive(Debug)]
+pub struct Gamepad {
+    axes: BTreeMap<Axis, AxisParams>,
+    device: String,
+    profile: Param<String>,
+}
+
+impl Gamepad {
+    /// Registers the parameters of the device named `device`, such as
+    /// `Xbox Wireless Controller`, and returns it mapped by its saved
+    /// profile and calibration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a parameter cannot be
+    /// registered.
+    pub fn new(params: &ParamServer, device: &str) -> Result<Self, ParamError> {
+        let prefix = format!("gamepad.{}", param_name(device));
+        let profile = params.register(
+            ParamSpec::new(&format!("{prefix}.profile"), DEFAULT_PROFILE.to_owned())
+                .description("Mapping profile: xbox, ps4, or generic"),
+        )?;
+        let mut axes = BTreeMap::new();
+
+        for axis in Axis::ALL {
+            let name = |field| format!("{prefix}.{axis}.{field}");
+            axes.insert(
+                axis,
+                AxisParams {
+                    min: params.register(
+                        ParamSpec::new(&name("min"), -32_768.0).description("Raw value at one end"),
+                    )?,
+                    center: params.register(
+                        ParamSpec::new(&name("center"), 0.0).description("Raw value at rest"),
+                    )?,
+                    max: params.register(
+                        ParamSpec::new(&name("max"), 32_767.0)
+                            .description("Raw value at the other end"),
+                    )?,
+                    invert: params.register(
+                        ParamSpec::new(&name("invert"), false).description("Reverse the axis"),
+                    )?,
+                    deadzone: params.register(
+                        ParamSpec::new(&name("deadzone"), 0.05)
+                            .range(0.0, 0.9)
+                            .description("Fraction of travel treated as zero"),
+                    )?,
+                },
+            );
+        }
+
+        Ok(Self {
+            axes,
+            device: device.to_owned(),
+            profile,
+        })
+    }
+
+    /// Returns the profile in use, falling back to the default if the
+    /// parameter names an unknown one.
+    #[must_use]
+    pub fn profile(&self) -> Profile {
+        let name = self.profile.get();
+        Profile::named(&name).unwrap_or_else(|| {
+            log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "unknown profile `{name}` for {}, using {DEFAULT_PROFILE}",
+                self.device
+            );
+            Profile::named(DEFAULT_PROFILE).expect("default profile should exist")
+        })
+    }
+
+    /// Selects the built-in profile `name`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`ParamError::OutOfRange`] if there is no
+    /// such profile, or an error if it cannot be persisted.
+    pub fn select_profile(&self, name: &str) -> Result<(), ParamError> {
+        if Profile::named(name).is_none() {
+            return Err(ParamError::OutOfRange {
+                name: self.profile.name().to_owned(),
+                value: crate::params::ParamValue::Text(name.to_owned()),
+            });
+        }
+
+        self.profile.set(name.to_owned())
+    }
+
+    /// Returns the calibration of `axis`.
+    #[must_use]
+    pub fn calibration(&self, axis: Axis) -> AxisCalibration {
+        let params = &self.axes[&axis];
+        AxisCalibration {
+            min: params.min.get(),
+            center: params.center.get(),
+            max: params.max.get(),
+            invert: params.invert.get(),
+            deadzone: params.deadzone.get(),
+        }
+    }
+
+    /// Replaces the calibration of `axis`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a value is out of range or
+    /// cannot be persisted.
+    pub fn calibrate(&self, axis: Axis, calibration: AxisCalibration) -> Result<(), ParamError> {
+        let params = &self.axes[&axis];
+        params.min.set(calibration.min)?;
+        params.center.set(calibration.center)?;
+        params.max.set(calibration.max)?;
+        params.invert.set(calibration.invert)?;
+        params.deadzone.set(calibration.deadzone)
+    }
+
+    /// Maps an absolute axis event with `code` and `raw` value.
+    #[must_use]
+    pub fn map_axis(&self, code: u16, raw: i32) -> Option<Input> {
+        let axis = self.profile().axis(code)?;
+        let value = self.calibration(axis).normalize(f64::from(raw));
+        Some(Input::Axis(axis, value))
+    }
+
+    /// Maps a key event with `code`, pressed if `value` is nonzero.
+    #[must_use]
+    pub fn map_button(&self, code: u16, value: i32) -> Option<Input> {
+        let button = self.profile().button(code)?;
+        Some(Input::Button(button, value != 0))
+    }
+}
+
+/// Current position of every axis and button, fed by mapped inputs from a
+/// physical or virtual controller alike.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GamepadState {
+    axes: BTreeMap<Axis, f64>,
+    pressed: BTreeSet<Button>,
+}
+
+impl GamepadState {
+    /// Creates a new `GamepadState` with every axis at rest and no button
+    /// pressed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the state with `input`.
+    pub fn apply(&mut self, input: Input) {
+        match input {
+            Input::Axis(axis, value) => {
+                self.axes.insert(axis, value.clamp(-1.0, 1.0));
+            }
+            Input::Button(button, true) => {
+                self.pressed.insert(button);
+            }
+            Input::Button(button, false) => {
+                self.pressed.remove(&button);
+            }
+        }
+    }
+
+    /// Returns the position of `axis`.
+    #[must_use]
+    pub fn axis(&self, axis: Axis) -> f64 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    /// Returns `true` if `button` is held.
+    #[must_use]
+    pub fn pressed(&self, button: Button) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    /// Returns the twist commanded by the left stick, forward when pushed
+    /// up and turning left when pushed left, scaled to `max_speed` in
+    /// metres per second and `max_turn` in radians per second.
+    #[must_use]
+    pub fn twist(&self, max_speed: f64, max_turn: f64) -> Twist {
+        Twist::new(
+            -self.axis(Axis::LeftY) * max_speed,
+            0.0,
+            -self.axis(Axis::LeftX) * max_turn,
+        )
+    }
+}
+
+/// Measures the range of each axis while the sticks are moved through their
+/// travel.
+///
+/// The first value seen on an axis is taken as its rest position, so the
+/// sticks and triggers should be left alone when calibration starts.
+#[derive(Clone, Debug, Default)]
+pub struct Calibration {
+    ranges: BTreeMap<u16, [f64; 3]>,
+}
+
+impl Calibration {
+    /// Creates a new `Calibration` with nothing measured.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an absolute axis event with `code` and `raw` value.
+    pub fn observe(&mut self, code: u16, raw: i32) {
+        let raw = f64::from(raw);
+        let [min, _, max] = self.ranges.entry(code).or_insert([raw; 3]);
+        *min = min.min(raw);
+        *max = max.max(raw);
+    }
+
+    /// Saves the measured range of every axis `gamepad`’s profile maps,
+    /// keeping each axis’s inversion and deadzone, and returns the axes
+    /// calibrated.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a value cannot be persisted.
+    pub fn apply(&self, gamepad: &Gamepad) -> Result<Vec<Axis>, ParamError> {
+        let profile = gamepad.profile();
+        let mut calibrated = Vec::new();
+
+        for (&code, &[min, center, max]) in &self.ranges {
+            let Some(axis) = profile.axis(code) else {
+                continue;
+            };
+
+            if min == max {
+                log_event!(SUBSYSTEM, Level::Warn, "{axis} did not move, skipping it");
+                continue;
+            }
+
+            gamepad.calibrate(
+                axis,
+                AxisCalibration {
+                    min,
+                    center,
+                    max,
+                    ..gamepad.calibration(axis)
+                },
+            )?;
+            calibrated.push(axis);
+        }
+
+        log_event!(
+            SUBSYSTEM,
+            Level::Info,
+            "calibrated {} axes of {}",
+            calibrated.len(),
+            gamepad.device
+        );
+        Ok(calibrated)
+    }
+}
+
+/// Converts a device name into a parameter name segment.
+fn param_name(device: &str) -> String {
+    let mut name = String::new();
+
+    for c in device.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+
+    name.trim_end_matches('_').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::ParamValue;
+
+    #[test]
+    fn it_should_map_codes_through_the_selected_profile() {
+        let params = ParamServer::new();
+        let gamepad = Gamepad::new(&params, "Wireless Controller").unwrap();
+        assert_eq!(
+            params.get("gamepad.wireless_controller.profile"),
+            Some(ParamValue::Text("generic".to_owned()))
+        );
+        assert_eq!(
+            gamepad.map_button(BTN_TRIGGER, 1),
+            Some(Input::Button(Button::South, true))
+        );
+        gamepad.select_profile("ps4").unwrap();
+        assert_eq!(
+            gamepad.map_button(BTN_TRIGGER, 0),
+            Some(Input::Button(Button::West, false))
+        );
+        assert!(matches!(
+            gamepad.select_profile("n64"),
+            Err(ParamError::OutOfRange { .. })
+        ));
+        assert_eq!(gamepad.profile().name(), "ps4");
+        assert_eq!(gamepad.map_axis(0x10, 0), None);
+    }
+
+    #[test]
+    fn it_should_track_state_and_command_a_twist() {
+        let mut state = GamepadState::new();
+        state.apply(Input::Axis(Axis::LeftY, -1.5));
+        state.apply(Input::Axis(Axis::LeftX, 0.5));
+        state.apply(Input::Button(Button::South, true));
+        assert!(state.pressed(Button::South));
+        assert_eq!(state.twist(0.4, 2.0), Twist::new(0.4, 0.0, -1.0));
+        state.apply(Input::Button(Button::South, false));
+        assert!(!state.pressed(Button::South));
+        assert_eq!("left_bumper".parse(), Ok(Button::LeftBumper));
+        assert_eq!("right_y".parse(), Ok(Axis::RightY));
+        assert!("wheel".parse::<Axis>().is_err());
+    }
+
+    #[test]
+    fn it_should_normalize_axes_by_their_calibration() {
+        let stick = AxisCalibration {
+            min: 0.0,
+            center: 130.0,
+            max: 255.0,
+            invert: true,
+            deadzone: 0.1,
+        };
+        assert_eq!(stick.normalize(0.0), 1.0);
+        assert_eq!(stick.normalize(255.0), -1.0);
+        assert_eq!(stick.normalize(135.0), 0.0);
+        let trigger = AxisCalibration {
+            center: 0.0,
+            invert: false,
+            deadzone: 0.0,
+            ..stick
+        };
+        assert_eq!(trigger.normalize(51.0), 0.2);
+        assert_eq!(trigger.normalize(-5.0), 0.0);
+    }
+
+    #[test]
+    fn it_should_save_a_measured_calibration_to_the_params() {
+        let params = ParamServer::new();
+        let gamepad = Gamepad::new(&params, "Xbox Wireless Controller").unwrap();
+        gamepad.select_profile("xbox").unwrap();
+        let mut calibration = Calibration::new();
+
+        for raw in [128, 0, 255, 128] {
+            calibration.observe(ABS_X, raw);
+        }
+
+        calibration.observe(ABS_Y, 128);
+        assert_eq!(calibration.apply(&gamepad).unwrap(), [Axis::LeftX]);
+        assert_eq!(
+            params.get("gamepad.xbox_wireless_controller.left_x.center"),
+            Some(ParamValue::Float(128.0))
+        );
+        assert_eq!(
+            gamepad.map_axis(ABS_X, 255),
+            Some(Input::Axis(Axis::LeftX, 1.0))
+        );
+        assert_eq!(param_name("  Sony  DS4 (v2) "), "sony_ds4_v2");
+    }
+}
@@ -0,0 +1,422 @@
+//! Hierarchical menus on a small display, driven by a knob and buttons.
+//!
+//! A [`Menu`] is a tree of actions and submenus built once at startup. A
+//! [`MenuSystem`] tracks where the user is in it, moves through it on each
+//! [`Input`], and draws the visible part on a [`TextDisplay`], such as a
+//! 128×64 OLED showing 8 lines of 21 characters. An action returns a message
+//! to show, like the robot’s IP address, until the next input.
+
+use std::fmt::{self, Debug, Formatter};
+use std::io::Error;
+
+use crate::hal::Encoder;
+
+/// Marker drawn before the selected entry.
+const CURSOR: &str = "> ";
+
+/// Marker drawn before every other entry.
+const NO_CURSOR: &str = "  ";
+
+/// Marker drawn after the label of a submenu.
+const SUBMENU: &str = " >";
+
+/// A user input that moves through a menu.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Input {
+    /// Moves to the previous entry, as by turning the knob left.
+    Previous,
+    /// Moves to the next entry, as by turning the knob right.
+    Next,
+    /// Runs the selected action or opens the selected submenu, as by
+    /// pressing the knob.
+    Select,
+    /// Dismisses a message or returns to the parent menu.
+    Back,
+}
+
+/// A display of lines of text.
+pub trait TextDisplay {
+    /// Returns the number of characters that fit on a line.
+    fn columns(&self) -> usize;
+
+    /// Returns the number of lines that fit on the display.
+    fn rows(&self) -> usize;
+
+    /// Replaces the contents of the display with `lines`, at most
+    /// [`TextDisplay::rows`] of at most [`TextDisplay::columns`]
+    /// characters.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the display cannot be driven.
+    fn show_lines(&mut self, lines: &[String]) -> Result<(), Error>;
+}
+
+type Action = Box<dyn FnMut() -> String + Send>;
+
+enum Entry {
+    Action(String, Action),
+    Submenu(Menu),
+}
+
+impl Entry {
+    fn label(&self) -> String {
+        match self {
+            Self::Action(label, _) => label.clone(),
+            Self::Submenu(menu) => format!("{}{SUBMENU}", menu.title),
+        }
+    }
+}
+
+/// A titled list of actions and submenus.
+pub struct Menu {
+    entries: Vec<Entry>,
+    title: String,
+}
+
+impl Menu {
+    /// Creates a new, empty `Menu` titled `title`.
+    pub fn new(title: &str) -> Self {
+        Self {
+            entries: Vec::new(),
+            title: title.to_owned(),
+        }
+    }
+
+    /// Adds an entry labelled `label` that runs `action` and shows the
+    /// message it returns.
+    #[must_use]
+    pub fn with_action(
+        mut self,
+        label: &str,
+        action: impl FnMut() -> String + Send + 'static,
+    ) -> Self {
+        self.entries
+            .push(Entry::Action(label.to_owned(), Box::new(action)));
+        self
+    }
+
+    /// Adds `submenu` as an entry labelled with its title.
+    #[must_use]
+    pub fn with_submenu(mut self, submenu: Menu) -> Self {
+        self.entries.push(Entry::Submenu(submenu));
+        self
+    }
+
+    /// Returns the title of the menu.
+    #[must_use]
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+}
+
+impl Debug for Menu {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Menu")
+            .field("title", &self.title)
+            .field(
+                "entries",
+                &self.entries.iter().map(Entry::label).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// Where the user is in a tree of menus.
+#[derive(Debug)]
+pub struct MenuSystem {
+    message: Option<String>,
+    root: Menu,
+    // The selected entry in each open menu, from the root down.
+    selection: Vec<usize>,
+}
+
+impl MenuSystem {
+    /// Creates a new `MenuSystem` at the first entry of `root`.
+    pub fn new(root: Menu) -> Self {
+        Self {
+            message: None,
+            root,
+            selection: vec![0],
+        }
+    }
+
+    /// Returns the message left by the last action, if it is still shown.
+    #[must_use]
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Returns the labels of the open menus, from the root down.
+    #[must_use]
+    pub fn path(&self) -> Vec<&str> {
+        let mut menu = &self.root;
+        let mut path = vec![menu.title.as_str()];
+
+        for &index in &self.selection[..self.selection.len() - 1] {
+            if let Entry::Submenu(submenu) = &menu.entries[index] {
+                menu = submenu;
+                path.push(&menu.title);
+            }
+        }
+
+        path
+    }
+
+    /// Moves through the menus on `input`.
+    pub fn handle(&mut self, input: Input) {
+        if self.message.take().is_some() {
+            return;
+        }
+
+        let depth = self.selection.len();
+        let len = self.current().entries.len();
+        let selected = &mut self.selection[depth - 1];
+
+        match input {
+            Input::Previous => *selected = selected.saturating_sub(1),
+            Input::Next => *selected = (*selected + 1).min(len.saturating_sub(1)),
+            Input::Back if depth > 1 => {
+                self.selection.pop();
+            }
+            Input::Back => {}
+            Input::Select => {
+                let index = *selected;
+
+                match self.current_mut().entries.get_mut(index) {
+                    Some(Entry::Action(_, action)) => self.message = Some(action()),
+                    Some(Entry::Submenu(_)) => self.selection.push(0),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Draws the open menu, or the message left by the last action, on
+    /// `display`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the display cannot be driven.
+    pub fn render(&self, display: &mut impl TextDisplay) -> Result<(), Error> {
+        let columns = display.columns();
+        let rows = display.rows();
+        let menu = self.current();
+        let mut lines = vec![truncate(&menu.title, columns)];
+        let body = rows.saturating_sub(1);
+
+        if let Some(message) = &self.message {
+            lines.extend(wrap(message, columns).into_iter().take(body));
+        } else {
+            let selected = self.selection[self.selection.len() - 1];
+            // Scroll just far enough to keep the selection in view.
+            let first = (selected + 1).saturating_sub(body);
+            lines.extend(menu.entries.iter().enumerate().skip(first).take(body).map(
+                |(index, entry)| {
+                    let marker = if index == selected { CURSOR } else { NO_CURSOR };
+                    truncate(&format!("{marker}{}", entry.label()), columns)
+                },
+            ));
+        }
+
+        display.show_lines(&lines)
+    }
+
+    fn current(&self) -> &Menu {
+        let depth = self.selection.len();
+        self.selection[..depth - 1]
+            .iter()
+            .fold(&self.root, |menu, &index| match &menu.entries[index] {
+                Entry::Submenu(submenu) => submenu,
+                Entry::Action(..) => unreachable!("only submenus should be opened"),
+            })
+    }
+
+    fn current_mut(&mut self) -> &mut Menu {
+        let depth = self.selection.len();
+        let mut menu = &mut self.root;
+
+        for &index in &self.selection[..depth - 1] {
+            menu = match &mut menu.entries[index] {
+                Entry::Submenu(submenu) => submenu,
+                Entry::Action(..) => unreachable!("only submenus should be opened"),
+            };
+        }
+
+        menu
+    }
+}
+
+/// Turns the rotation of a detented knob into [`Input::Previous`] and
+/// [`Input::Next`].
+#[derive(Debug)]
+pub struct Knob<E> {
+    counts_per_detent: i64,
+    encoder: E,
+    last: Option<i64>,
+}
+
+impl<E: Encoder> Knob<E> {
+    /// Creates a new `Knob` whose `encoder` counts `counts_per_detent` per
+    /// click, usually 4 for a quadrature knob.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `counts_per_detent` is not positive.
+    pub fn new(encoder: E, counts_per_detent: i64) -> Self {
+        assert!(
+            counts_per_detent > 0,
+            "counts_per_detent should be positive"
+        );
+        Self {
+            counts_per_detent,
+            encoder,
+            last: None,
+        }
+    }
+
+    /// Returns the inputs for every click since the last poll.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the encoder cannot be read.
+    pub fn poll(&mut self) -> Result<Vec<Input>, Error> {
+        let count = self.encoder.count()?;
+        let last = *self.last.get_or_insert(count);
+        let clicks = (count - last) / self.counts_per_detent;
+        // Keep partial clicks for the next poll.
+        self.last = Some(last + clicks * self.counts_per_detent);
+        let input = if clicks < 0 {
+            Input::Previous
+        } else {
+            Input::Next
+        };
+        Ok(vec![input; clicks.unsigned_abs() as usize])
+    }
+}
+
+fn truncate(text: &str, columns: usize) -> String {
+    text.chars().take(columns).collect()
+}
+
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+                lines.push(std::mem::take(&mut line));
+            }
+
+            if !line.is_empty() {
+                line.push(' ');
+            }
+
+            line.push_str(word);
+        }
+
+        lines.push(truncate(&line, columns));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Screen {
+        lines: Vec<String>,
+    }
+
+    impl TextDisplay for Screen {
+        fn columns(&self) -> usize {
+            12
+        }
+
+        fn rows(&self) -> usize {
+            3
+        }
+
+        fn show_lines(&mut self, lines: &[String]) -> Result<(), Error> {
+            self.lines = lines.to_vec();
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Count(i64);
+
+    impl Encoder for &mut Count {
+        fn count(&mut self) -> Result<i64, Error> {
+            Ok(self.0)
+        }
+    }
+
+    fn menus(shutdown: Arc<AtomicBool>) -> MenuSystem {
+        MenuSystem::new(
+            Menu::new("Otter Pi")
+                .with_action("Show IP", || "192.168.1.42".to_owned())
+                .with_submenu(
+                    Menu::new("System")
+                        .with_action("Self-test", || "All systems nominal".to_owned())
+                        .with_action("Shutdown", move || {
+                            shutdown.store(true, Ordering::Relaxed);
+                            "Shutting down".to_owned()
+                        }),
+                )
+                .with_action("Start mapping", || "Mapping".to_owned()),
+        )
+    }
+
+    #[test]
+    fn it_should_scroll_to_keep_the_selection_in_view() {
+        let mut system = menus(Arc::default());
+        let mut screen = Screen::default();
+        system.render(&mut screen).unwrap();
+        assert_eq!(screen.lines, ["Otter Pi", "> Show IP", "  System >"]);
+        system.handle(Input::Next);
+        system.handle(Input::Next);
+        system.handle(Input::Next);
+        system.render(&mut screen).unwrap();
+        assert_eq!(screen.lines, ["Otter Pi", "  System >", "> Start mapp"]);
+    }
+
+    #[test]
+    fn it_should_run_actions_in_submenus_and_show_their_messages() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let mut system = menus(Arc::clone(&shutdown));
+        let mut screen = Screen::default();
+        system.handle(Input::Next);
+        system.handle(Input::Select);
+        assert_eq!(system.path(), ["Otter Pi", "System"]);
+        system.handle(Input::Select);
+        system.render(&mut screen).unwrap();
+        assert_eq!(screen.lines, ["System", "All systems", "nominal"]);
+        system.handle(Input::Next);
+        assert_eq!(system.message(), None);
+        system.handle(Input::Next);
+        system.handle(Input::Select);
+        assert!(shutdown.load(Ordering::Relaxed));
+        system.handle(Input::Back);
+        system.handle(Input::Back);
+        assert_eq!(system.path(), ["Otter Pi"]);
+    }
+
+    #[test]
+    fn it_should_turn_knob_clicks_into_inputs() {
+        let mut count = Count(10);
+        let mut knob = Knob::new(&mut count, 4);
+        assert!(knob.poll().unwrap().is_empty());
+        knob.encoder.0 = 19;
+        assert_eq!(knob.poll().unwrap(), [Input::Next, Input::Next]);
+        knob.encoder.0 = 9;
+        assert_eq!(knob.poll().unwrap(), [Input::Previous, Input::Previous]);
+    }
+}
@@ -1,5 +1,12 @@
 //! Features available on Unix-like operating systems.
 
+#[cfg(target_os = "linux")]
+pub mod can;
+pub mod clock;
 mod convert;
+pub mod daemon;
+pub mod flock;
+pub mod fsutil;
 mod posix;
 pub mod temporary_directory;
+pub mod temporary_file;
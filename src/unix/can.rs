@@ -0,0 +1,473 @@
+//! CAN bus access through SocketCAN.
+//!
+//! Linux exposes CAN controllers, such as the MCP2515 on a CAN HAT, as
+//! network interfaces like `can0`, configured with `ip link`. A
+//! [`CanSocket`] sends and receives raw frames on one, optionally filtered
+//! in the kernel by ID, and a [`CyclicSender`] repeats a frame at a fixed
+//! period, as motor controllers such as the ODrive and VESC expect of their
+//! setpoints.
+
+use std::ffi::{c_int, c_uint, c_void, CString};
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::log_event;
+use crate::logging::Level;
+
+const SUBSYSTEM: &str = "can";
+
+/// CAN protocol family.
+const PF_CAN: c_int = 29;
+
+/// Raw socket type.
+const SOCK_RAW: c_int = 3;
+
+/// Flag for `socket` that closes the socket across `exec`.
+const SOCK_CLOEXEC: c_int = 0o2_000_000;
+
+/// Raw CAN protocol.
+const CAN_RAW: c_int = 1;
+
+/// Socket option level of raw CAN sockets.
+const SOL_CAN_RAW: c_int = 101;
+
+/// Raw CAN socket option setting the receive filters.
+const CAN_RAW_FILTER: c_int = 1;
+
+/// Flag in a frame’s ID marking a 29-bit extended ID.
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+
+/// Flag in a frame’s ID marking a remote transmission request.
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+
+/// Mask of a standard 11-bit ID.
+const CAN_SFF_MASK: u32 = 0x7FF;
+
+/// Mask of an extended 29-bit ID.
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+
+/// Length of a classic CAN frame as the kernel lays it out, in bytes.
+const FRAME_LEN: usize = 16;
+
+/// Largest payload of a classic CAN frame, in bytes.
+pub const MAX_DATA_LEN: usize = 8;
+
+/// CAN socket address.
+#[repr(C)]
+struct SockaddrCan {
+    can_family: u16,
+    can_ifindex: c_int,
+    can_addr: [u8; 16],
+}
+
+extern "C" {
+    /// Creates a socket in `domain` of `ty` using `protocol`.
+    ///
+    /// Returns a file descriptor on success, or -1 on failure and sets
+    /// `errno` to indicate the error.
+    fn socket(domain: c_int, ty: c_int, protocol: c_int) -> c_int;
+
+    /// Binds the socket `sockfd` to the address at `addr`, `addrlen` bytes
+    /// long.
+    ///
+    /// Returns 0 on success, or -1 on failure and sets `errno` to indicate
+    /// the error.
+    fn bind(sockfd: c_int, addr: *const c_void, addrlen: u32) -> c_int;
+
+    /// Sets the option `optname` at `level` on the socket `sockfd` to the
+    /// `optlen` bytes at `optval`.
+    ///
+    /// Returns 0 on success, or -1 on failure and sets `errno` to indicate
+    /// the error.
+    fn setsockopt(
+        sockfd: c_int,
+        level: c_int,
+        optname: c_int,
+        optval: *const c_void,
+        optlen: u32,
+    ) -> c_int;
+
+    /// Returns the index of the network interface named `ifname`, or 0 if
+    /// there is none and sets `errno` to indicate the error.
+    fn if_nametoindex(ifname: *const std::ffi::c_char) -> c_uint;
+}
+
+/// Identifier of a CAN frame, which also sets its priority.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Id {
+    /// An 11-bit standard ID.
+    Standard(u16),
+    /// A 29-bit extended ID.
+    Extended(u32),
+}
+
+impl Id {
+    fn raw(self) -> u32 {
+        match self {
+            Self::Standard(id) => u32::from(id) & CAN_SFF_MASK,
+            Self::Extended(id) => id & CAN_EFF_MASK | CAN_EFF_FLAG,
+        }
+    }
+}
+
+impl Display for Id {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Standard(id) => write!(f, "{id:03X}"),
+            Self::Extended(id) => write!(f, "{id:08X}"),
+        }
+    }
+}
+
+/// A classic CAN frame.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Frame {
+    data: [u8; MAX_DATA_LEN],
+    id: Id,
+    len: u8,
+    remote: bool,
+}
+
+impl Frame {
+    /// Creates a new data frame, or returns `None` if `data` is longer than
+    /// [`MAX_DATA_LEN`].
+    #[must_use]
+    pub fn new(id: Id, data: &[u8]) -> Option<Self> {
+        if data.len() > MAX_DATA_LEN {
+            return None;
+        }
+
+        let mut frame = Self {
+            data: [0; MAX_DATA_LEN],
+            id,
+            len: data.len() as u8,
+            remote: false,
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+
+    /// Creates a new remote frame, requesting `len` bytes of data from
+    /// whichever node sends `id`.
+    #[must_use]
+    pub fn remote(id: Id, len: u8) -> Self {
+        Self {
+            data: [0; MAX_DATA_LEN],
+            id,
+            len: len.min(MAX_DATA_LEN as u8),
+            remote: true,
+        }
+    }
+
+    /// Returns the ID of the frame.
+    #[must_use]
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Returns the data of the frame.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..usize::from(self.len)]
+        }
+    }
+
+    /// Returns whether the frame is a remote transmission request.
+    #[must_use]
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    fn to_bytes(self) -> [u8; FRAME_LEN] {
+        let mut raw_id = self.id.raw();
+
+        if self.remote {
+            raw_id |= CAN_RTR_FLAG;
+        }
+
+        let mut bytes = [0; FRAME_LEN];
+        bytes[..4].copy_from_slice(&raw_id.to_ne_bytes());
+        bytes[4] = self.len;
+        bytes[8..].copy_from_slice(&self.data);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; FRAME_LEN]) -> Self {
+        let raw_id = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let id = if raw_id & CAN_EFF_FLAG == 0 {
+            Id::Standard((raw_id & CAN_SFF_MASK) as u16)
+        } else {
+            Id::Extended(raw_id & CAN_EFF_MASK)
+        };
+        let mut data = [0; MAX_DATA_LEN];
+        data.copy_from_slice(&bytes[8..]);
+        Self {
+            data,
+            id,
+            len: bytes[4].min(MAX_DATA_LEN as u8),
+            remote: raw_id & CAN_RTR_FLAG != 0,
+        }
+    }
+}
+
+/// A filter passing frames whose ID matches under a mask.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[repr(C)]
+pub struct Filter {
+    id: u32,
+    mask: u32,
+}
+
+impl Filter {
+    /// Creates a new `Filter` passing frames of the same kind as `id` whose
+    /// ID bits under `mask` match it.
+    #[must_use]
+    pub fn new(id: Id, mask: u32) -> Self {
+        let raw = id.raw();
+        Self {
+            id: raw,
+            mask: mask & (CAN_EFF_MASK) | CAN_EFF_FLAG,
+        }
+    }
+
+    /// Creates a new `Filter` passing only frames with `id`.
+    #[must_use]
+    pub fn exact(id: Id) -> Self {
+        Self::new(id, CAN_EFF_MASK)
+    }
+
+    /// Returns whether the filter passes `frame`.
+    #[must_use]
+    pub fn matches(&self, frame: &Frame) -> bool {
+        (frame.id.raw() ^ self.id) & self.mask == 0
+    }
+}
+
+/// A raw CAN socket bound to an interface.
+#[derive(Debug)]
+pub struct CanSocket {
+    file: File,
+    interface: String,
+}
+
+impl CanSocket {
+    /// Opens a socket on `interface`, such as `can0`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no such interface or
+    /// the socket cannot be opened.
+    pub fn open(interface: &str) -> Result<Self, Error> {
+        let name =
+            CString::new(interface).map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+        let index = unsafe { if_nametoindex(name.as_ptr()) };
+
+        if index == 0 {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no network interface `{interface}`"),
+            ));
+        }
+
+        let fd = unsafe { socket(PF_CAN, SOCK_RAW | SOCK_CLOEXEC, CAN_RAW) };
+
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let address = SockaddrCan {
+            can_family: PF_CAN as u16,
+            can_ifindex: index as c_int,
+            can_addr: [0; 16],
+        };
+        let result = unsafe {
+            bind(
+                fd.as_raw_fd(),
+                (&address as *const SockaddrCan).cast(),
+                std::mem::size_of::<SockaddrCan>() as u32,
+            )
+        };
+
+        if result == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        log_event!(SUBSYSTEM, Level::Info, "opened {interface}");
+        Ok(Self {
+            file: File::from(fd),
+            interface: interface.to_owned(),
+        })
+    }
+
+    /// Returns the name of the interface.
+    #[must_use]
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// Receives only frames passing any of `filters`, or none if empty.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the filters cannot be set.
+    pub fn set_filters(&self, filters: &[Filter]) -> Result<(), Error> {
+        let result = unsafe {
+            setsockopt(
+                self.file.as_raw_fd(),
+                SOL_CAN_RAW,
+                CAN_RAW_FILTER,
+                filters.as_ptr().cast(),
+                std::mem::size_of_val(filters) as u32,
+            )
+        };
+
+        if result == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Sends `frame`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the frame cannot be sent, such
+    /// as when the controller is bus-off.
+    pub fn send(&self, frame: &Frame) -> Result<(), Error> {
+        log_event!(
+            SUBSYSTEM,
+            Level::Trace,
+            "sending {} {:02X?}",
+            frame.id,
+            frame.data()
+        );
+        (&self.file).write_all(&frame.to_bytes())
+    }
+
+    /// Waits for and returns the next frame passing the filters.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the socket cannot be read.
+    pub fn receive(&self) -> Result<Frame, Error> {
+        let mut bytes = [0; FRAME_LEN];
+
+        if (&self.file).read(&mut bytes)? != FRAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated CAN frame"));
+        }
+
+        Ok(Frame::from_bytes(&bytes))
+    }
+}
+
+/// Sends a frame repeatedly at a fixed period until dropped.
+#[derive(Debug)]
+pub struct CyclicSender {
+    frame: Arc<Mutex<Frame>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CyclicSender {
+    /// Starts sending `frame` on `socket` every `period`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the thread cannot be spawned.
+    pub fn spawn(socket: Arc<CanSocket>, frame: Frame, period: Duration) -> Result<Self, Error> {
+        let frame = Arc::new(Mutex::new(frame));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let frame = Arc::clone(&frame);
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(format!("{}-cyclic", socket.interface))
+                .spawn(move || {
+                    let mut next = Instant::now();
+
+                    while !stop.load(Ordering::Acquire) {
+                        let current = *frame.lock().unwrap_or_else(|e| e.into_inner());
+
+                        if let Err(error) = socket.send(&current) {
+                            log_event!(
+                                SUBSYSTEM,
+                                Level::Warn,
+                                "failed to send {} on {}: {error}",
+                                current.id,
+                                socket.interface
+                            );
+                        }
+
+                        next += period;
+                        thread::sleep(next.saturating_duration_since(Instant::now()));
+                    }
+                })?
+        };
+        Ok(Self {
+            frame,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Replaces the frame sent from the next period on.
+    pub fn update(&self, frame: Frame) {
+        *self.frame.lock().unwrap_or_else(|e| e.into_inner()) = frame;
+    }
+}
+
+impl Drop for CyclicSender {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_lay_frames_out_as_the_kernel_does() {
+        let frame = Frame::new(Id::Extended(0x0123_4567), &[1, 2, 3]).unwrap();
+        let bytes = frame.to_bytes();
+        assert_eq!(bytes[..4], (0x0123_4567 | CAN_EFF_FLAG).to_ne_bytes());
+        assert_eq!(bytes[4], 3);
+        assert_eq!(bytes[8..11], [1, 2, 3]);
+        assert_eq!(Frame::from_bytes(&bytes), frame);
+        let remote = Frame::remote(Id::Standard(0x7FF), 8);
+        assert_eq!(Frame::from_bytes(&remote.to_bytes()), remote);
+        assert!(remote.data().is_empty());
+        assert_eq!(Frame::new(Id::Standard(1), &[0; 9]), None);
+    }
+
+    #[test]
+    fn it_should_match_frames_against_filters() {
+        // ODrive axis 1 uses IDs 0x020 to 0x03F.
+        let filter = Filter::new(Id::Standard(0x020), 0x7E0);
+        let frame = |id| Frame::new(id, &[]).unwrap();
+        assert!(filter.matches(&frame(Id::Standard(0x029))));
+        assert!(!filter.matches(&frame(Id::Standard(0x049))));
+        assert!(!filter.matches(&frame(Id::Extended(0x029))));
+        assert!(Filter::exact(Id::Extended(0x029)).matches(&frame(Id::Extended(0x029))));
+    }
+
+    #[test]
+    fn it_should_fail_to_open_a_missing_interface() {
+        assert!(CanSocket::open("nocan0").is_err_and(|error| error.kind() == ErrorKind::NotFound));
+    }
+}
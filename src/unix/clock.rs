@@ -0,0 +1,69 @@
+//! Local time as configured for the system.
+
+use std::ffi::{c_char, c_int, c_long};
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Broken-down time, as filled in by `localtime_r`.
+#[repr(C)]
+struct Tm {
+    tm_sec: c_int,
+    tm_min: c_int,
+    tm_hour: c_int,
+    tm_mday: c_int,
+    tm_mon: c_int,
+    tm_year: c_int,
+    tm_wday: c_int,
+    tm_yday: c_int,
+    tm_isdst: c_int,
+    tm_gmtoff: c_long,
+    tm_zone: *const c_char,
+}
+
+extern "C" {
+    /// Converts the seconds since the epoch at `timep` into local time in
+    /// `result`, according to the system time zone.
+    ///
+    /// Returns `result` on success, or a null pointer on failure and sets
+    /// `errno` to indicate the error.
+    fn localtime_r(timep: *const c_long, result: *mut Tm) -> *mut Tm;
+
+    /// Loads the system time zone, which `localtime_r` does not do itself.
+    fn tzset();
+}
+
+/// Returns the offset of local time from UTC at `time`, in seconds, which
+/// changes with daylight saving.
+///
+/// # Errors
+///
+/// This function will return an error if the time cannot be converted.
+pub fn utc_offset(time: SystemTime) -> Result<i32, io::Error> {
+    // `time_t` is a `long` on every platform the robot runs on.
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as c_long,
+        Err(error) => -(error.duration().as_secs() as c_long),
+    };
+    let mut tm: Tm = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        tzset();
+        localtime_r(&seconds, &mut tm)
+    };
+
+    if result.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(tm.tm_gmtoff as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_return_an_offset_within_a_day() {
+        let offset = utc_offset(SystemTime::now()).unwrap();
+        assert!(offset.abs() < 24 * 60 * 60);
+    }
+}
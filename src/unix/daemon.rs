@@ -0,0 +1,303 @@
+//! Daemonization for running the robot in the background without systemd.
+
+use std::ffi::c_int;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::{env, process};
+
+use super::flock::FileLock;
+use crate::log_event;
+use crate::logging::Level;
+
+extern "C" {
+    /// Duplicates `oldfd` onto `newfd`, closing `newfd` first if necessary.
+    ///
+    /// Returns `newfd` on success, or -1 on failure and sets `errno` to indicate
+    /// the error.
+    fn dup2(oldfd: c_int, newfd: c_int) -> c_int;
+
+    /// Creates a child process that is a copy of the calling process.
+    ///
+    /// Returns the child’s process ID in the parent and 0 in the child on
+    /// success, or -1 on failure and sets `errno` to indicate the error.
+    fn fork() -> c_int;
+
+    /// Creates a new session with the calling process as its leader,
+    /// detaching it from its controlling terminal.
+    ///
+    /// Returns the new session ID on success, or -1 on failure and sets `errno`
+    /// to indicate the error.
+    fn setsid() -> c_int;
+
+    /// Sets the file mode creation mask of the calling process and returns the
+    /// previous mask.
+    fn umask(mask: u32) -> u32;
+
+    /// Terminates the calling process immediately, without running `atexit`
+    /// handlers or flushing standard I/O buffers.
+    fn _exit(status: c_int) -> !;
+}
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "daemon";
+
+/// Path to the null device used for detached standard streams.
+const DEV_NULL: &str = "/dev/null";
+
+/// Builder for detaching the current process into a background daemon.
+///
+/// # Examples
+///
+/// ```no_run
+/// use otter_pi::unix::daemon::Daemon;
+///
+/// let _pidfile = Daemon::new()
+///     .pidfile("/run/otter-pi.pid")
+///     .stdout("/var/log/otter-pi.log")
+///     .stderr("/var/log/otter-pi.log")
+///     .start()
+///     .unwrap();
+///
+/// // Only the daemon process reaches this point.
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Daemon {
+    pidfile: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+    stdout: Option<PathBuf>,
+    umask: u32,
+    working_dir: PathBuf,
+}
+
+impl Daemon {
+    /// Creates a new `Daemon` builder with the conventional defaults.
+    ///
+    /// By default, there is no pidfile, all standard streams are redirected to
+    /// `/dev/null`, the file mode creation mask is `0o027`, and the working
+    /// directory is `/`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the daemon’s process ID in a locked pidfile at `path`.
+    #[must_use]
+    pub fn pidfile(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pidfile = Some(path.into());
+        self
+    }
+
+    /// Appends the daemon’s standard output to the file at `path`.
+    #[must_use]
+    pub fn stdout(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stdout = Some(path.into());
+        self
+    }
+
+    /// Appends the daemon’s standard error to the file at `path`.
+    #[must_use]
+    pub fn stderr(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stderr = Some(path.into());
+        self
+    }
+
+    /// Sets the daemon’s file mode creation mask.
+    #[must_use]
+    pub fn umask(mut self, mask: u32) -> Self {
+        self.umask = mask;
+        self
+    }
+
+    /// Sets the daemon’s working directory.
+    #[must_use]
+    pub fn working_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.working_dir = path.into();
+        self
+    }
+
+    /// Detaches the current process from its terminal using a double fork.
+    ///
+    /// The pidfile is locked and the standard stream targets are opened before
+    /// forking so that failures are reported to the invoking process. The
+    /// original process exits on success, and the function only returns in the
+    /// daemon process. This should be called before any other threads are
+    /// spawned.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind [`ErrorKind::ResourceBusy`]
+    /// if another daemon holds the pidfile, or any other error if a file
+    /// cannot be opened or the process cannot be detached.
+    pub fn start(self) -> Result<Option<Pidfile>, Error> {
+        let mut pidfile = self.pidfile.as_deref().map(Pidfile::create).transpose()?;
+        let stdin = File::open(DEV_NULL)?;
+        let stdout = open_output(self.stdout.as_deref())?;
+        let stderr = open_output(self.stderr.as_deref())?;
+
+        match unsafe { fork() } {
+            -1 => return Err(Error::last_os_error()),
+            0 => {}
+            _ => process::exit(0),
+        }
+
+        if unsafe { setsid() } == -1 {
+            unsafe { _exit(1) };
+        }
+
+        match unsafe { fork() } {
+            -1 => unsafe { _exit(1) },
+            0 => {}
+            _ => unsafe { _exit(0) },
+        }
+
+        if let Some(pidfile) = &mut pidfile {
+            pidfile.lock.update_owner()?;
+        }
+
+        redirect(&stdin, 0)?;
+        redirect(&stdout, 1)?;
+        redirect(&stderr, 2)?;
+        unsafe { umask(self.umask) };
+        env::set_current_dir(&self.working_dir)?;
+        log_event!(SUBSYSTEM, Level::Info, "detached as pid {}", process::id());
+        Ok(pidfile)
+    }
+}
+
+impl Default for Daemon {
+    fn default() -> Self {
+        Self {
+            pidfile: None,
+            stderr: None,
+            stdout: None,
+            umask: 0o027,
+            working_dir: PathBuf::from("/"),
+        }
+    }
+}
+
+/// A locked file recording the process ID of a running daemon.
+///
+/// The file is removed when the object goes out of scope.
+#[derive(Debug)]
+pub struct Pidfile {
+    lock: FileLock,
+}
+
+impl Pidfile {
+    /// Creates and locks a pidfile at `path` for the current process.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind [`ErrorKind::ResourceBusy`]
+    /// if another running process holds the pidfile.
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        match FileLock::try_lock(path) {
+            Ok(lock) => Ok(Self { lock }),
+            Err(error) if error.kind() == ErrorKind::ResourceBusy => Err(Error::new(
+                ErrorKind::ResourceBusy,
+                format!("{} is held by another daemon: {error}", path.display()),
+            )),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns the path to the pidfile.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        self.lock.path()
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.lock.path());
+    }
+}
+
+fn open_output(path: Option<&Path>) -> Result<File, Error> {
+    match path {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path),
+        None => OpenOptions::new().write(true).open(DEV_NULL),
+    }
+}
+
+fn redirect(file: &File, fd: c_int) -> Result<(), Error> {
+    if unsafe { dup2(file.as_raw_fd(), fd) } == -1 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    mod daemon {
+        use super::*;
+
+        #[test]
+        fn it_should_create_a_default_daemon() {
+            assert_eq!(Daemon::new(), Daemon::default());
+        }
+
+        #[test]
+        fn it_should_detach_into_the_root_directory_by_default() {
+            assert_eq!(Daemon::new().working_dir, Path::new("/"));
+        }
+
+        #[test]
+        fn it_should_fail_before_forking_when_the_pidfile_is_held() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("otter-pi.pid");
+            let _pidfile = Pidfile::create(&path).unwrap();
+            let error = Daemon::new().pidfile(&path).start().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ResourceBusy);
+        }
+
+        #[test]
+        fn it_should_fail_before_forking_when_an_output_cannot_be_opened() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let error = Daemon::new()
+                .stdout(temp_dir.path().join("foo/bar.log"))
+                .start()
+                .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::NotFound);
+        }
+    }
+
+    mod pidfile {
+        use super::*;
+
+        #[test]
+        fn it_should_record_the_process_id() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("otter-pi.pid");
+            let _pidfile = Pidfile::create(&path).unwrap();
+            assert!(fs::read_to_string(&path)
+                .is_ok_and(|contents| contents == process::id().to_string()));
+        }
+
+        #[test]
+        fn it_should_name_the_running_daemon_when_held() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("otter-pi.pid");
+            let _pidfile = Pidfile::create(&path).unwrap();
+            let error = Pidfile::create(&path).unwrap_err();
+            assert!(error
+                .to_string()
+                .ends_with(&format!("(pid {})", process::id())));
+        }
+
+        #[test]
+        fn it_should_remove_the_file_after_going_out_of_scope() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("otter-pi.pid");
+            drop(Pidfile::create(&path).unwrap());
+            assert!(path.try_exists().is_ok_and(|exists| !exists));
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Advisory file locking for claiming exclusive ownership of devices.
+
+use std::ffi::c_int;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::log_event;
+use crate::logging::Level;
+
+extern "C" {
+    /// Applies or removes an advisory lock on the open file referred to by
+    /// `fd`.
+    ///
+    /// Locks are associated with the open file description, so they are
+    /// released when every file descriptor referring to it has been closed.
+    ///
+    /// Returns 0 on success, or -1 on failure and sets `errno` to indicate the
+    /// error.
+    fn flock(fd: c_int, operation: c_int) -> c_int;
+}
+
+/// Operation for `flock` that places an exclusive lock.
+const LOCK_EX: c_int = 2;
+
+/// Flag for `flock` that fails instead of blocking when the file is locked.
+const LOCK_NB: c_int = 4;
+
+/// Name of the subsystem used when logging.
+const SUBSYSTEM: &str = "flock";
+
+/// Default directory in which device lock files are created.
+pub const LOCK_DIR: &str = "/run/lock";
+
+/// An exclusive advisory lock on a file that records the owning process ID.
+///
+/// The lock is released when the object goes out of scope or the owning
+/// process exits, so a crashed process never leaves a stale lock behind.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Attempts to take an exclusive lock on the file at `path`, creating it
+    /// if necessary.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind [`ErrorKind::ResourceBusy`]
+    /// if another lock is already held on the file, or any other error if the
+    /// file cannot be opened or written.
+    pub fn try_lock(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o644)
+            .open(path)?;
+
+        if unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) } == -1 {
+            let error = Error::last_os_error();
+
+            let error = if error.kind() == ErrorKind::WouldBlock {
+                busy_error(read_owner(&mut file))
+            } else {
+                error
+            };
+
+            log_event!(
+                SUBSYSTEM,
+                Level::Warn,
+                "failed to lock {}: {error}",
+                path.display()
+            );
+            return Err(error);
+        }
+
+        log_event!(SUBSYSTEM, Level::Debug, "locked {}", path.display());
+
+        let mut lock = Self {
+            file,
+            path: path.to_owned(),
+        };
+        lock.update_owner()?;
+        Ok(lock)
+    }
+
+    /// Records the current process as the owner of the lock.
+    ///
+    /// Locks are inherited across `fork`, so a child process that takes over a
+    /// lock should call this to record its own process ID.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lock file cannot be written.
+    pub fn update_owner(&mut self) -> Result<(), Error> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(process::id().to_string().as_bytes())
+    }
+
+    /// Returns the path to the lock file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the underlying lock file.
+    #[must_use]
+    pub fn as_file(&self) -> &File {
+        &self.file
+    }
+}
+
+/// An exclusive claim on a device shared between processes.
+///
+/// Devices are identified by their path, such as `/dev/ttyUSB0` or
+/// `/sys/class/pwm/pwmchip0`, which is mapped to a lock file name.
+///
+/// # Examples
+///
+/// ```
+/// use otter_pi::unix::flock::DeviceLock;
+/// use otter_pi::unix::temporary_directory::TemporaryDirectory;
+///
+/// let lock_dir = TemporaryDirectory::new().unwrap();
+/// let lock = DeviceLock::acquire_in(lock_dir.path(), "/dev/ttyUSB0").unwrap();
+/// assert!(DeviceLock::acquire_in(lock_dir.path(), "/dev/ttyUSB0").is_err());
+/// drop(lock);
+/// assert!(DeviceLock::acquire_in(lock_dir.path(), "/dev/ttyUSB0").is_ok());
+/// ```
+#[derive(Debug)]
+pub struct DeviceLock {
+    device: PathBuf,
+    lock: FileLock,
+}
+
+impl DeviceLock {
+    /// Claims exclusive ownership of `device` using the system lock directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind [`ErrorKind::ResourceBusy`]
+    /// naming the owning process if the device is already claimed.
+    pub fn acquire(device: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::acquire_in(LOCK_DIR, device)
+    }
+
+    /// Claims exclusive ownership of `device` using lock files in `lock_dir`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind [`ErrorKind::ResourceBusy`]
+    /// naming the owning process if the device is already claimed.
+    pub fn acquire_in(lock_dir: impl AsRef<Path>, device: impl AsRef<Path>) -> Result<Self, Error> {
+        let device = device.as_ref();
+        let lock = FileLock::try_lock(lock_dir.as_ref().join(lock_file_name(device)))?;
+        Ok(Self {
+            device: device.to_owned(),
+            lock,
+        })
+    }
+
+    /// Returns the path of the claimed device.
+    #[must_use]
+    pub fn device(&self) -> &Path {
+        &self.device
+    }
+
+    /// Returns the path to the lock file backing the claim.
+    #[must_use]
+    pub fn lock_path(&self) -> &Path {
+        self.lock.path()
+    }
+}
+
+fn busy_error(owner: Option<u32>) -> Error {
+    let message = match owner {
+        Some(pid) => format!("device busy (pid {pid})"),
+        None => String::from("device busy"),
+    };
+
+    Error::new(ErrorKind::ResourceBusy, message)
+}
+
+fn lock_file_name(device: &Path) -> String {
+    let name = device
+        .to_string_lossy()
+        .trim_start_matches('/')
+        .replace('/', "_");
+    format!("otter-pi.{name}.lock")
+}
+
+fn read_owner(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    mod file_lock {
+        use super::*;
+
+        #[test]
+        fn it_should_create_the_lock_file() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("foo.lock");
+            let _lock = FileLock::try_lock(&path).unwrap();
+            assert!(path.is_file());
+        }
+
+        #[test]
+        fn it_should_record_the_owning_process_id() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("foo.lock");
+            let _lock = FileLock::try_lock(&path).unwrap();
+            assert!(fs::read_to_string(&path)
+                .is_ok_and(|contents| contents == process::id().to_string()));
+        }
+
+        #[test]
+        fn it_should_return_a_busy_error_naming_the_owner_when_already_locked() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("foo.lock");
+            let _lock = FileLock::try_lock(&path).unwrap();
+            let error = FileLock::try_lock(&path).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ResourceBusy);
+            assert_eq!(
+                error.to_string(),
+                format!("device busy (pid {})", process::id())
+            );
+        }
+
+        #[test]
+        fn it_should_release_the_lock_after_going_out_of_scope() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("foo.lock");
+            drop(FileLock::try_lock(&path).unwrap());
+            assert!(FileLock::try_lock(&path).is_ok());
+        }
+
+        #[test]
+        fn it_should_return_an_error_when_the_directory_does_not_exist() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let error = FileLock::try_lock(temp_dir.path().join("foo/bar.lock")).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::NotFound);
+        }
+    }
+
+    mod device_lock {
+        use super::*;
+
+        #[test]
+        fn it_should_name_the_lock_file_after_the_device() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let lock = DeviceLock::acquire_in(temp_dir.path(), "/sys/class/pwm/pwmchip0").unwrap();
+            assert_eq!(
+                lock.lock_path(),
+                temp_dir.path().join("otter-pi.sys_class_pwm_pwmchip0.lock")
+            );
+        }
+
+        #[test]
+        fn it_should_allow_different_devices_to_be_claimed() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let _lock_a = DeviceLock::acquire_in(temp_dir.path(), "/dev/ttyUSB0").unwrap();
+            assert!(DeviceLock::acquire_in(temp_dir.path(), "/dev/ttyUSB1").is_ok());
+        }
+
+        #[test]
+        fn it_should_fail_fast_when_the_device_is_already_claimed() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let _lock = DeviceLock::acquire_in(temp_dir.path(), "/dev/ttyUSB0").unwrap();
+            let error = DeviceLock::acquire_in(temp_dir.path(), "/dev/ttyUSB0").unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ResourceBusy);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Filesystem utilities that build on Unix-specific guarantees.
+
+use std::fs::File;
+use std::io::{Error, Write};
+use std::path::Path;
+
+use super::temporary_file::TemporaryFile;
+
+/// Atomically replaces the contents of the file at `path` with `contents`.
+///
+/// The contents are written to a temporary file in the same directory, synced
+/// to disk, and then renamed over `path`, after which the directory itself is
+/// synced. Readers, and the file left behind by a power loss, will only ever
+/// observe either the old or the new contents in full.
+///
+/// # Examples
+///
+/// ```
+/// use std::fs;
+///
+/// use otter_pi::unix::fsutil;
+/// use otter_pi::unix::temporary_directory::TemporaryDirectory;
+///
+/// let temp_dir = TemporaryDirectory::new().unwrap();
+/// let path = temp_dir.path().join("calibration");
+/// fsutil::write_atomic(&path, "offset = 0.5").unwrap();
+/// assert!(fs::read_to_string(&path).is_ok_and(|contents| contents == "offset = 0.5"));
+/// ```
+///
+/// # Errors
+///
+/// This function will return an error if the parent directory of `path` does
+/// not exist or is not writable, or if writing or syncing the data fails.
+pub fn write_atomic(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut temp_file = TemporaryFile::new_in(dir)?;
+    temp_file.as_file_mut().write_all(contents.as_ref())?;
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(path)?;
+    sync_dir(dir)
+}
+
+/// Syncs the directory entries of `dir` to disk.
+///
+/// # Errors
+///
+/// This function will return an error if `dir` cannot be opened or synced.
+pub fn sync_dir(dir: impl AsRef<Path>) -> Result<(), Error> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    mod write_atomic {
+        use super::*;
+
+        #[test]
+        fn it_should_create_a_file_with_the_given_contents() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("foo");
+            write_atomic(&path, "bar").unwrap();
+            assert!(fs::read_to_string(path).is_ok_and(|contents| contents == "bar"));
+        }
+
+        #[test]
+        fn it_should_replace_the_contents_of_an_existing_file() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            let path = temp_dir.path().join("foo");
+            fs::write(&path, "a much longer old value").unwrap();
+            write_atomic(&path, "bar").unwrap();
+            assert!(fs::read_to_string(path).is_ok_and(|contents| contents == "bar"));
+        }
+
+        #[test]
+        fn it_should_not_leave_temporary_files_behind() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            write_atomic(temp_dir.path().join("foo"), "bar").unwrap();
+            assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+        }
+
+        #[test]
+        fn it_should_return_an_error_when_the_parent_directory_does_not_exist() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            assert!(write_atomic(temp_dir.path().join("foo/bar"), "baz").is_err());
+        }
+    }
+
+    mod sync_dir {
+        use super::*;
+
+        #[test]
+        fn it_should_succeed_for_an_existing_directory() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            assert!(sync_dir(temp_dir.path()).is_ok());
+        }
+
+        #[test]
+        fn it_should_return_an_error_when_the_directory_does_not_exist() {
+            let temp_dir = TemporaryDirectory::new().unwrap();
+            assert!(sync_dir(temp_dir.path().join("foo")).is_err());
+        }
+    }
+}
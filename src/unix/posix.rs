@@ -1,7 +1,9 @@
 //! Features that are dependent on system conformance to POSIX standards.
 
-use std::ffi::{c_char, CString, NulError};
-use std::path::PathBuf;
+use std::ffi::{c_char, c_int, CString, NulError};
+use std::fs::File;
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
 use std::{env, io};
 
 use super::convert;
@@ -18,8 +20,42 @@ extern "C" {
     /// Returns a pointer to `template` on success, or a null pointer on failure and
     /// sets `errno` to indicate the error.
     fn mkdtemp(template: *mut c_char) -> *mut c_char;
+
+    /// Securely creates and opens a file with a unique name derived from
+    /// `template`.
+    ///
+    /// `template` follows the same rules as for `mkdtemp`. The file is created
+    /// with read and write permissions for the user only.
+    ///
+    /// Returns an open file descriptor on success, or -1 on failure and sets
+    /// `errno` to indicate the error.
+    fn mkstemp(template: *mut c_char) -> c_int;
+
+    /// Creates a new link `newpath` to the existing file `oldpath`, with both
+    /// paths resolved relative to the directories referred to by `olddirfd` and
+    /// `newdirfd`.
+    ///
+    /// Returns 0 on success, or -1 on failure and sets `errno` to indicate the
+    /// error.
+    #[cfg(target_os = "linux")]
+    fn linkat(
+        olddirfd: c_int,
+        oldpath: *const c_char,
+        newdirfd: c_int,
+        newpath: *const c_char,
+        flags: c_int,
+    ) -> c_int;
 }
 
+/// Special value for the directory file descriptor arguments of `linkat` that
+/// resolves relative paths against the current working directory.
+#[cfg(target_os = "linux")]
+const AT_FDCWD: c_int = -100;
+
+/// Flag for `linkat` that dereferences `oldpath` if it is a symbolic link.
+#[cfg(target_os = "linux")]
+const AT_SYMLINK_FOLLOW: c_int = 0x400;
+
 /// Securely creates a uniquely-named temporary directory.
 ///
 /// The path to the underlying temporary directory is based on the system’s
@@ -30,7 +66,7 @@ extern "C" {
 /// This function will return an error if it fails to create a temporary
 /// directory.
 pub fn create_temp_dir() -> Result<PathBuf, io::Error> {
-    let template = get_temp_dir_template()?.into_raw();
+    let template = get_temp_template(&env::temp_dir())?.into_raw();
     let result = unsafe { mkdtemp(template) };
     let error = io::Error::last_os_error();
     let path = unsafe { CString::from_raw(template) };
@@ -42,14 +78,63 @@ pub fn create_temp_dir() -> Result<PathBuf, io::Error> {
     }
 }
 
-/// Returns a template for use with `mkdtemp`.
+/// Securely creates and opens a uniquely-named temporary file within `dir`.
 ///
 /// # Errors
 ///
-/// This function will return an error if the system’s temporary directory path
-/// contains a nul byte.
-fn get_temp_dir_template() -> Result<CString, NulError> {
-    let mut template = env::temp_dir();
+/// This function will return an error if it fails to create a temporary file.
+pub fn create_temp_file(dir: &Path) -> Result<(File, PathBuf), io::Error> {
+    let template = get_temp_template(dir)?.into_raw();
+    let fd = unsafe { mkstemp(template) };
+    let error = io::Error::last_os_error();
+    let path = unsafe { CString::from_raw(template) };
+
+    if fd == -1 {
+        Err(error)
+    } else {
+        let file = unsafe { File::from_raw_fd(fd) };
+        Ok((file, convert::c_string_to_path_buf(path)))
+    }
+}
+
+/// Creates a new hard link at `to` for the file at `from`, following `from` if
+/// it is a symbolic link.
+///
+/// Following symbolic links allows files that only exist as an open file
+/// descriptor to be given a name through their `/proc/self/fd` entry.
+///
+/// # Errors
+///
+/// This function will return an error if either path contains a nul byte, or if
+/// the link cannot be created.
+#[cfg(target_os = "linux")]
+pub fn link_following_symlinks(from: &Path, to: &Path) -> Result<(), io::Error> {
+    let from = convert::path_buf_to_c_string(from.to_owned())?;
+    let to = convert::path_buf_to_c_string(to.to_owned())?;
+    let result = unsafe {
+        linkat(
+            AT_FDCWD,
+            from.as_ptr(),
+            AT_FDCWD,
+            to.as_ptr(),
+            AT_SYMLINK_FOLLOW,
+        )
+    };
+
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns a template within `dir` for use with `mkdtemp` and `mkstemp`.
+///
+/// # Errors
+///
+/// This function will return an error if `dir` contains a nul byte.
+fn get_temp_template(dir: &Path) -> Result<CString, NulError> {
+    let mut template = dir.to_owned();
     template.push("XXXXXX");
     convert::path_buf_to_c_string(template)
 }
@@ -88,4 +173,34 @@ mod tests {
             }
         }
     }
+
+    mod create_temp_file {
+        use std::fs;
+        use std::io::Write;
+
+        use super::*;
+
+        #[test]
+        fn it_should_return_a_path_within_the_given_directory() {
+            let dir = env::temp_dir();
+            let (_, path) = create_temp_file(&dir).expect("`create_temp_file()` should succeed");
+            assert!(path.starts_with(&dir));
+            let _ = fs::remove_file(path);
+        }
+
+        #[test]
+        fn it_should_return_a_writable_file_at_the_returned_path() {
+            let (mut file, path) =
+                create_temp_file(&env::temp_dir()).expect("`create_temp_file()` should succeed");
+            file.write_all(b"foo").expect("file should be writable");
+            assert!(fs::read(&path).is_ok_and(|contents| contents == b"foo"));
+            let _ = fs::remove_file(path);
+        }
+
+        #[test]
+        fn it_should_return_an_error_when_the_directory_does_not_exist() {
+            let dir = env::temp_dir().join("otter-pi-does-not-exist");
+            assert!(create_temp_file(&dir).is_err());
+        }
+    }
 }
@@ -120,6 +120,12 @@ mod tests {
         assert!(path.try_exists().is_ok_and(|exists| !exists));
     }
 
+    #[test]
+    fn it_should_be_re_exported_at_the_crate_root() {
+        let temp_dir = crate::TemporaryDirectory::new().unwrap();
+        assert!(temp_dir.path().is_dir());
+    }
+
     #[test]
     fn it_should_return_a_unique_path_for_each_instance() {
         let temp_dir_a = TemporaryDirectory::new().unwrap();
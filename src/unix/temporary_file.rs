@@ -0,0 +1,268 @@
+//! Abstractions to make managing temporary files easier.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use super::posix;
+
+/// A secure temporary file that is removed unless it is persisted.
+///
+/// Where the kernel and filesystem support it, the file is created with
+/// `O_TMPFILE` and has no name until it is persisted, so it can never be
+/// observed in a partially-written state. Otherwise it falls back to a
+/// uniquely-named file created with `mkstemp`.
+///
+/// # Examples
+///
+/// ```
+/// use std::fs;
+/// use std::io::Write;
+///
+/// use otter_pi::unix::temporary_directory::TemporaryDirectory;
+/// use otter_pi::unix::temporary_file::TemporaryFile;
+///
+/// let temp_dir = TemporaryDirectory::new().unwrap();
+/// let mut temp_file = TemporaryFile::new_in(temp_dir.path()).unwrap();
+/// temp_file.as_file_mut().write_all(b"foo").unwrap();
+///
+/// let path = temp_dir.path().join("bar");
+/// temp_file.persist(&path).unwrap();
+/// assert!(fs::read_to_string(&path).is_ok_and(|contents| contents == "foo"));
+/// ```
+#[derive(Debug)]
+pub struct TemporaryFile {
+    dir: PathBuf,
+    file: File,
+    path: Option<PathBuf>,
+}
+
+impl TemporaryFile {
+    /// Securely creates a temporary file in the system’s temporary directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if it fails to create a temporary
+    /// file.
+    pub fn new() -> Result<Self, Error> {
+        Self::new_in(env::temp_dir())
+    }
+
+    /// Securely creates a temporary file in `dir`.
+    ///
+    /// A file must be created on the same filesystem as its final destination
+    /// for [`TemporaryFile::persist`] to succeed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if it fails to create a temporary
+    /// file.
+    pub fn new_in(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+
+        #[cfg(target_os = "linux")]
+        if let Ok(file) = linux::open_unnamed(dir) {
+            return Ok(Self {
+                dir: dir.to_owned(),
+                file,
+                path: None,
+            });
+        }
+
+        let (file, path) = posix::create_temp_file(dir)?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            file,
+            path: Some(path),
+        })
+    }
+
+    /// Returns a reference to the underlying file.
+    #[must_use]
+    pub fn as_file(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns a mutable reference to the underlying file.
+    #[must_use]
+    pub fn as_file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Gives the temporary file a permanent name at `path`.
+    ///
+    /// Any existing file at `path` is atomically replaced. Data is not
+    /// guaranteed to be durable unless the file has been synced beforehand.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `path` is on a different
+    /// filesystem, or if the file cannot be linked or renamed into place.
+    pub fn persist(mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+
+        match self.path.take() {
+            Some(temp_path) => {
+                let result = fs::rename(&temp_path, path);
+
+                if result.is_err() {
+                    self.path = Some(temp_path);
+                }
+
+                result
+            }
+            #[cfg(target_os = "linux")]
+            None => linux::link_unnamed(&self.file, &self.dir, path),
+            #[cfg(not(target_os = "linux"))]
+            None => unreachable!("unnamed files are only created on Linux"),
+        }
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::c_int;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{Error, ErrorKind};
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::posix;
+
+    /// Flag for `open` that creates an unnamed file in the given directory.
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    const O_TMPFILE: c_int = 0o20_040_000;
+    #[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
+    const O_TMPFILE: c_int = 0o20_200_000;
+
+    /// Maximum number of attempts at finding an unused intermediate link name.
+    const MAX_LINK_ATTEMPTS: usize = 128;
+
+    static LINK_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Opens an unnamed file within `dir` using `O_TMPFILE`.
+    pub fn open_unnamed(dir: &Path) -> Result<File, Error> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .mode(0o600)
+            .custom_flags(O_TMPFILE)
+            .open(dir)
+    }
+
+    /// Links an unnamed file into `dir` and then renames it over `path`.
+    ///
+    /// `linkat` refuses to replace an existing file, so the file is first
+    /// linked to an unused intermediate name to keep the replacement atomic.
+    pub fn link_unnamed(file: &File, dir: &Path, path: &Path) -> Result<(), Error> {
+        let fd_path = format!("/proc/self/fd/{}", file.as_raw_fd());
+
+        for _ in 0..MAX_LINK_ATTEMPTS {
+            let count = LINK_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let link_path = dir.join(format!(".tmp-{}-{count}", process::id()));
+
+            match posix::link_following_symlinks(Path::new(&fd_path), &link_path) {
+                Ok(()) => {
+                    let result = fs::rename(&link_path, path);
+
+                    if result.is_err() {
+                        let _ = fs::remove_file(&link_path);
+                    }
+
+                    return result;
+                }
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(Error::new(
+            ErrorKind::AlreadyExists,
+            "failed to find an unused name for the temporary file",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use super::*;
+    use crate::unix::temporary_directory::TemporaryDirectory;
+
+    #[test]
+    fn it_should_create_a_readable_and_writable_file() {
+        let mut temp_file = TemporaryFile::new().unwrap();
+        temp_file.as_file_mut().write_all(b"foo").unwrap();
+        temp_file.as_file_mut().seek(SeekFrom::Start(0)).unwrap();
+        let mut contents = String::new();
+        temp_file
+            .as_file_mut()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "foo");
+    }
+
+    #[test]
+    fn it_should_persist_the_file_at_the_given_path() {
+        let temp_dir = TemporaryDirectory::new().unwrap();
+        let mut temp_file = TemporaryFile::new_in(temp_dir.path()).unwrap();
+        temp_file.as_file_mut().write_all(b"foo").unwrap();
+        let path = temp_dir.path().join("bar");
+        temp_file.persist(&path).unwrap();
+        assert!(fs::read_to_string(path).is_ok_and(|contents| contents == "foo"));
+    }
+
+    #[test]
+    fn it_should_replace_an_existing_file_when_persisting() {
+        let temp_dir = TemporaryDirectory::new().unwrap();
+        let path = temp_dir.path().join("bar");
+        fs::write(&path, "old").unwrap();
+        let mut temp_file = TemporaryFile::new_in(temp_dir.path()).unwrap();
+        temp_file.as_file_mut().write_all(b"new").unwrap();
+        temp_file.persist(&path).unwrap();
+        assert!(fs::read_to_string(path).is_ok_and(|contents| contents == "new"));
+    }
+
+    #[test]
+    fn it_should_only_leave_the_persisted_file_in_the_directory() {
+        let temp_dir = TemporaryDirectory::new().unwrap();
+        let temp_file = TemporaryFile::new_in(temp_dir.path()).unwrap();
+        temp_file.persist(temp_dir.path().join("bar")).unwrap();
+        let entries: Vec<_> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["bar"]);
+    }
+
+    #[test]
+    fn it_should_leave_nothing_behind_after_going_out_of_scope() {
+        let temp_dir = TemporaryDirectory::new().unwrap();
+
+        {
+            let mut temp_file = TemporaryFile::new_in(temp_dir.path()).unwrap();
+            temp_file.as_file_mut().write_all(b"foo").unwrap();
+        }
+
+        assert!(fs::read_dir(temp_dir.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn it_should_return_an_error_when_the_directory_does_not_exist() {
+        let temp_dir = TemporaryDirectory::new().unwrap();
+        assert!(TemporaryFile::new_in(temp_dir.path().join("foo")).is_err());
+    }
+}